@@ -0,0 +1,11 @@
+//! Analyses of complete programs.
+
+pub use self::bounds::{bounds, bounds_with, Bounds};
+pub use self::layers::{layer_stats, layer_stats_with, LayerStats, Settings};
+pub use self::limits::{soft_limits, soft_limits_with, Violation};
+pub use self::time::{estimate, estimate_with, power, power_with, AxisLimits, Estimate, MachineProfile, Power};
+
+mod bounds;
+mod layers;
+mod limits;
+mod time;
@@ -0,0 +1,150 @@
+use crate::Real;
+use crate::command::Plane;
+use crate::geometry::{AxisValues, Point3};
+use crate::interp::{InterpError, Machine};
+use crate::program::Program;
+use crate::toolpath::Segment;
+
+/// Tolerance used to measure the travel of arcs outside of the XY plane, in millimeters.
+const TOLERANCE: Real = 0.001;
+
+/// The extents of all motion of a program in machine coordinates and millimeters.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub min: Point3,
+    pub max: Point3,
+
+    /// The distance travelled in the XY plane by all moves, including rapids.
+    pub travel: Real,
+
+    /// The lowest and highest position reached by each axis besides X, Y and Z.
+    pub axes: AxisValues<(Real, Real)>,
+}
+
+impl Bounds {
+    /// The extent along each axis.
+    pub fn size(&self) -> Point3 {
+        Point3::new(self.max.x - self.min.x,
+                    self.max.y - self.min.y,
+                    self.max.z - self.min.z)
+    }
+
+    /// The lowest and highest Z coordinate reached.
+    pub fn z_range(&self) -> (Real, Real) {
+        (self.min.z, self.max.z)
+    }
+
+    /// Checks if all motion stays within the box given by its corners, e.g. the machine travel.
+    pub fn fits(&self, min: Point3, max: Point3) -> bool {
+        return self.min.x >= min.x && self.min.y >= min.y && self.min.z >= min.z
+                && self.max.x <= max.x && self.max.y <= max.y && self.max.z <= max.z;
+    }
+
+    fn extend(&mut self, point: Point3) {
+        self.min = Point3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z));
+        self.max = Point3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z));
+    }
+
+    fn extend_axes(&mut self, positions: AxisValues<Real>) {
+        self.axes = self.axes.map(|axis, &(min, max)| (min.min(positions[axis]), max.max(positions[axis])));
+    }
+}
+
+/// Calculates the bounds of all motion of the program, starting with a machine in its default
+/// state.
+///
+/// Arcs are accounted for with the extremes of their sweep, not only their end points. Returns
+/// `None` for programs without any motion and fails on the first block which can not be executed.
+pub fn bounds(program: &Program) -> Result<Option<Bounds>, InterpError> {
+    bounds_with(Machine::new(), program)
+}
+
+/// Calculates the bounds like `bounds`, but starting with the given machine.
+pub fn bounds_with(machine: Machine, program: &Program) -> Result<Option<Bounds>, InterpError> {
+    let mut bounds: Option<Bounds> = None;
+
+    for segment in machine.into_segments(program.iter().cloned()) {
+        let segment = segment?;
+
+        let ((first, second), axes, travel) = match segment {
+            Segment::Rapid { from, to, axes } | Segment::Line { from, to, axes, .. } => {
+                ((from, to), axes, from.xy().distance(to.xy()))
+            }
+
+            Segment::Arc { ref arc, axes, .. } => {
+                let travel = match arc.plane() {
+                    Plane::XY => arc.radius() * arc.sweep().abs(),
+                    _ => {
+                        let mut previous = arc.start();
+                        arc.flatten(TOLERANCE).into_iter()
+                                .map(|point| {
+                                    let distance = previous.xy().distance(point.xy());
+                                    previous = point;
+                                    distance
+                                })
+                                .sum()
+                    }
+                };
+
+                (arc.extents(), axes, travel)
+            }
+
+            Segment::Dwell { .. } => continue,
+        };
+
+        let bounds = bounds.get_or_insert(Bounds {
+            min: first,
+            max: first,
+            travel: 0.0,
+            axes: axes.from.map(|_, &position| (position, position)),
+        });
+        bounds.extend(first);
+        bounds.extend(second);
+        bounds.extend_axes(axes.from);
+        bounds.extend_axes(axes.to);
+        bounds.travel += travel;
+    }
+
+    return Ok(bounds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Axis;
+    use crate::parser::Parser;
+
+    fn program(lines: &[&str]) -> Program {
+        Program::from(Parser::new().parse_all(lines.iter()).unwrap())
+    }
+
+    #[test]
+    fn test_bounds() {
+        let bounds = bounds(&program(&["G0 Z5", "G0 X10", "G1 Z-2 F100", "G2 X10 Y0 I-10", "G0 Z5"])).unwrap().unwrap();
+
+        assert_eq!(bounds.min, Point3::new(-10.0, -10.0, -2.0));
+        assert_eq!(bounds.max, Point3::new(10.0, 10.0, 5.0));
+        assert_eq!(bounds.z_range(), (-2.0, 5.0));
+        assert_eq!(bounds.size(), Point3::new(20.0, 20.0, 7.0));
+        assert!((bounds.travel - (10.0 + 20.0 * crate::real::consts::PI)).abs() < 1e-9);
+
+        assert!(bounds.fits(Point3::new(-10.0, -10.0, -10.0), Point3::new(10.0, 10.0, 10.0)));
+        assert!(!bounds.fits(Point3::new(0.0, 0.0, -10.0), Point3::new(20.0, 20.0, 10.0)));
+    }
+
+    #[test]
+    fn test_bounds_axes() {
+        let bounds = bounds(&program(&["G0 X1 A90", "G1 A-45 W2 F100", "G0 A0"])).unwrap().unwrap();
+
+        assert_eq!(bounds.axes[Axis::A], (-45.0, 90.0));
+        assert_eq!(bounds.axes[Axis::W], (0.0, 2.0));
+        assert_eq!(bounds.axes[Axis::B], (0.0, 0.0));
+        assert_eq!(bounds.max, Point3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_bounds_empty() {
+        assert_eq!(bounds(&program(&["M3 S1000", "G4 P1"])).unwrap(), None);
+        assert!(bounds(&program(&["G0 X1", "G2 X2"])).is_err());
+    }
+}
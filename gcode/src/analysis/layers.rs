@@ -0,0 +1,199 @@
+use std::ops::Range;
+
+use crate::Real;
+use crate::interp::{InterpError, Machine};
+use crate::printer::{layers_with, PrinterState};
+use crate::program::Program;
+use crate::toolpath::Segment;
+
+use super::time::{estimate_with, MachineProfile};
+
+/// The fan and temperatures of a printer at some point.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Settings {
+    /// The speed of the part cooling fan, from 0 to 1.
+    pub fan: Real,
+
+    /// The target temperature of the hot end, in degrees Celsius.
+    pub hotend: Option<Real>,
+
+    /// The target temperature of the bed, in degrees Celsius.
+    pub bed: Option<Real>,
+}
+
+impl<'a> From<&'a PrinterState> for Settings {
+    fn from(printer: &'a PrinterState) -> Self {
+        Self {
+            fan: printer.fan,
+            hotend: printer.hotend,
+            bed: printer.bed,
+        }
+    }
+}
+
+/// Metrics of a single layer of a printed program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerStats {
+    /// The blocks belonging to the layer.
+    pub blocks: Range<usize>,
+
+    /// The height of the first extruding move in the layer, in millimeters.
+    pub z: Option<Real>,
+
+    /// The estimated time printing the layer takes, in seconds.
+    pub time: Real,
+
+    /// The length of filament extruded, in millimeters, minus retractions.
+    pub extrusion: Real,
+
+    /// The distance moved without extruding, in millimeters.
+    pub travel: Real,
+
+    /// The lowest and highest feed rate of moves in the layer, in millimeters per minute.
+    pub min_feed: Option<Real>,
+    pub max_feed: Option<Real>,
+
+    /// The settings in effect when the layer starts and after its last block.
+    pub start: Settings,
+    pub end: Settings,
+}
+
+/// Calculates the metrics of each layer of a printed program, split like `Program::layers`
+/// does.
+///
+/// Times are estimated with the given profile, see `estimate`.
+pub fn layer_stats(program: &Program, profile: &MachineProfile) -> Result<Vec<LayerStats>, InterpError> {
+    layer_stats_with(Machine::new(), program, profile)
+}
+
+/// Calculates the layer metrics like `layer_stats`, but starting with the given machine.
+pub fn layer_stats_with(machine: Machine, program: &Program, profile: &MachineProfile) -> Result<Vec<LayerStats>, InterpError> {
+    let mut printer = PrinterState::new();
+    let mut settings = Vec::with_capacity(program.len());
+    let mut extruded = Vec::with_capacity(program.len());
+    let mut reduced = Program::new();
+
+    for block in program {
+        let (extrusion, block) = printer.apply(block);
+        extruded.push(extrusion);
+        reduced.push(block);
+        settings.push(Settings::from(&printer));
+    }
+
+    let times = estimate_with(machine.clone(), &reduced, profile)?.blocks;
+
+    // The moves of each block as length and feed rate
+    let mut moves = vec![Vec::new(); program.len()];
+
+    let mut segments = machine.clone().into_segments(reduced.iter().cloned());
+    while let Some(segment) = segments.next() {
+        let segment = segment?;
+        let block = segments.machine().blocks() - 1;
+
+        let feed = match segment {
+            Segment::Rapid { .. } => None,
+            Segment::Line { feed, .. } | Segment::Arc { feed, .. } => feed,
+            Segment::Dwell { .. } => continue,
+        };
+
+        moves[block].push((segment.length(), feed));
+    }
+
+    let initial = Settings::from(&PrinterState::new());
+    let mut layers = Vec::new();
+    for layer in layers_with(machine, program)? {
+        let (start, end) = (layer.blocks.start, layer.blocks.end);
+
+        let mut stats = LayerStats {
+            blocks: layer.blocks,
+            z: layer.z,
+            time: times[start..end].iter().sum(),
+            extrusion: layer.extrusion,
+            travel: 0.0,
+            min_feed: None,
+            max_feed: None,
+            start: if start > 0 { settings[start - 1] } else { initial },
+            end: if end > 0 { settings[end - 1] } else { initial },
+        };
+
+        for block in start..end {
+            for &(length, feed) in &moves[block] {
+                if extruded[block] <= 0.0 {
+                    stats.travel += length;
+                }
+
+                if let Some(feed) = feed {
+                    stats.min_feed = Some(stats.min_feed.map_or(feed, |min| min.min(feed)));
+                    stats.max_feed = Some(stats.max_feed.map_or(feed, |max| max.max(feed)));
+                }
+            }
+        }
+
+        layers.push(stats);
+    }
+
+    return Ok(layers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn program(lines: &[&str]) -> Program {
+        Program::from(Parser::new().parse_all(lines.iter()).unwrap())
+    }
+
+    #[test]
+    fn test_markers() {
+        let layers = layer_stats(&program(&[
+            "M104 S200",
+            "G28",
+            ";LAYER:0",
+            "G0 Z0.2",
+            "G1 X10 E1 F600",
+            "G0 X20",
+            ";LAYER:1",
+            "M106 S255",
+            "G0 Z0.4",
+            "G1 X10 E2 F1200",
+            "G1 X0 E3 F300",
+        ]), &MachineProfile::default()).unwrap();
+
+        assert_eq!(layers.len(), 2);
+
+        assert_eq!(layers[0].blocks, 2..6);
+        assert_eq!(layers[0].z, Some(0.2));
+        assert_eq!(layers[0].extrusion, 1.0);
+        assert!((layers[0].travel - 10.2).abs() < 1e-9);
+        assert_eq!((layers[0].min_feed, layers[0].max_feed), (Some(600.0), Some(600.0)));
+        assert_eq!(layers[0].start, Settings { fan: 0.0, hotend: Some(200.0), bed: None });
+        assert!(layers[0].time > 0.0);
+
+        assert_eq!(layers[1].blocks, 6..11);
+        assert_eq!(layers[1].z, Some(0.4));
+        assert_eq!(layers[1].extrusion, 2.0);
+        assert_eq!((layers[1].min_feed, layers[1].max_feed), (Some(300.0), Some(1200.0)));
+        assert_eq!(layers[1].start.fan, 0.0);
+        assert_eq!(layers[1].end.fan, 1.0);
+    }
+
+    #[test]
+    fn test_travel() {
+        let layers = layer_stats(&program(&[
+            "G28",
+            "G0 Z0.2",
+            "G1 X10 E1 F600",
+            "G0 Z1",
+            "G0 X0",
+            "G0 Z0.2",
+            "G1 X10 E2",
+            "G0 Z0.4",
+            "G1 X0 E3",
+        ]), &MachineProfile::default()).unwrap();
+
+        assert_eq!(layers.len(), 2);
+        assert!((layers[0].travel - 11.8).abs() < 1e-9);
+        assert!((layers[1].travel - 0.2).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+
+use crate::Real;
+use crate::geometry::{Axis, AxisValues, Point3};
+use crate::interp::{InterpError, Machine};
+use crate::program::Program;
+use crate::toolpath::Segment;
+
+use super::MachineProfile;
+
+/// Tolerance for positions right on the limits, absorbing rounding errors of arcs.
+const TOLERANCE: Real = 0.0001;
+
+/// A move leaving the travel of the machine.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Violation {
+    /// The index of the block within the program.
+    pub block: usize,
+
+    /// The line number the block was parsed from, if known.
+    pub line: Option<usize>,
+
+    pub axis: char,
+
+    /// The position furthest outside of the travel reached by the move, in machine coordinates.
+    pub position: Real,
+
+    /// The limit exceeded.
+    pub limit: Real,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: ", line)?,
+            None => write!(f, "block {}: ", self.block)?,
+        }
+
+        let side = if self.position < self.limit { "minimum" } else { "maximum" };
+        write!(f, "{} reaches {} beyond its {} of {}", self.axis, self.position, side, self.limit)
+    }
+}
+
+/// Checks that all motion of the program stays within the travel of the machine, starting with a
+/// machine in its default state.
+///
+/// Reports every move leaving the travel given by `MachineProfile::travel` and the limits of the
+/// other axes, once for each axis and side. Arcs are checked with the extremes of their sweep, so
+/// bulging arcs are caught even if their end points are within the travel. Moves returning into
+/// the travel are reported as well, as they start outside of it. Fails on the first block which
+/// can not be executed.
+pub fn soft_limits(program: &Program, profile: &MachineProfile) -> Result<Vec<Violation>, InterpError> {
+    soft_limits_with(Machine::new(), program, profile)
+}
+
+/// Checks the travel like `soft_limits`, but starting with the given machine.
+pub fn soft_limits_with(machine: Machine, program: &Program, profile: &MachineProfile) -> Result<Vec<Violation>, InterpError> {
+    let mut violations = Vec::new();
+
+    let mut segments = machine.into_segments(program.iter().cloned());
+    while let Some(segment) = segments.next() {
+        let segment = segment?;
+        let block = segments.machine().blocks() - 1;
+
+        let ((min, max), axes) = match segment {
+            Segment::Rapid { from, to, axes } | Segment::Line { from, to, axes, .. } => {
+                ((corner(from, to, Real::min), corner(from, to, Real::max)), axes)
+            }
+            Segment::Arc { ref arc, axes, .. } => (arc.extents(), axes),
+            Segment::Dwell { .. } => continue,
+        };
+
+        let mut check = |axis: char, reached: (Real, Real), travel: (Real, Real)| {
+            let line = || program.iter().nth(block).and_then(|block| block.provenance().line());
+            if reached.0 < travel.0 - TOLERANCE {
+                violations.push(Violation { block, line: line(), axis, position: reached.0, limit: travel.0 });
+            }
+            if reached.1 > travel.1 + TOLERANCE {
+                violations.push(Violation { block, line: line(), axis, position: reached.1, limit: travel.1 });
+            }
+        };
+
+        if let Some((lower, upper)) = profile.travel {
+            check('X', (min.x, max.x), (lower.x, upper.x));
+            check('Y', (min.y, max.y), (lower.y, upper.y));
+            check('Z', (min.z, max.z), (lower.z, upper.z));
+        }
+
+        for axis in Axis::ALL.iter().cloned() {
+            if let Some(travel) = profile.axes[axis].and_then(|limits| limits.travel) {
+                check(axis.letter(), extremes(&axes.from, &axes.to, axis), travel);
+            }
+        }
+    }
+
+    return Ok(violations);
+}
+
+fn corner<F>(a: Point3, b: Point3, f: F) -> Point3
+    where F: Fn(Real, Real) -> Real {
+    Point3::new(f(a.x, b.x), f(a.y, b.y), f(a.z, b.z))
+}
+
+fn extremes(from: &AxisValues<Real>, to: &AxisValues<Real>, axis: Axis) -> (Real, Real) {
+    (from[axis].min(to[axis]), from[axis].max(to[axis]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::AxisLimits;
+    use crate::parser::Parser;
+
+    fn program(lines: &[&str]) -> Program {
+        Program::from(Parser::new().parse_all(lines.iter()).unwrap())
+    }
+
+    fn profile() -> MachineProfile {
+        MachineProfile::default().with_travel(Point3::new(0.0, 0.0, -50.0), Point3::new(100.0, 100.0, 0.0))
+    }
+
+    #[test]
+    fn test_soft_limits() {
+        let violations = soft_limits(&program(&["G0 Z5", "G0 Z-10", "G0 X80 Y50", "G1 X-1 Y101 F100"]), &profile()).unwrap();
+
+        assert_eq!(violations, vec![
+            Violation { block: 0, line: Some(1), axis: 'Z', position: 5.0, limit: 0.0 },
+            Violation { block: 1, line: Some(2), axis: 'Z', position: 5.0, limit: 0.0 },
+            Violation { block: 3, line: Some(4), axis: 'X', position: -1.0, limit: 0.0 },
+            Violation { block: 3, line: Some(4), axis: 'Y', position: 101.0, limit: 100.0 },
+        ]);
+        assert_eq!(violations[0].to_string(), "line 1: Z reaches 5 beyond its maximum of 0");
+        assert_eq!(violations[2].to_string(), "line 4: X reaches -1 beyond its minimum of 0");
+
+        assert!(soft_limits(&program(&["G0 Z5"]), &MachineProfile::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_soft_limits_arcs() {
+        // Both end points are within the travel, but the arc bulges beyond X100
+        let violations = soft_limits(&program(&["G0 X80 Y50 Z-1", "G2 X80 Y50 I15 F100"]), &profile()).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!((violations[0].block, violations[0].axis, violations[0].limit), (1, 'X', 100.0));
+        assert!((violations[0].position - 110.0).abs() < 1e-3);
+
+        // Touching the limit with the apex is fine
+        assert!(soft_limits(&program(&["G0 X80 Y50 Z-1", "G2 X80 Y50 I10 F100"]), &profile()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_soft_limits_axes() {
+        let profile = profile().with_axis(Axis::A, AxisLimits {
+            max_velocity: 3600.0,
+            rapid_velocity: 3600.0,
+            max_acceleration: 60.0,
+            travel: Some((-90.0, 90.0)),
+        });
+
+        let violations = soft_limits(&program(&["G0 Z-1 A45", "G0 A120"]), &profile).unwrap();
+        assert_eq!(violations, vec![
+            Violation { block: 1, line: Some(2), axis: 'A', position: 120.0, limit: 90.0 },
+        ]);
+    }
+}
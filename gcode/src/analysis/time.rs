@@ -0,0 +1,599 @@
+use crate::Real;
+use crate::geometry::{Axis, AxisValues, Point3};
+use crate::interp::{InterpError, Machine, Spindle, StateChange};
+use crate::program::Program;
+use crate::toolpath::{AxisTravel, Segment};
+
+/// The number of axes planned: X, Y, Z and all others.
+const AXES: usize = 9;
+
+/// Kinematic limits of a machine used to estimate how long a program takes and to check whether
+/// it stays within the travel of the machine.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MachineProfile {
+    /// The maximal velocity of each axis for feed moves in millimeters per minute.
+    pub max_velocity: Point3,
+
+    /// The velocity of each axis for rapids in millimeters per minute, which may well exceed the
+    /// maximal velocity of feed moves.
+    pub rapid_velocity: Point3,
+
+    /// The maximal acceleration of each axis in millimeters per second squared.
+    pub max_acceleration: Point3,
+
+    /// The deviation from the path allowed when passing corners, in millimeters. Larger values
+    /// allow higher velocities at corners (see GRBL's `$11`).
+    pub junction_deviation: Real,
+
+    /// The chord tolerance used to split arcs into line segments, in millimeters.
+    pub arc_tolerance: Real,
+
+    /// Whether the machine is a laser (see GRBL's `$32`).
+    ///
+    /// Otherwise, the machine comes to a stop whenever the spindle is switched or its speed is
+    /// changed, and waits for `spindle_delay`. Lasers only stop when switched, and move through
+    /// changes of the power without stopping.
+    pub laser_mode: bool,
+
+    /// The time to wait for the spindle to reach its speed after switching it on or changing its
+    /// speed, in seconds.
+    pub spindle_delay: Real,
+
+    /// The spindle speed (`S`) corresponding to full power or speed (see GRBL's `$30`).
+    pub max_spindle_speed: Real,
+
+    /// The limits of the axes besides X, Y and Z the machine has. Axes without limits follow the
+    /// motion of the others without slowing it down.
+    pub axes: AxisValues<Option<AxisLimits>>,
+
+    /// The lowest and highest position of X, Y and Z in machine coordinates, as the corners of
+    /// the work envelope (see GRBL's `$130` to `$132`), or `None` if not known.
+    pub travel: Option<(Point3, Point3)>,
+}
+
+/// Kinematic limits of an axis besides X, Y and Z, in degrees for rotary axes and millimeters
+/// otherwise (see `MachineProfile`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AxisLimits {
+    /// The maximal velocity for feed moves per minute.
+    pub max_velocity: Real,
+
+    /// The velocity for rapids per minute.
+    pub rapid_velocity: Real,
+
+    /// The maximal acceleration per second squared.
+    pub max_acceleration: Real,
+
+    /// The lowest and highest position in machine coordinates, or `None` if unlimited.
+    pub travel: Option<(Real, Real)>,
+}
+
+impl MachineProfile {
+    /// Adds an axis besides X, Y and Z to the machine.
+    pub fn with_axis(mut self, axis: Axis, limits: AxisLimits) -> Self {
+        self.axes[axis] = Some(limits);
+        self
+    }
+
+    /// Sets the work envelope of X, Y and Z by its corners in machine coordinates.
+    pub fn with_travel(mut self, min: Point3, max: Point3) -> Self {
+        self.travel = Some((min, max));
+        self
+    }
+}
+
+impl Default for MachineProfile {
+    /// The default settings of GRBL.
+    fn default() -> Self {
+        Self {
+            max_velocity: Point3::new(500.0, 500.0, 500.0),
+            rapid_velocity: Point3::new(500.0, 500.0, 500.0),
+            max_acceleration: Point3::new(10.0, 10.0, 10.0),
+            junction_deviation: 0.01,
+            arc_tolerance: 0.002,
+            laser_mode: false,
+            spindle_delay: 0.0,
+            max_spindle_speed: 1000.0,
+            axes: AxisValues::default(),
+            travel: None,
+        }
+    }
+}
+
+/// The estimated duration of a program, in seconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub total: Real,
+
+    /// The time spent on each block of the program, by index.
+    pub blocks: Vec<Real>,
+}
+
+/// The laser power along a straight move.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Power {
+    pub from: Point3,
+    pub to: Point3,
+
+    /// The average power as fraction of the maximal power.
+    ///
+    /// In dynamic mode (M4) the power follows the velocity, so it is lowered where the move
+    /// accelerates and decelerates. Rapids are always unpowered.
+    pub power: Real,
+}
+
+/// A straight move as seen by the motion planner, with velocities in millimeters per second.
+#[derive(Debug)]
+struct Move {
+    block: usize,
+    from: Point3,
+    to: Point3,
+    length: Real,
+
+    /// The travel of each axis per length, which is not normalized if rotary axes move along
+    direction: [Real; AXES],
+
+    /// The power at the velocity of the move and whether it is scaled down with lower velocities
+    power: Real,
+    dynamic: bool,
+
+    /// The velocity the move is executed with, if not limited by acceleration
+    velocity: Real,
+    acceleration: Real,
+
+    /// The maximal velocity when entering the move
+    entry: Real,
+}
+
+/// Estimates the duration of the program, starting with a machine in its default state.
+///
+/// Moves are planned like GRBL does: each move accelerates and decelerates within the limits of
+/// the moving axes, the velocity at corners is limited by the junction deviation and the machine
+/// comes to a stop for dwells, for spindle changes (see `MachineProfile::laser_mode`) and at the
+/// end of the program. Rapids are executed at the rapid velocity and feed moves at their feed rate,
+/// limited by the maximal velocity. Time spent on tool changes is not accounted for.
+///
+/// Like in RS274/NGC, the feed rate of moves including X, Y or Z applies to these axes, while the
+/// other axes move along. Otherwise, it applies to U, V and W, or to the rotary axes A, B and C in
+/// degrees per minute.
+pub fn estimate(program: &Program, profile: &MachineProfile) -> Result<Estimate, InterpError> {
+    estimate_with(Machine::new(), program, profile)
+}
+
+/// Estimates the duration like `estimate`, but starting with the given machine.
+pub fn estimate_with(machine: Machine, program: &Program, profile: &MachineProfile) -> Result<Estimate, InterpError> {
+    let (mut blocks, moves) = plan(machine, program, profile)?;
+
+    for (i, m) in moves.iter().enumerate() {
+        let exit = moves.get(i + 1).map_or(0.0, |next| next.entry);
+        blocks[m.block] += duration(m, exit);
+    }
+
+    return Ok(Estimate {
+        total: blocks.iter().sum(),
+        blocks,
+    });
+}
+
+/// Previews the laser power along all moves of the program, planned like `estimate` does.
+pub fn power(program: &Program, profile: &MachineProfile) -> Result<Vec<Power>, InterpError> {
+    power_with(Machine::new(), program, profile)
+}
+
+/// Previews the laser power like `power`, but starting with the given machine.
+pub fn power_with(machine: Machine, program: &Program, profile: &MachineProfile) -> Result<Vec<Power>, InterpError> {
+    let (_, moves) = plan(machine, program, profile)?;
+
+    return Ok(moves.iter()
+            .enumerate()
+            .map(|(i, m)| {
+                let power = if m.dynamic {
+                    let exit = moves.get(i + 1).map_or(0.0, |next| next.entry);
+                    let average = m.length / duration(m, exit);
+                    m.power * average / m.velocity
+                } else {
+                    m.power
+                };
+
+                Power { from: m.from, to: m.to, power }
+            })
+            .collect());
+}
+
+/// Splits the program into moves with their velocities planned, and returns them along with the
+/// time spent waiting in each block.
+fn plan(machine: Machine, program: &Program, profile: &MachineProfile) -> Result<(Vec<Real>, Vec<Move>), InterpError> {
+    let mut blocks = vec![0.0; program.len()];
+    let syncs = synchronizations(machine.clone(), program, profile);
+
+    let mut moves = Vec::<Move>::new();
+    let mut stop = true;
+    let mut next = 0;
+
+    let mut segments = machine.into_segments(program.iter().cloned());
+    while let Some(segment) = segments.next() {
+        let segment = segment?;
+        let block = segments.machine().blocks() - 1;
+        let state = segments.machine().state();
+
+        // Spindle changes happen before the motion of the same block
+        for &(sync, delay) in syncs.iter().skip(next).take_while(|&&(sync, _)| sync <= block) {
+            blocks[sync] += delay;
+            stop = true;
+            next += 1;
+        }
+
+        let (points, axes, feed, rapid) = match segment {
+            Segment::Rapid { from, to, axes } => (vec![from, to], axes, None, true),
+            Segment::Line { from, to, axes, feed } => (vec![from, to], axes, feed, false),
+            Segment::Arc { ref arc, axes, feed } => {
+                let mut points = vec![arc.start()];
+                points.extend(arc.flatten(profile.arc_tolerance));
+                (points, axes, feed, false)
+            }
+            Segment::Dwell { seconds } => {
+                blocks[block] += seconds;
+                stop = true;
+                continue;
+            }
+        };
+
+        let power = match (rapid, state.spindle) {
+            (true, _) | (_, Spindle::Off) => 0.0,
+            _ => (state.spindle_speed.unwrap_or(0.0) / profile.max_spindle_speed).clamp(0.0, 1.0),
+        };
+        let dynamic = state.spindle == Spindle::CounterClockwise;
+
+        let velocities = if rapid {
+            axis_limits(profile.rapid_velocity, profile, |limits| limits.rapid_velocity)
+        } else {
+            axis_limits(profile.max_velocity, profile, |limits| limits.max_velocity)
+        };
+        let accelerations = axis_limits(profile.max_acceleration, profile, |limits| limits.max_acceleration);
+
+        let positions = interpolate(&points, axes);
+        for (line, position) in points.windows(2).zip(positions.windows(2)) {
+            let travel = delta(&position[0], &position[1]);
+            let length = feed_length(&travel);
+            if length <= 0.0 {
+                continue;
+            }
+
+            let mut direction = [0.0; AXES];
+            for (direction, travel) in direction.iter_mut().zip(travel.iter()) {
+                *direction = travel / length;
+            }
+
+            let velocity = limit(&direction, &velocities, feed.unwrap_or(Real::INFINITY)) / 60.0;
+            let acceleration = limit(&direction, &accelerations, Real::INFINITY);
+
+            let entry = match moves.last() {
+                Some(previous) if !stop => {
+                    junction(&previous.direction, &direction, acceleration, profile.junction_deviation)
+                            .min(previous.velocity)
+                            .min(velocity)
+                }
+                _ => 0.0,
+            };
+
+            moves.push(Move { block, from: line[0], to: line[1], length, direction, power, dynamic, velocity, acceleration, entry });
+            stop = false;
+        }
+    }
+
+    // Waiting for the spindle after the last move
+    for &(sync, delay) in &syncs[next..] {
+        blocks[sync] += delay;
+    }
+
+    // Make sure every move can decelerate to the entry of the next one and end at rest
+    let mut exit = 0.0;
+    for m in moves.iter_mut().rev() {
+        m.entry = m.entry.min((exit * exit + 2.0 * m.acceleration * m.length).sqrt());
+        exit = m.entry;
+    }
+
+    // Make sure every move can accelerate from its entry to the entry of the next one
+    for i in 1..moves.len() {
+        let previous = &moves[i - 1];
+        let reachable = (previous.entry * previous.entry + 2.0 * previous.acceleration * previous.length).sqrt();
+        moves[i].entry = moves[i].entry.min(reachable);
+    }
+
+    return Ok((blocks, moves));
+}
+
+/// Finds the blocks changing the spindle such that the machine has to stop, along with the time
+/// waited for the spindle afterwards.
+///
+/// Blocks which can not be executed are skipped, as they are reported when planning the moves.
+fn synchronizations(mut machine: Machine, program: &Program, profile: &MachineProfile) -> Vec<(usize, Real)> {
+    let mut syncs = Vec::new();
+
+    for (i, block) in program.iter().enumerate() {
+        let before = machine.state().clone();
+        if machine.execute(block).is_err() {
+            continue;
+        }
+
+        let state = machine.state();
+        let changes = before.diff(state);
+
+        let switched = changes.iter().any(|change| matches!(change, StateChange::Spindle(_)));
+        let adjusted = changes.iter().any(|change| matches!(change, StateChange::SpindleSpeed(_)));
+
+        if profile.laser_mode {
+            if switched {
+                syncs.push((i, 0.0));
+            }
+        } else if switched || adjusted {
+            let delay = if state.spindle != Spindle::Off { profile.spindle_delay } else { 0.0 };
+            syncs.push((i, delay));
+        }
+    }
+
+    return syncs;
+}
+
+/// The limits of all axes, with the ones of X, Y and Z given.
+fn axis_limits<F>(linear: Point3, profile: &MachineProfile, f: F) -> [Real; AXES]
+    where F: Fn(&AxisLimits) -> Real {
+    let mut limits = [linear.x, linear.y, linear.z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+    for (limit, (_, axis)) in limits[3..].iter_mut().zip(profile.axes.iter()) {
+        *limit = axis.as_ref().map_or(Real::INFINITY, &f);
+    }
+
+    return limits;
+}
+
+/// The positions of all axes at the points, with the other axes moving linearly along the path.
+fn interpolate(points: &[Point3], axes: AxisTravel) -> Vec<[Real; AXES]> {
+    let total = points.windows(2).map(|line| line[0].distance(line[1])).sum::<Real>();
+
+    let mut travelled = 0.0;
+    return points.iter()
+            .enumerate()
+            .map(|(i, point)| {
+                if i > 0 {
+                    travelled += points[i - 1].distance(*point);
+                }
+
+                // Moves of the other axes only are split into a single line
+                let t = if total > 0.0 { travelled / total } else { i as Real };
+
+                let mut position = [point.x, point.y, point.z, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+                for (value, (axis, from)) in position[3..].iter_mut().zip(axes.from.iter()) {
+                    *value = from + (axes.to[axis] - from) * t;
+                }
+                position
+            })
+            .collect();
+}
+
+fn delta(from: &[Real; AXES], to: &[Real; AXES]) -> [Real; AXES] {
+    let mut delta = [0.0; AXES];
+    for (i, delta) in delta.iter_mut().enumerate() {
+        *delta = to[i] - from[i];
+    }
+
+    return delta;
+}
+
+/// The length the feed rate applies to: the travel of X, Y and Z if these move, or otherwise of U,
+/// V and W, or otherwise of A, B and C.
+fn feed_length(travel: &[Real; AXES]) -> Real {
+    let norm = |axes: &[Real]| axes.iter().map(|value| value * value).sum::<Real>().sqrt();
+
+    return [&travel[0..3], &travel[6..9], &travel[3..6]].iter()
+            .map(|axes| norm(axes))
+            .find(|&length| length > 0.0)
+            .unwrap_or(0.0);
+}
+
+/// Limits a value along the direction such that no axis exceeds its own limit.
+fn limit(direction: &[Real; AXES], limits: &[Real; AXES], value: Real) -> Real {
+    return direction.iter().zip(limits.iter())
+            .filter(|&(&component, _)| component != 0.0)
+            .fold(value, |value, (&component, &limit)| value.min(limit / component.abs()));
+}
+
+/// The maximal velocity when passing from one direction to the other, using GRBL's junction
+/// deviation model.
+fn junction(previous: &[Real; AXES], next: &[Real; AXES], acceleration: Real, deviation: Real) -> Real {
+    let norm = |direction: &[Real; AXES]| direction.iter().map(|value| value * value).sum::<Real>().sqrt();
+    let dot = previous.iter().zip(next.iter()).map(|(a, b)| a * b).sum::<Real>();
+    let cos = -dot / (norm(previous) * norm(next));
+
+    if cos > 0.999_999 {
+        // Reversing the direction requires a full stop
+        return 0.0;
+    }
+
+    if cos < -0.999_999 {
+        return Real::INFINITY;
+    }
+
+    let sin = (0.5 * (1.0 - cos)).sqrt();
+    return (acceleration * deviation * sin / (1.0 - sin)).sqrt();
+}
+
+/// The time needed for a move with the given exit velocity, using a trapezoidal velocity profile.
+fn duration(m: &Move, exit: Real) -> Real {
+    let accelerate = (m.velocity * m.velocity - m.entry * m.entry) / (2.0 * m.acceleration);
+    let decelerate = (m.velocity * m.velocity - exit * exit) / (2.0 * m.acceleration);
+
+    if accelerate + decelerate <= m.length {
+        return (m.velocity - m.entry) / m.acceleration
+                + (m.velocity - exit) / m.acceleration
+                + (m.length - accelerate - decelerate) / m.velocity;
+    }
+
+    // The move is too short to reach its velocity
+    let peak = ((2.0 * m.acceleration * m.length + m.entry * m.entry + exit * exit) / 2.0).sqrt();
+    return (peak - m.entry) / m.acceleration + (peak - exit) / m.acceleration;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Axis;
+    use crate::parser::Parser;
+
+    fn time(lines: &[&str]) -> Estimate {
+        let profile = MachineProfile {
+            max_velocity: Point3::new(6000.0, 6000.0, 600.0),
+            rapid_velocity: Point3::new(6000.0, 6000.0, 600.0),
+            max_acceleration: Point3::new(100.0, 100.0, 10.0),
+            ..MachineProfile::default()
+        };
+
+        let program = Program::from(Parser::new().parse_all(lines.iter()).unwrap());
+        return estimate(&program, &profile).unwrap();
+    }
+
+    fn close(a: Real, b: Real) -> bool {
+        (a - b).abs() < 1e-6 + crate::EPSILON * b.abs()
+    }
+
+    #[test]
+    fn test_trapezoid() {
+        // Accelerating and decelerating takes a second and 50mm each
+        let estimate = time(&["G1 X1000 F6000"]);
+        assert!(close(estimate.total, 11.0));
+
+        // Short moves never reach their velocity
+        assert!(close(time(&["G1 X1 F6000"]).total, 0.2));
+
+        // Rapids use the maximal velocity of the slowest axis
+        assert!(close(time(&["G0 Z100"]).total, 11.0));
+    }
+
+    #[test]
+    fn test_rapids() {
+        let profile = MachineProfile {
+            max_velocity: Point3::new(6000.0, 6000.0, 600.0),
+            rapid_velocity: Point3::new(60000.0, 60000.0, 600.0),
+            max_acceleration: Point3::new(100.0, 100.0, 10.0),
+            ..MachineProfile::default()
+        };
+
+        let estimate = |line: &str| estimate(&Program::from(vec![Parser::new().parse(line).unwrap()]), &profile).unwrap().total;
+
+        // Rapids only accelerate and decelerate, as they never reach their velocity
+        assert!(close(estimate("G0 X1000"), 2.0 * Real::sqrt(10.0)));
+
+        // Feed moves are still limited by the maximal velocity
+        assert!(close(estimate("G1 X1000 F60000"), 11.0));
+    }
+
+    #[test]
+    fn test_junctions() {
+        let straight = time(&["G1 X500 F6000", "X1000"]);
+        assert!(close(straight.total, 11.0));
+        assert!(close(straight.blocks[0], 5.5));
+
+        let corner = time(&["G1 X500 F6000", "Y500"]);
+        assert!(corner.total > 11.0);
+        assert!(corner.total < 22.0);
+
+        let reverse = time(&["G1 X500 F6000", "X0"]);
+        assert!(close(reverse.total, 12.0));
+    }
+
+    #[test]
+    fn test_dwell() {
+        let estimate = time(&["G1 X500 F6000", "G4 P2.5", "X1000"]);
+
+        assert_eq!(estimate.blocks.len(), 3);
+        assert!(close(estimate.blocks[1], 2.5));
+        assert!(close(estimate.total, 14.5));
+    }
+
+    #[test]
+    fn test_spindle() {
+        let profile = MachineProfile {
+            max_velocity: Point3::new(6000.0, 6000.0, 600.0),
+            max_acceleration: Point3::new(100.0, 100.0, 10.0),
+            spindle_delay: 2.0,
+            ..MachineProfile::default()
+        };
+
+        let program = Program::from(Parser::new().parse_all(["M3 S1000", "G1 X500 F6000", "S500 X1000", "M5"].iter()).unwrap());
+
+        // Changing the speed stops the machine and waits for the spindle
+        let estimate = estimate(&program, &profile).unwrap();
+        assert!(close(estimate.blocks[0], 2.0));
+        assert!(close(estimate.blocks[2], 2.0 + 6.0));
+        assert!(close(estimate.total, 2.0 + 6.0 + 2.0 + 6.0));
+
+        // Lasers move through changes of the power
+        let laser = MachineProfile { laser_mode: true, ..profile };
+        let estimate = super::estimate(&program, &laser).unwrap();
+        assert!(close(estimate.total, 11.0));
+    }
+
+    #[test]
+    fn test_power() {
+        let profile = MachineProfile {
+            max_velocity: Point3::new(6000.0, 6000.0, 600.0),
+            max_acceleration: Point3::new(100.0, 100.0, 10.0),
+            laser_mode: true,
+            ..MachineProfile::default()
+        };
+
+        let power = |lines: &[&str]| {
+            let program = Program::from(Parser::new().parse_all(lines.iter()).unwrap());
+            return super::power(&program, &profile).unwrap();
+        };
+
+        let constant = power(&["M3 S500", "G0 X10", "G1 X1010 F6000"]);
+        assert_eq!(constant.len(), 2);
+        assert_eq!(constant[0].power, 0.0);
+        assert_eq!(constant[1].power, 0.5);
+
+        // Accelerating and decelerating at half the velocity takes 2 of the 11 seconds
+        let dynamic = power(&["M4 S500", "G1 X1000 F6000"]);
+        assert!(close(dynamic[0].power, 0.5 * 1000.0 / 11.0 / 100.0));
+    }
+
+    #[test]
+    fn test_feed_rate_modes() {
+        // Moving 100mm in half a minute, at 200mm/min, with a short acceleration
+        assert!(close(time(&["G93 G1 X100 F2"]).total, 30.0 + 200.0 / 60.0 / 100.0));
+
+        // Feeding 0.1mm per revolution at 1000rpm
+        assert!(close(time(&["M3 S1000", "G95 G1 X100 F0.1"]).total, 60.0 + 100.0 / 60.0 / 100.0));
+    }
+
+    #[test]
+    fn test_rotary() {
+        let profile = MachineProfile {
+            max_velocity: Point3::new(6000.0, 6000.0, 600.0),
+            max_acceleration: Point3::new(100.0, 100.0, 10.0),
+            ..MachineProfile::default()
+        }.with_axis(Axis::A, AxisLimits { max_velocity: 3600.0, rapid_velocity: 3600.0, max_acceleration: 60.0, travel: None });
+
+        let estimate = |lines: &[&str]| {
+            let program = Program::from(Parser::new().parse_all(lines.iter()).unwrap());
+            return estimate(&program, &profile).unwrap().total;
+        };
+
+        // The feed rate of rotary moves is given in degrees per minute
+        assert!(close(estimate(&["G1 A360 F1800"]), 12.5));
+
+        // Rotary axes move along, but slow down the other axes to their own limit
+        assert!(close(estimate(&["G1 X1000 A360 F6000"]), 11.0));
+        assert!(close(estimate(&["G1 X100 A3600 F6000"]), 61.0));
+
+        // Axes the machine has no limits for follow without slowing down
+        assert!(close(estimate(&["G1 X1000 B3600 F6000"]), 11.0));
+    }
+
+    #[test]
+    fn test_arc() {
+        let estimate = time(&["G2 X0 Y0 I100 F600"]);
+
+        // The chords are slightly shorter than the arc
+        assert!((estimate.total - (2.0 * crate::real::consts::PI * 100.0 / 10.0 + 0.1)).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,338 @@
+//! User annotations and bookmarks attached to blocks or layers of a program.
+//!
+//! Annotations are kept next to the program in a sidecar file (`part.gcode.annotations`), so the
+//! program itself stays untouched. The sidecar has one annotation per line with tab separated
+//! fields: the target (`block 12` or `layer 3`), the name, the note and the color. With the
+//! `serde` feature, annotations can be serialized to any other format as well.
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::printer::Layer;
+
+/// The extension appended to the file name of a program to get the one of its sidecar.
+pub const SIDECAR_EXTENSION: &str = "annotations";
+
+#[derive(Debug)]
+pub enum AnnotationError {
+    Syntax {
+        line: usize,
+        message: String,
+    },
+
+    Io(io::Error),
+}
+
+impl fmt::Display for AnnotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnotationError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            AnnotationError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for AnnotationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            AnnotationError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AnnotationError {
+    fn from(err: io::Error) -> Self {
+        AnnotationError::Io(err)
+    }
+}
+
+/// What an annotation is attached to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Target {
+    /// The block at the given index.
+    Block(usize),
+
+    /// The layer at the given index (see `Program::layers`).
+    Layer(usize),
+}
+
+impl Target {
+    /// The blocks covered by the target, given the layers of the program.
+    ///
+    /// Returns `None` for layers which do not exist.
+    pub fn blocks(&self, layers: &[Layer]) -> Option<Range<usize>> {
+        match *self {
+            Target::Block(index) => Some(index..index + 1),
+            Target::Layer(index) => layers.get(index).map(|layer| layer.blocks.clone()),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Block(index) => write!(f, "block {}", index),
+            Target::Layer(index) => write!(f, "layer {}", index),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, index) = s.split_once(' ').ok_or_else(|| format!("invalid target: {}", s))?;
+        let index = index.trim().parse().map_err(|_| format!("invalid index: {}", index))?;
+
+        return match kind {
+            "block" => Ok(Target::Block(index)),
+            "layer" => Ok(Target::Layer(index)),
+            _ => Err(format!("invalid target: {}", s)),
+        };
+    }
+}
+
+/// A color given by its red, green and blue components.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+/// Writes the color in hexadecimal notation, like `#ff8000`.
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#')
+                .filter(|hex| hex.len() == 6 && hex.is_ascii())
+                .ok_or_else(|| format!("invalid color: {}", s))?;
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| format!("invalid color: {}", s));
+
+        return Ok(Color::new(component(0)?, component(2)?, component(4)?));
+    }
+}
+
+/// A named mark on a block or layer, optionally with a note and a color for highlighting it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Annotation {
+    pub target: Target,
+    pub name: String,
+    pub note: Option<String>,
+    pub color: Option<Color>,
+}
+
+impl Annotation {
+    pub fn new<S>(target: Target, name: S) -> Self
+        where S: Into<String> {
+        Self {
+            target,
+            name: name.into(),
+            note: None,
+            color: None,
+        }
+    }
+
+    pub fn with_note<S>(mut self, note: S) -> Self
+        where S: Into<String> {
+        self.note = Some(note.into());
+        self
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+}
+
+/// The path of the sidecar file of a program.
+pub fn sidecar_path<P>(program: P) -> PathBuf
+    where P: AsRef<Path> {
+    let mut path = program.as_ref().as_os_str().to_owned();
+    path.push(".");
+    path.push(SIDECAR_EXTENSION);
+    return PathBuf::from(path);
+}
+
+/// Reads the annotations of the program at the given path from its sidecar.
+///
+/// A missing sidecar means there are no annotations.
+pub fn read_sidecar<P>(program: P) -> Result<Vec<Annotation>, AnnotationError>
+    where P: AsRef<Path> {
+    let text = match fs::read_to_string(sidecar_path(program)) {
+        Ok(text) => text,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    return parse(&text);
+}
+
+/// Writes the annotations of the program at the given path to its sidecar, removing the sidecar
+/// if there are none.
+pub fn write_sidecar<P>(program: P, annotations: &[Annotation]) -> Result<(), AnnotationError>
+    where P: AsRef<Path> {
+    let path = sidecar_path(program);
+
+    if annotations.is_empty() {
+        return match fs::remove_file(path) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => Ok(result?),
+        };
+    }
+
+    fs::write(path, format(annotations))?;
+    return Ok(());
+}
+
+/// Formats annotations in the sidecar format.
+pub fn format(annotations: &[Annotation]) -> String {
+    let mut text = String::new();
+
+    for annotation in annotations {
+        text.push_str(&format!("{}\t{}\t{}\t{}\n",
+                               annotation.target,
+                               escape(&annotation.name),
+                               annotation.note.as_deref().map(escape).unwrap_or_default(),
+                               annotation.color.map(|color| color.to_string()).unwrap_or_default()));
+    }
+
+    return text;
+}
+
+/// Parses annotations from the sidecar format. Empty lines are skipped.
+pub fn parse(text: &str) -> Result<Vec<Annotation>, AnnotationError> {
+    let mut annotations = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let syntax = |message: String| AnnotationError::Syntax { line: i + 1, message };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() != 4 {
+            return Err(syntax(format!("expected 4 fields, found {}", fields.len())));
+        }
+
+        annotations.push(Annotation {
+            target: fields[0].parse().map_err(syntax)?,
+            name: unescape(fields[1]).map_err(syntax)?,
+            note: Some(fields[2]).filter(|note| !note.is_empty()).map(unescape).transpose().map_err(syntax)?,
+            color: Some(fields[3]).filter(|color| !color.is_empty()).map(str::parse).transpose().map_err(syntax)?,
+        });
+    }
+
+    return Ok(annotations);
+}
+
+/// Escapes the characters separating fields and lines.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+
+    return escaped;
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            other => return Err(format!("invalid escape: \\{}", other.map(String::from).unwrap_or_default())),
+        }
+    }
+
+    return Ok(unescaped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations() -> Vec<Annotation> {
+        vec![
+            Annotation::new(Target::Block(12), "tool change"),
+            Annotation::new(Target::Layer(3), "pause\there")
+                    .with_note("insert nuts\nbefore continuing \\ check")
+                    .with_color(Color::new(255, 128, 0)),
+        ]
+    }
+
+    #[test]
+    fn test_format() {
+        let text = format(&annotations());
+        assert_eq!(text, "block 12\ttool change\t\t\nlayer 3\tpause\\there\tinsert nuts\\nbefore continuing \\\\ check\t#ff8000\n");
+        assert_eq!(parse(&text).unwrap(), annotations());
+
+        assert!(matches!(parse("block 1\tname\n"), Err(AnnotationError::Syntax { line: 1, .. })));
+        assert!(matches!(parse("\nsheet 1\tname\t\t\n"), Err(AnnotationError::Syntax { line: 2, .. })));
+        assert!(matches!(parse("block 1\tname\t\t#12345\n"), Err(AnnotationError::Syntax { .. })));
+    }
+
+    #[test]
+    fn test_sidecar() {
+        let program = std::env::temp_dir().join(format!("gcode-annotate-{}.gcode", std::process::id()));
+        assert_eq!(sidecar_path(&program).extension().unwrap(), "annotations");
+
+        assert!(read_sidecar(&program).unwrap().is_empty());
+
+        write_sidecar(&program, &annotations()).unwrap();
+        assert_eq!(read_sidecar(&program).unwrap(), annotations());
+
+        write_sidecar(&program, &[]).unwrap();
+        assert!(!sidecar_path(&program).exists());
+    }
+
+    #[test]
+    fn test_target_blocks() {
+        let layers = vec![Layer { blocks: 2..5, z: Some(0.2), extrusion: 1.0 }];
+
+        assert_eq!(Target::Block(7).blocks(&layers), Some(7..8));
+        assert_eq!(Target::Layer(0).blocks(&layers), Some(2..5));
+        assert_eq!(Target::Layer(1).blocks(&layers), None);
+    }
+}
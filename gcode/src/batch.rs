@@ -0,0 +1,254 @@
+//! Analysis of all programs in a directory tree.
+//!
+//! Files are parsed leniently, so malformed lines are reported as diagnostics instead of failing
+//! the whole file. The analysis itself is given as a function and runs on multiple threads, like
+//! the estimate of the duration (see `Batch::estimate`), as run by `gcode-batch`.
+
+use std::error;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::Real;
+use crate::analysis::{self, Estimate, MachineProfile};
+use crate::parser::{Parser, ParserError};
+use crate::program::Program;
+
+/// File extensions recognized as G-code programs, compared case-insensitively.
+pub const EXTENSIONS: &[&str] = &["gcode", "gco", "g", "nc", "ngc", "tap", "cnc"];
+
+/// The result of analyzing a single file.
+#[derive(Debug)]
+pub struct FileReport<T> {
+    pub path: PathBuf,
+
+    /// Number of blocks parsed from the file.
+    pub blocks: usize,
+
+    /// Errors of lines skipped while parsing.
+    pub diagnostics: Vec<ParserError>,
+
+    pub result: T,
+}
+
+/// Any error, as returned by analyses.
+pub type BoxError = Box<dyn error::Error + Send + Sync>;
+
+/// A file which could not be read or analyzed.
+#[derive(Debug)]
+pub struct Failure {
+    pub path: PathBuf,
+    pub error: BoxError,
+}
+
+/// The aggregated results of a batch run, ordered by path.
+#[derive(Debug)]
+pub struct Report<T> {
+    pub files: Vec<FileReport<T>>,
+    pub failures: Vec<Failure>,
+}
+
+impl<T> Report<T> {
+    /// The total number of blocks in all successfully analyzed files.
+    pub fn blocks(&self) -> usize {
+        self.files.iter().map(|file| file.blocks).sum()
+    }
+
+    /// The total number of diagnostics in all successfully analyzed files.
+    pub fn diagnostics(&self) -> usize {
+        self.files.iter().map(|file| file.diagnostics.len()).sum()
+    }
+
+    /// Sums up a value calculated from the result of each file, e.g. an estimated time.
+    pub fn total<F>(&self, value: F) -> Real
+        where F: Fn(&T) -> Real {
+        self.files.iter().map(|file| value(&file.result)).sum()
+    }
+}
+
+impl Report<Estimate> {
+    /// The estimated duration of all successfully analyzed files, in seconds.
+    pub fn time(&self) -> Real {
+        self.total(|estimate| estimate.total)
+    }
+}
+
+/// Runs an analysis on all programs in a directory tree.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    root: PathBuf,
+    threads: usize,
+}
+
+impl Batch {
+    /// Creates a batch for the given directory using one thread per available CPU.
+    pub fn new<P>(root: P) -> Self
+        where P: Into<PathBuf> {
+        Self {
+            root: root.into(),
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Finds all programs below the root directory, sorted by path.
+    pub fn files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        collect(&self.root, &mut files)?;
+        files.sort();
+
+        return Ok(files);
+    }
+
+    /// Parses every program and passes it to the analysis.
+    ///
+    /// Fails only if the directory tree can not be listed. Files which can not be read and
+    /// analyses returning an error are reported as failures.
+    pub fn run<F, T>(&self, analysis: F) -> io::Result<Report<T>>
+        where F: Fn(&Program) -> Result<T, BoxError> + Sync,
+              T: Send {
+        let files = self.files()?;
+
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(files.len()));
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(files.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let path = match files.get(index) {
+                            Some(path) => path,
+                            None => break,
+                        };
+
+                        let result = analyze(path, &analysis);
+                        results.lock().expect("Poisoned results").push((index, result));
+                    }
+                });
+            }
+        });
+
+        let mut results = results.into_inner().expect("Poisoned results");
+        results.sort_by_key(|&(index, _)| index);
+
+        let mut report = Report {
+            files: Vec::new(),
+            failures: Vec::new(),
+        };
+
+        for (index, result) in results {
+            let path = files[index].clone();
+            match result {
+                Ok((blocks, diagnostics, result)) => report.files.push(FileReport { path, blocks, diagnostics, result }),
+                Err(error) => report.failures.push(Failure { path, error }),
+            }
+        }
+
+        return Ok(report);
+    }
+
+    /// Estimates the duration of every program on the machine (see `analysis::estimate`).
+    pub fn estimate(&self, profile: &MachineProfile) -> io::Result<Report<Estimate>> {
+        self.run(|program| Ok(analysis::estimate(program, profile)?))
+    }
+}
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect(&path, files)?;
+        } else if path.extension().and_then(OsStr::to_str)
+                .is_some_and(|extension| EXTENSIONS.iter().any(|known| extension.eq_ignore_ascii_case(known))) {
+            files.push(path);
+        }
+    }
+
+    return Ok(());
+}
+
+fn analyze<F, T>(path: &Path, analysis: &F) -> Result<(usize, Vec<ParserError>, T), BoxError>
+    where F: Fn(&Program) -> Result<T, BoxError> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut blocks = Parser::new_lenient()
+            .with_source(path.display().to_string())
+            .into_blocks(reader);
+    let program = blocks.by_ref().collect::<Result<Program, _>>()?;
+    let diagnostics = blocks.into_parser().take_diagnostics();
+
+    let result = analysis(&program)?;
+
+    return Ok((program.len(), diagnostics, result));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let root = std::env::temp_dir().join(format!("gcode-batch-{}", std::process::id()));
+        fs::create_dir_all(root.join("nested")).unwrap();
+
+        fs::write(root.join("a.nc"), "G0 X1\nG1 X2 F100\n").unwrap();
+        fs::write(root.join("nested/b.GCODE"), "G0 X1\nG1 X#\nM2\n").unwrap();
+        fs::write(root.join("nested/c.ngc"), "M3\n").unwrap();
+        fs::write(root.join("notes.txt"), "G0 X1\n").unwrap();
+
+        let report = Batch::new(&root)
+                .threads(2)
+                .run(|program| match program.len() {
+                    1 => Err("too short".into()),
+                    len => Ok(len as Real * 1.5),
+                })
+                .unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(report.files.iter().map(|file| file.path.strip_prefix(&root).unwrap()).collect::<Vec<_>>(),
+                   vec![Path::new("a.nc"), Path::new("nested/b.GCODE")]);
+        assert_eq!(report.blocks(), 4);
+        assert_eq!(report.diagnostics(), 1);
+        assert_eq!(report.total(|time| *time), 6.0);
+
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].error.to_string(), "too short");
+    }
+
+    #[test]
+    fn test_estimate() {
+        let root = std::env::temp_dir().join(format!("gcode-batch-estimate-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+
+        fs::write(root.join("a.nc"), "G1 X100 F6000
+").unwrap();
+        fs::write(root.join("b.nc"), "G1 X50 F3000
+G1 X0
+").unwrap();
+        fs::write(root.join("c.nc"), "G2 X10
+").unwrap();
+
+        let profile = MachineProfile::default();
+        let report = Batch::new(&root).estimate(&profile).unwrap();
+        let a = analysis::estimate(&Parser::new().parse_all("G1 X100 F6000".lines()).unwrap().into_iter().collect(), &profile).unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(report.files[0].result.total, a.total);
+        assert_eq!(report.time(), report.files.iter().map(|file| file.result.total).sum::<Real>());
+        assert!(report.time() > 3.0);
+        assert_eq!(report.failures.len(), 1);
+    }
+}
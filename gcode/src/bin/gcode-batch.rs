@@ -0,0 +1,69 @@
+//! Estimates the duration of all programs in a directory tree.
+//!
+//! Usage: `gcode-batch DIR [--threads N]`
+//!
+//! Prints the number of blocks, diagnostics and the estimated duration of each program, followed
+//! by the files which could not be analyzed and the totals. Exits with 1 if any file failed.
+
+// Functions with more than a single expression return explicitly, like in the library
+#![allow(clippy::needless_return)]
+
+use std::env;
+use std::error::Error;
+use std::process;
+
+use gcode::Real;
+use gcode::analysis::MachineProfile;
+use gcode::batch::Batch;
+
+const USAGE: &str = "usage: gcode-batch DIR [--threads N]";
+
+/// Formats seconds as hours, minutes and seconds.
+fn duration(seconds: Real) -> String {
+    let seconds = seconds.round() as u64;
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+fn run() -> Result<bool, Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let mut root = None;
+    let mut threads = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => threads = Some(args.next().ok_or(USAGE)?.parse::<usize>()?),
+            _ if root.is_none() && !arg.starts_with("--") => root = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let mut batch = Batch::new(root.ok_or(USAGE)?);
+    if let Some(threads) = threads {
+        batch = batch.threads(threads);
+    }
+
+    let report = batch.estimate(&MachineProfile::default())?;
+    for file in &report.files {
+        println!("{}\t{} blocks\t{} diagnostics\t{}", file.path.display(), file.blocks, file.diagnostics.len(),
+                 duration(file.result.total));
+    }
+    for failure in &report.failures {
+        println!("{}\tfailed: {}", failure.path.display(), failure.error);
+    }
+
+    println!("{} files\t{} blocks\t{} diagnostics\t{}\t{} failed", report.files.len(), report.blocks(),
+             report.diagnostics(), duration(report.time()), report.failures.len());
+
+    return Ok(report.failures.is_empty());
+}
+
+fn main() {
+    match run() {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    }
+}
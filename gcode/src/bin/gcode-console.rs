@@ -0,0 +1,124 @@
+//! Interactive console for controllers reachable by telnet, like Smoothieware and ESP3D.
+//!
+//! Usage: `gcode-console HOST[:PORT] [--dialect NAME] [--checksums] [--macro NAME=FILE]...`
+//!
+//! Lines entered are sent to the controller and its replies printed. Lines starting with `:` are
+//! commands of the console:
+//!
+//! - `:complete PREFIX` lists the completions of a line, like `:complete G38` or `:complete @p`.
+//!   Lines are read once complete, so there is no completion by pressing tab.
+//! - `:history` lists the lines entered so far, which are repeated by `!!` or `!NUMBER`.
+//! - `:quit` exits, as does the end of the input.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::time::Duration;
+
+use gcode::console::Console;
+use gcode::dialect::Dialect;
+use gcode::parser::Parser;
+use gcode::program::Program;
+use gcode::response::Response;
+use gcode::transport::{Telnet, TELNET_PORT};
+
+const USAGE: &str = "usage: gcode-console HOST[:PORT] [--dialect NAME] [--checksums] [--macro NAME=FILE]...";
+
+fn dialect(name: &str) -> Option<Dialect> {
+    match name.to_ascii_lowercase().as_str() {
+        "generic" => Some(Dialect::Generic),
+        "linuxcnc" => Some(Dialect::LinuxCnc),
+        "grbl" => Some(Dialect::Grbl),
+        "marlin" => Some(Dialect::Marlin),
+        "reprap" => Some(Dialect::RepRap),
+        "fanuc" => Some(Dialect::Fanuc),
+        _ => None,
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let mut addr = None;
+    let mut dialect_name = None;
+    let mut checksums = false;
+    let mut macros = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dialect" => dialect_name = Some(args.next().ok_or(USAGE)?),
+            "--checksums" => checksums = true,
+            "--macro" => macros.push(args.next().ok_or(USAGE)?),
+            _ if addr.is_none() && !arg.starts_with("--") => addr = Some(arg),
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let addr = addr.ok_or(USAGE)?;
+    let addr = if addr.contains(':') { addr } else { format!("{}:{}", addr, TELNET_PORT) };
+    let dialect = match dialect_name {
+        Some(name) => dialect(&name).ok_or_else(|| format!("unknown dialect: {}", name))?,
+        None => Dialect::default(),
+    };
+
+    let transport = Telnet::connect(addr.as_str(), Duration::from_secs(5))?;
+    let mut console = Console::new(transport).with_dialect(dialect).with_checksums(checksums);
+
+    for spec in macros {
+        let (name, path) = spec.split_once('=').ok_or(USAGE)?;
+        let source = fs::read_to_string(path)?;
+        let blocks = Parser::new().with_dialect(dialect).parse_all(source.lines())?;
+        console = console.with_macro(name, &Program::from(blocks));
+    }
+
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        write!(stdout, "> ")?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if let Some(prefix) = line.trim_start().strip_prefix(":complete ") {
+            for completion in console.complete(prefix) {
+                writeln!(stdout, "{}", completion)?;
+            }
+            continue;
+        }
+
+        match line.trim() {
+            ":quit" => return Ok(()),
+            ":history" => {
+                for (number, line) in console.history().iter().enumerate() {
+                    writeln!(stdout, "{:>4}  {}", number + 1, line)?;
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        match console.execute(line) {
+            Ok(replies) => {
+                for reply in replies.iter().filter(|reply| !reply.is_ok()) {
+                    match reply {
+                        Response::Other(text) => writeln!(stdout, "{}", text)?,
+                        reply => writeln!(stdout, "{:?}", reply)?,
+                    }
+                }
+            }
+            Err(err) => writeln!(stdout, "error: {}", err)?,
+        }
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,323 @@
+//! Fluent construction of programs.
+//!
+//! Each call to a motion method (`g0` to `g3`) starts a new block, to which the following words
+//! are added:
+//!
+//! ```
+//! use gcode::program::Program;
+//!
+//! let program = Program::builder()
+//!     .g0().z(5.0)
+//!     .g1().x(10.0).y(5.0).f(1500.0)
+//!     .build();
+//!
+//! assert_eq!(program.to_string(), "G0 Z5\nG1 X10 Y5 F1500\n");
+//! ```
+//!
+//! Giving the same axis twice within a block does not compile, which covers X, Y, Z, the rotary
+//! axes A, B, C, the secondary linear axes U, V, W and the extruder axis E:
+//!
+//! ```compile_fail
+//! use gcode::program::Program;
+//!
+//! let program = Program::builder().g1().x(10.0).x(5.0).build();
+//! ```
+//!
+//! ```compile_fail
+//! use gcode::program::Program;
+//!
+//! let program = Program::builder().g1().x(10.0).e(1.0).e(2.0).build();
+//! ```
+//!
+//! ```compile_fail
+//! use gcode::program::Program;
+//!
+//! let program = Program::builder().g1().u(1.0).u(2.0).build();
+//! ```
+//!
+//! All other words replace an earlier word with the same letter in the block.
+
+use std::marker::PhantomData;
+
+use crate::Real;
+use crate::parser::{Block, Word};
+use crate::program::Program;
+
+/// Marks an axis not given in the block yet.
+#[derive(Debug)]
+pub enum Unset {}
+
+/// Marks an axis already given in the block.
+#[derive(Debug)]
+pub enum Set {}
+
+/// Builds a program block by block.
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    blocks: Vec<Block>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a block without any words, e.g. to continue the current motion mode.
+    pub fn block(self) -> BlockBuilder<Unset, Unset, Unset> {
+        BlockBuilder {
+            program: self,
+            words: Vec::new(),
+            axes: PhantomData,
+            more_axes: PhantomData,
+        }
+    }
+
+    /// Starts a block with a rapid move.
+    pub fn g0(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.block().g(0.0)
+    }
+
+    /// Starts a block with a linear move.
+    pub fn g1(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.block().g(1.0)
+    }
+
+    /// Starts a block with a clockwise arc.
+    pub fn g2(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.block().g(2.0)
+    }
+
+    /// Starts a block with a counter-clockwise arc.
+    pub fn g3(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.block().g(3.0)
+    }
+
+    /// Adds an already complete block.
+    pub fn push(mut self, block: Block) -> Self {
+        self.blocks.push(block);
+        self
+    }
+
+    pub fn build(self) -> Program {
+        Program::from(self.blocks)
+    }
+}
+
+/// Builds a single block, tracking which axes are given in its type.
+///
+/// Besides X, Y and Z, the rotary axes A, B and C, the secondary linear axes U, V and W and the
+/// extruder axis E of printers are tracked.
+#[derive(Debug)]
+pub struct BlockBuilder<X, Y, Z, A = Unset, B = Unset, C = Unset, U = Unset, V = Unset, W = Unset, E = Unset> {
+    program: ProgramBuilder,
+    words: Vec<Word>,
+    axes: PhantomData<(X, Y, Z, A, B, C)>,
+    more_axes: PhantomData<(U, V, W, E)>,
+}
+
+impl<X, Y, Z, A, B, C, U, V, W, E> BlockBuilder<X, Y, Z, A, B, C, U, V, W, E> {
+    /// Adds a word, replacing an earlier one with the same letter unless it is a G or M code.
+    fn word(mut self, mnemonic: char, value: Real) -> Self {
+        if mnemonic != 'G' && mnemonic != 'M' {
+            self.words.retain(|word| word.mnemonic() != mnemonic);
+        }
+
+        self.words.push(Word::new(mnemonic, value));
+        self
+    }
+
+    /// Adds a word and marks an axis as given.
+    fn axis<X2, Y2, Z2, A2, B2, C2, U2, V2, W2, E2>(self, mnemonic: char, value: Real) -> BlockBuilder<X2, Y2, Z2, A2, B2, C2, U2, V2, W2, E2> {
+        let block = self.word(mnemonic, value);
+        BlockBuilder {
+            program: block.program,
+            words: block.words,
+            axes: PhantomData,
+            more_axes: PhantomData,
+        }
+    }
+
+    /// Adds a G code to the block.
+    pub fn g(self, code: Real) -> Self {
+        self.word('G', code)
+    }
+
+    /// Adds an M code to the block.
+    pub fn m(self, code: u32) -> Self {
+        self.word('M', code as Real)
+    }
+
+    pub fn i(self, value: Real) -> Self {
+        self.word('I', value)
+    }
+
+    pub fn j(self, value: Real) -> Self {
+        self.word('J', value)
+    }
+
+    pub fn k(self, value: Real) -> Self {
+        self.word('K', value)
+    }
+
+    pub fn r(self, value: Real) -> Self {
+        self.word('R', value)
+    }
+
+    pub fn f(self, value: Real) -> Self {
+        self.word('F', value)
+    }
+
+    pub fn s(self, value: Real) -> Self {
+        self.word('S', value)
+    }
+
+    pub fn p(self, value: Real) -> Self {
+        self.word('P', value)
+    }
+
+    pub fn t(self, tool: u32) -> Self {
+        self.word('T', tool as Real)
+    }
+
+    /// Finishes the block and continues with the program.
+    pub fn end(self) -> ProgramBuilder {
+        let mut program = self.program;
+        program.blocks.push(Block::new(self.words));
+        return program;
+    }
+
+    /// Finishes the block and starts another one without any words.
+    pub fn block(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.end().block()
+    }
+
+    /// Finishes the block and starts another one with a rapid move.
+    pub fn g0(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.end().g0()
+    }
+
+    /// Finishes the block and starts another one with a linear move.
+    pub fn g1(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.end().g1()
+    }
+
+    /// Finishes the block and starts another one with a clockwise arc.
+    pub fn g2(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.end().g2()
+    }
+
+    /// Finishes the block and starts another one with a counter-clockwise arc.
+    pub fn g3(self) -> BlockBuilder<Unset, Unset, Unset> {
+        self.end().g3()
+    }
+
+    /// Finishes the block and the program.
+    pub fn build(self) -> Program {
+        self.end().build()
+    }
+}
+
+impl<Y, Z, A, B, C, U, V, W, E> BlockBuilder<Unset, Y, Z, A, B, C, U, V, W, E> {
+    pub fn x(self, value: Real) -> BlockBuilder<Set, Y, Z, A, B, C, U, V, W, E> {
+        self.axis('X', value)
+    }
+}
+
+impl<X, Z, A, B, C, U, V, W, E> BlockBuilder<X, Unset, Z, A, B, C, U, V, W, E> {
+    pub fn y(self, value: Real) -> BlockBuilder<X, Set, Z, A, B, C, U, V, W, E> {
+        self.axis('Y', value)
+    }
+}
+
+impl<X, Y, A, B, C, U, V, W, E> BlockBuilder<X, Y, Unset, A, B, C, U, V, W, E> {
+    pub fn z(self, value: Real) -> BlockBuilder<X, Y, Set, A, B, C, U, V, W, E> {
+        self.axis('Z', value)
+    }
+}
+
+impl<X, Y, Z, B, C, U, V, W, E> BlockBuilder<X, Y, Z, Unset, B, C, U, V, W, E> {
+    /// Adds the rotary axis around X, in degrees.
+    pub fn a(self, value: Real) -> BlockBuilder<X, Y, Z, Set, B, C, U, V, W, E> {
+        self.axis('A', value)
+    }
+}
+
+impl<X, Y, Z, A, C, U, V, W, E> BlockBuilder<X, Y, Z, A, Unset, C, U, V, W, E> {
+    /// Adds the rotary axis around Y, in degrees.
+    pub fn b(self, value: Real) -> BlockBuilder<X, Y, Z, A, Set, C, U, V, W, E> {
+        self.axis('B', value)
+    }
+}
+
+impl<X, Y, Z, A, B, U, V, W, E> BlockBuilder<X, Y, Z, A, B, Unset, U, V, W, E> {
+    /// Adds the rotary axis around Z, in degrees.
+    pub fn c(self, value: Real) -> BlockBuilder<X, Y, Z, A, B, Set, U, V, W, E> {
+        self.axis('C', value)
+    }
+}
+
+impl<X, Y, Z, A, B, C, V, W, E> BlockBuilder<X, Y, Z, A, B, C, Unset, V, W, E> {
+    /// Adds the linear axis parallel to X.
+    pub fn u(self, value: Real) -> BlockBuilder<X, Y, Z, A, B, C, Set, V, W, E> {
+        self.axis('U', value)
+    }
+}
+
+impl<X, Y, Z, A, B, C, U, W, E> BlockBuilder<X, Y, Z, A, B, C, U, Unset, W, E> {
+    /// Adds the linear axis parallel to Y.
+    pub fn v(self, value: Real) -> BlockBuilder<X, Y, Z, A, B, C, U, Set, W, E> {
+        self.axis('V', value)
+    }
+}
+
+impl<X, Y, Z, A, B, C, U, V, E> BlockBuilder<X, Y, Z, A, B, C, U, V, Unset, E> {
+    /// Adds the linear axis parallel to Z.
+    pub fn w(self, value: Real) -> BlockBuilder<X, Y, Z, A, B, C, U, V, Set, E> {
+        self.axis('W', value)
+    }
+}
+
+impl<X, Y, Z, A, B, C, U, V, W> BlockBuilder<X, Y, Z, A, B, C, U, V, W, Unset> {
+    /// Adds the extruder axis of printers.
+    pub fn e(self, value: Real) -> BlockBuilder<X, Y, Z, A, B, C, U, V, W, Set> {
+        self.axis('E', value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_builder() {
+        let program = Program::builder()
+                .block().g(21.0).g(90.0)
+                .g0().z(5.0)
+                .g1().z(-1.0).f(100.0)
+                .g2().x(10.0).y(0.0).i(5.0).f(300.0).f(400.0)
+                .block().x(0.0)
+                .g0().a(90.0).b(-45.0).c(180.0)
+                .g1().u(1.0).v(2.0).w(-3.0).x(4.0)
+                .g1().x(20.0).e(1.5).f(1800.0)
+                .end()
+                .push(Block::new(vec![Word::new('M', 2.0)]))
+                .build();
+
+        let expected = Parser::new().parse_all([
+            "G21 G90",
+            "G0 Z5",
+            "G1 Z-1 F100",
+            "G2 X10 Y0 I5 F400",
+            "X0",
+            "G0 A90 B-45 C180",
+            "G1 U1 V2 W-3 X4",
+            "G1 X20 E1.5 F1800",
+            "M2",
+        ].iter()).unwrap();
+
+        assert_eq!(program, Program::from(expected.into_iter()
+                .map(|block| Block::new(block.words().to_vec()))
+                .collect::<Vec<_>>()));
+    }
+}
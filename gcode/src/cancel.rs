@@ -0,0 +1,146 @@
+//! Cooperative cancellation of long running operations.
+//!
+//! Operations working on iterators - like `Parser::parse_all`, `Parser::into_blocks` or
+//! `Machine::into_absolute` - are cancelled by guarding their input or output with a token. The
+//! guarded iterator ends as soon as the token is cancelled or its deadline has passed, so
+//! everything processed up to this point is returned as a partial result.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A token shared between an operation and the code wishing to cancel it.
+///
+/// Clones of a token share the cancellation state, so cancelling one of them cancels all.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a token which is cancelled at the given point in time, if not before.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(match self.deadline {
+            Some(current) => current.min(deadline),
+            None => deadline,
+        });
+        self
+    }
+
+    /// Returns a token which is cancelled after the given time from now, if not before.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Checks if the token was cancelled explicitly or its deadline has passed.
+    pub fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        return match self.deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        };
+    }
+
+    /// Wraps an iterator to end as soon as this token is cancelled.
+    pub fn guard<I>(&self, iter: I) -> Guarded<I::IntoIter>
+        where I: IntoIterator {
+        Guarded {
+            inner: iter.into_iter(),
+            token: self.clone(),
+        }
+    }
+}
+
+/// An iterator ending as soon as its token is cancelled.
+///
+/// The token is checked before each element is taken from the inner iterator, so no element is
+/// consumed and then dropped.
+#[derive(Debug, Clone)]
+pub struct Guarded<I> {
+    inner: I,
+    token: CancellationToken,
+}
+
+impl<I> Guarded<I> {
+    /// Whether the iteration ended, or will end, because of cancellation.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I> Iterator for Guarded<I>
+    where I: Iterator {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.token.is_cancelled() {
+            return None;
+        }
+
+        return self.inner.next();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_cancel() {
+        let token = CancellationToken::new();
+        let other = token.clone();
+
+        assert!(!token.is_cancelled());
+        other.cancel();
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let token = CancellationToken::new().with_timeout(Duration::from_secs(3600));
+        assert!(!token.is_cancelled());
+
+        let token = token.with_deadline(Instant::now());
+        assert!(token.is_cancelled());
+        assert!(token.clone().with_timeout(Duration::from_secs(3600)).is_cancelled());
+    }
+
+    #[test]
+    fn test_guard() {
+        let token = CancellationToken::new();
+
+        let mut lines = token.guard(vec!["G0 X1", "G0 X2", "G0 X3"]);
+        let mut parser = Parser::new();
+
+        let first = parser.parse_all(lines.by_ref().take(1)).unwrap();
+        assert_eq!(first.len(), 1);
+
+        token.cancel();
+
+        // The remaining lines are left untouched
+        assert!(parser.parse_all(lines.by_ref()).unwrap().is_empty());
+        assert!(lines.is_cancelled());
+        assert_eq!(lines.into_inner().count(), 2);
+    }
+}
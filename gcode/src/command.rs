@@ -0,0 +1,916 @@
+//! Typed commands classified from the words of a block.
+//!
+//! A block may contain multiple commands, like `G90 G21 G0 X10`. Parameter words (axes, feed
+//! rate, ...) are attached to the command consuming them. Every word which can not be classified
+//! is kept as `Command::Unknown`, so converting the commands back into a block never loses a word
+//! - although their order may change.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::Real;
+use crate::geometry::{AxisValues, Units};
+use crate::parser::{Block, Word};
+
+/// The target of a linear move. Missing axes keep their current position.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Move {
+    pub x: Option<Real>,
+    pub y: Option<Real>,
+    pub z: Option<Real>,
+
+    /// The targets of the axes besides X, Y and Z.
+    pub axes: AxisValues<Option<Real>>,
+
+    pub f: Option<Real>,
+}
+
+/// The target and center of an arc move, given either by the center offset (`I`, `J`, `K`) or
+/// the radius (`R`). Moving along the axis perpendicular to the plane makes the arc a helix, which
+/// may wind around several times (`P`).
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Arc {
+    pub x: Option<Real>,
+    pub y: Option<Real>,
+    pub z: Option<Real>,
+
+    /// The targets of the axes besides X, Y and Z, which move linearly along the arc.
+    pub axes: AxisValues<Option<Real>>,
+
+    pub i: Option<Real>,
+    pub j: Option<Real>,
+    pub k: Option<Real>,
+    pub r: Option<Real>,
+
+    /// The number of turns, of which all but the last are full circles.
+    pub p: Option<Real>,
+
+    pub f: Option<Real>,
+}
+
+/// A set of axis coordinates, used for homing and setting the position.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Axes {
+    pub x: Option<Real>,
+    pub y: Option<Real>,
+    pub z: Option<Real>,
+}
+
+/// The plane arcs are drawn in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Plane {
+    XY,
+    ZX,
+    YZ,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DistanceMode {
+    Absolute,
+    Relative,
+}
+
+/// How feed rates are given.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FeedRateMode {
+    /// Moves take one divided by the feed rate minutes (G93).
+    InverseTime,
+
+    /// In units per minute (G94).
+    UnitsPerMinute,
+
+    /// In units per revolution of the spindle (G95).
+    UnitsPerRevolution,
+}
+
+/// How X words are interpreted on lathes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LatheMode {
+    /// X words give the diameter of the workpiece (G7).
+    Diameter,
+
+    /// X words give the radius of the workpiece (G8).
+    Radius,
+}
+
+/// The side of the path a tool is offset to, looking in the direction of motion.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// The diameter cutter compensation offsets the path by.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CompensationDiameter {
+    /// The diameter of the tool with the given number in the tool table, or of the tool in the
+    /// spindle if no number is given (G41, G42).
+    Tool(Option<u32>),
+
+    /// The given diameter (G41.1, G42.1).
+    Given(Real),
+}
+
+/// The length a tool length offset shifts the Z axis by.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LengthOffset {
+    /// The length of the tool with the given number in the tool table, or of the tool in the
+    /// spindle if no number is given (G43).
+    Tool(Option<u32>),
+
+    /// The given length (G43.1).
+    Given(Real),
+}
+
+/// How G10 sets the origin of a coordinate system.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum WorkOffsetMode {
+    /// The axis words give the origin in machine coordinates (L2).
+    Origin,
+
+    /// The axis words give the coordinates the current position has afterwards (L20).
+    Position,
+}
+
+/// The positions stored for the moves of G28 and G30.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Predefined {
+    /// G28, the machine origin unless stored otherwise
+    Home,
+
+    /// G30
+    Secondary,
+}
+
+/// How a probing move (G38.2 to G38.5) trips the probe.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Probing {
+    /// Whether the probe trips when losing contact instead of making it (G38.4, G38.5).
+    pub away: bool,
+
+    /// Whether completing the move without tripping the probe is an error (G38.2, G38.4).
+    pub required: bool,
+}
+
+impl Probing {
+    const CODES: [Real; 4] = [38.2, 38.3, 38.4, 38.5];
+
+    fn from_code(code: Real) -> Option<Self> {
+        let index = Self::CODES.iter().position(|&known| known == code)?;
+        return Some(Self {
+            away: index >= 2,
+            required: index % 2 == 0,
+        });
+    }
+
+    pub fn code(&self) -> Real {
+        Self::CODES[usize::from(self.away) * 2 + usize::from(!self.required)]
+    }
+}
+
+/// The codes of the coordinate systems following G59, numbered from 7.
+const EXTENDED_COORDINATE_SYSTEMS: [Real; 3] = [59.1, 59.2, 59.3];
+
+/// The canned cycles for drilling, tapping and boring.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CycleKind {
+    /// G81
+    Drill,
+    /// G82
+    DrillDwell,
+    /// G83
+    PeckDrill,
+    /// G84
+    Tap,
+    /// G85
+    Bore,
+    /// G86
+    BoreSpindleStop,
+    /// G87
+    BackBore,
+    /// G88
+    BoreManual,
+    /// G89
+    BoreDwell,
+}
+
+impl CycleKind {
+    /// The kind of cycle started by a G code.
+    pub fn from_code(code: u32) -> Option<Self> {
+        return match code {
+            81 => Some(CycleKind::Drill),
+            82 => Some(CycleKind::DrillDwell),
+            83 => Some(CycleKind::PeckDrill),
+            84 => Some(CycleKind::Tap),
+            85 => Some(CycleKind::Bore),
+            86 => Some(CycleKind::BoreSpindleStop),
+            87 => Some(CycleKind::BackBore),
+            88 => Some(CycleKind::BoreManual),
+            89 => Some(CycleKind::BoreDwell),
+            _ => None,
+        };
+    }
+
+    /// The G code starting the cycle.
+    pub fn code(&self) -> u32 {
+        match self {
+            CycleKind::Drill => 81,
+            CycleKind::DrillDwell => 82,
+            CycleKind::PeckDrill => 83,
+            CycleKind::Tap => 84,
+            CycleKind::Bore => 85,
+            CycleKind::BoreSpindleStop => 86,
+            CycleKind::BackBore => 87,
+            CycleKind::BoreManual => 88,
+            CycleKind::BoreDwell => 89,
+        }
+    }
+}
+
+/// The parameters of a canned cycle given in a block. Parameters missing from the block keep
+/// their values from the previous cycle.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Cycle {
+    /// The position of the hole.
+    pub x: Option<Real>,
+    pub y: Option<Real>,
+
+    /// The bottom of the hole.
+    pub z: Option<Real>,
+
+    /// The retract plane.
+    pub r: Option<Real>,
+
+    /// The depth of each peck.
+    pub q: Option<Real>,
+
+    /// The dwell at the bottom of the hole, in seconds.
+    pub p: Option<Real>,
+
+    pub f: Option<Real>,
+
+    /// The number of repetitions.
+    pub l: Option<u32>,
+}
+
+/// The height canned cycles retract to after each hole.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ReturnMode {
+    /// G98, the height before the cycle started
+    Initial,
+    /// G99, the retract plane
+    Retract,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Command {
+    /// G0
+    RapidMove(Move),
+    /// G1
+    LinearMove(Move),
+    /// G2
+    ClockwiseArc(Arc),
+    /// G3
+    CounterClockwiseArc(Arc),
+    /// Axis words without a motion command, continuing the active motion mode. As this mode may
+    /// be an arc, the arc parameters are kept as well.
+    ModalMove(Arc),
+    /// G4
+    Dwell { seconds: Real },
+    /// G7 and G8
+    SetLatheMode(LatheMode),
+    /// G17, G18 and G19
+    SelectPlane(Plane),
+    /// G20 and G21
+    SetUnits(Units),
+    /// G10 L2 and G10 L20, with system 0 being the active one
+    SetWorkOffset { system: u8, mode: WorkOffsetMode, axes: Axes },
+    /// G28 and G30, passing the given intermediate point
+    GoToPredefined(Predefined, Axes),
+    /// G28.1 and G30.1, storing the current position
+    StorePredefined(Predefined),
+    /// G33, moving along with the spindle by the given distance per revolution (K)
+    SynchronizedMove { target: Move, pitch: Real },
+    /// G38.2 to G38.5, moving towards the target until the probe trips
+    Probe(Probing, Move),
+    /// G40
+    CancelCompensation,
+    /// G41, G42, G41.1 and G42.1
+    Compensation { side: Side, diameter: CompensationDiameter },
+    /// G43 and G43.1
+    ToolLengthOffset(LengthOffset),
+    /// G49
+    CancelToolLengthOffset,
+    /// G54 to G59 and G59.1 to G59.3, numbered from 1 to 9
+    SelectCoordinateSystem(u8),
+    /// G80
+    CancelCycle,
+    /// G81 to G89. Axis words without a motion command repeat the active cycle at another
+    /// position (see `ModalMove`).
+    Cycle(CycleKind, Cycle),
+    /// G90 and G91
+    SetDistanceMode(DistanceMode),
+    /// G92
+    SetPosition(Axes),
+    /// G93, G94 and G95
+    SetFeedRateMode(FeedRateMode),
+    /// G92.1, resetting the G92 offset to zero
+    ClearPositionOffset,
+    /// G92.2, ignoring the G92 offset until restored
+    SuspendPositionOffset,
+    /// G92.3
+    RestorePositionOffset,
+    /// G96, keeping the surface speed given by S (in meters or feet per minute) constant up to
+    /// the spindle speed limit given by D
+    ConstantSurfaceSpeed { speed: Option<Real>, limit: Option<Real> },
+    /// G97, ending constant surface speed
+    ConstantSpindleSpeed,
+    /// G98 and G99
+    SetReturnMode(ReturnMode),
+    /// M0 and M1
+    Pause { optional: bool },
+    /// M2 and M30
+    ProgramEnd { rewind: bool },
+    /// M3 and M4
+    SpindleOn { clockwise: bool, speed: Option<Real> },
+    /// M5
+    SpindleOff,
+    /// M6
+    ToolChange { tool: Option<u32> },
+    /// M7 and M8
+    CoolantOn { mist: bool },
+    /// M9
+    CoolantOff,
+    /// F without a move
+    SetFeedRate(Real),
+    /// S without turning on the spindle
+    SetSpindleSpeed(Real),
+    /// T without a tool change
+    SelectTool(u32),
+    /// A word not covered by any of the commands above.
+    Unknown(Word),
+}
+
+impl Command {
+    /// Classifies the words of a block into commands.
+    pub fn from_block(block: &Block) -> Vec<Command> {
+        let mut parameters = Parameters::default();
+        let mut codes = Vec::new();
+        let mut commands = Vec::new();
+
+        for word in block.words() {
+            match word.mnemonic() {
+                'G' | 'M' => codes.push(*word),
+                _ => {
+                    if !parameters.push(*word) {
+                        commands.push(Command::Unknown(*word));
+                    }
+                }
+            }
+        }
+
+        for word in codes {
+            commands.push(Self::classify(word, &mut parameters));
+        }
+
+        if parameters.has_axes() {
+            commands.push(Command::ModalMove(parameters.take_arc()));
+        }
+
+        if let Some(f) = parameters.take('F') {
+            commands.push(Command::SetFeedRate(f));
+        }
+
+        if let Some(s) = parameters.take('S') {
+            commands.push(Command::SetSpindleSpeed(s));
+        }
+
+        if let Some(t) = parameters.take('T') {
+            commands.push(match integer(t) {
+                Some(tool) => Command::SelectTool(tool),
+                None => Command::Unknown(Word::new('T', t)),
+            });
+        }
+
+        // Parameters not used by any command
+        commands.extend(parameters.words.into_iter().map(Command::Unknown));
+
+        return commands;
+    }
+
+    fn classify(word: Word, parameters: &mut Parameters) -> Command {
+        // Dynamic cutter compensation and tool length offsets are the only fractional codes
+        // taking parameters
+        if word.mnemonic() == 'G' && (word.value() == 41.1 || word.value() == 42.1) {
+            let side = if word.value() == 41.1 { Side::Left } else { Side::Right };
+            return match parameters.take('D') {
+                Some(diameter) => Command::Compensation { side, diameter: CompensationDiameter::Given(diameter) },
+                None => Command::Unknown(word),
+            };
+        }
+        if word.mnemonic() == 'G' && word.value() == 43.1 {
+            return match parameters.take('Z') {
+                Some(length) => Command::ToolLengthOffset(LengthOffset::Given(length)),
+                None => Command::Unknown(word),
+            };
+        }
+
+        if word.mnemonic() == 'G' {
+            if let Some(i) = EXTENDED_COORDINATE_SYSTEMS.iter().position(|&code| word.value() == code) {
+                return Command::SelectCoordinateSystem(7 + i as u8);
+            }
+
+            if let Some(probing) = Probing::from_code(word.value()) {
+                return Command::Probe(probing, parameters.take_move());
+            }
+
+            match word.value() {
+                28.1 => return Command::StorePredefined(Predefined::Home),
+                30.1 => return Command::StorePredefined(Predefined::Secondary),
+                92.1 => return Command::ClearPositionOffset,
+                92.2 => return Command::SuspendPositionOffset,
+                92.3 => return Command::RestorePositionOffset,
+                _ => {}
+            }
+        }
+
+        let code = match integer(word.value()) {
+            Some(code) => code,
+            None => return Command::Unknown(word),
+        };
+
+        return match (word.mnemonic(), code) {
+            ('G', 0) => Command::RapidMove(parameters.take_move()),
+            ('G', 1) => Command::LinearMove(parameters.take_move()),
+            ('G', 2) => Command::ClockwiseArc(parameters.take_arc()),
+            ('G', 3) => Command::CounterClockwiseArc(parameters.take_arc()),
+            ('G', 4) => match parameters.take('P') {
+                Some(seconds) => Command::Dwell { seconds },
+                None => Command::Unknown(word),
+            },
+            ('G', 10) => {
+                let mode = match parameters.peek('L').and_then(integer) {
+                    Some(2) => WorkOffsetMode::Origin,
+                    Some(20) => WorkOffsetMode::Position,
+                    _ => return Command::Unknown(word),
+                };
+                let system = match parameters.peek('P').and_then(integer) {
+                    Some(system) if system <= 9 => system as u8,
+                    _ => return Command::Unknown(word),
+                };
+
+                parameters.take('L');
+                parameters.take('P');
+                Command::SetWorkOffset { system, mode, axes: parameters.take_axes() }
+            }
+            ('G', 7) => Command::SetLatheMode(LatheMode::Diameter),
+            ('G', 8) => Command::SetLatheMode(LatheMode::Radius),
+            ('G', 17) => Command::SelectPlane(Plane::XY),
+            ('G', 18) => Command::SelectPlane(Plane::ZX),
+            ('G', 19) => Command::SelectPlane(Plane::YZ),
+            ('G', 20) => Command::SetUnits(Units::Inches),
+            ('G', 21) => Command::SetUnits(Units::Millimeters),
+            ('G', 28) => Command::GoToPredefined(Predefined::Home, parameters.take_axes()),
+            ('G', 30) => Command::GoToPredefined(Predefined::Secondary, parameters.take_axes()),
+            ('G', 33) => match parameters.take('K') {
+                Some(pitch) => Command::SynchronizedMove { target: parameters.take_move(), pitch },
+                None => Command::Unknown(word),
+            },
+            ('G', 40) => Command::CancelCompensation,
+            ('G', 41) | ('G', 42) => {
+                // Leave fractional tool numbers for the fallback
+                let tool = parameters.peek('D').and_then(integer);
+                if tool.is_some() {
+                    parameters.take('D');
+                }
+
+                let side = if code == 41 { Side::Left } else { Side::Right };
+                Command::Compensation { side, diameter: CompensationDiameter::Tool(tool) }
+            }
+            ('G', 43) => {
+                // Leave fractional tool numbers for the fallback
+                let tool = parameters.peek('H').and_then(integer);
+                if tool.is_some() {
+                    parameters.take('H');
+                }
+                Command::ToolLengthOffset(LengthOffset::Tool(tool))
+            }
+            ('G', 49) => Command::CancelToolLengthOffset,
+            ('G', 54..=59) => Command::SelectCoordinateSystem((code - 53) as u8),
+            ('G', 80) => Command::CancelCycle,
+            ('G', 81..=89) => match CycleKind::from_code(code) {
+                Some(kind) => Command::Cycle(kind, parameters.take_cycle()),
+                None => Command::Unknown(word),
+            },
+            ('G', 90) => Command::SetDistanceMode(DistanceMode::Absolute),
+            ('G', 91) => Command::SetDistanceMode(DistanceMode::Relative),
+            ('G', 92) => Command::SetPosition(parameters.take_axes()),
+            ('G', 93) => Command::SetFeedRateMode(FeedRateMode::InverseTime),
+            ('G', 94) => Command::SetFeedRateMode(FeedRateMode::UnitsPerMinute),
+            ('G', 95) => Command::SetFeedRateMode(FeedRateMode::UnitsPerRevolution),
+            ('G', 96) => Command::ConstantSurfaceSpeed { speed: parameters.take('S'), limit: parameters.take('D') },
+            ('G', 97) => Command::ConstantSpindleSpeed,
+            ('G', 98) => Command::SetReturnMode(ReturnMode::Initial),
+            ('G', 99) => Command::SetReturnMode(ReturnMode::Retract),
+            ('M', 0) => Command::Pause { optional: false },
+            ('M', 1) => Command::Pause { optional: true },
+            ('M', 2) => Command::ProgramEnd { rewind: false },
+            ('M', 30) => Command::ProgramEnd { rewind: true },
+            ('M', 3) => Command::SpindleOn { clockwise: true, speed: parameters.take('S') },
+            ('M', 4) => Command::SpindleOn { clockwise: false, speed: parameters.take('S') },
+            ('M', 5) => Command::SpindleOff,
+            ('M', 6) => {
+                // Leave fractional tool numbers for the fallback
+                let tool = parameters.peek('T').and_then(integer);
+                if tool.is_some() {
+                    parameters.take('T');
+                }
+                Command::ToolChange { tool }
+            }
+            ('M', 7) => Command::CoolantOn { mist: true },
+            ('M', 8) => Command::CoolantOn { mist: false },
+            ('M', 9) => Command::CoolantOff,
+            _ => Command::Unknown(word),
+        };
+    }
+
+    /// The words representing this command.
+    pub fn words(&self) -> Vec<Word> {
+        let code = |mnemonic: char, value: Real| vec![Word::new(mnemonic, value)];
+
+        return match *self {
+            Command::RapidMove(target) => words(code('G', 0.0), &move_words(target)),
+            Command::LinearMove(target) => words(code('G', 1.0), &move_words(target)),
+            Command::ClockwiseArc(arc) => words(code('G', 2.0), &arc_words(arc)),
+            Command::CounterClockwiseArc(arc) => words(code('G', 3.0), &arc_words(arc)),
+            Command::ModalMove(arc) => words(Vec::new(), &arc_words(arc)),
+            Command::Dwell { seconds } => words(code('G', 4.0), &[('P', Some(seconds))]),
+            Command::SetLatheMode(LatheMode::Diameter) => code('G', 7.0),
+            Command::SetLatheMode(LatheMode::Radius) => code('G', 8.0),
+            Command::SelectPlane(Plane::XY) => code('G', 17.0),
+            Command::SelectPlane(Plane::ZX) => code('G', 18.0),
+            Command::SelectPlane(Plane::YZ) => code('G', 19.0),
+            Command::SetUnits(Units::Inches) => code('G', 20.0),
+            Command::SetUnits(Units::Millimeters) => code('G', 21.0),
+            Command::SetWorkOffset { system, mode, axes } => {
+                let l = match mode {
+                    WorkOffsetMode::Origin => 2.0,
+                    WorkOffsetMode::Position => 20.0,
+                };
+                words(vec![Word::new('G', 10.0), Word::new('L', l), Word::new('P', Real::from(system))], &axes_words(axes))
+            }
+            Command::GoToPredefined(Predefined::Home, axes) => words(code('G', 28.0), &axes_words(axes)),
+            Command::GoToPredefined(Predefined::Secondary, axes) => words(code('G', 30.0), &axes_words(axes)),
+            Command::SynchronizedMove { target, pitch } => words(words(code('G', 33.0), &move_words(target)), &[('K', Some(pitch))]),
+            Command::Probe(probing, target) => words(code('G', probing.code()), &move_words(target)),
+            Command::StorePredefined(Predefined::Home) => code('G', 28.1),
+            Command::StorePredefined(Predefined::Secondary) => code('G', 30.1),
+            Command::CancelCompensation => code('G', 40.0),
+            Command::Compensation { side, diameter } => {
+                let code = match side {
+                    Side::Left => 41.0,
+                    Side::Right => 42.0,
+                };
+
+                match diameter {
+                    CompensationDiameter::Tool(tool) => words(vec![Word::new('G', code)], &[('D', tool.map(|n| n as Real))]),
+                    CompensationDiameter::Given(diameter) => words(vec![Word::new('G', code + 0.1)], &[('D', Some(diameter))]),
+                }
+            }
+            Command::ToolLengthOffset(LengthOffset::Tool(tool)) => words(code('G', 43.0), &[('H', tool.map(|n| n as Real))]),
+            Command::ToolLengthOffset(LengthOffset::Given(length)) => words(code('G', 43.1), &[('Z', Some(length))]),
+            Command::CancelToolLengthOffset => code('G', 49.0),
+            Command::SelectCoordinateSystem(system @ 7..=9) => code('G', EXTENDED_COORDINATE_SYSTEMS[usize::from(system) - 7]),
+            Command::SelectCoordinateSystem(system) => code('G', Real::from(system) + 53.0),
+            Command::CancelCycle => code('G', 80.0),
+            Command::Cycle(kind, cycle) => words(code('G', kind.code() as Real), &cycle_words(cycle)),
+            Command::SetDistanceMode(DistanceMode::Absolute) => code('G', 90.0),
+            Command::SetDistanceMode(DistanceMode::Relative) => code('G', 91.0),
+            Command::SetFeedRateMode(FeedRateMode::InverseTime) => code('G', 93.0),
+            Command::SetFeedRateMode(FeedRateMode::UnitsPerMinute) => code('G', 94.0),
+            Command::SetFeedRateMode(FeedRateMode::UnitsPerRevolution) => code('G', 95.0),
+            Command::SetPosition(axes) => words(code('G', 92.0), &axes_words(axes)),
+            Command::ClearPositionOffset => code('G', 92.1),
+            Command::SuspendPositionOffset => code('G', 92.2),
+            Command::RestorePositionOffset => code('G', 92.3),
+            Command::ConstantSurfaceSpeed { speed, limit } => words(code('G', 96.0), &[('S', speed), ('D', limit)]),
+            Command::ConstantSpindleSpeed => code('G', 97.0),
+            Command::SetReturnMode(ReturnMode::Initial) => code('G', 98.0),
+            Command::SetReturnMode(ReturnMode::Retract) => code('G', 99.0),
+            Command::Pause { optional } => code('M', if optional { 1.0 } else { 0.0 }),
+            Command::ProgramEnd { rewind } => code('M', if rewind { 30.0 } else { 2.0 }),
+            Command::SpindleOn { clockwise, speed } => words(code('M', if clockwise { 3.0 } else { 4.0 }), &[('S', speed)]),
+            Command::SpindleOff => code('M', 5.0),
+            Command::ToolChange { tool } => words(code('M', 6.0), &[('T', tool.map(|n| n as Real))]),
+            Command::CoolantOn { mist } => code('M', if mist { 7.0 } else { 8.0 }),
+            Command::CoolantOff => code('M', 9.0),
+            Command::SetFeedRate(f) => code('F', f),
+            Command::SetSpindleSpeed(s) => code('S', s),
+            Command::SelectTool(t) => code('T', t as Real),
+            Command::Unknown(word) => vec![word],
+        };
+    }
+
+    /// Converts a list of commands back into a single block.
+    pub fn to_block(commands: &[Command]) -> Block {
+        Block::new(commands.iter().flat_map(|command| command.words()).collect())
+    }
+}
+
+/// The parameter words of a block which are not yet consumed by a command.
+#[derive(Default)]
+struct Parameters {
+    words: Vec<Word>,
+}
+
+impl Parameters {
+    const LETTERS: &'static [char] = &['X', 'Y', 'Z', 'A', 'B', 'C', 'U', 'V', 'W', 'I', 'J', 'K', 'R', 'P', 'Q', 'L', 'D', 'H', 'F', 'S', 'T'];
+
+    /// Records a parameter word, returning false if it is no parameter or a duplicate.
+    fn push(&mut self, word: Word) -> bool {
+        if !Self::LETTERS.contains(&word.mnemonic()) || self.peek(word.mnemonic()).is_some() {
+            return false;
+        }
+
+        self.words.push(word);
+        return true;
+    }
+
+    fn peek(&self, mnemonic: char) -> Option<Real> {
+        self.words.iter()
+                .find(|word| word.mnemonic() == mnemonic)
+                .map(|word| word.value())
+    }
+
+    fn take(&mut self, mnemonic: char) -> Option<Real> {
+        let index = self.words.iter().position(|word| word.mnemonic() == mnemonic)?;
+        return Some(self.words.remove(index).value());
+    }
+
+    fn has_axes(&self) -> bool {
+        self.words.iter().any(|word| "XYZABCUVWIJKR".contains(word.mnemonic()))
+    }
+
+    fn take_axes(&mut self) -> Axes {
+        Axes {
+            x: self.take('X'),
+            y: self.take('Y'),
+            z: self.take('Z'),
+        }
+    }
+
+    fn take_additional_axes(&mut self) -> AxisValues<Option<Real>> {
+        AxisValues::from_fn(|axis| self.take(axis.letter()))
+    }
+
+    fn take_move(&mut self) -> Move {
+        let axes = self.take_axes();
+
+        return Move {
+            x: axes.x,
+            y: axes.y,
+            z: axes.z,
+            axes: self.take_additional_axes(),
+            f: self.take('F'),
+        };
+    }
+
+    fn take_arc(&mut self) -> Arc {
+        let axes = self.take_axes();
+
+        return Arc {
+            x: axes.x,
+            y: axes.y,
+            z: axes.z,
+            axes: self.take_additional_axes(),
+            i: self.take('I'),
+            j: self.take('J'),
+            k: self.take('K'),
+            r: self.take('R'),
+            p: self.take('P'),
+            f: self.take('F'),
+        };
+    }
+
+    fn take_cycle(&mut self) -> Cycle {
+        let axes = self.take_axes();
+
+        // Leave fractional repetitions for the fallback
+        let l = self.peek('L').and_then(integer);
+        if l.is_some() {
+            self.take('L');
+        }
+
+        return Cycle {
+            x: axes.x,
+            y: axes.y,
+            z: axes.z,
+            r: self.take('R'),
+            q: self.take('Q'),
+            p: self.take('P'),
+            f: self.take('F'),
+            l,
+        };
+    }
+}
+
+fn integer(value: Real) -> Option<u32> {
+    if value >= 0.0 && value.fract() == 0.0 && value <= u32::MAX as Real {
+        return Some(value as u32);
+    }
+
+    return None;
+}
+
+fn words(mut words: Vec<Word>, parameters: &[(char, Option<Real>)]) -> Vec<Word> {
+    words.extend(parameters.iter()
+            .filter_map(|&(mnemonic, value)| value.map(|value| Word::new(mnemonic, value))));
+    return words;
+}
+
+fn axes_words(axes: Axes) -> [(char, Option<Real>); 3] {
+    [('X', axes.x), ('Y', axes.y), ('Z', axes.z)]
+}
+
+fn move_words(target: Move) -> Vec<(char, Option<Real>)> {
+    let mut words = vec![('X', target.x), ('Y', target.y), ('Z', target.z)];
+    words.extend(additional_axes_words(target.axes));
+    words.push(('F', target.f));
+    return words;
+}
+
+fn cycle_words(cycle: Cycle) -> [(char, Option<Real>); 8] {
+    [('X', cycle.x), ('Y', cycle.y), ('Z', cycle.z), ('R', cycle.r), ('Q', cycle.q), ('P', cycle.p), ('F', cycle.f), ('L', cycle.l.map(|n| n as Real))]
+}
+
+fn arc_words(arc: Arc) -> Vec<(char, Option<Real>)> {
+    let mut words = vec![('X', arc.x), ('Y', arc.y), ('Z', arc.z)];
+    words.extend(additional_axes_words(arc.axes));
+    words.extend_from_slice(&[('I', arc.i), ('J', arc.j), ('K', arc.k), ('R', arc.r), ('P', arc.p), ('F', arc.f)]);
+    return words;
+}
+
+fn additional_axes_words(axes: AxisValues<Option<Real>>) -> Vec<(char, Option<Real>)> {
+    axes.iter().map(|(axis, &value)| (axis.letter(), value)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Axis;
+    use crate::parser::Parser;
+
+    fn commands(line: &str) -> Vec<Command> {
+        Command::from_block(&Parser::new().parse(line).unwrap())
+    }
+
+    #[test]
+    fn test_from_block() {
+        assert_eq!(commands("G90 G21 G1 X10 Y-2.5 F300"), vec![
+            Command::SetDistanceMode(DistanceMode::Absolute),
+            Command::SetUnits(Units::Millimeters),
+            Command::LinearMove(Move { x: Some(10.0), y: Some(-2.5), z: None, axes: AxisValues::default(), f: Some(300.0) }),
+        ]);
+
+        assert_eq!(commands("G2 X1 Y1 I1 J0"), vec![
+            Command::ClockwiseArc(Arc { x: Some(1.0), y: Some(1.0), i: Some(1.0), j: Some(0.0), ..Arc::default() }),
+        ]);
+
+        assert_eq!(commands("S12000 M3"), vec![Command::SpindleOn { clockwise: true, speed: Some(12000.0) }]);
+        assert_eq!(commands("T2 M6"), vec![Command::ToolChange { tool: Some(2) }]);
+        assert_eq!(commands("G4 P0.5"), vec![Command::Dwell { seconds: 0.5 }]);
+    }
+
+    #[test]
+    fn test_from_block_cycles() {
+        assert_eq!(commands("G99 G83 X1 Y2 Z-5 R1 Q2 F100"), vec![
+            Command::SetReturnMode(ReturnMode::Retract),
+            Command::Cycle(CycleKind::PeckDrill, Cycle {
+                x: Some(1.0),
+                y: Some(2.0),
+                z: Some(-5.0),
+                r: Some(1.0),
+                q: Some(2.0),
+                f: Some(100.0),
+                ..Cycle::default()
+            }),
+        ]);
+
+        assert_eq!(commands("G82 Z-1 P0.5 L3"), vec![
+            Command::Cycle(CycleKind::DrillDwell, Cycle { z: Some(-1.0), p: Some(0.5), l: Some(3), ..Cycle::default() }),
+        ]);
+        assert_eq!(commands("G80"), vec![Command::CancelCycle]);
+    }
+
+    #[test]
+    fn test_from_block_compensation() {
+        assert_eq!(commands("G41 D2"), vec![Command::Compensation { side: Side::Left, diameter: CompensationDiameter::Tool(Some(2)) }]);
+        assert_eq!(commands("G42"), vec![Command::Compensation { side: Side::Right, diameter: CompensationDiameter::Tool(None) }]);
+        assert_eq!(commands("G42.1 D1.5"), vec![Command::Compensation { side: Side::Right, diameter: CompensationDiameter::Given(1.5) }]);
+        assert_eq!(commands("G40"), vec![Command::CancelCompensation]);
+        assert_eq!(commands("G41.1"), vec![Command::Unknown(Word::new('G', 41.1))]);
+    }
+
+    #[test]
+    fn test_from_block_coordinate_systems() {
+        assert_eq!(commands("G55"), vec![Command::SelectCoordinateSystem(2)]);
+        assert_eq!(commands("G59.3"), vec![Command::SelectCoordinateSystem(9)]);
+        assert_eq!(commands("G10 L2 P3 X1 Z2"), vec![
+            Command::SetWorkOffset { system: 3, mode: WorkOffsetMode::Origin, axes: Axes { x: Some(1.0), y: None, z: Some(2.0) } },
+        ]);
+        assert_eq!(commands("G10 L20 P0 Y0"), vec![
+            Command::SetWorkOffset { system: 0, mode: WorkOffsetMode::Position, axes: Axes { x: None, y: Some(0.0), z: None } },
+        ]);
+        assert_eq!(commands("G92 X0 G92.2"), vec![Command::SetPosition(Axes { x: Some(0.0), ..Axes::default() }), Command::SuspendPositionOffset]);
+        assert_eq!(commands("G10 L1 P1"), vec![
+            Command::Unknown(Word::new('G', 10.0)),
+            Command::Unknown(Word::new('L', 1.0)),
+            Command::Unknown(Word::new('P', 1.0)),
+        ]);
+    }
+
+    #[test]
+    fn test_from_block_probe() {
+        assert_eq!(commands("G38.3 Z-5 F100"), vec![
+            Command::Probe(Probing { away: false, required: false }, Move { z: Some(-5.0), f: Some(100.0), ..Move::default() }),
+        ]);
+        assert_eq!(commands("G38.4 X2"), vec![
+            Command::Probe(Probing { away: true, required: true }, Move { x: Some(2.0), ..Move::default() }),
+        ]);
+        assert_eq!(Probing { away: true, required: false }.code(), 38.5);
+    }
+
+    #[test]
+    fn test_from_block_lathe() {
+        assert_eq!(commands("G7"), vec![Command::SetLatheMode(LatheMode::Diameter)]);
+        assert_eq!(commands("G96 D2500 S250 M3"), vec![
+            Command::ConstantSurfaceSpeed { speed: Some(250.0), limit: Some(2500.0) },
+            Command::SpindleOn { clockwise: true, speed: None },
+        ]);
+        assert_eq!(commands("G33 Z-20 K2"), vec![
+            Command::SynchronizedMove { target: Move { z: Some(-20.0), ..Move::default() }, pitch: 2.0 },
+        ]);
+        assert_eq!(commands("G33 Z-20"), vec![
+            Command::Unknown(Word::new('G', 33.0)),
+            Command::ModalMove(Arc { z: Some(-20.0), ..Arc::default() }),
+        ]);
+    }
+
+    #[test]
+    fn test_from_block_tool_length_offset() {
+        assert_eq!(commands("G43 H3"), vec![Command::ToolLengthOffset(LengthOffset::Tool(Some(3)))]);
+        assert_eq!(commands("G43"), vec![Command::ToolLengthOffset(LengthOffset::Tool(None))]);
+        assert_eq!(commands("G43.1 Z2.5"), vec![Command::ToolLengthOffset(LengthOffset::Given(2.5))]);
+        assert_eq!(commands("G49"), vec![Command::CancelToolLengthOffset]);
+    }
+
+    #[test]
+    fn test_from_block_modal() {
+        assert_eq!(commands("X5 F100"), vec![Command::ModalMove(Arc { x: Some(5.0), f: Some(100.0), ..Arc::default() })]);
+        assert_eq!(commands("X1 I-2"), vec![Command::ModalMove(Arc { x: Some(1.0), i: Some(-2.0), ..Arc::default() })]);
+        assert_eq!(commands("F100 S500 T1"), vec![
+            Command::SetFeedRate(100.0),
+            Command::SetSpindleSpeed(500.0),
+            Command::SelectTool(1),
+        ]);
+    }
+
+    #[test]
+    fn test_from_block_additional_axes() {
+        let mut axes = AxisValues::default();
+        axes[Axis::A] = Some(90.0);
+        axes[Axis::W] = Some(-1.0);
+
+        assert_eq!(commands("G0 X1 A90 W-1"), vec![Command::RapidMove(Move { x: Some(1.0), axes, ..Move::default() })]);
+        assert_eq!(commands("A90 W-1"), vec![Command::ModalMove(Arc { axes, ..Arc::default() })]);
+    }
+
+    #[test]
+    fn test_from_block_unknown() {
+        assert_eq!(commands("G5.1 Z-10 E1 M6 T1.5"), vec![
+            Command::Unknown(Word::new('E', 1.0)),
+            Command::Unknown(Word::new('G', 5.1)),
+            Command::ToolChange { tool: None },
+            Command::ModalMove(Arc { z: Some(-10.0), ..Arc::default() }),
+            Command::Unknown(Word::new('T', 1.5)),
+        ]);
+
+        assert_eq!(commands("G1 X1 X2"), vec![
+            Command::Unknown(Word::new('X', 2.0)),
+            Command::LinearMove(Move { x: Some(1.0), ..Move::default() }),
+        ]);
+    }
+
+    #[test]
+    fn test_to_block() {
+        for line in &["G90 G21 G1 X10 Y-2.5 F300", "G3 X0 Y2 R1", "M3 S1000", "M6 T4", "G92 X0 Y0 Z0", "G55", "M30", "X1 Y2", "G98 G81 X1 Y1 Z-2 R1 F50", "G80", "G41 D1", "G42.1 D0.5", "G43 H2", "G43.1 Z-1.5", "G49", "G59.2", "G10 L2 P1 X10 Y-5", "G10 L20 P0 Z0", "G92.1", "G92.3", "G28", "G30 Z5", "G30.1", "G38.2 Z-10 F50", "G38.5 X1", "G2 X10 Z-6 I-10 P3", "G7", "G96 S200 D3000", "G97 S500", "G33 Z-10 K1.5", "G1 X1 B-45 U2 F100", "G2 X1 C90 I1", "G93 G1 X10 F2", "G94", "G95 F0.1"] {
+            let block = Command::to_block(&commands(line));
+            assert_eq!(block.line(), *line);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! An interactive console for controlling a machine by hand, as run by `gcode-console`.
+//!
+//! Each line entered is sent through a sender and answered with the replies up to its
+//! acknowledgement. Lines starting with `@` run a macro instead, and `!!` or `!N` repeat the last
+//! or the N-th line of the history. Codes known to the dialect and macro names are offered as
+//! completions.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::Real;
+use crate::dialect::Dialect;
+use crate::modal::ModalGroup;
+use crate::parser::Word;
+use crate::program::Program;
+use crate::response::Response;
+use crate::sender::{Realtime, Sender, SenderError};
+
+#[derive(Debug)]
+pub enum ConsoleError {
+    Sender(SenderError),
+
+    UnknownMacro(String),
+
+    /// The history has no line with the given number, counting from 1.
+    UnknownHistory(usize),
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleError::Sender(err) => write!(f, "{}", err),
+            ConsoleError::UnknownMacro(name) => write!(f, "unknown macro: {}", name),
+            ConsoleError::UnknownHistory(number) => write!(f, "no line {} in history", number),
+        }
+    }
+}
+
+impl error::Error for ConsoleError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConsoleError::Sender(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<SenderError> for ConsoleError {
+    fn from(err: SenderError) -> Self {
+        ConsoleError::Sender(err)
+    }
+}
+
+/// The G and M codes known to the dialect, in ascending order.
+pub fn codes(dialect: Dialect) -> Vec<Word> {
+    // Codes are enumerated in tenths to cover ones like G38.2
+    let known = |mnemonic: char| (0..1000)
+            .map(move |tenths| Word::new(mnemonic, tenths as Real / 10.0))
+            .filter(|word| ModalGroup::of(word).is_some());
+
+    let mut codes = known('G').collect::<Vec<_>>();
+    match dialect.m_codes() {
+        Some(m_codes) => codes.extend(m_codes.iter().map(|&code| Word::new('M', code))),
+        None => codes.extend(known('M')),
+    }
+
+    return codes;
+}
+
+/// Sends lines entered by hand to a controller.
+pub struct Console<T> {
+    sender: Sender<T>,
+
+    dialect: Dialect,
+
+    /// The lines entered so far, oldest first
+    history: Vec<String>,
+
+    /// The non-empty blocks of the macros by name
+    macros: BTreeMap<String, Vec<String>>,
+}
+
+impl<T> Console<T>
+    where T: Read + Write {
+    pub fn new(transport: T) -> Self {
+        Self {
+            sender: Sender::new(transport, &Program::new()),
+            dialect: Dialect::default(),
+            history: Vec::new(),
+            macros: BTreeMap::new(),
+        }
+    }
+
+    /// Completes the codes known to the dialect.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Numbers and checksums all lines, as Marlin and RepRapFirmware expect (see
+    /// `Sender::with_checksums`).
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.sender = self.sender.with_checksums(checksums);
+        self
+    }
+
+    /// Runs the non-empty blocks of the program when entering `@` followed by the name.
+    pub fn with_macro<S>(mut self, name: S, program: &Program) -> Self
+        where S: Into<String> {
+        let lines = program.iter()
+                .filter(|block| !block.is_empty())
+                .map(|block| block.to_string())
+                .collect();
+        self.macros.insert(name.into(), lines);
+        self
+    }
+
+    /// The lines entered so far, oldest first, with repeated lines resolved and without unknown
+    /// macros.
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn transport(&self) -> &T {
+        self.sender.transport()
+    }
+
+    /// Sends the line, or the lines of the macro or of the history it refers to, returning the
+    /// replies.
+    pub fn execute(&mut self, line: &str) -> Result<Vec<Response>, ConsoleError> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let line = if line == "!!" {
+            self.history.last().cloned().ok_or(ConsoleError::UnknownHistory(self.history.len()))?
+        } else if let Some(Ok(number)) = line.strip_prefix('!').map(str::parse::<usize>) {
+            number.checked_sub(1)
+                    .and_then(|index| self.history.get(index))
+                    .cloned()
+                    .ok_or(ConsoleError::UnknownHistory(number))?
+        } else {
+            line.to_owned()
+        };
+
+        let lines = match line.strip_prefix('@') {
+            Some(name) => self.macros.get(name).cloned().ok_or_else(|| ConsoleError::UnknownMacro(name.to_owned()))?,
+            None => vec![line.clone()],
+        };
+
+        // Unknown macros are left out, so repeating the last line does not repeat the error
+        if self.history.last() != Some(&line) {
+            self.history.push(line);
+        }
+
+        let mut replies = Vec::new();
+        for line in lines {
+            replies.extend(self.sender.execute(line)?);
+        }
+
+        return Ok(replies);
+    }
+
+    /// Sends a real-time command right away (see `Sender::inject`).
+    pub fn inject(&mut self, command: Realtime) -> Result<(), ConsoleError> {
+        self.sender.inject(command)?;
+        return Ok(());
+    }
+
+    /// The lines completing the last word of the line, either with a code known to the dialect or,
+    /// after `@`, with the name of a macro.
+    pub fn complete(&self, line: &str) -> Vec<String> {
+        let start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let (head, word) = line.split_at(start);
+
+        if let Some(name) = word.strip_prefix('@') {
+            return self.macros.keys()
+                    .filter(|candidate| candidate.starts_with(name))
+                    .map(|candidate| format!("{}@{}", head, candidate))
+                    .collect();
+        }
+
+        let word = word.to_ascii_uppercase();
+        if word.is_empty() {
+            return Vec::new();
+        }
+
+        return codes(self.dialect).iter()
+                .map(|code| format!("{}{}", code.mnemonic(), code.value()))
+                .filter(|code| code.starts_with(&word))
+                .map(|code| format!("{}{}", head, code))
+                .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sender::tests::{program, Transport};
+
+    #[test]
+    fn test_execute() {
+        let replies = "ok\n[GC:G0 G54 G17 G21 G90 G94 M5 M9 T0 F0 S0]\nok\nok\nok\nok\nok\nok\nok\n";
+        let mut console = Console::new(Transport::new(replies)).with_macro("park", &program("G0 Z10\nG0 X0 Y0\n"));
+
+        assert!(console.execute("G0 X1").unwrap()[0].is_ok());
+        assert_eq!(console.execute("$G").unwrap(), vec![
+            Response::Other("[GC:G0 G54 G17 G21 G90 G94 M5 M9 T0 F0 S0]".to_owned()),
+            Response::parse("ok"),
+        ]);
+        assert_eq!(console.execute("  ").unwrap(), vec![]);
+        assert_eq!(console.execute("!1").unwrap().len(), 1);
+        assert_eq!(console.execute("@park").unwrap().len(), 2);
+        assert!(matches!(console.execute("@home"), Err(ConsoleError::UnknownMacro(_))));
+        assert!(matches!(console.execute("!9"), Err(ConsoleError::UnknownHistory(9))));
+        assert_eq!(console.execute("!!").unwrap().len(), 2);
+
+        assert_eq!(console.transport().sent(), "G0 X1\n$G\nG0 X1\nG0 Z10\nG0 X0 Y0\nG0 Z10\nG0 X0 Y0\n");
+        assert_eq!(console.history(), &["G0 X1", "$G", "G0 X1", "@park"]);
+    }
+
+    #[test]
+    fn test_complete() {
+        let console = Console::new(Transport::new("")).with_macro("park", &program("G0 Z10\n"));
+        assert_eq!(console.complete("G0 X1 g30"), vec!["G0 X1 G30", "G0 X1 G30.1"]);
+        assert_eq!(console.complete("G38"), vec!["G38.2", "G38.3", "G38.4", "G38.5"]);
+        assert_eq!(console.complete("@p"), vec!["@park"]);
+        assert_eq!(console.complete("G0 "), Vec::<String>::new());
+
+        // Only M codes known to the controller are offered
+        let console = console.with_dialect(Dialect::Grbl);
+        assert_eq!(console.complete("M5"), vec!["M5", "M56"]);
+    }
+}
@@ -0,0 +1,669 @@
+//! O-word control flow of LinuxCNC: subroutines, conditionals and loops.
+//!
+//! Blocks starting with an O-word (`o100 if [#1 GT 0]`) carry a `Control` instead of words. A
+//! `Flow` executes the control flow of a program and returns the blocks in the order they are
+//! executed, with all parameters and expressions substituted. The result can be interpreted
+//! like any other sequence of blocks.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::error;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostic::Code;
+use crate::expr::{Bracketed, Expr};
+use crate::params::ParamError;
+use crate::parser::Provenance;
+#[cfg(feature = "std")]
+use crate::params::{Parameter, Parameters};
+#[cfg(feature = "std")]
+use crate::parser::Block;
+#[cfg(feature = "std")]
+use crate::program::Program;
+
+/// The number of steps after which a flow is considered to loop forever.
+pub const MAX_STEPS: usize = 10_000_000;
+
+/// The maximal depth of nested subroutine calls.
+pub const MAX_DEPTH: usize = 100;
+
+/// The label of an O-word, tying together the blocks of a subroutine, conditional or loop.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Label {
+    Numbered(u32),
+
+    /// A named label (`o<name>`), in lower case and without whitespace.
+    Named(String),
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Label::Numbered(number) => write!(f, "o{}", number),
+            Label::Named(name) => write!(f, "o<{}>", name),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Keyword {
+    Sub,
+    EndSub,
+    Call,
+    Return,
+    If,
+    ElseIf,
+    Else,
+    EndIf,
+
+    /// Either starts a loop or, following a `do`, ends it.
+    While,
+    EndWhile,
+    Do,
+    Repeat,
+    EndRepeat,
+    Break,
+    Continue,
+}
+
+impl Keyword {
+    /// Finds the keyword with the given name, in lower case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "sub" => Some(Keyword::Sub),
+            "endsub" => Some(Keyword::EndSub),
+            "call" => Some(Keyword::Call),
+            "return" => Some(Keyword::Return),
+            "if" => Some(Keyword::If),
+            "elseif" => Some(Keyword::ElseIf),
+            "else" => Some(Keyword::Else),
+            "endif" => Some(Keyword::EndIf),
+            "while" => Some(Keyword::While),
+            "endwhile" => Some(Keyword::EndWhile),
+            "do" => Some(Keyword::Do),
+            "repeat" => Some(Keyword::Repeat),
+            "endrepeat" => Some(Keyword::EndRepeat),
+            "break" => Some(Keyword::Break),
+            "continue" => Some(Keyword::Continue),
+            _ => None,
+        };
+    }
+
+    /// The minimal and maximal number of arguments.
+    pub fn arguments(&self) -> (usize, usize) {
+        match self {
+            Keyword::Call => (0, usize::MAX),
+            Keyword::EndSub | Keyword::Return => (0, 1),
+            Keyword::If | Keyword::ElseIf | Keyword::While | Keyword::Repeat => (1, 1),
+            _ => (0, 0),
+        }
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Keyword::Sub => "sub",
+            Keyword::EndSub => "endsub",
+            Keyword::Call => "call",
+            Keyword::Return => "return",
+            Keyword::If => "if",
+            Keyword::ElseIf => "elseif",
+            Keyword::Else => "else",
+            Keyword::EndIf => "endif",
+            Keyword::While => "while",
+            Keyword::EndWhile => "endwhile",
+            Keyword::Do => "do",
+            Keyword::Repeat => "repeat",
+            Keyword::EndRepeat => "endrepeat",
+            Keyword::Break => "break",
+            Keyword::Continue => "continue",
+        })
+    }
+}
+
+/// The O-word of a block.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Control {
+    pub label: Label,
+    pub keyword: Keyword,
+
+    /// The arguments of a call, the condition of a conditional or loop, the count of a repeat
+    /// or the value returned from a subroutine.
+    pub arguments: Vec<Expr>,
+}
+
+impl Control {
+    pub fn new(label: Label, keyword: Keyword) -> Self {
+        Self {
+            label,
+            keyword,
+            arguments: Vec::new(),
+        }
+    }
+
+    pub fn with_argument(mut self, argument: Expr) -> Self {
+        self.arguments.push(argument);
+        self
+    }
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.label, self.keyword)?;
+        for argument in &self.arguments {
+            write!(f, " {}", Bracketed(argument))?;
+        }
+
+        return Ok(());
+    }
+}
+
+#[derive(Debug)]
+pub enum ControlError {
+    Unmatched {
+        label: Label,
+        keyword: Keyword,
+    },
+
+    UnknownSubroutine(Label),
+
+    TooDeep(usize),
+
+    StepLimit(usize),
+
+    Parameter(ParamError),
+
+    /// An error raised by a block, annotated with the block's origin.
+    Located {
+        provenance: Provenance,
+        error: Box<ControlError>,
+    },
+}
+
+impl fmt::Display for ControlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlError::Unmatched { label, keyword } => write!(f, "{} {} without matching block", label, keyword),
+            ControlError::UnknownSubroutine(label) => write!(f, "unknown subroutine: {}", label),
+            ControlError::TooDeep(depth) => write!(f, "subroutine calls nested deeper than {}", depth),
+            ControlError::StepLimit(steps) => write!(f, "exceeded {} steps, the program probably loops forever", steps),
+            ControlError::Parameter(err) => write!(f, "{}", err),
+            ControlError::Located { provenance, error } => write!(f, "{}: {}", provenance, error),
+        }
+    }
+}
+
+impl error::Error for ControlError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ControlError::Parameter(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl ControlError {
+    /// The stable code identifying the kind of error (see `diagnostic::CODES`), which is the one
+    /// of the underlying error for errors located at a block.
+    pub fn code(&self) -> Code {
+        match self {
+            ControlError::Unmatched { .. } => Code::error(501),
+            ControlError::UnknownSubroutine(_) => Code::error(502),
+            ControlError::TooDeep(_) => Code::error(503),
+            ControlError::StepLimit(_) => Code::error(504),
+            ControlError::Parameter(err) => err.code(),
+            ControlError::Located { error, .. } => error.code(),
+        }
+    }
+}
+
+impl From<ParamError> for ControlError {
+    fn from(err: ParamError) -> Self {
+        ControlError::Parameter(err)
+    }
+}
+
+/// The state of a subroutine call, or of the main program.
+#[derive(Debug)]
+#[cfg(feature = "std")]
+struct Frame {
+    /// The block to continue with after returning and the parameters from before the call
+    caller: Option<(usize, Parameters)>,
+
+    /// The iterations left of the repeat loops entered, by index of their `repeat` block
+    repeats: HashMap<usize, u64>,
+}
+
+/// Iterator executing the control flow of a program, returning the blocks without O-words in
+/// the order they are executed.
+///
+/// Parameters are substituted and assigned along the way, so the returned blocks contain neither
+/// expressions nor assignments. Subroutine definitions are skipped unless called, and the
+/// arguments of calls are passed in the local parameters `#1` and up. Values returned from a
+/// subroutine are available in `#<_value>`, with `#<_value_returned>` telling if there is one.
+#[cfg(feature = "std")]
+pub struct Flow<'a> {
+    blocks: &'a [Block],
+    parameters: Parameters,
+
+    /// The block closing each subroutine, conditional and loop, by index of its first block
+    end: HashMap<usize, usize>,
+
+    /// The first block of the subroutine, conditional or loop each inner block belongs to
+    owner: HashMap<usize, usize>,
+
+    /// The `elseif` and `else` blocks of each conditional, by index of its `if` block
+    branches: HashMap<usize, Vec<usize>>,
+
+    subroutines: HashMap<Label, usize>,
+
+    next: usize,
+    frames: Vec<Frame>,
+    steps: usize,
+    limit: usize,
+    failed: bool,
+}
+
+#[cfg(feature = "std")]
+impl<'a> Flow<'a> {
+    /// Prepares the control flow of the blocks, failing if its blocks are not properly nested.
+    pub fn new(blocks: &'a [Block]) -> Result<Self, ControlError> {
+        let mut flow = Self {
+            blocks,
+            parameters: Parameters::new(),
+            end: HashMap::new(),
+            owner: HashMap::new(),
+            branches: HashMap::new(),
+            subroutines: HashMap::new(),
+            next: 0,
+            frames: vec![Frame { caller: None, repeats: HashMap::new() }],
+            steps: 0,
+            limit: MAX_STEPS,
+            failed: false,
+        };
+
+        // The first blocks of all enclosing subroutines, conditionals and loops
+        let mut open: Vec<usize> = Vec::new();
+
+        for (i, block) in blocks.iter().enumerate() {
+            let control = match block.control() {
+                Some(control) => control,
+                None => continue,
+            };
+
+            let unmatched = || ControlError::Located {
+                provenance: block.provenance().clone(),
+                error: Box::new(ControlError::Unmatched { label: control.label.clone(), keyword: control.keyword }),
+            };
+
+            // The innermost enclosing block with the same label and one of the given keywords
+            let enclosing = |keywords: &[Keyword], innermost: bool| {
+                let mut candidates = open.iter().rev();
+                if innermost {
+                    candidates.next()
+                            .cloned()
+                            .filter(|&start| Self::matches(&blocks[start], &control.label, keywords))
+                } else {
+                    candidates.cloned().find(|&start| Self::matches(&blocks[start], &control.label, keywords))
+                }
+            };
+
+            match control.keyword {
+                Keyword::While if enclosing(&[Keyword::Do], true).is_some() => {
+                    let start = open.pop().expect("Matched do");
+                    flow.end.insert(start, i);
+                    flow.owner.insert(i, start);
+                }
+
+                Keyword::Sub => {
+                    flow.subroutines.insert(control.label.clone(), i);
+                    open.push(i);
+                }
+
+                Keyword::If | Keyword::While | Keyword::Do | Keyword::Repeat => open.push(i),
+
+                Keyword::ElseIf | Keyword::Else => {
+                    let start = enclosing(&[Keyword::If], true).ok_or_else(unmatched)?;
+                    flow.branches.entry(start).or_default().push(i);
+                    flow.owner.insert(i, start);
+                }
+
+                Keyword::EndSub | Keyword::EndIf | Keyword::EndWhile | Keyword::EndRepeat => {
+                    let opening = match control.keyword {
+                        Keyword::EndSub => Keyword::Sub,
+                        Keyword::EndIf => Keyword::If,
+                        Keyword::EndWhile => Keyword::While,
+                        _ => Keyword::Repeat,
+                    };
+
+                    let start = enclosing(&[opening], true).ok_or_else(unmatched)?;
+                    open.pop();
+                    flow.end.insert(start, i);
+                    flow.owner.insert(i, start);
+                }
+
+                Keyword::Break | Keyword::Continue => {
+                    let start = enclosing(&[Keyword::While, Keyword::Do, Keyword::Repeat], false).ok_or_else(unmatched)?;
+                    flow.owner.insert(i, start);
+                }
+
+                Keyword::Return => {
+                    let start = enclosing(&[Keyword::Sub], false).ok_or_else(unmatched)?;
+                    flow.owner.insert(i, start);
+                }
+
+                Keyword::Call => {}
+            }
+        }
+
+        if let Some(&start) = open.last() {
+            let control = blocks[start].control().expect("Control block");
+            return Err(ControlError::Located {
+                provenance: blocks[start].provenance().clone(),
+                error: Box::new(ControlError::Unmatched { label: control.label.clone(), keyword: control.keyword }),
+            });
+        }
+
+        return Ok(flow);
+    }
+
+    /// Sets the values of parameters before executing the first block.
+    pub fn with_parameters(mut self, parameters: Parameters) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// Sets the number of blocks, including O-words, executed at most.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// The values of the parameters assigned so far.
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    fn matches(block: &Block, label: &Label, keywords: &[Keyword]) -> bool {
+        return block.control()
+                .is_some_and(|control| &control.label == label && keywords.contains(&control.keyword));
+    }
+
+    fn keyword(&self, index: usize) -> Keyword {
+        self.blocks[index].control().expect("Control block").keyword
+    }
+
+    fn condition(&self, control: &Control) -> Result<bool, ControlError> {
+        return Ok(control.arguments[0].evaluate(&self.parameters)?.abs() >= 1e-6);
+    }
+
+    /// Executes the O-word of the block at `index` and determines the next block.
+    fn step(&mut self, index: usize, control: &Control) -> Result<usize, ControlError> {
+        return Ok(match control.keyword {
+            Keyword::Sub => self.end[&index] + 1,
+
+            Keyword::Call => {
+                let start = *self.subroutines.get(&control.label)
+                        .ok_or_else(|| ControlError::UnknownSubroutine(control.label.clone()))?;
+
+                if self.frames.len() > MAX_DEPTH {
+                    return Err(ControlError::TooDeep(MAX_DEPTH));
+                }
+
+                let arguments = control.arguments.iter()
+                        .map(|argument| argument.evaluate(&self.parameters))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                let saved = self.parameters.clone();
+                self.parameters.clear_locals();
+                for (i, value) in arguments.into_iter().enumerate() {
+                    self.parameters.set(Parameter::Numbered(i as u32 + 1), value);
+                }
+
+                self.frames.push(Frame { caller: Some((index + 1, saved)), repeats: HashMap::new() });
+                start + 1
+            }
+
+            Keyword::EndSub | Keyword::Return => {
+                let value = control.arguments.first()
+                        .map(|argument| argument.evaluate(&self.parameters))
+                        .transpose()?;
+
+                let (next, saved) = match self.frames.pop() {
+                    Some(Frame { caller: Some(caller), .. }) => caller,
+                    _ => return Err(ControlError::Unmatched { label: control.label.clone(), keyword: control.keyword }),
+                };
+
+                self.parameters.restore_locals(&saved);
+                self.parameters.set(Parameter::named("_value_returned"), if value.is_some() { 1.0 } else { 0.0 });
+                if let Some(value) = value {
+                    self.parameters.set(Parameter::named("_value"), value);
+                }
+
+                next
+            }
+
+            Keyword::If => {
+                if self.condition(control)? {
+                    return Ok(index + 1);
+                }
+
+                for &branch in self.branches.get(&index).map(Vec::as_slice).unwrap_or(&[]) {
+                    let branch_control = self.blocks[branch].control().expect("Control block");
+                    if branch_control.keyword == Keyword::Else || self.condition(branch_control)? {
+                        return Ok(branch + 1);
+                    }
+                }
+
+                self.end[&index] + 1
+            }
+
+            // Reached at the end of the branch taken
+            Keyword::ElseIf | Keyword::Else => self.end[&self.owner[&index]] + 1,
+
+            Keyword::While => match self.owner.get(&index) {
+                // Closing a do loop
+                Some(&start) => if self.condition(control)? { start + 1 } else { index + 1 },
+
+                None => if self.condition(control)? { index + 1 } else { self.end[&index] + 1 },
+            },
+
+            Keyword::EndWhile => self.owner[&index],
+
+            Keyword::Repeat => {
+                let count = control.arguments[0].evaluate(&self.parameters)?.round();
+                if count < 1.0 {
+                    return Ok(self.end[&index] + 1);
+                }
+
+                self.frame().repeats.insert(index, count as u64);
+                index + 1
+            }
+
+            Keyword::EndRepeat => {
+                let start = self.owner[&index];
+                let repeats = &mut self.frame().repeats;
+
+                let left = repeats.get_mut(&start).expect("Entered repeat");
+                *left -= 1;
+                if *left > 0 {
+                    start + 1
+                } else {
+                    repeats.remove(&start);
+                    index + 1
+                }
+            }
+
+            Keyword::Break => {
+                let start = self.owner[&index];
+                self.frame().repeats.remove(&start);
+                self.end[&start] + 1
+            }
+
+            Keyword::Continue => {
+                let start = self.owner[&index];
+                match self.keyword(start) {
+                    Keyword::While => start,
+                    _ => self.end[&start],
+                }
+            }
+
+            Keyword::EndIf | Keyword::Do => index + 1,
+        });
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("Main frame")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> Iterator for Flow<'a> {
+    type Item = Result<Block, ControlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let blocks = self.blocks;
+
+        while !self.failed {
+            let index = self.next;
+            let block = blocks.get(index)?;
+
+            self.steps += 1;
+            let result = if self.steps > self.limit {
+                Err(ControlError::StepLimit(self.limit))
+            } else if let Some(control) = block.control() {
+                self.step(index, control).map(|next| self.next = next)
+            } else {
+                self.next = index + 1;
+                match self.parameters.resolve(block) {
+                    Ok(block) => return Some(Ok(block)),
+                    Err(err) => Err(err.into()),
+                }
+            };
+
+            if let Err(error) = result {
+                self.failed = true;
+                return Some(Err(ControlError::Located {
+                    provenance: block.provenance().clone(),
+                    error: Box::new(error),
+                }));
+            }
+        }
+
+        return None;
+    }
+}
+
+#[cfg(feature = "std")]
+impl Program {
+    /// Executes the control flow of the program, returning the blocks in the order they are
+    /// executed (see `Flow`).
+    pub fn unroll(&self) -> Result<Program, ControlError> {
+        Flow::new(self.blocks())?.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn unroll(lines: &[&str]) -> Result<Vec<String>, ControlError> {
+        let program = Program::from(Parser::new().parse_all(lines.iter()).unwrap());
+        return Ok(program.unroll()?.iter().map(|block| block.to_string()).collect());
+    }
+
+    #[test]
+    fn test_subroutines() {
+        let blocks = unroll(&[
+            "o<square> sub",
+            "  G1 X#1",
+            "  o<square> if [#1 GT 5]",
+            "    o<square> return [#1 * 2]",
+            "  o<square> endif",
+            "o<square> endsub [#1]",
+            "#1 = 7",
+            "o<square> call [2]",
+            "G0 X#<_value> Y#1",
+            "o<square> call [10]",
+            "G0 X#<_value>",
+        ]).unwrap();
+
+        assert_eq!(blocks, vec!["", "G1 X2", "G0 X2 Y7", "G1 X10", "G0 X20"]);
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let program = |value: &str| unroll(&[
+            &format!("#1 = {}", value),
+            "o1 if [#1 EQ 1]",
+            "  G0 X1",
+            "o1 elseif [#1 EQ 2]",
+            "  G0 X2",
+            "o1 else",
+            "  G0 X3",
+            "o1 endif",
+            "M2",
+        ]).unwrap();
+
+        assert_eq!(program("1"), vec!["", "G0 X1", "M2"]);
+        assert_eq!(program("2"), vec!["", "G0 X2", "M2"]);
+        assert_eq!(program("5"), vec!["", "G0 X3", "M2"]);
+    }
+
+    #[test]
+    fn test_loops() {
+        let blocks = unroll(&[
+            "#1 = 0",
+            "o1 while [#1 LT 3]",
+            "  #1 = [#1 + 1]",
+            "  o1 if [#1 EQ 2]",
+            "    o1 continue",
+            "  o1 endif",
+            "  G0 X#1",
+            "o1 endwhile",
+            "o2 repeat [2]",
+            "  G0 Y#1",
+            "  o3 do",
+            "    #1 = [#1 - 1]",
+            "    o3 if [#1 LT 2]",
+            "      o3 break",
+            "    o3 endif",
+            "  o3 while [1]",
+            "o2 endrepeat",
+        ]).unwrap();
+
+        let moves = blocks.into_iter().filter(|block| block.starts_with('G')).collect::<Vec<_>>();
+        assert_eq!(moves, vec!["G0 X1", "G0 X3", "G0 Y3", "G0 Y1"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |lines: &[&str]| match unroll(lines) {
+            Err(ControlError::Located { error, .. }) => *error,
+            result => panic!("unexpected result: {:?}", result),
+        };
+
+        assert!(matches!(error(&["o1 if [1]", "o2 endif"]), ControlError::Unmatched { .. }));
+        assert!(matches!(error(&["o1 if [1]"]), ControlError::Unmatched { keyword: Keyword::If, .. }));
+        assert!(matches!(error(&["o1 break"]), ControlError::Unmatched { .. }));
+        assert!(matches!(error(&["o1 call"]), ControlError::UnknownSubroutine(_)));
+
+        let program = Program::from(Parser::new().parse_all(["o1 while [1]", "o1 endwhile"].iter()).unwrap());
+        let result = Flow::new(program.blocks()).unwrap().with_limit(100).collect::<Result<Vec<_>, _>>();
+        match result {
+            Err(ControlError::Located { error, .. }) => assert!(matches!(*error, ControlError::StepLimit(100))),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,471 @@
+//! Synthetic programs with known properties, for testing analyses against analytic expectations.
+//!
+//! Each generated sample carries the ground truth recorded while generating it, independently of
+//! the interpreter. Generation is deterministic for a given seed.
+
+
+use crate::Real;
+use crate::real::consts::PI;
+use crate::geometry::Point3;
+use crate::parser::{Block, Comment, CommentPosition, CommentStyle, Word};
+use crate::program::Program;
+
+/// The size of the square work area programs are generated in, in millimeters.
+const AREA: Real = 200.0;
+
+/// The height rapids are done at between cuts, in millimeters.
+const SAFE_Z: Real = 5.0;
+
+/// Number of straight segments per turn of a spiral.
+const SPIRAL_SEGMENTS: usize = 36;
+
+/// Filament extruded per millimeter of printed path.
+const EXTRUSION: Real = 0.05;
+
+/// A small, deterministic pseudo random number generator (xorshift64*).
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            // The state must never be zero
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+    }
+
+    /// A value uniformly distributed in `[min, max)`.
+    pub fn range(&mut self, min: Real, max: Real) -> Real {
+        let unit = (self.next_u64() >> 11) as Real / (1u64 << 53) as Real;
+        return min + (max - min) * unit;
+    }
+
+    /// Rounds a value in `[min, max)` to three decimals, as programs are usually written.
+    fn coordinate(&mut self, min: Real, max: Real) -> Real {
+        return (self.range(min, max) * 1000.0).round() / 1000.0;
+    }
+}
+
+/// The properties of a generated program, all in machine coordinates and millimeters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Truth {
+    /// The extents of all motion, including the origin the machine starts at.
+    pub min: Point3,
+    pub max: Point3,
+
+    /// The distance travelled in the XY plane by all moves, including rapids.
+    pub travel: Real,
+
+    /// The length of all feed moves.
+    pub cutting: Real,
+
+    /// The time all moves take at their programmed feed rate or the rapid rate, assuming
+    /// unlimited acceleration, in seconds.
+    pub time: Real,
+
+    /// The position after the last move.
+    pub end: Point3,
+
+    /// The length of filament extruded by printed programs.
+    pub extrusion: Real,
+
+    /// The number of layers of printed programs.
+    pub layers: usize,
+}
+
+/// A generated program along with its ground truth.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub program: Program,
+    pub truth: Truth,
+}
+
+/// Generates programs of different shapes.
+#[derive(Debug, Clone)]
+pub struct Corpus {
+    rng: Rng,
+
+    /// The velocity of rapids along each axis in millimeters per minute. All feed rates stay
+    /// below it.
+    rapid: Real,
+}
+
+impl Corpus {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+            rapid: 500.0,
+        }
+    }
+
+    /// Sets the velocity of rapids along each axis, which is used for the ground truth time.
+    ///
+    /// The rapid velocity of the machine profile used to estimate the generated programs must be
+    /// the same for all axes and match this velocity.
+    pub fn with_rapid(mut self, rapid: Real) -> Self {
+        self.rapid = rapid;
+        self
+    }
+
+    fn feed(&mut self) -> Real {
+        return (self.rng.range(0.1, 1.0) * self.rapid).round().max(1.0);
+    }
+
+    /// Cuts the outlines of random rectangles at random depths.
+    pub fn rectangles(&mut self, count: usize) -> Sample {
+        let mut recorder = Recorder::new(self.rapid);
+
+        for _ in 0..count {
+            let x = self.rng.coordinate(0.0, AREA / 2.0);
+            let y = self.rng.coordinate(0.0, AREA / 2.0);
+            let width = self.rng.coordinate(1.0, AREA / 2.0);
+            let height = self.rng.coordinate(1.0, AREA / 2.0);
+            let depth = self.rng.coordinate(0.1, 3.0);
+            let feed = self.feed();
+
+            recorder.lift(SAFE_Z);
+            recorder.rapid(Point3::new(x, y, SAFE_Z));
+            recorder.line(Point3::new(x, y, -depth), feed);
+            recorder.line(Point3::new(x + width, y, -depth), feed);
+            recorder.line(Point3::new(x + width, y + height, -depth), feed);
+            recorder.line(Point3::new(x, y + height, -depth), feed);
+            recorder.line(Point3::new(x, y, -depth), feed);
+        }
+
+        recorder.lift(SAFE_Z);
+
+        return recorder.finish();
+    }
+
+    /// Cuts random full circles as single arcs.
+    pub fn circles(&mut self, count: usize) -> Sample {
+        let mut recorder = Recorder::new(self.rapid);
+
+        for _ in 0..count {
+            let radius = self.rng.coordinate(1.0, AREA / 4.0);
+            let x = self.rng.coordinate(radius, AREA - radius);
+            let y = self.rng.coordinate(radius, AREA - radius);
+            let depth = self.rng.coordinate(0.1, 3.0);
+            let clockwise = self.rng.next_u64() % 2 == 0;
+            let feed = self.feed();
+
+            recorder.lift(SAFE_Z);
+            recorder.rapid(Point3::new(x + radius, y, SAFE_Z));
+            recorder.line(Point3::new(x + radius, y, -depth), feed);
+            recorder.circle(radius, clockwise, feed);
+        }
+
+        recorder.lift(SAFE_Z);
+
+        return recorder.finish();
+    }
+
+    /// Cuts an Archimedean spiral out from the center of the work area, made of straight
+    /// segments.
+    pub fn spiral(&mut self, turns: usize) -> Sample {
+        let mut recorder = Recorder::new(self.rapid);
+
+        let center = AREA / 2.0;
+        let radius = self.rng.coordinate(10.0, AREA / 2.0);
+        let depth = self.rng.coordinate(0.1, 3.0);
+        let feed = self.feed();
+
+        recorder.rapid(Point3::new(0.0, 0.0, SAFE_Z));
+        recorder.rapid(Point3::new(center, center, SAFE_Z));
+        recorder.line(Point3::new(center, center, -depth), feed);
+
+        let segments = turns * SPIRAL_SEGMENTS;
+        for i in 1..=segments {
+            let t = i as Real / segments as Real;
+            let angle = 2.0 * PI * turns as Real * t;
+            recorder.line(Point3::new(center + radius * t * angle.cos(),
+                                      center + radius * t * angle.sin(),
+                                      -depth), feed);
+        }
+
+        recorder.lift(SAFE_Z);
+
+        return recorder.finish();
+    }
+
+    /// Prints a stack of rectangular perimeters like a slicer does, with `;LAYER:` markers,
+    /// temperatures, a fan and absolute extrusion.
+    pub fn print(&mut self, layers: usize) -> Sample {
+        let mut recorder = Recorder::new(self.rapid);
+
+        let height = self.rng.coordinate(0.1, 0.4);
+        let x = self.rng.coordinate(0.0, AREA / 2.0);
+        let y = self.rng.coordinate(0.0, AREA / 2.0);
+        let width = self.rng.coordinate(5.0, AREA / 2.0);
+        let depth = self.rng.coordinate(5.0, AREA / 2.0);
+        let feed = self.feed();
+
+        recorder.words(vec![Word::new('M', 140.0), Word::new('S', 60.0)]);
+        recorder.words(vec![Word::new('M', 104.0), Word::new('S', 200.0)]);
+        recorder.words(vec![Word::new('G', 92.0), Word::new('E', 0.0)]);
+
+        for layer in 0..layers {
+            recorder.block(Block::new(Vec::new())
+                    .with_comment(Comment::new(format!("LAYER:{}", layer), CommentStyle::Semicolon, CommentPosition::Leading)));
+            if layer == 1 {
+                recorder.words(vec![Word::new('M', 106.0), Word::new('S', 255.0)]);
+            }
+
+            let z = ((layer + 1) as Real * height * 1000.0).round() / 1000.0;
+            recorder.lift(z);
+            recorder.rapid(Point3::new(x, y, z));
+            recorder.extrude(Point3::new(x + width, y, z), feed);
+            recorder.extrude(Point3::new(x + width, y + depth, z), feed);
+            recorder.extrude(Point3::new(x, y + depth, z), feed);
+            recorder.extrude(Point3::new(x, y, z), feed);
+        }
+
+        recorder.words(vec![Word::new('M', 107.0)]);
+        recorder.truth.layers = layers;
+
+        return recorder.finish();
+    }
+}
+
+/// Writes the blocks of a sample and records its ground truth along the way.
+struct Recorder {
+    program: Program,
+    truth: Truth,
+    position: Point3,
+    extruder: Real,
+    rapid: Real,
+}
+
+impl Recorder {
+    fn new(rapid: Real) -> Self {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+
+        Self {
+            program: Program::new(),
+            truth: Truth {
+                min: origin,
+                max: origin,
+                travel: 0.0,
+                cutting: 0.0,
+                time: 0.0,
+                end: origin,
+                extrusion: 0.0,
+                layers: 0,
+            },
+            position: origin,
+            extruder: 0.0,
+            rapid,
+        }
+    }
+
+    fn block(&mut self, block: Block) {
+        self.program.push(block);
+    }
+
+    fn words(&mut self, words: Vec<Word>) {
+        self.block(Block::new(words));
+    }
+
+    fn extend(&mut self, point: Point3) {
+        let (min, max) = (self.truth.min, self.truth.max);
+        self.truth.min = Point3::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z));
+        self.truth.max = Point3::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z));
+    }
+
+    fn travel(&mut self, to: Point3) {
+        self.extend(to);
+        self.truth.travel += self.position.xy().distance(to.xy());
+        self.position = to;
+    }
+
+    fn target(to: Point3) -> Vec<Word> {
+        return vec![Word::new('X', to.x), Word::new('Y', to.y), Word::new('Z', to.z)];
+    }
+
+    fn rapid(&mut self, to: Point3) {
+        // Each axis moves at the rapid rate, so the slowest one takes the longest
+        let delta = [to.x - self.position.x, to.y - self.position.y, to.z - self.position.z];
+        let longest = delta.iter().fold(0.0 as Real, |longest, delta| longest.max(delta.abs()));
+        self.truth.time += longest / self.rapid * 60.0;
+
+        let mut words = vec![Word::new('G', 0.0)];
+        words.extend(Self::target(to));
+        self.words(words);
+
+        self.travel(to);
+    }
+
+    /// Moves straight up or down to the given height.
+    fn lift(&mut self, z: Real) {
+        self.rapid(Point3::new(self.position.x, self.position.y, z));
+    }
+
+    fn feed(&mut self, length: Real, feed: Real) {
+        self.truth.cutting += length;
+        self.truth.time += length / feed * 60.0;
+    }
+
+    fn line(&mut self, to: Point3, feed: Real) {
+        let length = self.position.distance(to);
+        self.feed(length, feed);
+
+        let mut words = vec![Word::new('G', 1.0)];
+        words.extend(Self::target(to));
+        words.push(Word::new('F', feed));
+        self.words(words);
+
+        self.travel(to);
+    }
+
+    /// Cuts a full circle in the XY plane, starting at the rightmost point of the circle.
+    fn circle(&mut self, radius: Real, clockwise: bool, feed: Real) {
+        let center = Point3::new(self.position.x - radius, self.position.y, self.position.z);
+        let length = 2.0 * PI * radius;
+        self.feed(length, feed);
+
+        self.words(vec![Word::new('G', if clockwise { 2.0 } else { 3.0 }),
+                        Word::new('X', self.position.x), Word::new('Y', self.position.y),
+                        Word::new('I', -radius), Word::new('J', 0.0),
+                        Word::new('F', feed)]);
+
+        self.extend(Point3::new(center.x - radius, center.y - radius, center.z));
+        self.extend(Point3::new(center.x + radius, center.y + radius, center.z));
+        self.truth.travel += length;
+    }
+
+    fn extrude(&mut self, to: Point3, feed: Real) {
+        let length = self.position.distance(to);
+        self.feed(length, feed);
+
+        let extrusion = length * EXTRUSION;
+        self.extruder += extrusion;
+        self.truth.extrusion += extrusion;
+
+        self.words(vec![Word::new('G', 1.0), Word::new('X', to.x), Word::new('Y', to.y),
+                        Word::new('E', self.extruder), Word::new('F', feed)]);
+
+        self.travel(to);
+    }
+
+    fn finish(mut self) -> Sample {
+        self.truth.end = self.position;
+
+        return Sample {
+            program: self.program,
+            truth: self.truth,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{bounds, bounds_with, estimate, estimate_with, layer_stats, MachineProfile};
+    use crate::interp::Machine;
+    use crate::printer::PrinterState;
+
+    const SEEDS: u64 = 20;
+
+    fn close(actual: Real, expected: Real, tolerance: Real) -> bool {
+        return (actual - expected).abs() <= tolerance * expected.abs().max(1.0);
+    }
+
+    /// A profile with unlimited acceleration, so moves run at their feed rates all along.
+    fn ideal() -> MachineProfile {
+        MachineProfile {
+            max_velocity: Point3::new(500.0, 500.0, 500.0),
+            rapid_velocity: Point3::new(500.0, 500.0, 500.0),
+            max_acceleration: Point3::new(1e12, 1e12, 1e12),
+            arc_tolerance: 1e-5,
+            ..MachineProfile::default()
+        }
+    }
+
+    fn samples() -> Vec<Sample> {
+        let mut samples = Vec::new();
+        for seed in 0..SEEDS {
+            let mut corpus = Corpus::new(seed);
+            samples.push(corpus.rectangles(1 + seed as usize % 5));
+            samples.push(corpus.circles(1 + seed as usize % 3));
+            samples.push(corpus.spiral(1 + seed as usize % 4));
+        }
+
+        return samples;
+    }
+
+    #[test]
+    fn test_deterministic() {
+        assert_eq!(Corpus::new(7).rectangles(3).program, Corpus::new(7).rectangles(3).program);
+        assert_ne!(Corpus::new(7).rectangles(3).program, Corpus::new(8).rectangles(3).program);
+    }
+
+    #[test]
+    fn test_bounds() {
+        for sample in samples() {
+            let bounds = bounds(&sample.program).unwrap().unwrap();
+
+            assert!(close(bounds.min.x, sample.truth.min.x, crate::EPSILON), "{:?} != {:?}", bounds, sample.truth);
+            assert!(close(bounds.min.y, sample.truth.min.y, crate::EPSILON), "{:?} != {:?}", bounds, sample.truth);
+            assert!(close(bounds.min.z, sample.truth.min.z, crate::EPSILON), "{:?} != {:?}", bounds, sample.truth);
+            assert!(close(bounds.max.x, sample.truth.max.x, crate::EPSILON), "{:?} != {:?}", bounds, sample.truth);
+            assert!(close(bounds.max.y, sample.truth.max.y, crate::EPSILON), "{:?} != {:?}", bounds, sample.truth);
+            assert!(close(bounds.max.z, sample.truth.max.z, crate::EPSILON), "{:?} != {:?}", bounds, sample.truth);
+            assert!(close(bounds.travel, sample.truth.travel, crate::EPSILON), "{:?} != {:?}", bounds, sample.truth);
+        }
+    }
+
+    #[test]
+    fn test_interpreter() {
+        for sample in samples() {
+            let mut machine = Machine::new();
+            for block in &sample.program {
+                machine.execute(block).unwrap();
+            }
+
+            assert_eq!(machine.state().position, sample.truth.end);
+            assert_eq!(machine.blocks(), sample.program.len());
+        }
+    }
+
+    #[test]
+    fn test_estimate() {
+        for sample in samples() {
+            // Arcs are estimated as chords, which are slightly shorter
+            let estimate = estimate(&sample.program, &ideal()).unwrap();
+            assert!(close(estimate.total, sample.truth.time, 1e-4), "{} != {}", estimate.total, sample.truth.time);
+
+            // Limited acceleration only makes the program slower
+            let estimate = estimate_with(Machine::new(), &sample.program, &MachineProfile::default()).unwrap();
+            assert!(estimate.total >= sample.truth.time * (1.0 - 1e-4), "{} < {}", estimate.total, sample.truth.time);
+        }
+    }
+
+    #[test]
+    fn test_print() {
+        for seed in 0..SEEDS {
+            let sample = Corpus::new(seed).print(1 + seed as usize % 10);
+
+            let layers = layer_stats(&sample.program, &ideal()).unwrap();
+            assert_eq!(layers.len(), sample.truth.layers);
+            assert!(close(layers.iter().map(|layer| layer.extrusion).sum(), sample.truth.extrusion, crate::EPSILON));
+            assert!(close(layers.iter().map(|layer| layer.time).sum(), sample.truth.time, crate::EPSILON));
+
+            let mut printer = PrinterState::new();
+            let reduced = sample.program.iter()
+                    .map(|block| printer.apply(block).1)
+                    .collect::<Program>();
+            let bounds = bounds_with(Machine::new(), &reduced).unwrap().unwrap();
+            assert!(close(bounds.max.z, sample.truth.max.z, crate::EPSILON));
+            assert!(close(bounds.travel, sample.truth.travel, crate::EPSILON));
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Human friendly rendering of parser errors, quoting the offending line, and stable codes
+//! identifying each kind of error.
+//!
+//! ```text
+//! error[E0001]: illegal symbol: §
+//!  --> part.nc:3:4
+//!   |
+//! 3 | G1 §10 Y5
+//!   |    ^
+//!   = note: only letters, numbers, operators and comments are allowed
+//! ```
+
+use alloc::borrow::ToOwned;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::parser::{LexerError, ParserError};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// A stable code identifying a kind of diagnostic, written like `E0002` for errors and `W0101`
+/// for warnings.
+///
+/// Codes are grouped by hundreds: syntax (`00`), semantics of blocks (`01`), interpretation
+/// (`02`), arcs (`03`), parameters (`04`) and control flow (`05`). Codes never change their
+/// meaning, so hosts can use them to look up translated messages or documentation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Code {
+    severity: Severity,
+    number: u16,
+}
+
+impl Code {
+    pub const fn error(number: u16) -> Self {
+        Self { severity: Severity::Error, number }
+    }
+
+    pub const fn warning(number: u16) -> Self {
+        Self { severity: Severity::Warning, number }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn number(&self) -> u16 {
+        self.number
+    }
+
+    /// The description of the code from `CODES`.
+    pub fn description(&self) -> &'static str {
+        CODES.iter()
+                .find(|(code, _)| code == self)
+                .map_or("unknown diagnostic", |&(_, description)| description)
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = match self.severity {
+            Severity::Error => 'E',
+            Severity::Warning => 'W',
+        };
+
+        write!(f, "{}{:04}", prefix, self.number)
+    }
+}
+
+impl FromStr for Code {
+    type Err = ();
+
+    /// Parses codes listed in `CODES`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return CODES.iter()
+                .map(|&(code, _)| code)
+                .find(|code| code.to_string().eq_ignore_ascii_case(s))
+                .ok_or(());
+    }
+}
+
+/// All codes with a description of their meaning.
+pub const CODES: &[(Code, &str)] = &[
+    (Code::error(1), "illegal symbol"),
+    (Code::error(2), "invalid number"),
+    (Code::error(3), "invalid parameter name"),
+    (Code::error(4), "unknown operator or function"),
+    (Code::error(5), "unterminated string"),
+    (Code::error(10), "unexpected token"),
+    (Code::error(11), "missing value after a letter"),
+    (Code::error(12), "invalid parameter number"),
+    (Code::error(13), "letter not supported by the dialect"),
+    (Code::error(14), "error reading the input"),
+    (Code::error(101), "conflicting words of the same modal group"),
+    (Code::warning(101), "word given more than once in a block"),
+    (Code::warning(102), "line number not increasing"),
+    (Code::warning(103), "line number given more than once"),
+    (Code::warning(201), "feed move before the feed rate is set"),
+    (Code::warning(202), "move before the machine is homed"),
+    (Code::warning(203), "arc before a plane is selected"),
+    (Code::warning(204), "extrusion while the hot end is cold"),
+    (Code::error(201), "word not supported by the interpreter or dialect"),
+    (Code::error(202), "control flow which must be unrolled before interpretation"),
+    (Code::error(203), "canned cycle which must be expanded before interpretation"),
+    (Code::error(204), "axis words without active motion mode"),
+    (Code::error(205), "feed move without feed rate in inverse time mode"),
+    (Code::error(206), "unknown coordinate system"),
+    (Code::error(207), "unknown tool"),
+    (Code::error(208), "probing move completed without tripping the probe"),
+    (Code::error(301), "arc without center offset or radius"),
+    (Code::error(302), "arc with both center offset and radius"),
+    (Code::error(303), "arc center offset outside of the selected plane"),
+    (Code::error(304), "arc with zero radius"),
+    (Code::error(305), "arc radius differs between start and end"),
+    (Code::error(306), "arc radius too small to reach the end point"),
+    (Code::error(307), "full circle arc given by radius"),
+    (Code::error(308), "invalid number of arc turns"),
+    (Code::error(401), "undefined parameter"),
+    (Code::error(402), "invalid parameter number"),
+    (Code::error(403), "division by zero"),
+    (Code::error(404), "function undefined for its argument"),
+    (Code::error(501), "O-word without matching block"),
+    (Code::error(502), "unknown subroutine"),
+    (Code::error(503), "subroutine calls nested too deeply"),
+    (Code::error(504), "step limit exceeded, the program probably loops forever"),
+];
+
+/// A parser error prepared for display, optionally along with the line it occurred on.
+#[derive(Debug, Clone)]
+pub struct Diagnostic<'a> {
+    error: &'a ParserError,
+    source: Option<&'a str>,
+    line: Option<&'a str>,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(error: &'a ParserError) -> Self {
+        Self {
+            error,
+            source: None,
+            line: None,
+        }
+    }
+
+    /// Sets the name of the input, usually its file name, shown before the location.
+    pub fn with_source(mut self, source: &'a str) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Sets the line the error occurred on, which is quoted with the offending part marked.
+    pub fn with_line(mut self, line: &'a str) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    /// Looks up the line the error occurred on in the complete input.
+    pub fn with_text(self, text: &'a str) -> Self {
+        match text.lines().nth(self.error.span().line.saturating_sub(1)) {
+            Some(line) => self.with_line(line),
+            None => self,
+        }
+    }
+
+    /// The description of the error, without its location.
+    pub fn message(&self) -> String {
+        match self.error {
+            ParserError::SyntaxError(err) => match err {
+                LexerError::IllegalSymbol { symbol, .. } => format!("illegal symbol: {}", symbol),
+                LexerError::InvalidNumber { text, .. } => format!("invalid number: {}", text),
+                LexerError::InvalidName { text, .. } => format!("invalid parameter name: {}", text),
+                LexerError::UnknownIdentifier { text, .. } => format!("unknown operator or function: {}", text),
+                LexerError::UnterminatedString { .. } => "unterminated string".to_owned(),
+            },
+            ParserError::UnexpectedToken { token, .. } => format!("unexpected token: {:?}", token),
+            ParserError::MissingValue { .. } => "missing value".to_owned(),
+            ParserError::InvalidParameter { value, .. } => format!("invalid parameter number: {}", value),
+            ParserError::UnknownIdentifier { name, .. } => format!("unknown operator or function: {}", name),
+            ParserError::UnsupportedLetter { letter, .. } => format!("letter not supported by the dialect: {}", letter),
+            #[cfg(feature = "std")]
+            ParserError::Io { error, .. } => format!("read error: {}", error),
+        }
+    }
+
+    /// A hint on how to resolve the error, if there is a common cause.
+    pub fn note(&self) -> Option<&'static str> {
+        match self.error {
+            ParserError::SyntaxError(LexerError::IllegalSymbol { .. }) => Some("only letters, numbers, operators and comments are allowed"),
+            ParserError::SyntaxError(LexerError::InvalidNumber { .. }) => Some("numbers are written like 12, -0.5 or .25"),
+            ParserError::SyntaxError(LexerError::UnterminatedString { .. }) => Some("strings end with a double quote, which is doubled within the string"),
+            ParserError::MissingValue { .. } => Some("every letter must be followed by a number or an expression in brackets"),
+            ParserError::InvalidParameter { .. } => Some("parameters are numbered by positive integers"),
+            ParserError::UnsupportedLetter { .. } => Some("the dialect of the parser is selected by `Parser::with_dialect`"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.error.span();
+
+        writeln!(f, "error[{}]: {}", self.error.code(), self.message())?;
+
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        match self.source {
+            Some(source) => writeln!(f, "{}--> {}:{}", gutter, source, span)?,
+            None => writeln!(f, "{}--> {}", gutter, span)?,
+        }
+
+        // Read errors have no offending part to mark
+        #[cfg(feature = "std")]
+        let line = self.line.filter(|_| !matches!(self.error, ParserError::Io { .. }));
+        #[cfg(not(feature = "std"))]
+        let line = self.line;
+
+        if let Some(line) = line {
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+
+            // Mark at least a single character, also at the end of the line
+            let start = span.start.min(line.len());
+            let end = span.end.clamp(start, line.len());
+            let (start, end) = match (line.get(..start), line.get(start..end)) {
+                (Some(before), Some(marked)) => (before.chars().count(), marked.chars().count()),
+                _ => (span.column.saturating_sub(1), 0),
+            };
+
+            writeln!(f, "{} |", gutter)?;
+            writeln!(f, "{} | {}", number, line)?;
+            writeln!(f, "{} | {}{}", gutter, " ".repeat(start), "^".repeat(end.max(1)))?;
+        }
+
+        if let Some(note) = self.note() {
+            writeln!(f, "{} = note: {}", gutter, note)?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// Renders all errors, like the diagnostics collected by a lenient parser, quoting the lines of
+/// the complete input.
+pub fn render(errors: &[ParserError], text: &str, source: Option<&str>) -> String {
+    let mut rendered = Vec::with_capacity(errors.len());
+    for error in errors {
+        let mut diagnostic = Diagnostic::new(error).with_text(text);
+        if let Some(source) = source {
+            diagnostic = diagnostic.with_source(source);
+        }
+
+        rendered.push(diagnostic.to_string());
+    }
+
+    return rendered.join("\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::InterpError;
+    use crate::parser::Parser;
+    use crate::toolpath::ArcError;
+
+    #[test]
+    fn test_render() {
+        let err = Parser::new().parse("  G1 X1.2.3").unwrap_err();
+        assert_eq!(Diagnostic::new(&err).with_source("part.nc").with_line("  G1 X1.2.3").to_string(), "\
+error[E0002]: invalid number: 1.2.3
+ --> part.nc:1:7
+  |
+1 |   G1 X1.2.3
+  |       ^^^^^
+  = note: numbers are written like 12, -0.5 or .25
+");
+
+        let err = Parser::new().parse("G1 X").unwrap_err();
+        assert_eq!(Diagnostic::new(&err).to_string(), "\
+error[E0011]: missing value
+ --> 1:4
+  = note: every letter must be followed by a number or an expression in brackets
+");
+    }
+
+    #[test]
+    fn test_codes() {
+        for (i, (code, _)) in CODES.iter().enumerate() {
+            assert!(CODES[i + 1..].iter().all(|(other, _)| other != code), "duplicate code {}", code);
+            assert_eq!(code.to_string().parse(), Ok(*code));
+        }
+
+        assert_eq!(Code::error(2).to_string(), "E0002");
+        assert_eq!(Code::warning(101).to_string(), "W0101");
+        assert_eq!("e0403".parse::<Code>().unwrap().description(), "division by zero");
+        assert_eq!("E9999".parse::<Code>(), Err(()));
+
+        assert_eq!(Parser::new().parse("G1 X1 %").unwrap_err().code(), Code::error(10));
+        assert_eq!(Parser::new().parse("G1 X1..2").unwrap_err().code(), Code::error(2));
+        assert_eq!(InterpError::from(ArcError::ZeroRadius).code(), Code::error(304));
+    }
+
+    #[test]
+    fn test_render_all() {
+        let text = "G0 X1\nG1 Y€\n\n\n\n\n\n\n\n\nG1 X\n";
+
+        let mut parser = Parser::new_lenient();
+        parser.parse_all(text.lines()).unwrap();
+        let rendered = render(&parser.take_diagnostics(), text, None);
+
+        assert_eq!(rendered, "\
+error[E0001]: illegal symbol: €
+ --> 2:5
+  |
+2 | G1 Y€
+  |     ^
+  = note: only letters, numbers, operators and comments are allowed
+
+error[E0011]: missing value
+  --> 11:4
+   |
+11 | G1 X
+   |    ^
+   = note: every letter must be followed by a number or an expression in brackets
+");
+    }
+}
@@ -0,0 +1,248 @@
+//! Dialects of G-code spoken by different controllers.
+//!
+//! A dialect configures the syntax accepted by the parser (see `Parser::with_dialect`) and the
+//! codes known to the interpreter (see `Machine::with_dialect`). The generic dialect, which is the
+//! default, accepts everything the crate understands.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::Real;
+use crate::modal::ModalGroup;
+use crate::parser::{CommentStyle, Word};
+
+const ALL_LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+const BOTH_COMMENTS: &[CommentStyle] = &[CommentStyle::Parentheses, CommentStyle::Semicolon];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Dialect {
+    /// Everything the crate understands.
+    #[default]
+    Generic,
+
+    /// RS274/NGC as extended by LinuxCNC.
+    LinuxCnc,
+
+    Grbl,
+
+    /// The firmware of most FDM printers.
+    Marlin,
+
+    /// RepRapFirmware, used by Duet boards.
+    RepRap,
+
+    Fanuc,
+}
+
+impl Dialect {
+    /// The letters words may start with.
+    pub fn letters(&self) -> &'static str {
+        match *self {
+            Dialect::Generic | Dialect::Marlin | Dialect::RepRap | Dialect::Fanuc => ALL_LETTERS,
+
+            // Extrusion is not a thing for machine tools
+            Dialect::LinuxCnc => "ABCDFGHIJKLMNOPQRSTUVWXYZ",
+            Dialect::Grbl => "ABCFGIJKLMNPRSTXYZ",
+        }
+    }
+
+    /// The delimiters of comments. Other delimiters are illegal symbols.
+    pub fn comment_styles(&self) -> &'static [CommentStyle] {
+        match *self {
+            Dialect::Generic | Dialect::LinuxCnc | Dialect::Grbl | Dialect::RepRap => BOTH_COMMENTS,
+            Dialect::Marlin => &[CommentStyle::Semicolon],
+            Dialect::Fanuc => &[CommentStyle::Parentheses],
+        }
+    }
+
+    /// Whether some commands take free text as argument, like `M117 Hello world`.
+    pub fn has_string_arguments(&self) -> bool {
+        match *self {
+            Dialect::Marlin | Dialect::RepRap => true,
+            Dialect::Generic | Dialect::LinuxCnc | Dialect::Grbl | Dialect::Fanuc => false,
+        }
+    }
+
+    /// Whether the M word takes the rest of the line as free text argument (see
+    /// `Block::argument`), like the message of `M117` or the file name of `M23`.
+    pub fn takes_string_argument(&self, word: &Word) -> bool {
+        let codes: &[Real] = match *self {
+            // Select, write and print SD files, display and echo messages, start logging
+            Dialect::Marlin => &[23.0, 28.0, 32.0, 117.0, 118.0, 928.0],
+            Dialect::RepRap => &[23.0, 28.0, 32.0, 117.0, 118.0],
+            Dialect::Generic | Dialect::LinuxCnc | Dialect::Grbl | Dialect::Fanuc => &[],
+        };
+
+        return word.mnemonic() == 'M' && codes.contains(&word.value());
+    }
+
+    /// Whether words may take strings enclosed in double quotes as values, like `M98 P"homeall.g"`.
+    pub fn has_quoted_strings(&self) -> bool {
+        match *self {
+            Dialect::Generic | Dialect::LinuxCnc | Dialect::RepRap => true,
+            Dialect::Grbl | Dialect::Marlin | Dialect::Fanuc => false,
+        }
+    }
+
+    /// Whether lines starting with `$` are system commands, like `$H` for homing.
+    pub fn has_system_commands(&self) -> bool {
+        match *self {
+            Dialect::Generic | Dialect::Grbl => true,
+            Dialect::LinuxCnc | Dialect::Marlin | Dialect::RepRap | Dialect::Fanuc => false,
+        }
+    }
+
+    /// The characters a controller acts upon immediately, wherever they appear in the stream. They
+    /// are skipped when parsing.
+    pub fn realtime_characters(&self) -> &'static [char] {
+        match *self {
+            // Status report, feed hold, cycle start and soft reset
+            Dialect::Grbl => &['?', '!', '~', '\u{18}'],
+            _ => &[],
+        }
+    }
+
+    /// The M codes the controller knows, or `None` if not restricted.
+    pub fn m_codes(&self) -> Option<&'static [Real]> {
+        match *self {
+            Dialect::Grbl => Some(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 7.0, 8.0, 9.0, 30.0, 56.0]),
+            Dialect::LinuxCnc => Some(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 30.0, 48.0, 49.0, 50.0, 51.0,
+                                        52.0, 53.0, 60.0, 61.0, 62.0, 63.0, 64.0, 65.0, 66.0, 67.0, 68.0, 70.0, 71.0,
+                                        72.0, 73.0]),
+            Dialect::Generic | Dialect::Marlin | Dialect::RepRap | Dialect::Fanuc => None,
+        }
+    }
+
+    /// Whether the M word is known to the controller. User defined M codes of LinuxCNC (M100 to
+    /// M199) are always known.
+    pub fn knows(&self, word: &Word) -> bool {
+        if word.mnemonic() != 'M' {
+            return true;
+        }
+
+        let user = *self == Dialect::LinuxCnc && word.value() >= 100.0 && word.value() < 200.0 && crate::is_whole(word.value());
+        return user || self.m_codes().is_none_or(|codes| codes.contains(&word.value()));
+    }
+
+    /// The modal group of a G or M word, if the controller groups it.
+    ///
+    /// Printer firmwares execute a single command per block and have no modal groups.
+    pub fn modal_group(&self, word: &Word) -> Option<ModalGroup> {
+        match *self {
+            Dialect::Marlin | Dialect::RepRap => None,
+            _ => ModalGroup::of(word),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::{InterpError, Machine};
+    use crate::parser::{Block, Parser, ParserError, Span};
+
+    fn parse(dialect: Dialect, line: &str) -> Result<Vec<Word>, ParserError> {
+        Parser::new().with_dialect(dialect).parse(line).map(|block| block.words().to_vec())
+    }
+
+    #[test]
+    fn test_letters() {
+        assert!(parse(Dialect::Generic, "G1 X1 E0.5").is_ok());
+        assert!(parse(Dialect::Marlin, "G1 X1 E0.5").is_ok());
+
+        match parse(Dialect::Grbl, "G1 X1 E0.5") {
+            Err(ParserError::UnsupportedLetter { letter: 'E', span }) => assert_eq!(span.column, 7),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(parse(Dialect::Marlin, "G28 ; home").unwrap(), vec![Word::new('G', 28.0)]);
+        assert!(parse(Dialect::Marlin, "G28 (home)").is_err());
+
+        assert_eq!(parse(Dialect::Fanuc, "G28 (HOME)").unwrap(), vec![Word::new('G', 28.0)]);
+        assert!(parse(Dialect::Fanuc, "G28 ; home").is_err());
+    }
+
+    #[test]
+    fn test_string_arguments() {
+        let block = Parser::new().with_dialect(Dialect::Marlin).parse("M117 Printing (50%)... ; status").unwrap();
+        assert_eq!(block.words(), &[Word::new('M', 117.0)]);
+        assert_eq!(block.argument(), Some("Printing (50%)..."));
+        assert_eq!(block.comments()[0].text(), " status");
+        assert_eq!(block.to_string(), "M117 Printing (50%)... ; status");
+
+        let block = Parser::new().with_dialect(Dialect::Marlin).parse("  M117 Hi ; status").unwrap();
+        assert_eq!(block.comment_span(0), Some(Span { line: 1, column: 11, start: 10, end: 18 }));
+
+        let block = Parser::new().with_dialect(Dialect::Marlin).parse("N3 m23 part.gco").unwrap();
+        assert_eq!(block.argument(), Some("part.gco"));
+
+        let block = Parser::new().with_dialect(Dialect::Marlin).parse("M1170 S1").unwrap();
+        assert_eq!(block.argument(), None);
+        assert_eq!(block.words(), &[Word::new('M', 1170.0), Word::new('S', 1.0)]);
+
+        assert!(parse(Dialect::Generic, "M117 Hello world!").is_err());
+    }
+
+    #[test]
+    fn test_quoted_strings() {
+        let block = Parser::new().with_dialect(Dialect::RepRap).parse("M291 P\"Say \"\"hello  world\"\"\" S2").unwrap();
+        assert_eq!(block.words(), &[Word::new('M', 291.0), Word::new('P', 0.0), Word::new('S', 2.0)]);
+        assert_eq!(block.string(1), Some("Say \"hello  world\""));
+        assert_eq!(block.string(2), None);
+        assert_eq!(block.to_string(), "M291 P\"Say \"\"hello  world\"\"\" S2");
+
+        let block = Block::new(vec![Word::new('M', 98.0), Word::new('P', 0.0)]).with_string(1, "homeall.g");
+        assert_eq!(block.line(), "M98 P\"homeall.g\"");
+
+        assert!(parse(Dialect::RepRap, "M98 P\"homeall.g").is_err());
+        assert!(parse(Dialect::Marlin, "M98 P\"homeall.g\"").is_err());
+    }
+
+    #[test]
+    fn test_system_commands() {
+        let block = Parser::new().with_dialect(Dialect::Grbl).parse(" $J=G91 X1 F100").unwrap();
+        assert_eq!(block.system_command(), Some("J=G91 X1 F100"));
+        assert!(block.words().is_empty());
+        assert!(!block.is_empty());
+        assert_eq!(block.to_string(), "$J=G91 X1 F100");
+
+        assert_eq!(Parser::new().parse("$$").unwrap().system_command(), Some("$"));
+        assert!(parse(Dialect::LinuxCnc, "$H").is_err());
+    }
+
+    #[test]
+    fn test_realtime_characters() {
+        let block = Parser::new().with_dialect(Dialect::Grbl).parse("G1 X?1 ~Y2!").unwrap();
+        assert_eq!(block.words(), &[Word::new('G', 1.0), Word::new('X', 1.0), Word::new('Y', 2.0)]);
+
+        assert!(parse(Dialect::LinuxCnc, "G1 X1 ?").is_err());
+    }
+
+    #[test]
+    fn test_m_codes() {
+        assert!(Dialect::Grbl.knows(&Word::new('M', 3.0)));
+        assert!(!Dialect::Grbl.knows(&Word::new('M', 6.0)));
+        assert!(Dialect::LinuxCnc.knows(&Word::new('M', 6.0)));
+        assert!(Dialect::LinuxCnc.knows(&Word::new('M', 101.0)));
+        assert!(Dialect::Marlin.knows(&Word::new('M', 104.0)));
+
+        let block = Parser::new().parse("M6 T1").unwrap();
+        assert!(Machine::new().execute(&block).is_ok());
+        match Machine::new().with_dialect(Dialect::Grbl).execute(&block) {
+            Err(InterpError::UnsupportedWord { mnemonic: 'M', value }) => assert_eq!(value, 6.0),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_modal_groups() {
+        let block = Parser::new().parse("G0 G1 X1").unwrap();
+        assert!(block.check_modal_groups_with(Dialect::Generic).is_err());
+        assert!(block.check_modal_groups_with(Dialect::Marlin).is_ok());
+    }
+}
@@ -0,0 +1,286 @@
+//! Writing blocks and programs back to G-code.
+//!
+//! `Word`, `Block` and `Program` implement `Display` using the default options. Other options are
+//! applied by formatting through `Options::display`.
+
+use std::fmt;
+use std::io;
+
+use crate::Real;
+use crate::expr::Expr;
+use crate::parser::{quote, Block, CommentPosition, Word};
+use crate::program::Program;
+
+/// Letters whose integral values are written without decimals, regardless of the precision.
+const CODES: &[char] = &['G', 'M', 'N', 'O', 'T'];
+
+/// Options controlling how values are written.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Options {
+    /// The number of decimals written for values.
+    pub precision: usize,
+
+    /// Whether trailing zeros of the decimals, and a trailing decimal point, are removed.
+    pub trim_zeros: bool,
+
+    /// Whether the line numbers (N words) of blocks are written.
+    pub line_numbers: bool,
+
+    /// Whether the comments of blocks are written, each at its position.
+    pub comments: bool,
+
+    /// Whether the letters of words are written in lower case, like `g1 x2`.
+    pub lowercase: bool,
+
+    /// Whether words are separated by spaces. Otherwise they are written like `G1X2Y3`, while
+    /// everything following the words is still separated by a space.
+    pub spaces: bool,
+
+    /// Whether blocks parsed in lossless mode are written exactly as they were read, as long as
+    /// they were not modified, regardless of all other options (see `Block::original`).
+    pub verbatim: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            precision: 4,
+            trim_zeros: true,
+            line_numbers: true,
+            comments: true,
+            lowercase: false,
+            spaces: true,
+            verbatim: false,
+        }
+    }
+}
+
+impl Options {
+    /// Wraps a value to be formatted with these options.
+    pub fn display<'a, T>(&'a self, value: &'a T) -> Formatted<'a, T>
+        where T: Emit + ?Sized {
+        Formatted {
+            value,
+            options: self,
+        }
+    }
+
+    /// Writes the letter of a word in the configured case.
+    pub fn letter(&self, mnemonic: char) -> char {
+        if self.lowercase {
+            mnemonic.to_ascii_lowercase()
+        } else {
+            mnemonic
+        }
+    }
+
+    /// Formats a value of a word with the given letter.
+    pub fn value(&self, mnemonic: char, value: Real) -> String {
+        if CODES.contains(&mnemonic) && value.fract() == 0.0 {
+            return format!("{}", value as i64);
+        }
+
+        let text = format!("{:.*}", self.precision, value);
+        let text = if self.trim_zeros && text.contains('.') {
+            text.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            &text
+        };
+
+        // Avoid a negative sign for values rounding to zero
+        if text.trim_start_matches('-').chars().all(|c| c == '0' || c == '.') {
+            return text.trim_start_matches('-').to_owned();
+        }
+
+        return text.to_owned();
+    }
+}
+
+/// Values which can be written as G-code.
+pub trait Emit {
+    fn emit(&self, options: &Options, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl Emit for Word {
+    fn emit(&self, options: &Options, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", options.letter(self.mnemonic()), options.value(self.mnemonic(), self.value()))
+    }
+}
+
+impl Emit for Block {
+    /// Writes the block on a single line, without a line break.
+    fn emit(&self, options: &Options, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (true, Some(original)) = (options.verbatim, self.original()) {
+            return f.write_str(original);
+        }
+
+        if let Some(system) = self.system_command() {
+            return write!(f, "${}", system);
+        }
+
+        let mut parts = Vec::new();
+
+        if self.is_deleted() {
+            parts.push("/".to_owned());
+        }
+
+        if let (true, Some(line_number)) = (options.line_numbers, self.line_number()) {
+            parts.push(format!("{}{}", options.letter('N'), options.value('N', line_number)));
+        }
+
+        let comments = |position: CommentPosition| self.comments().iter()
+                .filter(move |comment| options.comments && comment.position() == position)
+                .map(ToString::to_string);
+
+        parts.extend(comments(CommentPosition::Leading));
+        for (i, word) in self.words().iter().enumerate() {
+            if i > 0 {
+                parts.extend(comments(CommentPosition::Inline(i)));
+            }
+            match (self.expression(i), self.string(i)) {
+                (Some(expr), _) => parts.push(format!("{}{}", options.letter(word.mnemonic()), expr)),
+                (None, Some(text)) => parts.push(format!("{}{}", options.letter(word.mnemonic()), quote(text))),
+                (None, None) => parts.push(options.display(word).to_string()),
+            }
+        }
+
+        let mut rest = Vec::new();
+
+        rest.extend(self.argument().map(ToOwned::to_owned));
+
+        rest.extend(self.assignments().iter()
+                .map(|(parameter, value)| match value {
+                    Expr::Number(number) => format!("{}={}", parameter, options.value('#', *number)),
+                    expr => format!("{}={}", parameter, expr),
+                }));
+
+        rest.extend(self.control().map(ToString::to_string));
+
+        rest.extend(comments(CommentPosition::Trailing));
+
+        let mut line = parts.join(if options.spaces { " " } else { "" });
+        for part in rest {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&part);
+        }
+
+        write!(f, "{}", line)
+    }
+}
+
+impl Emit for Program {
+    /// Writes each block on a line of its own, each ending with a line break.
+    fn emit(&self, options: &Options, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for block in self {
+            writeln!(f, "{}", options.display(block))?;
+        }
+
+        return Ok(());
+    }
+}
+
+/// A value formatted with specific options.
+pub struct Formatted<'a, T: ?Sized> {
+    value: &'a T,
+    options: &'a Options,
+}
+
+impl<'a, T> fmt::Display for Formatted<'a, T>
+    where T: Emit + ?Sized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.emit(self.options, f)
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.emit(&Options::default(), f)
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.emit(&Options::default(), f)
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.emit(&Options::default(), f)
+    }
+}
+
+/// Writes the program to the writer.
+pub fn write<W>(mut writer: W, program: &Program, options: &Options) -> io::Result<()>
+    where W: io::Write {
+    return write!(writer, "{}", options.display(program));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_word() {
+        assert_eq!(Word::new('G', 1.0).to_string(), "G1");
+        assert_eq!(Word::new('G', 38.2).to_string(), "G38.2");
+        assert_eq!(Word::new('X', -1.25).to_string(), "X-1.25");
+        assert_eq!(Word::new('X', -0.00001).to_string(), "X0");
+
+        let options = Options { precision: 3, trim_zeros: false, ..Options::default() };
+        assert_eq!(options.display(&Word::new('X', 2.0)).to_string(), "X2.000");
+        assert_eq!(options.display(&Word::new('M', 3.0)).to_string(), "M3");
+        assert_eq!(options.display(&Word::new('Y', 0.12345)).to_string(), "Y0.123");
+    }
+
+    #[test]
+    fn test_block() {
+        let block = Parser::new().parse("/n10 (start) g1 x1.50 (cut) y2 ; done").unwrap();
+        assert_eq!(block.to_string(), "/ N10 (start) G1 X1.5 (cut) Y2 ; done");
+
+        let options = Options { line_numbers: false, comments: false, ..Options::default() };
+        assert_eq!(options.display(&block).to_string(), "/ G1 X1.5 Y2");
+
+        let block = Parser::new().parse("G0 X#1 Y#<_Safe Y> Z[#1*-2] #1=2.50 #2 = #1").unwrap();
+        assert_eq!(block.to_string(), "G0 X#1 Y#<_safey> Z[#1 * -2] #1=2.5 #2=#1");
+
+        let options = Options { lowercase: true, spaces: false, ..Options::default() };
+        assert_eq!(options.display(&block).to_string(), "g0x#1y#<_safey>z[#1 * -2] #1=2.5 #2=#1");
+        assert_eq!(options.display(&Parser::new().parse("N5 G1 X1 (cut) Y2 ; done").unwrap()).to_string(), "n5g1x1(cut)y2 ; done");
+    }
+
+    #[test]
+    fn test_program() {
+        let program = Program::from(Parser::new().parse_all(["G0 X1", "", "(only a comment)", "M2"].iter()).unwrap());
+        assert_eq!(program.to_string(), "G0 X1\n\n(only a comment)\nM2\n");
+
+        let mut output = Vec::new();
+        write(&mut output, &program, &Options::default()).unwrap();
+        assert_eq!(output, b"G0 X1\n\n(only a comment)\nM2\n");
+    }
+
+    #[test]
+    fn test_verbatim() {
+        let text = "%\r\n  n10 g1  x1.500 ( cut )\r\n\r\n\tG0 Z5.0;retract  \r\nM2\r\n";
+        let blocks = Parser::new().with_lossless(true).into_blocks(text.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(blocks[1].original(), Some("  n10 g1  x1.500 ( cut )\r"));
+
+        let mut program = Program::from(blocks);
+        let options = Options { verbatim: true, ..Options::default() };
+        assert_eq!(options.display(&program).to_string(), text);
+
+        // Modified blocks are written from their content
+        let modified = program.blocks()[3].clone().with_line_number(Some(20.0));
+        assert_eq!(modified.original(), None);
+        program = Program::from(vec![program.blocks()[1].clone(), modified]);
+        assert_eq!(options.display(&program).to_string(), "  n10 g1  x1.500 ( cut )\r\nN20 G0 Z5 ;retract\n");
+
+        // Without lossless mode, there is nothing to write verbatim
+        let block = Parser::new().parse(" g1  x1.500").unwrap();
+        assert_eq!(block.original(), None);
+        assert_eq!(options.display(&block).to_string(), "G1 X1.5");
+    }
+}
@@ -0,0 +1,418 @@
+//! Expressions of RS274/NGC as used by LinuxCNC and Fanuc macros (`X[#1 * 2]`).
+//!
+//! Expressions are enclosed in brackets and combine numbers and parameters with binary operators
+//! and functions. Operators bind from `**` over `*`, `/` and `MOD`, and `+` and `-`, to the
+//! comparisons and finally `AND`, `OR` and `XOR`, and are evaluated from left to right. Angles of
+//! trigonometric functions are in degrees.
+//!
+//! Evaluating expressions requires the `std` feature, as the functions like `SQRT` and `SIN` are
+//! only provided by the standard library.
+
+use alloc::boxed::Box;
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::Real;
+use crate::params::Parameter;
+#[cfg(feature = "std")]
+use crate::params::{ParamError, Parameters};
+
+/// Tolerance for values used as parameter numbers and truth values.
+#[cfg(feature = "std")]
+const EPSILON: Real = 1e-6;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Operator {
+    Power,
+    Multiply,
+    Divide,
+    Modulo,
+    Add,
+    Subtract,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    And,
+    Or,
+    Xor,
+}
+
+impl Operator {
+    /// Finds the operator written as word, e.g. `MOD`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "MOD" => Some(Operator::Modulo),
+            "EQ" => Some(Operator::Equal),
+            "NE" => Some(Operator::NotEqual),
+            "GT" => Some(Operator::Greater),
+            "GE" => Some(Operator::GreaterEqual),
+            "LT" => Some(Operator::Less),
+            "LE" => Some(Operator::LessEqual),
+            "AND" => Some(Operator::And),
+            "OR" => Some(Operator::Or),
+            "XOR" => Some(Operator::Xor),
+            _ => None,
+        };
+    }
+
+    /// How strong the operator binds, higher values binding stronger.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Operator::Power => 4,
+            Operator::Multiply | Operator::Divide | Operator::Modulo => 3,
+            Operator::Add | Operator::Subtract => 2,
+            Operator::Equal | Operator::NotEqual | Operator::Greater | Operator::GreaterEqual
+            | Operator::Less | Operator::LessEqual => 1,
+            Operator::And | Operator::Or | Operator::Xor => 0,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn apply(&self, left: Real, right: Real) -> Result<Real, ParamError> {
+        let truth = |value: bool| if value { 1.0 } else { 0.0 };
+
+        return Ok(match self {
+            Operator::Power => left.powf(right),
+            Operator::Multiply => left * right,
+            Operator::Divide | Operator::Modulo if right == 0.0 => return Err(ParamError::DivisionByZero),
+            Operator::Divide => left / right,
+            Operator::Modulo => {
+                // The result takes the sign of neither operand but is always positive
+                let result = left % right;
+                if result < 0.0 { result + right.abs() } else { result }
+            }
+            Operator::Add => left + right,
+            Operator::Subtract => left - right,
+            Operator::Equal => truth((left - right).abs() < EPSILON),
+            Operator::NotEqual => truth((left - right).abs() >= EPSILON),
+            Operator::Greater => truth(left > right),
+            Operator::GreaterEqual => truth(left >= right),
+            Operator::Less => truth(left < right),
+            Operator::LessEqual => truth(left <= right),
+            Operator::And => truth(left.abs() >= EPSILON && right.abs() >= EPSILON),
+            Operator::Or => truth(left.abs() >= EPSILON || right.abs() >= EPSILON),
+            Operator::Xor => truth((left.abs() >= EPSILON) != (right.abs() >= EPSILON)),
+        });
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operator::Power => "**",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Modulo => "MOD",
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Equal => "EQ",
+            Operator::NotEqual => "NE",
+            Operator::Greater => "GT",
+            Operator::GreaterEqual => "GE",
+            Operator::Less => "LT",
+            Operator::LessEqual => "LE",
+            Operator::And => "AND",
+            Operator::Or => "OR",
+            Operator::Xor => "XOR",
+        })
+    }
+}
+
+/// Functions taking a single argument. `ATAN` takes two and is an expression of its own.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Function {
+    Abs,
+    Acos,
+    Asin,
+    Cos,
+    Exp,
+    Fix,
+    Fup,
+    Ln,
+    Round,
+    Sin,
+    Sqrt,
+    Tan,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        return match name {
+            "ABS" => Some(Function::Abs),
+            "ACOS" => Some(Function::Acos),
+            "ASIN" => Some(Function::Asin),
+            "COS" => Some(Function::Cos),
+            "EXP" => Some(Function::Exp),
+            "FIX" => Some(Function::Fix),
+            "FUP" => Some(Function::Fup),
+            "LN" => Some(Function::Ln),
+            "ROUND" => Some(Function::Round),
+            "SIN" => Some(Function::Sin),
+            "SQRT" => Some(Function::Sqrt),
+            "TAN" => Some(Function::Tan),
+            _ => None,
+        };
+    }
+
+    #[cfg(feature = "std")]
+    fn apply(&self, value: Real) -> Result<Real, ParamError> {
+        let domain = match self {
+            Function::Acos | Function::Asin => (-1.0..=1.0).contains(&value),
+            Function::Ln => value > 0.0,
+            Function::Sqrt => value >= 0.0,
+            _ => true,
+        };
+
+        if !domain {
+            return Err(ParamError::Domain { function: *self, value });
+        }
+
+        return Ok(match self {
+            Function::Abs => value.abs(),
+            Function::Acos => value.acos().to_degrees(),
+            Function::Asin => value.asin().to_degrees(),
+            Function::Cos => value.to_radians().cos(),
+            Function::Exp => value.exp(),
+            Function::Fix => value.floor(),
+            Function::Fup => value.ceil(),
+            Function::Ln => value.ln(),
+            Function::Round => value.round(),
+            Function::Sin => value.to_radians().sin(),
+            Function::Sqrt => value.sqrt(),
+            Function::Tan => value.to_radians().tan(),
+        });
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Function::Abs => "ABS",
+            Function::Acos => "ACOS",
+            Function::Asin => "ASIN",
+            Function::Cos => "COS",
+            Function::Exp => "EXP",
+            Function::Fix => "FIX",
+            Function::Fup => "FUP",
+            Function::Ln => "LN",
+            Function::Round => "ROUND",
+            Function::Sin => "SIN",
+            Function::Sqrt => "SQRT",
+            Function::Tan => "TAN",
+        })
+    }
+}
+
+/// The value of a word or a parameter assignment.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Expr {
+    Number(Real),
+    Parameter(Parameter),
+
+    /// The numbered parameter whose number is the value of the expression (`##1`, `#[#1 + 1]`).
+    Indirect(Box<Expr>),
+
+    Negate(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+    Function(Function, Box<Expr>),
+
+    /// The angle of the vector given by the second and the first expression
+    /// (`ATAN[y]/[x]`), in degrees.
+    Atan(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn binary(operator: Operator, left: Expr, right: Expr) -> Self {
+        Expr::Binary(operator, Box::new(left), Box::new(right))
+    }
+
+    pub fn function(function: Function, argument: Expr) -> Self {
+        Expr::Function(function, Box::new(argument))
+    }
+
+    /// Calculates the value of the expression, reading parameters from the given table.
+    #[cfg(feature = "std")]
+    pub fn evaluate(&self, parameters: &Parameters) -> Result<Real, ParamError> {
+        return match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Parameter(parameter) => parameters.get(parameter),
+            Expr::Indirect(number) => {
+                let number = number.evaluate(parameters)?;
+                if number < 0.0 || (number - number.round()).abs() >= EPSILON || number.round() > u32::MAX as Real {
+                    return Err(ParamError::InvalidNumber(number));
+                }
+
+                parameters.get(&Parameter::Numbered(number.round() as u32))
+            }
+            Expr::Negate(value) => Ok(-value.evaluate(parameters)?),
+            Expr::Binary(operator, left, right) => operator.apply(left.evaluate(parameters)?, right.evaluate(parameters)?),
+            Expr::Function(function, argument) => function.apply(argument.evaluate(parameters)?),
+            Expr::Atan(y, x) => Ok(y.evaluate(parameters)?.atan2(x.evaluate(parameters)?).to_degrees()),
+        };
+    }
+
+    /// Replaces all parts of the expression not referencing parameters by their values.
+    ///
+    /// Parts which can not be evaluated, e.g. a division by zero, are kept as they are.
+    #[cfg(feature = "std")]
+    pub fn fold(self) -> Self {
+        let constant = |expr: &Expr| match expr {
+            Expr::Number(value) => Some(*value),
+            _ => None,
+        };
+
+        let folded = match self {
+            Expr::Number(_) | Expr::Parameter(_) => return self,
+            Expr::Indirect(number) => Expr::Indirect(Box::new(number.fold())),
+            Expr::Negate(value) => Expr::Negate(Box::new(value.fold())),
+            Expr::Binary(operator, left, right) => Expr::binary(operator, left.fold(), right.fold()),
+            Expr::Function(function, argument) => Expr::function(function, argument.fold()),
+            Expr::Atan(y, x) => Expr::Atan(Box::new(y.fold()), Box::new(x.fold())),
+        };
+
+        let operands = match &folded {
+            Expr::Negate(value) => vec![constant(value)],
+            Expr::Binary(_, left, right) => vec![constant(left), constant(right)],
+            Expr::Function(_, argument) => vec![constant(argument)],
+            Expr::Atan(y, x) => vec![constant(y), constant(x)],
+            _ => vec![None],
+        };
+
+        if operands.iter().all(Option::is_some) {
+            if let Ok(value) = folded.evaluate(&Parameters::new()) {
+                return Expr::Number(value);
+            }
+        }
+
+        return folded;
+    }
+
+    /// Writes the expression without enclosing brackets, adding brackets to operands binding
+    /// weaker than the operator they belong to.
+    fn write(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Binary(operator, left, right) => {
+                let operand = |f: &mut fmt::Formatter<'_>, expr: &Expr, weaker: &dyn Fn(u8) -> bool| match expr {
+                    Expr::Binary(inner, ..) if weaker(inner.precedence()) => write!(f, "[{}]", Inner(expr)),
+                    _ => write!(f, "{}", Inner(expr)),
+                };
+
+                operand(f, left, &|precedence| precedence < operator.precedence())?;
+                write!(f, " {} ", operator)?;
+                operand(f, right, &|precedence| precedence <= operator.precedence())
+            }
+            Expr::Negate(value) => match **value {
+                Expr::Binary(..) => write!(f, "-[{}]", Inner(value)),
+                _ => write!(f, "-{}", Inner(value)),
+            },
+            Expr::Function(function, argument) => write!(f, "{}[{}]", function, Inner(argument)),
+            Expr::Atan(y, x) => write!(f, "ATAN[{}]/[{}]", Inner(y), Inner(x)),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+/// An expression written inside brackets.
+struct Inner<'a>(&'a Expr);
+
+impl<'a> fmt::Display for Inner<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write(f)
+    }
+}
+
+/// An expression written enclosed in brackets even if it is a plain number or parameter, like the
+/// arguments of O-words.
+pub struct Bracketed<'a>(pub &'a Expr);
+
+impl<'a> fmt::Display for Bracketed<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]", Inner(self.0))
+    }
+}
+
+/// Writes the expression as the value of a word, enclosed in brackets unless it is a plain
+/// number or parameter.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Parameter(parameter) => write!(f, "{}", parameter),
+            Expr::Indirect(number) => write!(f, "#{}", number),
+            _ => write!(f, "[{}]", Inner(self)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    /// Parses the expression given as value of an X word.
+    fn parse(expr: &str) -> Expr {
+        let block = Parser::new().parse(format!("X{}", expr)).unwrap();
+        return block.expression(0).unwrap().clone();
+    }
+
+    fn evaluate(expr: &str) -> Real {
+        let mut parameters = Parameters::new();
+        parameters.set(Parameter::Numbered(1), 3.0);
+        parameters.set(Parameter::Numbered(3), 7.0);
+        parameters.set(Parameter::named("depth"), -2.0);
+
+        return parse(expr).evaluate(&parameters).unwrap();
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(evaluate("[1 + 2 * 3]"), 7.0);
+        assert_eq!(evaluate("[[1 + 2] * 3]"), 9.0);
+        assert_eq!(evaluate("[10 - 4 - 3]"), 3.0);
+        assert_eq!(evaluate("[2 ** 3 ** 2]"), 64.0);
+        assert_eq!(evaluate("[-#1 + -2]"), -5.0);
+        assert_eq!(evaluate("[#1 * #<Depth>]"), -6.0);
+        assert_eq!(evaluate("[-7 MOD 3]"), 2.0);
+        assert_eq!(evaluate("[1 + 1 EQ 2 AND 3 GT 4]"), 0.0);
+        assert_eq!(evaluate("#[#1]"), 7.0);
+        assert_eq!(evaluate("##1"), 7.0);
+
+        assert!((evaluate("[SIN[30]]") - 0.5).abs() < 1e-12);
+        assert!((evaluate("[COS[60] + ASIN[1]]") - 90.5).abs() < 1e-12);
+        assert_eq!(evaluate("[ATAN[1]/[-1]]"), 135.0);
+        assert_eq!(evaluate("[SQRT[16] + FIX[-1.5] + FUP[1.2] + ROUND[2.5] + ABS[-1]]"), 8.0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let parameters = Parameters::new();
+
+        assert!(matches!(parse("[1 / [2 - 2]]").evaluate(&parameters), Err(ParamError::DivisionByZero)));
+        assert!(matches!(parse("[SQRT[-1]]").evaluate(&parameters), Err(ParamError::Domain { function: Function::Sqrt, .. })));
+        assert!(matches!(parse("#[1.5]").evaluate(&parameters), Err(ParamError::InvalidNumber(_))));
+        assert!(matches!(parse("[#<undefined>]").evaluate(&parameters), Err(ParamError::Undefined(_))));
+    }
+
+    #[test]
+    fn test_fold() {
+        assert_eq!(parse("[1 + 2 * 3]").fold(), Expr::Number(7.0));
+        assert_eq!(parse("[#1 + 2 * 3]").fold(), Expr::binary(Operator::Add, Expr::Parameter(Parameter::Numbered(1)), Expr::Number(6.0)));
+        assert_eq!(parse("[1 / 0]").fold(), parse("[1 / 0]"));
+    }
+
+    #[test]
+    fn test_display() {
+        for expr in &["[1 + 2 * 3]", "[[1 + 2] * 3]", "[10 - [4 - 3]]", "[-[#1 + 1] MOD 2]", "#[#1 + 1]", "##2",
+                      "[SIN[30] * ATAN[#1]/[2]]", "[1 EQ 2 OR 3 LT #<_x>]"] {
+            assert_eq!(parse(expr).to_string(), *expr);
+        }
+    }
+}
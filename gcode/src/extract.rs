@@ -0,0 +1,478 @@
+//! Extraction of parts of programs as standalone programs, e.g. to redo a few failed layers or to
+//! resume a job at some point.
+
+use std::ops::{Range, RangeInclusive};
+
+use crate::Real;
+use crate::analysis::{self, MachineProfile};
+use crate::annotate::{Annotation, Target};
+use crate::command::{Axes, Command, DistanceMode, FeedRateMode, LengthOffset, Move, WorkOffsetMode};
+use crate::geometry::{Point3, Units};
+use crate::interp::{InterpError, Machine, Motion, Spindle, State};
+use crate::parser::{Block, Word};
+use crate::printer::{self, PrinterState};
+use crate::program::{self, Program};
+
+/// The name recorded for the blocks of the preamble.
+const PASS: &str = "extract";
+
+/// A part of a program to extract.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Region {
+    /// The blocks parsed from the source lines in the range, counting from 1.
+    Lines(RangeInclusive<usize>),
+
+    /// The blocks of the layers in the range, counting from 0 (see `printer::layers`).
+    Layers(RangeInclusive<usize>),
+
+    /// The blocks with the indices in the range, counting from 0.
+    Blocks(RangeInclusive<usize>),
+
+    /// The blocks executing between the two points in time, in seconds from the start of the
+    /// program, as estimated for the machine (see `analysis::estimate`).
+    Time { from: Real, to: Real, profile: Box<MachineProfile> },
+}
+
+/// Extracts the region of the program as a program of its own, starting with a preamble which
+/// puts the machine into the state the program left it in before the region.
+///
+/// The preamble restates the units and distance mode, and everything else differing from the state
+/// of the machine at the start: the plane, feed rate mode, lathe mode and coordinate system, the
+/// origins of coordinate systems, the tool and its length offset, the spindle, coolant, the
+/// temperatures, fan and extruder of printers, the motion mode and the feed rate. Positioning
+/// moves rise to the highest Z the program reached before the region, move to the position the
+/// region starts at, and feed down to it, before the G92 offset is set again. Arc motion modes, which
+/// cannot be selected without a move, cutter compensation, the positions stored for G28 and G30
+/// and probing results are not restated.
+///
+/// The program end following the region, if any, is appended to it. Annotations of blocks in the
+/// region are kept. Regions selecting no blocks give an empty program.
+pub fn extract(program: &Program, region: &Region) -> Result<Program, InterpError> {
+    extract_with(Machine::new(), program, region)
+}
+
+/// Extracts the region of the program like `extract`, but starting with the given machine.
+pub fn extract_with(machine: Machine, program: &Program, region: &Region) -> Result<Program, InterpError> {
+    let blocks = select(&machine, program, region)?;
+    if blocks.is_empty() {
+        return Ok(Program::new());
+    }
+
+    let mut extracted = if blocks.start > 0 {
+        preamble(machine, &program.blocks()[..blocks.start])?
+    } else {
+        Vec::new()
+    };
+
+    let offset = extracted.len();
+    extracted.extend(program.blocks()[blocks.clone()].iter().cloned());
+
+    let ends = |block: &Block| block.words().iter().any(program::is_program_end);
+    if !program.blocks()[blocks.clone()].iter().any(ends) {
+        let end = program.blocks()[blocks.end..].iter()
+                .flat_map(|block| block.words())
+                .find(|word| program::is_program_end(word));
+        extracted.extend(end.map(|&word| Block::new(vec![word]).with_pass(PASS)));
+    }
+
+    let annotations = program.annotations().iter()
+            .filter_map(|annotation| match annotation.target {
+                Target::Block(block) if blocks.contains(&block) => Some(Annotation {
+                    target: Target::Block(block - blocks.start + offset),
+                    ..annotation.clone()
+                }),
+                _ => None,
+            })
+            .collect();
+
+    return Ok(Program::from(extracted).with_annotations(annotations));
+}
+
+/// The indices of the blocks in the region.
+fn select(machine: &Machine, program: &Program, region: &Region) -> Result<Range<usize>, InterpError> {
+    let blocks = program.blocks();
+
+    match *region {
+        Region::Lines(ref lines) => {
+            let inside = |block: &Block| block.provenance().line().is_some_and(|line| lines.contains(&line));
+            return Ok(match (blocks.iter().position(inside), blocks.iter().rposition(inside)) {
+                (Some(first), Some(last)) => first..last + 1,
+                _ => 0..0,
+            });
+        }
+
+        Region::Layers(ref layers) => {
+            let all = printer::layers_with(machine.clone(), program)?;
+            let last = (*layers.end()).min(all.len().saturating_sub(1));
+            return Ok(match all.get(*layers.start()) {
+                Some(first) if *layers.start() <= last => first.blocks.start..all[last].blocks.end,
+                _ => 0..0,
+            });
+        }
+
+        Region::Blocks(ref range) => {
+            let end = range.end().saturating_add(1).min(blocks.len());
+            return Ok((*range.start()).min(end)..end);
+        }
+
+        Region::Time { from, to, ref profile } => {
+            let estimate = analysis::estimate_with(machine.clone(), program, profile)?;
+
+            // Blocks still executing at `from` belong to the region
+            let mut start = 0.0;
+            let mut first = None;
+            let mut last = 0;
+            for (i, &duration) in estimate.blocks.iter().enumerate() {
+                if start >= to {
+                    break;
+                }
+                if first.is_none() && start + duration > from {
+                    first = Some(i);
+                }
+                last = i + 1;
+                start += duration;
+            }
+
+            return Ok(match first {
+                Some(first) => first..last,
+                None => 0..0,
+            });
+        }
+    }
+}
+
+/// The blocks of a preamble, executed as they are added to check them and to follow the state of
+/// the machine they are written for.
+struct Preamble {
+    blocks: Vec<Block>,
+    machine: Machine,
+    printer: PrinterState,
+}
+
+impl Preamble {
+    fn push(&mut self, words: Vec<Word>) -> Result<(), InterpError> {
+        if words.is_empty() {
+            return Ok(());
+        }
+
+        let block = Block::new(words).with_pass(PASS);
+        let (_, reduced) = self.printer.apply(&block);
+        self.machine.execute(&reduced)?;
+        self.blocks.push(block);
+        return Ok(());
+    }
+
+    fn command(&mut self, commands: &[Command]) -> Result<(), InterpError> {
+        self.push(Command::to_block(commands).words().to_vec())
+    }
+
+    fn state(&self) -> &State {
+        self.machine.state()
+    }
+}
+
+fn round(value: Real) -> Real {
+    (value * 1e4).round() / 1e4
+}
+
+/// Runs the blocks before the region and writes a preamble restating the state they leave the
+/// machine in.
+fn preamble(machine: Machine, blocks: &[Block]) -> Result<Vec<Block>, InterpError> {
+    let mut preamble = Preamble {
+        blocks: Vec::new(),
+        machine: machine.clone(),
+        printer: PrinterState::new(),
+    };
+
+    let mut machine = machine;
+    let mut printer = PrinterState::new();
+    let mut clearance = machine.state().position.z;
+    for block in blocks {
+        let (_, reduced) = printer.apply(block);
+        machine.execute(&reduced)?;
+        clearance = clearance.max(machine.state().position.z);
+    }
+
+    let target = machine.state();
+    let start = preamble.state().clone();
+    let scale = target.units.millimeters();
+
+    // Positions are restated in absolute coordinates, relative ones are selected after moving
+    let mut modes = vec![Command::SetUnits(target.units), Command::SetDistanceMode(DistanceMode::Absolute)];
+    if target.plane != start.plane {
+        modes.push(Command::SelectPlane(target.plane));
+    }
+    if target.feed_rate_mode != start.feed_rate_mode {
+        modes.push(Command::SetFeedRateMode(target.feed_rate_mode));
+    }
+    if target.lathe_mode != start.lathe_mode {
+        modes.push(Command::SetLatheMode(target.lathe_mode));
+    }
+    if target.coordinate_system != start.coordinate_system {
+        modes.push(Command::SelectCoordinateSystem(target.coordinate_system));
+    }
+    preamble.command(&modes)?;
+
+    for (i, (origin, current)) in target.work_offsets.iter().zip(start.work_offsets.iter()).enumerate() {
+        if origin != current {
+            preamble.command(&[Command::SetWorkOffset {
+                system: i as u8 + 1,
+                mode: WorkOffsetMode::Origin,
+                axes: Axes { x: Some(round(origin.x / scale)), y: Some(round(origin.y / scale)), z: Some(round(origin.z / scale)) },
+            }])?;
+        }
+    }
+
+    if target.tool != start.tool && target.tool.is_some() {
+        preamble.command(&[Command::ToolChange { tool: target.tool }])?;
+    }
+    if let Some(tool) = target.selected_tool.filter(|_| target.selected_tool != target.tool && target.selected_tool != start.selected_tool) {
+        preamble.command(&[Command::SelectTool(tool)])?;
+    }
+
+    if target.tool_length_offset != start.tool_length_offset {
+        // The offset of the tool table is preferred to the length it gives
+        let mut table = preamble.machine.clone();
+        let offset = if target.tool_length_offset == 0.0 {
+            Command::CancelToolLengthOffset
+        } else if target.tool.is_some() && table.execute(&Command::to_block(&[Command::ToolLengthOffset(LengthOffset::Tool(target.tool))])).is_ok()
+                && table.state().tool_length_offset == target.tool_length_offset {
+            Command::ToolLengthOffset(LengthOffset::Tool(target.tool))
+        } else {
+            Command::ToolLengthOffset(LengthOffset::Given(round(target.tool_length_offset / scale)))
+        };
+        preamble.command(&[offset])?;
+    }
+
+    // Printers heat up before moving
+    let heat = [(190.0, printer.bed), (109.0, printer.hotend)];
+    for &(code, temperature) in heat.iter() {
+        if let Some(temperature) = temperature {
+            preamble.push(vec![Word::new('M', code), Word::new('S', temperature)])?;
+        }
+    }
+
+    let mut spindle = Vec::new();
+    if target.surface_speed.is_some() && target.surface_speed != start.surface_speed {
+        let meters = match target.units {
+            Units::Millimeters => 1.0,
+            Units::Inches => 0.3048,
+        };
+        spindle.push(Command::ConstantSurfaceSpeed {
+            speed: target.surface_speed.map(|speed| round(speed / meters)),
+            limit: target.spindle_speed_limit,
+        });
+    }
+    let speed = target.spindle_speed.filter(|_| target.spindle_speed != start.spindle_speed && target.surface_speed.is_none());
+    match target.spindle {
+        Spindle::Off if speed.is_some() => spindle.extend(speed.map(Command::SetSpindleSpeed)),
+        Spindle::Off => {}
+        Spindle::Clockwise => spindle.push(Command::SpindleOn { clockwise: true, speed }),
+        Spindle::CounterClockwise => spindle.push(Command::SpindleOn { clockwise: false, speed }),
+    }
+    preamble.command(&spindle)?;
+
+    // Mist and flood coolant share a modal group, so each gets a block of its own
+    for &(mist, on) in [(true, target.mist), (false, target.flood)].iter() {
+        if on {
+            preamble.command(&[Command::CoolantOn { mist }])?;
+        }
+    }
+
+    if printer.fan > 0.0 {
+        preamble.push(vec![Word::new('M', 106.0), Word::new('S', (printer.fan * 255.0).round())])?;
+    }
+
+    position(&mut preamble, target, clearance)?;
+
+    if target.position_offset != preamble.state().position_offset || target.position_offset_enabled != preamble.state().position_offset_enabled {
+        let work = target.work_offsets[usize::from(target.coordinate_system) - 1];
+        let (position, offset) = (target.position, target.position_offset);
+        let value = |position: Real, work: Real, offset: Real| Some(round((position - work - offset) / scale));
+        preamble.command(&[Command::SetPosition(Axes {
+            x: value(position.x, work.x, offset.x),
+            y: value(position.y, work.y, offset.y),
+            z: value(position.z, work.z + target.tool_length_offset, offset.z),
+        })])?;
+
+        if !target.position_offset_enabled {
+            preamble.command(&[Command::SuspendPositionOffset])?;
+        }
+    }
+
+    if printer.relative_extrusion {
+        preamble.push(vec![Word::new('M', 83.0)])?;
+    } else if printer.extruder != 0.0 {
+        preamble.push(vec![Word::new('G', 92.0), Word::new('E', (printer.extruder * 1e5).round() / 1e5)])?;
+    }
+
+    let mut motion = Vec::new();
+    if target.distance_mode == DistanceMode::Relative {
+        motion.push(Command::SetDistanceMode(DistanceMode::Relative));
+    }
+    if target.motion != preamble.state().motion {
+        motion.extend(match target.motion {
+            Some(Motion::Rapid) => Some(Command::RapidMove(Move::default())),
+            Some(Motion::Linear) => Some(Command::LinearMove(Move::default())),
+            Some(Motion::ClockwiseArc) | Some(Motion::CounterClockwiseArc) | None => None,
+        });
+    }
+    if target.feed_rate != preamble.state().feed_rate && target.feed_rate_mode != FeedRateMode::InverseTime {
+        motion.extend(target.feed_rate.map(|feed| Command::SetFeedRate(round(feed / scale))));
+    }
+    preamble.command(&motion)?;
+
+    return Ok(preamble.blocks);
+}
+
+/// Moves to the position of the target state, passing the clearance height in machine coordinates.
+///
+/// The tool rises to the highest of the clearance height, the current and the target height before
+/// moving in XY, so it never moves sideways below any of them. Rapid moves end there, and the tool
+/// feeds down to the target height at the feed rate of the target state. Without a feed rate in
+/// units per minute or revolution, the feed move gives none, so controllers reject it instead of
+/// plunging.
+fn position(preamble: &mut Preamble, target: &State, clearance: Real) -> Result<(), InterpError> {
+    let current = preamble.state().clone();
+    if target.position == current.position && target.axes == current.axes {
+        return Ok(());
+    }
+
+    let rapid = |x: Option<Real>, y: Option<Real>, z: Option<Real>, axes| Command::RapidMove(Move { x, y, z, axes, f: None });
+
+    let work = current.to_work(target.position);
+    let scale = current.units.millimeters();
+    let axes = target.axes.map(|axis, &value| Some(value)
+            .filter(|&value| value != current.axes[axis])
+            .map(|value| round(if axis.is_rotary() { value } else { value / scale })));
+
+    let height = |z: Real| round(current.to_work(Point3::new(current.position.x, current.position.y, z)).z);
+
+    let top = clearance.max(current.position.z).max(target.position.z);
+    if top > current.position.z {
+        preamble.command(&[rapid(None, None, Some(height(top)), Default::default())])?;
+    }
+
+    preamble.command(&[rapid(Some(round(work.x)), Some(round(work.y)), None, axes)])?;
+
+    if target.position.z < top {
+        let f = target.feed_rate
+                .filter(|_| target.feed_rate_mode != FeedRateMode::InverseTime)
+                .map(|feed| round(feed / scale));
+        preamble.command(&[Command::LinearMove(Move { z: Some(round(work.z)), f, ..Move::default() })])?;
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::parser::Parser;
+
+    fn program(text: &str) -> Program {
+        Program::from(Parser::new().parse_all(text.lines()).unwrap())
+    }
+
+    fn extracted(text: &str, region: Region) -> String {
+        extract(&program(text), &region).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_extract_lines() {
+        let text = "G20 G90\nG10 L2 P2 X1\nG55 M6 T2\nM3 S1000 M8\nG0 Z0.5\nG0 X1 Y1\nG1 Z-0.1 F10\nG91 G1 X1\nG1 Y1\nM30";
+        assert_eq!(extracted(text, Region::Lines(8..=9)), concat!(
+            "G20 G90 G55\n",
+            "G10 L2 P2 X1 Y0 Z0\n",
+            "M6 T2\n",
+            "M3 S1000\n",
+            "M8\n",
+            "G0 Z0.5\n",
+            "G0 X1 Y1\n",
+            "G1 Z-0.1 F10\n",
+            "G91 G1 X1\n",
+            "G1 Y1\n",
+            "M30\n",
+        ));
+
+        // Regions from the start need no preamble, and those beyond the end are empty
+        assert_eq!(extracted(text, Region::Lines(1..=2)), "G20 G90\nG10 L2 P2 X1\nM30\n");
+        assert_eq!(extracted(text, Region::Lines(20..=30)), "");
+
+        // The tool rises to a target above it before moving sideways, and feeds down to one below
+        let text = "G21 G90\nG0 Z3\nG0 X5 Y5\nG1 X6 F100\nG1 Z-1\nG1 X7\nM2";
+        assert_eq!(extracted(text, Region::Lines(4..=4)), "G21 G90\nG0 Z3\nG0 X5 Y5\nG1 X6 F100\nM2\n");
+        assert_eq!(extracted(text, Region::Lines(6..=6)), "G21 G90\nG0 Z3\nG0 X6 Y5\nG1 Z-1 F100\nG1 X7\nM2\n");
+
+        // Blocks are counted from 0 and regions may extend beyond the end
+        assert_eq!(extracted(text, Region::Blocks(7..=8)), extracted(text, Region::Lines(8..=9)));
+        assert_eq!(extracted(text, Region::Blocks(7..=usize::MAX)), extracted(text, Region::Lines(8..=10)));
+        assert_eq!(extracted(text, Region::Blocks(20..=usize::MAX)), "");
+    }
+
+    #[test]
+    fn test_extract_state() {
+        // The preamble leaves the machine in the state the region started in
+        let text = "G21 G18 G7\nM4 S500\nG0 X10 Z5\nG92 X0 Z0\nG1 X-2 F0.2\nG95\nG1 Z-3 F0.1\nG2 X-2 Z-5 I0 K-1\nM5";
+        let original = program(text);
+
+        for line in 2..=8 {
+            let region = extract(&original, &Region::Lines(line..=9)).unwrap();
+            let split = region.len() - (10 - line);
+
+            let mut expected = Machine::new();
+            for block in &original.blocks()[..line - 1] {
+                expected.execute(block).unwrap();
+            }
+            let mut actual = Machine::new();
+            for block in &region.blocks()[..split] {
+                actual.execute(block).unwrap();
+            }
+
+            assert_eq!(actual.state(), expected.state(), "region from line {}:\n{}", line, region);
+        }
+    }
+
+    #[test]
+    fn test_extract_layers() {
+        let text = concat!(
+            "M140 S60\nM104 S200\nG28\nG92 E0\n",
+            ";LAYER:0\nG0 Z0.2 F3000\nG1 X10 E1 F1200\nM106 S128\n",
+            ";LAYER:1\nG0 Z0.4\nG1 X0 E2\n",
+            ";LAYER:2\nG0 Z0.6\nG1 X10 E3\n",
+            "M107\nM84\n",
+        );
+        let mut original = program(text);
+        original.annotate(Annotation::new(Target::Block(9), "layer"));
+
+        let machine = Machine::new().with_dialect(Dialect::Marlin);
+        let region = extract_with(machine, &original, &Region::Layers(1..=1)).unwrap();
+        assert_eq!(region.to_string(), concat!(
+            "G21 G90\n",
+            "M190 S60\n",
+            "M109 S200\n",
+            "M106 S128\n",
+            "G0 Z0.2\n",
+            "G0 X10 Y0\n",
+            "G92 E1\n",
+            "G1 F1200\n",
+            ";LAYER:1\n",
+            "G0 Z0.4\n",
+            "G1 X0 E2\n",
+        ));
+        assert_eq!(region.annotations(), &[Annotation::new(Target::Block(9), "layer")]);
+    }
+
+    #[test]
+    fn test_extract_time() {
+        let text = "G0 X0 Y0\nG1 X100 F6000\nG1 Y100\nG1 X0\nG1 Y0\nM2";
+        let profile = MachineProfile::default();
+        let times = analysis::estimate(&program(text), &profile).unwrap();
+        let middle = times.blocks[0] + times.blocks[1] + times.blocks[2] / 2.0;
+
+        // Blocks executing at the start of the region belong to it
+        let region = Region::Time { from: middle, to: middle + times.blocks[3], profile: Box::new(profile) };
+        assert_eq!(extracted(text, region), "G21 G90\nG0 X100 Y0\nG1 F6000\nG1 Y100\nG1 X0\nM2\n");
+    }
+}
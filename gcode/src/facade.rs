@@ -0,0 +1,225 @@
+//! High-level entry points for simple use cases, wiring parser, interpreter and analysis together
+//! with default settings:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), gcode::Error> {
+//! let analysis = gcode::open("part.nc")?.analyze()?;
+//! println!("{:.0} seconds", analysis.estimate.total);
+//! std::fs::write("part.svg", analysis.preview_svg())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Use the underlying modules directly for anything beyond the defaults, e.g. a machine with work
+//! offsets or a lenient parser.
+
+use std::error;
+use std::fmt::{self, Write};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::Real;
+use crate::analysis::{self, Bounds, Estimate, MachineProfile};
+use crate::geometry::Point3;
+use crate::interp::{InterpError, Machine};
+use crate::parser::{Parser, ParserError};
+use crate::program::Program;
+use crate::toolpath::Segment;
+
+/// The chord tolerance used to draw arcs, in millimeters.
+const PREVIEW_TOLERANCE: Real = 0.01;
+
+/// Any error raised by the entry points of this module.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+
+    Parser(ParserError),
+
+    Interp(InterpError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Parser(err) => write!(f, "{}", err),
+            Error::Interp(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Parser(err) => Some(err),
+            Error::Interp(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParserError> for Error {
+    fn from(err: ParserError) -> Self {
+        Error::Parser(err)
+    }
+}
+
+impl From<InterpError> for Error {
+    fn from(err: InterpError) -> Self {
+        Error::Interp(err)
+    }
+}
+
+/// Reads and parses the program at the given path, failing on the first malformed line.
+pub fn open<P>(path: P) -> Result<Program, Error>
+    where P: AsRef<Path> {
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path)?);
+
+    return Ok(Parser::new()
+            .with_source(path.display().to_string())
+            .into_blocks(reader)
+            .collect::<Result<Program, _>>()?);
+}
+
+/// The results of analyzing a program with `Program::analyze`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    /// The extents of all motion, or `None` if the program does not move.
+    pub bounds: Option<Bounds>,
+
+    pub estimate: Estimate,
+
+    /// All motion of the program in machine coordinates and millimeters.
+    pub segments: Vec<Segment>,
+}
+
+impl Analysis {
+    /// Draws the motion of the program as seen from above, with rapids dashed, as SVG document.
+    ///
+    /// The drawing uses millimeters as units, with the Y axis pointing up.
+    pub fn preview_svg(&self) -> String {
+        let (min, max) = match self.bounds {
+            Some(bounds) => (bounds.min, bounds.max),
+            None => (Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, 0.0)),
+        };
+
+        // Keep degenerated drawings visible
+        let margin = ((max.x - min.x).max(max.y - min.y) * 0.02).max(1.0);
+        let stroke = margin / 4.0;
+
+        let mut cuts = String::new();
+        let mut rapids = String::new();
+        let mut position = None;
+
+        for segment in &self.segments {
+            let (path, points) = match *segment {
+                Segment::Rapid { from, to, .. } => (&mut rapids, vec![from, to]),
+                Segment::Line { from, to, .. } => (&mut cuts, vec![from, to]),
+                Segment::Arc { ref arc, .. } => {
+                    let mut points = vec![arc.start()];
+                    points.extend(arc.flatten(PREVIEW_TOLERANCE));
+                    (&mut cuts, points)
+                }
+                Segment::Dwell { .. } => continue,
+            };
+
+            // Only start a new subpath where the previous one of the same kind did not end
+            let rapid = matches!(segment, Segment::Rapid { .. });
+            let continues = position == Some((rapid, points[0]));
+            for (i, point) in points.iter().enumerate() {
+                let command = if i == 0 && !continues { 'M' } else if i == 0 { continue } else { 'L' };
+                write!(path, "{}{} {}", command, number(point.x), number(-point.y)).expect("Write to string");
+            }
+
+            position = Some((rapid, points[points.len() - 1]));
+        }
+
+        let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+                              number(min.x - margin), number(-max.y - margin),
+                              number(max.x - min.x + 2.0 * margin), number(max.y - min.y + 2.0 * margin));
+        if !rapids.is_empty() {
+            svg.push_str(&format!("<path d=\"{}\" fill=\"none\" stroke=\"gray\" stroke-width=\"{}\" stroke-dasharray=\"{}\"/>\n",
+                                  rapids, number(stroke), number(stroke * 4.0)));
+        }
+        if !cuts.is_empty() {
+            svg.push_str(&format!("<path d=\"{}\" fill=\"none\" stroke=\"black\" stroke-width=\"{}\"/>\n",
+                                  cuts, number(stroke)));
+        }
+        svg.push_str("</svg>\n");
+
+        return svg;
+    }
+}
+
+/// Formats a coordinate for SVG, with a precision of a micrometer.
+fn number(value: Real) -> Real {
+    let value = (value * 1000.0).round() / 1000.0;
+    return if value == 0.0 { 0.0 } else { value };
+}
+
+impl Program {
+    /// Analyzes the program, starting with a machine in its default state and using the default
+    /// machine profile for the estimate.
+    pub fn analyze(&self) -> Result<Analysis, InterpError> {
+        self.analyze_with(Machine::new(), &MachineProfile::default())
+    }
+
+    /// Analyzes the program like `analyze`, but starting with the given machine and profile.
+    pub fn analyze_with(&self, machine: Machine, profile: &MachineProfile) -> Result<Analysis, InterpError> {
+        return Ok(Analysis {
+            bounds: analysis::bounds_with(machine.clone(), self)?,
+            estimate: analysis::estimate_with(machine.clone(), self, profile)?,
+            segments: machine.into_segments(self.iter().cloned()).collect::<Result<_, _>>()?,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        let path = std::env::temp_dir().join(format!("gcode-facade-{}.nc", std::process::id()));
+        std::fs::write(&path, "G0 X10 Y5\nG1 X0 F600\nG2 X0 Y5 I0 J-2.5\n").unwrap();
+
+        let program = open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let analysis = program.analyze().unwrap();
+        assert_eq!(analysis.segments.len(), 3);
+        assert_eq!(analysis.bounds.unwrap().max, Point3::new(10.0, 5.0, 0.0));
+        assert!(analysis.estimate.total > 1.0);
+
+        let svg = analysis.preview_svg();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"-3.5 -6 14.5 7\">\n"));
+        assert!(svg.contains("<path d=\"M0 0L10 -5\" fill=\"none\" stroke=\"gray\""));
+        assert!(svg.contains("<path d=\"M10 -5L0 -5L"));
+        assert!(svg.ends_with("L0 -5\" fill=\"none\" stroke=\"black\" stroke-width=\"0.25\"/>\n</svg>\n"));
+
+        assert!(open("/nonexistent/part.nc").is_err());
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::error::Error as _;
+        use crate::toolpath::ArcError;
+
+        let err = Error::from(InterpError::from(ArcError::ZeroRadius));
+        assert_eq!(err.to_string(), "arc with zero radius");
+        assert_eq!(err.source().unwrap().to_string(), "arc with zero radius");
+        assert!(err.source().unwrap().source().unwrap().source().is_none());
+
+        let err = open("/nonexistent/part.nc").unwrap_err();
+        assert!(err.source().unwrap().is::<io::Error>());
+    }
+}
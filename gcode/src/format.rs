@@ -0,0 +1,134 @@
+//! Formatting of programs into a canonical form.
+//!
+//! Formatting normalizes how blocks are written - whitespace, the case of letters, numbers and the
+//! order of words - without changing their meaning. Programs which only differ in their style are
+//! formatted to the same text, and formatting a formatted program does not change it, which keeps
+//! diffs of programs under version control meaningful.
+
+use crate::emit::Options;
+use crate::parser::{Block, Parser, ParserError};
+use crate::program::Program;
+use crate::transform::{self, CanonicalOrder};
+
+/// The style programs are formatted in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Style {
+    /// How blocks and values are written.
+    pub options: Options,
+
+    /// Whether the words of blocks are reordered like `CanonicalOrder` does.
+    pub reorder: bool,
+
+    /// The number of consecutive blank lines kept, or `None` to keep all. Blank lines at the start
+    /// and the end of the program are removed unless all are kept.
+    pub blank_lines: Option<usize>,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            options: Options::default(),
+            reorder: true,
+            blank_lines: Some(1),
+        }
+    }
+}
+
+impl Style {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_reorder(mut self, reorder: bool) -> Self {
+        self.reorder = reorder;
+        self
+    }
+
+    pub fn with_blank_lines(mut self, blank_lines: Option<usize>) -> Self {
+        self.blank_lines = blank_lines;
+        self
+    }
+}
+
+/// Whether the block is written as an empty line.
+fn is_blank(block: &Block) -> bool {
+    block.is_empty() && block.comments().is_empty() && block.line_number().is_none() && !block.is_deleted()
+}
+
+/// Formats the program in the given style, writing each block on a line of its own.
+pub fn format(program: &Program, style: &Style) -> String {
+    let program = if style.reorder {
+        transform::apply(&mut CanonicalOrder::new(), program.iter().cloned())
+    } else {
+        program.clone()
+    };
+
+    let mut lines = Vec::with_capacity(program.len());
+    let mut blanks = 0;
+
+    for block in &program {
+        if is_blank(block) {
+            blanks += 1;
+            if style.blank_lines.is_some_and(|limit| blanks > limit || lines.is_empty()) {
+                continue;
+            }
+        } else {
+            blanks = 0;
+        }
+
+        lines.push(style.options.display(block).to_string());
+    }
+
+    if style.blank_lines.is_some() {
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+    }
+
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+
+    return text;
+}
+
+/// Parses the text with the default parser and formats it in the given style.
+pub fn format_str(text: &str, style: &Style) -> Result<String, ParserError> {
+    let program = Program::from(Parser::new().parse_all(text.lines())?);
+    return Ok(format(&program, style));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let text = "\n\nn10 g1   y2.50 x1.000 f100\n\n\n\n(cut)  g2 x0 y0 i-1 ; done\nM30\n\n";
+        let formatted = format_str(text, &Style::new()).unwrap();
+        assert_eq!(formatted, "N10 G1 X1 Y2.5 F100\n\n(cut) G2 X0 Y0 I-1 ; done\nM30\n");
+
+        // Formatting is idempotent
+        assert_eq!(format_str(&formatted, &Style::new()).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_style() {
+        let style = Style::new()
+                .with_options(Options { lowercase: true, spaces: false, precision: 3, trim_zeros: false, ..Options::default() })
+                .with_reorder(false)
+                .with_blank_lines(None);
+
+        assert_eq!(format_str("Y2 X1 G0\n\n\nG1 Z-1.5 F200\n", &style).unwrap(), "y2.000x1.000g0\n\n\ng1z-1.500f200.000\n");
+        assert_eq!(format_str("", &style).unwrap(), "");
+
+        let style = Style::new().with_blank_lines(Some(0));
+        assert_eq!(format_str("G0 X1\n\n()\nG0 X2\n", &style).unwrap(), "G0 X1\n()\nG0 X2\n");
+    }
+}
@@ -0,0 +1,123 @@
+//! Generators producing complete programs from geometry.
+
+use crate::{Real, EPSILON};
+use crate::geometry::{Point, Units};
+use crate::parser::{Block, Word};
+use crate::program::Program;
+
+pub use self::depth::{DepthStepping, Tab};
+pub use self::direction::Direction;
+pub use self::drill::{Drilling, ToolMap};
+pub use self::facing::Facing;
+pub use self::isolation::Isolation;
+pub use self::pocket::Pocket;
+
+mod depth;
+mod direction;
+mod drill;
+mod facing;
+mod isolation;
+mod pocket;
+
+/// The G code selecting the given units.
+fn units(units: Units) -> Real {
+    match units {
+        Units::Inches => 20.0,
+        Units::Millimeters => 21.0,
+    }
+}
+
+/// Splits `total` into increasing steps of at most `step`, the last one ending exactly at `total`.
+fn steps(total: Real, step: Real) -> Vec<Real> {
+    let mut steps = Vec::new();
+
+    let mut current = 0.0;
+    while total - current > EPSILON {
+        current = (current + step).min(total);
+        steps.push(current);
+    }
+
+    return steps;
+}
+
+/// Collects blocks of a generated program while tracking the tool position and feed rate.
+struct Emitter {
+    program: Program,
+    position: Option<Point>,
+    z: Option<Real>,
+    feed: Option<Real>,
+}
+
+impl Emitter {
+    fn new() -> Self {
+        Self {
+            program: Program::new(),
+            position: None,
+            z: None,
+            feed: None,
+        }
+    }
+
+    fn block(&mut self, words: Vec<Word>) {
+        self.program.push(Block::new(words));
+    }
+
+    fn moves_z(&mut self, z: Real) -> bool {
+        return match self.z {
+            Some(current) if (current - z).abs() < EPSILON => false,
+            _ => {
+                self.z = Some(z);
+                true
+            }
+        };
+    }
+
+    fn feed(&mut self, words: &mut Vec<Word>, feed: Real) {
+        if self.feed != Some(feed) {
+            self.feed = Some(feed);
+            words.push(Word::new('F', feed));
+        }
+    }
+
+    fn retract(&mut self, z: Real) {
+        if self.moves_z(z) {
+            self.block(vec![Word::new('G', 0.0), Word::new('Z', z)]);
+        }
+    }
+
+    fn moves_to(&mut self, to: Point) -> bool {
+        return match self.position {
+            Some(current) if current.distance(to) < EPSILON => false,
+            _ => {
+                self.position = Some(to);
+                true
+            }
+        };
+    }
+
+    fn rapid(&mut self, to: Point) {
+        if !self.moves_to(to) {
+            return;
+        }
+
+        self.block(vec![Word::new('G', 0.0), Word::new('X', to.x), Word::new('Y', to.y)]);
+    }
+
+    fn plunge(&mut self, z: Real, feed: Real) {
+        if self.moves_z(z) {
+            let mut words = vec![Word::new('G', 1.0), Word::new('Z', z)];
+            self.feed(&mut words, feed);
+            self.block(words);
+        }
+    }
+
+    fn cut(&mut self, to: Point, feed: Real) {
+        if !self.moves_to(to) {
+            return;
+        }
+
+        let mut words = vec![Word::new('G', 1.0), Word::new('X', to.x), Word::new('Y', to.y)];
+        self.feed(&mut words, feed);
+        self.block(words);
+    }
+}
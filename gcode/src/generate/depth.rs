@@ -0,0 +1,327 @@
+use crate::Real;
+use crate::geometry::Contour;
+use crate::parser::Word;
+use crate::program::Program;
+
+use super::{steps, Direction, Emitter, EPSILON};
+
+/// A holding tab left standing while cutting along a contour.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tab {
+    /// Distance along the contour where the tab starts.
+    pub position: Real,
+
+    /// Length of the tab along the contour.
+    pub length: Real,
+}
+
+impl Tab {
+    pub fn new(position: Real, length: Real) -> Self {
+        Self { position, length }
+    }
+
+    fn contains(&self, distance: Real) -> bool {
+        distance > self.position && distance < self.position + self.length
+    }
+}
+
+/// Cuts contours in multiple passes of increasing depth.
+///
+/// Depths are measured downwards from the stock surface. Each pass goes at most `stepdown` deeper
+/// than the previous one. If a finishing allowance is configured, the roughing passes stop short
+/// of the final depth by this amount and a single finishing pass cuts the rest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthStepping {
+    depth: Real,
+    stepdown: Real,
+    feed: Real,
+    plunge_feed: Real,
+    surface: Real,
+    safe_z: Real,
+    finishing: Option<Real>,
+    tabs: Vec<Tab>,
+    tab_height: Real,
+    direction: Option<Direction>,
+    spindle: Option<Real>,
+}
+
+impl DepthStepping {
+    /// Creates a new generator cutting to the total `depth` in steps of `stepdown`.
+    ///
+    /// Panics if `stepdown` is not positive.
+    pub fn new(depth: Real, stepdown: Real, feed: Real) -> Self {
+        assert!(stepdown > 0.0, "stepdown must be positive");
+
+        Self {
+            depth,
+            stepdown,
+            feed,
+            plunge_feed: feed,
+            surface: 0.0,
+            safe_z: 5.0,
+            finishing: None,
+            tabs: Vec::new(),
+            tab_height: 0.0,
+            direction: None,
+            spindle: None,
+        }
+    }
+
+    pub fn plunge_feed(mut self, feed: Real) -> Self {
+        self.plunge_feed = feed;
+        self
+    }
+
+    /// Sets the Z height of the stock surface where the first pass starts.
+    pub fn surface(mut self, z: Real) -> Self {
+        self.surface = z;
+        self
+    }
+
+    /// Sets the Z height used for retracts and rapid moves.
+    pub fn safe_z(mut self, z: Real) -> Self {
+        self.safe_z = z;
+        self
+    }
+
+    /// Leaves `allowance` of material for a separate finishing pass at full depth.
+    pub fn finishing_pass(mut self, allowance: Real) -> Self {
+        self.finishing = Some(allowance);
+        self
+    }
+
+    /// Keeps tabs of the given height standing above the final depth.
+    pub fn tabs(mut self, height: Real, tabs: Vec<Tab>) -> Self {
+        self.tab_height = height;
+        self.tabs = tabs;
+        self
+    }
+
+    /// Orients closed contours for the given cutting direction instead of following them as given.
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    /// Turns on the spindle with the given speed before and off after cutting.
+    pub fn spindle(mut self, speed: Real) -> Self {
+        self.spindle = Some(speed);
+        self
+    }
+
+    /// The depths of all passes, in cutting order.
+    pub fn passes(&self) -> Vec<Real> {
+        let finishing = self.finishing
+                .map(|allowance| allowance.max(0.0).min(self.depth))
+                .unwrap_or(0.0);
+        let mut passes = steps(self.depth - finishing, self.stepdown);
+
+        if finishing > EPSILON {
+            passes.push(self.depth);
+        }
+
+        return passes;
+    }
+
+    /// Generates the complete program cutting all contours one after another.
+    pub fn generate(&self, contours: &[Contour]) -> Program {
+        let mut emitter = Emitter::new();
+
+        emitter.block(vec![Word::new('G', 90.0)]);
+        if let Some(speed) = self.spindle {
+            emitter.block(vec![Word::new('S', speed), Word::new('M', 3.0)]);
+        }
+
+        let passes = self.passes();
+        let tab_top = self.surface - self.depth + self.tab_height;
+
+        let oriented;
+        let contours = match self.direction {
+            Some(direction) => {
+                oriented = direction.apply(contours);
+                &oriented[..]
+            }
+            None => contours,
+        };
+
+        for contour in contours {
+            let start = match contour.start() {
+                Some(start) => start,
+                None => continue,
+            };
+
+            emitter.retract(self.safe_z);
+            emitter.rapid(start);
+
+            for (i, depth) in passes.iter().enumerate() {
+                let z = self.surface - depth;
+
+                if i > 0 && !contour.is_closed() {
+                    emitter.retract(self.safe_z);
+                    emitter.rapid(start);
+                }
+
+                self.cut(&mut emitter, contour, z, tab_top);
+            }
+
+            emitter.retract(self.safe_z);
+        }
+
+        if self.spindle.is_some() {
+            emitter.block(vec![Word::new('M', 5.0)]);
+        }
+        emitter.block(vec![Word::new('M', 2.0)]);
+
+        return emitter.program;
+    }
+
+    fn cut(&self, emitter: &mut Emitter, contour: &Contour, z: Real, tab_top: Real) {
+        let mut offset = 0.0;
+
+        for (from, to) in contour.edges() {
+            let length = from.distance(to);
+            if length < EPSILON {
+                continue;
+            }
+
+            // Split the edge at all tab boundaries lying on it
+            let mut cuts = vec![0.0, length];
+            for tab in &self.tabs {
+                for boundary in &[tab.position, tab.position + tab.length] {
+                    let local = boundary - offset;
+                    if local > EPSILON && local < length - EPSILON {
+                        cuts.push(local);
+                    }
+                }
+            }
+            cuts.sort_by(|a, b| a.partial_cmp(b).expect("NaN in tab position"));
+
+            for piece in cuts.windows(2) {
+                let middle = offset + (piece[0] + piece[1]) / 2.0;
+                let target = if self.tabs.iter().any(|tab| tab.contains(middle)) {
+                    z.max(tab_top)
+                } else {
+                    z
+                };
+
+                emitter.plunge(target, self.plunge_feed);
+                emitter.cut(from.lerp(to, piece[1] / length), self.feed);
+            }
+
+            offset += length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Point;
+    use crate::parser::Parser;
+
+    fn square() -> Contour {
+        Contour::closed(vec![Point::new(0.0, 0.0),
+                             Point::new(10.0, 0.0),
+                             Point::new(10.0, 10.0),
+                             Point::new(0.0, 10.0)])
+    }
+
+    fn program(lines: &[&str]) -> Program {
+        Parser::new().parse_all(lines.iter()).unwrap().into()
+    }
+
+    #[test]
+    fn test_passes() {
+        assert_eq!(DepthStepping::new(3.0, 1.0, 100.0).passes(), vec![1.0, 2.0, 3.0]);
+        assert_eq!(DepthStepping::new(2.5, 1.0, 100.0).passes(), vec![1.0, 2.0, 2.5]);
+        assert_eq!(DepthStepping::new(3.0, 1.0, 100.0).finishing_pass(0.5).passes(), vec![1.0, 2.0, 2.5, 3.0]);
+        assert_eq!(DepthStepping::new(0.0, 1.0, 100.0).passes(), Vec::<Real>::new());
+    }
+
+    #[test]
+    fn test_generate_closed() {
+        let program = DepthStepping::new(2.0, 1.0, 500.0)
+                .plunge_feed(100.0)
+                .generate(&[square()]);
+
+        assert_eq!(program, self::program(&[
+            "G90",
+            "G0 Z5",
+            "G0 X0 Y0",
+            "G1 Z-1 F100",
+            "G1 X10 Y0 F500",
+            "G1 X10 Y10",
+            "G1 X0 Y10",
+            "G1 X0 Y0",
+            "G1 Z-2 F100",
+            "G1 X10 Y0 F500",
+            "G1 X10 Y10",
+            "G1 X0 Y10",
+            "G1 X0 Y0",
+            "G0 Z5",
+            "M2",
+        ]));
+    }
+
+    #[test]
+    fn test_generate_open() {
+        let contour = Contour::open(vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0)]);
+        let program = DepthStepping::new(2.0, 1.0, 500.0)
+                .generate(&[contour]);
+
+        assert_eq!(program, self::program(&[
+            "G90",
+            "G0 Z5",
+            "G0 X0 Y0",
+            "G1 Z-1 F500",
+            "G1 X10 Y0",
+            "G0 Z5",
+            "G0 X0 Y0",
+            "G1 Z-2",
+            "G1 X10 Y0",
+            "G0 Z5",
+            "M2",
+        ]));
+    }
+
+    #[test]
+    fn test_generate_direction() {
+        let program = DepthStepping::new(1.0, 1.0, 500.0)
+                .direction(Direction::Climb)
+                .generate(&[square()]);
+
+        assert_eq!(program, self::program(&[
+            "G90",
+            "G0 Z5",
+            "G0 X0 Y10",
+            "G1 Z-1 F500",
+            "G1 X10 Y10",
+            "G1 X10 Y0",
+            "G1 X0 Y0",
+            "G1 X0 Y10",
+            "G0 Z5",
+            "M2",
+        ]));
+    }
+
+    #[test]
+    fn test_generate_tabs() {
+        let program = DepthStepping::new(2.0, 1.0, 500.0)
+                .tabs(1.5, vec![Tab::new(4.0, 2.0)])
+                .generate(&[square()]);
+
+        assert_eq!(&program.blocks()[12..], self::program(&[
+            "G1 Z-2",
+            "G1 X4 Y0",
+            "G1 Z-0.5",
+            "G1 X6 Y0",
+            "G1 Z-2",
+            "G1 X10 Y0",
+            "G1 X10 Y10",
+            "G1 X0 Y10",
+            "G1 X0 Y0",
+            "G0 Z5",
+            "M2",
+        ]).blocks());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::EPSILON;
+use crate::geometry::{Contour, Orientation};
+
+/// The direction the tool moves along the material, assuming a clockwise spindle rotation (M3).
+///
+/// When climb milling the cutting edges enter the material at full chip thickness, which gives a
+/// better surface finish on rigid machines. Conventional milling reduces the chip thickness on
+/// entry and is less prone to pulling the tool into the material on machines with backlash.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Climb,
+    Conventional,
+}
+
+impl Direction {
+    /// The orientation of loops cut on the outside of the material, like the outline of a part.
+    pub fn outer(&self) -> Orientation {
+        match *self {
+            Direction::Climb => Orientation::Clockwise,
+            Direction::Conventional => Orientation::CounterClockwise,
+        }
+    }
+
+    /// The orientation of loops cut on the inside of the material, like holes and pockets.
+    pub fn inner(&self) -> Orientation {
+        self.outer().reversed()
+    }
+
+    /// Orients all closed contours according to this direction.
+    ///
+    /// Contours nested inside an even number of other, larger contours are considered to be outer
+    /// ones, all others are holes. Contours of the same size are counted once, so repeated passes
+    /// along the same outline are oriented alike. Open contours are kept as they are.
+    pub fn apply(&self, contours: &[Contour]) -> Vec<Contour> {
+        return contours.iter()
+                .enumerate()
+                .map(|(i, contour)| {
+                    let start = match contour.start() {
+                        Some(start) if contour.is_closed() => start,
+                        _ => return contour.clone(),
+                    };
+
+                    let area = contour.area().abs();
+                    let mut containing = contours.iter()
+                            .enumerate()
+                            .filter(|&(j, other)| j != i && other.is_closed() && other.contains(start))
+                            .map(|(_, other)| other.area().abs())
+                            .filter(|&other| other > area + EPSILON)
+                            .collect::<Vec<_>>();
+
+                    containing.sort_by(|a, b| a.total_cmp(b));
+                    containing.dedup_by(|a, b| *a - *b <= EPSILON);
+                    let depth = containing.len();
+
+                    return contour.oriented(if depth % 2 == 0 { self.outer() } else { self.inner() });
+                })
+                .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Point;
+
+    #[test]
+    fn test_apply() {
+        let outline = Contour::rectangle(Point::new(0.0, 0.0), 10.0, 10.0);
+        let hole = Contour::rectangle(Point::new(0.0, 0.0), 2.0, 2.0);
+        let path = Contour::open(vec![Point::new(20.0, 0.0), Point::new(30.0, 0.0)]);
+
+        let contours = vec![outline.clone(), hole.reversed(), path.clone()];
+
+        assert_eq!(Direction::Climb.apply(&contours), vec![outline.reversed(), hole.clone(), path.clone()]);
+        assert_eq!(Direction::Conventional.apply(&contours), vec![outline.clone(), hole.reversed(), path]);
+
+        // Passes along the same outline are not nested inside each other
+        let contours = vec![outline.clone(), outline.clone(), hole.clone()];
+        assert_eq!(Direction::Climb.apply(&contours), vec![outline.reversed(), outline.reversed(), hole]);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+
+use crate::Real;
+use crate::geometry::Point;
+use crate::import::excellon::Excellon;
+use crate::parser::Word;
+use crate::program::Program;
+
+use super::{units, Emitter};
+
+/// Assigns the tools available on the machine to the drill diameters requested by a file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ToolMap {
+    tools: Vec<(u32, Real)>,
+}
+
+impl ToolMap {
+    pub fn new() -> Self {
+        Self {
+            tools: Vec::new(),
+        }
+    }
+
+    /// Makes the tool with the given number and diameter available.
+    pub fn tool(mut self, number: u32, diameter: Real) -> Self {
+        self.tools.push((number, diameter));
+        self
+    }
+
+    /// The available tool with the diameter closest to the requested one.
+    pub fn map(&self, diameter: Real) -> Option<u32> {
+        self.tools.iter()
+                .min_by(|a, b| (a.1 - diameter).abs()
+                        .partial_cmp(&(b.1 - diameter).abs())
+                        .expect("NaN in tool diameter"))
+                .map(|&(number, _)| number)
+    }
+}
+
+/// Drills all holes of an Excellon file, grouped by tool.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drilling {
+    depth: Real,
+    feed: Real,
+    surface: Real,
+    safe_z: Real,
+    tool_map: Option<ToolMap>,
+    spindle: Option<Real>,
+}
+
+impl Drilling {
+    pub fn new(depth: Real, feed: Real) -> Self {
+        Self {
+            depth,
+            feed,
+            surface: 0.0,
+            safe_z: 1.0,
+            tool_map: None,
+            spindle: None,
+        }
+    }
+
+    /// Sets the Z height of the stock surface.
+    pub fn surface(mut self, z: Real) -> Self {
+        self.surface = z;
+        self
+    }
+
+    /// Sets the Z height used for retracts and rapid moves.
+    pub fn safe_z(mut self, z: Real) -> Self {
+        self.safe_z = z;
+        self
+    }
+
+    /// Maps the drill diameters to machine tools instead of using the tool numbers of the file.
+    pub fn tool_map(mut self, tool_map: ToolMap) -> Self {
+        self.tool_map = Some(tool_map);
+        self
+    }
+
+    /// Turns on the spindle with the given speed after each tool change.
+    pub fn spindle(mut self, speed: Real) -> Self {
+        self.spindle = Some(speed);
+        self
+    }
+
+    pub fn generate(&self, excellon: &Excellon) -> Program {
+        // Holes per machine tool, ordered by tool number
+        let mut groups: BTreeMap<u32, Vec<Point>> = BTreeMap::new();
+        for tool in excellon.tools() {
+            let number = self.tool_map.as_ref()
+                    .and_then(|tool_map| tool_map.map(tool.diameter()))
+                    .unwrap_or(tool.number());
+            groups.entry(number).or_default().extend(tool.holes());
+        }
+
+        let mut emitter = Emitter::new();
+
+        emitter.block(vec![Word::new('G', units(excellon.units()))]);
+        emitter.block(vec![Word::new('G', 90.0)]);
+
+        for (number, holes) in groups {
+            if holes.is_empty() {
+                continue;
+            }
+
+            emitter.retract(self.safe_z);
+            if self.spindle.is_some() {
+                emitter.block(vec![Word::new('M', 5.0)]);
+            }
+            emitter.block(vec![Word::new('T', number as Real), Word::new('M', 6.0)]);
+            if let Some(speed) = self.spindle {
+                emitter.block(vec![Word::new('S', speed), Word::new('M', 3.0)]);
+            }
+
+            for hole in holes {
+                emitter.retract(self.safe_z);
+                emitter.rapid(hole);
+                emitter.plunge(self.surface - self.depth, self.feed);
+            }
+        }
+
+        emitter.retract(self.safe_z);
+        if self.spindle.is_some() {
+            emitter.block(vec![Word::new('M', 5.0)]);
+        }
+        emitter.block(vec![Word::new('M', 2.0)]);
+
+        return emitter.program;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    const EXAMPLE: &str = "M48\nMETRIC\nT1C0.8\nT2C1.0\nT3C1.1\n%\nT1\nX1.0Y1.0\nT2\nX2.0Y2.0\nT3\nX3.0Y3.0\nM30\n";
+
+    #[test]
+    fn test_tool_map() {
+        let tool_map = ToolMap::new()
+                .tool(5, 0.8)
+                .tool(6, 1.05);
+
+        assert_eq!(tool_map.map(0.7), Some(5));
+        assert_eq!(tool_map.map(1.0), Some(6));
+        assert_eq!(ToolMap::new().map(1.0), None);
+    }
+
+    #[test]
+    fn test_generate() {
+        let excellon = Excellon::parse(EXAMPLE).unwrap();
+        let program = Drilling::new(2.0, 100.0)
+                .tool_map(ToolMap::new().tool(5, 0.8).tool(6, 1.05))
+                .generate(&excellon);
+
+        assert_eq!(program, Parser::new().parse_all([
+            "G21",
+            "G90",
+            "G0 Z1",
+            "T5 M6",
+            "G0 X1 Y1",
+            "G1 Z-2 F100",
+            "G0 Z1",
+            "T6 M6",
+            "G0 X2 Y2",
+            "G1 Z-2",
+            "G0 Z1",
+            "G0 X3 Y3",
+            "G1 Z-2",
+            "G0 Z1",
+            "M2",
+        ].iter()).unwrap().into());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::Real;
+use crate::geometry::Point;
+use crate::parser::Word;
+use crate::program::Program;
+
+use super::{steps, Emitter};
+
+/// Faces the top of a rectangular stock, or surfaces a spoilboard, in parallel passes along X.
+///
+/// Each level is cut in a zig-zag, with the passes reaching past both sides of the stock by the
+/// tool radius and a lead-in distance. The tool plunges and steps over outside of the stock only,
+/// so it always enters the material sideways. Optionally, a thin finishing pass with its own feed
+/// rate removes the last material after the roughing levels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Facing {
+    tool_diameter: Real,
+    stepover: Real,
+    depth: Real,
+    stepdown: Real,
+    feed: Real,
+    plunge_feed: Real,
+    lead_in: Real,
+    finishing: Option<(Real, Real)>,
+    surface: Real,
+    safe_z: Real,
+    spindle: Option<Real>,
+}
+
+impl Facing {
+    /// Creates a new generator removing `depth` from the stock in steps of `stepdown`.
+    ///
+    /// The stepover defaults to 70% of the tool diameter and the lead-in to 2 millimeters.
+    ///
+    /// Panics if `tool_diameter` or `stepdown` is not positive.
+    pub fn new(tool_diameter: Real, depth: Real, stepdown: Real, feed: Real) -> Self {
+        assert!(tool_diameter > 0.0, "tool diameter must be positive");
+        assert!(stepdown > 0.0, "stepdown must be positive");
+
+        Self {
+            tool_diameter,
+            stepover: tool_diameter * 0.7,
+            depth,
+            stepdown,
+            feed,
+            plunge_feed: feed,
+            lead_in: 2.0,
+            finishing: None,
+            surface: 0.0,
+            safe_z: 5.0,
+            spindle: None,
+        }
+    }
+
+    /// Sets the distance between two neighbouring passes.
+    ///
+    /// Panics if `stepover` is not positive.
+    pub fn stepover(mut self, stepover: Real) -> Self {
+        assert!(stepover > 0.0, "stepover must be positive");
+        self.stepover = stepover;
+        self
+    }
+
+    pub fn plunge_feed(mut self, feed: Real) -> Self {
+        self.plunge_feed = feed;
+        self
+    }
+
+    /// Sets the distance between the tool and the stock where the tool plunges.
+    pub fn lead_in(mut self, distance: Real) -> Self {
+        self.lead_in = distance;
+        self
+    }
+
+    /// Leaves `allowance` for a finishing level cut with the given feed rate.
+    pub fn finishing(mut self, allowance: Real, feed: Real) -> Self {
+        self.finishing = Some((allowance, feed));
+        self
+    }
+
+    /// Sets the Z height of the stock surface where the first pass starts.
+    pub fn surface(mut self, z: Real) -> Self {
+        self.surface = z;
+        self
+    }
+
+    /// Sets the Z height used for retracts and rapid moves.
+    pub fn safe_z(mut self, z: Real) -> Self {
+        self.safe_z = z;
+        self
+    }
+
+    /// Turns on the spindle with the given speed before and off after cutting.
+    pub fn spindle(mut self, speed: Real) -> Self {
+        self.spindle = Some(speed);
+        self
+    }
+
+    /// The Z heights of all levels along with their feed rates, from top to bottom.
+    pub fn levels(&self) -> Vec<(Real, Real)> {
+        let (allowance, finish_feed) = self.finishing.unwrap_or((0.0, self.feed));
+        let roughing = (self.depth - allowance).max(0.0);
+
+        let mut levels = steps(roughing, self.stepdown).into_iter()
+                .map(|depth| (self.surface - depth, self.feed))
+                .collect::<Vec<_>>();
+        if self.finishing.is_some() && self.depth > roughing {
+            levels.push((self.surface - self.depth, finish_feed));
+        }
+
+        return levels;
+    }
+
+    /// The Y coordinates of the passes covering the stock between `min` and `max`, which are
+    /// spread evenly from one edge of the stock to the other.
+    pub fn passes(&self, min: Point, max: Point) -> Vec<Real> {
+        let width = max.y - min.y;
+        let count = (width / self.stepover).ceil().max(0.0) as usize;
+
+        return (0..=count)
+                .map(|i| if count == 0 { min.y } else { min.y + width * i as Real / count as Real })
+                .collect();
+    }
+
+    /// Generates the complete program facing the stock between the corners `min` and `max`.
+    pub fn generate(&self, min: Point, max: Point) -> Program {
+        let mut emitter = Emitter::new();
+
+        emitter.block(vec![Word::new('G', 90.0)]);
+        if let Some(speed) = self.spindle {
+            emitter.block(vec![Word::new('S', speed), Word::new('M', 3.0)]);
+        }
+
+        let overhang = self.tool_diameter / 2.0 + self.lead_in;
+        let (left, right) = (min.x - overhang, max.x + overhang);
+        let passes = self.passes(min, max);
+
+        for (z, feed) in self.levels() {
+            emitter.retract(self.safe_z);
+            emitter.rapid(Point::new(left, passes[0]));
+            emitter.plunge(z, self.plunge_feed);
+
+            for (i, &y) in passes.iter().enumerate() {
+                let (from, to) = if i % 2 == 0 { (left, right) } else { (right, left) };
+                emitter.cut(Point::new(from, y), feed);
+                emitter.cut(Point::new(to, y), feed);
+            }
+        }
+
+        emitter.retract(self.safe_z);
+
+        if self.spindle.is_some() {
+            emitter.block(vec![Word::new('M', 5.0)]);
+        }
+        emitter.block(vec![Word::new('M', 2.0)]);
+
+        return emitter.program;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(Facing::new(10.0, 1.2, 0.5, 1000.0).levels(), vec![(-0.5, 1000.0), (-1.0, 1000.0), (-1.2, 1000.0)]);
+        assert_eq!(Facing::new(10.0, 1.2, 0.5, 1000.0).finishing(0.2, 600.0).levels(), vec![(-0.5, 1000.0), (-1.0, 1000.0), (-1.2, 600.0)]);
+        assert_eq!(Facing::new(10.0, 0.1, 0.5, 1000.0).finishing(0.2, 600.0).surface(3.0).levels(), vec![(2.9, 600.0)]);
+    }
+
+    #[test]
+    fn test_passes() {
+        let facing = Facing::new(10.0, 1.0, 1.0, 1000.0);
+        assert_eq!(facing.passes(Point::new(0.0, 0.0), Point::new(50.0, 21.0)), vec![0.0, 7.0, 14.0, 21.0]);
+        assert_eq!(facing.passes(Point::new(0.0, 5.0), Point::new(50.0, 5.0)), vec![5.0]);
+    }
+
+    #[test]
+    fn test_generate() {
+        let program = Facing::new(6.0, 1.5, 1.0, 800.0)
+                .stepover(5.0)
+                .lead_in(1.0)
+                .finishing(0.5, 400.0)
+                .plunge_feed(200.0)
+                .spindle(18000.0)
+                .generate(Point::new(0.0, 0.0), Point::new(20.0, 10.0));
+
+        assert_eq!(program, Parser::new().parse_all([
+            "G90",
+            "S18000 M3",
+            "G0 Z5",
+            "G0 X-4 Y0",
+            "G1 Z-1 F200",
+            "G1 X24 Y0 F800",
+            "G1 X24 Y5",
+            "G1 X-4 Y5",
+            "G1 X-4 Y10",
+            "G1 X24 Y10",
+            "G0 Z5",
+            "G0 X-4 Y0",
+            "G1 Z-1.5 F200",
+            "G1 X24 Y0 F400",
+            "G1 X24 Y5",
+            "G1 X-4 Y5",
+            "G1 X-4 Y10",
+            "G1 X24 Y10",
+            "G0 Z5",
+            "M5",
+            "M2",
+        ].iter()).unwrap().into());
+    }
+}
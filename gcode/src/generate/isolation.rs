@@ -0,0 +1,121 @@
+use crate::Real;
+use crate::import::gerber::Gerber;
+use crate::parser::{Block, Word};
+use crate::program::Program;
+
+use super::{units, DepthStepping, Direction};
+
+/// Mills isolation channels around the copper features of a Gerber layer.
+///
+/// Each pass follows the outlines of all features grown by the tool radius plus the stepover of
+/// the passes before. By default, the channels are climb milled, cutting clockwise around the
+/// copper.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Isolation {
+    tool_diameter: Real,
+    depth: Real,
+    feed: Real,
+    plunge_feed: Real,
+    passes: usize,
+    overlap: Real,
+    direction: Direction,
+    safe_z: Real,
+    spindle: Option<Real>,
+}
+
+impl Isolation {
+    /// Panics if `tool_diameter` is not positive.
+    pub fn new(tool_diameter: Real, depth: Real, feed: Real) -> Self {
+        assert!(tool_diameter > 0.0, "tool diameter must be positive");
+
+        Self {
+            tool_diameter,
+            depth,
+            feed,
+            plunge_feed: feed,
+            passes: 1,
+            overlap: 0.5,
+            direction: Direction::Climb,
+            safe_z: 1.0,
+            spindle: None,
+        }
+    }
+
+    /// Sets the number of passes widening the channels.
+    pub fn passes(mut self, passes: usize) -> Self {
+        self.passes = passes.max(1);
+        self
+    }
+
+    /// Sets the fraction of the tool diameter by which neighbouring passes overlap.
+    pub fn overlap(mut self, overlap: Real) -> Self {
+        self.overlap = overlap.clamp(0.0, 0.95);
+        self
+    }
+
+    /// Sets the cutting direction around the copper features.
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn plunge_feed(mut self, feed: Real) -> Self {
+        self.plunge_feed = feed;
+        self
+    }
+
+    /// Sets the Z height used for retracts and rapid moves.
+    pub fn safe_z(mut self, z: Real) -> Self {
+        self.safe_z = z;
+        self
+    }
+
+    /// Turns on the spindle with the given speed before and off after cutting.
+    pub fn spindle(mut self, speed: Real) -> Self {
+        self.spindle = Some(speed);
+        self
+    }
+
+    pub fn generate(&self, gerber: &Gerber) -> Program {
+        let radius = self.tool_diameter / 2.0;
+        let stepover = self.tool_diameter * (1.0 - self.overlap);
+
+        let contours = (0..self.passes)
+                .flat_map(|pass| gerber.outlines(radius + pass as Real * stepover))
+                .map(|contour| contour.oriented(self.direction.outer()))
+                .collect::<Vec<_>>();
+
+        let mut cutting = DepthStepping::new(self.depth, self.depth.max(Real::MIN_POSITIVE), self.feed)
+                .plunge_feed(self.plunge_feed)
+                .safe_z(self.safe_z);
+        if let Some(speed) = self.spindle {
+            cutting = cutting.spindle(speed);
+        }
+
+        let mut program = Program::new();
+        program.push(Block::new(vec![Word::new('G', units(gerber.units()))]));
+        program.extend(cutting.generate(&contours));
+
+        return program;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let gerber = Gerber::parse("%FSLAX26Y26*%%MOIN*%%ADD10R,1X1*%D10*X0Y0D03*").unwrap();
+        let program = Isolation::new(0.2, 0.1, 100.0)
+                .passes(2)
+                .generate(&gerber);
+
+        let lines = program.iter().map(|block| block.line()).collect::<Vec<_>>();
+        assert_eq!(&lines[..4], &["G20", "G90", "G0 Z1", "G0 X-0.6 Y0.6"]);
+
+        // Both passes around the pad, clockwise
+        assert!(lines.contains(&"G1 X0.6 Y0.6"));
+        assert!(lines.contains(&"G1 X0.7 Y0.7"));
+    }
+}
@@ -0,0 +1,327 @@
+use crate::Real;
+use crate::geometry::{Contour, Point};
+use crate::parser::Word;
+use crate::program::Program;
+
+use super::{steps, Direction, Emitter};
+
+/// The precision in millimeters to which the middle of a pocket is located.
+const CENTRE_TOLERANCE: Real = 1e-3;
+
+/// Clears the inside of a closed boundary with concentric offset rings.
+///
+/// The rings are cut from the inside out, starting in the middle of the pocket and linking each
+/// ring to the next one without retracting. Material left inside the innermost ring is cleared
+/// by a pass along the middle of the pocket first, where the offset boundary collapses into a
+/// point or a line.
+///
+/// The stepover between two rings is limited by the maximal engagement angle of the tool, if
+/// configured. This is no adaptive clearing though: the limit only holds along straight walls,
+/// and the tool engages further in inside corners of the rings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pocket {
+    tool_diameter: Real,
+    stepover: Real,
+    engagement: Option<Real>,
+    direction: Direction,
+    depth: Real,
+    stepdown: Real,
+    feed: Real,
+    plunge_feed: Real,
+    surface: Real,
+    safe_z: Real,
+    spindle: Option<Real>,
+}
+
+impl Pocket {
+    /// Creates a new generator clearing a pocket of the given `depth` in steps of `stepdown`.
+    ///
+    /// The stepover defaults to 40% of the tool diameter.
+    ///
+    /// Panics if `tool_diameter` or `stepdown` is not positive.
+    pub fn new(tool_diameter: Real, depth: Real, stepdown: Real, feed: Real) -> Self {
+        assert!(tool_diameter > 0.0, "tool diameter must be positive");
+        assert!(stepdown > 0.0, "stepdown must be positive");
+
+        Self {
+            tool_diameter,
+            stepover: tool_diameter * 0.4,
+            engagement: None,
+            direction: Direction::Climb,
+            depth,
+            stepdown,
+            feed,
+            plunge_feed: feed,
+            surface: 0.0,
+            safe_z: 5.0,
+            spindle: None,
+        }
+    }
+
+    /// Sets the distance between two neighbouring rings.
+    ///
+    /// Panics if `stepover` is not positive.
+    pub fn stepover(mut self, stepover: Real) -> Self {
+        assert!(stepover > 0.0, "stepover must be positive");
+        self.stepover = stepover;
+        self
+    }
+
+    /// Limits the angle (in degrees) of the tool circumference being engaged in material.
+    pub fn max_engagement(mut self, degrees: Real) -> Self {
+        self.engagement = Some(degrees);
+        self
+    }
+
+    /// Sets the cutting direction of the rings, which defaults to climb milling.
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn plunge_feed(mut self, feed: Real) -> Self {
+        self.plunge_feed = feed;
+        self
+    }
+
+    /// Sets the Z height of the stock surface where the first pass starts.
+    pub fn surface(mut self, z: Real) -> Self {
+        self.surface = z;
+        self
+    }
+
+    /// Sets the Z height used for retracts and rapid moves.
+    pub fn safe_z(mut self, z: Real) -> Self {
+        self.safe_z = z;
+        self
+    }
+
+    /// Turns on the spindle with the given speed before and off after cutting.
+    pub fn spindle(mut self, speed: Real) -> Self {
+        self.spindle = Some(speed);
+        self
+    }
+
+    /// The effective stepover after applying the engagement limit.
+    ///
+    /// Cutting along a straight wall with a radial depth of `a` engages the tool over an angle of
+    /// `acos(1 - a / r)`, which is solved for `a` here.
+    pub fn effective_stepover(&self) -> Real {
+        let radius = self.tool_diameter / 2.0;
+
+        return match self.engagement {
+            Some(degrees) => {
+                let degrees = degrees.clamp(1.0, 180.0);
+                self.stepover.min(radius * (1.0 - degrees.to_radians().cos()))
+            }
+            None => self.stepover,
+        };
+    }
+
+    /// Calculates the tool center rings clearing the boundary, innermost first.
+    ///
+    /// If the innermost ring leaves material in the middle, the first ring is the path along the
+    /// middle instead, going back and forth along a line or staying at a single point.
+    pub fn rings(&self, boundary: &Contour) -> Vec<Contour> {
+        let radius = self.tool_diameter / 2.0;
+        let stepover = self.effective_stepover();
+
+        let mut rings = Vec::new();
+
+        let mut offset = radius;
+        while let Some(ring) = boundary.offset(-offset) {
+            rings.push(ring.oriented(self.direction.inner()));
+            offset += stepover;
+        }
+
+        if !rings.is_empty() {
+            rings.extend(centre(boundary, offset - stepover, offset, radius));
+        }
+
+        rings.reverse();
+
+        // Start each ring close to where the previous one started to keep linking moves short
+        for i in 1..rings.len() {
+            let start = rings[i - 1].start().expect("Empty ring");
+            rings[i] = rotate_to(&rings[i], start);
+        }
+
+        return rings;
+    }
+
+    /// Generates the complete program clearing the pocket inside the boundary.
+    pub fn generate(&self, boundary: &Contour) -> Program {
+        let mut emitter = Emitter::new();
+
+        emitter.block(vec![Word::new('G', 90.0)]);
+        if let Some(speed) = self.spindle {
+            emitter.block(vec![Word::new('S', speed), Word::new('M', 3.0)]);
+        }
+
+        let rings = self.rings(boundary);
+
+        if let Some(entry) = rings.first().and_then(|ring| ring.start()) {
+            emitter.retract(self.safe_z);
+            emitter.rapid(entry);
+
+            for depth in steps(self.depth, self.stepdown) {
+                // Return to the entry point over the floor cleared by the previous level
+                emitter.cut(entry, self.feed);
+                emitter.plunge(self.surface - depth, self.plunge_feed);
+
+                for ring in &rings {
+                    for point in ring.points().iter().chain(ring.start().iter()) {
+                        emitter.cut(*point, self.feed);
+                    }
+                }
+            }
+
+            emitter.retract(self.safe_z);
+        }
+
+        if self.spindle.is_some() {
+            emitter.block(vec![Word::new('M', 5.0)]);
+        }
+        emitter.block(vec![Word::new('M', 2.0)]);
+
+        return emitter.program;
+    }
+}
+
+/// The path along the middle of the region left uncut by a tool of the given radius on the ring at
+/// offset `ring`, if any, given the boundary collapses when offset by `collapsed`.
+///
+/// The middle is where the boundary offset as far as possible collapses into a point or a line.
+fn centre(boundary: &Contour, ring: Real, collapsed: Real, radius: Real) -> Option<Contour> {
+    let (mut inside, mut outside) = (ring, collapsed);
+    while outside - inside > CENTRE_TOLERANCE / 2.0 {
+        let middle = (inside + outside) / 2.0;
+        match boundary.offset(-middle) {
+            Some(_) => inside = middle,
+            None => outside = middle,
+        }
+    }
+
+    if inside <= ring + radius + CENTRE_TOLERANCE {
+        return None;
+    }
+
+    // The offset boundary is a sliver around the middle, so the points at either end of it are
+    // merged, up to a few times the width of a sliver of that area and length apart
+    let sliver = boundary.offset(-inside)?;
+    let width = 2.0 * sliver.area().abs() / sliver.length().max(CENTRE_TOLERANCE);
+    let mut clusters = Vec::<Vec<Point>>::new();
+    for &point in sliver.points() {
+        match clusters.iter_mut().find(|cluster| cluster[0].distance(point) <= 4.0 * width + CENTRE_TOLERANCE) {
+            Some(cluster) => cluster.push(point),
+            None => clusters.push(vec![point]),
+        }
+    }
+
+    let points = clusters.iter()
+            .map(|cluster| {
+                let n = cluster.len() as Real;
+                let (x, y) = cluster.iter().fold((0.0, 0.0), |(x, y), point| (x + point.x, y + point.y));
+                Point::new((x / n * 1e3).round() / 1e3, (y / n * 1e3).round() / 1e3)
+            })
+            .collect();
+
+    return Some(Contour::closed(points));
+}
+
+/// Rotates the points of a closed contour to start at the point closest to `target`.
+fn rotate_to(contour: &Contour, target: Point) -> Contour {
+    let points = contour.points();
+
+    let start = (0..points.len())
+            .min_by(|&a, &b| points[a].distance(target)
+                    .partial_cmp(&points[b].distance(target))
+                    .expect("NaN in contour"))
+            .unwrap_or(0);
+
+    let mut points = points.to_vec();
+    points.rotate_left(start);
+
+    return Contour::closed(points);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn square(size: Real) -> Contour {
+        Contour::closed(vec![Point::new(0.0, 0.0),
+                             Point::new(0.0, size),
+                             Point::new(size, size),
+                             Point::new(size, 0.0)])
+    }
+
+    #[test]
+    fn test_effective_stepover() {
+        assert_eq!(Pocket::new(4.0, 1.0, 1.0, 100.0).effective_stepover(), 1.6);
+        assert_eq!(Pocket::new(4.0, 1.0, 1.0, 100.0).stepover(2.0).effective_stepover(), 2.0);
+        assert!((Pocket::new(4.0, 1.0, 1.0, 100.0).stepover(2.0).max_engagement(60.0).effective_stepover() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rings() {
+        let rings = Pocket::new(4.0, 1.0, 1.0, 100.0)
+                .stepover(2.0)
+                .rings(&square(20.0));
+
+        assert_eq!(rings.len(), 4);
+        assert_eq!(rings.iter().map(|ring| ring.area()).collect::<Vec<_>>(), vec![16.0, 64.0, 144.0, 256.0]);
+        assert_eq!(rings.iter().map(|ring| ring.start()).collect::<Vec<_>>(), vec![
+            Some(Point::new(12.0, 8.0)),
+            Some(Point::new(14.0, 6.0)),
+            Some(Point::new(16.0, 4.0)),
+            Some(Point::new(18.0, 2.0)),
+        ]);
+    }
+
+    #[test]
+    fn test_rings_centre() {
+        // A line along the middle of a narrow pocket, cut back and forth
+        let slot = Contour::closed(vec![Point::new(0.0, 0.0), Point::new(0.0, 10.0), Point::new(30.0, 10.0), Point::new(30.0, 0.0)]);
+        let rings = Pocket::new(4.0, 1.0, 1.0, 100.0).stepover(3.0).rings(&slot);
+
+        assert_eq!(rings.len(), 2);
+        assert_eq!(rings[0].points(), &[Point::new(5.0, 5.0), Point::new(25.0, 5.0)]);
+        assert_eq!(rings[1].area(), 156.0);
+    }
+
+    #[test]
+    fn test_rings_too_small() {
+        assert!(Pocket::new(4.0, 1.0, 1.0, 100.0).rings(&square(3.0)).is_empty());
+    }
+
+    #[test]
+    fn test_generate() {
+        let program = Pocket::new(4.0, 2.0, 1.0, 500.0)
+                .stepover(3.0)
+                .generate(&square(10.0));
+
+        // The second ring would collapse, so the middle left by the first one is cut at its center
+        assert_eq!(program, Parser::new().parse_all([
+            "G90",
+            "G0 Z5",
+            "G0 X5 Y5",
+            "G1 Z-1 F500",
+            "G1 X8 Y2",
+            "G1 X8 Y8",
+            "G1 X2 Y8",
+            "G1 X2 Y2",
+            "G1 X8 Y2",
+            "G1 X5 Y5",
+            "G1 Z-2",
+            "G1 X8 Y2",
+            "G1 X8 Y8",
+            "G1 X2 Y8",
+            "G1 X2 Y2",
+            "G1 X8 Y2",
+            "G0 Z5",
+            "M2",
+        ].iter()).unwrap().into());
+    }
+}
@@ -0,0 +1,506 @@
+use std::ops::{Index, IndexMut};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Real, EPSILON};
+use crate::real::consts::PI;
+
+/// Units of length used by a program or an imported document.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Units {
+    Millimeters,
+    Inches,
+}
+
+impl Units {
+    /// The length of one unit in millimeters.
+    pub fn millimeters(&self) -> Real {
+        match *self {
+            Units::Millimeters => 1.0,
+            Units::Inches => 25.4,
+        }
+    }
+}
+
+/// The direction a closed contour is traversed in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    Clockwise,
+    CounterClockwise,
+}
+
+impl Orientation {
+    pub fn reversed(&self) -> Self {
+        match *self {
+            Orientation::Clockwise => Orientation::CounterClockwise,
+            Orientation::CounterClockwise => Orientation::Clockwise,
+        }
+    }
+}
+
+/// A point in the XY plane.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Point {
+    pub x: Real,
+    pub y: Real,
+}
+
+impl Point {
+    pub fn new(x: Real, y: Real) -> Self {
+        Self { x, y }
+    }
+
+    pub fn distance(&self, other: Point) -> Real {
+        (other.x - self.x).hypot(other.y - self.y)
+    }
+
+    /// Interpolates linearly between this point (t = 0) and the other one (t = 1).
+    pub fn lerp(&self, other: Point, t: Real) -> Point {
+        Point {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+        }
+    }
+}
+
+/// A point in space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Point3 {
+    pub x: Real,
+    pub y: Real,
+    pub z: Real,
+}
+
+impl Point3 {
+    pub fn new(x: Real, y: Real, z: Real) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn distance(&self, other: Point3) -> Real {
+        ((other.x - self.x).powi(2) + (other.y - self.y).powi(2) + (other.z - self.z).powi(2)).sqrt()
+    }
+
+    /// The projection of this point onto the XY plane.
+    pub fn xy(&self) -> Point {
+        Point::new(self.x, self.y)
+    }
+}
+
+/// The axes of a machine besides X, Y and Z.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Axis {
+    A,
+    B,
+    C,
+    U,
+    V,
+    W,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 6] = [Axis::A, Axis::B, Axis::C, Axis::U, Axis::V, Axis::W];
+
+    pub fn from_letter(letter: char) -> Option<Axis> {
+        return Axis::ALL.iter().cloned().find(|axis| axis.letter() == letter.to_ascii_uppercase());
+    }
+
+    pub fn letter(&self) -> char {
+        match *self {
+            Axis::A => 'A',
+            Axis::B => 'B',
+            Axis::C => 'C',
+            Axis::U => 'U',
+            Axis::V => 'V',
+            Axis::W => 'W',
+        }
+    }
+
+    /// Whether the axis rotates around X, Y or Z (A, B and C) with positions in degrees, instead
+    /// of moving parallel to them (U, V and W) with positions in units of length.
+    pub fn is_rotary(&self) -> bool {
+        match *self {
+            Axis::A | Axis::B | Axis::C => true,
+            Axis::U | Axis::V | Axis::W => false,
+        }
+    }
+
+    fn index(&self) -> usize {
+        return Axis::ALL.iter().position(|axis| axis == self).expect("Axis is listed");
+    }
+}
+
+/// A value for each of the axes besides X, Y and Z.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AxisValues<T>(pub [T; 6]);
+
+impl<T> AxisValues<T> {
+    /// Creates the values by calling the function for each axis.
+    pub fn from_fn<F>(mut f: F) -> Self
+        where F: FnMut(Axis) -> T {
+        let [a, b, c, u, v, w] = Axis::ALL;
+        AxisValues([f(a), f(b), f(c), f(u), f(v), f(w)])
+    }
+
+    /// All axes with their values.
+    pub fn iter(&self) -> impl Iterator<Item=(Axis, &T)> + '_ {
+        Axis::ALL.iter().cloned().zip(self.0.iter())
+    }
+
+    /// Applies the function to the value of each axis.
+    pub fn map<U, F>(&self, mut f: F) -> AxisValues<U>
+        where F: FnMut(Axis, &T) -> U {
+        AxisValues::from_fn(|axis| f(axis, &self[axis]))
+    }
+}
+
+impl<T> Index<Axis> for AxisValues<T> {
+    type Output = T;
+
+    fn index(&self, axis: Axis) -> &T {
+        &self.0[axis.index()]
+    }
+}
+
+impl<T> IndexMut<Axis> for AxisValues<T> {
+    fn index_mut(&mut self, axis: Axis) -> &mut T {
+        &mut self.0[axis.index()]
+    }
+}
+
+/// A polyline in the XY plane, either open or closed.
+///
+/// A closed contour implicitly connects its last point back to the first one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contour {
+    points: Vec<Point>,
+    closed: bool,
+}
+
+impl Contour {
+    pub fn open(points: Vec<Point>) -> Self {
+        Self {
+            points,
+            closed: false,
+        }
+    }
+
+    pub fn closed(points: Vec<Point>) -> Self {
+        Self {
+            points,
+            closed: true,
+        }
+    }
+
+    /// Approximates a circle by a counter-clockwise regular polygon with the given number of
+    /// vertices on the circle.
+    pub fn circle(center: Point, radius: Real, segments: usize) -> Self {
+        let segments = segments.max(3);
+
+        return Self::closed((0..segments)
+                .map(|i| {
+                    let angle = 2.0 * PI * i as Real / segments as Real;
+                    Point::new(center.x + radius * angle.cos(), center.y + radius * angle.sin())
+                })
+                .collect());
+    }
+
+    /// Creates a counter-clockwise axis-aligned rectangle centered around a point.
+    pub fn rectangle(center: Point, width: Real, height: Real) -> Self {
+        let (w, h) = (width / 2.0, height / 2.0);
+
+        return Self::closed(vec![Point::new(center.x - w, center.y - h),
+                                 Point::new(center.x + w, center.y - h),
+                                 Point::new(center.x + w, center.y + h),
+                                 Point::new(center.x - w, center.y + h)]);
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// The point where traversing the contour starts.
+    pub fn start(&self) -> Option<Point> {
+        self.points.first().cloned()
+    }
+
+    /// Iterates over all edges of the contour, including the closing edge of closed contours.
+    pub fn edges(&self) -> impl Iterator<Item=(Point, Point)> + '_ {
+        let closing = if self.closed && self.points.len() > 2 {
+            Some((self.points[self.points.len() - 1], self.points[0]))
+        } else {
+            None
+        };
+
+        return self.points.windows(2)
+                .map(|w| (w[0], w[1]))
+                .chain(closing);
+    }
+
+    /// The total length of all edges.
+    pub fn length(&self) -> Real {
+        self.edges()
+                .map(|(a, b)| a.distance(b))
+                .sum()
+    }
+
+    /// The signed area enclosed by the contour, positive for counter-clockwise orientation.
+    ///
+    /// Open contours are treated as if they were closed.
+    pub fn area(&self) -> Real {
+        let n = self.points.len();
+
+        return (0..n)
+                .map(|i| cross(self.points[i], self.points[(i + 1) % n]))
+                .sum::<Real>() / 2.0;
+    }
+
+    /// The orientation of the contour, if it encloses any area at all.
+    pub fn orientation(&self) -> Option<Orientation> {
+        let area = self.area();
+
+        return if area > EPSILON {
+            Some(Orientation::CounterClockwise)
+        } else if area < -EPSILON {
+            Some(Orientation::Clockwise)
+        } else {
+            None
+        };
+    }
+
+    /// Returns the contour traversed in the given orientation, reversing it if required.
+    ///
+    /// Degenerate contours without an orientation are returned unchanged.
+    pub fn oriented(&self, orientation: Orientation) -> Self {
+        return match self.orientation() {
+            Some(current) if current != orientation => self.reversed(),
+            _ => self.clone(),
+        };
+    }
+
+    /// Checks if a point lies inside the area enclosed by the contour using the even-odd rule.
+    ///
+    /// Open contours are treated as if they were closed.
+    pub fn contains(&self, point: Point) -> bool {
+        let n = self.points.len();
+
+        let mut inside = false;
+        for i in 0..n {
+            let (a, b) = (self.points[i], self.points[(i + 1) % n]);
+            if (a.y > point.y) != (b.y > point.y) {
+                let x = a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x);
+                if point.x < x {
+                    inside = !inside;
+                }
+            }
+        }
+
+        return inside;
+    }
+
+    /// Returns the same contour traversed in the opposite direction.
+    pub fn reversed(&self) -> Self {
+        let mut points = self.points.clone();
+        points.reverse();
+
+        return Self {
+            points,
+            closed: self.closed,
+        };
+    }
+
+    /// Offsets a closed contour by `distance`, growing the enclosed area for positive and shrinking
+    /// it for negative distances.
+    ///
+    /// Edges which vanish while shrinking are dropped. Returns `None` if the contour is open,
+    /// collapses completely or would split into multiple parts.
+    pub fn offset(&self, distance: Real) -> Option<Contour> {
+        if !self.closed {
+            return None;
+        }
+
+        let area = self.area();
+        if area.abs() < EPSILON {
+            return None;
+        }
+
+        // The left-hand normal points inwards for counter-clockwise contours
+        let shift = if area > 0.0 { -distance } else { distance };
+
+        // The offset edges as lines, each given by a point on the line and its unit direction
+        let mut lines = self.edges()
+                .filter(|(a, b)| a.distance(*b) > EPSILON)
+                .map(|(a, b)| {
+                    let length = a.distance(b);
+                    let direction = Point::new((b.x - a.x) / length, (b.y - a.y) / length);
+                    let origin = Point::new(a.x - direction.y * shift, a.y + direction.x * shift);
+                    (origin, direction)
+                })
+                .collect::<Vec<_>>();
+
+        let points = loop {
+            let n = lines.len();
+            if n < 3 {
+                return None;
+            }
+
+            // Line i starts at the intersection with its predecessor
+            let points = (0..n)
+                    .map(|i| intersect(lines[(i + n - 1) % n], lines[i]))
+                    .collect::<Vec<_>>();
+
+            // Drop parallel neighbours and lines which got reversed by the offset
+            let vanished = (0..n).find(|&i| match (points[i], points[(i + 1) % n]) {
+                (Some(a), Some(b)) => (b.x - a.x) * lines[i].1.x + (b.y - a.y) * lines[i].1.y < EPSILON,
+                _ => true,
+            });
+
+            match vanished {
+                Some(i) => { lines.remove(i); }
+                None => break points.into_iter().flatten().collect::<Vec<_>>(),
+            }
+        };
+
+        let contour = Contour::closed(points);
+        if contour.area() * area <= 0.0 || contour.intersects_itself() {
+            return None;
+        }
+
+        return Some(contour);
+    }
+
+    fn intersects_itself(&self) -> bool {
+        let edges = self.edges().collect::<Vec<_>>();
+        let n = edges.len();
+
+        for i in 0..n {
+            for j in (i + 2)..n {
+                if self.closed && i == 0 && j == n - 1 {
+                    continue;
+                }
+
+                if segments_intersect(edges[i], edges[j]) {
+                    return true;
+                }
+            }
+        }
+
+        return false;
+    }
+}
+
+fn cross(a: Point, b: Point) -> Real {
+    a.x * b.y - a.y * b.x
+}
+
+/// Intersects two lines given by a point and a direction each.
+fn intersect((p, u): (Point, Point), (q, v): (Point, Point)) -> Option<Point> {
+    let denominator = cross(u, v);
+    if denominator.abs() < EPSILON {
+        return None;
+    }
+
+    let t = cross(Point::new(q.x - p.x, q.y - p.y), v) / denominator;
+
+    return Some(Point::new(p.x + u.x * t, p.y + u.y * t));
+}
+
+fn segments_intersect((a, b): (Point, Point), (c, d): (Point, Point)) -> bool {
+    let side = |p: Point, q: Point, r: Point| cross(Point::new(q.x - p.x, q.y - p.y), Point::new(r.x - p.x, r.y - p.y));
+
+    let d1 = side(c, d, a);
+    let d2 = side(c, d, b);
+    let d3 = side(a, b, c);
+    let d4 = side(a, b, d);
+
+    return d1 * d2 < 0.0 && d3 * d4 < 0.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contour_edges() {
+        let points = vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(10.0, 10.0)];
+
+        assert_eq!(Contour::open(points.clone()).edges().count(), 2);
+        assert_eq!(Contour::closed(points.clone()).edges().count(), 3);
+        assert_eq!(Contour::closed(points).edges().last(), Some((Point::new(10.0, 10.0), Point::new(0.0, 0.0))));
+    }
+
+    #[test]
+    fn test_contour_length() {
+        let points = vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(10.0, 10.0), Point::new(0.0, 10.0)];
+
+        assert_eq!(Contour::open(points.clone()).length(), 30.0);
+        assert_eq!(Contour::closed(points).length(), 40.0);
+    }
+
+    #[test]
+    fn test_contour_shapes() {
+        let circle = Contour::circle(Point::new(1.0, 1.0), 2.0, 4);
+        assert_eq!(circle.points().len(), 4);
+        assert!((circle.area() - 8.0).abs() < 1e-9);
+
+        assert_eq!(Contour::rectangle(Point::new(1.0, 1.0), 4.0, 2.0).area(), 8.0);
+    }
+
+    #[test]
+    fn test_contour_area() {
+        let points = vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(10.0, 10.0), Point::new(0.0, 10.0)];
+
+        assert_eq!(Contour::closed(points.clone()).area(), 100.0);
+        assert_eq!(Contour::closed(points).reversed().area(), -100.0);
+    }
+
+    #[test]
+    fn test_contour_orientation() {
+        let square = Contour::rectangle(Point::new(0.0, 0.0), 2.0, 2.0);
+
+        assert_eq!(square.orientation(), Some(Orientation::CounterClockwise));
+        assert_eq!(square.reversed().orientation(), Some(Orientation::Clockwise));
+        assert_eq!(square.oriented(Orientation::Clockwise), square.reversed());
+        assert_eq!(square.oriented(Orientation::CounterClockwise), square);
+        assert_eq!(Contour::open(vec![Point::new(0.0, 0.0), Point::new(1.0, 1.0)]).orientation(), None);
+    }
+
+    #[test]
+    fn test_contour_contains() {
+        let square = Contour::rectangle(Point::new(0.0, 0.0), 2.0, 2.0);
+
+        assert!(square.contains(Point::new(0.5, -0.5)));
+        assert!(square.reversed().contains(Point::new(0.5, -0.5)));
+        assert!(!square.contains(Point::new(1.5, 0.0)));
+    }
+
+    #[test]
+    fn test_contour_offset() {
+        let square = Contour::closed(vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(10.0, 10.0), Point::new(0.0, 10.0)]);
+
+        assert_eq!(square.offset(-2.0), Some(Contour::closed(vec![Point::new(2.0, 2.0), Point::new(8.0, 2.0), Point::new(8.0, 8.0), Point::new(2.0, 8.0)])));
+        assert_eq!(square.reversed().offset(1.0).map(|c| c.area()), Some(-144.0));
+        assert_eq!(square.offset(-5.0), None);
+        assert_eq!(square.offset(-6.0), None);
+        assert_eq!(Contour::open(square.points().to_vec()).offset(1.0), None);
+    }
+
+    #[test]
+    fn test_contour_offset_concave() {
+        // An L-shape with both legs being 4 wide
+        let l = Contour::closed(vec![Point::new(0.0, 0.0), Point::new(20.0, 0.0), Point::new(20.0, 4.0),
+                                     Point::new(4.0, 4.0), Point::new(4.0, 10.0), Point::new(0.0, 10.0)]);
+
+        assert_eq!(l.offset(-1.0).map(|c| c.points().len()), Some(6));
+        assert_eq!(l.offset(-1.0).map(|c| c.area()), Some(18.0 * 2.0 + 2.0 * 6.0));
+        assert_eq!(l.offset(-2.5), None);
+    }
+}
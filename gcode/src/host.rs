@@ -0,0 +1,436 @@
+//! Clients for print hosts, dispatching programs to Moonraker (Klipper) and OctoPrint.
+//!
+//! Both hosts offer an HTTP API for uploading files, starting jobs and sending single commands.
+//! The clients speak plain HTTP/1.0 and block until the host replied.
+//!
+//! This covers dispatching programs only. There are no async clients, and the status of a job
+//! is not followed, which would take the WebSocket APIs of both hosts.
+
+use std::collections::hash_map::RandomState;
+use std::error;
+use std::fmt::{self, Write as _};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::program::Program;
+
+#[derive(Debug)]
+pub enum HostError {
+    Io(io::Error),
+
+    Status {
+        status: u16,
+        body: String,
+    },
+
+    InvalidResponse,
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostError::Io(err) => write!(f, "{}", err),
+            HostError::Status { status, body } => write!(f, "request failed with status {}: {}", status, body),
+            HostError::InvalidResponse => f.write_str("invalid response"),
+        }
+    }
+}
+
+impl error::Error for HostError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            HostError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HostError {
+    fn from(err: io::Error) -> Self {
+        HostError::Io(err)
+    }
+}
+
+/// A host managing a printer, accepting programs and commands.
+pub trait PrintHost {
+    /// Stores the program under the given file name, replacing any file of that name.
+    fn upload(&self, name: &str, program: &Program) -> Result<(), HostError>;
+
+    /// Starts printing the stored file of the given name.
+    fn start(&self, name: &str) -> Result<(), HostError>;
+
+    /// Executes a single line of G-code right away.
+    fn send(&self, command: &str) -> Result<(), HostError>;
+
+    /// Stores the program and starts printing it.
+    fn print(&self, name: &str, program: &Program) -> Result<(), HostError> {
+        self.upload(name, program)?;
+        return self.start(name);
+    }
+}
+
+/// The location of a host and how to authenticate with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+    /// The host name or address, with port
+    addr: String,
+
+    /// Header authenticating requests, if required
+    api_key: Option<String>,
+
+    /// Time to wait for connecting and for each read
+    timeout: Duration,
+}
+
+/// A request sent to an endpoint.
+struct Request<'a> {
+    method: &'a str,
+    path: String,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Endpoint {
+    fn new<S>(addr: S) -> Self
+        where S: Into<String> {
+        Self {
+            addr: addr.into(),
+            api_key: None,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Sends the request and returns the body of the response if successful.
+    fn request(&self, request: Request<'_>) -> Result<String, HostError> {
+        event!(debug, method = request.method, path = %request.path, "sending request");
+
+        let addr = self.addr.to_socket_addrs()?
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+
+        let mut head = format!("{} {} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n",
+                               request.method, request.path, self.addr, request.body.len());
+        if let Some(ref content_type) = request.content_type {
+            write!(head, "Content-Type: {}\r\n", content_type).expect("Write to string");
+        }
+        if let Some(ref api_key) = self.api_key {
+            write!(head, "X-Api-Key: {}\r\n", api_key).expect("Write to string");
+        }
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(&request.body)?;
+        stream.flush()?;
+
+        // Without keep-alive, the response ends with the connection
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let response = String::from_utf8_lossy(&response);
+
+        let (head, body) = match response.find("\r\n\r\n") {
+            Some(i) => (&response[..i], &response[i + 4..]),
+            None => (&response[..], ""),
+        };
+
+        let status: u16 = head.split_whitespace()
+                .nth(1)
+                .and_then(|status| status.parse().ok())
+                .ok_or(HostError::InvalidResponse)?;
+        if !(200..300).contains(&status) {
+            return Err(HostError::Status { status, body: body.to_owned() });
+        }
+
+        return Ok(body.to_owned());
+    }
+}
+
+/// A random boundary for a multipart form, which does not occur in any of its parts.
+fn boundary(parts: &[&str]) -> String {
+    loop {
+        // Each state is seeded differently, so hashing nothing yields a new random number
+        let boundary = format!("----gcode-upload-{:016x}", RandomState::new().build_hasher().finish());
+        if !parts.iter().any(|part| part.contains(&boundary)) {
+            return boundary;
+        }
+    }
+}
+
+/// A multipart form uploading the program as file, with additional fields.
+fn form(name: &str, program: &Program, fields: &[(&str, &str)]) -> (String, Vec<u8>) {
+    let name = name.replace('"', "");
+    let program = program.to_string();
+
+    let mut parts = vec![name.as_str(), program.as_str()];
+    parts.extend(fields.iter().map(|(_, value)| *value));
+    let boundary = boundary(&parts);
+
+    let mut body = Vec::new();
+    for (field, value) in fields {
+        write!(body, "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, field, value)
+                .expect("Write to vector");
+    }
+    write!(body, "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+           boundary, name).expect("Write to vector");
+    write!(body, "{}\r\n--{}--\r\n", program, boundary).expect("Write to vector");
+
+    return (format!("multipart/form-data; boundary={}", boundary), body);
+}
+
+/// Percent-encodes the text for use in a URL.
+fn encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for b in text.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(b as char),
+            b => write!(encoded, "%{:02X}", b).expect("Write to string"),
+        }
+    }
+
+    return encoded;
+}
+
+/// Quotes the text as JSON string.
+fn json(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).expect("Write to string"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    return quoted;
+}
+
+/// A client for the API of Moonraker, the host of Klipper.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Moonraker {
+    endpoint: Endpoint,
+}
+
+impl Moonraker {
+    /// Creates a client for the host at the given address, like `printer.local:7125`.
+    pub fn new<S>(addr: S) -> Self
+        where S: Into<String> {
+        Self {
+            endpoint: Endpoint::new(addr),
+        }
+    }
+
+    /// Authenticates using the API key, if the host requires one.
+    pub fn with_api_key<S>(mut self, api_key: S) -> Self
+        where S: Into<String> {
+        self.endpoint.api_key = Some(api_key.into());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.endpoint.timeout = timeout;
+        self
+    }
+}
+
+impl PrintHost for Moonraker {
+    fn upload(&self, name: &str, program: &Program) -> Result<(), HostError> {
+        let (content_type, body) = form(name, program, &[("root", "gcodes")]);
+        self.endpoint.request(Request {
+            method: "POST",
+            path: "/server/files/upload".to_owned(),
+            content_type: Some(content_type),
+            body,
+        })?;
+
+        return Ok(());
+    }
+
+    fn start(&self, name: &str) -> Result<(), HostError> {
+        self.endpoint.request(Request {
+            method: "POST",
+            path: format!("/printer/print/start?filename={}", encode(name)),
+            content_type: None,
+            body: Vec::new(),
+        })?;
+
+        return Ok(());
+    }
+
+    fn send(&self, command: &str) -> Result<(), HostError> {
+        self.endpoint.request(Request {
+            method: "POST",
+            path: format!("/printer/gcode/script?script={}", encode(command)),
+            content_type: None,
+            body: Vec::new(),
+        })?;
+
+        return Ok(());
+    }
+}
+
+/// A client for the API of OctoPrint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoPrint {
+    endpoint: Endpoint,
+}
+
+impl OctoPrint {
+    /// Creates a client for the host at the given address, like `octopi.local:80`, using the API
+    /// key OctoPrint requires.
+    pub fn new<S, K>(addr: S, api_key: K) -> Self
+        where S: Into<String>,
+              K: Into<String> {
+        let mut endpoint = Endpoint::new(addr);
+        endpoint.api_key = Some(api_key.into());
+
+        Self {
+            endpoint,
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.endpoint.timeout = timeout;
+        self
+    }
+}
+
+impl PrintHost for OctoPrint {
+    fn upload(&self, name: &str, program: &Program) -> Result<(), HostError> {
+        let (content_type, body) = form(name, program, &[]);
+        self.endpoint.request(Request {
+            method: "POST",
+            path: "/api/files/local".to_owned(),
+            content_type: Some(content_type),
+            body,
+        })?;
+
+        return Ok(());
+    }
+
+    fn start(&self, name: &str) -> Result<(), HostError> {
+        self.endpoint.request(Request {
+            method: "POST",
+            path: format!("/api/files/local/{}", encode(name)),
+            content_type: Some("application/json".to_owned()),
+            body: b"{\"command\":\"select\",\"print\":true}".to_vec(),
+        })?;
+
+        return Ok(());
+    }
+
+    fn send(&self, command: &str) -> Result<(), HostError> {
+        self.endpoint.request(Request {
+            method: "POST",
+            path: "/api/printer/command".to_owned(),
+            content_type: Some("application/json".to_owned()),
+            body: format!("{{\"command\":{}}}", json(command)).into_bytes(),
+        })?;
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::parser::Parser;
+
+    /// Serves the given responses, one per connection, returning the requests received.
+    fn serve(responses: &'static [&'static str]) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+
+                // Read the head and as much of the body as announced
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                loop {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length: usize = text.lines()
+                                .find_map(|line| line.strip_prefix("Content-Length: "))
+                                .unwrap()
+                                .parse()
+                                .unwrap();
+                        if request.len() >= end + 4 + length {
+                            requests.push(text);
+                            break;
+                        }
+                    }
+                }
+
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+
+            return requests;
+        });
+
+        return (addr, server);
+    }
+
+    #[test]
+    fn test_form() {
+        // A program quoting the boundary of another upload, e.g. by a comment
+        let program: Program = Parser::new().parse_all("G28".lines()).unwrap().into_iter().collect();
+        let (content_type, _) = form("part.gcode", &program, &[]);
+        let quoted = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+
+        let program: Program = Parser::new().parse_all(format!("G28 ; {}", quoted).lines()).unwrap().into_iter().collect();
+        let (content_type, body) = form("part.gcode", &program, &[("root", "gcodes")]);
+        let boundary = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+        assert_ne!(boundary, quoted);
+
+        let body = String::from_utf8(body).unwrap();
+        assert_eq!(body.matches(boundary).count(), 3);
+        assert!(body.ends_with(&format!("G28 ; {}\n\r\n--{}--\r\n", quoted, boundary)));
+    }
+
+    #[test]
+    fn test_moonraker() {
+        let (addr, server) = serve(&["HTTP/1.0 201 Created\r\n\r\n{}", "HTTP/1.0 200 OK\r\n\r\n{}", "HTTP/1.1 200 OK\r\n\r\n{}"]);
+        let program: Program = Parser::new().parse_all("G28\nG1 X10 F600\n".lines()).unwrap().into_iter().collect();
+
+        let host = Moonraker::new(addr.clone());
+        host.print("part 1.gcode", &program).unwrap();
+        host.send("G28 X").unwrap();
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with(&format!("POST /server/files/upload HTTP/1.0\r\nHost: {}\r\n", addr)));
+        assert!(requests[0].contains("name=\"file\"; filename=\"part 1.gcode\"\r\nContent-Type: application/octet-stream\r\n\r\nG28\nG1 X10 F600\n\r\n"));
+        assert!(requests[1].starts_with("POST /printer/print/start?filename=part%201.gcode HTTP/1.0\r\n"));
+        assert!(requests[2].starts_with("POST /printer/gcode/script?script=G28%20X HTTP/1.0\r\n"));
+    }
+
+    #[test]
+    fn test_octoprint() {
+        let (addr, server) = serve(&["HTTP/1.0 204 No Content\r\n\r\n", "HTTP/1.0 409 Conflict\r\n\r\nPrinter is not operational"]);
+
+        let host = OctoPrint::new(addr, "SECRET");
+        host.send("M117 \"Hi\"").unwrap();
+        match host.start("part.gcode") {
+            Err(HostError::Status { status: 409, body }) => assert_eq!(body, "Printer is not operational"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /api/printer/command HTTP/1.0\r\n"));
+        assert!(requests[0].contains("X-Api-Key: SECRET\r\n"));
+        assert!(requests[0].ends_with("\r\n\r\n{\"command\":\"M117 \\\"Hi\\\"\"}"));
+        assert!(requests[1].ends_with("{\"command\":\"select\",\"print\":true}"));
+    }
+}
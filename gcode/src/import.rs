@@ -0,0 +1,113 @@
+//! Importers turning foreign file formats into geometry for the generators.
+
+use std::error;
+use std::fmt;
+
+use crate::Real;
+
+pub mod excellon;
+pub mod gerber;
+pub mod stl;
+
+#[derive(Debug)]
+pub enum ImportError {
+    InvalidCommand {
+        line: usize,
+        command: String,
+    },
+
+    InvalidNumber {
+        line: usize,
+        text: String,
+    },
+
+    UndefinedAperture {
+        line: usize,
+        aperture: u32,
+    },
+
+    UndefinedTool {
+        line: usize,
+        tool: u32,
+    },
+
+    UnexpectedEnd,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::InvalidCommand { line, command } => write!(f, "{}: invalid command: {}", line, command),
+            ImportError::InvalidNumber { line, text } => write!(f, "{}: invalid number: {}", line, text),
+            ImportError::UndefinedAperture { line, aperture } => write!(f, "{}: undefined aperture: D{}", line, aperture),
+            ImportError::UndefinedTool { line, tool } => write!(f, "{}: undefined tool: T{}", line, tool),
+            ImportError::UnexpectedEnd => f.write_str("unexpected end of input"),
+        }
+    }
+}
+
+impl error::Error for ImportError {}
+
+/// How coordinates without a decimal point are to be interpreted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct NumberFormat {
+    integer: usize,
+    decimal: usize,
+
+    /// Whether leading zeros are omitted (and trailing zeros are always present)
+    omit_leading: bool,
+}
+
+impl NumberFormat {
+    fn parse(&self, text: &str, line: usize) -> Result<Real, ImportError> {
+        let invalid = || ImportError::InvalidNumber {
+            line,
+            text: text.to_owned(),
+        };
+
+        if text.contains('.') {
+            return text.parse().map_err(|_| invalid());
+        }
+
+        let (sign, digits) = match text.chars().next() {
+            Some('-') => (-1.0, &text[1..]),
+            Some('+') => (1.0, &text[1..]),
+            _ => (1.0, text),
+        };
+
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let value: Real = if self.omit_leading {
+            digits.parse().map_err(|_| invalid())?
+        } else {
+            // Trailing zeros are omitted - pad the number to its full width
+            let width = self.integer + self.decimal;
+            let padded = format!("{:0<width$}", digits, width = width);
+            padded.parse().map_err(|_| invalid())?
+        };
+
+        return Ok(sign * value / Real::powi(10.0, self.decimal as i32));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_number_format() {
+        let leading = NumberFormat { integer: 2, decimal: 4, omit_leading: true };
+        assert_eq!(leading.parse("12345", 1).unwrap(), 1.2345);
+        assert_eq!(leading.parse("-500", 1).unwrap(), -0.05);
+        assert_eq!(leading.parse("1.5", 1).unwrap(), 1.5);
+
+        let trailing = NumberFormat { integer: 2, decimal: 4, omit_leading: false };
+        assert_eq!(trailing.parse("0123", 1).unwrap(), 1.23);
+        assert_eq!(trailing.parse("+1", 1).unwrap(), 10.0);
+
+        assert!(leading.parse("1x", 1).is_err());
+        assert!(leading.parse("-", 1).is_err());
+    }
+}
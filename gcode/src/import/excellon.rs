@@ -0,0 +1,216 @@
+//! Import of Excellon drill files.
+//!
+//! Supported are tool definitions in the header and drill hits in the body. Routed slots are
+//! ignored.
+
+use crate::Real;
+use crate::geometry::{Point, Units};
+
+use super::{ImportError, NumberFormat};
+
+/// A drill tool with all holes drilled by it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tool {
+    number: u32,
+    diameter: Real,
+    holes: Vec<Point>,
+}
+
+impl Tool {
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn diameter(&self) -> Real {
+        self.diameter
+    }
+
+    pub fn holes(&self) -> &[Point] {
+        &self.holes
+    }
+}
+
+/// A parsed Excellon drill file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Excellon {
+    units: Units,
+    tools: Vec<Tool>,
+}
+
+impl Excellon {
+    pub fn parse(input: &str) -> Result<Self, ImportError> {
+        let mut units = Units::Inches;
+        let mut format = NumberFormat { integer: 2, decimal: 4, omit_leading: true };
+        let mut tools: Vec<Tool> = Vec::new();
+
+        let mut header = false;
+        let mut tool = None;
+        let mut position = Point::new(0.0, 0.0);
+
+        for (index, line) in input.lines().enumerate() {
+            let line_number = index + 1;
+
+            let line = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            }.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || ImportError::InvalidCommand {
+                line: line_number,
+                command: line.to_owned(),
+            };
+
+            if line == "M48" {
+                header = true;
+                continue;
+            }
+
+            if line == "%" || line == "M95" {
+                header = false;
+                continue;
+            }
+
+            if line.starts_with("METRIC") || line.starts_with("INCH") {
+                let metric = line.starts_with("METRIC");
+                units = if metric { Units::Millimeters } else { Units::Inches };
+
+                // LZ keeps leading zeros, so trailing zeros are omitted
+                format = NumberFormat {
+                    integer: if metric { 3 } else { 2 },
+                    decimal: if metric { 3 } else { 4 },
+                    omit_leading: !line.contains("LZ"),
+                };
+                continue;
+            }
+
+            if let Some(definition) = line.strip_prefix('T') {
+                let digits = definition.find(|c: char| !c.is_ascii_digit()).map_or(definition, |end| &definition[..end]);
+                let number = digits.parse::<u32>().map_err(|_| invalid())?;
+
+                if let Some(start) = line.find('C').map(|c| c + 1) {
+                    let end = line[start..].find(|c: char| c.is_ascii_alphabetic())
+                            .map_or(line.len(), |end| start + end);
+                    let text = &line[start..end];
+                    let diameter = text.parse::<Real>().map_err(|_| ImportError::InvalidNumber {
+                        line: line_number,
+                        text: text.to_owned(),
+                    })?;
+
+                    tools.retain(|tool| tool.number != number);
+                    tools.push(Tool {
+                        number,
+                        diameter,
+                        holes: Vec::new(),
+                    });
+                }
+
+                if !header {
+                    // T0 unloads the tool
+                    tool = if number == 0 { None } else { Some(number) };
+                }
+                continue;
+            }
+
+            if header {
+                continue;
+            }
+
+            if line.starts_with('X') || line.starts_with('Y') {
+                let x = coordinate(line, 'X').map(|text| format.parse(text, line_number)).transpose()?;
+                let y = coordinate(line, 'Y').map(|text| format.parse(text, line_number)).transpose()?;
+                position = Point::new(x.unwrap_or(position.x), y.unwrap_or(position.y));
+
+                let number = tool.ok_or_else(invalid)?;
+                let tool = tools.iter_mut()
+                        .find(|tool| tool.number == number)
+                        .ok_or(ImportError::UndefinedTool { line: line_number, tool: number })?;
+                tool.holes.push(position);
+                continue;
+            }
+
+            // Other commands like G05 (drill mode), G90 or M30 are irrelevant for drilling
+        }
+
+        return Ok(Self {
+            units,
+            tools,
+        });
+    }
+
+    pub fn units(&self) -> Units {
+        self.units
+    }
+
+    /// All tools in order of their definition.
+    pub fn tools(&self) -> &[Tool] {
+        &self.tools
+    }
+}
+
+/// Extracts the text of a coordinate from a line like `X1.5Y-2`.
+fn coordinate(line: &str, axis: char) -> Option<&str> {
+    let start = line.find(axis)? + 1;
+    let end = line[start..].find(|c: char| c.is_ascii_alphabetic()).map_or(line.len(), |end| start + end);
+
+    return Some(&line[start..end]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "M48
+; DRILL file
+METRIC,TZ
+T1C0.800
+T2C1.000
+%
+G90
+G05
+T1
+X10.0Y5.0
+X12.5
+T2
+X0Y-1.5
+T0
+M30
+";
+
+    #[test]
+    fn test_parse() {
+        let excellon = Excellon::parse(EXAMPLE).unwrap();
+
+        assert_eq!(excellon.units(), Units::Millimeters);
+        assert_eq!(excellon.tools(), &[
+            Tool {
+                number: 1,
+                diameter: 0.8,
+                holes: vec![Point::new(10.0, 5.0), Point::new(12.5, 5.0)],
+            },
+            Tool {
+                number: 2,
+                diameter: 1.0,
+                holes: vec![Point::new(0.0, -1.5)],
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_implied_decimals() {
+        let excellon = Excellon::parse("M48\nINCH,LZ\nT01C0.035\n%\nT01\nX01Y0025\n").unwrap();
+
+        assert_eq!(excellon.units(), Units::Inches);
+        assert_eq!(excellon.tools()[0].holes(), &[Point::new(1.0, 0.25)]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Excellon::parse("M48\nT1C0.8\n%\nX1Y1\n").is_err());
+        assert!(Excellon::parse("M48\nT1C0.8\n%\nT3\nX1Y1\n").is_err());
+        assert!(Excellon::parse("M48\nT1Cabc\n%\n").is_err());
+    }
+}
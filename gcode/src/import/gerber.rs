@@ -0,0 +1,641 @@
+//! Import of Gerber (RS-274X) copper and outline layers.
+//!
+//! Supported are apertures of the standard templates, linear and circular interpolation, flashes
+//! and regions. Aperture macros, step and repeat, and polarity changes are ignored.
+
+use std::collections::HashMap;
+
+use crate::Real;
+use crate::real::consts::PI;
+use crate::geometry::{Contour, Point, Units};
+
+use super::{ImportError, NumberFormat};
+
+/// Number of polygon vertices used to approximate full circles.
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Aperture {
+    Circle {
+        diameter: Real,
+    },
+
+    Rectangle {
+        width: Real,
+        height: Real,
+    },
+
+    Obround {
+        width: Real,
+        height: Real,
+    },
+
+    Polygon {
+        diameter: Real,
+        vertices: usize,
+    },
+}
+
+impl Aperture {
+    /// The width of a line drawn with this aperture.
+    fn width(&self) -> Real {
+        match *self {
+            Aperture::Circle { diameter } => diameter,
+            Aperture::Rectangle { width, height } => width.min(height),
+            Aperture::Obround { width, height } => width.min(height),
+            Aperture::Polygon { diameter, .. } => diameter,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Feature {
+    /// A line drawn along a path with an aperture.
+    Stroke {
+        path: Vec<Point>,
+        aperture: Aperture,
+    },
+
+    /// An aperture flashed at a single position.
+    Flash {
+        position: Point,
+        aperture: Aperture,
+    },
+
+    /// A filled area.
+    Region {
+        contour: Contour,
+    },
+}
+
+impl Feature {
+    /// The outline of the feature grown by `clearance`.
+    pub fn outline(&self, clearance: Real) -> Contour {
+        return match *self {
+            Feature::Stroke { ref path, aperture } => {
+                stroke(path, aperture.width() / 2.0 + clearance)
+            }
+
+            Feature::Flash { position, aperture: Aperture::Circle { diameter } } |
+            Feature::Flash { position, aperture: Aperture::Polygon { diameter, .. } } => {
+                Contour::circle(position, diameter / 2.0 + clearance, CIRCLE_SEGMENTS)
+            }
+
+            Feature::Flash { position, aperture: Aperture::Rectangle { width, height } } => {
+                Contour::rectangle(position, width + 2.0 * clearance, height + 2.0 * clearance)
+            }
+
+            Feature::Flash { position, aperture: Aperture::Obround { width, height } } => {
+                let length = (width - height).max(0.0) / 2.0;
+                let along = (height - width).max(0.0) / 2.0;
+                let path = vec![Point::new(position.x - length, position.y - along),
+                                Point::new(position.x + length, position.y + along)];
+                stroke(&path, width.min(height) / 2.0 + clearance)
+            }
+
+            Feature::Region { ref contour } => {
+                contour.offset(clearance).unwrap_or_else(|| contour.clone())
+            }
+        };
+    }
+}
+
+/// A parsed Gerber layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gerber {
+    units: Units,
+    features: Vec<Feature>,
+}
+
+impl Gerber {
+    pub fn parse(input: &str) -> Result<Self, ImportError> {
+        let mut parser = GerberParser::new();
+
+        for (line, command, extended) in commands(input) {
+            if extended {
+                parser.extended(line, &command)?;
+            } else {
+                parser.command(line, &command)?;
+            }
+        }
+
+        parser.finish_stroke();
+
+        return Ok(Self {
+            units: parser.units,
+            features: parser.features,
+        });
+    }
+
+    pub fn units(&self) -> Units {
+        self.units
+    }
+
+    pub fn features(&self) -> &[Feature] {
+        &self.features
+    }
+
+    /// The outlines of all features grown by `clearance`.
+    ///
+    /// Every feature is outlined on its own - overlapping features are not merged.
+    pub fn outlines(&self, clearance: Real) -> Vec<Contour> {
+        self.features.iter()
+                .map(|feature| feature.outline(clearance))
+                .collect()
+    }
+}
+
+/// Splits the input into commands, each with its line number and whether it is an extended one.
+fn commands(input: &str) -> Vec<(usize, String, bool)> {
+    let mut commands = Vec::new();
+
+    let mut line = 1;
+    let mut start = 1;
+    let mut extended = false;
+    let mut current = String::new();
+
+    for c in input.chars() {
+        match c {
+            '\n' => line += 1,
+            '\r' | ' ' | '\t' => {}
+            '%' => {
+                extended = !extended;
+                current.clear();
+            }
+            '*' => {
+                if !current.is_empty() {
+                    commands.push((start, current.clone(), extended));
+                }
+                current.clear();
+            }
+            c => {
+                if current.is_empty() {
+                    start = line;
+                }
+                current.push(c);
+            }
+        }
+    }
+
+    return commands;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Interpolation {
+    Linear,
+    Clockwise,
+    CounterClockwise,
+}
+
+struct GerberParser {
+    units: Units,
+    format: NumberFormat,
+    apertures: HashMap<u32, Aperture>,
+    features: Vec<Feature>,
+
+    aperture: Option<u32>,
+    interpolation: Interpolation,
+    multi_quadrant: bool,
+    position: Point,
+
+    /// The stroke or region contour currently being drawn
+    path: Vec<Point>,
+    region: bool,
+}
+
+impl GerberParser {
+    fn new() -> Self {
+        Self {
+            units: Units::Millimeters,
+            format: NumberFormat { integer: 3, decimal: 6, omit_leading: true },
+            apertures: HashMap::new(),
+            features: Vec::new(),
+            aperture: None,
+            interpolation: Interpolation::Linear,
+            multi_quadrant: false,
+            position: Point::new(0.0, 0.0),
+            path: Vec::new(),
+            region: false,
+        }
+    }
+
+    fn extended(&mut self, line: usize, command: &str) -> Result<(), ImportError> {
+        let invalid = || ImportError::InvalidCommand {
+            line,
+            command: command.to_owned(),
+        };
+
+        if command.starts_with("FS") {
+            // Format specification like FSLAX24Y24
+            let spec = command.as_bytes();
+            if spec.len() < 10 {
+                return Err(invalid());
+            }
+
+            let digit = |i: usize| (spec[i] as char).to_digit(10).map(|d| d as usize).ok_or_else(invalid);
+            self.format = NumberFormat {
+                integer: digit(5)?,
+                decimal: digit(6)?,
+                omit_leading: spec[2] != b'T',
+            };
+        } else if command == "MOMM" {
+            self.units = Units::Millimeters;
+        } else if command == "MOIN" {
+            self.units = Units::Inches;
+        } else if let Some(definition) = command.strip_prefix("ADD") {
+            // Aperture definition like ADD10C,0.2 or ADD11R,1.2X0.8
+            let split = definition.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+            let number = definition[..split].parse::<u32>().map_err(|_| invalid())?;
+
+            let (template, modifiers) = match definition[split..].find(',') {
+                Some(comma) => (&definition[split..split + comma], &definition[split + comma + 1..]),
+                None => (&definition[split..], ""),
+            };
+
+            let modifiers = modifiers.split('X')
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.parse::<Real>().map_err(|_| ImportError::InvalidNumber { line, text: m.to_owned() }))
+                    .collect::<Result<Vec<_>, _>>()?;
+            let modifier = |i: usize| modifiers.get(i).cloned().ok_or_else(invalid);
+
+            let aperture = match template {
+                "C" => Aperture::Circle { diameter: modifier(0)? },
+                "R" => Aperture::Rectangle { width: modifier(0)?, height: modifier(1)? },
+                "O" => Aperture::Obround { width: modifier(0)?, height: modifier(1)? },
+                "P" => Aperture::Polygon { diameter: modifier(0)?, vertices: modifier(1)? as usize },
+
+                // Macro apertures are approximated by a point
+                _ => Aperture::Circle { diameter: 0.0 },
+            };
+
+            self.apertures.insert(number, aperture);
+        }
+
+        return Ok(());
+    }
+
+    fn command(&mut self, line: usize, command: &str) -> Result<(), ImportError> {
+        if command.starts_with("G04") || command.starts_with("G4") {
+            return Ok(());
+        }
+
+        let mut x = None;
+        let mut y = None;
+        let mut i = 0.0;
+        let mut j = 0.0;
+
+        for (letter, value) in words(command) {
+            let number = |value: &str| value.parse::<u32>().map_err(|_| ImportError::InvalidNumber {
+                line,
+                text: value.to_owned(),
+            });
+
+            match letter {
+                'G' => match number(value)? {
+                    1 => self.interpolation = Interpolation::Linear,
+                    2 => self.interpolation = Interpolation::Clockwise,
+                    3 => self.interpolation = Interpolation::CounterClockwise,
+                    36 => {
+                        self.finish_stroke();
+                        self.region = true;
+                    }
+                    37 => {
+                        self.finish_region();
+                        self.region = false;
+                    }
+                    70 => self.units = Units::Inches,
+                    71 => self.units = Units::Millimeters,
+                    74 => self.multi_quadrant = false,
+                    75 => self.multi_quadrant = true,
+                    _ => {}
+                },
+
+                'X' => x = Some(self.format.parse(value, line)?),
+                'Y' => y = Some(self.format.parse(value, line)?),
+                'I' => i = self.format.parse(value, line)?,
+                'J' => j = self.format.parse(value, line)?,
+
+                'D' => {
+                    let target = Point::new(x.unwrap_or(self.position.x), y.unwrap_or(self.position.y));
+                    match number(value)? {
+                        1 => self.draw(target, i, j),
+                        2 => self.move_to(target),
+                        3 => self.flash(line, target)?,
+                        aperture => {
+                            if !self.apertures.contains_key(&aperture) {
+                                return Err(ImportError::UndefinedAperture { line, aperture });
+                            }
+
+                            self.finish_stroke();
+                            self.aperture = Some(aperture);
+                        }
+                    }
+                }
+
+                'M' => {
+                    self.finish_stroke();
+                }
+
+                _ => {
+                    return Err(ImportError::InvalidCommand {
+                        line,
+                        command: command.to_owned(),
+                    });
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn draw(&mut self, target: Point, i: Real, j: Real) {
+        if self.path.is_empty() {
+            self.path.push(self.position);
+        }
+
+        if self.interpolation != Interpolation::Linear {
+            let clockwise = self.interpolation == Interpolation::Clockwise;
+            let center = self.center(target, i, j);
+            let points = arc(self.position, target, center, clockwise);
+            self.path.extend(points);
+        }
+
+        self.path.push(target);
+        self.position = target;
+    }
+
+    /// Determines the center of an arc, guessing the signs of the offsets in single quadrant mode.
+    fn center(&self, target: Point, i: Real, j: Real) -> Point {
+        if self.multi_quadrant {
+            return Point::new(self.position.x + i, self.position.y + j);
+        }
+
+        let candidates = [(i, j), (-i, j), (i, -j), (-i, -j)];
+        let error = |&(i, j): &(Real, Real)| {
+            let center = Point::new(self.position.x + i, self.position.y + j);
+            (center.distance(self.position) - center.distance(target)).abs()
+        };
+
+        let (i, j) = candidates.iter()
+                .min_by(|a, b| error(a).partial_cmp(&error(b)).expect("NaN in arc"))
+                .cloned()
+                .expect("No candidates");
+
+        return Point::new(self.position.x + i, self.position.y + j);
+    }
+
+    fn move_to(&mut self, target: Point) {
+        if self.region {
+            self.finish_region();
+        } else {
+            self.finish_stroke();
+        }
+
+        self.position = target;
+    }
+
+    fn flash(&mut self, line: usize, target: Point) -> Result<(), ImportError> {
+        self.finish_stroke();
+
+        let aperture = self.current_aperture(line)?;
+        self.features.push(Feature::Flash {
+            position: target,
+            aperture,
+        });
+
+        self.position = target;
+
+        return Ok(());
+    }
+
+    fn current_aperture(&self, line: usize) -> Result<Aperture, ImportError> {
+        let number = self.aperture.unwrap_or(0);
+        return self.apertures.get(&number)
+                .cloned()
+                .ok_or(ImportError::UndefinedAperture { line, aperture: number });
+    }
+
+    fn finish_stroke(&mut self) {
+        if self.region || self.path.is_empty() {
+            return;
+        }
+
+        let path = std::mem::take(&mut self.path);
+        let aperture = self.aperture
+                .and_then(|number| self.apertures.get(&number))
+                .cloned()
+                .unwrap_or(Aperture::Circle { diameter: 0.0 });
+
+        self.features.push(Feature::Stroke {
+            path,
+            aperture,
+        });
+    }
+
+    fn finish_region(&mut self) {
+        let mut points = std::mem::take(&mut self.path);
+
+        // Regions are closed explicitly by repeating the first point
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+
+        if points.len() > 2 {
+            self.features.push(Feature::Region {
+                contour: Contour::closed(points),
+            });
+        }
+    }
+}
+
+/// Splits a command into its letters and their (textual) values.
+fn words(command: &str) -> Vec<(char, &str)> {
+    let mut words = Vec::new();
+
+    let mut rest = command;
+    while let Some(letter) = rest.chars().next() {
+        let value = &rest[letter.len_utf8()..];
+        let end = value.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(value.len());
+
+        words.push((letter, &value[..end]));
+        rest = &value[end..];
+    }
+
+    return words;
+}
+
+/// Approximates the inner points of an arc from `from` to `to` around `center`.
+fn arc(from: Point, to: Point, center: Point, clockwise: bool) -> Vec<Point> {
+    let radius = center.distance(from);
+    let start = (from.y - center.y).atan2(from.x - center.x);
+    let end = (to.y - center.y).atan2(to.x - center.x);
+
+    let mut sweep = end - start;
+    if clockwise && sweep >= 0.0 {
+        sweep -= 2.0 * PI;
+    }
+    if !clockwise && sweep <= 0.0 {
+        sweep += 2.0 * PI;
+    }
+
+    let segments = ((sweep.abs() / (2.0 * PI)) * CIRCLE_SEGMENTS as Real).ceil().max(1.0) as usize;
+
+    return (1..segments)
+            .map(|k| {
+                let angle = start + sweep * k as Real / segments as Real;
+                Point::new(center.x + radius * angle.cos(), center.y + radius * angle.sin())
+            })
+            .collect();
+}
+
+/// Outlines a path drawn with a round tool of the given radius.
+fn stroke(path: &[Point], radius: Real) -> Contour {
+    let mut points = path.to_vec();
+    points.dedup();
+
+    if points.len() < 2 {
+        let center = points.first().cloned().unwrap_or(Point::new(0.0, 0.0));
+        return Contour::circle(center, radius, CIRCLE_SEGMENTS);
+    }
+
+    let n = points.len();
+    let direction = |a: Point, b: Point| {
+        let length = a.distance(b);
+        Point::new((b.x - a.x) / length, (b.y - a.y) / length)
+    };
+
+    // The offset of the left-hand side at every vertex, using mitered joins
+    let offsets = (0..n)
+            .map(|i| {
+                let incoming = if i > 0 { Some(direction(points[i - 1], points[i])) } else { None };
+                let outgoing = if i + 1 < n { Some(direction(points[i], points[i + 1])) } else { None };
+
+                let (u, v) = match (incoming, outgoing) {
+                    (Some(u), Some(v)) => (u, v),
+                    (Some(u), None) => (u, u),
+                    (None, Some(v)) => (v, v),
+                    (None, None) => unreachable!(),
+                };
+
+                let normal = Point::new(-(u.y + v.y), u.x + v.x);
+                let scale = 1.0 + u.x * v.x + u.y * v.y;
+                if scale < 1e-6 {
+                    return Point::new(-u.y * radius, u.x * radius);
+                }
+
+                // Limit miters at sharp corners to twice the radius
+                let factor = (radius / scale).min(2.0 * radius / normal.x.hypot(normal.y));
+                Point::new(normal.x * factor, normal.y * factor)
+            })
+            .collect::<Vec<_>>();
+
+    let cap = |center: Point, from: Point| {
+        let start = from.y.atan2(from.x);
+        (1..CIRCLE_SEGMENTS / 2)
+                .map(|k| {
+                    let angle = start + PI * k as Real / (CIRCLE_SEGMENTS / 2) as Real;
+                    Point::new(center.x + radius * angle.cos(), center.y + radius * angle.sin())
+                })
+                .collect::<Vec<_>>()
+    };
+
+    let mut outline = Vec::new();
+
+    // Right side forward, end cap, left side backward and start cap - counter-clockwise
+    for i in 0..n {
+        outline.push(Point::new(points[i].x - offsets[i].x, points[i].y - offsets[i].y));
+    }
+    outline.extend(cap(points[n - 1], Point::new(-offsets[n - 1].x, -offsets[n - 1].y)));
+    for i in (0..n).rev() {
+        outline.push(Point::new(points[i].x + offsets[i].x, points[i].y + offsets[i].y));
+    }
+    outline.extend(cap(points[0], offsets[0]));
+
+    return Contour::closed(outline);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "G04 Example*
+%FSLAX26Y26*%
+%MOMM*%
+%ADD10C,0.2*%
+%ADD11R,1.0X0.5*%
+D10*
+X0Y0D02*
+X10000000Y0D01*
+X10000000Y5000000D01*
+D11*
+X20000000Y0D03*
+G36*
+X0Y10000000D02*
+X5000000Y10000000D01*
+X5000000Y15000000D01*
+X0Y10000000D01*
+G37*
+M02*
+";
+
+    #[test]
+    fn test_parse() {
+        let gerber = Gerber::parse(EXAMPLE).unwrap();
+
+        assert_eq!(gerber.units(), Units::Millimeters);
+        assert_eq!(gerber.features(), &[
+            Feature::Stroke {
+                path: vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0), Point::new(10.0, 5.0)],
+                aperture: Aperture::Circle { diameter: 0.2 },
+            },
+            Feature::Flash {
+                position: Point::new(20.0, 0.0),
+                aperture: Aperture::Rectangle { width: 1.0, height: 0.5 },
+            },
+            Feature::Region {
+                contour: Contour::closed(vec![Point::new(0.0, 10.0), Point::new(5.0, 10.0), Point::new(5.0, 15.0)]),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Gerber::parse("%FSLAX26Y26*%D12*").is_err());
+        assert!(Gerber::parse("%FSLAX26Y26*%X1Q2D02*").is_err());
+    }
+
+    #[test]
+    fn test_arc() {
+        let gerber = Gerber::parse("%FSLAX26Y26*%%ADD10C,0.1*%D10*G75*X1000000Y0D02*G03X-1000000Y0I-1000000J0D01*").unwrap();
+
+        match gerber.features() {
+            [Feature::Stroke { path, .. }] => {
+                assert_eq!(path.len(), CIRCLE_SEGMENTS / 2 + 1);
+                assert!(path.iter().all(|p| (p.distance(Point::new(0.0, 0.0)) - 1.0).abs() < 1e-9));
+                assert!(path.iter().all(|p| p.y >= -1e-9));
+            }
+            features => panic!("unexpected features: {:?}", features),
+        }
+    }
+
+    #[test]
+    fn test_outline() {
+        let stroke = Feature::Stroke {
+            path: vec![Point::new(0.0, 0.0), Point::new(10.0, 0.0)],
+            aperture: Aperture::Circle { diameter: 2.0 },
+        };
+
+        let outline = stroke.outline(0.5);
+        assert!(outline.area() > 0.0);
+        assert!(outline.points().iter().all(|p| p.x >= -1.5 - 1e-9 && p.x <= 11.5 + 1e-9));
+        assert!(outline.points().iter().all(|p| p.y.abs() <= 1.5 + 1e-9));
+        assert!(outline.points().contains(&Point::new(0.0, -1.5)));
+        assert!(outline.points().contains(&Point::new(10.0, 1.5)));
+
+        let flash = Feature::Flash {
+            position: Point::new(0.0, 0.0),
+            aperture: Aperture::Rectangle { width: 2.0, height: 1.0 },
+        };
+        assert_eq!(flash.outline(0.5).area(), 6.0);
+    }
+}
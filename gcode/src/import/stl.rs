@@ -0,0 +1,322 @@
+//! Import of STL meshes and slicing them into planar contours.
+//!
+//! The slicer only extracts outlines - there is no infill or support generation. The resulting
+//! contours can be fed into the generators, e.g. `DepthStepping` for tracing a single slice.
+
+use std::collections::HashMap;
+
+use crate::Real;
+use crate::geometry::{Contour, Point, Point3};
+
+use super::ImportError;
+
+/// A triangle mesh read from an STL file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mesh {
+    triangles: Vec<[Point3; 3]>,
+}
+
+impl Mesh {
+    pub fn new(triangles: Vec<[Point3; 3]>) -> Self {
+        Self {
+            triangles,
+        }
+    }
+
+    /// Parses a binary or ASCII STL file.
+    pub fn parse(input: &[u8]) -> Result<Self, ImportError> {
+        // ASCII files may start with "solid" as well, so the size is checked to detect binary ones
+        if input.len() >= 84 {
+            let count = u32::from_le_bytes([input[80], input[81], input[82], input[83]]) as usize;
+            if input.len() == 84 + count * 50 {
+                return Self::parse_binary(input);
+            }
+        }
+
+        if input.starts_with(b"solid") {
+            let text = std::str::from_utf8(input).map_err(|_| ImportError::InvalidCommand {
+                line: 1,
+                command: "non UTF-8 input".to_owned(),
+            })?;
+            return Self::parse_ascii(text);
+        }
+
+        return Self::parse_binary(input);
+    }
+
+    fn parse_binary(input: &[u8]) -> Result<Self, ImportError> {
+        if input.len() < 84 {
+            return Err(ImportError::UnexpectedEnd);
+        }
+
+        let count = u32::from_le_bytes([input[80], input[81], input[82], input[83]]) as usize;
+        if input.len() < 84 + count * 50 {
+            return Err(ImportError::UnexpectedEnd);
+        }
+
+        let float = |offset: usize| f32::from_le_bytes([input[offset], input[offset + 1], input[offset + 2], input[offset + 3]]) as Real;
+        let vertex = |offset: usize| Point3::new(float(offset), float(offset + 4), float(offset + 8));
+
+        let triangles = (0..count)
+                .map(|i| {
+                    // Skip the normal, which is recalculated from the vertex order anyway
+                    let offset = 84 + i * 50 + 12;
+                    [vertex(offset), vertex(offset + 12), vertex(offset + 24)]
+                })
+                .collect();
+
+        return Ok(Self::new(triangles));
+    }
+
+    fn parse_ascii(input: &str) -> Result<Self, ImportError> {
+        let mut triangles = Vec::new();
+        let mut vertices = Vec::with_capacity(3);
+
+        for (index, line) in input.lines().enumerate() {
+            let mut parts = line.split_whitespace();
+
+            match parts.next() {
+                Some("vertex") => {
+                    let coordinates = parts
+                            .map(|part| part.parse::<Real>().map_err(|_| ImportError::InvalidNumber {
+                                line: index + 1,
+                                text: part.to_owned(),
+                            }))
+                            .collect::<Result<Vec<_>, _>>()?;
+
+                    if coordinates.len() != 3 {
+                        return Err(ImportError::InvalidCommand {
+                            line: index + 1,
+                            command: line.trim().to_owned(),
+                        });
+                    }
+
+                    vertices.push(Point3::new(coordinates[0], coordinates[1], coordinates[2]));
+                }
+
+                Some("endfacet") => {
+                    if vertices.len() != 3 {
+                        return Err(ImportError::InvalidCommand {
+                            line: index + 1,
+                            command: line.trim().to_owned(),
+                        });
+                    }
+
+                    triangles.push([vertices[0], vertices[1], vertices[2]]);
+                    vertices.clear();
+                }
+
+                _ => {}
+            }
+        }
+
+        return Ok(Self::new(triangles));
+    }
+
+    pub fn triangles(&self) -> &[[Point3; 3]] {
+        &self.triangles
+    }
+
+    /// The lowest and highest Z coordinate of all vertices.
+    pub fn z_range(&self) -> Option<(Real, Real)> {
+        self.triangles.iter()
+                .flat_map(|triangle| triangle.iter())
+                .fold(None, |range, vertex| match range {
+                    None => Some((vertex.z, vertex.z)),
+                    Some((min, max)) => Some((vertex.z.min(min), vertex.z.max(max))),
+                })
+    }
+
+    /// Cuts the mesh with the horizontal plane at the given height.
+    ///
+    /// Outer contours are returned counter-clockwise and holes clockwise, assuming the triangles
+    /// of the mesh are oriented with their normals pointing outwards.
+    pub fn slice(&self, z: Real) -> Vec<Contour> {
+        let segments = self.triangles.iter()
+                .filter_map(|triangle| intersect(triangle, z))
+                .collect::<Vec<_>>();
+
+        return chain(segments);
+    }
+
+    /// Slices the mesh in layers of the given height, starting half a layer above its bottom.
+    pub fn layers(&self, height: Real) -> Vec<(Real, Vec<Contour>)> {
+        let (bottom, top) = match self.z_range() {
+            Some(range) if height > 0.0 => range,
+            _ => return Vec::new(),
+        };
+
+        let mut layers = Vec::new();
+
+        let mut z = bottom + height / 2.0;
+        while z < top {
+            layers.push((z, self.slice(z)));
+            z += height;
+        }
+
+        return layers;
+    }
+}
+
+/// Intersects a triangle with a horizontal plane, returning the segment oriented such that the
+/// solid lies on its left.
+fn intersect(triangle: &[Point3; 3], z: Real) -> Option<(Point, Point)> {
+    // Vertices on the plane count as above it to avoid degenerate intersections
+    let above = |vertex: &Point3| vertex.z >= z;
+
+    let mut points = Vec::with_capacity(2);
+    for i in 0..3 {
+        let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+        if above(&a) != above(&b) {
+            // Calculate the intersection from a canonical order so that neighbouring triangles
+            // sharing this edge produce bitwise identical points
+            let (low, high) = if a.z < b.z { (a, b) } else { (b, a) };
+            let t = (z - low.z) / (high.z - low.z);
+            points.push(Point::new(low.x + (high.x - low.x) * t, low.y + (high.y - low.y) * t));
+        }
+    }
+
+    if points.len() != 2 {
+        return None;
+    }
+
+    // The face normal projected onto the plane points away from the solid
+    let (a, b, c) = (triangle[0], triangle[1], triangle[2]);
+    let nx = (b.y - a.y) * (c.z - a.z) - (b.z - a.z) * (c.y - a.y);
+    let ny = (b.z - a.z) * (c.x - a.x) - (b.x - a.x) * (c.z - a.z);
+
+    let (p, q) = (points[0], points[1]);
+    return if (q.x - p.x) * -ny + (q.y - p.y) * nx >= 0.0 {
+        Some((p, q))
+    } else {
+        Some((q, p))
+    };
+}
+
+/// Joins segments sharing their end points into contours.
+fn chain(segments: Vec<(Point, Point)>) -> Vec<Contour> {
+    let key = |p: Point| (p.x.to_bits(), p.y.to_bits());
+
+    let mut starts: HashMap<_, Vec<usize>> = HashMap::new();
+    for (i, (start, _)) in segments.iter().enumerate() {
+        starts.entry(key(*start)).or_default().push(i);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut contours = Vec::new();
+
+    for first in 0..segments.len() {
+        if used[first] {
+            continue;
+        }
+
+        let mut points = Vec::new();
+        let mut closed = false;
+
+        let mut current = first;
+        loop {
+            used[current] = true;
+
+            let (start, end) = segments[current];
+            points.push(start);
+
+            if key(end) == key(segments[first].0) {
+                closed = true;
+                break;
+            }
+
+            let next = starts.get(&key(end))
+                    .and_then(|candidates| candidates.iter().find(|&&i| !used[i]));
+            match next {
+                Some(&next) => current = next,
+                None => {
+                    points.push(end);
+                    break;
+                }
+            }
+        }
+
+        contours.push(if closed {
+            Contour::closed(points)
+        } else {
+            Contour::open(points)
+        });
+    }
+
+    return contours;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit cube made of outward facing triangles.
+    fn cube() -> Mesh {
+        let v = |x: Real, y: Real, z: Real| Point3::new(x, y, z);
+        let quad = |a: Point3, b: Point3, c: Point3, d: Point3| vec![[a, b, c], [a, c, d]];
+
+        let mut triangles = Vec::new();
+        triangles.extend(quad(v(0., 0., 0.), v(0., 1., 0.), v(1., 1., 0.), v(1., 0., 0.)));
+        triangles.extend(quad(v(0., 0., 1.), v(1., 0., 1.), v(1., 1., 1.), v(0., 1., 1.)));
+        triangles.extend(quad(v(0., 0., 0.), v(1., 0., 0.), v(1., 0., 1.), v(0., 0., 1.)));
+        triangles.extend(quad(v(1., 0., 0.), v(1., 1., 0.), v(1., 1., 1.), v(1., 0., 1.)));
+        triangles.extend(quad(v(1., 1., 0.), v(0., 1., 0.), v(0., 1., 1.), v(1., 1., 1.)));
+        triangles.extend(quad(v(0., 1., 0.), v(0., 0., 0.), v(0., 0., 1.), v(0., 1., 1.)));
+
+        return Mesh::new(triangles);
+    }
+
+    #[test]
+    fn test_slice() {
+        let contours = cube().slice(0.5);
+
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].is_closed());
+        assert_eq!(contours[0].area(), 1.0);
+        assert_eq!(contours[0].length(), 4.0);
+    }
+
+    #[test]
+    fn test_slice_outside() {
+        assert!(cube().slice(2.0).is_empty());
+    }
+
+    #[test]
+    fn test_layers() {
+        let layers = cube().layers(0.25);
+
+        assert_eq!(layers.iter().map(|(z, _)| *z).collect::<Vec<_>>(), vec![0.125, 0.375, 0.625, 0.875]);
+        assert!(layers.iter().all(|(_, contours)| contours.len() == 1));
+    }
+
+    #[test]
+    fn test_parse_ascii() {
+        let mesh = Mesh::parse(b"solid test
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid test
+").unwrap();
+
+        assert_eq!(mesh.triangles(), &[[Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)]]);
+    }
+
+    #[test]
+    fn test_parse_binary() {
+        let mut input = vec![0u8; 80];
+        input.extend(&1u32.to_le_bytes());
+        for value in &[0f32, 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 0.] {
+            input.extend(&value.to_le_bytes());
+        }
+        input.extend(&[0, 0]);
+
+        let mesh = Mesh::parse(&input).unwrap();
+        assert_eq!(mesh.triangles(), &[[Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0), Point3::new(0.0, 1.0, 0.0)]]);
+
+        assert!(Mesh::parse(&input[..100]).is_err());
+    }
+}
@@ -0,0 +1,1538 @@
+//! Interpreter tracking the modal state of a machine while executing blocks.
+//!
+//! Positions are kept in machine coordinates and millimeters, independent of the units and
+//! coordinate system selected by the program.
+
+use std::error;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::Real;
+use crate::command::{Arc, Axes, Command, DistanceMode, FeedRateMode, LatheMode, LengthOffset, Move, Plane, Predefined, Probing, Side, WorkOffsetMode};
+use crate::control::Label;
+use crate::diagnostic::Code;
+use crate::geometry::{AxisValues, Point3, Units};
+use crate::dialect::Dialect;
+use crate::modal::SemanticError;
+use crate::params::{ParamError, Parameter, Parameters};
+use crate::parser::{Block, Provenance, Word};
+use crate::tools::ToolTable;
+use crate::toolpath::{ArcCenter, ArcError, ArcSegment, Segments};
+
+/// The motion mode continued by blocks containing axis words only.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Motion {
+    Rapid,
+    Linear,
+    ClockwiseArc,
+    CounterClockwiseArc,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Spindle {
+    Off,
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Debug)]
+pub enum InterpError {
+    Semantic(SemanticError),
+
+    UnsupportedWord {
+        mnemonic: char,
+        value: Real,
+    },
+
+    Arc(ArcError),
+
+    Parameter(ParamError),
+
+    /// O-words must be executed by unrolling the program first (see `control::Flow`).
+    ControlFlow(Label),
+
+    /// Canned cycles must be lowered to plain moves first (see `transform::ExpandCycles`).
+    CannedCycle(u32),
+
+    NoMotionMode,
+
+    MissingInverseTimeFeed,
+
+    UnknownCoordinateSystem(u8),
+
+    UnknownTool(u32),
+
+    ProbeNotTripped,
+
+    /// An error raised while executing a block in a pass, annotated with the block's origin.
+    Located {
+        provenance: Provenance,
+        error: Box<InterpError>,
+    },
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpError::Semantic(err) => write!(f, "{}", err),
+            InterpError::UnsupportedWord { mnemonic, value } => write!(f, "unsupported word: {}{}", mnemonic, value),
+            InterpError::Arc(err) => write!(f, "{}", err),
+            InterpError::Parameter(err) => write!(f, "{}", err),
+            InterpError::ControlFlow(label) => write!(f, "unexpected control flow: {}", label),
+            InterpError::CannedCycle(code) => write!(f, "unexpanded canned cycle: G{}", code),
+            InterpError::NoMotionMode => f.write_str("axis words without active motion mode"),
+            InterpError::MissingInverseTimeFeed => f.write_str("feed move without feed rate in inverse time mode"),
+            InterpError::UnknownCoordinateSystem(system) => write!(f, "unknown coordinate system: {}", system),
+            InterpError::UnknownTool(tool) => write!(f, "unknown tool: T{}", tool),
+            InterpError::ProbeNotTripped => f.write_str("probing move completed without tripping the probe"),
+            InterpError::Located { provenance, error } => write!(f, "{}: {}", provenance, error),
+        }
+    }
+}
+
+impl error::Error for InterpError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            InterpError::Semantic(err) => Some(err),
+            InterpError::Arc(err) => Some(err),
+            InterpError::Parameter(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl InterpError {
+    /// The stable code identifying the kind of error (see `diagnostic::CODES`), which is the one
+    /// of the underlying error for wrapped errors and errors located at a block.
+    pub fn code(&self) -> Code {
+        match self {
+            InterpError::Semantic(err) => err.code(),
+            InterpError::UnsupportedWord { .. } => Code::error(201),
+            InterpError::Arc(err) => err.code(),
+            InterpError::Parameter(err) => err.code(),
+            InterpError::ControlFlow(_) => Code::error(202),
+            InterpError::CannedCycle(_) => Code::error(203),
+            InterpError::NoMotionMode => Code::error(204),
+            InterpError::MissingInverseTimeFeed => Code::error(205),
+            InterpError::UnknownCoordinateSystem(_) => Code::error(206),
+            InterpError::UnknownTool(_) => Code::error(207),
+            InterpError::ProbeNotTripped => Code::error(208),
+            InterpError::Located { error, .. } => error.code(),
+        }
+    }
+}
+
+impl From<SemanticError> for InterpError {
+    fn from(err: SemanticError) -> Self {
+        InterpError::Semantic(err)
+    }
+}
+
+impl From<ArcError> for InterpError {
+    fn from(err: ArcError) -> Self {
+        InterpError::Arc(err)
+    }
+}
+
+impl From<ParamError> for InterpError {
+    fn from(err: ParamError) -> Self {
+        InterpError::Parameter(err)
+    }
+}
+
+impl From<Word> for InterpError {
+    fn from(word: Word) -> Self {
+        InterpError::UnsupportedWord {
+            mnemonic: word.mnemonic(),
+            value: word.value(),
+        }
+    }
+}
+
+/// The first of the parameters receiving the position of the last probing move, in work
+/// coordinates (#5061 to #5063), followed by whether the probe tripped (#5070).
+pub const PROBE_PARAMETERS: u32 = 5061;
+
+/// The number of selectable coordinate systems (G54 to G59 and G59.1 to G59.3).
+pub const COORDINATE_SYSTEMS: usize = 9;
+
+/// A single difference between two states, carrying the value of the state compared to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateChange {
+    Position(Point3),
+    Axes(AxisValues<Real>),
+    Units(Units),
+    DistanceMode(DistanceMode),
+    Plane(Plane),
+    Motion(Option<Motion>),
+    Compensation(Option<Side>),
+    FeedRate(Option<Real>),
+    FeedRateMode(FeedRateMode),
+    Spindle(Spindle),
+    SpindleSpeed(Option<Real>),
+    SurfaceSpeed {
+        speed: Option<Real>,
+        limit: Option<Real>,
+    },
+    LatheMode(LatheMode),
+    Mist(bool),
+    Flood(bool),
+    Tool(Option<u32>),
+    SelectedTool(Option<u32>),
+    ToolLengthOffset(Real),
+    CoordinateSystem(u8),
+    WorkOffset {
+        system: u8,
+        origin: Point3,
+    },
+    PositionOffset(Point3),
+    PositionOffsetEnabled(bool),
+    PredefinedPosition(Predefined, Point3),
+    Probe(Option<ProbeResult>),
+}
+
+/// The outcome of a probing move.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProbeResult {
+    /// The position the move stopped at in machine coordinates, which is its end if the probe did
+    /// not trip.
+    pub position: Point3,
+
+    pub tripped: bool,
+}
+
+/// A snapshot of the modal state of the machine.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct State {
+    /// The position in machine coordinates, always in millimeters.
+    pub position: Point3,
+
+    /// The positions of the axes besides X, Y and Z, in degrees for rotary axes and millimeters
+    /// otherwise. Coordinate systems and offsets only apply to X, Y and Z.
+    pub axes: AxisValues<Real>,
+
+    pub units: Units,
+    pub distance_mode: DistanceMode,
+    pub plane: Plane,
+    pub motion: Option<Motion>,
+
+    /// The side of the path cutter compensation offsets the tool to, if active. Positions are
+    /// always the programmed ones, without the compensation (see `transform::CompensateCutter`).
+    pub compensation: Option<Side>,
+
+    /// The feed rate in millimeters per minute, in millimeters per revolution or as inverse time,
+    /// depending on the feed rate mode (see `feed_per_minute`).
+    pub feed_rate: Option<Real>,
+
+    pub feed_rate_mode: FeedRateMode,
+
+    pub spindle: Spindle,
+    pub spindle_speed: Option<Real>,
+
+    /// The surface speed kept constant by G96, in meters per minute.
+    pub surface_speed: Option<Real>,
+
+    /// The maximum spindle speed while keeping the surface speed constant.
+    pub spindle_speed_limit: Option<Real>,
+
+    /// Whether X words give diameters or radii, for lathes. Positions are always radii.
+    pub lathe_mode: LatheMode,
+
+    pub mist: bool,
+    pub flood: bool,
+
+    /// The tool currently loaded into the spindle.
+    pub tool: Option<u32>,
+
+    /// The tool selected for the next tool change.
+    pub selected_tool: Option<u32>,
+
+    /// The tool length offset applied to the Z axis by G43, in millimeters.
+    pub tool_length_offset: Real,
+
+    /// The active coordinate system, numbered from 1 (G54) to 9 (G59.3).
+    pub coordinate_system: u8,
+
+    /// The origins of all coordinate systems in machine coordinates.
+    pub work_offsets: [Point3; COORDINATE_SYSTEMS],
+
+    /// The additional offset set by G92.
+    pub position_offset: Point3,
+
+    /// Whether the G92 offset is applied, which G92.2 suspends and G92.3 restores.
+    pub position_offset_enabled: bool,
+
+    /// The position G28 moves to in machine coordinates, stored by G28.1.
+    pub home_position: Point3,
+
+    /// The position G30 moves to in machine coordinates, stored by G30.1.
+    pub secondary_position: Point3,
+
+    /// The outcome of the last probing move.
+    pub probe: Option<ProbeResult>,
+}
+
+impl State {
+    /// The origin of the active work coordinate system, including the G92 offset and the tool
+    /// length offset.
+    pub fn origin(&self) -> Point3 {
+        let work = self.work_offsets[usize::from(self.coordinate_system) - 1];
+        let offset = self.active_position_offset();
+
+        return Point3::new(work.x + offset.x,
+                           work.y + offset.y,
+                           work.z + offset.z + self.tool_length_offset);
+    }
+
+    /// The stored position moved to by G28 or G30.
+    pub fn predefined(&self, position: Predefined) -> Point3 {
+        match position {
+            Predefined::Home => self.home_position,
+            Predefined::Secondary => self.secondary_position,
+        }
+    }
+
+    /// The G92 offset, if applied, or zero otherwise.
+    pub fn active_position_offset(&self) -> Point3 {
+        if self.position_offset_enabled {
+            return self.position_offset;
+        }
+
+        return Point3::new(0.0, 0.0, 0.0);
+    }
+
+    /// Lists all differences from this state to the other one, in the order of the fields.
+    pub fn diff(&self, other: &State) -> Vec<StateChange> {
+        let mut changes = Vec::new();
+
+        if self.position != other.position {
+            changes.push(StateChange::Position(other.position));
+        }
+        if self.axes != other.axes {
+            changes.push(StateChange::Axes(other.axes));
+        }
+        if self.units != other.units {
+            changes.push(StateChange::Units(other.units));
+        }
+        if self.distance_mode != other.distance_mode {
+            changes.push(StateChange::DistanceMode(other.distance_mode));
+        }
+        if self.plane != other.plane {
+            changes.push(StateChange::Plane(other.plane));
+        }
+        if self.motion != other.motion {
+            changes.push(StateChange::Motion(other.motion));
+        }
+        if self.compensation != other.compensation {
+            changes.push(StateChange::Compensation(other.compensation));
+        }
+        if self.feed_rate != other.feed_rate {
+            changes.push(StateChange::FeedRate(other.feed_rate));
+        }
+        if self.feed_rate_mode != other.feed_rate_mode {
+            changes.push(StateChange::FeedRateMode(other.feed_rate_mode));
+        }
+        if self.spindle != other.spindle {
+            changes.push(StateChange::Spindle(other.spindle));
+        }
+        if self.spindle_speed != other.spindle_speed {
+            changes.push(StateChange::SpindleSpeed(other.spindle_speed));
+        }
+        if self.surface_speed != other.surface_speed || self.spindle_speed_limit != other.spindle_speed_limit {
+            changes.push(StateChange::SurfaceSpeed {
+                speed: other.surface_speed,
+                limit: other.spindle_speed_limit,
+            });
+        }
+        if self.lathe_mode != other.lathe_mode {
+            changes.push(StateChange::LatheMode(other.lathe_mode));
+        }
+        if self.mist != other.mist {
+            changes.push(StateChange::Mist(other.mist));
+        }
+        if self.flood != other.flood {
+            changes.push(StateChange::Flood(other.flood));
+        }
+        if self.tool != other.tool {
+            changes.push(StateChange::Tool(other.tool));
+        }
+        if self.selected_tool != other.selected_tool {
+            changes.push(StateChange::SelectedTool(other.selected_tool));
+        }
+        if self.tool_length_offset != other.tool_length_offset {
+            changes.push(StateChange::ToolLengthOffset(other.tool_length_offset));
+        }
+        if self.coordinate_system != other.coordinate_system {
+            changes.push(StateChange::CoordinateSystem(other.coordinate_system));
+        }
+        for (i, (current, origin)) in self.work_offsets.iter().zip(other.work_offsets.iter()).enumerate() {
+            if current != origin {
+                changes.push(StateChange::WorkOffset {
+                    system: i as u8 + 1,
+                    origin: *origin,
+                });
+            }
+        }
+        if self.position_offset != other.position_offset {
+            changes.push(StateChange::PositionOffset(other.position_offset));
+        }
+        if self.position_offset_enabled != other.position_offset_enabled {
+            changes.push(StateChange::PositionOffsetEnabled(other.position_offset_enabled));
+        }
+        if self.home_position != other.home_position {
+            changes.push(StateChange::PredefinedPosition(Predefined::Home, other.home_position));
+        }
+        if self.secondary_position != other.secondary_position {
+            changes.push(StateChange::PredefinedPosition(Predefined::Secondary, other.secondary_position));
+        }
+        if self.probe != other.probe {
+            changes.push(StateChange::Probe(other.probe));
+        }
+
+        return changes;
+    }
+
+    /// The position in machine coordinates and the units of the program.
+    pub fn machine_position(&self) -> Point3 {
+        let scale = self.units.millimeters();
+        return Point3::new(self.position.x / scale, self.position.y / scale, self.position.z / scale);
+    }
+
+    /// The feed rate of a move of the given length in millimeters per minute, if known.
+    pub fn feed_per_minute(&self, length: Real) -> Option<Real> {
+        let feed_rate = self.feed_rate?;
+
+        return match self.feed_rate_mode {
+            FeedRateMode::InverseTime => Some(feed_rate * length),
+            FeedRateMode::UnitsPerMinute => Some(feed_rate),
+            FeedRateMode::UnitsPerRevolution => self.spindle_rpm().map(|rpm| feed_rate * rpm),
+        };
+    }
+
+    /// Sets the feed rate given in the current units and feed rate mode.
+    fn set_feed_rate(&mut self, f: Real) {
+        self.feed_rate = Some(match self.feed_rate_mode {
+            FeedRateMode::InverseTime => f,
+            FeedRateMode::UnitsPerMinute | FeedRateMode::UnitsPerRevolution => f * self.units.millimeters(),
+        });
+    }
+
+    /// The speed the spindle turns at in revolutions per minute, if known.
+    ///
+    /// While keeping the surface speed constant, this depends on the distance of the tool from
+    /// the X origin of the work coordinate system, which is the axis of the spindle on lathes.
+    pub fn spindle_rpm(&self) -> Option<Real> {
+        let speed = match self.surface_speed {
+            Some(speed) => speed,
+            None => return self.spindle_speed,
+        };
+
+        let radius = (self.position.x - self.origin().x).abs();
+        let rpm = speed * 1000.0 / (2.0 * crate::real::consts::PI * radius);
+
+        return match self.spindle_speed_limit {
+            Some(limit) => Some(rpm.min(limit)),
+            None if rpm.is_finite() => Some(rpm),
+            None => None,
+        };
+    }
+
+    /// The position in the active work coordinate system and units.
+    pub fn work_position(&self) -> Point3 {
+        self.to_work(self.position)
+    }
+
+    /// Converts a point in machine coordinates to the active work coordinate system and units.
+    pub fn to_work(&self, point: Point3) -> Point3 {
+        let origin = self.origin();
+        let scale = self.units.millimeters();
+
+        return Point3::new((point.x - origin.x) / scale * self.diameter_factor(),
+                           (point.y - origin.y) / scale,
+                           (point.z - origin.z) / scale);
+    }
+
+    /// Validates an arc move starting at the current position, given in the current units and
+    /// distance mode.
+    pub fn arc(&self, clockwise: bool, arc: &Arc) -> Result<ArcSegment, InterpError> {
+        let scale = self.units.millimeters();
+
+        let offsets = [('I', arc.i), ('J', arc.j), ('K', arc.k)];
+        let (first, second) = match self.plane {
+            Plane::XY => ('I', 'J'),
+            Plane::ZX => ('K', 'I'),
+            Plane::YZ => ('J', 'K'),
+        };
+
+        if let Some(&(axis, _)) = offsets.iter().find(|&&(axis, value)| value.is_some() && axis != first && axis != second) {
+            return Err(ArcError::OffPlaneOffset { axis }.into());
+        }
+
+        let center = match (arc.r, arc.i.is_some() || arc.j.is_some() || arc.k.is_some()) {
+            (Some(_), true) => return Err(ArcError::AmbiguousCenter.into()),
+            (Some(r), false) => ArcCenter::Radius(r * scale),
+            (None, true) => ArcCenter::Offset(Point3::new(arc.i.unwrap_or(0.0) * scale,
+                                                          arc.j.unwrap_or(0.0) * scale,
+                                                          arc.k.unwrap_or(0.0) * scale)),
+            (None, false) => return Err(ArcError::MissingCenter.into()),
+        };
+
+        let turns = match arc.p {
+            Some(turns) if turns < 1.0 || turns.fract() != 0.0 || turns > u32::MAX as Real => {
+                return Err(ArcError::InvalidTurns { turns }.into());
+            }
+            Some(turns) => turns as u32,
+            None => 1,
+        };
+
+        let end = self.target(arc.x, arc.y, arc.z);
+
+        return Ok(ArcSegment::new(self.plane, self.position, end, clockwise, center)?.with_turns(turns));
+    }
+
+    /// Calculates the machine coordinates of a target given in program coordinates.
+    fn target(&self, x: Option<Real>, y: Option<Real>, z: Option<Real>) -> Point3 {
+        let origin = self.origin();
+        let scale = self.units.millimeters();
+
+        let resolve = |value: Option<Real>, position: Real, origin: Real| match (value, self.distance_mode) {
+            (Some(value), DistanceMode::Absolute) => origin + value * scale,
+            (Some(value), DistanceMode::Relative) => position + value * scale,
+            (None, _) => position,
+        };
+
+        return Point3::new(resolve(x.map(|x| x / self.diameter_factor()), self.position.x, origin.x),
+                           resolve(y, self.position.y, origin.y),
+                           resolve(z, self.position.z, origin.z));
+    }
+
+    /// Calculates the positions of the axes besides X, Y and Z for the given words.
+    fn target_axes(&self, axes: &AxisValues<Option<Real>>) -> AxisValues<Real> {
+        return axes.map(|axis, &value| {
+            let scale = if axis.is_rotary() { 1.0 } else { self.units.millimeters() };
+            match (value, self.distance_mode) {
+                (Some(value), DistanceMode::Absolute) => value * scale,
+                (Some(value), DistanceMode::Relative) => self.axes[axis] + value * scale,
+                (None, _) => self.axes[axis],
+            }
+        });
+    }
+
+    /// The ratio of X words to radii.
+    fn diameter_factor(&self) -> Real {
+        match self.lathe_mode {
+            LatheMode::Diameter => 2.0,
+            LatheMode::Radius => 1.0,
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let zero = Point3::new(0.0, 0.0, 0.0);
+
+        Self {
+            position: zero,
+            axes: AxisValues::default(),
+            units: Units::Millimeters,
+            distance_mode: DistanceMode::Absolute,
+            plane: Plane::XY,
+            motion: None,
+            compensation: None,
+            feed_rate: None,
+            feed_rate_mode: FeedRateMode::UnitsPerMinute,
+            spindle: Spindle::Off,
+            spindle_speed: None,
+            surface_speed: None,
+            spindle_speed_limit: None,
+            lathe_mode: LatheMode::Radius,
+            mist: false,
+            flood: false,
+            tool: None,
+            selected_tool: None,
+            tool_length_offset: 0.0,
+            coordinate_system: 1,
+            work_offsets: [zero; COORDINATE_SYSTEMS],
+            position_offset: zero,
+            position_offset_enabled: true,
+            home_position: zero,
+            secondary_position: zero,
+            probe: None,
+        }
+    }
+}
+
+/// Executes blocks one after another, updating its state accordingly.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Machine {
+    state: State,
+
+    parameters: Parameters,
+
+    /// The tools looked up by tool length offsets
+    tools: ToolTable,
+
+    /// The machine coordinates at which probing moves trip the probe
+    probe_contact: Axes,
+
+    /// Number of blocks executed so far
+    blocks: usize,
+
+    /// The controller whose M codes and modal groups apply
+    dialect: Dialect,
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_state(state: State) -> Self {
+        Self {
+            state,
+            parameters: Parameters::new(),
+            tools: ToolTable::new(),
+            probe_contact: Axes::default(),
+            blocks: 0,
+            dialect: Dialect::default(),
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Uses the lengths of the given tools for tool length offsets (G43).
+    pub fn with_tools(mut self, tools: ToolTable) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn tools(&self) -> &ToolTable {
+        &self.tools
+    }
+
+    /// Simulates a workpiece touching the probe at the given machine coordinates.
+    ///
+    /// Probing moves trip the probe where they first reach one of the coordinates, regardless of
+    /// the direction. Without any coordinates, the probe never trips.
+    pub fn with_probe_contact(mut self, contact: Axes) -> Self {
+        self.probe_contact = contact;
+        self
+    }
+
+    /// Rejects M codes unknown to the controller and checks modal groups as it does.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    pub fn into_state(self) -> State {
+        self.state
+    }
+
+    /// The values of the parameters assigned so far.
+    pub fn parameters(&self) -> &Parameters {
+        &self.parameters
+    }
+
+    /// The parameters, e.g. for setting values before executing a program.
+    pub fn parameters_mut(&mut self) -> &mut Parameters {
+        &mut self.parameters
+    }
+
+    /// The number of blocks passed to `execute` so far, including failed ones.
+    pub fn blocks(&self) -> usize {
+        self.blocks
+    }
+
+    /// Sets the origin of a coordinate system (numbered from 1 to 9) in machine coordinates.
+    pub fn set_work_offset(&mut self, system: u8, origin: Point3) -> Result<(), InterpError> {
+        if system < 1 || usize::from(system) > COORDINATE_SYSTEMS {
+            return Err(InterpError::UnknownCoordinateSystem(system));
+        }
+
+        self.state.work_offsets[usize::from(system) - 1] = origin;
+        return Ok(());
+    }
+
+    /// Executes a single block.
+    ///
+    /// The commands of the block are executed in the order defined by RS274/NGC, regardless of
+    /// the order of the words. If the block can not be executed, the state is left unchanged.
+    pub fn execute(&mut self, block: &Block) -> Result<(), InterpError> {
+        return self.apply(block).map(|_| ());
+    }
+
+    /// Executes a single block like `execute` and returns it rewritten to absolute machine
+    /// coordinates.
+    ///
+    /// All axis words of moves are resolved against the distance mode, the coordinate system, the
+    /// G92 offset and the tool length offset, keeping the units of the program. The words
+    /// selecting these modes are removed, except for G91 which is replaced by G90.
+    ///
+    /// The provenance of the block is kept and errors are annotated with it.
+    pub fn absolute(&mut self, block: &Block) -> Result<Block, InterpError> {
+        if block.is_deleted() {
+            self.blocks += 1;
+            return Ok(block.clone());
+        }
+
+        let commands = self.apply(block).map_err(|error| InterpError::Located {
+            provenance: block.provenance().clone(),
+            error: Box::new(error),
+        })?;
+
+        return Ok(Command::to_block(&commands)
+                .with_line_number(block.line_number())
+                .with_provenance(block.provenance().clone())
+                .with_pass("absolute"));
+    }
+
+    /// Executes the block and returns its commands in order of execution, rewritten to absolute
+    /// machine coordinates.
+    fn apply(&mut self, block: &Block) -> Result<Vec<Command>, InterpError> {
+        self.blocks += 1;
+        span!(DEBUG, "execute", block = self.blocks - 1, line = block.line());
+
+        if block.is_deleted() {
+            return Ok(Vec::new());
+        }
+
+        if let Some(control) = block.control() {
+            return Err(InterpError::ControlFlow(control.label.clone()));
+        }
+
+        // Parameters are only assigned if the whole block executes
+        let mut parameters = None;
+        let resolved;
+        let block = if block.is_resolved() {
+            block
+        } else {
+            let mut assigned = self.parameters.clone();
+            resolved = assigned.resolve(block)?;
+            parameters = Some(assigned);
+            &resolved
+        };
+
+        block.check_modal_groups_with(self.dialect)?;
+        if let Some(word) = block.words().iter().find(|word| !self.dialect.knows(word)) {
+            return Err((*word).into());
+        }
+
+        let mut commands = Command::from_block(block);
+        commands.sort_by_key(order);
+
+        let mut state = self.state.clone();
+        let mut rewritten = Vec::with_capacity(commands.len());
+        for command in commands {
+            rewritten.extend(absolute(&state, command));
+
+            let result = execute(&mut state, &self.tools, &self.probe_contact, command);
+
+            #[cfg(feature = "tracing")]
+            {
+                if let Err(ref err) = result {
+                    tracing::debug!(error = %err, "failed to execute block");
+                }
+            }
+
+            result?;
+        }
+
+        let probed = state.probe.filter(|_| rewritten.iter().any(|command| matches!(command, Command::Probe(..))));
+
+        self.state = state;
+        if let Some(parameters) = parameters {
+            self.parameters = parameters;
+        }
+
+        if let Some(result) = probed {
+            let work = self.state.to_work(result.position);
+            for (i, value) in [work.x, work.y, work.z].iter().enumerate() {
+                self.parameters.set(Parameter::Numbered(PROBE_PARAMETERS + i as u32), *value);
+            }
+            self.parameters.set(Parameter::Numbered(5070), if result.tripped { 1.0 } else { 0.0 });
+        }
+
+        return Ok(rewritten);
+    }
+
+    /// Turns an iterator over blocks into one over the blocks rewritten by `absolute`.
+    pub fn into_absolute<I>(self, blocks: I) -> Absolute<I::IntoIter>
+        where I: IntoIterator<Item=Block> {
+        Absolute {
+            machine: self,
+            blocks: blocks.into_iter(),
+        }
+    }
+
+    /// Turns an iterator over blocks into one over the segments the tool moves along.
+    ///
+    /// See `toolpath::Segments` for details.
+    pub fn into_segments<I>(self, blocks: I) -> Segments<I::IntoIter>
+        where I: IntoIterator<Item=Block> {
+        Segments::new(self, blocks.into_iter())
+    }
+}
+
+/// Iterator adapter rewriting all moves into absolute machine coordinates.
+///
+/// See `Machine::absolute` for details.
+pub struct Absolute<I> {
+    machine: Machine,
+    blocks: I,
+}
+
+impl<I> Absolute<I> {
+    /// The machine executing the blocks, e.g. for inspecting the state after the last block.
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+}
+
+impl<I> Iterator for Absolute<I>
+    where I: Iterator<Item=Block> {
+    type Item = Result<Block, InterpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block = self.blocks.next()?;
+        return Some(self.machine.absolute(&block));
+    }
+}
+
+/// The position of a command in the order of execution.
+fn order(command: &Command) -> u8 {
+    match *command {
+        Command::Unknown(_) => 0,
+        Command::SetFeedRateMode(_) => 1,
+        Command::SetFeedRate(_) => 2,
+        Command::SetSpindleSpeed(_) | Command::ConstantSurfaceSpeed { .. } | Command::ConstantSpindleSpeed => 3,
+        Command::SelectTool(_) => 4,
+        Command::ToolChange { .. } => 5,
+        Command::SpindleOn { .. } | Command::SpindleOff => 6,
+        Command::CoolantOn { .. } | Command::CoolantOff => 7,
+        Command::Dwell { .. } => 8,
+        Command::SelectPlane(_) => 9,
+        Command::SetUnits(_) => 10,
+        Command::CancelCompensation | Command::Compensation { .. } => 11,
+        Command::CancelToolLengthOffset | Command::ToolLengthOffset(_) => 12,
+        Command::SelectCoordinateSystem(_) => 13,
+        Command::SetDistanceMode(_) | Command::SetReturnMode(_) | Command::SetLatheMode(_) => 14,
+        Command::SetWorkOffset { .. } | Command::GoToPredefined(..) | Command::StorePredefined(_) | Command::SetPosition(_) | Command::ClearPositionOffset
+        | Command::SuspendPositionOffset | Command::RestorePositionOffset => 15,
+        Command::RapidMove(_) | Command::LinearMove(_) | Command::ClockwiseArc(_) | Command::CounterClockwiseArc(_)
+        | Command::ModalMove(_) | Command::SynchronizedMove { .. } | Command::Probe(..) | Command::CancelCycle
+        | Command::Cycle(..) => 16,
+        Command::Pause { .. } | Command::ProgramEnd { .. } => 17,
+    }
+}
+
+fn execute(state: &mut State, tools: &ToolTable, probe_contact: &Axes, command: Command) -> Result<(), InterpError> {
+    let scale = state.units.millimeters();
+
+    match command {
+        Command::Unknown(word) => return Err(word.into()),
+
+        Command::SetFeedRateMode(mode) => {
+            // Feed rates of different modes are not comparable
+            if mode != state.feed_rate_mode {
+                state.feed_rate = None;
+            }
+            state.feed_rate_mode = mode;
+        }
+        Command::SetFeedRate(f) => state.set_feed_rate(f),
+        Command::SetSpindleSpeed(s) => state.spindle_speed = Some(s),
+        Command::ConstantSurfaceSpeed { speed, limit } => {
+            // The surface speed is given in feet per minute while programming in inches
+            let meters = match state.units {
+                Units::Millimeters => 1.0,
+                Units::Inches => 0.3048,
+            };
+
+            state.surface_speed = speed.or(state.spindle_speed).map(|speed| speed * meters);
+            state.spindle_speed_limit = limit;
+        }
+        Command::ConstantSpindleSpeed => {
+            state.surface_speed = None;
+            state.spindle_speed_limit = None;
+        }
+
+        Command::SelectTool(tool) => state.selected_tool = Some(tool),
+        Command::ToolChange { tool } => {
+            if tool.is_some() {
+                state.selected_tool = tool;
+            }
+            state.tool = state.selected_tool;
+        }
+
+        Command::SpindleOn { clockwise, speed } => {
+            if speed.is_some() {
+                state.spindle_speed = speed;
+            }
+            state.spindle = if clockwise { Spindle::Clockwise } else { Spindle::CounterClockwise };
+        }
+        Command::SpindleOff => state.spindle = Spindle::Off,
+
+        Command::CoolantOn { mist: true } => state.mist = true,
+        Command::CoolantOn { mist: false } => state.flood = true,
+        Command::CoolantOff => {
+            state.mist = false;
+            state.flood = false;
+        }
+
+        Command::Dwell { .. } => {}
+
+        Command::SelectPlane(plane) => state.plane = plane,
+        Command::SetUnits(units) => state.units = units,
+        Command::CancelCompensation => state.compensation = None,
+        Command::Compensation { side, .. } => state.compensation = Some(side),
+        Command::CancelToolLengthOffset => state.tool_length_offset = 0.0,
+        Command::ToolLengthOffset(LengthOffset::Given(length)) => state.tool_length_offset = length * scale,
+        Command::ToolLengthOffset(LengthOffset::Tool(number)) => {
+            // An empty spindle has no length
+            state.tool_length_offset = match number.or(state.tool) {
+                Some(number) => tools.get(number).ok_or(InterpError::UnknownTool(number))?.length,
+                None => 0.0,
+            };
+        }
+
+        Command::SelectCoordinateSystem(system) => state.coordinate_system = system,
+        Command::SetDistanceMode(mode) => state.distance_mode = mode,
+        Command::SetReturnMode(_) => {}
+        Command::SetLatheMode(mode) => state.lathe_mode = mode,
+
+        Command::SetWorkOffset { system, mode, axes } => {
+            let system = if system == 0 { state.coordinate_system } else { system };
+            let index = usize::from(system) - 1;
+            if index >= COORDINATE_SYSTEMS {
+                return Err(InterpError::UnknownCoordinateSystem(system));
+            }
+
+            let current = state.work_offsets[index];
+            let resolve = |value: Option<Real>, current: Real, position: Real, offset: Real| match (value, mode) {
+                (Some(value), WorkOffsetMode::Origin) => value * scale,
+                (Some(value), WorkOffsetMode::Position) => position - offset - value * scale,
+                (None, _) => current,
+            };
+
+            // The G92 offset and tool length offset stay applied on top of the new origin
+            let offset = state.active_position_offset();
+            state.work_offsets[index] = Point3::new(
+                resolve(axes.x, current.x, state.position.x, offset.x),
+                resolve(axes.y, current.y, state.position.y, offset.y),
+                resolve(axes.z, current.z, state.position.z, offset.z + state.tool_length_offset));
+        }
+        Command::GoToPredefined(position, axes) => {
+            let target = state.predefined(position);
+            state.position = predefined(state, axes, target);
+        }
+        Command::StorePredefined(Predefined::Home) => state.home_position = state.position,
+        Command::StorePredefined(Predefined::Secondary) => state.secondary_position = state.position,
+        Command::SetPosition(axes) => {
+            let work = state.work_offsets[usize::from(state.coordinate_system) - 1];
+            let origin = Point3::new(work.x, work.y, work.z + state.tool_length_offset);
+
+            // Shift the offset such that the current position gets the given coordinates
+            let offset = |value: Option<Real>, position: Real, origin: Real, current: Real| match value {
+                Some(value) => position - origin - value * scale,
+                None => current,
+            };
+
+            // Axes not given keep the offset in effect, which is zero while suspended
+            let current = state.active_position_offset();
+            state.position_offset = Point3::new(offset(axes.x, state.position.x, origin.x, current.x),
+                                                offset(axes.y, state.position.y, origin.y, current.y),
+                                                offset(axes.z, state.position.z, origin.z, current.z));
+            state.position_offset_enabled = true;
+        }
+        Command::ClearPositionOffset => {
+            state.position_offset = Point3::new(0.0, 0.0, 0.0);
+            state.position_offset_enabled = true;
+        }
+        Command::SuspendPositionOffset => state.position_offset_enabled = false,
+        Command::RestorePositionOffset => state.position_offset_enabled = true,
+
+        Command::RapidMove(target) => motion(state, Motion::Rapid, &linear(target))?,
+        Command::LinearMove(target) => motion(state, Motion::Linear, &linear(target))?,
+        Command::ClockwiseArc(arc) => motion(state, Motion::ClockwiseArc, &arc)?,
+        Command::CounterClockwiseArc(arc) => motion(state, Motion::CounterClockwiseArc, &arc)?,
+        Command::ModalMove(arc) => {
+            let mode = state.motion.ok_or(InterpError::NoMotionMode)?;
+            motion(state, mode, &arc)?;
+        }
+
+        Command::SynchronizedMove { target, .. } => {
+            // Like probing moves, threading moves are not continued by axis words
+            state.position = state.target(target.x, target.y, target.z);
+            state.axes = state.target_axes(&target.axes);
+            state.motion = None;
+        }
+        Command::Probe(probing, target) => probe(state, probe_contact, probing, target)?,
+
+        Command::CancelCycle => state.motion = None,
+        Command::Cycle(kind, _) => return Err(InterpError::CannedCycle(kind.code())),
+
+        Command::Pause { .. } => {}
+        Command::ProgramEnd { .. } => {
+            // Ending a program resets some of the modes (see RS274/NGC, 3.6.1)
+            state.distance_mode = DistanceMode::Absolute;
+            state.plane = Plane::XY;
+            state.coordinate_system = 1;
+            state.spindle = Spindle::Off;
+            state.mist = false;
+            state.flood = false;
+            state.compensation = None;
+            state.motion = Some(Motion::Linear);
+            if state.feed_rate_mode != FeedRateMode::UnitsPerMinute {
+                state.feed_rate_mode = FeedRateMode::UnitsPerMinute;
+                state.feed_rate = None;
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/// Rewrites a command to absolute machine coordinates, given the state before executing it.
+fn absolute(state: &State, command: Command) -> Option<Command> {
+    let scale = state.units.millimeters();
+    let resolve = |x: Option<Real>, y: Option<Real>, z: Option<Real>| {
+        let target = state.target(x, y, z);
+        (x.map(|_| target.x / scale * state.diameter_factor()), y.map(|_| target.y / scale), z.map(|_| target.z / scale))
+    };
+    let resolve_axes = |axes: AxisValues<Option<Real>>| {
+        let target = state.target_axes(&axes);
+        axes.map(|axis, value| value.map(|_| if axis.is_rotary() { target[axis] } else { target[axis] / scale }))
+    };
+
+    return match command {
+        Command::RapidMove(mut target) | Command::LinearMove(mut target) => {
+            let (x, y, z) = resolve(target.x, target.y, target.z);
+            target.x = x;
+            target.y = y;
+            target.z = z;
+            target.axes = resolve_axes(target.axes);
+
+            Some(match command {
+                Command::RapidMove(_) => Command::RapidMove(target),
+                _ => Command::LinearMove(target),
+            })
+        }
+
+        Command::ClockwiseArc(mut arc) | Command::CounterClockwiseArc(mut arc) | Command::ModalMove(mut arc) => {
+            let (x, y, z) = resolve(arc.x, arc.y, arc.z);
+            arc.x = x;
+            arc.y = y;
+            arc.z = z;
+            arc.axes = resolve_axes(arc.axes);
+
+            Some(match command {
+                Command::ClockwiseArc(_) => Command::ClockwiseArc(arc),
+                Command::CounterClockwiseArc(_) => Command::CounterClockwiseArc(arc),
+                _ => Command::ModalMove(arc),
+            })
+        }
+
+        Command::Probe(probing, mut target) => {
+            let (x, y, z) = resolve(target.x, target.y, target.z);
+            target.x = x;
+            target.y = y;
+            target.z = z;
+            target.axes = resolve_axes(target.axes);
+
+            Some(Command::Probe(probing, target))
+        }
+
+        Command::SynchronizedMove { mut target, pitch } => {
+            let (x, y, z) = resolve(target.x, target.y, target.z);
+            target.x = x;
+            target.y = y;
+            target.z = z;
+            target.axes = resolve_axes(target.axes);
+
+            Some(Command::SynchronizedMove { target, pitch })
+        }
+
+        Command::GoToPredefined(position, axes) => {
+            let (x, y, z) = resolve(axes.x, axes.y, axes.z);
+            Some(Command::GoToPredefined(position, Axes { x, y, z }))
+        }
+
+        Command::SetDistanceMode(_) => Some(Command::SetDistanceMode(DistanceMode::Absolute)),
+        Command::SelectCoordinateSystem(_) | Command::SetWorkOffset { .. } | Command::SetPosition(_) => None,
+        Command::ClearPositionOffset | Command::SuspendPositionOffset | Command::RestorePositionOffset => None,
+        Command::ToolLengthOffset(_) | Command::CancelToolLengthOffset => None,
+
+        command => Some(command),
+    };
+}
+
+fn linear(target: Move) -> Arc {
+    Arc {
+        x: target.x,
+        y: target.y,
+        z: target.z,
+        axes: target.axes,
+        f: target.f,
+        ..Arc::default()
+    }
+}
+
+fn motion(state: &mut State, mode: Motion, arc: &Arc) -> Result<(), InterpError> {
+    match mode {
+        Motion::ClockwiseArc => { state.arc(true, arc)?; }
+        Motion::CounterClockwiseArc => { state.arc(false, arc)?; }
+        Motion::Rapid | Motion::Linear => {}
+    }
+
+    // Inverse time feed rates only apply to the move of the same block
+    if state.feed_rate_mode == FeedRateMode::InverseTime && mode != Motion::Rapid && arc.f.is_none() {
+        return Err(InterpError::MissingInverseTimeFeed);
+    }
+
+    if let Some(f) = arc.f {
+        state.set_feed_rate(f);
+    }
+
+    state.position = state.target(arc.x, arc.y, arc.z);
+    state.axes = state.target_axes(&arc.axes);
+    state.motion = Some(mode);
+
+    return Ok(());
+}
+
+/// Moves towards the target until the probe trips at one of the contact coordinates.
+///
+/// The motion mode is cleared afterwards, so probing moves are not continued by axis words.
+fn probe(state: &mut State, contact: &Axes, probing: Probing, target: Move) -> Result<(), InterpError> {
+    if let Some(f) = target.f {
+        state.set_feed_rate(f);
+    }
+
+    let from = state.position;
+    let to = state.target(target.x, target.y, target.z);
+
+    // The fraction of the move at which it first reaches one of the contact coordinates
+    let trip = [(contact.x, from.x, to.x), (contact.y, from.y, to.y), (contact.z, from.z, to.z)].iter()
+            .filter_map(|&(contact, from, to)| {
+                let contact = contact?;
+                if from == to || (contact - from).signum() == (contact - to).signum() && contact != to {
+                    return None;
+                }
+                Some((contact - from) / (to - from))
+            })
+            .fold(None, |first: Option<Real>, t| Some(first.map_or(t, |first| first.min(t))));
+
+    if trip.is_none() && probing.required {
+        return Err(InterpError::ProbeNotTripped);
+    }
+
+    let axes = state.target_axes(&target.axes);
+    let (position, axes) = match trip {
+        Some(t) => (Point3::new(from.x + (to.x - from.x) * t, from.y + (to.y - from.y) * t, from.z + (to.z - from.z) * t),
+                    axes.map(|axis, to| state.axes[axis] + (to - state.axes[axis]) * t)),
+        None => (to, axes),
+    };
+
+    state.position = position;
+    state.axes = axes;
+    state.probe = Some(ProbeResult { position, tripped: trip.is_some() });
+    state.motion = None;
+
+    return Ok(());
+}
+
+/// The position after moving the given axes to a stored position, passing the given intermediate
+/// point. If no axis is given, all axes move to the stored position.
+fn predefined(state: &State, axes: Axes, stored: Point3) -> Point3 {
+    if axes == Axes::default() {
+        return stored;
+    }
+
+    let intermediate = state.target(axes.x, axes.y, axes.z);
+
+    let select = |value: Option<Real>, stored: Real, position: Real| if value.is_some() { stored } else { position };
+    return Point3::new(select(axes.x, stored.x, intermediate.x),
+                       select(axes.y, stored.y, intermediate.y),
+                       select(axes.z, stored.z, intermediate.z));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::Parameter;
+    use crate::parser::Parser;
+    use crate::tools::Tool;
+
+    fn run(lines: &[&str]) -> Machine {
+        let mut machine = Machine::new();
+        for block in Parser::new().parse_all(lines.iter()).unwrap() {
+            machine.execute(&block).unwrap();
+        }
+
+        return machine;
+    }
+
+    #[test]
+    fn test_moves() {
+        let machine = run(&["G0 X10 Y5", "G1 Z-1 F100", "G91", "X1 Y1", "G2 X1 Y-1 I0 J-1"]);
+
+        assert_eq!(machine.state().position, Point3::new(12.0, 5.0, -1.0));
+        assert_eq!(machine.state().motion, Some(Motion::ClockwiseArc));
+        assert_eq!(machine.state().distance_mode, DistanceMode::Relative);
+        assert_eq!(machine.state().feed_rate, Some(100.0));
+    }
+
+    #[test]
+    fn test_units() {
+        let machine = run(&["G20 G1 X1 F10"]);
+
+        assert_eq!(machine.state().position, Point3::new(25.4, 0.0, 0.0));
+        assert_eq!(machine.state().feed_rate, Some(254.0));
+        assert_eq!(machine.state().work_position(), Point3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_offsets() {
+        let mut machine = Machine::new();
+        machine.set_work_offset(2, Point3::new(100.0, 0.0, 0.0)).unwrap();
+
+        for block in Parser::new().parse_all(["G55 G0 X10", "G92 X0", "G0 X5"].iter()).unwrap() {
+            machine.execute(&block).unwrap();
+        }
+
+        assert_eq!(machine.state().position, Point3::new(115.0, 0.0, 0.0));
+        assert_eq!(machine.state().work_position(), Point3::new(5.0, 0.0, 0.0));
+        assert_eq!(machine.state().position_offset, Point3::new(10.0, 0.0, 0.0));
+
+        assert!(machine.set_work_offset(10, Point3::new(0.0, 0.0, 0.0)).is_err());
+    }
+
+    #[test]
+    fn test_position_offset() {
+        let mut machine = Machine::new();
+        machine.set_work_offset(1, Point3::new(10.0, 0.0, 0.0)).unwrap();
+
+        let mut parser = Parser::new();
+        let mut execute = |line: &str| {
+            machine.execute(&parser.parse(line).unwrap()).unwrap();
+            machine.state().clone()
+        };
+
+        assert_eq!(execute("G0 X20 Y5").work_position(), Point3::new(20.0, 5.0, 0.0));
+        assert_eq!(execute("G92 X0 Y0").work_position(), Point3::new(0.0, 0.0, 0.0));
+        assert_eq!(execute("G92.2").work_position(), Point3::new(20.0, 5.0, 0.0));
+        assert_eq!(execute("G92.3").work_position(), Point3::new(0.0, 0.0, 0.0));
+
+        // Setting the position while suspended drops the suspended offset of the other axes
+        execute("G92.2");
+        let state = execute("G92 X1");
+        assert_eq!(state.work_position(), Point3::new(1.0, 5.0, 0.0));
+        assert_eq!(state.position_offset, Point3::new(19.0, 0.0, 0.0));
+
+        assert_eq!(execute("G92.1").work_position(), Point3::new(20.0, 5.0, 0.0));
+        assert_eq!(execute("G92.3").work_position(), Point3::new(20.0, 5.0, 0.0));
+
+        // Work offsets set with G10 L20 keep the G92 offset applied on top
+        execute("G92 X0");
+        assert_eq!(execute("G10 L20 P1 X5").work_position(), Point3::new(5.0, 5.0, 0.0));
+        assert_eq!(execute("G92.1").work_position(), Point3::new(25.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn test_probe() {
+        let mut machine = Machine::new().with_probe_contact(Axes { z: Some(-13.0), ..Axes::default() });
+        machine.set_work_offset(1, Point3::new(0.0, 0.0, -10.0)).unwrap();
+
+        let mut parser = Parser::new();
+        let mut execute = |line: &str| machine.execute(&parser.parse(line).unwrap()).map(|_| machine.clone());
+        let parameter = |machine: &Machine, number: u32| machine.parameters().get(&Parameter::Numbered(number)).unwrap();
+
+        let machine = execute("G0 X1 Z5").and_then(|_| execute("G38.2 Z-5 F100")).unwrap();
+        assert_eq!(machine.state().position, Point3::new(1.0, 0.0, -13.0));
+        assert_eq!(machine.state().probe, Some(ProbeResult { position: Point3::new(1.0, 0.0, -13.0), tripped: true }));
+        assert_eq!(machine.state().motion, None);
+        assert_eq!((parameter(&machine, 5061), parameter(&machine, 5063), parameter(&machine, 5070)), (1.0, -3.0, 1.0));
+
+        // Probing away trips when leaving the contact
+        let machine = execute("G38.4 Z10").unwrap();
+        assert_eq!(machine.state().position.z, -13.0);
+
+        let machine = execute("G0 Z10").and_then(|_| execute("G38.3 X5")).unwrap();
+        assert_eq!(machine.state().position, Point3::new(5.0, 0.0, 0.0));
+        assert_eq!(parameter(&machine, 5070), 0.0);
+
+        match execute("G38.2 X10") {
+            Err(InterpError::ProbeNotTripped) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_lathe() {
+        let machine = run(&["G7 G0 X20 Z5", "G91 X-4"]);
+        assert_eq!(machine.state().position, Point3::new(8.0, 0.0, 5.0));
+        assert_eq!(machine.state().work_position(), Point3::new(16.0, 0.0, 5.0));
+
+        let machine = run(&["G8 G0 X20", "G96 S300 D3000 M3"]);
+        assert_eq!(machine.state().surface_speed, Some(300.0));
+        assert!((machine.state().spindle_rpm().unwrap() - 300000.0 / (40.0 * crate::real::consts::PI)).abs() < crate::EPSILON);
+
+        // Limited close to the axis
+        let machine = run(&["G96 S300 D2000 M3", "G0 X0"]);
+        assert_eq!(machine.state().spindle_rpm(), Some(2000.0));
+
+        let machine = run(&["G20 G96 S1000", "G0 X1", "G97 S800"]);
+        assert_eq!(machine.state().surface_speed, None);
+        assert_eq!(machine.state().spindle_rpm(), Some(800.0));
+
+        let machine = run(&["G20", "G96 S1000 D5000", "G0 X1"]);
+        assert!((machine.state().surface_speed.unwrap() - 304.8).abs() < crate::EPSILON);
+
+        let machine = run(&["G1 X0 F100", "G33 X10 Z-20 K2"]);
+        assert_eq!(machine.state().position, Point3::new(10.0, 0.0, -20.0));
+        assert_eq!(machine.state().motion, None);
+    }
+
+    #[test]
+    fn test_additional_axes() {
+        let machine = run(&["G0 X1 A90 U5", "G91 A-180 U1", "G20 C10 V1"]);
+        assert_eq!(machine.state().position, Point3::new(1.0, 0.0, 0.0));
+        assert_eq!(machine.state().axes, AxisValues([-90.0, 0.0, 10.0, 6.0, 25.4, 0.0]));
+
+        let blocks = Parser::new().parse_all(["G0 A90 U5", "G91 G20 G1 A-90 V1 F10"].iter()).unwrap();
+        let lines = Machine::new().into_absolute(blocks)
+                .map(|block| block.unwrap().line().to_owned())
+                .collect::<Vec<_>>();
+
+        assert_eq!(lines, vec!["G0 A90 U5", "G20 G90 G1 A0 V1 F10"]);
+    }
+
+    #[test]
+    fn test_feed_rate_modes() {
+        let machine = run(&["G1 X10 F100", "G93 G1 X20 F2"]);
+        assert_eq!(machine.state().feed_rate_mode, FeedRateMode::InverseTime);
+        assert_eq!(machine.state().feed_rate, Some(2.0));
+        assert_eq!(machine.state().feed_per_minute(10.0), Some(20.0));
+
+        // Every feed move needs its own feed rate in inverse time mode, rapids do not
+        let mut machine = run(&["G93 G1 X10 F2", "G0 X0"]);
+        match machine.execute(&Parser::new().parse("G1 X5").unwrap()) {
+            Err(InterpError::MissingInverseTimeFeed) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let machine = run(&["G1 F100", "G20", "G95 F0.01"]);
+        assert!((machine.state().feed_rate.unwrap() - 0.254).abs() < crate::EPSILON);
+        assert_eq!(machine.state().feed_per_minute(1.0), None);
+
+        let machine = run(&["G95 F0.1 S1000 M3"]);
+        assert_eq!(machine.state().feed_per_minute(1.0), Some(100.0));
+
+        // Switching modes and ending the program forget the feed rate
+        let machine = run(&["G1 F100", "G95"]);
+        assert_eq!(machine.state().feed_rate, None);
+        let machine = run(&["G93 G1 X1 F2", "M2"]);
+        assert_eq!((machine.state().feed_rate_mode, machine.state().feed_rate), (FeedRateMode::UnitsPerMinute, None));
+    }
+
+    #[test]
+    fn test_home() {
+        let machine = run(&["G0 X10 Y10 Z10", "G28 G91 Z0"]);
+        assert_eq!(machine.state().position, Point3::new(10.0, 10.0, 0.0));
+
+        let machine = run(&["G0 X10 Y10 Z10", "G28"]);
+        assert_eq!(machine.state().position, Point3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_predefined() {
+        let machine = run(&["G0 X5 Y5 Z20", "G30.1", "G0 X0 Y0 Z0", "G30 Z10"]);
+        assert_eq!(machine.state().secondary_position, Point3::new(5.0, 5.0, 20.0));
+        assert_eq!(machine.state().position, Point3::new(0.0, 0.0, 20.0));
+
+        let machine = run(&["G0 X5 Y5 Z20", "G28.1", "G0 X1 Y1 Z1", "G28"]);
+        assert_eq!(machine.state().position, Point3::new(5.0, 5.0, 20.0));
+        assert_eq!(machine.state().secondary_position, Point3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_modal_state() {
+        let machine = run(&["T5 M6", "S10000 M3", "M8", "G18"]);
+
+        assert_eq!(machine.state().tool, Some(5));
+        assert_eq!(machine.state().spindle, Spindle::Clockwise);
+        assert_eq!(machine.state().spindle_speed, Some(10000.0));
+        assert!(machine.state().flood);
+        assert_eq!(machine.state().plane, Plane::ZX);
+    }
+
+    #[test]
+    fn test_work_offsets() {
+        let machine = run(&["G10 L2 P2 X100 Y50", "G55 G0 X1 Y1", "G10 L2 P0 Z-10", "G20 G59.3 G0 X1", "G10 L20 P0 X0 Y1"]);
+
+        let state = machine.state();
+        assert_eq!(state.coordinate_system, 9);
+        assert_eq!(state.work_offsets[1], Point3::new(100.0, 50.0, -10.0));
+        assert_eq!(state.work_offsets[8], Point3::new(25.4, 25.6, 0.0));
+        assert_eq!(state.machine_position(), Point3::new(1.0, 51.0 / 25.4, 0.0));
+        assert_eq!(state.work_position(), Point3::new(0.0, 1.0, 0.0));
+
+        let mut machine = Machine::new();
+        assert!(machine.execute(&Parser::new().parse("G10 L2 P0 X1").unwrap()).is_ok());
+        assert_eq!(machine.state().work_offsets[0], Point3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_tool_length_offset() {
+        let tools = ToolTable::new().with_tool(1, Tool::new(6.0).with_length(30.0)).with_tool(2, Tool::new(3.0).with_length(45.0));
+        let mut machine = Machine::new().with_tools(tools);
+        let mut parser = Parser::new();
+        let mut execute = |line: &str| machine.execute(&parser.parse(line).unwrap()).map(|_| machine.state().clone());
+
+        let state = execute("T1 M6 G43 G0 Z5").unwrap();
+        assert_eq!(state.tool_length_offset, 30.0);
+        assert_eq!(state.position, Point3::new(0.0, 0.0, 35.0));
+        assert_eq!(state.work_position(), Point3::new(0.0, 0.0, 5.0));
+
+        assert_eq!(execute("G43 H2 Z0").unwrap().position.z, 45.0);
+        execute("G20 G43.1 Z1").unwrap();
+        assert_eq!(execute("G0 Z0").unwrap().position.z, 25.4);
+        assert_eq!(execute("G21 G49 Z0").unwrap().position.z, 0.0);
+
+        match execute("G43 H3") {
+            Err(InterpError::UnknownTool(3)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_absolute() {
+        let mut machine = Machine::new();
+        machine.set_work_offset(1, Point3::new(100.0, 50.0, 0.0)).unwrap();
+
+        let blocks = Parser::new().parse_all([
+            "G0 X10 Y10",
+            "G91 G1 X5 F100",
+            "Y-5",
+            "G2 X5 Y5 I5",
+            "G92 X0 Y0",
+            "G90 G0 X1",
+            "N10 G28 G91 Z0",
+        ].iter()).unwrap();
+
+        let lines = machine.into_absolute(blocks)
+                .map(|block| block.unwrap().line().to_owned())
+                .collect::<Vec<_>>();
+
+        assert_eq!(lines, vec![
+            "G0 X110 Y60",
+            "G90 G1 X115 F100",
+            "Y55",
+            "G2 X120 Y60 I5",
+            "",
+            "G90 G0 X121",
+            "N10 G90 G28 Z0",
+        ]);
+    }
+
+    #[test]
+    fn test_absolute_diameter() {
+        let blocks = Parser::new().parse_all(["G7 G91 G0 X4 Z1", "G33 X2 Z-1 K1"].iter()).unwrap();
+        let lines = Machine::new().into_absolute(blocks)
+                .map(|block| block.unwrap().line().to_owned())
+                .collect::<Vec<_>>();
+
+        assert_eq!(lines, vec!["G7 G90 G0 X4 Z1", "G33 X6 Z0 K1"]);
+    }
+
+    #[test]
+    fn test_absolute_provenance() {
+        let blocks = Parser::new().with_source("part.ngc").parse_all(["G91", "G0 X1", "G0 X1 E1"].iter()).unwrap();
+        let mut absolute = Machine::new().into_absolute(blocks);
+
+        let block = absolute.nth(1).unwrap().unwrap();
+        assert_eq!(block.provenance().line(), Some(2));
+        assert_eq!(block.provenance().to_string(), "part.ngc:2 (absolute)");
+
+        let err = absolute.next().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "part.ngc:3: unsupported word: E1");
+    }
+
+    #[test]
+    fn test_arcs() {
+        let machine = run(&["G0 X10", "G3 X-10 R10", "G2 X10 I10", "G18 G2 X0 Z10 I-10", "G19 G3 Y10 Z0 K-10"]);
+        assert_eq!(machine.state().position, Point3::new(0.0, 10.0, 0.0));
+
+        let mut machine = Machine::new();
+        let mut parser = Parser::new();
+        for line in &["G2 X10", "G2 X10 I5 R5", "G2 X10 I5 K1", "G2 X10 I4", "G2 R5"] {
+            assert!(machine.execute(&parser.parse(line).unwrap()).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = run(&["G0 X10", "M3 S1000"]).into_state();
+
+        let mut machine = Machine::with_state(before.clone());
+        machine.set_work_offset(2, Point3::new(5.0, 0.0, 0.0)).unwrap();
+        for block in Parser::new().parse_all(["G20 G91", "G1 X1 F10", "M5"].iter()).unwrap() {
+            machine.execute(&block).unwrap();
+        }
+
+        assert_eq!(before.diff(machine.state()), vec![
+            StateChange::Position(Point3::new(35.4, 0.0, 0.0)),
+            StateChange::Units(Units::Inches),
+            StateChange::DistanceMode(DistanceMode::Relative),
+            StateChange::Motion(Some(Motion::Linear)),
+            StateChange::FeedRate(Some(254.0)),
+            StateChange::Spindle(Spindle::Off),
+            StateChange::WorkOffset { system: 2, origin: Point3::new(5.0, 0.0, 0.0) },
+        ]);
+
+        assert!(before.diff(&before).is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut machine = Machine::new();
+        let mut parser = Parser::new();
+
+        assert!(machine.execute(&parser.parse("X10").unwrap()).is_err());
+        assert!(machine.execute(&parser.parse("G0 G1 X10").unwrap()).is_err());
+        assert!(machine.execute(&parser.parse("G91 G0 X10 E5").unwrap()).is_err());
+
+        // Failing blocks do not change the state at all
+        assert_eq!(machine.state(), &State::default());
+    }
+
+    #[test]
+    fn test_parameters() {
+        let machine = run(&["#1 = 10 #<_z> = -2", "G0 X#1 Y#2", "G1 Z#<_z> F#1", "#1 = #<_z>"]);
+
+        assert_eq!(machine.state().position, Point3::new(10.0, 0.0, -2.0));
+        assert_eq!(machine.state().feed_rate, Some(10.0));
+        assert_eq!(machine.parameters().get(&Parameter::Numbered(1)).unwrap(), -2.0);
+
+        // Failing blocks do not assign parameters
+        let mut machine = Machine::new();
+        let mut parser = Parser::new();
+        assert!(machine.execute(&parser.parse("#1 = 5 G0 G1 X10").unwrap()).is_err());
+        assert!(machine.execute(&parser.parse("G0 X#<undefined>").unwrap()).is_err());
+        assert_eq!(machine.parameters(), &Parameters::new());
+
+        assert!(matches!(machine.execute(&parser.parse("o1 if [1]").unwrap()), Err(InterpError::ControlFlow(_))));
+    }
+}
@@ -1,13 +1,148 @@
+// Functions with more than a single expression return explicitly, and the parser lives in a
+// module of the same name
+#![allow(clippy::needless_return, clippy::module_inception)]
+// Without the `std` feature, only the lexer, the parser and the types of blocks are available
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
 
+#[macro_use]
+mod trace;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod annotate;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod builder;
+#[cfg(feature = "std")]
+pub mod cancel;
+#[cfg(feature = "std")]
+pub mod command;
+#[cfg(feature = "std")]
+pub mod console;
+pub mod control;
+#[cfg(feature = "std")]
+pub mod corpus;
+pub mod diagnostic;
+pub mod dialect;
+#[cfg(feature = "std")]
+pub mod emit;
+pub mod expr;
+#[cfg(feature = "std")]
+pub mod extract;
+#[cfg(feature = "std")]
+pub mod facade;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod generate;
+#[cfg(feature = "std")]
+pub mod geometry;
+#[cfg(feature = "std")]
+pub mod host;
+#[cfg(feature = "std")]
+pub mod import;
+#[cfg(feature = "std")]
+pub mod interp;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod metadata;
+pub mod modal;
+pub mod params;
 pub mod parser;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod plasma;
+#[cfg(feature = "std")]
+pub mod prelude;
+#[cfg(feature = "std")]
+pub mod printer;
+#[cfg(feature = "std")]
+pub mod program;
+pub mod remap;
+#[cfg(feature = "std")]
+pub mod response;
+#[cfg(feature = "std")]
+pub mod sd;
+#[cfg(feature = "std")]
+pub mod sender;
+#[cfg(feature = "std")]
+pub mod thumbnail;
+#[cfg(feature = "std")]
+pub mod toolpath;
+#[cfg(feature = "std")]
+pub mod tools;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod transform;
+
+#[cfg(feature = "std")]
+pub use crate::facade::{open, Error};
+
+/// The numeric type of word values and of all arithmetic following from them.
+///
+/// This is `f64`, or `f32` with the `f32` feature for targets lacking double precision hardware.
+/// Fixed-point types are not supported, as they lack the functions of floating point types the
+/// geometry relies on, like `sqrt`, `atan2` and `hypot`.
+#[cfg(not(feature = "f32"))]
+pub type Real = f64;
+
+#[cfg(feature = "f32")]
+pub type Real = f32;
+
+/// The module of `Real`, providing its constants.
+#[cfg(not(feature = "f32"))]
+pub use core::f64 as real;
+
+#[cfg(feature = "f32")]
+pub use core::f32 as real;
+
+/// The tolerance below which lengths, areas and other values are treated as equal. It is scaled to
+/// the precision of `Real`, as `f32` cannot tell coordinates of a few hundred millimeters apart
+/// to the nanometer.
+#[cfg(all(feature = "std", not(feature = "f32")))]
+pub(crate) const EPSILON: Real = 1e-9;
+
+#[cfg(all(feature = "std", feature = "f32"))]
+pub(crate) const EPSILON: Real = 1024.0 * Real::EPSILON;
+
+/// Whether the value is a whole number, like `value.fract() == 0.0` which is not available
+/// without the `std` feature.
+pub(crate) fn is_whole(value: Real) -> bool {
+    // Values too large for a fractional part are whole unless infinite
+    let limit = 1.0 / Real::EPSILON;
+    if !(-limit < value && value < limit) {
+        return value.is_finite();
+    }
+
+    return value == value as i64 as Real;
+}
 
 
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn test_is_whole() {
+        assert!(is_whole(0.0));
+        assert!(is_whole(-28.0));
+        assert!(is_whole(1e20));
+        assert!(!is_whole(28.1));
+        assert!(!is_whole(-0.5));
+        assert!(!is_whole(Real::INFINITY));
+        assert!(!is_whole(Real::NAN));
+    }
 }
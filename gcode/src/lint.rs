@@ -0,0 +1,457 @@
+//! Checks of programs for likely mistakes, which do not necessarily prevent their execution.
+//!
+//! A linter runs a set of rules over a program, each reporting findings for the blocks it deems
+//! suspicious. Findings carry a stable code (see `diagnostic::CODES`), which tells their severity.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::Real;
+use crate::diagnostic::{Code, Severity};
+use crate::dialect::Dialect;
+use crate::modal::SemanticError;
+use crate::parser::{Block, Span, Word};
+use crate::printer::PrinterState;
+use crate::program::Program;
+
+/// The lowest temperature of the hot end allowing extrusion, like Marlin's `EXTRUDE_MINTEMP`.
+pub const MIN_EXTRUSION_TEMPERATURE: Real = 170.0;
+
+/// Letters of axis words.
+const AXES: &str = "XYZABCUVW";
+
+/// Non-modal G codes taking axis words without moving along them.
+const NON_MODAL: &[Real] = &[4.0, 10.0, 28.0, 28.1, 30.0, 30.1, 92.0, 92.1, 92.2, 92.3];
+
+/// A suspicious spot in a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub code: Code,
+    pub message: String,
+
+    /// The index of the block within the program.
+    pub block: usize,
+
+    /// The location of the offending word, if known.
+    pub span: Option<Span>,
+}
+
+impl Finding {
+    /// Creates a finding for the word at `word` of the block, taking its span if available.
+    pub fn at<S>(code: Code, message: S, index: usize, block: &Block, word: Option<usize>) -> Self
+        where S: Into<String> {
+        Self {
+            code,
+            message: message.into(),
+            block: index,
+            span: word.and_then(|word| block.span(word)),
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.code.severity()
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity() {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        match self.span {
+            Some(span) => write!(f, "{}: {}[{}]: {}", span, severity, self.code, self.message),
+            None => write!(f, "block {}: {}[{}]: {}", self.block, severity, self.code, self.message),
+        }
+    }
+}
+
+/// A check of programs.
+///
+/// Rules hold no state between programs, so a linter can check any number of programs.
+pub trait Rule {
+    /// The name of the rule, like `duplicate-word`.
+    fn name(&self) -> &'static str;
+
+    /// Checks the program, pushing findings to `findings`.
+    fn check(&self, program: &Program, findings: &mut Vec<Finding>);
+}
+
+/// A set of rules checked together.
+pub struct Linter {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Linter {
+    /// Creates a linter checking all built-in rules.
+    pub fn new() -> Self {
+        Self::empty()
+                .with_rule(DuplicateWord)
+                .with_rule(LineNumbers)
+                .with_rule(ModalConflict::default())
+                .with_rule(FeedRate)
+                .with_rule(Homing)
+                .with_rule(Plane)
+                .with_rule(ColdExtrusion)
+    }
+
+    /// Creates a linter without any rules.
+    pub fn empty() -> Self {
+        Self {
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn with_rule<R>(mut self, rule: R) -> Self
+        where R: Rule + 'static {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// The names of all rules checked.
+    pub fn rules(&self) -> impl Iterator<Item=&'static str> + '_ {
+        self.rules.iter().map(|rule| rule.name())
+    }
+
+    /// Checks the program against all rules, returning the findings ordered by block.
+    pub fn check(&self, program: &Program) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for rule in &self.rules {
+            event!(trace, rule = rule.name(), "checking rule");
+            rule.check(program, &mut findings);
+        }
+
+        findings.sort_by_key(|finding| finding.block);
+        return findings;
+    }
+}
+
+/// Checks the program against all built-in rules.
+pub fn lint(program: &Program) -> Vec<Finding> {
+    Linter::new().check(program)
+}
+
+fn is_code(word: &Word, mnemonic: char, codes: &[Real]) -> bool {
+    word.mnemonic() == mnemonic && codes.contains(&word.value())
+}
+
+/// The index of the first word of the block matching the predicate.
+fn find<P>(block: &Block, predicate: P) -> Option<usize>
+    where P: Fn(&Word) -> bool {
+    block.words().iter().position(predicate)
+}
+
+/// Tracks the motion mode through the blocks of a program, telling which blocks move.
+#[derive(Debug, Default)]
+struct Motion {
+    mode: Option<Real>,
+}
+
+impl Motion {
+    /// Updates the motion mode and returns the index of the first axis word if the block moves.
+    fn apply(&mut self, block: &Block) -> Option<usize> {
+        for word in block.words() {
+            if is_code(word, 'G', &[0.0, 1.0, 2.0, 3.0]) {
+                self.mode = Some(word.value());
+            } else if is_code(word, 'G', &[80.0]) {
+                self.mode = None;
+            }
+        }
+
+        if self.mode.is_none() || find(block, |word| is_code(word, 'G', NON_MODAL)).is_some() {
+            return None;
+        }
+
+        return find(block, |word| AXES.contains(word.mnemonic()));
+    }
+}
+
+/// Reports words given more than once in a block, like `G1 X1 X2` (W0101).
+///
+/// G and M words of different modal groups may appear multiple times.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DuplicateWord;
+
+impl Rule for DuplicateWord {
+    fn name(&self) -> &'static str {
+        "duplicate-word"
+    }
+
+    fn check(&self, program: &Program, findings: &mut Vec<Finding>) {
+        for (index, block) in program.iter().enumerate() {
+            let words = block.words();
+            for (i, word) in words.iter().enumerate() {
+                if word.mnemonic() == 'G' || word.mnemonic() == 'M' {
+                    continue;
+                }
+
+                if words[..i].iter().any(|other| other.mnemonic() == word.mnemonic()) {
+                    findings.push(Finding::at(Code::warning(101), format!("duplicate word: {}", word), index, block, Some(i)));
+                }
+            }
+        }
+    }
+}
+
+/// Reports line numbers not increasing along the program (W0102) and line numbers given to more
+/// than one block (W0103).
+///
+/// Controllers searching for line numbers, like for `GOTO` or when resuming a program, may find
+/// the wrong block otherwise.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LineNumbers;
+
+impl Rule for LineNumbers {
+    fn name(&self) -> &'static str {
+        "line-numbers"
+    }
+
+    fn check(&self, program: &Program, findings: &mut Vec<Finding>) {
+        let mut first = HashMap::new();
+        let mut previous: Option<Real> = None;
+
+        for (index, block) in program.iter().enumerate() {
+            let number = match block.line_number() {
+                Some(number) => number,
+                None => continue,
+            };
+
+            if let Some(&other) = first.get(&number.to_bits()) {
+                let message = format!("line number N{} already given in block {}", number, other);
+                findings.push(Finding::at(Code::warning(103), message, index, block, None));
+            } else if let Some(previous) = previous.filter(|&previous| number <= previous) {
+                let message = format!("line number N{} after N{}", number, previous);
+                findings.push(Finding::at(Code::warning(102), message, index, block, None));
+            }
+
+            first.entry(number.to_bits()).or_insert(index);
+            previous = Some(number);
+        }
+    }
+}
+
+/// Reports blocks with multiple words of the same modal group, like `G0 G1` (E0101).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ModalConflict {
+    pub dialect: Dialect,
+}
+
+impl Rule for ModalConflict {
+    fn name(&self) -> &'static str {
+        "modal-conflict"
+    }
+
+    fn check(&self, program: &Program, findings: &mut Vec<Finding>) {
+        for (index, block) in program.iter().enumerate() {
+            if let Err(err) = block.check_modal_groups_with(self.dialect) {
+                let SemanticError::ModalGroupConflict { second_mnemonic, second_value, .. } = err;
+                let word = block.words().iter().rposition(|word| word.mnemonic() == second_mnemonic && word.value() == second_value);
+                findings.push(Finding::at(err.code(), err.to_string(), index, block, word));
+            }
+        }
+    }
+}
+
+/// Reports the first feed move of a program without a feed rate set before (W0201).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct FeedRate;
+
+impl Rule for FeedRate {
+    fn name(&self) -> &'static str {
+        "feed-rate"
+    }
+
+    fn check(&self, program: &Program, findings: &mut Vec<Finding>) {
+        let mut motion = Motion::default();
+        for (index, block) in program.iter().enumerate() {
+            if find(block, |word| word.mnemonic() == 'F').is_some() {
+                return;
+            }
+
+            if let (Some(word), Some(mode)) = (motion.apply(block), motion.mode) {
+                if mode != 0.0 {
+                    findings.push(Finding::at(Code::warning(201), "feed move before the feed rate is set", index, block, Some(word)));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Reports the first move of a program which is not preceded by homing with `G28` or `$H` (W0202).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Homing;
+
+impl Rule for Homing {
+    fn name(&self) -> &'static str {
+        "homing"
+    }
+
+    fn check(&self, program: &Program, findings: &mut Vec<Finding>) {
+        let mut motion = Motion::default();
+        for (index, block) in program.iter().enumerate() {
+            let homes = find(block, |word| is_code(word, 'G', &[28.0])).is_some()
+                    || block.system_command().is_some_and(|command| command.eq_ignore_ascii_case("H"));
+            if homes {
+                return;
+            }
+
+            if let Some(word) = motion.apply(block) {
+                findings.push(Finding::at(Code::warning(202), "move before the machine is homed", index, block, Some(word)));
+                return;
+            }
+        }
+    }
+}
+
+/// Reports the first arc of a program which is not preceded by a plane selection (W0203).
+///
+/// The default plane differs between controllers, so programs should select it explicitly.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Plane;
+
+impl Rule for Plane {
+    fn name(&self) -> &'static str {
+        "plane"
+    }
+
+    fn check(&self, program: &Program, findings: &mut Vec<Finding>) {
+        let mut motion = Motion::default();
+        for (index, block) in program.iter().enumerate() {
+            if find(block, |word| is_code(word, 'G', &[17.0, 18.0, 19.0])).is_some() {
+                return;
+            }
+
+            let word = motion.apply(block);
+            if let (Some(word), Some(2.0)) | (Some(word), Some(3.0)) = (word, motion.mode) {
+                findings.push(Finding::at(Code::warning(203), "arc before a plane is selected", index, block, Some(word)));
+                return;
+            }
+        }
+    }
+}
+
+/// Reports extrusion while the hot end is set below `MIN_EXTRUSION_TEMPERATURE` (W0204).
+///
+/// Only the first extrusion is reported after each change of the temperature.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ColdExtrusion;
+
+impl Rule for ColdExtrusion {
+    fn name(&self) -> &'static str {
+        "cold-extrusion"
+    }
+
+    fn check(&self, program: &Program, findings: &mut Vec<Finding>) {
+        let mut printer = PrinterState::new();
+        let mut reported = false;
+
+        for (index, block) in program.iter().enumerate() {
+            let hotend = printer.hotend;
+            let (extruded, _) = printer.apply(block);
+            if printer.hotend != hotend {
+                reported = false;
+            }
+
+            let cold = printer.hotend.is_none_or(|temperature| temperature < MIN_EXTRUSION_TEMPERATURE);
+            if extruded > 0.0 && cold && !reported {
+                let message = match printer.hotend {
+                    Some(temperature) => format!("extrusion with the hot end set to {}°C", temperature),
+                    None => "extrusion before the hot end is heated".to_owned(),
+                };
+
+                let word = find(block, |word| word.mnemonic() == 'E');
+                findings.push(Finding::at(Code::warning(204), message, index, block, word));
+                reported = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn program(text: &str) -> Program {
+        Parser::new().parse_all(text.lines()).unwrap().into_iter().collect()
+    }
+
+    fn codes(findings: &[Finding]) -> Vec<(String, usize)> {
+        findings.iter().map(|finding| (finding.code.to_string(), finding.block)).collect()
+    }
+
+    #[test]
+    fn test_clean() {
+        let findings = lint(&program("G28\nG17 G21 G90\nG0 X10 Y10\nG1 X20 F600\nG2 X30 Y10 I5 J0\nM30"));
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn test_findings() {
+        let findings = lint(&program("G21\nG0 X10\nG1 X20 X30\nG2 X10 Y10 I5\nG0 G1 Y2"));
+        assert_eq!(codes(&findings), vec![
+            ("W0202".to_owned(), 1),
+            ("W0101".to_owned(), 2),
+            ("W0201".to_owned(), 2),
+            ("W0203".to_owned(), 3),
+            ("E0101".to_owned(), 4),
+        ]);
+
+        assert_eq!(findings[1].span, Some(Span { line: 3, column: 8, start: 7, end: 10 }));
+        assert_eq!(findings[1].to_string(), "3:8: warning[W0101]: duplicate word: X30");
+        assert_eq!(findings[4].severity(), Severity::Error);
+        assert_eq!(findings[4].span.map(|span| span.start), Some(3));
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let linter = Linter::empty().with_rule(LineNumbers);
+        assert!(linter.check(&program("N10 G0 X1\nG0 X2\nN20 G0 X3\nN25 M30")).is_empty());
+
+        let findings = linter.check(&program("N10 G0 X1\nN30 G0 X2\nN20 G0 X3\nN10 M30"));
+        assert_eq!(codes(&findings), vec![("W0102".to_owned(), 2), ("W0103".to_owned(), 3)]);
+        assert_eq!(findings[0].to_string(), "block 2: warning[W0102]: line number N20 after N30");
+        assert_eq!(findings[1].message, "line number N10 already given in block 0");
+    }
+
+    #[test]
+    fn test_homing() {
+        assert!(lint(&program("G28 X0 Y0\nG0 X1")).iter().all(|finding| finding.code != Code::warning(202)));
+        assert!(Linter::empty().with_rule(Homing).check(&Parser::new().with_dialect(Dialect::Grbl)
+                .parse_all(["$H", "G0 X1"].iter())
+                .unwrap()
+                .into_iter()
+                .collect()).is_empty());
+
+        // Setting positions does not move
+        let findings = Linter::empty().with_rule(Homing).check(&program("G92 X0\nG0 X1"));
+        assert_eq!(codes(&findings), vec![("W0202".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn test_cold_extrusion() {
+        let linter = Linter::empty().with_rule(ColdExtrusion);
+
+        let findings = linter.check(&program("G1 X1 E1\nG1 X2 E2\nM104 S150\nG1 X3 E3\nM109 S210\nG1 X4 E4\nG1 E3"));
+        assert_eq!(codes(&findings), vec![("W0204".to_owned(), 0), ("W0204".to_owned(), 3)]);
+        assert_eq!(findings[0].message, "extrusion before the hot end is heated");
+        assert_eq!(findings[1].message, "extrusion with the hot end set to 150°C");
+
+        assert!(linter.check(&program("M109 S200\nG1 X1 E5")).is_empty());
+    }
+
+    #[test]
+    fn test_rules() {
+        let linter = Linter::new();
+        assert_eq!(linter.rules().collect::<Vec<_>>(),
+                   vec!["duplicate-word", "line-numbers", "modal-conflict", "feed-rate", "homing", "plane", "cold-extrusion"]);
+    }
+}
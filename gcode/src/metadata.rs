@@ -0,0 +1,211 @@
+//! Job information slicers leave in comments.
+//!
+//! Cura, PrusaSlicer and Slic3r describe the job in comments at the start or the end of a
+//! program, and mark layers and feature types along the way. The conventions differ slightly
+//! between slicers, but are easily told apart.
+
+use crate::Real;
+use crate::parser::Comment;
+use crate::program::Program;
+
+/// The slicers whose comment conventions are recognized.
+const SLICERS: &[&str] = &["Cura_SteamEngine", "PrusaSlicer", "SuperSlicer", "OrcaSlicer", "Slic3r"];
+
+/// Information about a job found in the comments of a program.
+///
+/// All fields are optional, as slicers record different information.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct JobMetadata {
+    /// The name of the slicer generating the program.
+    pub slicer: Option<String>,
+    pub slicer_version: Option<String>,
+
+    /// The number of layers, as announced by the slicer or counted from the layer markers.
+    pub layer_count: Option<usize>,
+
+    /// The time printing takes as estimated by the slicer, in seconds.
+    pub estimated_time: Option<Real>,
+
+    /// The length of filament used, in millimeters.
+    pub filament_length: Option<Real>,
+
+    /// The weight of filament used, in grams.
+    pub filament_weight: Option<Real>,
+
+    /// The feature types (`;TYPE:`) in order of their first appearance.
+    pub types: Vec<String>,
+}
+
+/// Checks if the comment marks the start of a new layer.
+pub fn is_layer_start(comment: &Comment) -> bool {
+    let text = comment.text().trim();
+    return text.starts_with("LAYER:") || text == "LAYER_CHANGE";
+}
+
+/// Extracts the metadata from the comments of the program.
+pub fn metadata(program: &Program) -> JobMetadata {
+    let mut metadata = JobMetadata::default();
+    let mut layers = 0;
+
+    for comment in program.iter().flat_map(|block| block.comments()) {
+        let text = comment.text().trim();
+
+        if is_layer_start(comment) {
+            layers += 1;
+            continue;
+        }
+
+        if let Some(generator) = strip_prefix(text, "generated by ").or_else(|| strip_prefix(text, "Generated with ")) {
+            if let Some(&slicer) = SLICERS.iter().find(|&&slicer| generator.starts_with(slicer)) {
+                metadata.slicer = Some(slicer.trim_end_matches("_SteamEngine").to_owned());
+                metadata.slicer_version = generator[slicer.len()..].split_whitespace().next().map(str::to_owned);
+            }
+            continue;
+        }
+
+        let (key, value) = match text.find([':', '=']) {
+            Some(index) => (text[..index].trim(), text[index + 1..].trim()),
+            None => continue,
+        };
+
+        match key {
+            "LAYER_COUNT" => metadata.layer_count = value.parse().ok(),
+            "TIME" => metadata.estimated_time = value.parse().ok(),
+            "TYPE" if !metadata.types.iter().any(|known| known == value) => metadata.types.push(value.to_owned()),
+
+            // Cura reports meters, Slic3r millimeters with the volume appended
+            "Filament used" | "filament used" => metadata.filament_length = length(value),
+            "filament used [mm]" => metadata.filament_length = value.parse().ok(),
+            "filament used [g]" => metadata.filament_weight = value.parse().ok(),
+
+            key if key.starts_with("estimated printing time") && !key.contains("silent") => {
+                metadata.estimated_time = duration(value);
+            }
+
+            _ => {}
+        }
+    }
+
+    if metadata.layer_count.is_none() && layers > 0 {
+        metadata.layer_count = Some(layers);
+    }
+
+    return metadata;
+}
+
+/// Matches a prefix ignoring the case of ASCII letters.
+fn strip_prefix<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    return match text.get(..prefix.len()) {
+        Some(head) if head.eq_ignore_ascii_case(prefix) => Some(&text[prefix.len()..]),
+        _ => None,
+    };
+}
+
+/// Parses a length like `1.5m` or `1500mm (3.6cm3)` into millimeters.
+fn length(value: &str) -> Option<Real> {
+    let value = value.split_whitespace().next()?;
+
+    let (number, scale) = if let Some(number) = value.strip_suffix("mm") {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 1000.0)
+    } else {
+        (value, 1.0)
+    };
+
+    return number.parse::<Real>().ok().map(|number| number * scale);
+}
+
+/// Parses a duration like `1d 2h 3m 4s` into seconds.
+fn duration(value: &str) -> Option<Real> {
+    let mut seconds = 0.0;
+
+    for part in value.split_whitespace() {
+        let (number, unit) = part.split_at(part.len().checked_sub(1)?);
+        let scale = match unit {
+            "d" => 86400.0,
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            _ => return None,
+        };
+
+        seconds += number.parse::<Real>().ok()? * scale;
+    }
+
+    return Some(seconds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse(lines: &[&str]) -> JobMetadata {
+        metadata(&Program::from(Parser::new().parse_all(lines.iter()).unwrap()))
+    }
+
+    #[test]
+    fn test_cura() {
+        let metadata = parse(&[
+            ";FLAVOR:Marlin",
+            ";TIME:6666",
+            ";Filament used: 1.5m",
+            ";Generated with Cura_SteamEngine 4.8.0",
+            ";LAYER_COUNT:100",
+            ";LAYER:0",
+            "G1 X1 E1 ;TYPE:SKIRT",
+            ";TYPE:WALL-OUTER",
+            ";LAYER:1",
+            ";TYPE:WALL-OUTER",
+        ]);
+
+        assert_eq!(metadata, JobMetadata {
+            slicer: Some("Cura".to_owned()),
+            slicer_version: Some("4.8.0".to_owned()),
+            layer_count: Some(100),
+            estimated_time: Some(6666.0),
+            filament_length: Some(1500.0),
+            filament_weight: None,
+            types: vec!["SKIRT".to_owned(), "WALL-OUTER".to_owned()],
+        });
+    }
+
+    #[test]
+    fn test_prusa_slicer() {
+        let metadata = parse(&[
+            "; generated by PrusaSlicer 2.5.0+win64 on 2023-01-01 at 12:00:00 UTC",
+            ";LAYER_CHANGE",
+            ";Z:0.2",
+            ";TYPE:Perimeter",
+            ";LAYER_CHANGE",
+            "; filament used [mm] = 1234.5",
+            "; filament used [g] = 3.7",
+            "; estimated printing time (normal mode) = 1d 1h 2m 3s",
+            "; estimated printing time (silent mode) = 2d 1h 2m 3s",
+        ]);
+
+        assert_eq!(metadata.slicer.as_deref(), Some("PrusaSlicer"));
+        assert_eq!(metadata.slicer_version.as_deref(), Some("2.5.0+win64"));
+        assert_eq!(metadata.layer_count, Some(2));
+        assert_eq!(metadata.estimated_time, Some(86400.0 + 3723.0));
+        assert_eq!(metadata.filament_length, Some(1234.5));
+        assert_eq!(metadata.filament_weight, Some(3.7));
+        assert_eq!(metadata.types, vec!["Perimeter".to_owned()]);
+    }
+
+    #[test]
+    fn test_slic3r() {
+        let metadata = parse(&[
+            "; generated by Slic3r 1.3.0 on 2020-01-01 at 12:00:00",
+            "; filament used = 1500.5mm (3.6cm3)",
+        ]);
+
+        assert_eq!(metadata.slicer.as_deref(), Some("Slic3r"));
+        assert_eq!(metadata.slicer_version.as_deref(), Some("1.3.0"));
+        assert_eq!(metadata.filament_length, Some(1500.5));
+        assert_eq!(metadata.layer_count, None);
+
+        assert_eq!(parse(&["G1 X1 (not a slicer)"]), JobMetadata::default());
+    }
+}
@@ -0,0 +1,232 @@
+//! Modal groups as defined by RS274/NGC.
+//!
+//! The codes of a modal group are mutually exclusive - at most one of them can be active at any
+//! time and hence at most one of them may appear in a single block.
+
+use alloc::vec::Vec;
+use core::error;
+use core::fmt;
+
+use crate::Real;
+use crate::diagnostic::Code;
+use crate::dialect::Dialect;
+use crate::parser::{Block, Word};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ModalGroup {
+    /// G4, G10, G28, G30, G53, G92 - not modal at all, but grouped by RS274/NGC.
+    NonModal,
+    /// G0, G1, G2, G3, G33, G38.2 - G38.5, G80 - G89
+    Motion,
+    /// G17, G18, G19
+    PlaneSelection,
+    /// G90, G91
+    DistanceMode,
+    /// G93, G94, G95
+    FeedRateMode,
+    /// G20, G21
+    Units,
+    /// G40, G41, G42
+    CutterRadiusCompensation,
+    /// G43, G49
+    ToolLengthOffset,
+    /// G98, G99
+    ReturnMode,
+    /// G54 - G59.3
+    CoordinateSystem,
+    /// G61, G61.1, G64
+    PathControl,
+    /// G96, G97
+    SpindleSpeedMode,
+    /// G7, G8
+    LatheDiameterMode,
+    /// M0, M1, M2, M30, M60
+    Stopping,
+    /// M6
+    ToolChange,
+    /// M3, M4, M5
+    Spindle,
+    /// M7, M8, M9
+    Coolant,
+    /// M48, M49
+    Override,
+}
+
+impl ModalGroup {
+    /// The modal group of a G or M word, if it is a known code.
+    pub fn of(word: &Word) -> Option<ModalGroup> {
+        // Codes are compared in tenths to cover ones like G38.2
+        let tenths = word.value() * 10.0;
+        if !crate::is_whole(tenths) || tenths < 0.0 {
+            return None;
+        }
+
+        return match (word.mnemonic(), tenths as u32) {
+            ('G', 40) | ('G', 100) | ('G', 280) | ('G', 281) | ('G', 300) | ('G', 301) | ('G', 530) | ('G', 920..=923) => Some(ModalGroup::NonModal),
+            ('G', 0) | ('G', 10) | ('G', 20) | ('G', 30) | ('G', 330) | ('G', 382..=385) | ('G', 800) | ('G', 810) | ('G', 820) | ('G', 830)
+            | ('G', 840) | ('G', 850) | ('G', 860) | ('G', 870) | ('G', 880) | ('G', 890) => Some(ModalGroup::Motion),
+            ('G', 170) | ('G', 180) | ('G', 190) => Some(ModalGroup::PlaneSelection),
+            ('G', 900) | ('G', 910) => Some(ModalGroup::DistanceMode),
+            ('G', 930) | ('G', 940) | ('G', 950) => Some(ModalGroup::FeedRateMode),
+            ('G', 200) | ('G', 210) => Some(ModalGroup::Units),
+            ('G', 400) | ('G', 410) | ('G', 420) => Some(ModalGroup::CutterRadiusCompensation),
+            ('G', 430) | ('G', 431) | ('G', 490) => Some(ModalGroup::ToolLengthOffset),
+            ('G', 980) | ('G', 990) => Some(ModalGroup::ReturnMode),
+            ('G', 540) | ('G', 550) | ('G', 560) | ('G', 570) | ('G', 580) | ('G', 590..=593) => Some(ModalGroup::CoordinateSystem),
+            ('G', 610) | ('G', 611) | ('G', 640) => Some(ModalGroup::PathControl),
+            ('G', 960) | ('G', 970) => Some(ModalGroup::SpindleSpeedMode),
+            ('G', 70) | ('G', 80) => Some(ModalGroup::LatheDiameterMode),
+            ('M', 0) | ('M', 10) | ('M', 20) | ('M', 300) | ('M', 600) => Some(ModalGroup::Stopping),
+            ('M', 60) => Some(ModalGroup::ToolChange),
+            ('M', 30) | ('M', 40) | ('M', 50) => Some(ModalGroup::Spindle),
+            ('M', 70) | ('M', 80) | ('M', 90) => Some(ModalGroup::Coolant),
+            ('M', 480) | ('M', 490) => Some(ModalGroup::Override),
+            _ => None,
+        };
+    }
+
+    /// Whether two codes of this group may appear in the same block.
+    fn allows(&self, first: &Word, second: &Word) -> bool {
+        return match *self {
+            // Mist and flood coolant can be turned on at the same time
+            ModalGroup::Coolant => first.value() != 9.0 && second.value() != 9.0,
+            _ => false,
+        };
+    }
+}
+
+impl fmt::Display for ModalGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            ModalGroup::NonModal => "non-modal",
+            ModalGroup::Motion => "motion",
+            ModalGroup::PlaneSelection => "plane selection",
+            ModalGroup::DistanceMode => "distance mode",
+            ModalGroup::FeedRateMode => "feed rate mode",
+            ModalGroup::Units => "units",
+            ModalGroup::CutterRadiusCompensation => "cutter radius compensation",
+            ModalGroup::ToolLengthOffset => "tool length offset",
+            ModalGroup::ReturnMode => "return mode",
+            ModalGroup::CoordinateSystem => "coordinate system",
+            ModalGroup::PathControl => "path control",
+            ModalGroup::SpindleSpeedMode => "spindle speed mode",
+            ModalGroup::LatheDiameterMode => "lathe diameter mode",
+            ModalGroup::Stopping => "stopping",
+            ModalGroup::ToolChange => "tool change",
+            ModalGroup::Spindle => "spindle",
+            ModalGroup::Coolant => "coolant",
+            ModalGroup::Override => "override",
+        };
+
+        return f.write_str(name);
+    }
+}
+
+/// A block which is syntactically valid but violates the rules of RS274/NGC.
+#[derive(Debug)]
+pub enum SemanticError {
+    ModalGroupConflict {
+        group: ModalGroup,
+        first_mnemonic: char,
+        first_value: Real,
+        second_mnemonic: char,
+        second_value: Real,
+    },
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemanticError::ModalGroupConflict { group, first_mnemonic, first_value, second_mnemonic, second_value } => write!(f, "{}{} and {}{} are both in the {} modal group", first_mnemonic, first_value, second_mnemonic, second_value, group),
+        }
+    }
+}
+
+impl error::Error for SemanticError {}
+
+impl SemanticError {
+    /// The stable code identifying the kind of error (see `diagnostic::CODES`).
+    pub fn code(&self) -> Code {
+        match self {
+            SemanticError::ModalGroupConflict { .. } => Code::error(101),
+        }
+    }
+}
+
+impl Block {
+    /// Checks that no two G or M words of the block belong to the same modal group.
+    ///
+    /// Words of the non-modal group are exempt from this check.
+    pub fn check_modal_groups(&self) -> Result<(), SemanticError> {
+        self.check_modal_groups_with(Dialect::Generic)
+    }
+
+    /// Checks the modal groups like `check_modal_groups`, but using the groups of the dialect.
+    pub fn check_modal_groups_with(&self, dialect: Dialect) -> Result<(), SemanticError> {
+        let mut seen: Vec<(ModalGroup, Word)> = Vec::new();
+
+        for word in self.words() {
+            let group = match dialect.modal_group(word) {
+                Some(ModalGroup::NonModal) | None => continue,
+                Some(group) => group,
+            };
+
+            if let Some((_, first)) = seen.iter().find(|(other, first)| *other == group && !group.allows(first, word)) {
+                return Err(SemanticError::ModalGroupConflict {
+                    group,
+                    first_mnemonic: first.mnemonic(),
+                    first_value: first.value(),
+                    second_mnemonic: word.mnemonic(),
+                    second_value: word.value(),
+                });
+            }
+
+            seen.push((group, *word));
+        }
+
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn check(line: &str) -> Result<(), SemanticError> {
+        Parser::new().parse(line).unwrap().check_modal_groups()
+    }
+
+    #[test]
+    fn test_of() {
+        assert_eq!(ModalGroup::of(&Word::new('G', 1.0)), Some(ModalGroup::Motion));
+        assert_eq!(ModalGroup::of(&Word::new('G', 38.2)), Some(ModalGroup::Motion));
+        assert_eq!(ModalGroup::of(&Word::new('G', 59.3)), Some(ModalGroup::CoordinateSystem));
+        assert_eq!(ModalGroup::of(&Word::new('M', 30.0)), Some(ModalGroup::Stopping));
+        assert_eq!(ModalGroup::of(&Word::new('G', 1.5)), None);
+        assert_eq!(ModalGroup::of(&Word::new('X', 1.0)), None);
+    }
+
+    #[test]
+    fn test_check_modal_groups() {
+        assert!(check("G90 G21 G17 G1 X1 M3 S100").is_ok());
+        assert!(check("G4 P1 G92 X0").is_ok());
+        assert!(check("M7 M8").is_ok());
+
+        match check("G0 X1 G1 Y1") {
+            Err(SemanticError::ModalGroupConflict { group: ModalGroup::Motion, first_value, second_value, .. }) => {
+                assert_eq!((first_value, second_value), (0.0, 1.0));
+            }
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        assert!(check("G20 G21").is_err());
+        assert!(check("M3 M5").is_err());
+        assert!(check("M8 M9").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(check("G90 G91").unwrap_err().to_string(), "G90 and G91 are both in the distance mode modal group");
+    }
+}
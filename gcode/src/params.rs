@@ -0,0 +1,236 @@
+//! Numbered and named parameters of RS274/NGC.
+//!
+//! Words can take their values from parameters (`X#1`, `Z#<_depth>`) or expressions using them,
+//! and blocks can assign values to them (`#1 = 10`). The parser records these expressions on the
+//! block, leaving the value of the word itself at zero, and `Parameters` substitutes the actual
+//! values before a block is executed.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+use core::error;
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::Real;
+use crate::diagnostic::Code;
+use crate::expr::Function;
+#[cfg(feature = "std")]
+use crate::expr::Expr;
+#[cfg(feature = "std")]
+use crate::parser::Block;
+
+/// A reference to a parameter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Parameter {
+    /// A numbered parameter (`#1`).
+    Numbered(u32),
+
+    /// A named parameter (`#<name>`), with its name in lower case and without whitespace.
+    Named(String),
+}
+
+impl Parameter {
+    /// Creates a reference to a named parameter, normalizing its name like the parser does.
+    pub fn named<S>(name: S) -> Self
+        where S: AsRef<str> {
+        Parameter::Named(name.as_ref().chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| c.to_ascii_lowercase())
+                .collect())
+    }
+
+    /// Whether the parameter is global, i.e. a named one starting with an underscore.
+    pub fn is_global(&self) -> bool {
+        match self {
+            Parameter::Numbered(_) => false,
+            Parameter::Named(name) => name.starts_with('_'),
+        }
+    }
+}
+
+impl fmt::Display for Parameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Parameter::Numbered(number) => write!(f, "#{}", number),
+            Parameter::Named(name) => write!(f, "#<{}>", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParamError {
+    Undefined(Parameter),
+
+    InvalidNumber(Real),
+
+    DivisionByZero,
+
+    Domain {
+        function: Function,
+        value: Real,
+    },
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Undefined(parameter) => write!(f, "undefined parameter: {}", parameter),
+            ParamError::InvalidNumber(value) => write!(f, "invalid parameter number: {}", value),
+            ParamError::DivisionByZero => f.write_str("division by zero"),
+            ParamError::Domain { function, value } => write!(f, "{} is undefined for {}", function, value),
+        }
+    }
+}
+
+impl error::Error for ParamError {}
+
+impl ParamError {
+    /// The stable code identifying the kind of error (see `diagnostic::CODES`).
+    pub fn code(&self) -> Code {
+        match self {
+            ParamError::Undefined(_) => Code::error(401),
+            ParamError::InvalidNumber(_) => Code::error(402),
+            ParamError::DivisionByZero => Code::error(403),
+            ParamError::Domain { .. } => Code::error(404),
+        }
+    }
+}
+
+/// The last numbered parameter local to a subroutine, which receive the arguments of its call.
+pub const LOCALS: u32 = 30;
+
+/// The values of all parameters set so far.
+///
+/// Numbered parameters which have never been set read as zero, while reading an undefined named
+/// parameter is an error.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Parameters {
+    numbered: BTreeMap<u32, Real>,
+    named: BTreeMap<String, Real>,
+}
+
+impl Parameters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, parameter: &Parameter) -> Result<Real, ParamError> {
+        return match parameter {
+            Parameter::Numbered(number) => Ok(self.numbered.get(number).cloned().unwrap_or(0.0)),
+            Parameter::Named(name) => self.named.get(name).cloned()
+                    .ok_or_else(|| ParamError::Undefined(parameter.clone())),
+        };
+    }
+
+    pub fn set(&mut self, parameter: Parameter, value: Real) {
+        match parameter {
+            Parameter::Numbered(number) => self.numbered.insert(number, value),
+            Parameter::Named(name) => self.named.insert(name, value),
+        };
+    }
+
+    /// Removes all parameters local to a subroutine: the numbered ones up to `LOCALS` and the
+    /// named ones which are not global.
+    pub fn clear_locals(&mut self) {
+        self.numbered.retain(|&number, _| number > LOCALS);
+        self.named.retain(|name, _| name.starts_with('_'));
+    }
+
+    /// Restores the parameters local to a subroutine to their values in `saved`, keeping all
+    /// other parameters.
+    pub fn restore_locals(&mut self, saved: &Parameters) {
+        self.clear_locals();
+
+        self.numbered.extend(saved.numbered.iter().filter(|&(&number, _)| number <= LOCALS));
+        self.named.extend(saved.named.iter()
+                .filter(|(name, _)| !name.starts_with('_'))
+                .map(|(name, &value)| (name.clone(), value)));
+    }
+
+    /// Substitutes the values of all expressions of the block and applies its assignments,
+    /// returning the block without expressions and assignments.
+    ///
+    /// As defined by RS274/NGC, all values are read before any assignment of the block takes
+    /// effect. Nothing is assigned if a value can not be read.
+    #[cfg(feature = "std")]
+    pub fn resolve(&mut self, block: &Block) -> Result<Block, ParamError> {
+        let assignments = block.assignments().iter()
+                .map(|(parameter, value)| Ok((parameter.clone(), value.evaluate(self)?)))
+                .collect::<Result<Vec<_>, ParamError>>()?;
+
+        let resolved = block.resolve(|expr: &Expr| expr.evaluate(self))?;
+
+        for (parameter, value) in assignments {
+            self.set(parameter, value);
+        }
+
+        return Ok(resolved);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_resolve() {
+        let mut parser = Parser::new();
+        let mut parameters = Parameters::new();
+
+        let block = parameters.resolve(&parser.parse("#1 = 10 #<_Safe Z> = 5").unwrap()).unwrap();
+        assert!(block.is_empty());
+        assert_eq!(parameters.get(&Parameter::Numbered(1)).unwrap(), 10.0);
+        assert_eq!(parameters.get(&Parameter::named("_safez")).unwrap(), 5.0);
+
+        // Values are read before assigning
+        let block = parameters.resolve(&parser.parse("G0 X#1 Z[#<_safez> * 2] #1=#2").unwrap()).unwrap();
+        assert_eq!(block.words().iter().map(|word| word.value()).collect::<Vec<_>>(), vec![0.0, 10.0, 10.0]);
+        assert!(block.is_resolved());
+        assert_eq!(parameters.get(&Parameter::Numbered(1)).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_locals() {
+        let mut parameters = Parameters::new();
+        parameters.set(Parameter::Numbered(1), 1.0);
+        parameters.set(Parameter::Numbered(31), 31.0);
+        parameters.set(Parameter::named("local"), 2.0);
+        parameters.set(Parameter::named("_global"), 3.0);
+
+        let saved = parameters.clone();
+        parameters.clear_locals();
+        assert_eq!(parameters.get(&Parameter::Numbered(1)).unwrap(), 0.0);
+        assert!(parameters.get(&Parameter::named("local")).is_err());
+
+        parameters.set(Parameter::Numbered(1), 10.0);
+        parameters.set(Parameter::Numbered(31), 20.0);
+        parameters.set(Parameter::named("_global"), 30.0);
+        parameters.restore_locals(&saved);
+
+        assert_eq!(parameters.get(&Parameter::Numbered(1)).unwrap(), 1.0);
+        assert_eq!(parameters.get(&Parameter::Numbered(31)).unwrap(), 20.0);
+        assert_eq!(parameters.get(&Parameter::named("local")).unwrap(), 2.0);
+        assert_eq!(parameters.get(&Parameter::named("_global")).unwrap(), 30.0);
+    }
+
+    #[test]
+    fn test_undefined() {
+        let mut parameters = Parameters::new();
+        let block = Parser::new().parse("#2 = 1 G0 X#<depth>").unwrap();
+
+        match parameters.resolve(&block) {
+            Err(ParamError::Undefined(parameter)) => assert_eq!(parameter, Parameter::named("depth")),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // Nothing is assigned if a value is undefined
+        assert_eq!(parameters, Parameters::new());
+    }
+}
@@ -1,68 +1,244 @@
 // TODO: Checksums
 
-pub use self::parser::Parser;
+pub use self::lexer::{LexerError, Span, Token};
+pub use self::parser::{Block, Comment, CommentPosition, CommentStyle, Parser, ParserError, Provenance, Word};
+#[cfg(feature = "std")]
+pub use self::parser::Blocks;
+#[cfg(feature = "std")]
+pub(crate) use self::parser::quote;
 
 mod lexer {
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::error;
+    use core::fmt;
+
     use arrayvec::ArrayString;
-    use failure::Fail;
+    #[cfg(feature = "serde")]
+    use serde::{Deserialize, Serialize};
+
+    use crate::Real;
+    use crate::diagnostic::Code;
+    use crate::dialect::Dialect;
+    use crate::expr::Operator;
+    use super::parser::CommentStyle;
+
+    /// The location of a piece of input.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct Span {
+        /// The line number, starting at 1.
+        pub line: usize,
+
+        /// The column of the first character, starting at 1.
+        pub column: usize,
+
+        /// The byte offset of the first character, relative to the start of the line.
+        pub start: usize,
+
+        /// The byte offset after the last character, relative to the start of the line.
+        pub end: usize,
+    }
 
+    impl fmt::Display for Span {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}:{}", self.line, self.column)
+        }
+    }
 
-    #[derive(Debug, Fail)]
+    #[derive(Debug)]
+    #[non_exhaustive]
     pub enum LexerError {
-        #[fail(display = "illegal symbol: {}", symbol)]
         IllegalSymbol {
             symbol: char,
+            span: Span,
         },
 
-        #[fail(display = "invalid number: {}", text)]
         InvalidNumber {
             text: String,
+            span: Span,
+        },
+
+        InvalidName {
+            text: String,
+            span: Span,
+        },
+
+        UnknownIdentifier {
+            text: String,
+            span: Span,
+        },
+
+        UnterminatedString {
+            span: Span,
         },
     }
 
+    impl fmt::Display for LexerError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                LexerError::IllegalSymbol { symbol, span } => write!(f, "{}: illegal symbol: {}", span, symbol),
+                LexerError::InvalidNumber { text, span } => write!(f, "{}: invalid number: {}", span, text),
+                LexerError::InvalidName { text, span } => write!(f, "{}: invalid parameter name: {}", span, text),
+                LexerError::UnknownIdentifier { text, span } => write!(f, "{}: unknown operator or function: {}", span, text),
+                LexerError::UnterminatedString { span } => write!(f, "{}: unterminated string", span),
+            }
+        }
+    }
+
+    impl error::Error for LexerError {}
+
+    impl LexerError {
+        pub fn span(&self) -> Span {
+            match *self {
+                LexerError::IllegalSymbol { span, .. } => span,
+                LexerError::InvalidNumber { span, .. } => span,
+                LexerError::InvalidName { span, .. } => span,
+                LexerError::UnknownIdentifier { span, .. } => span,
+                LexerError::UnterminatedString { span } => span,
+            }
+        }
+
+        /// The stable code identifying the kind of error (see `diagnostic::CODES`).
+        pub fn code(&self) -> Code {
+            match *self {
+                LexerError::IllegalSymbol { .. } => Code::error(1),
+                LexerError::InvalidNumber { .. } => Code::error(2),
+                LexerError::InvalidName { .. } => Code::error(3),
+                LexerError::UnknownIdentifier { .. } => Code::error(4),
+                LexerError::UnterminatedString { .. } => Code::error(5),
+            }
+        }
+    }
+
     #[derive(Debug, Copy, Clone, PartialEq)]
     pub enum Token {
         BlockDelete,
         Letter(char),
-        Number(f64),
+        Number(Real),
         Demarcation,
+
+        /// The `#` starting a parameter reference.
+        Parameter,
+
+        /// The name of a parameter enclosed in `<` and `>`, in lower case and without whitespace.
+        Name(ArrayString<[u8; 64]>),
+
+        /// The `=` of a parameter assignment.
+        Assign,
+
+        LeftBracket,
+        RightBracket,
+
+        /// An operator written as symbol inside an expression (`+`, `-`, `*`, `/` and `**`).
+        Operator(Operator),
+
+        /// A word inside an expression naming a function or an operator (like `SIN` or `MOD`), in
+        /// upper case.
+        Identifier(ArrayString<[u8; 8]>),
+
+        /// A string enclosed in double quotes, with quotes inside doubled. The text is taken from
+        /// the span of the token, as the lexer does not keep it.
+        Quoted,
+    }
+
+    /// A location in the input.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct Position {
+        pub line: usize,
+        pub column: usize,
+        pub offset: usize,
+    }
+
+    impl Position {
+        #[cfg(test)]
+        pub fn start() -> Self {
+            Self {
+                line: 1,
+                column: 1,
+                offset: 0,
+            }
+        }
+
+        fn advance(&mut self, c: char) {
+            self.offset += c.len_utf8();
+
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
     }
 
     pub struct Reader<I> {
         input: I,
         current: Option<char>,
+
+        /// Position of the current character
+        position: Position,
+
+        /// Position of the character following the current one in the input
+        following: Position,
+
+        /// Offset after the last enhanced character
+        end: usize,
     }
 
     impl<I> Reader<I>
         where I: Iterator<Item=char> {
-        pub fn new(mut input: I) -> Self {
-            let current = Self::next(&mut input);
+        #[cfg(test)]
+        pub fn new(input: I) -> Self {
+            Self::with_position(input, Position::start())
+        }
 
-            return Self {
+        pub fn with_position(input: I, position: Position) -> Self {
+            let mut reader = Self {
                 input,
-                current,
+                current: None,
+                position,
+                following: position,
+                end: position.offset,
             };
+            reader.next();
+
+            return reader;
         }
 
-        fn next(input: &mut I) -> Option<char> {
-            let mut next = input.next();
-            while let Some(c) = next {
-                if c == ' ' || c == '\t' {
-                    next = input.next();
-                } else {
-                    return Some(c);
+        fn next(&mut self) {
+            loop {
+                let position = self.following;
+                let next = self.input.next();
+
+                if let Some(c) = next {
+                    self.following.advance(c);
+
+                    if c == ' ' || c == '\t' {
+                        continue;
+                    }
                 }
-            }
 
-            return None;
+                self.current = next;
+                self.position = position;
+                return;
+            }
         }
 
         pub fn current(&self) -> Option<char> { self.current }
 
+        /// The position of the current character or the end of input.
+        pub fn position(&self) -> Position { self.position }
+
+        /// The offset after the last enhanced character.
+        pub fn end(&self) -> usize { self.end }
+
         pub fn enhance(&mut self) -> char {
             let current = self.current.expect("Enhanced after end of input");
 
-            self.current = Self::next(&mut self.input);
+            self.end = self.position.offset + current.len_utf8();
+            self.next();
 
             return current;
         }
@@ -71,13 +247,63 @@ mod lexer {
     pub struct Lexer<I> {
         reader: Reader<I>,
 
+        /// Span of the last token
+        span: Span,
+
+        /// Spans of the comments skipped so far, including their delimiters
+        comments: Vec<Span>,
+
+        /// Number of brackets opened and not yet closed
+        depth: usize,
+
+        /// Comment styles and realtime characters to recognize
+        dialect: Dialect,
     }
 
     impl<I> Lexer<I>
         where I: Iterator<Item=char> {
+        #[cfg(test)]
         pub fn new(input: I) -> Self {
-            Self {
-                reader: Reader::new(input),
+            Self::with_position(input, Position::start())
+        }
+
+        pub fn with_position(input: I, position: Position) -> Self {
+            let reader = Reader::with_position(input, position);
+            let span = Span {
+                line: position.line,
+                column: position.column,
+                start: position.offset,
+                end: position.offset,
+            };
+
+            return Self {
+                reader,
+                span,
+                comments: Vec::new(),
+                depth: 0,
+                dialect: Dialect::default(),
+            };
+        }
+
+        pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+            self.dialect = dialect;
+            self
+        }
+
+        /// The span of the token returned last.
+        pub fn span(&self) -> Span { self.span }
+
+        /// Returns and clears the spans of the comments skipped so far.
+        pub fn take_comments(&mut self) -> Vec<Span> {
+            core::mem::take(&mut self.comments)
+        }
+
+        fn span_from(&self, start: Position) -> Span {
+            Span {
+                line: start.line,
+                column: start.column,
+                start: start.offset,
+                end: self.reader.end().max(start.offset),
             }
         }
 
@@ -109,27 +335,69 @@ mod lexer {
         }
 
         pub fn next(&mut self) -> Result<Option<Token>, LexerError> {
-            // Skip comments
-            if self.reader.current() == Some(';') { self.accept_while(|c| c != '\n', |_| {}) };
-            if self.reader.current() == Some('(') { self.accept_until(|c| c == ')', |_| {}) };
+            // Skip comments, remembering where they are, and realtime characters
+            loop {
+                let start = self.reader.position();
+                let styles = self.dialect.comment_styles();
+                match self.reader.current() {
+                    Some(';') if styles.contains(&CommentStyle::Semicolon) => self.accept_while(|c| c != '\n', |_| {}),
+                    Some('(') if styles.contains(&CommentStyle::Parentheses) => self.accept_until(|c| c == ')', |_| {}),
+                    Some(c) if self.dialect.realtime_characters().contains(&c) => {
+                        self.reader.enhance();
+                        continue;
+                    }
+                    _ => break,
+                }
+
+                let span = self.span_from(start);
+                self.comments.push(span);
+            }
+
+            let start = self.reader.position();
 
             // generate tokens
-            return match self.reader.current() {
+            let token = match self.reader.current() {
+                Some('[') => {
+                    self.depth += 1;
+                    self.tok_symbol(Token::LeftBracket)
+                }
+                Some(']') => {
+                    self.depth = self.depth.saturating_sub(1);
+                    self.tok_symbol(Token::RightBracket)
+                }
+
+                // Inside of expressions, signs are operators and letters form words
+                Some(c) if self.depth > 0 && "+-*/".contains(c) => self.tok_operator(),
+                Some(c) if self.depth > 0 && c.is_ascii_alphabetic() => self.tok_identifier(start),
+                Some('.') if self.depth > 0 => self.tok_number(start),
+
                 Some('/') => self.tok_block_delete(),
                 Some('%') => self.tok_demarcation(),
+                Some('#') => self.tok_symbol(Token::Parameter),
+                Some('=') => self.tok_symbol(Token::Assign),
+                Some('<') => self.tok_name(start),
+                Some('"') if self.dialect.has_quoted_strings() => self.tok_quoted(start),
 
                 Some(c) if c.is_ascii_alphabetic() => self.tok_letter(),
 
-                Some('+') | Some('-') | Some('.') => self.tok_number(),
-                Some(c) if c.is_numeric() => self.tok_number(),
+                Some('+') | Some('-') | Some('.') => self.tok_number(start),
+                Some(c) if c.is_numeric() => self.tok_number(start),
 
                 Some(c) => {
-                    Err(LexerError::IllegalSymbol { symbol: c })
+                    let span = Span {
+                        end: start.offset + c.len_utf8(),
+                        ..self.span_from(start)
+                    };
+                    Err(LexerError::IllegalSymbol { symbol: c, span })
                 }
                 None => {
                     Ok(None)
                 }
             };
+
+            self.span = self.span_from(start);
+
+            return token;
         }
 
         fn tok_block_delete(&mut self) -> Result<Option<Token>, LexerError> {
@@ -146,6 +414,81 @@ mod lexer {
             return Ok(Some(Token::Demarcation));
         }
 
+        fn tok_symbol(&mut self, token: Token) -> Result<Option<Token>, LexerError> {
+            self.reader.enhance();
+
+            return Ok(Some(token));
+        }
+
+        fn tok_name(&mut self, start: Position) -> Result<Option<Token>, LexerError> {
+            let c = self.reader.enhance();
+            debug_assert_eq!('<', c);
+
+            let mut buffer = ArrayString::<[u8; 64]>::new();
+            let mut overflow = false;
+
+            // Whitespaces are skipped by the reader, which is what RS274/NGC wants for names
+            self.accept_while(|c| c != '>', |c| overflow |= buffer.try_push(c.to_ascii_lowercase()).is_err());
+
+            if self.reader.current() != Some('>') || overflow || buffer.is_empty() {
+                return Err(LexerError::InvalidName { text: buffer.to_string(), span: self.span_from(start) });
+            }
+            self.reader.enhance();
+
+            return Ok(Some(Token::Name(buffer)));
+        }
+
+        fn tok_quoted(&mut self, start: Position) -> Result<Option<Token>, LexerError> {
+            let c = self.reader.enhance();
+            debug_assert_eq!('"', c);
+
+            loop {
+                match self.reader.current() {
+                    None => return Err(LexerError::UnterminatedString { span: self.span_from(start) }),
+                    Some('"') => {
+                        self.reader.enhance();
+
+                        // A quote directly following the closing one is an escaped quote
+                        if self.reader.current() != Some('"') || self.reader.position().offset != self.reader.end() {
+                            return Ok(Some(Token::Quoted));
+                        }
+                        self.reader.enhance();
+                    }
+                    Some(_) => {
+                        self.reader.enhance();
+                    }
+                }
+            }
+        }
+
+        fn tok_operator(&mut self) -> Result<Option<Token>, LexerError> {
+            let operator = match self.reader.enhance() {
+                '+' => Operator::Add,
+                '-' => Operator::Subtract,
+                '/' => Operator::Divide,
+                _ if self.reader.current() == Some('*') => {
+                    self.reader.enhance();
+                    Operator::Power
+                }
+                _ => Operator::Multiply,
+            };
+
+            return Ok(Some(Token::Operator(operator)));
+        }
+
+        fn tok_identifier(&mut self, start: Position) -> Result<Option<Token>, LexerError> {
+            let mut buffer = ArrayString::<[u8; 8]>::new();
+            let mut overflow = false;
+
+            self.accept_while(|c| c.is_ascii_alphabetic(), |c| overflow |= buffer.try_push(c.to_ascii_uppercase()).is_err());
+
+            if overflow {
+                return Err(LexerError::UnknownIdentifier { text: buffer.to_string(), span: self.span_from(start) });
+            }
+
+            return Ok(Some(Token::Identifier(buffer)));
+        }
+
         fn tok_letter(&mut self) -> Result<Option<Token>, LexerError> {
             let c = self.reader.enhance();
             debug_assert!(c.is_ascii_alphabetic());
@@ -153,16 +496,19 @@ mod lexer {
             return Ok(Some(Token::Letter(c.to_ascii_uppercase())));
         }
 
-        fn tok_number(&mut self) -> Result<Option<Token>, LexerError> {
+        fn tok_number(&mut self, start: Position) -> Result<Option<Token>, LexerError> {
             let mut buffer = ArrayString::<[u8; 32]>::new();
+            let mut overflow = false;
 
-            // There can be whitespaces inside a number - just skip them
-            self.accept_while(|c| c.is_numeric() || c == '+' || c == '-' || c == '.',
-                              |c| buffer.push(c));
+            // There can be whitespaces inside a number - just skip them. Inside of expressions,
+            // signs are operators instead.
+            let signs = self.depth == 0;
+            self.accept_while(|c| c.is_numeric() || c == '.' || (signs && (c == '+' || c == '-')),
+                              |c| overflow |= buffer.try_push(c).is_err());
 
             return match buffer.parse() {
-                Ok(value) => Ok(Some(Token::Number(value))),
-                Err(err) => Err(LexerError::InvalidNumber { text: buffer.to_string() }),
+                Ok(value) if !overflow => Ok(Some(Token::Number(value))),
+                _ => Err(LexerError::InvalidNumber { text: buffer.to_string(), span: self.span_from(start) }),
             };
         }
     }
@@ -211,6 +557,19 @@ mod lexer {
             assert_eq!(None, r.current());
         }
 
+        #[test]
+        fn test_reader_position() {
+            let mut r = Reader::new("x\n y".chars());
+            assert_eq!(r.position(), Position { line: 1, column: 1, offset: 0 });
+            assert_eq!('x', r.enhance());
+            assert_eq!(r.position(), Position { line: 1, column: 2, offset: 1 });
+            assert_eq!('\n', r.enhance());
+            assert_eq!(r.position(), Position { line: 2, column: 2, offset: 3 });
+            assert_eq!('y', r.enhance());
+            assert_eq!(r.position(), Position { line: 2, column: 3, offset: 4 });
+            assert_eq!(r.end(), 4);
+        }
+
         #[test]
         fn test_lex_empty() {
             let mut l = Lexer::new("".chars());
@@ -265,6 +624,46 @@ mod lexer {
             assert_eq!(l.next().unwrap(), Some(Token::Number(42.3)));
         }
 
+        #[test]
+        fn test_lex_parameters() {
+            let mut l = Lexer::new("#1 = #<_Safe Z> X#2".chars());
+            assert_eq!(l.next().unwrap(), Some(Token::Parameter));
+            assert_eq!(l.next().unwrap(), Some(Token::Number(1.0)));
+            assert_eq!(l.next().unwrap(), Some(Token::Assign));
+            assert_eq!(l.next().unwrap(), Some(Token::Parameter));
+            assert_eq!(l.next().unwrap(), Some(Token::Name(ArrayString::from("_safez").unwrap())));
+            assert_eq!(l.next().unwrap(), Some(Token::Letter('X')));
+            assert_eq!(l.next().unwrap(), Some(Token::Parameter));
+            assert_eq!(l.next().unwrap(), Some(Token::Number(2.0)));
+            assert_eq!(l.next().unwrap(), None);
+
+            let mut l = Lexer::new("#<depth".chars());
+            l.next().unwrap();
+            assert_eq!(l.next().unwrap_err().span(), Span { line: 1, column: 2, start: 1, end: 7 });
+        }
+
+        #[test]
+        fn test_lex_expressions() {
+            let mut l = Lexer::new("X[1-#2 ** sin[.5]] Y-1".chars());
+            assert_eq!(l.next().unwrap(), Some(Token::Letter('X')));
+            assert_eq!(l.next().unwrap(), Some(Token::LeftBracket));
+            assert_eq!(l.next().unwrap(), Some(Token::Number(1.0)));
+            assert_eq!(l.next().unwrap(), Some(Token::Operator(Operator::Subtract)));
+            assert_eq!(l.next().unwrap(), Some(Token::Parameter));
+            assert_eq!(l.next().unwrap(), Some(Token::Number(2.0)));
+            assert_eq!(l.next().unwrap(), Some(Token::Operator(Operator::Power)));
+            assert_eq!(l.next().unwrap(), Some(Token::Identifier(ArrayString::from("SIN").unwrap())));
+            assert_eq!(l.next().unwrap(), Some(Token::LeftBracket));
+            assert_eq!(l.next().unwrap(), Some(Token::Number(0.5)));
+            assert_eq!(l.next().unwrap(), Some(Token::RightBracket));
+            assert_eq!(l.next().unwrap(), Some(Token::RightBracket));
+
+            // Outside of expressions, signs belong to numbers again
+            assert_eq!(l.next().unwrap(), Some(Token::Letter('Y')));
+            assert_eq!(l.next().unwrap(), Some(Token::Number(-1.0)));
+            assert_eq!(l.next().unwrap(), None);
+        }
+
         #[test]
         fn test_lex_whitespaces() {
             let mut l = Lexer::new(" / N123 G1  ".chars());
@@ -289,6 +688,37 @@ mod lexer {
             assert_eq!(l.next().unwrap(), None);
         }
 
+        #[test]
+        fn test_lex_span() {
+            let mut l = Lexer::new("G1 (comment) X - 12.5".chars());
+            assert_eq!(l.next().unwrap(), Some(Token::Letter('G')));
+            assert_eq!(l.span(), Span { line: 1, column: 1, start: 0, end: 1 });
+            assert_eq!(l.next().unwrap(), Some(Token::Number(1.0)));
+            assert_eq!(l.span(), Span { line: 1, column: 2, start: 1, end: 2 });
+            assert_eq!(l.next().unwrap(), Some(Token::Letter('X')));
+            assert_eq!(l.span(), Span { line: 1, column: 14, start: 13, end: 14 });
+            assert_eq!(l.next().unwrap(), Some(Token::Number(-12.5)));
+            assert_eq!(l.span(), Span { line: 1, column: 16, start: 15, end: 21 });
+        }
+
+        #[test]
+        fn test_lex_error_span() {
+            let mut l = Lexer::new("G1 \u{e4}".chars());
+            l.next().unwrap();
+            l.next().unwrap();
+            match l.next() {
+                Err(LexerError::IllegalSymbol { symbol, span }) => {
+                    assert_eq!(symbol, '\u{e4}');
+                    assert_eq!(span, Span { line: 1, column: 4, start: 3, end: 5 });
+                }
+                result => panic!("unexpected result: {:?}", result),
+            }
+
+            let mut l = Lexer::new("X1.2.3".chars());
+            l.next().unwrap();
+            assert_eq!(l.next().unwrap_err().span(), Span { line: 1, column: 2, start: 1, end: 6 });
+        }
+
         #[test]
         fn test_lex_block_comment() {
             let mut l = Lexer::new("G (ignored) G".chars());
@@ -303,25 +733,139 @@ mod lexer {
             assert_eq!(l.next().unwrap(), Some(Token::Letter('G')));
             assert_eq!(l.next().unwrap(), None);
         }
+
+        #[test]
+        fn test_lex_comment_spans() {
+            let mut l = Lexer::new("(a)(b c) G ; d".chars());
+            assert_eq!(l.next().unwrap(), Some(Token::Letter('G')));
+            assert_eq!(l.next().unwrap(), None);
+
+            let comments = l.take_comments();
+            assert_eq!(comments.iter().map(|span| (span.start, span.end)).collect::<Vec<_>>(),
+                       vec![(0, 3), (3, 8), (11, 14)]);
+            assert!(l.take_comments().is_empty());
+        }
     }
 }
 
 mod parser {
-    use failure::Fail;
-    use super::lexer::{Lexer, LexerError, Token};
-
-    #[derive(Debug, Fail)]
+    use alloc::borrow::ToOwned;
+    use alloc::boxed::Box;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use core::error;
+    use core::fmt;
+    #[cfg(feature = "std")]
+    use std::io::{self, BufRead};
+
+    #[cfg(feature = "serde")]
+    use serde::{Deserialize, Serialize};
+    use crate::Real;
+    use crate::control::{Control, Keyword, Label};
+    use crate::diagnostic::Code;
+    use crate::dialect::Dialect;
+    use crate::expr::{Expr, Function, Operator};
+    use crate::params::Parameter;
+    use crate::remap::AxisMap;
+    use super::lexer::{Lexer, LexerError, Position, Span, Token};
+
+    #[derive(Debug)]
+    #[non_exhaustive]
     pub enum ParserError {
-        #[fail(display = "syntax error: {}", 0)]
         SyntaxError(LexerError),
 
-        #[fail(display = "unexpected token: {:?}", token)]
         UnexpectedToken {
             token: Token,
+            span: Span,
         },
 
-        #[fail(display = "missing value")]
-        MissingValue,
+        MissingValue {
+            span: Span,
+        },
+
+        InvalidParameter {
+            value: Real,
+            span: Span,
+        },
+
+        UnknownIdentifier {
+            name: String,
+            span: Span,
+        },
+
+        UnsupportedLetter {
+            letter: char,
+            span: Span,
+        },
+
+        #[cfg(feature = "std")]
+        Io {
+            line: usize,
+
+            error: io::Error,
+        },
+    }
+
+    impl fmt::Display for ParserError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ParserError::SyntaxError(err) => write!(f, "syntax error: {}", err),
+                ParserError::UnexpectedToken { token, span } => write!(f, "{}: unexpected token: {:?}", span, token),
+                ParserError::MissingValue { span } => write!(f, "{}: missing value", span),
+                ParserError::InvalidParameter { value, span } => write!(f, "{}: invalid parameter number: {}", span, value),
+                ParserError::UnknownIdentifier { name, span } => write!(f, "{}: unknown operator or function: {}", span, name),
+                ParserError::UnsupportedLetter { letter, span } => write!(f, "{}: letter not supported by the dialect: {}", span, letter),
+                #[cfg(feature = "std")]
+                ParserError::Io { line, error } => write!(f, "{}: read error: {}", line, error),
+            }
+        }
+    }
+
+    impl error::Error for ParserError {
+        fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+            match self {
+                #[cfg(feature = "std")]
+                ParserError::Io { error, .. } => Some(error),
+                _ => None,
+            }
+        }
+    }
+
+    impl ParserError {
+        /// The location of the offending input.
+        pub fn span(&self) -> Span {
+            match *self {
+                ParserError::SyntaxError(ref err) => err.span(),
+                ParserError::UnexpectedToken { span, .. } => span,
+                ParserError::MissingValue { span } => span,
+                ParserError::InvalidParameter { span, .. } => span,
+                ParserError::UnknownIdentifier { span, .. } => span,
+                ParserError::UnsupportedLetter { span, .. } => span,
+                #[cfg(feature = "std")]
+                ParserError::Io { line, .. } => Span {
+                    line,
+                    column: 1,
+                    start: 0,
+                    end: 0,
+                },
+            }
+        }
+
+        /// The stable code identifying the kind of error (see `diagnostic::CODES`).
+        pub fn code(&self) -> Code {
+            match *self {
+                ParserError::SyntaxError(ref err) => err.code(),
+                ParserError::UnexpectedToken { .. } => Code::error(10),
+                ParserError::MissingValue { .. } => Code::error(11),
+                ParserError::InvalidParameter { .. } => Code::error(12),
+                ParserError::UnknownIdentifier { .. } => Code::error(4),
+                ParserError::UnsupportedLetter { .. } => Code::error(13),
+                #[cfg(feature = "std")]
+                ParserError::Io { .. } => Code::error(14),
+            }
+        }
     }
 
     impl From<LexerError> for ParserError {
@@ -331,121 +875,1281 @@ mod parser {
     }
 
     #[derive(Debug, Copy, Clone, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
     pub struct Word {
         mnemonic: char,
-        value: f64,
-    }
-
-    #[derive(Debug, Clone, PartialEq)]
-    pub struct Block {
-        line_number: Option<f64>,
-        deleted: bool,
-
-        words: Vec<Word>,
-
-        line: String,
+        value: Real,
     }
 
-    impl Block {
-        pub fn empty(line: &str) -> Self {
+    impl Word {
+        /// Creates a word from its letter and value. The letter is normalized to upper case.
+        pub fn new(mnemonic: char, value: Real) -> Self {
             Self {
-                line_number: None,
-                deleted: false,
-                words: Vec::new(),
-                line: line.to_owned(),
+                mnemonic: mnemonic.to_ascii_uppercase(),
+                value,
             }
         }
 
-        pub fn is_empty(&self) -> bool {
-            self.words.is_empty()
+        pub fn mnemonic(&self) -> char {
+            self.mnemonic
+        }
+
+        pub fn value(&self) -> Real {
+            self.value
         }
     }
 
-    pub struct Parser {}
+    /// Where a block originates from and which passes touched it on its way.
+    #[derive(Debug, Clone, PartialEq, Default)]
+    pub struct Provenance {
+        source: Option<Arc<str>>,
+        line: Option<usize>,
+        passes: Vec<&'static str>,
+    }
 
-    impl Parser {
-        pub fn new() -> Self {
-            Self {}
+    impl Provenance {
+        /// The name of the source the block was parsed from, usually a file name.
+        pub fn source(&self) -> Option<&str> {
+            self.source.as_deref()
         }
 
-        pub fn parse_all<I, S>(&mut self, input: I) -> Result<Vec<Block>, ParserError>
-            where I: Iterator<Item=S>,
-                  S: AsRef<str> {
-            return input.map(|line| self.parse(line))
-                    .collect();
+        /// The line number (starting at 1) the block was parsed from.
+        pub fn line(&self) -> Option<usize> {
+            self.line
         }
 
-        pub fn parse<S>(&mut self, line: S) -> Result<Block, ParserError>
-            where S: AsRef<str> {
-            let line = line.as_ref().trim();
+        /// The names of all passes which created or modified the block, in order.
+        pub fn passes(&self) -> &[&'static str] {
+            &self.passes
+        }
+    }
 
-            let mut block = Block::empty(line);
+    impl fmt::Display for Provenance {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match (&self.source, self.line) {
+                (Some(source), Some(line)) => write!(f, "{}:{}", source, line)?,
+                (Some(source), None) => write!(f, "{}", source)?,
+                (None, Some(line)) => write!(f, "line {}", line)?,
+                (None, None) => write!(f, "generated")?,
+            }
 
-            let mut lexer = Lexer::new(line.chars());
-            let mut current = lexer.next()?;
+            if !self.passes.is_empty() {
+                write!(f, " ({})", self.passes.join(", "))?;
+            }
 
-            // FIXME: Implement demarcation handling
+            return Ok(());
+        }
+    }
 
-            if current == Some(Token::BlockDelete) {
-                block.deleted = true;
-                current = lexer.next()?;
-            }
+    /// The delimiters of a comment.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum CommentStyle {
+        /// Enclosed in `(` and `)`.
+        Parentheses,
 
-            loop {
-                match current {
-                    None => break,
+        /// Started by `;` and running to the end of the line.
+        Semicolon,
+    }
 
-                    Some(Token::Letter(letter)) => {
-                        current = lexer.next()?;
-                        match current {
-                            Some(Token::Number(value)) => {
-                                current = lexer.next()?;
-                                if letter == 'N' {
-                                    block.line_number = Some(value);
-                                } else {
-                                    block.words.push(Word {
-                                        mnemonic: letter,
-                                        value,
-                                    });
-                                }
-                            }
-                            Some(token) => {
-                                return Err(ParserError::UnexpectedToken { token });
-                            }
-                            None => {
-                                return Err(ParserError::MissingValue);
-                            }
-                        }
-                    }
+    /// Where a comment is placed relative to the words of its block.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum CommentPosition {
+        /// Before the first word, after the block delete and line number.
+        Leading,
 
-                    Some(token) => {
-                        return Err(ParserError::UnexpectedToken { token });
-                    }
-                }
-            }
+        /// Before the word at the given index.
+        Inline(usize),
 
-            return Ok(block);
-        }
+        /// After the last word.
+        Trailing,
     }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct Comment {
+        text: String,
+        style: CommentStyle,
+        position: CommentPosition,
+    }
 
-        #[test]
-        fn test_parser_empty() {
-            let b = Parser::new().parse("").unwrap();
-            assert!(b.is_empty());
+    impl Comment {
+        pub fn new<S>(text: S, style: CommentStyle, position: CommentPosition) -> Self
+            where S: Into<String> {
+            Self {
+                text: text.into(),
+                style,
+                position,
+            }
         }
 
-        #[test]
+        /// The text of the comment without its delimiters.
+        pub fn text(&self) -> &str {
+            &self.text
+        }
+
+        pub fn style(&self) -> CommentStyle {
+            self.style
+        }
+
+        pub fn position(&self) -> CommentPosition {
+            self.position
+        }
+    }
+
+    /// Writes the comment including its delimiters.
+    impl fmt::Display for Comment {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.style {
+                CommentStyle::Parentheses => write!(f, "({})", self.text),
+                CommentStyle::Semicolon => write!(f, ";{}", self.text),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub struct Block {
+        line_number: Option<Real>,
+        deleted: bool,
+
+        words: Vec<Word>,
+
+        /// Indices of the words taking their values from expressions
+        expressions: Vec<(usize, Expr)>,
+
+        assignments: Vec<(Parameter, Expr)>,
+
+        /// The O-word of the block, taking the place of all other words
+        control: Option<Control>,
+
+        /// Free text following the words, like the message of `M117`
+        argument: Option<String>,
+
+        /// Indices of the words taking quoted strings as values
+        strings: Vec<(usize, String)>,
+
+        /// The text following the `$` of a Grbl system command, taking the place of everything else
+        system: Option<String>,
+
+        comments: Vec<Comment>,
+
+        line: String,
+
+        /// The exact text of the line the block was parsed from in lossless mode, until modified
+        original: Option<String>,
+
+        /// Locations of the words and their values in the source line, if parsed
+        spans: Vec<(Span, Span)>,
+
+        /// Locations of the comments in the source line, including their delimiters, if parsed
+        comment_spans: Vec<Span>,
+
+        /// Where the block came from, which is specific to the process and not serialized
+        #[cfg_attr(feature = "serde", serde(skip))]
+        provenance: Provenance,
+    }
+
+    /// Blocks are compared by their content, regardless of their location and provenance.
+    impl PartialEq for Block {
+        fn eq(&self, other: &Self) -> bool {
+            self.line_number == other.line_number
+                    && self.deleted == other.deleted
+                    && self.words == other.words
+                    && self.expressions == other.expressions
+                    && self.assignments == other.assignments
+                    && self.control == other.control
+                    && self.argument == other.argument
+                    && self.strings == other.strings
+                    && self.system == other.system
+                    && self.comments == other.comments
+                    && self.line == other.line
+        }
+    }
+
+    impl Block {
+        /// Creates a block from the given words, rendering its line from them.
+        pub fn new(words: Vec<Word>) -> Self {
+            let mut block = Self {
+                line_number: None,
+                deleted: false,
+                words,
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
+                line: String::new(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
+            };
+            block.render();
+
+            return block;
+        }
+
+        /// Sets the line number (N word) of the block and re-renders its line.
+        pub fn with_line_number(mut self, line_number: Option<Real>) -> Self {
+            self.line_number = line_number;
+            self.render();
+            self
+        }
+
+        /// Marks the block as deleted (`/`) and re-renders its line.
+        pub fn with_deleted(mut self, deleted: bool) -> Self {
+            self.deleted = deleted;
+            self.render();
+            self
+        }
+
+        fn render(&mut self) {
+            self.original = None;
+
+            if let Some(ref system) = self.system {
+                self.line = format!("${}", system);
+                return;
+            }
+
+            let mut parts = Vec::new();
+
+            if self.deleted {
+                parts.push("/".to_owned());
+            }
+
+            if let Some(line_number) = self.line_number {
+                parts.push(format!("N{}", format_value(line_number)));
+            }
+
+            parts.extend(self.words.iter()
+                    .enumerate()
+                    .map(|(i, word)| match (self.expression(i), self.string(i)) {
+                        (Some(expr), _) => format!("{}{}", word.mnemonic, expr),
+                        (None, Some(text)) => format!("{}{}", word.mnemonic, quote(text)),
+                        (None, None) => format!("{}{}", word.mnemonic, format_value(word.value)),
+                    }));
+
+            parts.extend(self.argument.iter().cloned());
+
+            parts.extend(self.assignments.iter()
+                    .map(|(parameter, value)| format!("{}={}", parameter, value)));
+
+            parts.extend(self.control.iter().map(ToString::to_string));
+
+            self.line = parts.join(" ");
+        }
+
+        pub fn empty(line: &str) -> Self {
+            Self {
+                line_number: None,
+                deleted: false,
+                words: Vec::new(),
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
+                line: line.to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
+            }
+        }
+
+        /// Whether the block has neither words, parameter assignments, an O-word nor a system
+        /// command.
+        pub fn is_empty(&self) -> bool {
+            self.words.is_empty() && self.assignments.is_empty() && self.control.is_none() && self.system.is_none()
+        }
+
+        pub fn line_number(&self) -> Option<Real> {
+            self.line_number
+        }
+
+        pub fn is_deleted(&self) -> bool {
+            self.deleted
+        }
+
+        pub fn words(&self) -> &[Word] {
+            &self.words
+        }
+
+        /// The words taking their values from parameters or expressions, by index. The values of
+        /// these words are zero until the block is resolved (see `params::Parameters::resolve`).
+        pub fn expressions(&self) -> &[(usize, Expr)] {
+            &self.expressions
+        }
+
+        /// The expression giving the value of the word at `index`, if any.
+        pub fn expression(&self, index: usize) -> Option<&Expr> {
+            self.expressions.iter()
+                    .find(|(i, _)| *i == index)
+                    .map(|(_, expr)| expr)
+        }
+
+        /// The values assigned to parameters by the block, in order.
+        pub fn assignments(&self) -> &[(Parameter, Expr)] {
+            &self.assignments
+        }
+
+        /// Whether the block has neither expressions nor assignments.
+        pub fn is_resolved(&self) -> bool {
+            self.expressions.is_empty() && self.assignments.is_empty()
+        }
+
+        /// Lets the word at `index` take its value from an expression and re-renders its line.
+        pub fn with_expression(mut self, index: usize, expr: Expr) -> Self {
+            self.expressions.retain(|(i, _)| *i != index);
+            self.expressions.push((index, expr));
+            self.expressions.sort_by_key(|(i, _)| *i);
+            self.render();
+            self
+        }
+
+        /// Adds an assignment of a value to a parameter and re-renders its line.
+        pub fn with_assignment(mut self, parameter: Parameter, value: Expr) -> Self {
+            self.assignments.push((parameter, value));
+            self.render();
+            self
+        }
+
+        /// Replaces the values of the words given by expressions by the values returned by
+        /// `value` and drops the assignments, keeping the source line and the locations of the
+        /// words in it.
+        pub fn resolve<F, E>(&self, mut value: F) -> Result<Block, E>
+            where F: FnMut(&Expr) -> Result<Real, E> {
+            let mut block = self.clone();
+            for (index, expr) in block.expressions.drain(..) {
+                block.words[index].value = value(&expr)?;
+            }
+            block.assignments.clear();
+
+            return Ok(block);
+        }
+
+        /// The O-word of the block, if any (see `control::Flow`).
+        pub fn control(&self) -> Option<&Control> {
+            self.control.as_ref()
+        }
+
+        /// Makes the block an O-word block and re-renders its line.
+        pub fn with_control(mut self, control: Control) -> Self {
+            self.control = Some(control);
+            self.render();
+            self
+        }
+
+        /// The words taking quoted strings as values, like `P"macro.g"`, by index. The values of
+        /// these words are zero.
+        pub fn strings(&self) -> &[(usize, String)] {
+            &self.strings
+        }
+
+        /// The quoted string given as value of the word at `index`, if any.
+        pub fn string(&self, index: usize) -> Option<&str> {
+            self.strings.iter()
+                    .find(|(i, _)| *i == index)
+                    .map(|(_, text)| text.as_str())
+        }
+
+        /// Lets the word at `index` take a quoted string as value and re-renders its line.
+        pub fn with_string<S>(mut self, index: usize, text: S) -> Self
+            where S: Into<String> {
+            self.strings.retain(|(i, _)| *i != index);
+            self.strings.push((index, text.into()));
+            self.strings.sort_by_key(|(i, _)| *i);
+            self.render();
+            self
+        }
+
+        /// The text following the `$` of a Grbl system command like `$H` or `$J=G91 X1 F100`, if
+        /// the block is one.
+        pub fn system_command(&self) -> Option<&str> {
+            self.system.as_deref()
+        }
+
+        /// Makes the block a Grbl system command and re-renders its line.
+        pub fn with_system_command<S>(mut self, text: S) -> Self
+            where S: Into<String> {
+            self.system = Some(text.into());
+            self.render();
+            self
+        }
+
+        /// The free text argument of commands like `M117 Hello world`, if the dialect has them
+        /// (see `Dialect::takes_string_argument`).
+        pub fn argument(&self) -> Option<&str> {
+            self.argument.as_deref()
+        }
+
+        /// Sets the free text argument of the block and re-renders its line.
+        pub fn with_argument(mut self, argument: Option<String>) -> Self {
+            self.argument = argument;
+            self.render();
+            self
+        }
+
+        pub fn comments(&self) -> &[Comment] {
+            &self.comments
+        }
+
+        /// Adds a comment to the block.
+        pub fn with_comment(mut self, comment: Comment) -> Self {
+            self.comments.push(comment);
+            self.original = None;
+            self
+        }
+
+        /// Removes all comments from the block.
+        pub fn without_comments(mut self) -> Self {
+            if !self.comments.is_empty() {
+                self.comments.clear();
+                self.comment_spans.clear();
+                self.original = None;
+            }
+            self
+        }
+
+        /// The source line this block was parsed from.
+        pub fn line(&self) -> &str {
+            &self.line
+        }
+
+        /// The exact text of the line the block was parsed from, including its whitespace and
+        /// comments, if parsed in lossless mode (see `Parser::with_lossless`).
+        ///
+        /// The text is dropped as soon as the block is modified, so it always matches the block.
+        pub fn original(&self) -> Option<&str> {
+            self.original.as_deref()
+        }
+
+        /// The location of the word at `index` in the source line, from its letter to the end of
+        /// its value.
+        ///
+        /// Only available for blocks created by the parser.
+        pub fn span(&self, index: usize) -> Option<Span> {
+            self.spans.get(index).map(|&(word, _)| word)
+        }
+
+        /// The location of the value of the word at `index` in the source line.
+        ///
+        /// Only available for blocks created by the parser.
+        pub fn value_span(&self, index: usize) -> Option<Span> {
+            self.spans.get(index).map(|&(_, value)| value)
+        }
+
+        /// The location of the comment at `index` in the source line, including its delimiters.
+        ///
+        /// Only available for blocks created by the parser.
+        pub fn comment_span(&self, index: usize) -> Option<Span> {
+            self.comment_spans.get(index).copied()
+        }
+
+        /// The index of the word covering the byte at `offset` in the source line, e.g. the one
+        /// under the cursor of an editor.
+        pub fn word_at(&self, offset: usize) -> Option<usize> {
+            self.spans.iter().position(|(word, _)| word.start <= offset && offset < word.end)
+        }
+
+        /// Replaces the value of the word at `index` in `source`, the untrimmed line this block was
+        /// parsed from, keeping all other bytes of the line untouched.
+        ///
+        /// Returns `None` if the location of the word is unknown or does not fit the given line.
+        pub fn edit_value(&self, source: &str, index: usize, value: Real) -> Option<String> {
+            let span = self.value_span(index)?;
+            if span.end > source.len() || !source.is_char_boundary(span.start) || !source.is_char_boundary(span.end) {
+                return None;
+            }
+
+            return Some(format!("{}{}{}", &source[..span.start], format_value(value), &source[span.end..]));
+        }
+
+        pub fn provenance(&self) -> &Provenance {
+            &self.provenance
+        }
+
+        /// Replaces the provenance, e.g. to carry it over from the block this one was derived from.
+        pub fn with_provenance(mut self, provenance: Provenance) -> Self {
+            self.provenance = provenance;
+            self
+        }
+
+        /// Records that the block was created or modified by the given pass.
+        pub fn with_pass(mut self, pass: &'static str) -> Self {
+            self.provenance.passes.push(pass);
+            self
+        }
+
+        /// Replaces each word by the result of `f`, keeping the source line and the locations of
+        /// the words in it. The exact text of the line is dropped if any word changes.
+        pub fn map_words<F>(mut self, f: F) -> Self
+            where F: FnMut(&Word) -> Word {
+            let words = self.words.iter().map(f).collect::<Vec<_>>();
+            if words != self.words {
+                self.words = words;
+                self.original = None;
+            }
+            self
+        }
+    }
+
+    /// Encloses the text in double quotes, doubling the quotes inside.
+    pub(crate) fn quote(text: &str) -> String {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    }
+
+    /// Splits a line at the end of the first M word taking a free text argument, returning the
+    /// words before and the text after.
+    fn split_argument(line: &str, dialect: Dialect) -> (&str, Option<&str>) {
+        let bytes = line.as_bytes();
+
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b';' | b'(' => break,
+                b'M' | b'm' if i == 0 || !bytes[i - 1].is_ascii_alphanumeric() => {
+                    let digits = bytes[i + 1..].iter().take_while(|b| b.is_ascii_digit()).count();
+                    let end = i + 1 + digits;
+
+                    let separated = end == bytes.len() || bytes[end].is_ascii_whitespace();
+                    if digits > 0 && separated {
+                        let word = Word::new('M', line[i + 1..end].parse().expect("Digits only"));
+                        if dialect.takes_string_argument(&word) {
+                            return (&line[..end], Some(&line[end..]));
+                        }
+                    }
+
+                    i = end;
+                }
+                _ => i += 1,
+            }
+        }
+
+        return (line, None);
+    }
+
+    /// Formats a value with at most four decimals and without trailing zeros.
+    fn format_value(value: Real) -> String {
+        let text = format!("{:.4}", value);
+        let text = text.trim_end_matches('0').trim_end_matches('.');
+
+        return match text {
+            "-0" | "" => "0".to_owned(),
+            text => text.to_owned(),
+        };
+    }
+
+    pub struct Parser {
+        /// Number of lines parsed so far
+        line: usize,
+
+        /// Whether to skip lines with errors instead of aborting
+        lenient: bool,
+
+        /// Errors of lines skipped in lenient mode
+        diagnostics: Vec<ParserError>,
+
+        /// Name of the input recorded in the provenance of all blocks
+        source: Option<Arc<str>>,
+
+        /// Axis letters of the machine converted to conventional ones
+        axis_map: AxisMap,
+
+        /// Syntax accepted by the parser
+        dialect: Dialect,
+
+        /// Whether blocks keep the exact text of their lines
+        lossless: bool,
+    }
+
+    impl Default for Parser {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Parser {
+        pub fn new() -> Self {
+            Self {
+                line: 0,
+                lenient: false,
+                diagnostics: Vec::new(),
+                source: None,
+                axis_map: AxisMap::new(),
+                dialect: Dialect::default(),
+                lossless: false,
+            }
+        }
+
+        /// Creates a parser in recovery mode.
+        ///
+        /// Instead of failing on the first malformed line, `parse_all` records the error as a
+        /// diagnostic, skips the line and continues with the next one.
+        pub fn new_lenient() -> Self {
+            Self {
+                lenient: true,
+                ..Self::new()
+            }
+        }
+
+        /// Sets the name of the input, usually its file name, recorded in the provenance of all
+        /// parsed blocks.
+        pub fn with_source<S>(mut self, source: S) -> Self
+            where S: Into<String> {
+            self.source = Some(Arc::from(source.into()));
+            self
+        }
+
+        /// Converts the axis letters of all parsed blocks from the machine's to conventional ones.
+        pub fn with_axis_map(mut self, axis_map: AxisMap) -> Self {
+            self.axis_map = axis_map;
+            self
+        }
+
+        /// Restricts the accepted letters and comment styles to those of the dialect.
+        pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+            self.dialect = dialect;
+            self
+        }
+
+        pub fn dialect(&self) -> Dialect {
+            self.dialect
+        }
+
+        /// Makes all parsed blocks keep the exact text of their lines, including whitespace, case,
+        /// comments and the formatting of numbers, so that blocks not modified afterwards are
+        /// written back byte for byte (see `emit::Options::verbatim`).
+        ///
+        /// When reading with `into_blocks`, the carriage return of lines ending with CRLF is kept
+        /// as well. Blocks with axis letters converted by an axis map are not kept.
+        pub fn with_lossless(mut self, lossless: bool) -> Self {
+            self.lossless = lossless;
+            self
+        }
+
+        pub fn is_lossless(&self) -> bool {
+            self.lossless
+        }
+
+        pub fn is_lenient(&self) -> bool {
+            self.lenient
+        }
+
+        /// The errors of all lines skipped in recovery mode so far.
+        pub fn diagnostics(&self) -> &[ParserError] {
+            &self.diagnostics
+        }
+
+        /// Returns and clears the recorded diagnostics.
+        pub fn take_diagnostics(&mut self) -> Vec<ParserError> {
+            core::mem::take(&mut self.diagnostics)
+        }
+
+        pub fn parse_all<I, S>(&mut self, input: I) -> Result<Vec<Block>, ParserError>
+            where I: Iterator<Item=S>,
+                  S: AsRef<str> {
+            if !self.lenient {
+                return input.map(|line| self.parse(line))
+                        .collect();
+            }
+
+            let mut blocks = Vec::new();
+            for line in input {
+                match self.parse(line) {
+                    Ok(block) => blocks.push(block),
+                    Err(err) => self.skip(err),
+                }
+            }
+
+            return Ok(blocks);
+        }
+
+        /// Creates an iterator parsing the blocks of the reader line by line.
+        #[cfg(feature = "std")]
+        pub fn from_reader<R>(reader: R) -> Blocks<R>
+            where R: BufRead {
+            Self::new().into_blocks(reader)
+        }
+
+        /// Turns this parser into an iterator over the blocks of the reader.
+        ///
+        /// Lines are read as they become available and may end with either LF or CRLF. In lenient
+        /// mode, lines with errors are skipped and recorded as diagnostics.
+        #[cfg(feature = "std")]
+        pub fn into_blocks<R>(self, reader: R) -> Blocks<R>
+            where R: BufRead {
+            Blocks {
+                parser: self,
+                reader,
+                buffer: Vec::new(),
+            }
+        }
+
+        pub fn parse<S>(&mut self, line: S) -> Result<Block, ParserError>
+            where S: AsRef<str> {
+            self.line += 1;
+            span!(TRACE, "parse", line = self.line);
+
+            let raw = line.as_ref();
+            let line = raw.trim();
+
+            // Positions are reported relative to the untrimmed line
+            let leading = &raw[..raw.len() - raw.trim_start().len()];
+            let position = Position {
+                line: self.line,
+                column: 1 + leading.chars().count(),
+                offset: leading.len(),
+            };
+
+            let mut block = Block::empty(line);
+            if self.lossless {
+                block.original = Some(raw.to_owned());
+            }
+            block.provenance = Provenance {
+                source: self.source.clone(),
+                line: Some(self.line),
+                passes: Vec::new(),
+            };
+
+            if let (true, Some(system)) = (self.dialect.has_system_commands(), line.strip_prefix('$')) {
+                block.system = Some(system.to_owned());
+
+                event!(trace, system, "parsed system command");
+                return Ok(block);
+            }
+
+            // Free text is not tokenized at all
+            let (code, argument) = match self.dialect.has_string_arguments() {
+                true => split_argument(line, self.dialect),
+                false => (line, None),
+            };
+
+            let mut lexer = Lexer::with_position(code.chars(), position).with_dialect(self.dialect);
+            let mut tokens = Tokens::new(&mut lexer)?;
+
+            // A `%` on a line of its own marks the start or the end of the program on tape
+            if tokens.current == Some(Token::Demarcation) {
+                tokens.advance()?;
+                if let Some(token) = tokens.current {
+                    return Err(ParserError::UnexpectedToken { token, span: tokens.span() });
+                }
+            }
+
+            if tokens.current == Some(Token::BlockDelete) {
+                block.deleted = true;
+                tokens.advance()?;
+            }
+
+            loop {
+                match tokens.current {
+                    None => break,
+
+                    Some(Token::Letter('O')) if block.words.is_empty() && block.assignments.is_empty() => {
+                        let span = tokens.span();
+
+                        tokens.advance()?;
+                        let label = match tokens.current {
+                            Some(Token::Number(value)) => {
+                                let value_span = tokens.span();
+
+                                tokens.advance()?;
+                                match tokens.current {
+                                    Some(Token::Letter(_)) if value >= 0.0 && crate::is_whole(value) => Label::Numbered(value as u32),
+
+                                    // Program numbers are plain words
+                                    _ => {
+                                        block.words.push(Word {
+                                            mnemonic: 'O',
+                                            value,
+                                        });
+                                        block.spans.push((Span { end: value_span.end, ..span }, value_span));
+                                        continue;
+                                    }
+                                }
+                            }
+                            Some(Token::Name(name)) => {
+                                tokens.advance()?;
+                                Label::Named(name.to_string())
+                            }
+                            Some(token) => {
+                                return Err(ParserError::UnexpectedToken { token, span: tokens.span() });
+                            }
+                            None => {
+                                return Err(ParserError::MissingValue { span });
+                            }
+                        };
+
+                        block.control = Some(tokens.control(label)?);
+                        if let Some(token) = tokens.current {
+                            return Err(ParserError::UnexpectedToken { token, span: tokens.span() });
+                        }
+                    }
+
+                    Some(Token::Letter(letter)) => {
+                        let span = tokens.span();
+                        if !self.dialect.letters().contains(letter) {
+                            return Err(ParserError::UnsupportedLetter { letter, span });
+                        }
+
+                        tokens.advance()?;
+                        match tokens.current {
+                            Some(Token::Number(value)) => {
+                                let value_span = tokens.span();
+
+                                tokens.advance()?;
+                                if letter == 'N' {
+                                    block.line_number = Some(value);
+                                } else {
+                                    block.words.push(Word {
+                                        mnemonic: letter,
+                                        value,
+                                    });
+                                    block.spans.push((Span { end: value_span.end, ..span }, value_span));
+                                }
+                            }
+                            Some(Token::Quoted) if letter != 'N' => {
+                                let value_span = tokens.span();
+                                tokens.advance()?;
+
+                                let text = &raw[value_span.start + 1..value_span.end - 1];
+                                block.strings.push((block.words.len(), text.replace("\"\"", "\"")));
+                                block.words.push(Word {
+                                    mnemonic: letter,
+                                    value: 0.0,
+                                });
+                                block.spans.push((Span { end: value_span.end, ..span }, value_span));
+                            }
+                            Some(Token::Parameter) | Some(Token::LeftBracket) if letter != 'N' => {
+                                let value_span = tokens.span();
+                                let expr = tokens.value()?;
+                                let value_span = Span { end: tokens.end, ..value_span };
+
+                                block.expressions.push((block.words.len(), expr));
+                                block.words.push(Word {
+                                    mnemonic: letter,
+                                    value: 0.0,
+                                });
+                                block.spans.push((Span { end: value_span.end, ..span }, value_span));
+                            }
+                            Some(token) => {
+                                return Err(ParserError::UnexpectedToken { token, span: tokens.span() });
+                            }
+                            None => {
+                                return Err(ParserError::MissingValue { span });
+                            }
+                        }
+                    }
+
+                    Some(Token::Parameter) => {
+                        let span = tokens.span();
+                        let parameter = match tokens.reference()? {
+                            Expr::Parameter(parameter) => parameter,
+                            _ => return Err(ParserError::UnexpectedToken { token: Token::Parameter, span }),
+                        };
+
+                        match tokens.current {
+                            Some(Token::Assign) => tokens.advance()?,
+                            Some(token) => return Err(ParserError::UnexpectedToken { token, span: tokens.span() }),
+                            None => return Err(ParserError::MissingValue { span: Span { end: tokens.end, ..span } }),
+                        }
+
+                        let value = tokens.value()?;
+                        block.assignments.push((parameter, value));
+                    }
+
+                    Some(token) => {
+                        return Err(ParserError::UnexpectedToken { token, span: tokens.span() });
+                    }
+                }
+            }
+
+            for span in lexer.take_comments() {
+                let text = &raw[span.start..span.end];
+                let (text, style) = match text.strip_prefix(';') {
+                    Some(text) => (text.trim_end(), CommentStyle::Semicolon),
+                    None => (text[1..].strip_suffix(')').unwrap_or(&text[1..]), CommentStyle::Parentheses),
+                };
+
+                let index = block.spans.iter().filter(|(word, _)| word.start < span.start).count();
+                let position = match index {
+                    0 => CommentPosition::Leading,
+                    index if index == block.words.len() => CommentPosition::Trailing,
+                    index => CommentPosition::Inline(index),
+                };
+
+                block.comments.push(Comment::new(text, style, position));
+                block.comment_spans.push(span);
+            }
+
+            if let Some(argument) = argument {
+                let (text, comment) = match argument.find(';') {
+                    Some(i) if self.dialect.comment_styles().contains(&CommentStyle::Semicolon) => (&argument[..i], Some(&argument[i + 1..])),
+                    _ => (argument, None),
+                };
+
+                let text = text.trim();
+                if !text.is_empty() {
+                    block.argument = Some(text.to_owned());
+                }
+                if let Some(comment) = comment {
+                    // The comment is a slice of the line, including the semicolon right before it
+                    let start = leading.len() + (comment.as_ptr() as usize - line.as_ptr() as usize) - 1;
+                    block.comment_spans.push(Span {
+                        line: self.line,
+                        column: 1 + raw[..start].chars().count(),
+                        start,
+                        end: start + 1 + comment.trim_end().len(),
+                    });
+
+                    block.comments.push(Comment::new(comment.trim_end(), CommentStyle::Semicolon, CommentPosition::Trailing));
+                }
+            }
+
+            if !self.axis_map.is_identity() {
+                block = self.axis_map.canonical_block(block);
+            }
+
+            event!(trace, words = block.words.len(), "parsed block");
+            return Ok(block);
+        }
+
+        /// Records the error of a line skipped in lenient mode.
+        fn skip(&mut self, err: ParserError) {
+            event!(warn, line = self.line, error = %err, "skipping malformed line");
+            self.diagnostics.push(err);
+        }
+    }
+
+    /// The tokens of a line with a single token of lookahead.
+    struct Tokens<'a, I> {
+        lexer: &'a mut Lexer<I>,
+        current: Option<Token>,
+
+        /// Offset after the last token consumed
+        end: usize,
+    }
+
+    impl<'a, I> Tokens<'a, I>
+        where I: Iterator<Item=char> {
+        fn new(lexer: &'a mut Lexer<I>) -> Result<Self, ParserError> {
+            let end = lexer.span().start;
+            let current = lexer.next()?;
+
+            return Ok(Self {
+                lexer,
+                current,
+                end,
+            });
+        }
+
+        /// The span of the current token.
+        fn span(&self) -> Span {
+            self.lexer.span()
+        }
+
+        fn advance(&mut self) -> Result<(), ParserError> {
+            self.end = self.lexer.span().end;
+            self.current = self.lexer.next()?;
+            return Ok(());
+        }
+
+        fn unexpected<T>(&self) -> Result<T, ParserError> {
+            return Err(match self.current {
+                Some(token) => ParserError::UnexpectedToken { token, span: self.span() },
+                None => ParserError::MissingValue { span: self.span() },
+            });
+        }
+
+        /// Parses the keyword and the bracketed arguments of an O-word following its label.
+        fn control(&mut self, label: Label) -> Result<Control, ParserError> {
+            let span = self.span();
+
+            let mut name = String::new();
+            while let Some(Token::Letter(letter)) = self.current {
+                name.push(letter.to_ascii_lowercase());
+                self.advance()?;
+            }
+
+            if name.is_empty() {
+                return self.unexpected();
+            }
+
+            let keyword = match Keyword::from_name(&name) {
+                Some(keyword) => keyword,
+                None => return Err(ParserError::UnknownIdentifier { name, span: Span { end: self.end, ..span } }),
+            };
+
+            let mut control = Control::new(label, keyword);
+            let (min, max) = keyword.arguments();
+            while self.current == Some(Token::LeftBracket) && control.arguments.len() < max {
+                control.arguments.push(self.bracketed()?);
+            }
+
+            if control.arguments.len() < min {
+                return self.unexpected();
+            }
+
+            return Ok(control);
+        }
+
+        /// Parses a number, a parameter reference or an expression.
+        ///
+        /// Inside of expressions, values can also be negated and be the result of a function.
+        fn value(&mut self) -> Result<Expr, ParserError> {
+            return match self.current {
+                Some(Token::Number(value)) => {
+                    self.advance()?;
+                    Ok(Expr::Number(value))
+                }
+                Some(Token::Parameter) => self.reference(),
+                Some(Token::LeftBracket) => self.bracketed(),
+                Some(Token::Operator(Operator::Subtract)) => {
+                    self.advance()?;
+                    Ok(Expr::Negate(Box::new(self.value()?)))
+                }
+                Some(Token::Operator(Operator::Add)) => {
+                    self.advance()?;
+                    self.value()
+                }
+                Some(Token::Identifier(name)) => self.function(&name),
+                _ => self.unexpected(),
+            };
+        }
+
+        /// Parses a parameter reference starting with `#`.
+        fn reference(&mut self) -> Result<Expr, ParserError> {
+            debug_assert_eq!(self.current, Some(Token::Parameter));
+            self.advance()?;
+
+            return match self.current {
+                Some(Token::Number(value)) if value >= 0.0 && crate::is_whole(value) && value <= u32::MAX as Real => {
+                    self.advance()?;
+                    Ok(Expr::Parameter(Parameter::Numbered(value as u32)))
+                }
+                Some(Token::Number(value)) => Err(ParserError::InvalidParameter { value, span: self.span() }),
+                Some(Token::Name(name)) => {
+                    self.advance()?;
+                    Ok(Expr::Parameter(Parameter::Named(name.to_string())))
+                }
+                Some(Token::Parameter) | Some(Token::LeftBracket) => Ok(Expr::Indirect(Box::new(self.value()?))),
+                _ => self.unexpected(),
+            };
+        }
+
+        /// Parses an expression enclosed in brackets.
+        fn bracketed(&mut self) -> Result<Expr, ParserError> {
+            if self.current != Some(Token::LeftBracket) {
+                return self.unexpected();
+            }
+            self.advance()?;
+
+            let expr = self.binary(0)?;
+
+            if self.current != Some(Token::RightBracket) {
+                return self.unexpected();
+            }
+            self.advance()?;
+
+            return Ok(expr);
+        }
+
+        /// Parses operations of operators binding at least as strong as `precedence`.
+        fn binary(&mut self, precedence: u8) -> Result<Expr, ParserError> {
+            let mut left = self.value()?;
+
+            loop {
+                let operator = match self.current {
+                    Some(Token::Operator(operator)) => operator,
+                    Some(Token::Identifier(name)) => match Operator::from_name(&name) {
+                        Some(operator) => operator,
+                        None => return Err(ParserError::UnknownIdentifier { name: name.to_string(), span: self.span() }),
+                    },
+                    _ => return Ok(left),
+                };
+
+                if operator.precedence() < precedence {
+                    return Ok(left);
+                }
+                self.advance()?;
+
+                let right = self.binary(operator.precedence() + 1)?;
+                left = Expr::binary(operator, left, right);
+            }
+        }
+
+        fn function(&mut self, name: &str) -> Result<Expr, ParserError> {
+            if name == "ATAN" {
+                self.advance()?;
+                let y = self.bracketed()?;
+
+                // Following the closing bracket, the lexer takes a slash for a block delete
+                match self.current {
+                    Some(Token::Operator(Operator::Divide)) | Some(Token::BlockDelete) => self.advance()?,
+                    _ => return self.unexpected(),
+                }
+
+                let x = self.bracketed()?;
+                return Ok(Expr::Atan(Box::new(y), Box::new(x)));
+            }
+
+            let function = match Function::from_name(name) {
+                Some(function) => function,
+                None => return Err(ParserError::UnknownIdentifier { name: name.to_owned(), span: self.span() }),
+            };
+            self.advance()?;
+
+            return Ok(Expr::function(function, self.bracketed()?));
+        }
+    }
+
+    /// Iterator over the blocks parsed from a reader.
+    #[cfg(feature = "std")]
+    pub struct Blocks<R> {
+        parser: Parser,
+        reader: R,
+        buffer: Vec<u8>,
+    }
+
+    #[cfg(feature = "std")]
+    impl<R> Blocks<R> {
+        /// The parser used to parse the lines, e.g. for accessing its diagnostics.
+        pub fn parser(&self) -> &Parser {
+            &self.parser
+        }
+
+        pub fn into_parser(self) -> Parser {
+            self.parser
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl<R> Iterator for Blocks<R>
+        where R: BufRead {
+        type Item = Result<Block, ParserError>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            loop {
+                self.buffer.clear();
+                match self.reader.read_until(b'\n', &mut self.buffer) {
+                    Ok(0) => return None,
+                    Ok(_) => {}
+                    Err(error) => {
+                        event!(error, line = self.parser.line + 1, %error, "failed to read line");
+                        return Some(Err(ParserError::Io { line: self.parser.line + 1, error }));
+                    }
+                }
+
+                if self.buffer.last() == Some(&b'\n') {
+                    self.buffer.pop();
+                }
+                if self.buffer.last() == Some(&b'\r') && !self.parser.lossless {
+                    self.buffer.pop();
+                }
+
+                // Invalid UTF-8 is replaced and reported as illegal symbol by the lexer
+                let line = String::from_utf8_lossy(&self.buffer);
+
+                match self.parser.parse(line) {
+                    Err(err) if self.parser.lenient => self.parser.skip(err),
+                    result => return Some(result),
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_parser_provenance() {
+            let mut p = Parser::new().with_source("part.ngc");
+            p.parse("G0").unwrap();
+
+            let b = p.parse("G1").unwrap().with_pass("test");
+            assert_eq!(b.provenance().source(), Some("part.ngc"));
+            assert_eq!(b.provenance().line(), Some(2));
+            assert_eq!(b.provenance().passes(), &["test"]);
+            assert_eq!(b.provenance().to_string(), "part.ngc:2 (test)");
+
+            // Provenance does not take part in comparison
+            assert_eq!(b, Block::new(vec![Word::new('G', 1.0)]));
+        }
+
+        #[test]
+        fn test_parser_spans() {
+            let source = "  G1 x 10.50 (feed) F1500 ; fast";
+            let b = Parser::new().parse(source).unwrap();
+
+            assert_eq!(b.span(1), Some(Span { line: 1, column: 6, start: 5, end: 12 }));
+            assert_eq!(b.value_span(1), Some(Span { line: 1, column: 8, start: 7, end: 12 }));
+            assert_eq!(b.span(3), None);
+
+            assert_eq!(b.edit_value(source, 2, 800.0).as_deref(), Some("  G1 x 10.50 (feed) F800 ; fast"));
+            assert_eq!(b.edit_value(source, 1, -2.0).as_deref(), Some("  G1 x -2 (feed) F1500 ; fast"));
+            assert_eq!(b.edit_value("G1", 2, 800.0), None);
+
+            assert_eq!(Block::new(vec![Word::new('G', 1.0)]).span(0), None);
+
+            assert_eq!(b.comment_span(0), Some(Span { line: 1, column: 14, start: 13, end: 19 }));
+            assert_eq!(b.comment_span(1), Some(Span { line: 1, column: 27, start: 26, end: 32 }));
+            assert_eq!(b.comment_span(2), None);
+
+            assert_eq!(b.word_at(9), Some(1));
+            assert_eq!(b.word_at(20), Some(2));
+            assert_eq!(b.word_at(4), None);
+            assert_eq!(b.word_at(15), None);
+        }
+
+        #[test]
+        fn test_parser_empty() {
+            let b = Parser::new().parse("").unwrap();
+            assert!(b.is_empty());
+        }
+
+        #[test]
+        fn test_parser_comments() {
+            let b = Parser::new().parse("  N5 (first)(second) G1 ( move  to ) X1 (unterminated").unwrap();
+            assert_eq!(b.words(), &[Word { mnemonic: 'G', value: 1.0 }, Word { mnemonic: 'X', value: 1.0 }]);
+            assert_eq!(b.comments(), &[
+                Comment::new("first", CommentStyle::Parentheses, CommentPosition::Leading),
+                Comment::new("second", CommentStyle::Parentheses, CommentPosition::Leading),
+                Comment::new(" move  to ", CommentStyle::Parentheses, CommentPosition::Inline(1)),
+                Comment::new("unterminated", CommentStyle::Parentheses, CommentPosition::Trailing),
+            ]);
+
+            let b = Parser::new().parse(";LAYER:0 ").unwrap();
+            assert!(b.is_empty());
+            assert_eq!(b.comments(), &[Comment::new("LAYER:0", CommentStyle::Semicolon, CommentPosition::Leading)]);
+            assert_eq!(b.comments()[0].to_string(), ";LAYER:0");
+        }
+
+        #[test]
         fn test_parser_simple() {
             let b = Parser::new().parse("G1").unwrap();
             assert_eq!(b, Block {
                 line_number: None,
                 deleted: false,
                 words: vec![Word { mnemonic: 'G', value: 1.0 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "G1".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             });
         }
 
@@ -458,7 +2162,18 @@ mod parser {
                 words: vec![Word { mnemonic: 'G', value: 1.0 },
                             Word { mnemonic: 'X', value: 12.34 },
                             Word { mnemonic: 'Y', value: -45.67 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "G1 X12.34 Y-45.67".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             });
         }
 
@@ -471,7 +2186,18 @@ mod parser {
                 words: vec![Word { mnemonic: 'G', value: 1.0 },
                             Word { mnemonic: 'X', value: 12.34 },
                             Word { mnemonic: 'Y', value: -45.67 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "G1 N9876 X12.34 Y-45.67".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             });
         }
 
@@ -483,10 +2209,195 @@ mod parser {
                 deleted: true,
                 words: vec![Word { mnemonic: 'G', value: 1.0 },
                             Word { mnemonic: 'X', value: 100.0 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "/ G1 X100".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             });
         }
 
+        #[test]
+        fn test_parser_demarcation() {
+            let mut p = Parser::new();
+            assert!(p.parse("%").unwrap().is_empty());
+            assert_eq!(p.parse("% (end of tape)").unwrap().comments().len(), 1);
+            assert!(p.parse("% G0 X1").is_err());
+        }
+
+        #[test]
+        fn test_block_accessors() {
+            let b = Parser::new().parse("/ N10 G1 x5").unwrap();
+            assert_eq!(b.line_number(), Some(10.0));
+            assert!(b.is_deleted());
+            assert_eq!(b.words(), &[Word::new('G', 1.0), Word::new('X', 5.0)]);
+            assert_eq!(b.words()[1].mnemonic(), 'X');
+            assert_eq!(b.words()[1].value(), 5.0);
+            assert_eq!(b.line(), "/ N10 G1 x5");
+        }
+
+        #[test]
+        fn test_block_new() {
+            let b = Block::new(vec![Word::new('g', 1.0), Word::new('X', -0.5), Word::new('Y', 1.0 / 3.0)]);
+            assert_eq!(b.line(), "G1 X-0.5 Y0.3333");
+            assert_eq!(b.line_number(), None);
+            assert!(!b.is_deleted());
+
+            let b = b.with_line_number(Some(20.0)).with_deleted(true);
+            assert_eq!(b.line(), "/ N20 G1 X-0.5 Y0.3333");
+        }
+
+        #[test]
+        fn test_parser_error_span() {
+            let mut p = Parser::new();
+            p.parse("G1").unwrap();
+
+            let err = p.parse("  G1 X").unwrap_err();
+            assert_eq!(err.span(), Span { line: 2, column: 6, start: 5, end: 6 });
+            assert_eq!(err.to_string(), "2:6: missing value");
+
+            let err = p.parse("G1 X1 $").unwrap_err();
+            assert_eq!(err.span(), Span { line: 3, column: 7, start: 6, end: 7 });
+            assert_eq!(err.to_string(), "syntax error: 3:7: illegal symbol: $");
+
+            let err = p.parse("G1 X1 %").unwrap_err();
+            assert_eq!(err.span(), Span { line: 4, column: 7, start: 6, end: 7 });
+        }
+
+        #[test]
+        fn test_parser_parameters() {
+            let source = "#1=10 G1 X#1 Y #<_Safe Z> #<depth> = #1";
+            let b = Parser::new().parse(source).unwrap();
+
+            assert_eq!(b.words(), &[Word::new('G', 1.0), Word::new('X', 0.0), Word::new('Y', 0.0)]);
+            assert_eq!(b.expressions(), &[
+                (1, Expr::Parameter(Parameter::Numbered(1))),
+                (2, Expr::Parameter(Parameter::named("_safez"))),
+            ]);
+            assert_eq!(b.assignments(), &[
+                (Parameter::Numbered(1), Expr::Number(10.0)),
+                (Parameter::named("depth"), Expr::Parameter(Parameter::Numbered(1))),
+            ]);
+            assert!(!b.is_resolved());
+            assert_eq!(b.value_span(1), Some(Span { line: 1, column: 11, start: 10, end: 12 }));
+
+            let r = b.resolve(|expr| match expr {
+                Expr::Parameter(Parameter::Numbered(_)) => Ok::<_, ()>(1.0),
+                _ => Ok(2.0),
+            }).unwrap();
+            assert_eq!(r.words(), &[Word::new('G', 1.0), Word::new('X', 1.0), Word::new('Y', 2.0)]);
+            assert!(r.is_resolved());
+
+            let b = Parser::new().parse("#1=10").unwrap();
+            assert!(!b.is_empty());
+
+            let mut p = Parser::new();
+            assert!(matches!(p.parse("#1.5=2"), Err(ParserError::InvalidParameter { value, .. }) if value == 1.5));
+            assert!(matches!(p.parse("#1 X2"), Err(ParserError::UnexpectedToken { .. })));
+            assert!(matches!(p.parse("#1="), Err(ParserError::MissingValue { .. })));
+            assert!(matches!(p.parse("N#1"), Err(ParserError::UnexpectedToken { .. })));
+        }
+
+        #[test]
+        fn test_parser_expressions() {
+            let source = "G1 X[#1 + 2] Y#[1 * 2] #3 = [ATAN[1]/[2]]";
+            let b = Parser::new().parse(source).unwrap();
+
+            assert_eq!(b.expressions(), &[
+                (1, Expr::binary(Operator::Add, Expr::Parameter(Parameter::Numbered(1)), Expr::Number(2.0))),
+                (2, Expr::Indirect(Box::new(Expr::binary(Operator::Multiply, Expr::Number(1.0), Expr::Number(2.0))))),
+            ]);
+            assert_eq!(b.value_span(1), Some(Span { line: 1, column: 5, start: 4, end: 12 }));
+
+            let mut p = Parser::new();
+            assert!(matches!(p.parse("G1 X[1 + 2"), Err(ParserError::MissingValue { .. })));
+            assert!(matches!(p.parse("G1 X[1 #2]"), Err(ParserError::UnexpectedToken { .. })));
+            assert!(matches!(p.parse("G1 X[FOO[1]]"), Err(ParserError::UnknownIdentifier { .. })));
+            assert!(matches!(p.parse("G1 X[1 FOO 2]"), Err(ParserError::UnknownIdentifier { .. })));
+
+            // Assignments to indirect parameters are not supported
+            assert!(matches!(p.parse("#[1] = 2"), Err(ParserError::UnexpectedToken { .. })));
+            assert!(matches!(p.parse("##1 = 2"), Err(ParserError::UnexpectedToken { .. })));
+        }
+
+        #[test]
+        fn test_parser_control() {
+            let mut p = Parser::new();
+
+            let b = p.parse("O100 CALL [1] [#2 + 1]").unwrap();
+            assert_eq!(b.control(), Some(&Control::new(Label::Numbered(100), Keyword::Call)
+                    .with_argument(Expr::Number(1.0))
+                    .with_argument(Expr::binary(Operator::Add, Expr::Parameter(Parameter::Numbered(2)), Expr::Number(1.0)))));
+            assert!(b.words().is_empty());
+            assert_eq!(b.to_string(), "o100 call [1] [#2 + 1]");
+
+            let b = p.parse("o<Drill Hole> endsub").unwrap();
+            assert_eq!(b.control(), Some(&Control::new(Label::Named("drillhole".to_owned()), Keyword::EndSub)));
+            assert_eq!(p.parse(b.to_string()).unwrap().control(), b.control());
+
+            // Program numbers stay words
+            let b = p.parse("O1234").unwrap();
+            assert_eq!(b.words(), &[Word::new('O', 1234.0)]);
+            assert!(b.control().is_none());
+
+            assert!(matches!(p.parse("o1 if"), Err(ParserError::MissingValue { .. })));
+            assert!(matches!(p.parse("o1 else [1]"), Err(ParserError::UnexpectedToken { .. })));
+            assert!(matches!(p.parse("o1 goto"), Err(ParserError::UnknownIdentifier { .. })));
+            assert!(matches!(p.parse("o1 endif G0"), Err(ParserError::UnknownIdentifier { .. })));
+        }
+
+        #[test]
+        fn test_parser_lenient() {
+            let input = "G1 X1\nG1 X\nG1 X2\nG1 $\nG1 X3";
+
+            assert!(Parser::new().parse_all(input.lines()).is_err());
+
+            let mut p = Parser::new_lenient();
+            let b = p.parse_all(input.lines()).unwrap();
+            assert_eq!(b.iter().map(|b| b.line()).collect::<Vec<_>>(), vec!["G1 X1", "G1 X2", "G1 X3"]);
+            assert_eq!(p.diagnostics().iter().map(|e| e.span().line).collect::<Vec<_>>(), vec![2, 4]);
+
+            assert_eq!(p.take_diagnostics().len(), 2);
+            assert!(p.diagnostics().is_empty());
+        }
+
+        #[test]
+        fn test_parser_long_number() {
+            let err = Parser::new().parse("X1234567890123456789012345678901234567890").unwrap_err();
+            assert_eq!(err.span(), Span { line: 1, column: 2, start: 1, end: 41 });
+        }
+
+        #[test]
+        fn test_parser_reader() {
+            let input = "G1 X1\r\nG1 X2\n\nG1 X3";
+
+            // A buffer of a single byte forces lines to be assembled from partial reads
+            let reader = io::BufReader::with_capacity(1, input.as_bytes());
+            let b = Parser::from_reader(reader).collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(b.iter().map(|b| b.line()).collect::<Vec<_>>(), vec!["G1 X1", "G1 X2", "", "G1 X3"]);
+        }
+
+        #[test]
+        fn test_parser_reader_errors() {
+            let input = &b"G1 X1\nG1 X\nG1 \xff\nG1 X3\n"[..];
+
+            let results = Parser::from_reader(input).collect::<Vec<_>>();
+            assert_eq!(results.len(), 4);
+            assert_eq!(results[1].as_ref().unwrap_err().span().line, 2);
+            assert_eq!(results[2].as_ref().unwrap_err().span().line, 3);
+
+            let mut blocks = Parser::new_lenient().into_blocks(input);
+            assert_eq!(blocks.by_ref().count(), 2);
+            assert_eq!(blocks.parser().diagnostics().len(), 2);
+        }
+
         #[test]
         fn test_parser_multiline() {
             let b = Parser::new().parse_all("N0010 G1 X000 Y000\nN0020 G1 X100 Y000\nN0030 G1 X100 Y100\nN0040 G1 X000 Y100\nN0050 G1 X000 Y000\n".lines()).unwrap();
@@ -497,7 +2408,18 @@ mod parser {
                 words: vec![Word { mnemonic: 'G', value: 1.0 },
                             Word { mnemonic: 'X', value: 000.0 },
                             Word { mnemonic: 'Y', value: 000.0 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "N0010 G1 X000 Y000".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             }));
             assert_eq!(b.next(), Some(&Block {
                 line_number: Some(20.0),
@@ -505,7 +2427,18 @@ mod parser {
                 words: vec![Word { mnemonic: 'G', value: 1.0 },
                             Word { mnemonic: 'X', value: 100.0 },
                             Word { mnemonic: 'Y', value: 000.0 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "N0020 G1 X100 Y000".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             }));
             assert_eq!(b.next(), Some(&Block {
                 line_number: Some(30.0),
@@ -513,7 +2446,18 @@ mod parser {
                 words: vec![Word { mnemonic: 'G', value: 1.0 },
                             Word { mnemonic: 'X', value: 100.0 },
                             Word { mnemonic: 'Y', value: 100.0 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "N0030 G1 X100 Y100".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             }));
             assert_eq!(b.next(), Some(&Block {
                 line_number: Some(40.0),
@@ -521,7 +2465,18 @@ mod parser {
                 words: vec![Word { mnemonic: 'G', value: 1.0 },
                             Word { mnemonic: 'X', value: 000.0 },
                             Word { mnemonic: 'Y', value: 100.0 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "N0040 G1 X000 Y100".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             }));
             assert_eq!(b.next(), Some(&Block {
                 line_number: Some(50.0),
@@ -529,7 +2484,18 @@ mod parser {
                 words: vec![Word { mnemonic: 'G', value: 1.0 },
                             Word { mnemonic: 'X', value: 000.0 },
                             Word { mnemonic: 'Y', value: 000.0 }],
+                expressions: Vec::new(),
+                assignments: Vec::new(),
+                control: None,
+                argument: None,
+                strings: Vec::new(),
+                system: None,
+                comments: Vec::new(),
                 line: "N0050 G1 X000 Y000".to_owned(),
+                original: None,
+                spans: Vec::new(),
+                comment_spans: Vec::new(),
+                provenance: Provenance::default(),
             }));
             assert_eq!(b.next(), None);
         }
@@ -0,0 +1,186 @@
+//! Parsing on a dedicated thread while the blocks are consumed on another one.
+//!
+//! The parser thread sends chunks of blocks through a bounded channel. If the consumer falls
+//! behind, the parser blocks as soon as `capacity` blocks are in flight, which bounds the memory
+//! used for large files.
+
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::vec;
+
+use crate::parser::{Block, Parser, ParserError};
+
+/// Configuration of a pipelined parser.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Pipeline {
+    capacity: usize,
+    chunk: usize,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            capacity: 16 * 1024,
+            chunk: 256,
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of parsed blocks waiting to be consumed at which the parser blocks.
+    ///
+    /// The capacity is rounded up to a multiple of the chunk size.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets the number of blocks sent to the consumer at once.
+    ///
+    /// Larger chunks reduce the overhead of synchronizing the threads, but delay the first block.
+    pub fn chunk(mut self, chunk: usize) -> Self {
+        self.chunk = chunk.max(1);
+        self
+    }
+
+    /// Starts parsing the lines of the reader on a new thread.
+    ///
+    /// Blocks and errors are returned in order, exactly like `Parser::into_blocks` does.
+    pub fn spawn<R>(self, parser: Parser, reader: R) -> Pipelined
+        where R: BufRead + Send + 'static {
+        let (sender, receiver) = mpsc::sync_channel(self.capacity.div_ceil(self.chunk));
+        let chunk = self.chunk;
+
+        let worker = thread::spawn(move || {
+            let mut blocks = parser.into_blocks(reader);
+
+            loop {
+                let results = blocks.by_ref().take(chunk).collect::<Vec<_>>();
+                if results.is_empty() {
+                    break;
+                }
+
+                // The consumer is gone and does not want any more blocks
+                if sender.send(results).is_err() {
+                    event!(debug, "pipeline closed by consumer");
+                    break;
+                }
+            }
+
+            return blocks.into_parser();
+        });
+
+        return Pipelined {
+            receiver: Some(receiver),
+            worker: Some(worker),
+            current: Vec::new().into_iter(),
+        };
+    }
+}
+
+/// Iterator over the blocks parsed on a parser thread.
+pub struct Pipelined {
+    receiver: Option<Receiver<Vec<Result<Block, ParserError>>>>,
+    worker: Option<JoinHandle<Parser>>,
+    current: vec::IntoIter<Result<Block, ParserError>>,
+}
+
+impl Pipelined {
+    /// Stops parsing and returns the parser, e.g. for accessing its diagnostics.
+    ///
+    /// Blocks not consumed so far are dropped. Panics of the parser thread are propagated.
+    pub fn into_parser(mut self) -> Parser {
+        self.stop().expect("Parser already taken")
+    }
+
+    fn stop(&mut self) -> Option<Parser> {
+        // Closing the channel lets the parser thread finish its current chunk and return
+        drop(self.receiver.take());
+
+        let worker = self.worker.take()?;
+        return match worker.join() {
+            Ok(parser) => Some(parser),
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+    }
+}
+
+impl Iterator for Pipelined {
+    type Item = Result<Block, ParserError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.current.next() {
+                return Some(result);
+            }
+
+            self.current = self.receiver.as_ref()?.recv().ok()?.into_iter();
+        }
+    }
+}
+
+impl Drop for Pipelined {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.stop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn input(lines: usize) -> String {
+        return (0..lines)
+                .map(|i| if i % 100 == 99 { "G1 X#".to_owned() } else { format!("G1 X{} Y{}", i, i % 7) })
+                .collect::<Vec<_>>()
+                .join("\n");
+    }
+
+    #[test]
+    fn test_order() {
+        let input = input(1000);
+
+        let sequential = Parser::new_lenient().into_blocks(Cursor::new(input.clone()))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+        let mut pipelined = Pipeline::new()
+                .capacity(10)
+                .chunk(3)
+                .spawn(Parser::new_lenient(), Cursor::new(input));
+        let blocks = pipelined.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(blocks, sequential);
+        assert_eq!(pipelined.into_parser().diagnostics().len(), 10);
+    }
+
+    #[test]
+    fn test_errors() {
+        let results = Pipeline::new()
+                .spawn(Parser::new(), Cursor::new("G1 X1\nG1 X\nG1 X2"))
+                .map(|result| result.is_ok())
+                .collect::<Vec<_>>();
+
+        assert_eq!(results, vec![true, false, true]);
+    }
+
+    #[test]
+    fn test_early_stop() {
+        // The parser thread is blocked on the full channel and must stop when dropping
+        let mut pipelined = Pipeline::new()
+                .capacity(1)
+                .chunk(1)
+                .spawn(Parser::new(), Cursor::new(input(10_000)));
+
+        assert!(pipelined.next().unwrap().is_ok());
+        drop(pipelined);
+    }
+}
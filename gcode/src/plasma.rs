@@ -0,0 +1,165 @@
+//! Extensions of G-code used by plasma cutters and arc welders.
+//!
+//! The torch is switched on and off with M codes, usually the ones of the spindle. To start a cut,
+//! the torch pierces the material from a height above the cutting height and dwells until the arc
+//! is through. Torch height controllers (THC) are only enabled once the torch reached the cutting
+//! height, and the width of the kerf is compensated by the controller.
+
+use crate::Real;
+use crate::command::{Command, Side};
+use crate::parser::{Block, Word};
+use crate::program::Program;
+
+/// The codes and settings of a plasma cutter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlasmaProfile {
+    /// The words switching the torch on.
+    pub torch_on: Vec<Word>,
+
+    /// The words switching the torch off.
+    pub torch_off: Vec<Word>,
+
+    /// The words enabling and disabling the torch height controller, if any.
+    pub thc: Option<(Vec<Word>, Vec<Word>)>,
+
+    /// The height above the material the torch pierces from, in millimeters.
+    pub pierce_height: Real,
+
+    /// The time to dwell after switching the torch on, in seconds.
+    pub pierce_delay: Real,
+
+    /// The height above the material while cutting, in millimeters.
+    pub cut_height: Real,
+
+    /// The width of the cut, in millimeters.
+    pub kerf: Real,
+
+    /// The side the kerf is compensated to by the controller (G41.1 or G42.1), if at all.
+    pub kerf_side: Option<Side>,
+}
+
+impl Default for PlasmaProfile {
+    /// Switches the torch with M3 and M5, without a torch height controller.
+    fn default() -> Self {
+        Self {
+            torch_on: vec![Word::new('M', 3.0)],
+            torch_off: vec![Word::new('M', 5.0)],
+            thc: None,
+            pierce_height: 3.8,
+            pierce_delay: 0.5,
+            cut_height: 1.5,
+            kerf: 1.5,
+            kerf_side: None,
+        }
+    }
+}
+
+/// A torch switched on to pierce the material.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Pierce {
+    /// The block switching the torch on.
+    pub block: usize,
+
+    /// The time dwelled before the torch moves, in seconds.
+    pub delay: Real,
+}
+
+impl PlasmaProfile {
+    /// Whether the block contains all of the words.
+    fn contains(block: &Block, words: &[Word]) -> bool {
+        !words.is_empty() && words.iter().all(|word| block.words().contains(word))
+    }
+
+    pub fn is_torch_on(&self, block: &Block) -> bool {
+        Self::contains(block, &self.torch_on)
+    }
+
+    pub fn is_torch_off(&self, block: &Block) -> bool {
+        Self::contains(block, &self.torch_off)
+    }
+
+    /// The words starting the compensation of the kerf, if configured.
+    pub fn kerf_compensation(&self) -> Option<Vec<Word>> {
+        let code = match self.kerf_side? {
+            Side::Left => 41.1,
+            Side::Right => 42.1,
+        };
+
+        return Some(vec![Word::new('G', code), Word::new('D', self.kerf)]);
+    }
+
+    /// Finds all pierces of a program, along with the time dwelled at each.
+    ///
+    /// The delay of a pierce includes all dwells between switching the torch on and the first
+    /// move after that.
+    pub fn pierces(&self, program: &Program) -> Vec<Pierce> {
+        let mut pierces = Vec::new();
+        let mut current: Option<Pierce> = None;
+
+        for (i, block) in program.iter().enumerate() {
+            if self.is_torch_on(block) {
+                pierces.extend(current.take());
+                current = Some(Pierce { block: i, delay: 0.0 });
+            }
+
+            let pierce = match current.as_mut() {
+                Some(pierce) => pierce,
+                None => continue,
+            };
+
+            for command in Command::from_block(block) {
+                match command {
+                    Command::Dwell { seconds } => pierce.delay += seconds,
+                    Command::RapidMove(_) | Command::LinearMove(_) | Command::ClockwiseArc(_)
+                    | Command::CounterClockwiseArc(_) | Command::ModalMove(_) => {
+                        pierces.extend(current.take());
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            if self.is_torch_off(block) {
+                pierces.extend(current.take());
+            }
+        }
+
+        pierces.extend(current);
+        return pierces;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_pierces() {
+        let profile = PlasmaProfile {
+            thc: Some((vec![Word::new('M', 65.0), Word::new('P', 2.0)], vec![Word::new('M', 64.0), Word::new('P', 2.0)])),
+            ..PlasmaProfile::default()
+        };
+
+        let program = Program::from(Parser::new().parse_all([
+            "G0 X10 Y10 Z3.8",
+            "M3 G4 P0.3",
+            "G4 P0.2",
+            "G1 Z1.5 F300",
+            "M65 P2",
+            "G1 X20",
+            "M5",
+            "M3",
+            "G1 X30",
+            "M5",
+        ].iter()).unwrap());
+
+        assert_eq!(profile.pierces(&program), vec![
+            Pierce { block: 1, delay: 0.5 },
+            Pierce { block: 7, delay: 0.0 },
+        ]);
+
+        assert!(profile.is_torch_off(&program.blocks()[6]));
+        assert!(PlasmaProfile::contains(&program.blocks()[4], &profile.thc.as_ref().unwrap().0));
+    }
+}
@@ -0,0 +1,15 @@
+//! Re-exports of the types needed for most programs, to be glob imported:
+//!
+//! ```
+//! use gcode::prelude::*;
+//! ```
+
+pub use crate::analysis::{Bounds, Estimate, MachineProfile};
+pub use crate::command::Command;
+pub use crate::facade::{open, Analysis, Error};
+pub use crate::geometry::{Axis, Point, Point3, Units};
+pub use crate::interp::{InterpError, Machine, State};
+pub use crate::parser::{Block, Parser, ParserError, Word};
+pub use crate::program::Program;
+pub use crate::toolpath::Segment;
+pub use crate::transform::Pass;
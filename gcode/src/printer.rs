@@ -0,0 +1,300 @@
+//! Extensions of G-code used by FDM printers.
+//!
+//! Printers extrude with the `E` axis and set temperatures and fans with M codes the interpreter
+//! does not know. The printer state tracks these words separately, leaving blocks the interpreter
+//! can execute.
+
+use std::ops::Range;
+
+use crate::Real;
+use crate::command::Command;
+use crate::interp::{InterpError, Machine};
+use crate::metadata::is_layer_start;
+use crate::parser::{Block, Word};
+use crate::program::Program;
+
+/// M codes of printers, which take their parameters from `S`, `P` and `R` words.
+const CODES: &[Real] = &[82.0, 83.0, 104.0, 106.0, 107.0, 109.0, 140.0, 190.0];
+
+/// Distance below which two heights are considered the same, in millimeters.
+const EPSILON: Real = 1e-6;
+
+/// The state of the extruder, heaters and fan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrinterState {
+    /// The position of the extruder, in millimeters of filament.
+    pub extruder: Real,
+
+    /// Whether `E` words are relative to the current position (M83).
+    pub relative_extrusion: bool,
+
+    /// The speed of the part cooling fan, from 0 to 1.
+    pub fan: Real,
+
+    /// The target temperature of the hot end, in degrees Celsius.
+    pub hotend: Option<Real>,
+
+    /// The target temperature of the bed, in degrees Celsius.
+    pub bed: Option<Real>,
+}
+
+impl Default for PrinterState {
+    fn default() -> Self {
+        Self {
+            extruder: 0.0,
+            relative_extrusion: false,
+            fan: 0.0,
+            hotend: None,
+            bed: None,
+        }
+    }
+}
+
+impl PrinterState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies the printer words of a block and returns the filament extruded by it, along with
+    /// the block reduced to the words the interpreter knows.
+    ///
+    /// Words the interpreter does not know at all, like other M codes used by printers, are
+    /// dropped as well, along with the parameters of blocks containing such codes. Retractions
+    /// count as negative extrusion.
+    pub fn apply(&mut self, block: &Block) -> (Real, Block) {
+        let words = block.words();
+        let value = |mnemonic: char| words.iter().find(|word| word.mnemonic() == mnemonic).map(|word| word.value());
+        let has = |mnemonic: char, code: Real| words.iter().any(|word| word.mnemonic() == mnemonic && word.value() == code);
+
+        let mut extruded = 0.0;
+        if let Some(e) = value('E') {
+            if has('G', 92.0) {
+                self.extruder = e;
+            } else if self.relative_extrusion {
+                extruded = e;
+                self.extruder += e;
+            } else {
+                extruded = e - self.extruder;
+                self.extruder = e;
+            }
+        }
+
+        let printer = words.iter().any(|word| word.mnemonic() == 'M' && CODES.contains(&word.value()));
+        for word in words.iter().filter(|word| word.mnemonic() == 'M') {
+            match word.value() as u32 {
+                82 => self.relative_extrusion = false,
+                83 => self.relative_extrusion = true,
+                104 | 109 => self.hotend = value('S').or_else(|| value('R')).or(self.hotend),
+                140 | 190 => self.bed = value('S').or_else(|| value('R')).or(self.bed),
+                106 => self.fan = value('S').map_or(1.0, |s| (s / 255.0).clamp(0.0, 1.0)),
+                107 => self.fan = 0.0,
+                _ => {}
+            }
+        }
+
+        let kept = words.iter()
+                .filter(|word| word.mnemonic() != 'E')
+                .filter(|word| !(word.mnemonic() == 'M' && CODES.contains(&word.value())))
+                .filter(|word| !(printer && ['S', 'P', 'R'].contains(&word.mnemonic())))
+                .cloned()
+                .collect::<Vec<Word>>();
+
+        // Setting only the extruder position leaves nothing to set for G92
+        let axes = kept.iter().any(|word| ['X', 'Y', 'Z'].contains(&word.mnemonic()));
+        let kept = kept.into_iter()
+                .filter(|word| axes || value('E').is_none() || !(word.mnemonic() == 'G' && word.value() == 92.0))
+                .collect::<Vec<_>>();
+
+        let commands = Command::from_block(&Block::new(kept));
+
+        // Parameters of unknown codes, like `M84 X`, are no moves
+        let unknown = commands.iter()
+                .any(|command| matches!(command, Command::Unknown(word) if word.mnemonic() == 'G' || word.mnemonic() == 'M'));
+        let commands = commands.into_iter()
+                .filter(|command| match command {
+                    Command::Unknown(_) => false,
+                    Command::ModalMove(_) | Command::SetFeedRate(_) | Command::SetSpindleSpeed(_) | Command::SelectTool(_) => !unknown,
+                    _ => true,
+                })
+                .collect::<Vec<_>>();
+
+        let reduced = Command::to_block(&commands)
+                .with_line_number(block.line_number())
+                .with_deleted(block.is_deleted())
+                .with_provenance(block.provenance().clone());
+
+        return (extruded, reduced);
+    }
+}
+
+/// A layer of a printed program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    /// The blocks belonging to the layer.
+    pub blocks: Range<usize>,
+
+    /// The height of the first extruding move in the layer, in millimeters.
+    pub z: Option<Real>,
+
+    /// The length of filament extruded, in millimeters, minus retractions.
+    pub extrusion: Real,
+}
+
+/// Splits a printed program into layers, starting with a machine in its default state.
+///
+/// Layers start at the layer markers left by slicers (`;LAYER:` or `;LAYER_CHANGE`). Programs
+/// without markers are split whenever the printer starts extruding at a height above the current
+/// layer, with the new layer starting at the block moving to that height. Blocks before the first
+/// layer, like the start code of the printer, do not belong to any layer.
+pub fn layers(program: &Program) -> Result<Vec<Layer>, InterpError> {
+    layers_with(Machine::new(), program)
+}
+
+/// Splits a printed program into layers like `layers`, but starting with the given machine.
+pub fn layers_with(machine: Machine, program: &Program) -> Result<Vec<Layer>, InterpError> {
+    let mut printer = PrinterState::new();
+    let (extruded, reduced): (Vec<Real>, Program) = program.iter()
+            .map(|block| printer.apply(block))
+            .unzip();
+
+    let markers = program.iter()
+            .enumerate()
+            .filter(|(_, block)| block.comments().iter().any(is_layer_start))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+    let mut starts = markers.clone();
+
+    // The height of the first extruding move of each block
+    let mut heights = vec![None; program.len()];
+
+    let mut layer = None;
+    let mut raised = 0;
+    let mut segments = machine.into_segments(reduced);
+    while let Some(segment) = segments.next() {
+        let segment = segment?;
+        let block = segments.machine().blocks() - 1;
+
+        let (start, end) = match (segment.start(), segment.end()) {
+            (Some(start), Some(end)) => (start, end),
+            _ => continue,
+        };
+
+        if (end.z - start.z).abs() > EPSILON {
+            raised = block;
+        }
+
+        if extruded[block] > 0.0 {
+            heights[block] = heights[block].or(Some(end.z));
+
+            if markers.is_empty() && layer.is_none_or(|z| end.z > z + EPSILON) {
+                layer = Some(end.z);
+                starts.push(raised.min(block));
+            }
+        }
+    }
+
+    starts.dedup();
+
+    return Ok(starts.iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = starts.get(i + 1).cloned().unwrap_or(program.len());
+
+                Layer {
+                    blocks: start..end,
+                    z: heights[start..end].iter().flatten().next().cloned(),
+                    extrusion: extruded[start..end].iter().sum(),
+                }
+            })
+            .collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn apply(printer: &mut PrinterState, line: &str) -> (Real, String) {
+        let (extruded, block) = printer.apply(&Parser::new().parse(line).unwrap());
+        return (extruded, block.line().to_owned());
+    }
+
+    #[test]
+    fn test_extrusion() {
+        let mut printer = PrinterState::new();
+
+        assert_eq!(apply(&mut printer, "G1 X10 E1.5 F1200"), (1.5, "G1 X10 F1200".to_owned()));
+        assert_eq!(apply(&mut printer, "G1 E0.5"), (-1.0, "G1".to_owned()));
+        assert_eq!(apply(&mut printer, "G92 E0"), (0.0, "".to_owned()));
+        assert_eq!(printer.extruder, 0.0);
+
+        assert_eq!(apply(&mut printer, "M83"), (0.0, "".to_owned()));
+        assert_eq!(apply(&mut printer, "G1 X20 E2"), (2.0, "G1 X20".to_owned()));
+        assert_eq!(apply(&mut printer, "G1 X30 E2"), (2.0, "G1 X30".to_owned()));
+        assert_eq!(printer.extruder, 4.0);
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut printer = PrinterState::new();
+
+        assert_eq!(apply(&mut printer, "M104 S210"), (0.0, "".to_owned()));
+        assert_eq!(apply(&mut printer, "M190 S60"), (0.0, "".to_owned()));
+        assert_eq!(apply(&mut printer, "M106 S127.5"), (0.0, "".to_owned()));
+        assert_eq!(apply(&mut printer, "M84 X0"), (0.0, "".to_owned()));
+        assert_eq!(printer.hotend, Some(210.0));
+        assert_eq!(printer.bed, Some(60.0));
+        assert_eq!(printer.fan, 0.5);
+
+        assert_eq!(apply(&mut printer, "M107"), (0.0, "".to_owned()));
+        assert_eq!(printer.fan, 0.0);
+
+        // Spindle speeds are kept for other codes
+        assert_eq!(apply(&mut printer, "M3 S100"), (0.0, "M3 S100".to_owned()));
+    }
+
+    fn program(lines: &[&str]) -> Program {
+        Program::from(Parser::new().parse_all(lines.iter()).unwrap())
+    }
+
+    #[test]
+    fn test_layers_markers() {
+        let layers = program(&[
+            "M104 S200",
+            "G28",
+            ";LAYER:0",
+            "G0 Z0.2",
+            "G1 X10 E1 F600",
+            "G1 E0.5",
+            ";LAYER:1",
+            "G0 Z0.4",
+            "G1 X0 E2.5",
+        ]).layers().unwrap();
+
+        assert_eq!(layers, vec![
+            Layer { blocks: 2..6, z: Some(0.2), extrusion: 0.5 },
+            Layer { blocks: 6..9, z: Some(0.4), extrusion: 2.0 },
+        ]);
+    }
+
+    #[test]
+    fn test_layers_heights() {
+        let layers = program(&[
+            "G28",
+            "G0 Z0.2",
+            "G1 X10 E1 F600",
+            "G0 Z1",
+            "G0 X0",
+            "G0 Z0.2",
+            "G1 X10 E2",
+            "G0 Z0.4",
+            "G1 X0 E3",
+        ]).layers().unwrap();
+
+        // Hopping up for travel does not start a layer
+        assert_eq!(layers, vec![
+            Layer { blocks: 1..7, z: Some(0.2), extrusion: 2.0 },
+            Layer { blocks: 7..9, z: Some(0.4), extrusion: 1.0 },
+        ]);
+    }
+}
@@ -0,0 +1,314 @@
+use std::iter::FromIterator;
+use std::slice;
+use std::vec;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::annotate::{Annotation, Target};
+use crate::builder::ProgramBuilder;
+use crate::extract::{self, Region};
+use crate::interp::InterpError;
+use crate::parser::{Block, Comment, CommentPosition, Word};
+use crate::printer::{self, Layer};
+use crate::transform::{self, Renumber};
+
+/// Whether the block is a `%` marking the start or the end of a program on tape.
+fn is_demarcation(block: &Block) -> bool {
+    block.is_empty() && block.line().trim_start().starts_with('%')
+}
+
+/// Whether the block is a blank line or a comment.
+fn is_blank(block: &Block) -> bool {
+    block.is_empty() && !is_demarcation(block)
+}
+
+pub(crate) fn is_program_end(word: &Word) -> bool {
+    word.mnemonic() == 'M' && (word.value() == 2.0 || word.value() == 30.0)
+}
+
+/// Removes the program end (M2 or M30) from the block, dropping blocks left without words.
+fn without_program_end(block: Block) -> Option<Block> {
+    if !block.words().iter().any(is_program_end) {
+        return Some(block);
+    }
+
+    let words = block.words().iter().filter(|word| !is_program_end(word)).cloned().collect::<Vec<_>>();
+    if words.is_empty() {
+        return None;
+    }
+
+    let mut stripped = Block::new(words)
+            .with_line_number(block.line_number())
+            .with_deleted(block.is_deleted())
+            .with_provenance(block.provenance().clone());
+    for comment in block.comments() {
+        let position = match comment.position() {
+            CommentPosition::Inline(_) => CommentPosition::Trailing,
+            position => position,
+        };
+        stripped = stripped.with_comment(Comment::new(comment.text(), comment.style(), position));
+    }
+
+    return Some(stripped);
+}
+
+/// A sequence of blocks forming a complete G-code program.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Program {
+    blocks: Vec<Block>,
+
+    annotations: Vec<Annotation>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self {
+            blocks: Vec::new(),
+            annotations: Vec::new(),
+        }
+    }
+
+    /// Starts building a program block by block (see `builder`).
+    pub fn builder() -> ProgramBuilder {
+        ProgramBuilder::new()
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn push(&mut self, block: Block) {
+        self.blocks.push(block);
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Block> {
+        self.blocks.iter()
+    }
+
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// Attaches an annotation to a block or layer of the program.
+    pub fn annotate(&mut self, annotation: Annotation) {
+        self.annotations.push(annotation);
+    }
+
+    /// Replaces all annotations, e.g. by the ones read from a sidecar (see `annotate`).
+    pub fn with_annotations(mut self, annotations: Vec<Annotation>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// The annotations attached to the given block or layer.
+    pub fn annotations_of(&self, target: Target) -> impl Iterator<Item=&Annotation> {
+        self.annotations.iter().filter(move |annotation| annotation.target == target)
+    }
+
+    /// Removes all annotations attached to the given block or layer.
+    pub fn remove_annotations(&mut self, target: Target) {
+        self.annotations.retain(|annotation| annotation.target != target);
+    }
+
+    /// Splits a printed program into layers (see `printer::layers`).
+    pub fn layers(&self) -> Result<Vec<Layer>, InterpError> {
+        printer::layers(self)
+    }
+
+    /// Extracts a region of the program as a standalone program (see `extract::extract`).
+    pub fn extract(&self, region: &Region) -> Result<Program, InterpError> {
+        extract::extract(self, region)
+    }
+
+    /// Joins programs one after another into a single one, like start G-code, a body and end
+    /// G-code.
+    ///
+    /// Only the last program may end the whole one, so program ends (M2 or M30) are removed from
+    /// the others. Of the `%` demarcations, only one starting the first program and one ending the
+    /// last are kept. Annotations of blocks are kept, those of layers dropped. Blocks with line
+    /// numbers are renumbered in steps of 10 if their numbers do not increase otherwise.
+    pub fn concat<I>(programs: I) -> Program
+        where I: IntoIterator<Item=Program> {
+        let programs = programs.into_iter().collect::<Vec<_>>();
+        let last = programs.len().saturating_sub(1);
+
+        let mut joined = Program::new();
+        for (i, program) in programs.into_iter().enumerate() {
+            joined.append(program, i == 0, i == last);
+        }
+
+        joined.renumber();
+        return joined;
+    }
+
+    /// Inserts the blocks of the other program before the block at `index`, like a macro.
+    ///
+    /// Program ends and `%` demarcations of the other program are removed, and its annotations of
+    /// blocks are kept like for `concat`, as are line numbers.
+    ///
+    /// Panics if `index` is beyond the end of the program.
+    pub fn splice(&mut self, index: usize, other: Program) {
+        assert!(index <= self.blocks.len(), "index out of bounds");
+
+        let tail = self.blocks.split_off(index);
+        let (moved, kept) = std::mem::take(&mut self.annotations).into_iter()
+                .partition::<Vec<_>, _>(|annotation| matches!(annotation.target, Target::Block(block) if block >= index));
+        self.annotations = kept;
+
+        self.append(other, false, false);
+
+        let inserted = self.blocks.len() - index;
+        self.blocks.extend(tail);
+        self.annotations.extend(moved.into_iter().map(|annotation| match annotation.target {
+            Target::Block(block) => Annotation { target: Target::Block(block + inserted), ..annotation },
+            Target::Layer(_) => annotation,
+        }));
+
+        self.renumber();
+    }
+
+    /// Appends the blocks of the program, without its program ends unless it is the `last` one
+    /// and without demarcations unless they start the `first` or end the `last` one.
+    fn append(&mut self, program: Program, first: bool, last: bool) {
+        let start = program.blocks.iter().position(|block| !is_blank(block));
+        let end = program.blocks.iter().rposition(|block| !is_blank(block));
+
+        let mut indices = Vec::with_capacity(program.blocks.len());
+        for (i, block) in program.blocks.into_iter().enumerate() {
+            let block = if is_demarcation(&block) {
+                Some(block).filter(|_| (first && Some(i) == start) || (last && Some(i) == end))
+            } else if last {
+                Some(block)
+            } else {
+                without_program_end(block)
+            };
+
+            indices.push(block.as_ref().map(|_| self.blocks.len()));
+            self.blocks.extend(block);
+        }
+
+        for annotation in program.annotations {
+            if let Target::Block(block) = annotation.target {
+                if let Some(&Some(index)) = indices.get(block) {
+                    self.annotations.push(Annotation { target: Target::Block(index), ..annotation });
+                }
+            }
+        }
+    }
+
+    /// Renumbers the blocks with line numbers in steps of 10 from the first one, unless their
+    /// numbers increase already.
+    fn renumber(&mut self) {
+        let numbers = self.blocks.iter().filter_map(Block::line_number).collect::<Vec<_>>();
+        if numbers.windows(2).all(|pair| pair[0] < pair[1]) {
+            return;
+        }
+
+        let start = numbers[0].max(0.0) as u64;
+        let blocks = std::mem::take(&mut self.blocks);
+        self.blocks = transform::apply(&mut Renumber::new(start, 10).with_numbered_only(true), blocks).blocks;
+    }
+}
+
+impl From<Vec<Block>> for Program {
+    fn from(blocks: Vec<Block>) -> Self {
+        Self {
+            blocks,
+            annotations: Vec::new(),
+        }
+    }
+}
+
+impl FromIterator<Block> for Program {
+    fn from_iter<I>(iter: I) -> Self
+        where I: IntoIterator<Item=Block> {
+        Self {
+            blocks: iter.into_iter().collect(),
+            annotations: Vec::new(),
+        }
+    }
+}
+
+impl Extend<Block> for Program {
+    fn extend<I>(&mut self, iter: I)
+        where I: IntoIterator<Item=Block> {
+        self.blocks.extend(iter);
+    }
+}
+
+impl IntoIterator for Program {
+    type Item = Block;
+    type IntoIter = vec::IntoIter<Block>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.blocks.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Program {
+    type Item = &'a Block;
+    type IntoIter = slice::Iter<'a, Block>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.blocks.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::Options;
+    use crate::parser::Parser;
+
+    fn program(text: &str) -> Program {
+        Program::from(Parser::new().parse_all(text.lines()).unwrap())
+    }
+
+    #[test]
+    fn test_concat() {
+        // Demarcations are only written back verbatim
+        let lossless = |text: &str| Program::from(Parser::new().with_lossless(true).parse_all(text.lines()).unwrap());
+        let start = lossless("%\nG21 G90\nM30\n%");
+        let body = lossless("%\nG0 X1 ; body\nG1 X2 F100 M30 ; done\n%");
+        let end = lossless("\n%\nG0 Z10\nM30 ; end\n%\n");
+
+        let joined = Program::concat(vec![start, body, end]);
+        let options = Options { verbatim: true, ..Options::default() };
+        assert_eq!(options.display(&joined).to_string(), "%\nG21 G90\nG0 X1 ; body\nG1 X2 F100 ; done\n\nG0 Z10\nM30 ; end\n%\n");
+    }
+
+    #[test]
+    fn test_concat_line_numbers() {
+        let joined = Program::concat(vec![program("N10 G21\nN20 G90"), program("N30 G0 X1")]);
+        assert_eq!(joined.to_string(), "N10 G21\nN20 G90\nN30 G0 X1\n");
+
+        let joined = Program::concat(vec![program("N10 G21\nN20 G90"), program("G0 X0\nN10 G0 X1\nN20 M30")]);
+        assert_eq!(joined.to_string(), "N10 G21\nN20 G90\nG0 X0\nN30 G0 X1\nN40 M30\n");
+    }
+
+    #[test]
+    fn test_splice() {
+        let mut body = program("G0 X1\nG0 X2\nM30");
+        body.annotate(Annotation::new(Target::Block(1), "second"));
+
+        let mut park = program("%\nG0 Z10\nM0\nM30\n%");
+        park.annotate(Annotation::new(Target::Block(2), "pause"));
+
+        body.splice(1, park);
+        assert_eq!(body.to_string(), "G0 X1\nG0 Z10\nM0\nG0 X2\nM30\n");
+        assert_eq!(body.annotations(), &[
+            Annotation::new(Target::Block(2), "pause"),
+            Annotation::new(Target::Block(3), "second"),
+        ]);
+    }
+}
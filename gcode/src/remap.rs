@@ -0,0 +1,310 @@
+//! Remapping of axis letters for machines with unconventional axis assignments.
+//!
+//! A map assigns each axis letter of the machine the conventional letter meaning the same motion,
+//! e.g. swapping Y and Z for a foam cutter or using A for the E axis of a printer. Programs are
+//! converted to the conventional letters when parsed, so the interpreter, analyses and passes work
+//! unchanged, and back to the machine letters before being emitted.
+//!
+//! Arc offsets (`I`, `J` and `K`) follow their axes, as do the plane selections. Mirroring the
+//! linear axes by an odd number of swaps reverses the direction of arcs, so G2 and G3 are
+//! exchanged as well.
+
+use crate::Real;
+use crate::parser::{Block, Word};
+#[cfg(feature = "std")]
+use crate::transform::Pass;
+
+/// The letters of all axes which can be remapped.
+pub const AXES: [char; 10] = ['X', 'Y', 'Z', 'A', 'B', 'C', 'U', 'V', 'W', 'E'];
+
+/// The arc offset letters belonging to the linear axes.
+const OFFSETS: [char; 3] = ['I', 'J', 'K'];
+
+/// The planes (G17, G18 and G19) given by their first and second axis.
+const PLANES: [(Real, char, char); 3] = [(17.0, 'X', 'Y'), (18.0, 'Z', 'X'), (19.0, 'Y', 'Z')];
+
+/// A permutation of the axis letters, from machine letters to conventional ones.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct AxisMap {
+    /// The conventional letter of the machine axis at the same position in `AXES`
+    canonical: [char; 10],
+}
+
+impl Default for AxisMap {
+    fn default() -> Self {
+        Self {
+            canonical: AXES,
+        }
+    }
+}
+
+impl AxisMap {
+    /// Creates a map keeping all letters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exchanges the conventional meaning of the two machine axes.
+    ///
+    /// Panics if one of the letters is not an axis.
+    pub fn swap(mut self, a: char, b: char) -> Self {
+        let index = |letter: char| AXES.iter().position(|&axis| axis == letter.to_ascii_uppercase())
+                .unwrap_or_else(|| panic!("not an axis: {}", letter));
+
+        self.canonical.swap(index(a), index(b));
+        self
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.canonical == AXES
+    }
+
+    /// The conventional letter for a letter used by the machine.
+    pub fn to_canonical(&self, letter: char) -> char {
+        return self.letter(letter, |index| self.canonical[index], |axis| self.canonical_axis(axis));
+    }
+
+    /// The machine letter for a conventional letter.
+    pub fn to_machine(&self, letter: char) -> char {
+        return self.letter(letter, |index| self.machine_axis(AXES[index]), |axis| self.machine_axis(axis));
+    }
+
+    /// Converts a word using machine letters to conventional ones.
+    pub fn canonical_word(&self, word: &Word) -> Word {
+        return self.word(word, |letter| self.to_canonical(letter));
+    }
+
+    /// Converts a word using conventional letters to machine ones.
+    pub fn machine_word(&self, word: &Word) -> Word {
+        return self.word(word, |letter| self.to_machine(letter));
+    }
+
+    /// Converts all words of a block using machine letters to conventional ones.
+    ///
+    /// The source line of the block is kept as is.
+    pub fn canonical_block(&self, block: Block) -> Block {
+        block.map_words(|word| self.canonical_word(word))
+    }
+
+    /// Converts all words of a block using conventional letters to machine ones.
+    ///
+    /// The source line of the block is kept as is.
+    pub fn machine_block(&self, block: Block) -> Block {
+        block.map_words(|word| self.machine_word(word))
+    }
+
+    fn canonical_axis(&self, axis: char) -> char {
+        return match AXES.iter().position(|&a| a == axis) {
+            Some(index) => self.canonical[index],
+            None => axis,
+        };
+    }
+
+    fn machine_axis(&self, axis: char) -> char {
+        return match self.canonical.iter().position(|&a| a == axis) {
+            Some(index) => AXES[index],
+            None => axis,
+        };
+    }
+
+    /// Maps axis letters by `axis` and the offsets along with their linear axes by `linear`.
+    fn letter<A, L>(&self, letter: char, axis: A, linear: L) -> char
+        where A: Fn(usize) -> char,
+              L: Fn(char) -> char {
+        if let Some(index) = AXES.iter().position(|&a| a == letter) {
+            return axis(index);
+        }
+
+        if let Some(index) = OFFSETS.iter().position(|&offset| offset == letter) {
+            // Offsets of axes mapped to rotary or other axes have no counterpart
+            return match AXES[..3].iter().position(|&a| a == linear(AXES[index])) {
+                Some(index) => OFFSETS[index],
+                None => letter,
+            };
+        }
+
+        return letter;
+    }
+
+    /// Whether the linear axes are mirrored, which reverses the direction of arcs.
+    fn is_mirrored(&self) -> bool {
+        let linear = &self.canonical[..3];
+
+        // Count the inversions of the permutation, if the linear axes are mapped onto each other
+        if !AXES[..3].iter().all(|axis| linear.contains(axis)) {
+            return false;
+        }
+
+        let position = |axis: char| AXES.iter().position(|&a| a == axis).unwrap_or(0);
+        let inversions = (0..3)
+                .flat_map(|i| (i + 1..3).map(move |j| (i, j)))
+                .filter(|&(i, j)| position(linear[i]) > position(linear[j]))
+                .count();
+
+        return inversions % 2 == 1;
+    }
+
+    fn word<F>(&self, word: &Word, letter: F) -> Word
+        where F: Fn(char) -> char {
+        if word.mnemonic() != 'G' {
+            return Word::new(letter(word.mnemonic()), word.value());
+        }
+
+        let value = word.value();
+
+        if (value == 2.0 || value == 3.0) && self.is_mirrored() {
+            return Word::new('G', 5.0 - value);
+        }
+
+        if let Some(&(_, first, second)) = PLANES.iter().find(|&&(code, _, _)| code == value) {
+            let (first, second) = (letter(first), letter(second));
+
+            // The order of the axes does not matter for the plane, only for the arc direction
+            if let Some(&(code, _, _)) = PLANES.iter()
+                    .find(|&&(_, a, b)| (a, b) == (first, second) || (b, a) == (first, second)) {
+                return Word::new('G', code);
+            }
+        }
+
+        return *word;
+    }
+}
+
+/// Converts the axis letters of all blocks in one direction of an axis map.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg(feature = "std")]
+pub struct Remap {
+    map: AxisMap,
+    canonical: bool,
+}
+
+#[cfg(feature = "std")]
+impl Remap {
+    /// Creates a pass converting machine letters to conventional ones.
+    pub fn to_canonical(map: AxisMap) -> Self {
+        Self {
+            map,
+            canonical: true,
+        }
+    }
+
+    /// Creates a pass converting conventional letters to machine ones, e.g. before emitting.
+    pub fn to_machine(map: AxisMap) -> Self {
+        Self {
+            map,
+            canonical: false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Pass for Remap {
+    fn name(&self) -> &'static str {
+        "remap"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        if self.map.is_identity() {
+            output.push(block);
+            return;
+        }
+
+        let block = if self.canonical {
+            self.map.canonical_block(block)
+        } else {
+            self.map.machine_block(block)
+        };
+
+        output.push(block.with_pass(self.name()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::Options;
+    use crate::parser::Parser;
+    use crate::program::Program;
+    use crate::transform::apply;
+
+    fn words(map: &AxisMap, line: &str, canonical: bool) -> Vec<Word> {
+        let block = Parser::new().parse(line).unwrap();
+        let block = if canonical { map.canonical_block(block) } else { map.machine_block(block) };
+        return block.words().to_vec();
+    }
+
+    #[test]
+    fn test_swap() {
+        let map = AxisMap::new().swap('Y', 'Z');
+        assert!(!map.is_identity());
+        assert!(map.is_mirrored());
+
+        assert_eq!(words(&map, "G17 G2 X1 Y2 Z3 I4 J5 F100", true), vec![
+            Word::new('G', 18.0),
+            Word::new('G', 3.0),
+            Word::new('X', 1.0),
+            Word::new('Z', 2.0),
+            Word::new('Y', 3.0),
+            Word::new('I', 4.0),
+            Word::new('K', 5.0),
+            Word::new('F', 100.0),
+        ]);
+
+        assert_eq!(AxisMap::new().swap('y', 'z').swap('Z', 'Y'), AxisMap::new());
+    }
+
+    #[test]
+    fn test_rotate() {
+        // An even permutation keeps the direction of arcs
+        let map = AxisMap::new().swap('X', 'Y').swap('Y', 'Z');
+        assert!(!map.is_mirrored());
+
+        for &letter in &['X', 'Y', 'Z', 'I', 'J', 'K', 'A', 'F'] {
+            assert_eq!(map.to_machine(map.to_canonical(letter)), letter);
+        }
+
+        assert_eq!(words(&map, "G2 G19", false), vec![Word::new('G', 2.0), Word::new('G', 17.0)]);
+    }
+
+    #[test]
+    fn test_extruder() {
+        let map = AxisMap::new().swap('E', 'A');
+        assert!(!map.is_mirrored());
+
+        assert_eq!(words(&map, "G1 X1 E0.5", true), vec![Word::new('G', 1.0), Word::new('X', 1.0), Word::new('A', 0.5)]);
+        assert_eq!(words(&map, "G1 A0.5", false), vec![Word::new('G', 1.0), Word::new('E', 0.5)]);
+    }
+
+    #[test]
+    fn test_pass() {
+        let map = AxisMap::new().swap('Y', 'Z');
+        let blocks = Parser::new().parse_all(["G0 Y1 Z2"].iter()).unwrap();
+
+        let canonical = apply(&mut Remap::to_canonical(map), blocks.clone());
+        assert_eq!(canonical.blocks()[0].words(), &[Word::new('G', 0.0), Word::new('Z', 1.0), Word::new('Y', 2.0)]);
+        assert_eq!(canonical.blocks()[0].line(), "G0 Y1 Z2");
+        assert_eq!(canonical.blocks()[0].span(1), blocks[0].span(1));
+
+        let machine = apply(&mut Remap::to_machine(map), canonical.clone());
+        assert_eq!(machine.blocks()[0].words(), blocks[0].words());
+
+        let parsed = Parser::new().with_axis_map(map).parse("G0 Y1 Z2").unwrap();
+        assert_eq!(parsed.words(), canonical.blocks()[0].words());
+    }
+
+    #[test]
+    fn test_pass_lossless() {
+        // Converted blocks are written from their words, not from the source text
+        let map = AxisMap::new().swap('Y', 'Z');
+        let options = Options { verbatim: true, ..Options::default() };
+
+        let blocks = Parser::new().with_lossless(true).parse_all(["G0  Y1 Z2", "M5"].iter()).unwrap();
+        let canonical = apply(&mut Remap::to_canonical(map), blocks);
+        assert_eq!(canonical.blocks()[0].original(), None);
+        assert_eq!(canonical.blocks()[1].original(), Some("M5"));
+        assert_eq!(options.display(&canonical).to_string(), "G0 Z1 Y2\nM5\n");
+
+        let parsed = Parser::new().with_lossless(true).with_axis_map(map).parse_all(["G0  Y1 Z2"].iter()).unwrap();
+        assert_eq!(options.display(&Program::from(parsed)).to_string(), "G0 Z1 Y2\n");
+    }
+}
@@ -0,0 +1,302 @@
+//! Replies of controllers to the lines sent to them.
+//!
+//! Marlin, RepRapFirmware and Grbl acknowledge each line with `ok` and report errors, busy states
+//! and temperatures in between. The replies of the firmwares differ, but are easily told apart.
+
+use crate::Real;
+use crate::geometry::Point3;
+
+/// The temperature of a heater or sensor, like `T0:200.1 /210.0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Temperature {
+    /// The name of the sensor as reported, like `T`, `T1`, `B` or `C`.
+    pub sensor: String,
+
+    /// The measured temperature, in degrees Celsius.
+    pub current: Real,
+
+    /// The temperature the heater is set to, if reported.
+    pub target: Option<Real>,
+}
+
+/// A status report of Grbl, like `<Idle|MPos:1.000,2.000,0.000|FS:0,0|WCO:0.000,0.000,0.000>`,
+/// sent in reply to `?` (see `Realtime::StatusReport`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    /// The state of the machine, like `Idle`, `Run`, `Hold:0` or `Alarm`.
+    pub state: String,
+
+    /// The position in machine coordinates, if reported.
+    pub machine_position: Option<Point3>,
+
+    /// The position in work coordinates, if reported.
+    pub work_position: Option<Point3>,
+
+    /// The offset of the work coordinates, which Grbl reports only now and then.
+    pub work_offset: Option<Point3>,
+}
+
+impl Status {
+    /// The position in machine coordinates, either as reported or from the position in work
+    /// coordinates and their offset.
+    pub fn position(&self) -> Option<Point3> {
+        if self.machine_position.is_some() {
+            return self.machine_position;
+        }
+
+        let (work, offset) = (self.work_position?, self.work_offset?);
+        return Some(Point3::new(work.x + offset.x, work.y + offset.y, work.z + offset.z));
+    }
+}
+
+/// A single line received from a controller.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// The acknowledgement of a line, optionally followed by the line number and the free space in
+    /// the planner and the command buffer (Marlin's `ADVANCED_OK`) or by temperatures (`M105`).
+    Ok {
+        line: Option<u64>,
+        planner: Option<u32>,
+        buffer: Option<u32>,
+        temperatures: Vec<Temperature>,
+    },
+
+    /// An error with the code of Grbl (`error:20`) or the message of Marlin (`Error:Printer
+    /// halted`).
+    Error {
+        code: Option<u32>,
+        message: String,
+    },
+
+    /// A Grbl alarm (`ALARM:1`), locking the controller until reset or unlocked.
+    Alarm(u32),
+
+    /// The controller is still busy executing a long command, like `echo:busy: processing`.
+    Busy(String),
+
+    /// The controller asks for the line with the given number to be sent again.
+    Resend(u64),
+
+    /// A temperature report sent without request (`M155`) or while heating.
+    Temperatures(Vec<Temperature>),
+
+    /// A status report of Grbl.
+    Status(Status),
+
+    /// Anything else, like echoes and welcome messages.
+    Other(String),
+}
+
+impl Response {
+    /// Parses a line received from a controller, without the line break.
+    pub fn parse(line: &str) -> Self {
+        let line = line.trim();
+
+        if line == "ok" || line.starts_with("ok ") {
+            return parse_ok(&line[2..]);
+        }
+
+        if let Some(code) = line.strip_prefix("error:") {
+            return match code.trim().parse() {
+                Ok(code) => Response::Error { code: Some(code), message: String::new() },
+                Err(_) => Response::Error { code: None, message: code.trim().to_owned() },
+            };
+        }
+
+        if let Some(message) = line.strip_prefix("Error:") {
+            return Response::Error { code: None, message: message.trim().to_owned() };
+        }
+
+        if let Some(Ok(code)) = line.strip_prefix("ALARM:").map(|code| code.trim().parse()) {
+            return Response::Alarm(code);
+        }
+
+        if let Some(state) = line.strip_prefix("echo:busy:") {
+            return Response::Busy(state.trim().to_owned());
+        }
+
+        if let Some(Ok(number)) = line.strip_prefix("Resend:").map(|number| number.trim().parse()) {
+            return Response::Resend(number);
+        }
+
+        if let Some(report) = line.strip_prefix('<').and_then(|report| report.strip_suffix('>')) {
+            return Response::Status(parse_status(report));
+        }
+
+        let temperatures = parse_temperatures(line);
+        if !temperatures.is_empty() {
+            return Response::Temperatures(temperatures);
+        }
+
+        return Response::Other(line.to_owned());
+    }
+
+    /// Whether the controller accepted the line and is ready for the next one.
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Response::Ok { .. })
+    }
+}
+
+/// Parses the rest of an `ok` line, which is either advanced acknowledgement or a temperature
+/// report.
+fn parse_ok(rest: &str) -> Response {
+    let mut line = None;
+    let mut planner = None;
+    let mut buffer = None;
+
+    for field in rest.split_whitespace() {
+        let (key, value) = field.split_at(field.chars().next().map_or(0, char::len_utf8));
+        let value = value.trim_start_matches(':');
+        match key {
+            "N" => line = value.parse().ok(),
+            "P" => planner = value.parse().ok(),
+            "B" => buffer = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    let temperatures = parse_temperatures(rest);
+    if !temperatures.is_empty() {
+        return Response::Ok { line: None, planner: None, buffer: None, temperatures };
+    }
+
+    return Response::Ok { line, planner, buffer, temperatures };
+}
+
+/// Parses a status report without the angle brackets, with the fields separated by `|` as in Grbl
+/// 1.1 or by `,` as in Grbl 0.9.
+fn parse_status(report: &str) -> Status {
+    let state = report.split(['|', ',']).next().unwrap_or_default().to_owned();
+
+    let position = |key: &str| {
+        let start = report.find(key)? + key.len();
+        let mut values = report[start..].split(['|', ',']).map(|value| value.trim().parse::<Real>().ok());
+        return Some(Point3::new(values.next()??, values.next()??, values.next()??));
+    };
+
+    return Status {
+        state,
+        machine_position: position("MPos:"),
+        work_position: position("WPos:"),
+        work_offset: position("WCO:"),
+    };
+}
+
+/// Parses the sensors of a temperature report like `T:200.1 /210.0 B:60.0 /60.0 @:127`, skipping
+/// the heater powers.
+fn parse_temperatures(line: &str) -> Vec<Temperature> {
+    let mut temperatures: Vec<Temperature> = Vec::new();
+
+    for field in line.split_whitespace() {
+        // The target is either a field of its own or attached to the current temperature
+        if let Some(target) = field.strip_prefix('/') {
+            if let Some(last) = temperatures.last_mut() {
+                last.target = target.parse().ok();
+            }
+            continue;
+        }
+
+        let (sensor, value) = match field.find(':') {
+            Some(i) => (&field[..i], &field[i + 1..]),
+            None => continue,
+        };
+
+        let known = sensor.starts_with(|c| "TBCPAR".contains(c)) && sensor[1..].chars().all(|c| c.is_ascii_digit());
+        if !known {
+            continue;
+        }
+
+        let (current, target) = match value.find('/') {
+            Some(i) => (&value[..i], value[i + 1..].parse().ok()),
+            None => (value, None),
+        };
+
+        // Temperatures always have decimals, which tells `B:60.0` from the buffer in `ok N12 B:3`
+        if !current.contains('.') {
+            continue;
+        }
+
+        if let Ok(current) = current.parse() {
+            temperatures.push(Temperature { sensor: sensor.to_owned(), current, target });
+        }
+    }
+
+    return temperatures;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temperature(sensor: &str, current: Real, target: Option<Real>) -> Temperature {
+        Temperature { sensor: sensor.to_owned(), current, target }
+    }
+
+    #[test]
+    fn test_ok() {
+        assert_eq!(Response::parse("ok\r"), Response::Ok { line: None, planner: None, buffer: None, temperatures: vec![] });
+        assert_eq!(Response::parse("ok N12 P15 B3"), Response::Ok { line: Some(12), planner: Some(15), buffer: Some(3), temperatures: vec![] });
+        assert_eq!(Response::parse("ok N:12 P:15 B:3"), Response::Ok { line: Some(12), planner: Some(15), buffer: Some(3), temperatures: vec![] });
+        assert!(Response::parse("ok").is_ok());
+        assert!(!Response::parse("okay").is_ok());
+    }
+
+    #[test]
+    fn test_status() {
+        let status = match Response::parse("<Idle|MPos:1.000,2.500,-3.000|FS:0,0|WCO:1.000,0.000,0.000>") {
+            Response::Status(status) => status,
+            response => panic!("unexpected response: {:?}", response),
+        };
+        assert_eq!(status.state, "Idle");
+        assert_eq!(status.position(), Some(Point3::new(1.0, 2.5, -3.0)));
+        assert_eq!(status.work_offset, Some(Point3::new(1.0, 0.0, 0.0)));
+
+        // Positions in work coordinates are converted with the offset, if reported
+        let status = match Response::parse("<Hold:0|WPos:0.000,2.500,-3.000|FS:0,0|WCO:1.000,0.000,0.000>") {
+            Response::Status(status) => status,
+            response => panic!("unexpected response: {:?}", response),
+        };
+        assert_eq!((status.state.as_str(), status.position()), ("Hold:0", Some(Point3::new(1.0, 2.5, -3.0))));
+
+        // Grbl 0.9 separates all fields by commas
+        let status = match Response::parse("<Run,MPos:5.000,0.000,0.000,WPos:0.000,0.000,0.000>") {
+            Response::Status(status) => status,
+            response => panic!("unexpected response: {:?}", response),
+        };
+        assert_eq!((status.state.as_str(), status.position()), ("Run", Some(Point3::new(5.0, 0.0, 0.0))));
+        assert_eq!(status.work_position, Some(Point3::new(0.0, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_temperatures() {
+        assert_eq!(Response::parse("ok T:200.1 /210.0 B:60.0 /60.0 @:127 B@:0"), Response::Ok {
+            line: None,
+            planner: None,
+            buffer: None,
+            temperatures: vec![temperature("T", 200.1, Some(210.0)), temperature("B", 60.0, Some(60.0))],
+        });
+        assert_eq!(Response::parse(" T0:25.3/0.0 T1:24.9/0.0 B:22.0"), Response::Temperatures(vec![
+            temperature("T0", 25.3, Some(0.0)),
+            temperature("T1", 24.9, Some(0.0)),
+            temperature("B", 22.0, None),
+        ]));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Response::parse("error:20"), Response::Error { code: Some(20), message: String::new() });
+        assert_eq!(Response::parse("Error:Printer halted. kill() called!"), Response::Error {
+            code: None,
+            message: "Printer halted. kill() called!".to_owned(),
+        });
+        assert_eq!(Response::parse("ALARM:1"), Response::Alarm(1));
+    }
+
+    #[test]
+    fn test_other() {
+        assert_eq!(Response::parse("echo:busy: processing"), Response::Busy("processing".to_owned()));
+        assert_eq!(Response::parse("Resend: 42"), Response::Resend(42));
+        assert_eq!(Response::parse("Grbl 1.1h ['$' for help]"), Response::Other("Grbl 1.1h ['$' for help]".to_owned()));
+        assert_eq!(Response::parse("echo:SD card ok"), Response::Other("echo:SD card ok".to_owned()));
+    }
+}
@@ -0,0 +1,265 @@
+//! Uploading programs to the SD card of printers.
+//!
+//! Marlin and RepRapFirmware write the lines following `M28` to a file on their SD card instead of
+//! executing them, until `M29`. The lines are sent numbered and checksummed, so lines corrupted on
+//! the way are sent again (see `Sender::with_checksums`). Once written, the size of the file is read
+//! back from the file list (`M20`) and compared with the size of the lines written, if listed.
+//!
+//! Files opened for writing are truncated, so interrupted uploads start over (see
+//! `Upload::restart`). MeatPack compression is not supported.
+
+use std::error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::emit::Options;
+use crate::parser::{Block, Word};
+use crate::program::Program;
+use crate::response::Response;
+use crate::sender::{FlowControl, Progress, Sender, SenderError};
+
+#[derive(Debug)]
+pub enum UploadError {
+    Sender(SenderError),
+
+    /// The file is not in the file list after writing it.
+    Missing(String),
+
+    /// The file list reports a size differing from the one of the lines written, in bytes.
+    Size {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::Sender(err) => write!(f, "{}", err),
+            UploadError::Missing(name) => write!(f, "file not listed after upload: {}", name),
+            UploadError::Size { expected, actual } => write!(f, "file has {} bytes instead of {}", actual, expected),
+        }
+    }
+}
+
+impl error::Error for UploadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            UploadError::Sender(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<SenderError> for UploadError {
+    fn from(err: SenderError) -> Self {
+        UploadError::Sender(err)
+    }
+}
+
+/// A file on the SD card, as listed by `M20`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    pub name: String,
+
+    /// The size in bytes, if listed, as Marlin does.
+    pub size: Option<u64>,
+}
+
+/// Parses the file list of `M20` from the replies, between `Begin file list` and `End file list`.
+pub fn files(replies: &[Response]) -> Vec<File> {
+    let mut files = Vec::new();
+    let mut listing = false;
+
+    for reply in replies {
+        let line = match reply {
+            Response::Other(line) => line.as_str(),
+            _ => continue,
+        };
+
+        match line {
+            "Begin file list" => listing = true,
+            "End file list" => listing = false,
+            _ if listing => {
+                let mut fields = line.split_whitespace();
+                if let Some(name) = fields.next() {
+                    files.push(File {
+                        name: name.to_owned(),
+                        size: fields.next().and_then(|size| size.parse().ok()),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    return files;
+}
+
+/// Writes a program to a file on the SD card of a printer.
+pub struct Upload<T> {
+    sender: Sender<T>,
+
+    /// The file name, followed by the non-empty blocks of the program, enclosed in `M28` and `M29`
+    program: Program,
+
+    name: String,
+
+    flow_control: FlowControl,
+}
+
+impl<T> Upload<T>
+    where T: Read + Write {
+    /// Creates an upload of the non-empty blocks of the program to the file with the given name.
+    ///
+    /// Marlin lists files by their short name, so names must be in 8.3 format to verify them.
+    pub fn new<S>(transport: T, name: S, program: &Program) -> Self
+        where S: Into<String> {
+        let name = name.into();
+
+        let mut blocks = vec![Block::new(vec![Word::new('M', 28.0)]).with_argument(Some(name.clone()))];
+        blocks.extend(program.iter().filter(|block| !block.is_empty()).cloned());
+        blocks.push(Block::new(vec![Word::new('M', 29.0)]));
+        let program = Program::from(blocks);
+
+        let flow_control = FlowControl::default();
+        Self {
+            sender: Sender::new(transport, &program).with_checksums(true).with_flow_control(flow_control),
+            program,
+            name,
+            flow_control,
+        }
+    }
+
+    /// Allows up to `window` lines to be sent before the first of them is acknowledged (see
+    /// `Sender::with_window`).
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.flow_control = FlowControl::Lines(window.max(1));
+        self.sender = self.sender.with_flow_control(self.flow_control);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The size of the file once written, in bytes, as Marlin writes each line without line
+    /// number and checksum, followed by a carriage return and a line feed.
+    pub fn size(&self) -> u64 {
+        let options = Options {
+            line_numbers: false,
+            comments: false,
+            ..Options::default()
+        };
+
+        let blocks = &self.program.blocks()[1..self.program.len() - 1];
+        return blocks.iter().map(|block| options.display(block).to_string().len() as u64 + 2).sum();
+    }
+
+    /// The progress of the upload so far, counting `M28` and `M29` as lines.
+    pub fn progress(&self) -> Progress {
+        self.sender.progress()
+    }
+
+    pub fn transport(&self) -> &T {
+        self.sender.transport()
+    }
+
+    pub fn into_transport(self) -> T {
+        self.sender.into_transport()
+    }
+
+    /// Writes the file and verifies its size, if the file list reports it.
+    pub fn run(&mut self) -> Result<(), UploadError> {
+        self.sender.run()?;
+
+        let files = files(&self.sender.execute("M20")?);
+        let file = files.iter()
+                .find(|file| file.name.eq_ignore_ascii_case(&self.name))
+                .ok_or_else(|| UploadError::Missing(self.name.clone()))?;
+
+        let expected = self.size();
+        match file.size {
+            Some(actual) if actual != expected => return Err(UploadError::Size { expected, actual }),
+            _ => return Ok(()),
+        }
+    }
+
+    /// Starts the upload over on a new connection, e.g. after the transport failed or timed out.
+    ///
+    /// Unlike jobs, uploads are not continued where they stopped (see `Sender::reconnect`), as
+    /// the firmware truncates the file when opening it again.
+    pub fn restart(&mut self, transport: T) {
+        event!(info, name = %self.name, "restarting upload");
+        self.sender = Sender::new(transport, &self.program).with_checksums(true).with_flow_control(self.flow_control);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sender::checksum;
+    use crate::sender::tests::{program, Transport};
+
+    const WRITTEN: &str = "ok\nWriting to file: TEST.GCO\nok\nok\nok\nDone saving file.\nok\n";
+
+    fn framed(number: usize, line: &str) -> String {
+        let line = format!("N{} {}", number, line);
+        format!("{}*{}\n", line, checksum(&line))
+    }
+
+    #[test]
+    fn test_files() {
+        let replies = ["Begin file list", "TEST.GCO 42", "/SUB/PART.GCO 7 part.gcode", "NOSIZE.G", "End file list", "ok"];
+        let replies = replies.iter().map(|line| Response::parse(line)).collect::<Vec<_>>();
+        assert_eq!(files(&replies), vec![
+            File { name: "TEST.GCO".to_owned(), size: Some(42) },
+            File { name: "/SUB/PART.GCO".to_owned(), size: Some(7) },
+            File { name: "NOSIZE.G".to_owned(), size: None },
+        ]);
+    }
+
+    #[test]
+    fn test_upload() {
+        let job = program("G28 ; home\n\nG1 X10 F600\n");
+        let replies = format!("{}Begin file list\nTEST.GCO 18\nEnd file list\nok\n", WRITTEN);
+        let mut upload = Upload::new(Transport::new(&replies), "test.gco", &job);
+        assert_eq!(upload.size(), 18);
+
+        upload.run().unwrap();
+        assert_eq!(upload.transport().sent(), [
+            framed(0, "M110 N0"),
+            framed(1, "M28 test.gco"),
+            framed(2, "G28"),
+            framed(3, "G1 X10 F600"),
+            framed(4, "M29"),
+            framed(5, "M20"),
+        ].concat());
+        assert_eq!(upload.progress().lines_acknowledged, 4);
+
+        // The size is verified if listed
+        let replies = format!("{}Begin file list\nTEST.GCO 12\nEnd file list\nok\n", WRITTEN);
+        let mut upload = Upload::new(Transport::new(&replies), "test.gco", &job);
+        assert!(matches!(upload.run(), Err(UploadError::Size { expected: 18, actual: 12 })));
+
+        let replies = format!("{}Begin file list\nTEST.GCO\nEnd file list\nok\n", WRITTEN);
+        Upload::new(Transport::new(&replies), "test.gco", &job).run().unwrap();
+
+        let replies = format!("{}Begin file list\nEnd file list\nok\n", WRITTEN);
+        let mut upload = Upload::new(Transport::new(&replies), "test.gco", &job);
+        assert!(matches!(upload.run(), Err(UploadError::Missing(_))));
+    }
+
+    #[test]
+    fn test_restart() {
+        let job = program("G28\nG1 X10 F600\n");
+        let mut upload = Upload::new(Transport::new("ok\nok\nok\n"), "test.gco", &job).with_window(2);
+        assert!(matches!(upload.run(), Err(UploadError::Sender(SenderError::Disconnected))));
+
+        // The upload starts over with the line number reset
+        let replies = format!("{}Begin file list\nTEST.GCO 18\nEnd file list\nok\n", WRITTEN);
+        upload.restart(Transport::new(&replies));
+        upload.run().unwrap();
+        assert!(upload.transport().sent().starts_with(&[framed(0, "M110 N0"), framed(1, "M28 test.gco")].concat()));
+    }
+}
@@ -0,0 +1,1533 @@
+//! Streaming programs to controllers.
+//!
+//! The sender writes the blocks of a program to a transport, usually a serial port, and reads the
+//! replies of the controller from it. Each line is answered with `ok` once the controller took it
+//! from its buffer, so the number of lines in flight is limited by counting these replies.
+//! Alternatively, the number of bytes in flight is limited to the size of the controller's receive
+//! buffer, as recommended for Grbl (see `FlowControl`).
+//!
+//! If the connection is lost, the job can be resumed on a new connection (see `Sender::reconnect`).
+//!
+//! The job can be paused, resumed and cancelled from other threads through a `JobHandle`.
+//!
+//! Real-time commands, like a feed hold or an emergency stop, bypass the stream (see `Realtime`).
+//!
+//! With checksums enabled, lines are numbered and checksummed as Marlin and RepRapFirmware
+//! expect, and lines the controller asks for with `Resend: N` are sent again.
+//!
+//! Lines are classified by how long the controller may take to answer them (see `TimeoutClass`),
+//! so homing or heating can be given more time than a line answered right away.
+//!
+//! Programs can be simulated before streaming them, so they are only sent to the controller if
+//! they run and end as expected (see `Sender::verify_then_run`).
+//!
+//! After an alarm of Grbl, the job can be resumed once the machine is homed and the state of the
+//! program is restored (see `Sender::recover`).
+
+use std::collections::{HashMap, VecDeque};
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Real;
+use crate::analysis::Estimate;
+use crate::emit::Options;
+use crate::extract::{self, Region};
+use crate::geometry::Point3;
+use crate::interp::{InterpError, Machine, State, StateChange};
+use crate::metadata;
+use crate::parser::Block;
+use crate::program::Program;
+use crate::response::Response;
+
+#[derive(Debug)]
+pub enum SenderError {
+    Io(io::Error),
+
+    Disconnected,
+
+    Timeout(Duration),
+
+    Rejected {
+        code: u32,
+        line: String,
+    },
+
+    Alarm(u32),
+
+    /// The simulation of the program failed at the block with the given index.
+    Invalid {
+        block: usize,
+        error: InterpError,
+    },
+
+    /// The simulated program ended in a state differing from the expected one, in the given values
+    /// of the expected state.
+    Unexpected(Vec<StateChange>),
+
+    /// The controller reported a position differing from the expected one after recovering, in
+    /// machine coordinates.
+    Misplaced {
+        expected: Point3,
+        actual: Point3,
+    },
+}
+
+impl fmt::Display for SenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SenderError::Io(err) => write!(f, "{}", err),
+            SenderError::Disconnected => f.write_str("transport closed"),
+            SenderError::Timeout(timeout) => write!(f, "no reply within {:?}", timeout),
+            SenderError::Rejected { code, line } => write!(f, "line rejected with error {}: {}", code, line),
+            SenderError::Alarm(code) => write!(f, "alarm {}", code),
+            SenderError::Invalid { block, error } => write!(f, "simulation failed at block {}: {}", block, error),
+            SenderError::Unexpected(changes) => write!(f, "simulation ended with {} unexpected values", changes.len()),
+            SenderError::Misplaced { expected, actual } => write!(f, "machine at {:?} instead of {:?}", actual, expected),
+        }
+    }
+}
+
+impl error::Error for SenderError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SenderError::Io(err) => Some(err),
+            SenderError::Invalid { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SenderError {
+    fn from(err: io::Error) -> Self {
+        SenderError::Io(err)
+    }
+}
+
+/// The XOR of all bytes of the line, as appended after `*` by hosts of Marlin.
+pub fn checksum(line: &str) -> u8 {
+    line.bytes().fold(0, |checksum, b| checksum ^ b)
+}
+
+/// Simulates the program on the machine, returning its state at the end.
+///
+/// Fails with `SenderError::Invalid` if a block can not be executed, and with
+/// `SenderError::Unexpected` if the state at the end differs from the expected one, if given.
+pub fn verify(program: &Program, mut machine: Machine, expected: Option<&State>) -> Result<State, SenderError> {
+    for (index, block) in program.iter().enumerate() {
+        machine.execute(block).map_err(|error| SenderError::Invalid { block: index, error })?;
+    }
+
+    let state = machine.into_state();
+    if let Some(expected) = expected {
+        let changes = state.diff(expected);
+        if !changes.is_empty() {
+            return Err(SenderError::Unexpected(changes));
+        }
+    }
+
+    return Ok(state);
+}
+
+/// The size of the receive buffer of Grbl, in bytes.
+pub const GRBL_RX_BUFFER: usize = 128;
+
+/// The distance the position reported after recovering may differ from the expected one, in
+/// millimeters, as Grbl reports three decimals.
+pub const POSITION_TOLERANCE: Real = 0.01;
+
+/// The number of status reports requested after recovering, until one reports the position.
+/// Grbl reports the offset needed to derive it from the position in work coordinates at least
+/// every 30 reports.
+const STATUS_REPORTS: usize = 30;
+
+/// How the sender limits the lines in flight.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FlowControl {
+    /// At most the given number of lines are sent before the first of them is acknowledged.
+    Lines(usize),
+
+    /// Lines are sent as long as all unacknowledged lines, including their line breaks, fit into a
+    /// receive buffer of the given size in bytes (see `GRBL_RX_BUFFER`).
+    Characters(usize),
+}
+
+impl Default for FlowControl {
+    fn default() -> Self {
+        FlowControl::Lines(1)
+    }
+}
+
+/// The steps of Grbl's feed and spindle speed overrides.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Override {
+    /// Back to 100%.
+    Reset,
+
+    /// Plus 10%.
+    CoarseIncrease,
+
+    /// Minus 10%.
+    CoarseDecrease,
+
+    /// Plus 1%.
+    FineIncrease,
+
+    /// Minus 1%.
+    FineDecrease,
+}
+
+impl Override {
+    fn offset(&self) -> u8 {
+        match *self {
+            Override::Reset => 0,
+            Override::CoarseIncrease => 1,
+            Override::CoarseDecrease => 2,
+            Override::FineIncrease => 3,
+            Override::FineDecrease => 4,
+        }
+    }
+}
+
+/// Commands acted upon by the controller as soon as they are received, regardless of the lines
+/// waiting in its buffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Realtime {
+    /// Grbl's `?`, answered by a status report.
+    StatusReport,
+
+    /// Grbl's `!`, decelerating to a stop.
+    FeedHold,
+
+    /// Grbl's `~`, resuming after a feed hold.
+    CycleStart,
+
+    /// Grbl's Ctrl-X, aborting all motion and discarding all buffered lines.
+    SoftReset,
+
+    /// Grbl's jog cancel, stopping a `$J=` jog.
+    JogCancel,
+
+    FeedOverride(Override),
+
+    /// Grbl's rapid override to the given percentage, either 100, 50 or 25.
+    RapidOverride(u8),
+
+    SpindleOverride(Override),
+
+    /// Marlin's `M112`, killing the printer.
+    EmergencyStop,
+
+    /// Marlin's `M108`, cancelling the wait for heaters.
+    CancelHeating,
+
+    /// Marlin's `M410`, stopping all steppers at once.
+    QuickStop,
+}
+
+impl Realtime {
+    /// The bytes sent to the controller.
+    pub fn bytes(&self) -> Vec<u8> {
+        match *self {
+            Realtime::StatusReport => vec![b'?'],
+            Realtime::FeedHold => vec![b'!'],
+            Realtime::CycleStart => vec![b'~'],
+            Realtime::SoftReset => vec![0x18],
+            Realtime::JogCancel => vec![0x85],
+            Realtime::FeedOverride(step) => vec![0x90 + step.offset()],
+            Realtime::RapidOverride(percent) if percent <= 25 => vec![0x97],
+            Realtime::RapidOverride(percent) if percent <= 50 => vec![0x96],
+            Realtime::RapidOverride(_) => vec![0x95],
+            Realtime::SpindleOverride(step) => vec![0x99 + step.offset()],
+
+            // Marlin's emergency parser picks these from the input without line number
+            Realtime::EmergencyStop => b"M112\n".to_vec(),
+            Realtime::CancelHeating => b"M108\n".to_vec(),
+            Realtime::QuickStop => b"M410\n".to_vec(),
+        }
+    }
+
+    /// Whether the controller discards all lines buffered so far.
+    pub fn aborts(&self) -> bool {
+        matches!(self, Realtime::SoftReset | Realtime::EmergencyStop)
+    }
+
+    /// Writes the command to the writer, e.g. a clone of the transport used by a sender waiting
+    /// for a reply in another thread.
+    pub fn write_to<W>(&self, mut writer: W) -> io::Result<()>
+        where W: Write {
+        writer.write_all(&self.bytes())?;
+        return writer.flush();
+    }
+}
+
+/// How long the controller may take to answer a line, as it answers some commands only once
+/// they are executed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TimeoutClass {
+    /// Answered right away, like changing modes or settings.
+    Instant,
+
+    /// Moves and dwells, answered once there is room in the planner.
+    Motion,
+
+    /// Homing with `G28`, `G30` or Grbl's `$H`, answered once all axes are homed.
+    Homing,
+
+    /// Waiting for temperatures with `M109`, `M190`, `M191` or `M116`, answered once they are
+    /// reached.
+    Heating,
+
+    /// Probing with `G38.x` or bed levelling with `G29`, answered once all points are probed.
+    Probing,
+}
+
+impl TimeoutClass {
+    /// Classifies the block by the command taking the longest.
+    pub fn of(block: &Block) -> Self {
+        // Codes are compared in tenths, so G28.1 is not taken for G28
+        let has = |mnemonic: char, codes: &[u32]| block.words().iter()
+                .any(|word| word.mnemonic() == mnemonic && codes.contains(&((word.value() * 10.0).round() as u32)));
+
+        if has('G', &[382, 383, 384, 385, 290]) {
+            return TimeoutClass::Probing;
+        }
+
+        if has('G', &[280, 300]) || block.system_command().is_some_and(|command| command.starts_with('H')) {
+            return TimeoutClass::Homing;
+        }
+
+        if has('M', &[1090, 1160, 1900, 1910]) {
+            return TimeoutClass::Heating;
+        }
+
+        // Axis words without any G or M code continue the current motion mode
+        let modal = block.words().iter().all(|word| word.mnemonic() != 'G' && word.mnemonic() != 'M')
+                && block.words().iter().any(|word| "XYZABCUVWE".contains(word.mnemonic()));
+        if modal
+                || has('G', &[0, 10, 20, 30, 40, 50, 51, 52, 330, 331, 730, 760, 810, 820, 830, 840, 850, 860, 870, 880, 890])
+                || block.system_command().is_some_and(|command| command.starts_with("J=")) {
+            return TimeoutClass::Motion;
+        }
+
+        return TimeoutClass::Instant;
+    }
+}
+
+/// The state of a job streamed by a sender.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum JobState {
+    Running,
+
+    /// Pausing was requested, but lines or the park macro are still in flight.
+    Pausing,
+
+    /// All lines sent so far are acknowledged and no more lines are sent until resumed.
+    Paused,
+
+    /// Cancelling was requested, but lines are still in flight.
+    Cancelling,
+
+    Cancelled,
+
+    /// All lines are sent and acknowledged.
+    Finished,
+
+    /// The stream was aborted by an error.
+    Failed,
+}
+
+impl JobState {
+    /// Whether the job is over.
+    pub fn is_final(&self) -> bool {
+        matches!(self, JobState::Cancelled | JobState::Finished | JobState::Failed)
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    state: JobState,
+    listeners: Vec<mpsc::Sender<JobState>>,
+}
+
+/// Controls the job of a sender, possibly from another thread.
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    job: Arc<(Mutex<Job>, Condvar)>,
+}
+
+impl JobHandle {
+    fn new() -> Self {
+        let job = Job {
+            state: JobState::Running,
+            listeners: Vec::new(),
+        };
+
+        Self {
+            job: Arc::new((Mutex::new(job), Condvar::new())),
+        }
+    }
+
+    pub fn state(&self) -> JobState {
+        self.job.0.lock().expect("Job poisoned").state
+    }
+
+    /// Returns a receiver for all changes of the state from now on.
+    pub fn subscribe(&self) -> mpsc::Receiver<JobState> {
+        let (sender, receiver) = mpsc::channel();
+        self.job.0.lock().expect("Job poisoned").listeners.push(sender);
+        return receiver;
+    }
+
+    /// Stops sending lines once the block being sent is complete. The job is paused as soon as
+    /// all lines sent so far, and the park macro if any, are acknowledged.
+    pub fn pause(&self) {
+        self.transition(|state| state == JobState::Running, JobState::Pausing);
+    }
+
+    /// Continues sending lines after pausing.
+    pub fn resume(&self) {
+        self.transition(|state| state == JobState::Pausing || state == JobState::Paused, JobState::Running);
+    }
+
+    /// Stops sending lines for good. The job is cancelled as soon as all lines sent so far are
+    /// acknowledged.
+    pub fn cancel(&self) {
+        self.transition(|state| !state.is_final(), JobState::Cancelling);
+    }
+
+    /// Changes the state if the current one matches, notifying all listeners.
+    fn transition<P>(&self, from: P, to: JobState)
+        where P: Fn(JobState) -> bool {
+        let (ref job, ref changed) = *self.job;
+        let mut job = job.lock().expect("Job poisoned");
+        if !from(job.state) || job.state == to {
+            return;
+        }
+
+        event!(debug, from = ?job.state, to = ?to, "job state changed");
+        job.state = to;
+        job.listeners.retain(|listener| listener.send(to).is_ok());
+        changed.notify_all();
+    }
+
+    /// Blocks until the job is no longer paused.
+    fn wait_while_paused(&self) {
+        let (ref job, ref changed) = *self.job;
+        let mut job = job.lock().expect("Job poisoned");
+        while job.state == JobState::Paused {
+            job = changed.wait(job).expect("Job poisoned");
+        }
+    }
+}
+
+/// The progress of a job, as reported after each acknowledged line.
+///
+/// Only lines of the program are counted, not the reset of line numbers, park macros and resent
+/// lines.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Progress {
+    pub lines_sent: usize,
+    pub lines_acknowledged: usize,
+    pub lines_total: usize,
+
+    /// The number of bytes written to the transport, including everything not counted as line,
+    /// but not the lines the controller discarded and requested again.
+    pub bytes_sent: usize,
+
+    /// The estimated duration of the lines not yet acknowledged, in seconds, if an estimate was
+    /// given (see `Sender::with_estimate`).
+    pub remaining_time: Option<Real>,
+
+    /// The number of layers started by the lines acknowledged so far, if the program has layer
+    /// comments (see `metadata::is_layer_start`).
+    pub layer: Option<usize>,
+}
+
+/// A line to send.
+#[derive(Debug, Clone)]
+struct Line {
+    text: String,
+
+    /// The index of the block in the program, if the line is part of it
+    block: Option<usize>,
+
+    /// The number of layers started up to and including the block
+    layer: Option<usize>,
+
+    /// The estimated duration of the block
+    time: Real,
+
+    class: TimeoutClass,
+}
+
+impl Line {
+    /// The line of the block, without line number and comments.
+    fn of(block: &Block) -> Self {
+        let options = Options {
+            line_numbers: false,
+            comments: false,
+            ..Options::default()
+        };
+
+        Self {
+            class: TimeoutClass::of(block),
+            ..Line::extra(options.display(block).to_string())
+        }
+    }
+
+    fn extra<S>(text: S) -> Self
+        where S: Into<String> {
+        Self {
+            text: text.into(),
+            block: None,
+            layer: None,
+            time: 0.0,
+            class: TimeoutClass::Instant,
+        }
+    }
+}
+
+/// A line sent but not yet acknowledged.
+#[derive(Debug, Copy, Clone)]
+struct Pending {
+    index: usize,
+
+    /// The number of bytes sent, including the line break
+    length: usize,
+
+    /// Whether the controller discards the line, as it requested an earlier one again. It still
+    /// answers the line, so it takes up room in the window until then.
+    discarded: bool,
+}
+
+/// Streams the lines of a program to a controller.
+pub struct Sender<T> {
+    transport: T,
+
+    /// The lines to send, without line numbers and comments
+    lines: Vec<Line>,
+
+    /// Index of the line to send next
+    next: usize,
+
+    /// The lines sent but not yet acknowledged, oldest first
+    in_flight: VecDeque<Pending>,
+
+    flow_control: FlowControl,
+
+    /// Whether lines are sent numbered and checksummed
+    checksums: bool,
+
+    /// Bytes received but not yet forming a complete line
+    buffer: Vec<u8>,
+
+    /// The time to wait for a reply before giving up, if limited
+    timeout: Option<Duration>,
+
+    /// The times to wait for replies to lines of the given classes, overriding `timeout`
+    timeouts: HashMap<TimeoutClass, Duration>,
+
+    handle: JobHandle,
+
+    /// The lines sent before pausing, like retracting and parking the tool
+    park: Vec<Line>,
+
+    /// The index after the park macro inserted for the current pause, if any
+    parked: Option<usize>,
+
+    /// The index after the last line sent and the last line acknowledged
+    sent: usize,
+    acknowledged: usize,
+
+    progress: Progress,
+
+    listeners: Vec<mpsc::Sender<Progress>>,
+}
+
+impl<T> Sender<T>
+    where T: Read + Write {
+    /// Creates a sender for the non-empty blocks of the program, sending one line at a time.
+    pub fn new(transport: T, program: &Program) -> Self {
+        let mut layers = 0;
+        let mut lines = Vec::new();
+        for (index, block) in program.iter().enumerate() {
+            layers += block.comments().iter().filter(|comment| metadata::is_layer_start(comment)).count();
+            if block.is_empty() {
+                continue;
+            }
+
+            lines.push(Line {
+                block: Some(index),
+                layer: Some(layers).filter(|&layers| layers > 0),
+                ..Line::of(block)
+            });
+        }
+
+        let progress = Progress {
+            lines_total: lines.len(),
+            ..Progress::default()
+        };
+
+        Self {
+            transport,
+            lines,
+            next: 0,
+            in_flight: VecDeque::new(),
+            flow_control: FlowControl::default(),
+            checksums: false,
+            buffer: Vec::new(),
+            timeout: None,
+            timeouts: HashMap::new(),
+            handle: JobHandle::new(),
+            park: Vec::new(),
+            parked: None,
+            sent: 0,
+            acknowledged: 0,
+            progress,
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Allows up to `window` lines to be sent before the first of them is acknowledged.
+    ///
+    /// The controller must be able to buffer that many lines, otherwise its input overflows.
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.flow_control = FlowControl::Lines(window.max(1));
+        self
+    }
+
+    pub fn with_flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.flow_control = flow_control;
+        self
+    }
+
+    /// Numbers and checksums all lines, starting with a reset of the line number (`M110 N0`).
+    ///
+    /// Must be enabled before the first line is sent.
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        if checksums && !self.checksums {
+            self.lines.insert(0, Line::extra("M110 N0"));
+        } else if !checksums && self.checksums {
+            self.lines.remove(0);
+        }
+
+        self.checksums = checksums;
+        self
+    }
+
+    /// Sends the non-empty blocks of the macro each time the job is paused, once all lines before
+    /// are sent.
+    pub fn with_park_macro(mut self, park: &Program) -> Self {
+        self.park = park.iter()
+                .filter(|block| !block.is_empty())
+                .map(|block| Line {
+                    class: TimeoutClass::of(block),
+                    ..Line::extra(block.to_string())
+                })
+                .collect();
+        self
+    }
+
+    /// Reports the remaining time of the job using the estimate of the program (see
+    /// `analysis::estimate`).
+    pub fn with_estimate(mut self, estimate: &Estimate) -> Self {
+        for line in &mut self.lines {
+            line.time = line.block.and_then(|block| estimate.blocks.get(block)).cloned().unwrap_or(0.0);
+        }
+
+        self.progress.remaining_time = Some(self.lines[self.acknowledged..].iter().map(|line| line.time).sum());
+        self
+    }
+
+    /// The progress of the job so far.
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+
+    /// Returns a receiver for the progress after each acknowledged line from now on.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<Progress> {
+        let (sender, receiver) = mpsc::channel();
+        self.listeners.push(sender);
+        return receiver;
+    }
+
+    /// A handle for pausing, resuming and cancelling the job.
+    pub fn handle(&self) -> JobHandle {
+        self.handle.clone()
+    }
+
+    pub fn state(&self) -> JobState {
+        self.handle.state()
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Fails with `SenderError::Timeout` if the controller does not reply in time.
+    ///
+    /// Reads failing with `TimedOut` or `WouldBlock`, as reported by transports with read timeouts,
+    /// are retried until then. Without a timeout, they are retried forever. Each reply restarts
+    /// the wait, so controllers reporting progress, like Marlin's `busy` while homing or its
+    /// temperatures while heating, are waited for as long as they keep reporting.
+    ///
+    /// Applies to all lines whose class has no timeout of its own (see `with_class_timeout`).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails with `SenderError::Timeout` if the controller does not reply in time while lines of
+    /// the given class are in flight.
+    ///
+    /// With several lines in flight, the longest of their timeouts applies, as the controller may
+    /// be executing any of them.
+    pub fn with_class_timeout(mut self, class: TimeoutClass, timeout: Duration) -> Self {
+        self.timeouts.insert(class, timeout);
+        self
+    }
+
+    /// Continues the job on a new connection, e.g. after the transport failed or timed out.
+    ///
+    /// All lines not yet acknowledged are sent again, as it is unknown whether the controller
+    /// received them. With checksums, the line number is reset first. Controllers resetting on
+    /// connect, like most boards running Marlin, lose their state and must be ready to receive
+    /// before continuing.
+    pub fn reconnect(&mut self, transport: T) {
+        let first = self.in_flight.iter().find(|pending| !pending.discarded).map_or(self.next, |pending| pending.index);
+        event!(info, line = first, "reconnecting");
+
+        self.transport = transport;
+        self.buffer.clear();
+        self.in_flight.clear();
+        self.next = first;
+
+        if self.checksums {
+            self.lines.insert(first, Line::extra(format!("M110 N{}", first)));
+
+            // Lines following the reset move one line later, so they are not counted twice
+            let shift = |index: &mut usize| if *index > first {
+                *index += 1;
+            };
+            shift(&mut self.sent);
+            shift(&mut self.acknowledged);
+            if let Some(ref mut parked) = self.parked {
+                shift(parked);
+            }
+        }
+
+        self.handle.transition(|state| state == JobState::Failed, JobState::Running);
+    }
+
+    /// The number of lines to send, including the reset of the line number.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Whether all lines are sent and acknowledged.
+    pub fn is_done(&self) -> bool {
+        self.next >= self.lines.len() && self.in_flight.is_empty()
+    }
+
+    /// Sends lines until the window is full, then waits for and handles the next reply.
+    ///
+    /// Returns `None` once all lines are acknowledged or the job is cancelled. While the job is
+    /// paused, blocks until it is resumed or cancelled. Errors reported by Grbl and alarms abort
+    /// the stream.
+    pub fn step(&mut self) -> Result<Option<Response>, SenderError> {
+        let result = self.advance();
+        if result.is_err() {
+            self.handle.transition(|_| true, JobState::Failed);
+        }
+
+        return result;
+    }
+
+    fn advance(&mut self) -> Result<Option<Response>, SenderError> {
+        loop {
+            // The lines to send before waiting for the next reply
+            let end = match self.handle.state() {
+                JobState::Running => {
+                    self.parked = None;
+                    self.lines.len()
+                }
+
+                JobState::Pausing => {
+                    let next = self.next;
+                    let park = &self.park;
+                    let lines = &mut self.lines;
+                    *self.parked.get_or_insert_with(|| {
+                        lines.splice(next..next, park.iter().cloned());
+                        next + park.len()
+                    })
+                }
+
+                JobState::Paused => {
+                    self.handle.wait_while_paused();
+                    continue;
+                }
+
+                JobState::Cancelling => {
+                    self.next = self.lines.len();
+                    self.lines.len()
+                }
+
+                JobState::Cancelled | JobState::Finished | JobState::Failed => return Ok(None),
+            };
+
+            while self.next < end && self.fits(self.next) {
+                self.send(self.next)?;
+                self.next += 1;
+            }
+
+            if self.in_flight.is_empty() && self.next >= end {
+                let state = match self.handle.state() {
+                    JobState::Pausing => JobState::Paused,
+                    JobState::Cancelling => JobState::Cancelled,
+                    _ => JobState::Finished,
+                };
+                self.handle.transition(|state| !state.is_final(), state);
+                continue;
+            }
+
+            break;
+        }
+
+        let response = Response::parse(&self.receive()?);
+        self.reply(&response)?;
+
+        return Ok(Some(response));
+    }
+
+    /// Acknowledges, rejects or resends lines as the reply demands.
+    fn reply(&mut self, response: &Response) -> Result<(), SenderError> {
+        match *response {
+            Response::Ok { .. } => {
+                if let Some(pending) = self.in_flight.pop_front().filter(|pending| !pending.discarded) {
+                    self.acknowledge(pending.index);
+                }
+            }
+
+            Response::Error { code: Some(code), .. } => {
+                let line = self.in_flight.pop_front().map(|pending| self.lines[pending.index].text.clone()).unwrap_or_default();
+                return Err(SenderError::Rejected { code, line });
+            }
+
+            Response::Alarm(code) => {
+                return Err(SenderError::Alarm(code));
+            }
+
+            // Lines are numbered by their index, with the reset being line zero. The controller
+            // requests the line again for each discarded line it answers, which is ignored.
+            Response::Resend(number) if self.checksums && (number as usize) < self.lines.len() => {
+                if self.in_flight.iter().any(|pending| pending.discarded) {
+                    event!(debug, line = number, "ignoring repeated resend request");
+                } else {
+                    event!(debug, line = number, "resending");
+                    self.resend(number as usize);
+                }
+            }
+
+            _ => {}
+        }
+
+        return Ok(());
+    }
+
+    /// Streams all remaining lines, returning once all of them are acknowledged.
+    pub fn run(&mut self) -> Result<(), SenderError> {
+        while self.step()?.is_some() {}
+
+        return Ok(());
+    }
+
+    /// Simulates the program (see `verify`) and streams it only if the simulation succeeds,
+    /// returning the state the program is expected to leave the controller in.
+    ///
+    /// The program must be the one the sender was created for. If the simulation fails, nothing
+    /// is sent and the job fails.
+    pub fn verify_then_run(&mut self, program: &Program, machine: Machine, expected: Option<&State>) -> Result<State, SenderError> {
+        let state = verify(program, machine, expected).inspect_err(|_| {
+            self.handle.transition(|_| true, JobState::Failed);
+        })?;
+
+        self.run()?;
+        return Ok(state);
+    }
+
+    /// Prepares the job to continue after an alarm of Grbl, which loses the lines in flight, the
+    /// modes and, if it stopped abruptly, the position.
+    ///
+    /// The controller is reset and homed (`$H`). The work offsets and modes in effect at the first
+    /// line not acknowledged are restored from a simulation of the program up to there, moving
+    /// back to the position the line starts at above the highest point reached so far and feeding
+    /// down to it (see `extract::extract_with`). The position
+    /// reported by the controller, if any, is then compared with the simulated one, failing with
+    /// `SenderError::Misplaced` if they differ by more than `POSITION_TOLERANCE`. Afterwards, the
+    /// job continues with `run`.
+    ///
+    /// The program must be the one the sender was created for, and the machine must have the
+    /// work offsets of the controller. Positions are compared in millimeters, as Grbl reports
+    /// them by default.
+    pub fn recover(&mut self, program: &Program, machine: Machine) -> Result<(), SenderError> {
+        let result = self.restore(program, machine);
+        match result {
+            Ok(()) => self.handle.transition(|state| state == JobState::Failed, JobState::Running),
+            Err(_) => self.handle.transition(|_| true, JobState::Failed),
+        }
+
+        return result;
+    }
+
+    fn restore(&mut self, program: &Program, machine: Machine) -> Result<(), SenderError> {
+        // The first line not acknowledged, skipping lines besides the program
+        let at = self.acknowledged;
+        let start = self.lines[at..].iter().position(|line| line.block.is_some()).map_or(self.lines.len(), |i| at + i);
+        let first = self.lines.get(start).and_then(|line| line.block).unwrap_or(program.len());
+        event!(info, block = first, "recovering");
+
+        let invalid = |error| SenderError::Invalid { block: first, error };
+        let resumed = extract::extract_with(machine.clone(), program, &Region::Blocks(first..=usize::MAX)).map_err(invalid)?;
+        let mut expected = machine;
+        for block in &program.blocks()[..first] {
+            expected.execute(block).map_err(invalid)?;
+        }
+
+        Realtime::SoftReset.write_to(&mut self.transport)?;
+        self.buffer.clear();
+        self.in_flight.clear();
+        self.parked = None;
+
+        // Grbl greets once it is reset
+        while !self.receive()?.starts_with("Grbl ") {}
+
+        let preamble = &resumed.blocks()[..resumed.len() - (program.len() - first)];
+        let recovery = Some(Line { class: TimeoutClass::Homing, ..Line::extra("$H") }).into_iter()
+                .chain(preamble.iter().map(Line::of))
+                .collect::<Vec<_>>();
+        let end = at + recovery.len();
+        self.lines.splice(at..start, recovery);
+
+        // All lines not acknowledged are sent again
+        self.next = at;
+        self.sent = at;
+        self.progress.lines_sent = self.progress.lines_acknowledged;
+
+        self.stream(end)?;
+
+        let expected = expected.state().position;
+        for _ in 0..STATUS_REPORTS {
+            Realtime::StatusReport.write_to(&mut self.transport)?;
+            let actual = loop {
+                if let Response::Status(status) = Response::parse(&self.receive()?) {
+                    break status.position();
+                }
+            };
+
+            match actual {
+                Some(actual) if actual.distance(expected) > POSITION_TOLERANCE => {
+                    return Err(SenderError::Misplaced { expected, actual });
+                }
+                Some(_) => return Ok(()),
+                None => continue,
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// Sends the line after all lines of the job, returning the replies up to and including its
+    /// acknowledgement, like the file list of `M20`.
+    ///
+    /// The line is sent regardless of the state of the job, which must be over.
+    pub fn execute<S>(&mut self, line: S) -> Result<Vec<Response>, SenderError>
+        where S: Into<String> {
+        self.lines.push(Line::extra(line));
+        return self.stream(self.lines.len());
+    }
+
+    /// Sends all lines up to the given index, returning the replies until all of them are
+    /// acknowledged.
+    fn stream(&mut self, end: usize) -> Result<Vec<Response>, SenderError> {
+        let mut responses = Vec::new();
+        while self.acknowledged < end {
+            while self.next < end && self.fits(self.next) {
+                self.send(self.next)?;
+                self.next += 1;
+            }
+
+            let response = Response::parse(&self.receive()?);
+            self.reply(&response)?;
+            responses.push(response);
+        }
+
+        return Ok(responses);
+    }
+
+    /// Sends a real-time command right away, bypassing the lines waiting to be sent.
+    ///
+    /// Commands aborting the program end the stream, as the controller discards all lines sent so
+    /// far and will not acknowledge them.
+    pub fn inject(&mut self, command: Realtime) -> Result<(), SenderError> {
+        event!(debug, command = ?command, "injecting");
+        command.write_to(&mut self.transport)?;
+
+        if command.aborts() {
+            self.in_flight.clear();
+            self.next = self.lines.len();
+            self.handle.transition(|state| !state.is_final(), JobState::Cancelled);
+        }
+
+        return Ok(());
+    }
+
+    /// Whether the line can be sent without exceeding the limits of the flow control.
+    fn fits(&self, index: usize) -> bool {
+        match self.flow_control {
+            FlowControl::Lines(window) => self.in_flight.len() < window,
+
+            // A line longer than the buffer is sent once the buffer is empty
+            FlowControl::Characters(size) => {
+                let used: usize = self.in_flight.iter().map(|pending| pending.length).sum();
+                self.in_flight.is_empty() || used + self.line(index).len() < size
+            }
+        }
+    }
+
+    /// The line as sent, with line number and checksum if enabled.
+    fn line(&self, index: usize) -> String {
+        if !self.checksums {
+            return self.lines[index].text.clone();
+        }
+
+        let line = format!("N{} {}", index, self.lines[index].text);
+        return format!("{}*{}", line, checksum(&line));
+    }
+
+    fn send(&mut self, index: usize) -> Result<(), SenderError> {
+        let line = self.line(index);
+
+        event!(trace, line = %line, "sending");
+        self.transport.write_all(line.as_bytes())?;
+        self.transport.write_all(b"\n")?;
+        self.transport.flush()?;
+
+        self.in_flight.push_back(Pending {
+            index,
+            length: line.len() + 1,
+            discarded: false,
+        });
+
+        self.progress.bytes_sent += line.len() + 1;
+        if index >= self.sent {
+            self.progress.lines_sent += self.lines[self.sent..=index].iter().filter(|line| line.block.is_some()).count();
+            self.sent = index + 1;
+        }
+
+        return Ok(());
+    }
+
+    /// Continues sending at the given line, as the controller discards all lines from there on.
+    fn resend(&mut self, index: usize) {
+        for pending in self.in_flight.iter_mut().filter(|pending| pending.index >= index) {
+            pending.discarded = true;
+        }
+
+        // The discarded lines are counted again once resent
+        if index < self.sent {
+            self.progress.lines_sent -= self.lines[index..self.sent].iter().filter(|line| line.block.is_some()).count();
+            self.progress.bytes_sent -= self.in_flight.iter().filter(|pending| pending.discarded).map(|pending| pending.length).sum::<usize>();
+            self.sent = index;
+        }
+
+        self.next = index;
+    }
+
+    /// Counts all lines up to the given one as acknowledged and notifies the listeners.
+    fn acknowledge(&mut self, index: usize) {
+        if index >= self.acknowledged {
+            let lines = &self.lines[self.acknowledged..=index];
+            self.progress.lines_acknowledged += lines.iter().filter(|line| line.block.is_some()).count();
+            if let Some(ref mut remaining) = self.progress.remaining_time {
+                *remaining = (*remaining - lines.iter().map(|line| line.time).sum::<Real>()).max(0.0);
+            }
+            self.progress.layer = self.lines[..=index].iter().rev().find(|line| line.block.is_some()).and_then(|line| line.layer);
+            self.acknowledged = index + 1;
+        }
+
+        let progress = self.progress;
+        self.listeners.retain(|listener| listener.send(progress).is_ok());
+    }
+
+    /// The time to wait for the next reply, if limited.
+    fn timeout(&self) -> Option<Duration> {
+        let mut timeout = None;
+        for pending in self.in_flight.iter().filter(|pending| !pending.discarded) {
+            let class = self.timeouts.get(&self.lines[pending.index].class).copied().or(self.timeout)?;
+            timeout = Some(timeout.map_or(class, |timeout: Duration| timeout.max(class)));
+        }
+
+        return timeout.or(self.timeout);
+    }
+
+    /// Reads the next non-empty line from the transport.
+    fn receive(&mut self) -> Result<String, SenderError> {
+        let mut chunk = [0u8; 256];
+        let start = Instant::now();
+        let timeout = self.timeout();
+
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_owned();
+                if line.is_empty() {
+                    continue;
+                }
+
+                return Ok(line);
+            }
+
+            match self.transport.read(&mut chunk) {
+                Ok(0) => return Err(SenderError::Disconnected),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref err) if err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock => {
+                    match timeout {
+                        Some(timeout) if start.elapsed() >= timeout => return Err(SenderError::Timeout(timeout)),
+                        _ => thread::sleep(Duration::from_millis(1)),
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::geometry::Point3;
+    use crate::parser::Parser;
+
+    /// A controller replying with a script, recording everything sent to it.
+    pub struct Transport {
+        pub replies: Cursor<Vec<u8>>,
+        pub sent: Vec<u8>,
+    }
+
+    impl Transport {
+        pub fn new(replies: &str) -> Self {
+            Self {
+                replies: Cursor::new(replies.as_bytes().to_vec()),
+                sent: Vec::new(),
+            }
+        }
+
+        pub fn sent(&self) -> &str {
+            std::str::from_utf8(&self.sent).unwrap()
+        }
+    }
+
+    impl Read for Transport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            // Reply slowly, like a serial line
+            let n = buf.len().min(3);
+            self.replies.read(&mut buf[..n])
+        }
+    }
+
+    /// A controller never replying, like a serial port with read timeout.
+    pub struct Silent;
+
+    impl Read for Silent {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    impl Write for Silent {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for Transport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub fn program(source: &str) -> Program {
+        Parser::new().parse_all(source.lines()).unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum("N0 M110 N0"), 125);
+    }
+
+    #[test]
+    fn test_run() {
+        let program = program("G21 (metric)\n\nG1 X10 F600\n");
+        let mut sender = Sender::new(Transport::new("ok\r\necho:busy: processing\nok\n"), &program);
+        assert_eq!(sender.len(), 2);
+
+        sender.run().unwrap();
+        assert!(sender.is_done());
+        assert_eq!(sender.transport().sent(), "G21\nG1 X10 F600\n");
+    }
+
+    #[test]
+    fn test_window() {
+        let program = program("G0 X1\nG0 X2\nG0 X3\n");
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\n"), &program).with_window(2);
+
+        assert!(sender.step().unwrap().unwrap().is_ok());
+        assert_eq!(sender.transport().sent(), "G0 X1\nG0 X2\n");
+
+        sender.run().unwrap();
+        assert_eq!(sender.transport().sent(), "G0 X1\nG0 X2\nG0 X3\n");
+    }
+
+    #[test]
+    fn test_characters() {
+        let program = program("G1 X1.5 Y2 F600\nG1 X3 Y4\nG1 X5 Y6\nG1 X7\n");
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\nok\n"), &program)
+                .with_flow_control(FlowControl::Characters(32));
+
+        // 16 and 9 bytes fit, but not another 9
+        assert!(sender.step().unwrap().unwrap().is_ok());
+        assert_eq!(sender.transport().sent(), "G1 X1.5 Y2 F600\nG1 X3 Y4\n");
+
+        // With the first line acknowledged, both others fit
+        assert!(sender.step().unwrap().unwrap().is_ok());
+        assert_eq!(sender.transport().sent(), "G1 X1.5 Y2 F600\nG1 X3 Y4\nG1 X5 Y6\nG1 X7\n");
+
+        sender.run().unwrap();
+
+        // Lines longer than the buffer are sent one at a time
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\nok\n"), &program)
+                .with_flow_control(FlowControl::Characters(4));
+        sender.step().unwrap();
+        assert_eq!(sender.transport().sent(), "G1 X1.5 Y2 F600\n");
+        sender.run().unwrap();
+    }
+
+    #[test]
+    fn test_inject() {
+        let program = program("G0 X1\nG0 X2\nG0 X3\n");
+        let mut sender = Sender::new(Transport::new("ok\nok\n"), &program);
+
+        sender.step().unwrap();
+        sender.inject(Realtime::FeedHold).unwrap();
+        sender.inject(Realtime::FeedOverride(Override::CoarseDecrease)).unwrap();
+        sender.inject(Realtime::RapidOverride(50)).unwrap();
+        assert_eq!(sender.transport().sent, b"G0 X1\n!\x92\x96");
+
+        sender.inject(Realtime::SoftReset).unwrap();
+        assert!(sender.is_done());
+        assert_eq!(sender.step().unwrap(), None);
+
+        let mut sender = Sender::new(Transport::new(""), &program);
+        sender.inject(Realtime::EmergencyStop).unwrap();
+        assert_eq!(sender.transport().sent(), "M112\n");
+    }
+
+    #[test]
+    fn test_job_control() {
+        let job = program("G0 X1\nG0 X2\nG0 X3\n");
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\nok\nok\n"), &job)
+                .with_window(2)
+                .with_park_macro(&program("G91 G0 Z5\nG90\n"));
+        let handle = sender.handle();
+        let changes = handle.subscribe();
+
+        // Resume as soon as paused, while the sender waits
+        let paused = handle.subscribe();
+        let resumer = handle.clone();
+        let thread = std::thread::spawn(move || {
+            while paused.recv().unwrap() != JobState::Paused {}
+            resumer.resume();
+        });
+
+        sender.step().unwrap();
+        handle.pause();
+        assert_eq!(sender.state(), JobState::Pausing);
+
+        // The line in flight and the park macro complete the pause
+        sender.run().unwrap();
+        thread.join().unwrap();
+
+        assert_eq!(sender.transport().sent(), "G0 X1\nG0 X2\nG91 G0 Z5\nG90\nG0 X3\n");
+        assert_eq!(changes.try_iter().collect::<Vec<_>>(),
+                   vec![JobState::Pausing, JobState::Paused, JobState::Running, JobState::Finished]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let job = program("G0 X1\nG0 X2\nG0 X3\n");
+        let mut sender = Sender::new(Transport::new("ok\nok\n"), &job).with_window(2);
+
+        sender.step().unwrap();
+        sender.handle().cancel();
+        sender.run().unwrap();
+
+        assert_eq!(sender.state(), JobState::Cancelled);
+        assert_eq!(sender.transport().sent(), "G0 X1\nG0 X2\n");
+
+        let mut sender = Sender::new(Transport::new("ALARM:1\n"), &job);
+        assert!(sender.run().is_err());
+        assert_eq!(sender.state(), JobState::Failed);
+    }
+
+    #[test]
+    fn test_progress() {
+        let job = program(";LAYER:0\nG1 X1 F600\nG1 X2\n;LAYER:1\nG1 Z0.2\n");
+        let estimate = Estimate { total: 6.0, blocks: vec![0.0, 1.0, 2.0, 0.0, 3.0] };
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\nok\n"), &job)
+                .with_checksums(true)
+                .with_estimate(&estimate);
+        let progress = sender.subscribe();
+
+        assert_eq!(sender.progress().remaining_time, Some(6.0));
+        sender.run().unwrap();
+
+        let progress: Vec<Progress> = progress.try_iter().collect();
+        assert_eq!(progress.len(), 4);
+        assert_eq!(progress[0], Progress {
+            lines_sent: 0,
+            lines_acknowledged: 0,
+            lines_total: 3,
+            bytes_sent: 15,
+            remaining_time: Some(6.0),
+            layer: None,
+        });
+        assert_eq!((progress[1].lines_acknowledged, progress[1].remaining_time, progress[1].layer), (1, Some(5.0), Some(1)));
+        assert_eq!((progress[3].lines_sent, progress[3].remaining_time, progress[3].layer), (3, Some(0.0), Some(2)));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut sender = Sender::new(Silent, &program("G0 X1\n")).with_timeout(Duration::from_millis(5));
+        assert!(matches!(sender.run(), Err(SenderError::Timeout(_))));
+        assert_eq!(sender.state(), JobState::Failed);
+    }
+
+    #[test]
+    fn test_timeout_classes() {
+        let class = |line: &str| TimeoutClass::of(&Parser::new().with_dialect(Dialect::Grbl).parse(line).unwrap());
+        assert_eq!(class("G21 G90"), TimeoutClass::Instant);
+        assert_eq!(class("M104 S200"), TimeoutClass::Instant);
+        assert_eq!(class("G1 X10 F600"), TimeoutClass::Motion);
+        assert_eq!(class("X10"), TimeoutClass::Motion);
+        assert_eq!(class("G92 X0"), TimeoutClass::Instant);
+        assert_eq!(class("G28 X0 Y0"), TimeoutClass::Homing);
+        assert_eq!(class("G28.1"), TimeoutClass::Instant);
+        assert_eq!(class("G30.1"), TimeoutClass::Instant);
+        assert_eq!(class("$H"), TimeoutClass::Homing);
+        assert_eq!(class("M109 S200"), TimeoutClass::Heating);
+        assert_eq!(class("G38.2 Z-10 F100"), TimeoutClass::Probing);
+        assert_eq!(class("G5.1 X1 I1"), TimeoutClass::Motion);
+
+        // Homing gets its own timeout, everything else the default one
+        let mut sender = Sender::new(Silent, &program("G28\n"))
+                .with_timeout(Duration::from_millis(5))
+                .with_class_timeout(TimeoutClass::Homing, Duration::from_millis(20));
+        let start = Instant::now();
+        assert!(matches!(sender.run(), Err(SenderError::Timeout(timeout)) if timeout == Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        let mut sender = Sender::new(Silent, &program("G21\n"))
+                .with_timeout(Duration::from_millis(5))
+                .with_class_timeout(TimeoutClass::Homing, Duration::from_millis(20));
+        assert!(matches!(sender.run(), Err(SenderError::Timeout(timeout)) if timeout == Duration::from_millis(5)));
+
+        // Without a default, only lines of classes with a timeout of their own time out
+        let mut sender = Sender::new(Silent, &program("G38.2 Z-10\n"))
+                .with_class_timeout(TimeoutClass::Probing, Duration::from_millis(5));
+        assert!(matches!(sender.run(), Err(SenderError::Timeout(_))));
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let job = program("G0 X1\nG0 X2\nG0 X3\n");
+        let mut sender = Sender::new(Transport::new("ok\n"), &job).with_window(2).with_checksums(true);
+        assert!(matches!(sender.run(), Err(SenderError::Disconnected)));
+        assert_eq!(sender.transport().sent(), "N0 M110 N0*125\nN1 G0 X1*97\nN2 G0 X2*97\n");
+
+        // The lines in flight are sent again, numbered after the reset
+        sender.reconnect(Transport::new("ok\nok\nok\nok\n"));
+        assert_eq!(sender.state(), JobState::Running);
+        assert_eq!((sender.progress().lines_sent, sender.progress().lines_acknowledged), (2, 0));
+        sender.run().unwrap();
+        assert_eq!(sender.transport().sent(), format!("N1 M110 N1*{}\nN2 G0 X1*{}\nN3 G0 X2*{}\nN4 G0 X3*{}\n",
+                                                      checksum("N1 M110 N1"), checksum("N2 G0 X1"),
+                                                      checksum("N3 G0 X2"), checksum("N4 G0 X3")));
+
+        let progress = sender.progress();
+        assert_eq!((progress.lines_sent, progress.lines_acknowledged, progress.lines_total), (3, 3, 3));
+    }
+
+    #[test]
+    fn test_checksums_line_numbers() {
+        // Line numbers of the program are replaced by the consecutive ones checksummed
+        let job = program("N10 G0 X1\nN20 G0 X2\n");
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\n"), &job).with_checksums(true);
+        sender.run().unwrap();
+        assert_eq!(sender.transport().sent(), "N0 M110 N0*125\nN1 G0 X1*97\nN2 G0 X2*97\n");
+    }
+
+    #[test]
+    fn test_resend() {
+        let program = program("G0 X1\nG0 X2\n");
+        let replies = "ok\nok\nError:checksum mismatch, Last Line: 1\nResend: 2\nok\nok\n";
+        let mut sender = Sender::new(Transport::new(replies), &program).with_checksums(true);
+
+        sender.run().unwrap();
+        assert_eq!(sender.transport().sent(), "N0 M110 N0*125\nN1 G0 X1*97\nN2 G0 X2*97\nN2 G0 X2*97\n");
+    }
+
+    #[test]
+    fn test_resend_window() {
+        // The controller discards the lines following the rejected one, answering each of them
+        let program = program("G0 X1\nG0 X2\nG0 X3\nG0 X4\n");
+        let discarded = "Error:Line Number is not Last Line Number+1, Last Line: 1\nResend: 2\nok\n";
+        let replies = format!("ok\nok\nError:checksum mismatch, Last Line: 1\nResend: 2\nok\n{}{}ok\nok\nok\n", discarded, discarded);
+        let mut sender = Sender::new(Transport::new(&replies), &program).with_checksums(true).with_window(3);
+
+        sender.run().unwrap();
+        assert_eq!(sender.transport().sent(), concat!(
+            "N0 M110 N0*125\nN1 G0 X1*97\nN2 G0 X2*97\nN3 G0 X3*97\nN4 G0 X4*97\n",
+            "N2 G0 X2*97\nN3 G0 X3*97\nN4 G0 X4*97\n",
+        ));
+
+        let progress = sender.progress();
+        assert_eq!((progress.lines_sent, progress.lines_acknowledged), (4, 4));
+        assert_eq!(progress.bytes_sent, sender.transport().sent().len() - 3 * 12);
+    }
+
+    #[test]
+    fn test_verify_then_run() {
+        let job = program("G21 G90\nG0 X10 Y5\n");
+        let expected = verify(&program("G0 X10 Y5\n"), Machine::new(), None).unwrap();
+
+        let mut sender = Sender::new(Transport::new("ok\nok\n"), &job);
+        let state = sender.verify_then_run(&job, Machine::new(), Some(&expected)).unwrap();
+        assert_eq!(state.machine_position(), Point3::new(10.0, 5.0, 0.0));
+        assert_eq!(sender.state(), JobState::Finished);
+        assert_eq!(sender.transport().sent(), "G21 G90\nG0 X10 Y5\n");
+
+        // Nothing is sent if the program ends elsewhere
+        let expected = verify(&program("G0 X5\n"), Machine::new(), None).unwrap();
+        let mut sender = Sender::new(Transport::new("ok\nok\n"), &job);
+        match sender.verify_then_run(&job, Machine::new(), Some(&expected)) {
+            Err(SenderError::Unexpected(changes)) => assert_eq!(changes, vec![StateChange::Position(Point3::new(5.0, 0.0, 0.0))]),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(sender.state(), JobState::Failed);
+        assert_eq!(sender.transport().sent(), "");
+
+        // Nor if it does not run at all
+        let job = program("G0 X1\nG2 X10\n");
+        let mut sender = Sender::new(Transport::new("ok\nok\n"), &job);
+        assert!(matches!(sender.verify_then_run(&job, Machine::new(), None), Err(SenderError::Invalid { block: 1, .. })));
+        assert_eq!(sender.transport().sent(), "");
+    }
+
+    #[test]
+    fn test_recover() {
+        let job = program("G21 G90\nG10 L2 P1 X5\nG0 X10 Y5\nG1 Z-1 F100\nG1 X20\n");
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\nALARM:1\n"), &job);
+        assert!(matches!(sender.run(), Err(SenderError::Alarm(1))));
+
+        let replies = concat!(
+            "Grbl 1.1h ['$' for help]\n[MSG:'$H'|'$X' to unlock]\n",
+            "ok\nok\nok\nok\nok\n",
+            "<Idle|MPos:15.000,5.000,0.000|FS:0,0>\n",
+            "ok\nok\n",
+        );
+        sender.reconnect(Transport::new(replies));
+        sender.recover(&job, Machine::new()).unwrap();
+        assert_eq!(sender.state(), JobState::Running);
+
+        sender.run().unwrap();
+        assert_eq!(sender.transport().sent(), concat!(
+            "\u{18}$H\nG21 G90\nG10 L2 P1 X5 Y0 Z0\nG0 X10 Y5\n?",
+            "G1 Z-1 F100\nG1 X20\n",
+        ));
+
+        let progress = sender.progress();
+        assert_eq!((progress.lines_sent, progress.lines_acknowledged, progress.lines_total), (5, 5, 5));
+
+        // Resuming below the clearance height feeds down to the cut instead of plunging rapidly
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\nok\nALARM:1\n"), &job);
+        assert!(sender.run().is_err());
+        let replies = "Grbl 1.1h\nok\nok\nok\nok\nok\n<Idle|MPos:15.000,5.000,-1.000|FS:0,0>\nok\n";
+        sender.reconnect(Transport::new(replies));
+        sender.recover(&job, Machine::new()).unwrap();
+        sender.run().unwrap();
+        assert_eq!(sender.transport().sent(), "\u{18}$H\nG21 G90\nG10 L2 P1 X5 Y0 Z0\nG0 X10 Y5\nG1 Z-1 F100\n?G1 X20\n");
+
+        // The job does not continue elsewhere
+        let mut sender = Sender::new(Transport::new("ok\nok\nok\nALARM:1\n"), &job);
+        assert!(sender.run().is_err());
+        sender.reconnect(Transport::new("Grbl 1.1h\nok\nok\nok\nok\nok\n<Idle|MPos:0.000,0.000,0.000|FS:0,0>\n"));
+        assert!(matches!(sender.recover(&job, Machine::new()), Err(SenderError::Misplaced { .. })));
+        assert_eq!(sender.state(), JobState::Failed);
+    }
+
+    #[test]
+    fn test_execute() {
+        let replies = "ok\nok\nBegin file list\nTEST.GCO 42\nEnd file list\nok\n";
+        let mut sender = Sender::new(Transport::new(replies), &program("G28\n")).with_checksums(true);
+        sender.run().unwrap();
+
+        let replies = sender.execute("M20").unwrap();
+        assert_eq!(replies[..3], [
+            Response::Other("Begin file list".to_owned()),
+            Response::Other("TEST.GCO 42".to_owned()),
+            Response::Other("End file list".to_owned()),
+        ]);
+        assert!(replies[3].is_ok());
+        assert!(sender.transport().sent().ends_with(&format!("N2 M20*{}\n", checksum("N2 M20"))));
+    }
+
+    #[test]
+    fn test_errors() {
+        let program = program("G0 X1\nG5 X2\n");
+        let mut sender = Sender::new(Transport::new("ok\nerror:20\n"), &program);
+        match sender.run() {
+            Err(SenderError::Rejected { code: 20, line }) => assert_eq!(line, "G5 X2"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let mut sender = Sender::new(Transport::new("ALARM:1\n"), &program);
+        assert!(matches!(sender.run(), Err(SenderError::Alarm(1))));
+
+        let mut sender = Sender::new(Transport::new("ok\n"), &program);
+        assert!(matches!(sender.run(), Err(SenderError::Disconnected)));
+    }
+}
@@ -0,0 +1,287 @@
+//! Preview images embedded in programs.
+//!
+//! PrusaSlicer, SuperSlicer and newer versions of Cura embed thumbnails as base64 encoded image
+//! data in comments, enclosed by a header and a footer:
+//!
+//! ```text
+//! ; thumbnail begin 16x16 1084
+//! ; iVBORw0KGgoAAAANSUhEUgAAABAAAAAQCAYAAAAf8/9hAAAACXBIWXMAAAsTAAALEwEAmpwYAAAA
+//! ; ...
+//! ; thumbnail end
+//! ```
+//!
+//! The header carries the size of the image and the number of base64 characters. Formats other
+//! than PNG use a suffix, like `thumbnail_JPG`.
+
+use std::error;
+use std::fmt;
+
+use crate::parser::{Block, Comment, CommentPosition, CommentStyle};
+use crate::program::Program;
+
+/// The number of base64 characters written per line, like PrusaSlicer does.
+const LINE_LENGTH: usize = 78;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, PartialEq)]
+pub enum ThumbnailError {
+    InvalidHeader {
+        header: String,
+    },
+
+    Unterminated {
+        block: usize,
+    },
+
+    LengthMismatch {
+        expected: usize,
+        actual: usize,
+    },
+
+    InvalidData {
+        block: usize,
+    },
+}
+
+impl fmt::Display for ThumbnailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThumbnailError::InvalidHeader { header } => write!(f, "invalid thumbnail header: {}", header),
+            ThumbnailError::Unterminated { block } => write!(f, "thumbnail started at block {} is not terminated", block),
+            ThumbnailError::LengthMismatch { expected, actual } => write!(f, "thumbnail has {} characters but announces {}", actual, expected),
+            ThumbnailError::InvalidData { block } => write!(f, "invalid base64 data in thumbnail started at block {}", block),
+        }
+    }
+}
+
+impl error::Error for ThumbnailError {}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ThumbnailFormat {
+    Png,
+    Jpg,
+    Qoi,
+}
+
+impl ThumbnailFormat {
+    /// The keyword starting the header of thumbnails in this format.
+    fn keyword(&self) -> &'static str {
+        match *self {
+            ThumbnailFormat::Png => "thumbnail",
+            ThumbnailFormat::Jpg => "thumbnail_JPG",
+            ThumbnailFormat::Qoi => "thumbnail_QOI",
+        }
+    }
+}
+
+/// An image embedded in a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub format: ThumbnailFormat,
+
+    /// The encoded image file.
+    pub data: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Creates the comment blocks embedding this thumbnail.
+    pub fn to_blocks(&self) -> Vec<Block> {
+        let encoded = encode(&self.data);
+
+        let comment = |text: String| Block::new(Vec::new())
+                .with_comment(Comment::new(text, CommentStyle::Semicolon, CommentPosition::Leading));
+
+        let mut blocks = Vec::new();
+        blocks.push(comment(format!(" {} begin {}x{} {}", self.format.keyword(), self.width, self.height, encoded.len())));
+        blocks.extend(encoded.as_bytes()
+                .chunks(LINE_LENGTH)
+                .map(|line| comment(format!(" {}", String::from_utf8_lossy(line)))));
+        blocks.push(comment(format!(" {} end", self.format.keyword())));
+
+        return blocks;
+    }
+}
+
+/// Finds and decodes all thumbnails in the program.
+pub fn thumbnails(program: &Program) -> Result<Vec<Thumbnail>, ThumbnailError> {
+    let mut thumbnails = Vec::new();
+
+    // The header of the thumbnail being read, its starting block and the data so far
+    let mut current: Option<(Thumbnail, usize, usize, String)> = None;
+
+    for (index, block) in program.iter().enumerate() {
+        for comment in block.comments() {
+            let text = comment.text().trim();
+
+            current = match current.take() {
+                None => header(text)?.map(|(thumbnail, length)| (thumbnail, length, index, String::new())),
+
+                Some((mut thumbnail, length, start, data)) => {
+                    if text != format!("{} end", thumbnail.format.keyword()) {
+                        Some((thumbnail, length, start, data + text))
+                    } else {
+                        if data.len() != length {
+                            return Err(ThumbnailError::LengthMismatch { expected: length, actual: data.len() });
+                        }
+
+                        thumbnail.data = decode(&data).ok_or(ThumbnailError::InvalidData { block: start })?;
+                        thumbnails.push(thumbnail);
+                        None
+                    }
+                }
+            };
+        }
+    }
+
+    if let Some((_, _, block, _)) = current {
+        return Err(ThumbnailError::Unterminated { block });
+    }
+
+    return Ok(thumbnails);
+}
+
+/// Returns a copy of the program with the thumbnails embedded at its start.
+pub fn embed(program: &Program, thumbnails: &[Thumbnail]) -> Program {
+    return thumbnails.iter()
+            .flat_map(Thumbnail::to_blocks)
+            .chain(program.iter().cloned())
+            .collect();
+}
+
+/// Parses a thumbnail header into an empty thumbnail and the announced number of characters.
+fn header(text: &str) -> Result<Option<(Thumbnail, usize)>, ThumbnailError> {
+    let mut parts = text.split_whitespace();
+
+    let format = match parts.next() {
+        Some("thumbnail") => ThumbnailFormat::Png,
+        Some("thumbnail_JPG") => ThumbnailFormat::Jpg,
+        Some("thumbnail_QOI") => ThumbnailFormat::Qoi,
+        _ => return Ok(None),
+    };
+
+    if parts.next() != Some("begin") {
+        return Ok(None);
+    }
+
+    let invalid = || ThumbnailError::InvalidHeader { header: text.to_owned() };
+
+    let size = parts.next().ok_or_else(invalid)?;
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let width = width.parse().map_err(|_| invalid())?;
+    let height = height.parse().map_err(|_| invalid())?;
+    let length = parts.next().ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+
+    return Ok(Some((Thumbnail { width, height, format, data: Vec::new() }, length)));
+}
+
+fn encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    return encoded;
+}
+
+fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+
+        bits = bits << 6 | value;
+        count += 6;
+
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+
+    return Some(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_base64() {
+        for &(data, text) in &[(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"foobar", "Zm9vYmFy")] {
+            assert_eq!(encode(data), text);
+            assert_eq!(decode(text).unwrap(), data);
+        }
+
+        assert_eq!(decode("Zm9v!"), None);
+    }
+
+    #[test]
+    fn test_thumbnails() {
+        let program = Program::from(Parser::new().parse_all([
+            "; generated by PrusaSlicer",
+            "; thumbnail begin 2x1 12",
+            "; Zm9v",
+            "; YmFy",
+            "; YmF6",
+            "; thumbnail end",
+            "; thumbnail_JPG begin 1x1 4",
+            "; Zg==",
+            "; thumbnail_JPG end",
+            "G1 X1",
+        ].iter()).unwrap());
+
+        assert_eq!(thumbnails(&program).unwrap(), vec![
+            Thumbnail { width: 2, height: 1, format: ThumbnailFormat::Png, data: b"foobarbaz".to_vec() },
+            Thumbnail { width: 1, height: 1, format: ThumbnailFormat::Jpg, data: b"f".to_vec() },
+        ]);
+    }
+
+    #[test]
+    fn test_embed() {
+        let thumbnail = Thumbnail {
+            width: 16,
+            height: 16,
+            format: ThumbnailFormat::Qoi,
+            data: (0..=255).collect(),
+        };
+
+        let program = Program::from(Parser::new().parse_all(["G1 X1"].iter()).unwrap());
+        let embedded = embed(&program, std::slice::from_ref(&thumbnail));
+
+        let lines = embedded.to_string();
+        assert!(lines.starts_with("; thumbnail_QOI begin 16x16 344\n"));
+        assert!(lines.lines().all(|line| line.len() <= LINE_LENGTH + 2));
+        assert!(lines.ends_with("; thumbnail_QOI end\nG1 X1\n"));
+
+        // Round trip through the parser
+        let parsed = Program::from(Parser::new().parse_all(lines.lines()).unwrap());
+        assert_eq!(thumbnails(&parsed).unwrap(), vec![thumbnail]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let parse = |lines: &[&str]| thumbnails(&Program::from(Parser::new().parse_all(lines.iter()).unwrap()));
+
+        assert_eq!(parse(&["; thumbnail begin 2x1"]), Err(ThumbnailError::InvalidHeader { header: "thumbnail begin 2x1".to_owned() }));
+        assert_eq!(parse(&["G0", "; thumbnail begin 2x1 4", "; Zm9v"]), Err(ThumbnailError::Unterminated { block: 1 }));
+        assert_eq!(parse(&["; thumbnail begin 2x1 5", "; Zm9v", "; thumbnail end"]), Err(ThumbnailError::LengthMismatch { expected: 5, actual: 4 }));
+        assert_eq!(parse(&["; thumbnail begin 2x1 4", "; Zm9!", "; thumbnail end"]), Err(ThumbnailError::InvalidData { block: 0 }));
+    }
+}
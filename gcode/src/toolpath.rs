@@ -0,0 +1,964 @@
+//! Geometric segments the tool moves along.
+
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+
+use crate::Real;
+use crate::real::consts::PI;
+use crate::command::{Command, DistanceMode, Move, Plane, Predefined};
+use crate::diagnostic::Code;
+use crate::geometry::{Axis, AxisValues, Point3};
+use crate::interp::{InterpError, Machine, Motion};
+use crate::parser::{Block, Word};
+use crate::transform::Pass;
+
+/// Absolute difference of the start and end radius tolerated in arcs, in millimeters.
+const RADIUS_TOLERANCE: Real = 0.05;
+
+/// Relative difference of the start and end radius tolerated in arcs.
+const RADIUS_TOLERANCE_RELATIVE: Real = 0.001;
+
+/// Distance below which two points are considered the same, in millimeters.
+const EPSILON: Real = 1e-6;
+
+#[derive(Debug)]
+pub enum ArcError {
+    MissingCenter,
+
+    AmbiguousCenter,
+
+    OffPlaneOffset {
+        axis: char,
+    },
+
+    ZeroRadius,
+
+    RadiusMismatch {
+        start: Real,
+        end: Real,
+    },
+
+    RadiusTooSmall {
+        radius: Real,
+        distance: Real,
+    },
+
+    FullCircleWithRadius,
+
+    InvalidTurns {
+        turns: Real,
+    },
+}
+
+impl fmt::Display for ArcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArcError::MissingCenter => f.write_str("arc without center offset or radius"),
+            ArcError::AmbiguousCenter => f.write_str("arc with both center offset and radius"),
+            ArcError::OffPlaneOffset { axis } => write!(f, "arc with center offset {} outside of the selected plane", axis),
+            ArcError::ZeroRadius => f.write_str("arc with zero radius"),
+            ArcError::RadiusMismatch { start, end } => write!(f, "arc radius differs between start ({}) and end ({})", start, end),
+            ArcError::RadiusTooSmall { radius, distance } => write!(f, "arc radius {} is too small to reach the end point at distance {}", radius, distance),
+            ArcError::FullCircleWithRadius => f.write_str("full circle arc given by radius"),
+            ArcError::InvalidTurns { turns } => write!(f, "invalid number of arc turns: {}", turns),
+        }
+    }
+}
+
+impl error::Error for ArcError {}
+
+impl ArcError {
+    /// The stable code identifying the kind of error (see `diagnostic::CODES`).
+    pub fn code(&self) -> Code {
+        match self {
+            ArcError::MissingCenter => Code::error(301),
+            ArcError::AmbiguousCenter => Code::error(302),
+            ArcError::OffPlaneOffset { .. } => Code::error(303),
+            ArcError::ZeroRadius => Code::error(304),
+            ArcError::RadiusMismatch { .. } => Code::error(305),
+            ArcError::RadiusTooSmall { .. } => Code::error(306),
+            ArcError::FullCircleWithRadius => Code::error(307),
+            ArcError::InvalidTurns { .. } => Code::error(308),
+        }
+    }
+}
+
+/// How the center of an arc is specified.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ArcCenter {
+    /// The offset of the center from the start point (`I`, `J` and `K`).
+    Offset(Point3),
+
+    /// The radius of the arc (`R`). Negative radii select the arc spanning more than 180 degrees.
+    Radius(Real),
+}
+
+/// An arc in one of the three planes, optionally moving along the axis perpendicular to it.
+///
+/// Angles are measured in radians in the selected plane, counter-clockwise from its first axis:
+/// X for G17, Z for G18 and Y for G19.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ArcSegment {
+    plane: Plane,
+    start: Point3,
+    end: Point3,
+    center: Point3,
+    radius: Real,
+    start_angle: Real,
+    sweep: Real,
+}
+
+impl ArcSegment {
+    /// Creates and validates an arc from `start` to `end` as specified by a G2 or G3 move.
+    ///
+    /// Arcs given by a center offset must have the same radius at start and end, up to a small
+    /// tolerance. If start and end coincide, the arc is a full circle.
+    pub fn new(plane: Plane, start: Point3, end: Point3, clockwise: bool, center: ArcCenter) -> Result<Self, ArcError> {
+        let (su, sv, _) = project(plane, start);
+        let (eu, ev, _) = project(plane, end);
+
+        let (cu, cv) = match center {
+            ArcCenter::Offset(offset) => {
+                let (ou, ov, _) = project(plane, offset);
+                (su + ou, sv + ov)
+            }
+
+            ArcCenter::Radius(radius) => {
+                let distance = (eu - su).hypot(ev - sv);
+                if distance < EPSILON {
+                    return Err(ArcError::FullCircleWithRadius);
+                }
+
+                let half = distance / 2.0;
+                if radius.abs() < half - RADIUS_TOLERANCE {
+                    return Err(ArcError::RadiusTooSmall { radius: radius.abs(), distance });
+                }
+
+                // Distance of the center from the middle of the chord
+                let height = (radius * radius - half * half).max(0.0).sqrt();
+
+                // The center lies left of the chord for short counter-clockwise arcs
+                let side = if clockwise == (radius < 0.0) { 1.0 } else { -1.0 };
+                let (nu, nv) = (-(ev - sv) / distance, (eu - su) / distance);
+
+                ((su + eu) / 2.0 + nu * height * side, (sv + ev) / 2.0 + nv * height * side)
+            }
+        };
+
+        let radius = (su - cu).hypot(sv - cv);
+        if radius < EPSILON {
+            return Err(ArcError::ZeroRadius);
+        }
+
+        let end_radius = (eu - cu).hypot(ev - cv);
+        let deviation = (end_radius - radius).abs();
+        if deviation > RADIUS_TOLERANCE && deviation > radius * RADIUS_TOLERANCE_RELATIVE {
+            return Err(ArcError::RadiusMismatch { start: radius, end: end_radius });
+        }
+
+        let start_angle = (sv - cv).atan2(su - cu);
+        let end_angle = (ev - cv).atan2(eu - cu);
+
+        let full = (eu - su).hypot(ev - sv) < EPSILON;
+        let mut sweep = end_angle - start_angle;
+        if clockwise {
+            if full || sweep >= 0.0 {
+                sweep -= 2.0 * PI;
+            }
+        } else if full || sweep <= 0.0 {
+            sweep += 2.0 * PI;
+        }
+
+        let (_, _, linear) = project(plane, start);
+
+        return Ok(Self {
+            plane,
+            start,
+            end,
+            center: unproject(plane, cu, cv, linear),
+            radius,
+            start_angle,
+            sweep,
+        });
+    }
+
+    /// Winds the arc around the center for the given number of turns, adding full circles before
+    /// the last one.
+    pub fn with_turns(mut self, turns: u32) -> Self {
+        let extra = (turns.max(1) - 1) as Real * 2.0 * PI;
+        self.sweep += if self.sweep < 0.0 { -extra } else { extra };
+        self
+    }
+
+    pub fn plane(&self) -> Plane {
+        self.plane
+    }
+
+    pub fn start(&self) -> Point3 {
+        self.start
+    }
+
+    pub fn end(&self) -> Point3 {
+        self.end
+    }
+
+    /// The center of the arc, at the height of the start point along the perpendicular axis.
+    pub fn center(&self) -> Point3 {
+        self.center
+    }
+
+    pub fn radius(&self) -> Real {
+        self.radius
+    }
+
+    pub fn start_angle(&self) -> Real {
+        self.start_angle
+    }
+
+    /// The angle at the end of the arc, which is `start_angle + sweep` and hence not normalized.
+    pub fn end_angle(&self) -> Real {
+        self.start_angle + self.sweep
+    }
+
+    /// The signed angle covered by the arc, negative for clockwise arcs.
+    pub fn sweep(&self) -> Real {
+        self.sweep
+    }
+
+    pub fn is_clockwise(&self) -> bool {
+        self.sweep < 0.0
+    }
+
+    pub fn is_full_circle(&self) -> bool {
+        (self.sweep.abs() - 2.0 * PI).abs() < crate::EPSILON
+    }
+
+    /// Whether the arc moves along the axis perpendicular to its plane.
+    pub fn is_helical(&self) -> bool {
+        (project(self.plane, self.start).2 - project(self.plane, self.end).2).abs() > EPSILON
+    }
+
+    /// The point at the given fraction (0 at the start, 1 at the end) of the arc.
+    pub fn point_at(&self, t: Real) -> Point3 {
+        let (_, _, start) = project(self.plane, self.start);
+        let (_, _, end) = project(self.plane, self.end);
+        let (cu, cv, _) = project(self.plane, self.center);
+
+        let angle = self.start_angle + self.sweep * t;
+        return unproject(self.plane,
+                         cu + self.radius * angle.cos(),
+                         cv + self.radius * angle.sin(),
+                         start + (end - start) * t);
+    }
+
+    /// The corners of the axis aligned box enclosing the arc, as minimum and maximum.
+    pub fn extents(&self) -> (Point3, Point3) {
+        let mut min = self.start;
+        let mut max = self.start;
+        let mut extend = |point: Point3| {
+            min = Point3::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z));
+            max = Point3::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z));
+        };
+
+        extend(self.end);
+
+        // The arc reaches its extremes in the plane at multiples of a quarter turn
+        for quadrant in 0..4 {
+            let angle = quadrant as Real * PI / 2.0;
+            let distance = if self.sweep >= 0.0 { angle - self.start_angle } else { self.start_angle - angle };
+            let distance = distance.rem_euclid(2.0 * PI);
+
+            if distance <= self.sweep.abs() {
+                extend(self.point_at(distance / self.sweep.abs()));
+            }
+        }
+
+        return (min, max);
+    }
+
+    /// Approximates the arc by line segments deviating at most `tolerance` from it.
+    ///
+    /// Returns the end points of all segments, excluding the start and ending exactly at the end
+    /// of the arc.
+    pub fn flatten(&self, tolerance: Real) -> Vec<Point3> {
+        // The maximal angle of a chord whose middle is at most `tolerance` away from the arc
+        let step = if tolerance < self.radius {
+            2.0 * (1.0 - tolerance / self.radius).acos()
+        } else {
+            PI / 2.0
+        };
+
+        let segments = (self.sweep.abs() / step).ceil().max(1.0) as usize;
+
+        return (1..=segments)
+                .map(|i| if i == segments { self.end } else { self.point_at(i as Real / segments as Real) })
+                .collect();
+    }
+
+    /// The length of the arc, including the movement along the perpendicular axis.
+    pub fn length(&self) -> Real {
+        let (_, _, start) = project(self.plane, self.start);
+        let (_, _, end) = project(self.plane, self.end);
+
+        return (self.sweep.abs() * self.radius).hypot(end - start);
+    }
+}
+
+/// Replaces arc moves by linear moves approximating them within a chord tolerance.
+///
+/// The pass interprets the program to know where arcs start. The replacing moves use the
+/// coordinate system, units and distance mode active at the arc. Other words of a block containing
+/// an arc are kept in a block of their own before the linear moves. Blocks which can not be
+/// interpreted are passed through unchanged.
+#[derive(Debug, Clone)]
+pub struct FlattenArcs {
+    tolerance: Real,
+    machine: Machine,
+}
+
+impl FlattenArcs {
+    /// Creates a pass keeping the linear moves within `tolerance` (in millimeters) of the arcs.
+    ///
+    /// Panics if `tolerance` is not positive.
+    pub fn new(tolerance: Real) -> Self {
+        assert!(tolerance > 0.0, "tolerance must be positive");
+
+        Self {
+            tolerance,
+            machine: Machine::new(),
+        }
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+}
+
+impl Pass for FlattenArcs {
+    fn name(&self) -> &'static str {
+        "flatten-arcs"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let before = self.machine.state().clone();
+        if self.machine.execute(&block).is_err() {
+            output.push(block);
+            return;
+        }
+
+        let state = self.machine.state();
+
+        let commands = Command::from_block(&block);
+        let (index, clockwise, arc) = match commands.iter().enumerate().find_map(|(i, command)| match *command {
+            Command::ClockwiseArc(arc) => Some((i, true, arc)),
+            Command::CounterClockwiseArc(arc) => Some((i, false, arc)),
+            Command::ModalMove(arc) => match state.motion {
+                Some(Motion::ClockwiseArc) => Some((i, true, arc)),
+                Some(Motion::CounterClockwiseArc) => Some((i, false, arc)),
+                _ => None,
+            },
+            _ => None,
+        }) {
+            Some(found) => found,
+            None => {
+                output.push(block);
+                return;
+            }
+        };
+
+        // The arc starts at the previous position, but with all modes of the block applied
+        let mut start = state.clone();
+        start.position = before.position;
+
+        let segment = match start.arc(clockwise, &arc) {
+            Ok(segment) => segment,
+            Err(_) => {
+                output.push(block);
+                return;
+            }
+        };
+
+        let derived = |words: Vec<Word>| Block::new(words)
+                .with_deleted(block.is_deleted())
+                .with_provenance(block.provenance().clone())
+                .with_pass(self.name());
+
+        let others = commands.iter()
+                .enumerate()
+                .filter(|&(i, _)| i != index)
+                .flat_map(|(_, command)| command.words())
+                .collect::<Vec<_>>();
+
+        let mut line_number = block.line_number();
+        if !others.is_empty() {
+            output.push(derived(others).with_line_number(line_number.take()));
+        }
+
+        // Coordinates are rounded to the precision of the output, so relative moves do not drift
+        let round = |value: Real| (value * 1e4).round() / 1e4;
+        let work = |point: Point3| {
+            let point = state.to_work(point);
+            (round(point.x), round(point.y), round(point.z))
+        };
+
+        // The axis perpendicular to the plane is only moved by helical arcs
+        let linear = match segment.plane() {
+            Plane::XY => 'Z',
+            Plane::ZX => 'Y',
+            Plane::YZ => 'X',
+        };
+        let helical = segment.is_helical();
+
+        let mut previous = work(segment.start());
+        let mut feed = arc.f;
+        for point in segment.flatten(self.tolerance) {
+            let current = work(point);
+
+            let (x, y, z) = match state.distance_mode {
+                DistanceMode::Absolute => current,
+                DistanceMode::Relative => (current.0 - previous.0, current.1 - previous.1, current.2 - previous.2),
+            };
+
+            let mut words = vec![Word::new('G', 1.0)];
+            for &(axis, value) in &[('X', x), ('Y', y), ('Z', z)] {
+                if axis != linear || helical {
+                    words.push(Word::new(axis, value));
+                }
+            }
+            if let Some(f) = feed.take() {
+                words.push(Word::new('F', f));
+            }
+
+            output.push(derived(words).with_line_number(line_number.take()));
+            previous = current;
+        }
+    }
+}
+
+/// The motion of the axes besides X, Y and Z along a segment, in degrees for rotary axes and
+/// millimeters otherwise. The axes move linearly with the progress along the segment.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct AxisTravel {
+    pub from: AxisValues<Real>,
+    pub to: AxisValues<Real>,
+}
+
+impl AxisTravel {
+    pub fn new(from: AxisValues<Real>, to: AxisValues<Real>) -> Self {
+        Self { from, to }
+    }
+
+    /// Keeping all axes at the given positions.
+    pub fn stationary(at: AxisValues<Real>) -> Self {
+        Self { from: at, to: at }
+    }
+
+    /// The distance travelled by each axis, signed by its direction.
+    pub fn deltas(&self) -> AxisValues<Real> {
+        self.to.map(|axis, to| to - self.from[axis])
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.deltas().iter().any(|(_, delta)| delta.abs() > EPSILON)
+    }
+
+    /// The length the feed rate applies to for a segment of the given length, which is the
+    /// travel of U, V and W if the tool stays in place, or otherwise of A, B and C.
+    fn feed_length(&self, length: Real) -> Real {
+        if length > EPSILON {
+            return length;
+        }
+
+        let deltas = self.deltas();
+        let norm = |axes: &[Axis]| axes.iter().map(|&axis| deltas[axis].powi(2)).sum::<Real>().sqrt();
+
+        let linear = norm(&[Axis::U, Axis::V, Axis::W]);
+        return if linear > EPSILON { linear } else { norm(&[Axis::A, Axis::B, Axis::C]) };
+    }
+}
+
+/// A single motion of the tool in machine coordinates and millimeters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// A move at rapid rate (G0).
+    Rapid {
+        from: Point3,
+        to: Point3,
+        axes: AxisTravel,
+    },
+
+    /// A straight move at the feed rate, given in millimeters per minute.
+    Line {
+        from: Point3,
+        to: Point3,
+        axes: AxisTravel,
+        feed: Option<Real>,
+    },
+
+    Arc {
+        arc: ArcSegment,
+        axes: AxisTravel,
+        feed: Option<Real>,
+    },
+
+    /// Staying in place for the given time (G4).
+    Dwell {
+        seconds: Real,
+    },
+}
+
+impl Segment {
+    /// The point the segment starts at, if it moves the tool.
+    pub fn start(&self) -> Option<Point3> {
+        match *self {
+            Segment::Rapid { from, .. } | Segment::Line { from, .. } => Some(from),
+            Segment::Arc { ref arc, .. } => Some(arc.start()),
+            Segment::Dwell { .. } => None,
+        }
+    }
+
+    /// The point the segment ends at, if it moves the tool.
+    pub fn end(&self) -> Option<Point3> {
+        match *self {
+            Segment::Rapid { to, .. } | Segment::Line { to, .. } => Some(to),
+            Segment::Arc { ref arc, .. } => Some(arc.end()),
+            Segment::Dwell { .. } => None,
+        }
+    }
+
+    /// The motion of the axes besides X, Y and Z, if the segment moves the tool.
+    pub fn axes(&self) -> Option<AxisTravel> {
+        match *self {
+            Segment::Rapid { axes, .. } | Segment::Line { axes, .. } | Segment::Arc { axes, .. } => Some(axes),
+            Segment::Dwell { .. } => None,
+        }
+    }
+
+    /// The distance travelled by the tool along the segment, not counting the axes besides X, Y
+    /// and Z.
+    pub fn length(&self) -> Real {
+        match *self {
+            Segment::Rapid { from, to, .. } | Segment::Line { from, to, .. } => from.distance(to),
+            Segment::Arc { ref arc, .. } => arc.length(),
+            Segment::Dwell { .. } => 0.0,
+        }
+    }
+}
+
+/// Iterator over the segments of a program.
+///
+/// Each block is executed by the machine and turned into the segments it moves along, in the
+/// order of execution. Blocks without motion produce no segments, except for dwells. Moving only
+/// the axes besides X, Y and Z produces segments starting and ending at the same point. A failing
+/// block yields an error annotated with its provenance and is otherwise skipped.
+///
+/// Moves to stored positions (G28, G30) are rapids to the intermediate point and on to the stored
+/// position, unless modeled otherwise (see `with_predefined_moves`).
+pub struct Segments<I> {
+    machine: Machine,
+    blocks: I,
+    pending: VecDeque<Segment>,
+    predefined: Option<PredefinedMoves>,
+}
+
+/// Turns a move to a stored position into segments, given the start, the intermediate point and
+/// the end of the move.
+pub type PredefinedMoves = Box<dyn FnMut(Predefined, Point3, Point3, Point3) -> Vec<Segment>>;
+
+impl<I> Segments<I> {
+    pub fn new(machine: Machine, blocks: I) -> Self {
+        Self {
+            machine,
+            blocks,
+            pending: VecDeque::new(),
+            predefined: None,
+        }
+    }
+
+    /// Models moves to stored positions with the given function, e.g. to account for a homing
+    /// cycle seeking the limit switches at a slow feed rate.
+    pub fn with_predefined_moves<F>(mut self, model: F) -> Self
+        where F: FnMut(Predefined, Point3, Point3, Point3) -> Vec<Segment> + 'static {
+        self.predefined = Some(Box::new(model));
+        self
+    }
+
+    /// The machine executing the blocks, e.g. for inspecting the state after the last block.
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Executes a block and queues its segments.
+    fn step(&mut self, block: &Block) -> Result<(), InterpError> {
+        let before = self.machine.state().clone();
+        self.machine.execute(block)?;
+
+        if block.is_deleted() {
+            return Ok(());
+        }
+
+        let after = self.machine.state();
+        let axes = AxisTravel::new(before.axes, after.axes);
+        let moves = |from: Point3, to: Point3| from.distance(to) > EPSILON || axes.is_moving();
+
+        // Motions start at the previous position, but with all modes of the block applied
+        let mut start = after.clone();
+        start.position = before.position;
+        start.axes = before.axes;
+
+        for command in Command::from_block(block) {
+            match command {
+                Command::Dwell { seconds } => self.pending.push_back(Segment::Dwell { seconds }),
+
+                Command::Probe(..) => {
+                    let (from, to) = (before.position, after.position);
+                    if moves(from, to) {
+                        let feed = start.feed_per_minute(axes.feed_length(from.distance(to)));
+                        self.pending.push_back(Segment::Line { from, to, axes, feed });
+                    }
+                }
+
+                Command::SynchronizedMove { pitch, .. } => {
+                    // The feed follows the spindle, as fast as it turns at the start of the move
+                    let (from, to) = (before.position, after.position);
+                    let feed = start.spindle_rpm().map(|rpm| rpm * pitch * start.units.millimeters());
+                    if moves(from, to) {
+                        self.pending.push_back(Segment::Line { from, to, axes, feed });
+                    }
+                }
+
+                Command::GoToPredefined(position, axes) => {
+                    let mut machine = Machine::with_state(start.clone());
+                    machine.execute(&Command::to_block(&[Command::RapidMove(Move { x: axes.x, y: axes.y, z: axes.z, ..Move::default() })]))?;
+
+                    let intermediate = machine.state().position;
+                    match self.predefined {
+                        Some(ref mut model) => self.pending.extend(model(position, before.position, intermediate, after.position)),
+                        None => {
+                            for &(from, to) in &[(before.position, intermediate), (intermediate, after.position)] {
+                                if from.distance(to) > EPSILON {
+                                    self.pending.push_back(Segment::Rapid { from, to, axes: AxisTravel::stationary(after.axes) });
+                                }
+                            }
+                        }
+                    }
+                }
+
+                Command::RapidMove(_) | Command::LinearMove(_) | Command::ClockwiseArc(_)
+                | Command::CounterClockwiseArc(_) | Command::ModalMove(_) => {
+                    let (from, to) = (before.position, after.position);
+                    let feed = |length: Real| start.feed_per_minute(axes.feed_length(length));
+
+                    self.pending.extend(match (command, after.motion) {
+                        (Command::ClockwiseArc(arc), _) | (Command::ModalMove(arc), Some(Motion::ClockwiseArc)) => {
+                            let arc = start.arc(true, &arc)?;
+                            Some(Segment::Arc { arc, axes, feed: feed(arc.length()) })
+                        }
+                        (Command::CounterClockwiseArc(arc), _) | (Command::ModalMove(arc), Some(Motion::CounterClockwiseArc)) => {
+                            let arc = start.arc(false, &arc)?;
+                            Some(Segment::Arc { arc, axes, feed: feed(arc.length()) })
+                        }
+                        _ if !moves(from, to) => None,
+                        (Command::RapidMove(_), _) | (Command::ModalMove(_), Some(Motion::Rapid)) =>
+                            Some(Segment::Rapid { from, to, axes }),
+                        _ => Some(Segment::Line { from, to, axes, feed: feed(from.distance(to)) }),
+                    });
+                }
+
+                _ => {}
+            }
+        }
+
+        return Ok(());
+    }
+}
+
+impl<I> Iterator for Segments<I>
+    where I: Iterator<Item=Block> {
+    type Item = Result<Segment, InterpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(segment) = self.pending.pop_front() {
+                return Some(Ok(segment));
+            }
+
+            let block = self.blocks.next()?;
+            if let Err(error) = self.step(&block) {
+                self.pending.clear();
+                return Some(Err(InterpError::Located {
+                    provenance: block.provenance().clone(),
+                    error: Box::new(error),
+                }));
+            }
+        }
+    }
+}
+
+/// Turns blocks into the segments the tool moves along, starting with a machine in its default
+/// state.
+///
+/// See `Machine::into_segments` to start with a configured machine.
+pub fn segments<I>(blocks: I) -> Segments<I::IntoIter>
+    where I: IntoIterator<Item=Block> {
+    Machine::new().into_segments(blocks)
+}
+
+/// Splits a point into the coordinates along the two axes of the plane and the perpendicular one.
+fn project(plane: Plane, point: Point3) -> (Real, Real, Real) {
+    match plane {
+        Plane::XY => (point.x, point.y, point.z),
+        Plane::ZX => (point.z, point.x, point.y),
+        Plane::YZ => (point.y, point.z, point.x),
+    }
+}
+
+fn unproject(plane: Plane, u: Real, v: Real, w: Real) -> Point3 {
+    match plane {
+        Plane::XY => Point3::new(u, v, w),
+        Plane::ZX => Point3::new(v, w, u),
+        Plane::YZ => Point3::new(w, u, v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn p(x: Real, y: Real, z: Real) -> Point3 {
+        Point3::new(x, y, z)
+    }
+
+    fn close(a: Point3, b: Point3) -> bool {
+        a.distance(b) < crate::EPSILON
+    }
+
+    #[test]
+    fn test_arc_offset() {
+        let arc = ArcSegment::new(Plane::XY, p(1.0, 0.0, 0.0), p(0.0, 1.0, 0.0), false, ArcCenter::Offset(p(-1.0, 0.0, 0.0))).unwrap();
+
+        assert_eq!(arc.center(), p(0.0, 0.0, 0.0));
+        assert_eq!(arc.radius(), 1.0);
+        assert_eq!(arc.start_angle(), 0.0);
+        assert!((arc.end_angle() - PI / 2.0).abs() < crate::EPSILON);
+        assert!(!arc.is_clockwise());
+        assert!((arc.length() - PI / 2.0).abs() < crate::EPSILON);
+
+        // The same points clockwise take the long way around
+        let arc = ArcSegment::new(Plane::XY, p(1.0, 0.0, 0.0), p(0.0, 1.0, 0.0), true, ArcCenter::Offset(p(-1.0, 0.0, 0.0))).unwrap();
+        assert!((arc.sweep() + 1.5 * PI).abs() < crate::EPSILON);
+    }
+
+    #[test]
+    fn test_arc_radius() {
+        let short = ArcSegment::new(Plane::XY, p(0.0, 0.0, 0.0), p(2.0, 0.0, 0.0), true, ArcCenter::Radius(1.0)).unwrap();
+        assert!(close(short.center(), p(1.0, 0.0, 0.0)));
+        assert!((short.sweep() + PI).abs() < crate::EPSILON);
+
+        let short = ArcSegment::new(Plane::XY, p(0.0, 0.0, 0.0), p(2.0, 2.0, 0.0), true, ArcCenter::Radius(2.0)).unwrap();
+        assert!(close(short.center(), p(2.0, 0.0, 0.0)));
+        assert!((short.sweep() + PI / 2.0).abs() < crate::EPSILON);
+
+        let long = ArcSegment::new(Plane::XY, p(0.0, 0.0, 0.0), p(2.0, 2.0, 0.0), true, ArcCenter::Radius(-2.0)).unwrap();
+        assert!(close(long.center(), p(0.0, 2.0, 0.0)));
+        assert!((long.sweep() + 1.5 * PI).abs() < crate::EPSILON);
+    }
+
+    #[test]
+    fn test_arc_full_circle() {
+        let arc = ArcSegment::new(Plane::XY, p(1.0, 0.0, 0.0), p(1.0, 0.0, -1.0), true, ArcCenter::Offset(p(-1.0, 0.0, 0.0))).unwrap();
+
+        assert!(arc.is_full_circle());
+        assert!(arc.is_clockwise());
+        assert!((arc.length() - (2.0 * PI).hypot(1.0)).abs() < crate::EPSILON);
+    }
+
+    #[test]
+    fn test_arc_planes() {
+        // G18 arcs are counter-clockwise when going from Z towards X
+        let arc = ArcSegment::new(Plane::ZX, p(0.0, 5.0, 1.0), p(1.0, 5.0, 0.0), false, ArcCenter::Offset(p(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(arc.center(), p(0.0, 5.0, 0.0));
+        assert!((arc.sweep() - PI / 2.0).abs() < crate::EPSILON);
+
+        let arc = ArcSegment::new(Plane::YZ, p(0.0, 1.0, 0.0), p(0.0, 0.0, 1.0), false, ArcCenter::Offset(p(0.0, -1.0, 0.0))).unwrap();
+        assert!((arc.sweep() - PI / 2.0).abs() < crate::EPSILON);
+    }
+
+    #[test]
+    fn test_arc_helix() {
+        // Three full turns descending 6 millimeters
+        let arc = ArcSegment::new(Plane::XY, p(10.0, 0.0, 0.0), p(10.0, 0.0, -6.0), true, ArcCenter::Offset(p(-10.0, 0.0, 0.0))).unwrap().with_turns(3);
+
+        assert!(arc.is_helical());
+        assert!((arc.sweep() + 6.0 * PI).abs() < crate::EPSILON);
+        assert!((arc.length() - (60.0 * PI).hypot(6.0)).abs() < crate::EPSILON);
+        assert!(close(arc.point_at(1.0 / 12.0), p(0.0, -10.0, -0.5)));
+        assert!(close(*arc.flatten(0.01).last().unwrap(), p(10.0, 0.0, -6.0)));
+
+        let arc = ArcSegment::new(Plane::ZX, p(0.0, 0.0, 0.0), p(0.0, 5.0, 2.0), false, ArcCenter::Offset(p(0.0, 0.0, 1.0))).unwrap();
+        assert!(arc.is_helical());
+        assert!((arc.length() - PI.hypot(5.0)).abs() < crate::EPSILON);
+
+        let segments = segments(Parser::new().parse_all(["G0 X10", "G2 X10 Z-6 I-10 P3 F100", "G2 X0 I-5 P0.5"].iter()).unwrap())
+                .collect::<Vec<_>>();
+        match segments[1] {
+            Ok(Segment::Arc { ref arc, .. }) => assert!((arc.length() - (60.0 * PI).hypot(6.0)).abs() < crate::EPSILON),
+            ref segment => panic!("unexpected segment: {:?}", segment),
+        }
+        assert!(segments[2].is_err());
+    }
+
+    #[test]
+    fn test_arc_extents() {
+        let arc = ArcSegment::new(Plane::XY, p(1.0, 0.0, 0.0), p(0.0, -1.0, 2.0), false, ArcCenter::Offset(p(-1.0, 0.0, 0.0))).unwrap();
+        let (min, max) = arc.extents();
+        assert!(close(min, p(-1.0, -1.0, 0.0)));
+        assert!(close(max, p(1.0, 1.0, 2.0)));
+
+        let arc = ArcSegment::new(Plane::ZX, p(0.0, 0.0, 1.0), p(1.0, 0.0, 0.0), false, ArcCenter::Offset(p(0.0, 0.0, -1.0))).unwrap();
+        let (min, max) = arc.extents();
+        assert!(close(min, p(0.0, 0.0, 0.0)));
+        assert!(close(max, p(1.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let arc = ArcSegment::new(Plane::XY, p(1.0, 0.0, 0.0), p(-1.0, 0.0, 0.0), false, ArcCenter::Radius(1.0)).unwrap();
+
+        let points = arc.flatten(0.01);
+        assert_eq!(points.len(), 12);
+        assert_eq!(points.last(), Some(&p(-1.0, 0.0, 0.0)));
+
+        // The middle of every chord stays within the tolerance
+        let mut previous = arc.start();
+        for point in points {
+            let middle = p((previous.x + point.x) / 2.0, (previous.y + point.y) / 2.0, 0.0);
+            assert!(1.0 - middle.distance(arc.center()) <= 0.01);
+            previous = point;
+        }
+
+        assert_eq!(arc.flatten(5.0).len(), 2);
+    }
+
+    #[test]
+    fn test_flatten_arcs() {
+        let blocks = Parser::new().parse_all(["G0 X10 Y0", "N5 G3 X-10 Y0 R10 F300 M8", "G91", "G2 X20 I10", "G0 X1"].iter()).unwrap();
+        let program = apply(&mut FlattenArcs::new(5.0), blocks);
+
+        let lines = program.iter().map(|block| block.line()).collect::<Vec<_>>();
+        assert_eq!(lines, vec![
+            "G0 X10 Y0",
+            "N5 M8",
+            "G1 X0 Y10 F300",
+            "G1 X-10 Y0",
+            "G91",
+            "G1 X10 Y10",
+            "G1 X10 Y-10",
+            "G0 X1",
+        ]);
+
+        assert_eq!(program.blocks()[2].provenance().passes(), &["flatten-arcs"]);
+    }
+
+    #[test]
+    fn test_segments() {
+        let blocks = Parser::new().parse_all(["G21 G0 X10", "G1 Y10 F100", "G4 P0.5", "G3 X0 Y0 R10", "G20 X1 R1", "G28 Z1", "G1 X1"].iter()).unwrap();
+
+        let segments = segments(blocks).collect::<Vec<_>>();
+        assert_eq!(segments.len(), 7);
+
+        let segments = segments.into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(segments[0], Segment::Rapid { from: p(0.0, 0.0, 0.0), to: p(10.0, 0.0, 0.0), axes: AxisTravel::default() });
+        assert_eq!(segments[1], Segment::Line { from: p(10.0, 0.0, 0.0), to: p(10.0, 10.0, 0.0), axes: AxisTravel::default(), feed: Some(100.0) });
+        assert_eq!(segments[2], Segment::Dwell { seconds: 0.5 });
+
+        match segments[3] {
+            Segment::Arc { ref arc, feed, .. } => {
+                assert!(close(arc.end(), p(0.0, 0.0, 0.0)));
+                assert!(!arc.is_clockwise());
+                assert_eq!(feed, Some(100.0));
+            }
+            ref segment => panic!("unexpected segment: {:?}", segment),
+        }
+
+        // The modal arc continues in inches
+        assert!(close(segments[4].end().unwrap(), p(25.4, 0.0, 0.0)));
+
+        // Homing moves through the intermediate point, the following move has no length
+        assert!(close(segments[5].end().unwrap(), p(25.4, 0.0, 25.4)));
+        assert_eq!(segments[6], Segment::Rapid { from: segments[5].end().unwrap(), to: p(25.4, 0.0, 0.0), axes: AxisTravel::default() });
+    }
+
+    #[test]
+    fn test_segments_predefined() {
+        let blocks = Parser::new().parse_all(["G0 X10 Z5", "G30.1", "G0 X0 Z0", "G30 Z2"].iter()).unwrap();
+
+        // A homing cycle seeking at a slow feed rate instead of rapids
+        let segments = Machine::new().into_segments(blocks)
+                .with_predefined_moves(|position, from, intermediate, to| {
+                    assert_eq!(position, Predefined::Secondary);
+                    vec![
+                        Segment::Line { from, to: intermediate, axes: AxisTravel::default(), feed: Some(50.0) },
+                        Segment::Line { from: intermediate, to, axes: AxisTravel::default(), feed: Some(50.0) },
+                    ]
+                })
+                .collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(&segments[2..], &[
+            Segment::Line { from: p(0.0, 0.0, 0.0), to: p(0.0, 0.0, 2.0), axes: AxisTravel::default(), feed: Some(50.0) },
+            Segment::Line { from: p(0.0, 0.0, 2.0), to: p(0.0, 0.0, 5.0), axes: AxisTravel::default(), feed: Some(50.0) },
+        ]);
+    }
+
+    #[test]
+    fn test_segments_axes() {
+        let blocks = Parser::new().parse_all(["G0 X10 A90", "G1 B-45 F100", "G20 G91 G1 X1 W1"].iter()).unwrap();
+        let segments = segments(blocks).collect::<Result<Vec<_>, _>>().unwrap();
+
+        let positions = |a: Real, b: Real, w: Real| AxisValues([a, b, 0.0, 0.0, 0.0, w]);
+        assert_eq!(segments, vec![
+            Segment::Rapid { from: p(0.0, 0.0, 0.0), to: p(10.0, 0.0, 0.0), axes: AxisTravel::new(positions(0.0, 0.0, 0.0), positions(90.0, 0.0, 0.0)) },
+            Segment::Line { from: p(10.0, 0.0, 0.0), to: p(10.0, 0.0, 0.0), axes: AxisTravel::new(positions(90.0, 0.0, 0.0), positions(90.0, -45.0, 0.0)), feed: Some(100.0) },
+            Segment::Line { from: p(10.0, 0.0, 0.0), to: p(35.4, 0.0, 0.0), axes: AxisTravel::new(positions(90.0, -45.0, 0.0), positions(90.0, -45.0, 25.4)), feed: Some(100.0) },
+        ]);
+        assert!((segments[2].axes().unwrap().deltas()[Axis::W] - 25.4).abs() < crate::EPSILON);
+        assert!((segments[2].length() - 25.4).abs() < crate::EPSILON);
+    }
+
+    #[test]
+    fn test_segments_threading() {
+        let blocks = Parser::new().parse_all(["G7 G0 X20 Z2", "S500 M3", "G33 Z-30 K1.5", "G96 S100 D1000", "G33 Z-40 K1"].iter()).unwrap();
+
+        let segments = segments(blocks).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(&segments[1..], &[
+            Segment::Line { from: p(10.0, 0.0, 2.0), to: p(10.0, 0.0, -30.0), axes: AxisTravel::default(), feed: Some(750.0) },
+            Segment::Line { from: p(10.0, 0.0, -30.0), to: p(10.0, 0.0, -40.0), axes: AxisTravel::default(), feed: Some(1000.0) },
+        ]);
+    }
+
+    #[test]
+    fn test_segments_error() {
+        let blocks = Parser::new().parse_all(["G0 X10", "G2 X0", "G1 X5 F10"].iter()).unwrap();
+
+        let segments = segments(blocks).collect::<Vec<_>>();
+        assert_eq!(segments.len(), 3);
+        assert!(segments[1].is_err());
+        assert_eq!(segments[2].as_ref().unwrap().start(), Some(p(10.0, 0.0, 0.0)));
+        assert_eq!(segments[2].as_ref().unwrap().length(), 5.0);
+    }
+
+    #[test]
+    fn test_arc_errors() {
+        let start = p(0.0, 0.0, 0.0);
+
+        assert!(ArcSegment::new(Plane::XY, start, p(2.0, 0.0, 0.0), true, ArcCenter::Offset(p(1.5, 0.0, 0.0))).is_err());
+        assert!(ArcSegment::new(Plane::XY, start, p(4.0, 0.0, 0.0), true, ArcCenter::Radius(1.0)).is_err());
+        assert!(ArcSegment::new(Plane::XY, start, start, true, ArcCenter::Radius(1.0)).is_err());
+        assert!(ArcSegment::new(Plane::XY, start, start, true, ArcCenter::Offset(start)).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Tables of the tools available on a machine.
+//!
+//! Tool tables are stored as comma separated lines of the tool number, the diameter and
+//! optionally the length of the tool, all in millimeters (`3, 6.35, 42.1`). Empty lines and
+//! lines starting with `#` are skipped.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::Real;
+
+#[derive(Debug)]
+pub enum ToolTableError {
+    Syntax {
+        line: usize,
+        message: String,
+    },
+
+    Io(io::Error),
+}
+
+impl fmt::Display for ToolTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolTableError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            ToolTableError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for ToolTableError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ToolTableError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ToolTableError {
+    fn from(err: io::Error) -> Self {
+        ToolTableError::Io(err)
+    }
+}
+
+/// A tool of a machine.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tool {
+    /// The diameter of the cutter, in millimeters.
+    pub diameter: Real,
+
+    /// The length offset applied by G43, in millimeters.
+    pub length: Real,
+}
+
+impl Tool {
+    pub fn new(diameter: Real) -> Self {
+        Self {
+            diameter,
+            length: 0.0,
+        }
+    }
+
+    pub fn with_length(mut self, length: Real) -> Self {
+        self.length = length;
+        self
+    }
+
+    pub fn radius(&self) -> Real {
+        self.diameter / 2.0
+    }
+}
+
+/// The tools of a machine by their numbers (`T` words).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ToolTable {
+    tools: BTreeMap<u32, Tool>,
+}
+
+impl ToolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tool with the given number, replacing any tool with the same number.
+    pub fn with_tool(mut self, number: u32, tool: Tool) -> Self {
+        self.insert(number, tool);
+        self
+    }
+
+    pub fn insert(&mut self, number: u32, tool: Tool) {
+        self.tools.insert(number, tool);
+    }
+
+    pub fn get(&self, number: u32) -> Option<&Tool> {
+        self.tools.get(&number)
+    }
+
+    /// All tools, ordered by their numbers.
+    pub fn iter(&self) -> impl Iterator<Item=(u32, &Tool)> + '_ {
+        self.tools.iter().map(|(&number, tool)| (number, tool))
+    }
+
+    /// Reads a tool table from the file at the given path.
+    pub fn read<P>(path: P) -> Result<Self, ToolTableError>
+        where P: AsRef<Path> {
+        return Self::parse(&fs::read_to_string(path)?);
+    }
+
+    /// Parses a tool table. Later lines replace tools with the same number.
+    pub fn parse(text: &str) -> Result<Self, ToolTableError> {
+        let mut table = Self::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let syntax = |message: String| ToolTableError::Syntax { line: i + 1, message };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+            if fields.len() != 2 && fields.len() != 3 {
+                return Err(syntax(format!("expected 2 or 3 fields, found {}", fields.len())));
+            }
+
+            let number = fields[0].parse::<u32>()
+                    .map_err(|_| syntax(format!("invalid tool number: {}", fields[0])))?;
+            let value = |field: &str| field.parse::<Real>()
+                    .map_err(|_| syntax(format!("invalid number: {}", field)));
+
+            let mut tool = Tool::new(value(fields[1])?);
+            if let Some(length) = fields.get(2) {
+                tool = tool.with_length(value(length)?);
+            }
+
+            table.insert(number, tool);
+        }
+
+        return Ok(table);
+    }
+
+    /// Formats the table in the format read by `parse`.
+    pub fn format(&self) -> String {
+        return self.iter()
+                .map(|(number, tool)| format!("{}, {}, {}\n", number, tool.diameter, tool.length))
+                .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let table = ToolTable::parse("# number, diameter, length\n1, 6, 42.5\n\n  3,0.8  \n").unwrap();
+        assert_eq!(table, ToolTable::new()
+                .with_tool(1, Tool::new(6.0).with_length(42.5))
+                .with_tool(3, Tool::new(0.8)));
+        assert_eq!(ToolTable::parse(&table.format()).unwrap(), table);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        match ToolTable::parse("1, 6\n2") {
+            Err(ToolTableError::Syntax { line: 2, .. }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match ToolTable::parse("T1, 6") {
+            Err(ToolTableError::Syntax { line: 1, .. }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
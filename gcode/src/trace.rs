@@ -0,0 +1,18 @@
+//! Instrumentation using `tracing`, which compiles to nothing unless the `tracing` feature is
+//! enabled.
+
+/// Emits an event at the given level, e.g. `event!(debug, line = 1, "parsed")`.
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
+
+/// Enters a span at the given level for the rest of the enclosing scope.
+macro_rules! span {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::span!(tracing::Level::$level, $($arg)+).entered();
+    };
+}
@@ -0,0 +1,389 @@
+//! Passes rewriting programs block by block.
+//!
+//! Passes are combined with `Chain` and run either over a complete program with `apply` or lazily
+//! over the blocks of a parser with `stream`, which handles files of any size:
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! # use std::io::BufReader;
+//! use gcode::parser::Parser;
+//! use gcode::toolpath::FlattenArcs;
+//! use gcode::transform::{self, Chain, Renumber, StripComments};
+//!
+//! let chain = Chain::new()
+//!         .with_pass(StripComments::new())
+//!         .with_pass(FlattenArcs::new(0.01))
+//!         .with_pass(Renumber::new(10, 10));
+//!
+//! let blocks = Parser::from_reader(BufReader::new(File::open("part.nc").unwrap()));
+//! for block in transform::stream(chain, blocks) {
+//!     println!("{}", block.unwrap());
+//! }
+//! ```
+
+use std::collections::VecDeque;
+
+use crate::Real;
+use crate::command::Command;
+use crate::geometry::Point3;
+use crate::interp::{Machine, Motion};
+use crate::parser::{Block, Comment, CommentPosition, Word};
+use crate::printer::PrinterState;
+use crate::program::Program;
+
+pub use self::arcs::FitArcs;
+pub use self::backlash::Backlash;
+pub use self::comments::StripComments;
+pub use self::compensation::CompensateCutter;
+pub use self::cycles::ExpandCycles;
+pub use self::feed::ScaleFeed;
+pub use self::leveling::{Leveling, Mesh, MeshError};
+pub use self::order::CanonicalOrder;
+pub use self::orient::OrientLoops;
+pub use self::plasma::PlasmaCut;
+pub use self::redundant::{RemoveRedundant, Savings};
+pub use self::renumber::{Renumber, StripLineNumbers};
+pub use self::simplify::Simplify;
+pub use self::skew::Skew;
+pub use self::travel::ReorderPaths;
+pub use self::units::ConvertUnits;
+
+mod arcs;
+mod backlash;
+mod comments;
+mod compensation;
+mod cycles;
+mod feed;
+pub mod golden;
+mod leveling;
+mod order;
+mod orient;
+mod plasma;
+mod redundant;
+mod renumber;
+mod simplify;
+mod skew;
+mod travel;
+mod units;
+
+/// A transformation applied to the blocks of a program one after another.
+///
+/// A pass may drop blocks, replace them by any number of other blocks or hold them back until
+/// later. Blocks created or modified by a pass should record its name in their provenance.
+pub trait Pass {
+    /// The name of the pass as recorded in the provenance of blocks.
+    fn name(&self) -> &'static str;
+
+    /// Processes the next block, pushing the resulting blocks to `output`.
+    fn process(&mut self, block: Block, output: &mut Vec<Block>);
+
+    /// Called after the last block, pushing any blocks held back to `output`.
+    fn finish(&mut self, _output: &mut Vec<Block>) {}
+}
+
+/// Runs all blocks through the pass, collecting the output into a new program.
+pub fn apply<P, I>(pass: &mut P, blocks: I) -> Program
+    where P: Pass + ?Sized,
+          I: IntoIterator<Item=Block> {
+    let mut output = Vec::new();
+
+    for block in blocks {
+        pass.process(block, &mut output);
+    }
+    pass.finish(&mut output);
+
+    return Program::from(output);
+}
+
+/// Runs the blocks of a stream through the pass lazily, only reading blocks as the output is
+/// consumed.
+///
+/// Errors of the stream, like those of `Parser::into_blocks`, are passed through in order. Blocks
+/// held back by the pass are returned after the end of the stream.
+pub fn stream<P, I, E>(pass: P, blocks: I) -> Stream<P, I::IntoIter>
+    where P: Pass,
+          I: IntoIterator<Item=Result<Block, E>> {
+    Stream {
+        pass,
+        blocks: blocks.into_iter(),
+        output: Vec::new(),
+        pending: VecDeque::new(),
+        finished: false,
+    }
+}
+
+/// The iterator returned by `stream`.
+pub struct Stream<P, I> {
+    pass: P,
+    blocks: I,
+
+    /// Buffer for the output of the pass, reused for all blocks
+    output: Vec<Block>,
+
+    /// Blocks produced by the pass, but not yet returned
+    pending: VecDeque<Block>,
+
+    finished: bool,
+}
+
+impl<P, I> Stream<P, I> {
+    pub fn pass(&self) -> &P {
+        &self.pass
+    }
+
+    pub fn into_pass(self) -> P {
+        self.pass
+    }
+}
+
+impl<P, I, E> Iterator for Stream<P, I>
+    where P: Pass,
+          I: Iterator<Item=Result<Block, E>> {
+    type Item = Result<Block, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(block) = self.pending.pop_front() {
+                return Some(Ok(block));
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            match self.blocks.next() {
+                Some(Ok(block)) => self.pass.process(block, &mut self.output),
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    self.pass.finish(&mut self.output);
+                    self.finished = true;
+                }
+            }
+
+            self.pending.extend(self.output.drain(..));
+        }
+    }
+}
+
+/// Passes run one after another, each processing the output of the one before.
+///
+/// A chain is a pass itself, so it can be used wherever a single pass can. Blocks record the names
+/// of the passes of the chain which touched them, not the chain itself.
+#[derive(Default)]
+pub struct Chain {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a pass to the end of the chain.
+    pub fn with_pass<P>(mut self, pass: P) -> Self
+        where P: Pass + 'static {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// The names of all passes in order.
+    pub fn passes(&self) -> impl Iterator<Item=&'static str> + '_ {
+        self.passes.iter().map(|pass| pass.name())
+    }
+
+    /// Runs the blocks through the passes, pushing the output of the last one to `output`.
+    fn run(passes: &mut [Box<dyn Pass>], blocks: Vec<Block>, output: &mut Vec<Block>) {
+        match passes.split_first_mut() {
+            None => output.extend(blocks),
+            Some((first, rest)) => {
+                let mut next = Vec::new();
+                for block in blocks {
+                    first.process(block, &mut next);
+                }
+
+                Self::run(rest, next, output);
+            }
+        }
+    }
+}
+
+impl Pass for Chain {
+    fn name(&self) -> &'static str {
+        "chain"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        Self::run(&mut self.passes, vec![block], output);
+    }
+
+    /// Finishes the passes in order, running the blocks held back by each through all following
+    /// passes before finishing those.
+    fn finish(&mut self, output: &mut Vec<Block>) {
+        for i in 0..self.passes.len() {
+            let (current, rest) = self.passes[i..].split_first_mut().expect("Pass in range");
+
+            let mut held = Vec::new();
+            current.finish(&mut held);
+            Self::run(rest, held, output);
+        }
+    }
+}
+
+/// Replaces a block by blocks with the given words, recording the pass in their provenance.
+///
+/// The first block keeps the line number and the comments of the replaced block, with inline
+/// comments moved to its end. Without any words, the replaced block is dropped unless it has a
+/// line number or comments to keep.
+fn replace(block: &Block, mut blocks: Vec<Vec<Word>>, pass: &'static str, output: &mut Vec<Block>) {
+    if blocks.is_empty() {
+        if block.line_number().is_none() && block.comments().is_empty() {
+            return;
+        }
+        blocks.push(Vec::new());
+    }
+
+    for (i, words) in blocks.into_iter().enumerate() {
+        let mut replaced = Block::new(words)
+                .with_provenance(block.provenance().clone())
+                .with_pass(pass);
+
+        if i == 0 {
+            replaced = replaced.with_line_number(block.line_number());
+            for comment in block.comments() {
+                let position = match comment.position() {
+                    CommentPosition::Inline(_) => CommentPosition::Trailing,
+                    position => position,
+                };
+                replaced = replaced.with_comment(Comment::new(comment.text(), comment.style(), position));
+            }
+        }
+
+        output.push(replaced);
+    }
+}
+
+/// Interprets the block, with the words of printers removed, and returns the start and the end of
+/// the straight move (G0 or G1) it makes, in machine coordinates.
+///
+/// Returns `None` for blocks without such a move, moving axes besides X, Y and Z, with expressions
+/// or which can not be interpreted. The machine and the printer state are updated regardless.
+fn straight_move(machine: &mut Machine, printer: &mut PrinterState, block: &Block) -> Option<(Point3, Point3)> {
+    let (_, reduced) = printer.apply(block);
+
+    let before = machine.state().position;
+    if !block.expressions().is_empty() || machine.execute(&reduced).is_err() {
+        return None;
+    }
+
+    let state = machine.state();
+    if !matches!(state.motion, Some(Motion::Rapid) | Some(Motion::Linear)) {
+        return None;
+    }
+
+    let straight = Command::from_block(&reduced).iter().any(|command| match command {
+        Command::RapidMove(target) | Command::LinearMove(target) => target.axes.iter().all(|(_, value)| value.is_none()),
+        Command::ModalMove(arc) => arc.axes.iter().all(|(_, value)| value.is_none()),
+        _ => false,
+    });
+
+    return if straight { Some((before, state.position)) } else { None };
+}
+
+/// Whether the block consists of nothing but a linear move along X, Y and Z, optionally extruding
+/// and setting the feed rate, which can be merged with others.
+fn is_plain_move(block: &Block) -> bool {
+    let words = block.words().iter().all(|word| match word.mnemonic() {
+        'G' => word.value() == 1.0,
+        'X' | 'Y' | 'Z' | 'E' | 'F' => true,
+        _ => false,
+    });
+
+    return words && block.comments().is_empty() && block.line_number().is_none() && !block.is_deleted()
+            && block.assignments().is_empty() && block.control().is_none() && block.argument().is_none();
+}
+
+/// Replaces the X, Y and Z words of the block, where the first of them was, keeping all others.
+fn with_axes(block: &Block, axes: &[(char, Real)]) -> Vec<Word> {
+    let position = block.words().iter().position(|word| matches!(word.mnemonic(), 'X' | 'Y' | 'Z'));
+
+    let mut words = block.words().iter()
+            .filter(|word| !matches!(word.mnemonic(), 'X' | 'Y' | 'Z'))
+            .cloned()
+            .collect::<Vec<_>>();
+
+    let position = position.unwrap_or(words.len()).min(words.len());
+    words.splice(position..position, axes.iter().map(|&(axis, value)| Word::new(axis, (value * 1e4).round() / 1e4)));
+
+    return words;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{Parser, ParserError};
+    use crate::toolpath::FlattenArcs;
+
+    fn blocks(lines: &[&str]) -> Vec<Block> {
+        Parser::new().parse_all(lines.iter()).unwrap()
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut chain = Chain::new()
+                .with_pass(StripComments::new())
+                .with_pass(FlattenArcs::new(1.0))
+                .with_pass(Renumber::new(10, 10));
+        assert_eq!(chain.passes().collect::<Vec<_>>(), vec!["strip-comments", "flatten-arcs", "renumber"]);
+
+        let program = apply(&mut chain, blocks(&["(start)", "G0 X10 ; rapid", "G2 X-10 I-10 F100", "M2"]));
+        let lines = program.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "N10 G0 X10");
+        assert!(lines[1].starts_with("N20 G1 X"));
+        assert_eq!(lines.last().unwrap(), &format!("N{} M2", 10 * lines.len()));
+        assert_eq!(program.blocks()[1].provenance().passes(), &["flatten-arcs", "renumber"]);
+    }
+
+    /// Holds back all blocks until the end of the program.
+    struct Delay(Vec<Block>);
+
+    impl Pass for Delay {
+        fn name(&self) -> &'static str {
+            "delay"
+        }
+
+        fn process(&mut self, block: Block, _output: &mut Vec<Block>) {
+            self.0.push(block);
+        }
+
+        fn finish(&mut self, output: &mut Vec<Block>) {
+            output.append(&mut self.0);
+        }
+    }
+
+    #[test]
+    fn test_chain_finish() {
+        let mut chain = Chain::new()
+                .with_pass(Delay(Vec::new()))
+                .with_pass(Renumber::new(1, 1))
+                .with_pass(Delay(Vec::new()));
+
+        let program = apply(&mut chain, blocks(&["G0 X1", "G0 X2"]));
+        assert_eq!(program.to_string(), "N1 G0 X1\nN2 G0 X2\n");
+
+        assert_eq!(apply(&mut Chain::new(), blocks(&["G0 X1"])).to_string(), "G0 X1\n");
+    }
+
+    #[test]
+    fn test_stream() {
+        let text = "(start)\nG0 X1\nG1 X§\nG0 X2\n";
+        let mut stream = stream(Chain::new().with_pass(StripComments::new()).with_pass(Renumber::new(1, 1)),
+                                Parser::new().into_blocks(text.as_bytes()));
+
+        assert_eq!(stream.next().unwrap().unwrap().to_string(), "N1 G0 X1");
+        assert!(matches!(stream.next(), Some(Err(ParserError::SyntaxError(_)))));
+        assert_eq!(stream.next().unwrap().unwrap().to_string(), "N2 G0 X2");
+        assert!(stream.next().is_none());
+        assert_eq!(stream.pass().passes().count(), 2);
+    }
+}
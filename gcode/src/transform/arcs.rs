@@ -0,0 +1,324 @@
+use crate::Real;
+use crate::command::{DistanceMode, Plane};
+use crate::geometry::{Point, Point3};
+use crate::interp::{Machine, Motion, State};
+use crate::parser::{Block, Word};
+use crate::printer::PrinterState;
+use crate::real::consts::PI;
+
+use super::{is_plain_move, straight_move, Pass};
+
+/// The fewest moves replaced by an arc.
+const MIN_SEGMENTS: usize = 3;
+
+/// The largest radius of fitted arcs in millimeters. Flatter curves are left to the lines, as
+/// the centers of such arcs are far away and sensitive to rounding.
+const MAX_RADIUS: Real = 1000.0;
+
+/// A linear move held back to be fitted.
+#[derive(Debug, Clone)]
+struct Pending {
+    block: Block,
+
+    /// The end of the move in machine coordinates
+    end: Point3,
+
+    /// The filament extruded by the move and the position of the extruder after it
+    extruded: Real,
+    extruder: Real,
+}
+
+/// Replaces runs of short feed moves lying on a circular arc with `G2` and `G3` moves, the inverse
+/// of `FlattenArcs`.
+///
+/// Runs consist of consecutive blocks with nothing but a linear move in the XY plane, optionally
+/// extruding with `E`, without comments or line numbers, like for `Simplify`. Moves are fitted
+/// greedily from the start of a run: at least three of them are replaced by an arc if all their
+/// end points and the middle of each move are within `tolerance` of it. Arcs are written with the
+/// center offset `I` and `J` and carry the filament extruded by the moves they replace. Moves
+/// which do not fit are passed through unchanged.
+#[derive(Debug, Clone)]
+pub struct FitArcs {
+    tolerance: Real,
+    machine: Machine,
+    printer: PrinterState,
+
+    /// The start of the current run, the state of the machine within it and whether extrusion
+    /// is relative
+    start: Option<(Point3, State, bool)>,
+    run: Vec<Pending>,
+}
+
+impl FitArcs {
+    /// Creates a pass keeping the arcs within `tolerance` (in millimeters) of the original path.
+    ///
+    /// Panics if `tolerance` is not positive.
+    pub fn new(tolerance: Real) -> Self {
+        assert!(tolerance > 0.0, "tolerance must be positive");
+
+        Self {
+            tolerance,
+            machine: Machine::new(),
+            printer: PrinterState::new(),
+            start: None,
+            run: Vec::new(),
+        }
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// Writes the run to `output`, replacing the moves fitting an arc.
+    fn flush(&mut self, output: &mut Vec<Block>) {
+        let run = std::mem::take(&mut self.run);
+        let (start, state, relative) = match self.start.take() {
+            Some(start) => start,
+            None => return,
+        };
+
+        let mut points = vec![Point::new(start.x, start.y)];
+        points.extend(run.iter().map(|pending| Point::new(pending.end.x, pending.end.y)));
+
+        let extruding = run.iter().any(|pending| pending.block.words().iter().any(|word| word.mnemonic() == 'E'));
+
+        let mut previous = state.to_work(start);
+        let mut run = run.into_iter().enumerate().peekable();
+        let mut i = 0;
+
+        while i < points.len() - 1 {
+            // Extend the arc as long as the moves fit it
+            let mut fitted = None;
+            let mut j = i + MIN_SEGMENTS;
+            while j < points.len() {
+                match fit(&points[i..=j], self.tolerance) {
+                    Some(arc) => fitted = Some((j, arc)),
+                    None => break,
+                }
+                j += 1;
+            }
+
+            let (j, (center, clockwise)) = match fitted {
+                Some(fitted) => fitted,
+                None => {
+                    let (_, pending) = run.next().expect("move for each point");
+                    previous = state.to_work(pending.end);
+                    output.push(pending.block);
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let mut first = None;
+            let mut extruded = 0.0;
+            let mut last = None;
+            while let Some((_, pending)) = run.next_if(|&(k, _)| k < j) {
+                extruded += pending.extruded;
+                first.get_or_insert_with(|| pending.block.clone());
+                last = Some(pending);
+            }
+            let (first, last) = (first.expect("fitted moves"), last.expect("fitted moves"));
+
+            let current = state.to_work(last.end);
+            let center = state.to_work(Point3::new(center.x, center.y, last.end.z));
+            let begin = state.to_work(Point3::new(points[i].x, points[i].y, last.end.z));
+
+            let mut words = vec![Word::new('G', if clockwise { 2.0 } else { 3.0 })];
+            for &(axis, current, previous) in &[('X', current.x, previous.x), ('Y', current.y, previous.y)] {
+                let value = match state.distance_mode {
+                    DistanceMode::Absolute => current,
+                    DistanceMode::Relative => current - previous,
+                };
+                words.push(Word::new(axis, (value * 1e4).round() / 1e4));
+            }
+            words.push(Word::new('I', ((center.x - begin.x) * 1e4).round() / 1e4));
+            words.push(Word::new('J', ((center.y - begin.y) * 1e4).round() / 1e4));
+            if extruding {
+                let e = if relative { extruded } else { last.extruder };
+                words.push(Word::new('E', (e * 1e5).round() / 1e5));
+            }
+            words.extend(first.words().iter().find(|word| word.mnemonic() == 'F').cloned());
+
+            output.push(Block::new(words)
+                    .with_provenance(first.provenance().clone())
+                    .with_pass(self.name()));
+
+            previous = current;
+            i = j;
+        }
+    }
+}
+
+/// The center and direction of an arc through all points and passing the middle of the lines
+/// between them within `tolerance`, if there is one.
+fn fit(points: &[Point], tolerance: Real) -> Option<(Point, bool)> {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let (center, radius) = circle(first, points[points.len() / 2], last)?;
+    if radius > MAX_RADIUS {
+        return None;
+    }
+
+    // The points must advance around the center in one direction, less than a full circle
+    let angle = |point: Point| (point.y - center.y).atan2(point.x - center.x);
+    let mut sweep = 0.0;
+    let mut direction = 0.0;
+    for pair in points.windows(2) {
+        let mut delta = angle(pair[1]) - angle(pair[0]);
+        if delta > PI {
+            delta -= 2.0 * PI;
+        } else if delta <= -PI {
+            delta += 2.0 * PI;
+        }
+
+        if delta == 0.0 || delta * direction < 0.0 {
+            return None;
+        }
+
+        direction = delta.signum();
+        sweep += delta;
+    }
+
+    if sweep.abs() >= 2.0 * PI - 1e-6 {
+        return None;
+    }
+
+    let on_arc = points.iter().all(|point| (point.distance(center) - radius).abs() <= tolerance);
+    let chords = points.windows(2).all(|pair| radius - pair[0].lerp(pair[1], 0.5).distance(center) <= tolerance);
+    if !on_arc || !chords {
+        return None;
+    }
+
+    return Some((center, sweep < 0.0));
+}
+
+/// The center and radius of the circle through the three points, unless they are on a line.
+fn circle(a: Point, b: Point, c: Point) -> Option<(Point, Real)> {
+    // Relative to the first point to keep precision far from the origin
+    let (bx, by) = (b.x - a.x, b.y - a.y);
+    let (cx, cy) = (c.x - a.x, c.y - a.y);
+
+    let d = 2.0 * (bx * cy - by * cx);
+    if d.abs() < 1e-12 {
+        return None;
+    }
+
+    let (b2, c2) = (bx * bx + by * by, cx * cx + cy * cy);
+    let center = Point::new(a.x + (cy * b2 - by * c2) / d, a.y + (bx * c2 - cx * b2) / d);
+    return Some((center, center.distance(a)));
+}
+
+impl Pass for FitArcs {
+    fn name(&self) -> &'static str {
+        "fit-arcs"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let extruder = self.printer.extruder;
+        let moved = straight_move(&mut self.machine, &mut self.printer, &block);
+        let extrusion = self.printer.extruder - extruder;
+        let state = self.machine.state();
+        let planar = state.motion == Some(Motion::Linear) && state.plane == Plane::XY;
+
+        match moved {
+            Some((from, to)) if is_plain_move(&block) && planar && from.z == to.z && extrusion >= 0.0 => {
+                // A feed rate or a change between extruding and travelling starts a new run
+                let feed = block.words().iter().any(|word| word.mnemonic() == 'F');
+                let continues = self.run.first().is_some_and(|first| !feed && (first.extruded > 0.0) == (extrusion > 0.0));
+
+                if !continues {
+                    self.flush(output);
+                    self.start = Some((from, self.machine.state().clone(), self.printer.relative_extrusion));
+                }
+
+                self.run.push(Pending {
+                    block,
+                    end: to,
+                    extruded: extrusion,
+                    extruder: self.printer.extruder,
+                });
+            }
+            _ => {
+                self.flush(output);
+                output.push(block);
+            }
+        }
+    }
+
+    fn finish(&mut self, output: &mut Vec<Block>) {
+        self.flush(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn fit_arcs(tolerance: Real, lines: &[String]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut FitArcs::new(tolerance), blocks).iter().map(ToString::to_string).collect();
+    }
+
+    /// Moves along the circle of radius 10 around the origin, in steps of 10 degrees.
+    fn quarter(from: usize, to: usize, extra: &str) -> Vec<String> {
+        (from..=to).map(|step| {
+            let angle = step as Real * PI / 18.0;
+            let (x, y) = ((10.0 * angle.cos() * 1e4).round() / 1e4, (10.0 * angle.sin() * 1e4).round() / 1e4);
+            format!("G1 X{} Y{}{}", x, y, extra)
+        }).collect()
+    }
+
+    #[test]
+    fn test_fit_arcs() {
+        let mut lines = vec!["G0 X10 Y0".to_owned(), "G1 F100".to_owned()];
+        lines.extend(quarter(1, 9, ""));
+        lines.push("G1 X0 Y20".to_owned());
+        lines.push("M5".to_owned());
+
+        assert_eq!(fit_arcs(0.05, &lines), vec!["G0 X10 Y0", "G1 F100", "G3 X0 Y10 I-10 J0", "G1 X0 Y20", "M5"]);
+
+        // Clockwise when going backwards
+        let mut lines = vec!["G0 X0 Y10".to_owned()];
+        lines.extend(quarter(0, 8, "").into_iter().rev());
+        lines[1].push_str(" F100");
+        assert_eq!(fit_arcs(0.05, &lines), vec!["G0 X0 Y10", "G2 X10 Y0 I0 J-10 F100"]);
+    }
+
+    #[test]
+    fn test_fit_arcs_unchanged() {
+        // Lines, too few moves and moves deviating from the arc are kept
+        let lines: Vec<String> = ["G1 X1 F100", "G1 X2", "G1 X3", "G1 X4"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(fit_arcs(0.05, &lines), lines);
+
+        let mut lines = vec!["G0 X10 Y0 F100".to_owned()];
+        lines.extend(quarter(1, 2, ""));
+        assert_eq!(fit_arcs(0.05, &lines), lines);
+
+        lines.extend(quarter(3, 6, ""));
+        lines[3] = "G1 X9 Y4".to_owned();
+        assert_eq!(fit_arcs(0.05, &lines), lines);
+    }
+
+    #[test]
+    fn test_fit_arcs_extrusion() {
+        let mut lines = vec!["M83".to_owned(), "G0 X10 Y0".to_owned(), "G1 X9.8481 Y1.7365 E0.1 F1200".to_owned()];
+        lines.extend(quarter(2, 9, " E0.1"));
+
+        assert_eq!(fit_arcs(0.05, &lines), vec!["M83", "G0 X10 Y0", "G3 X0 Y10 I-10 J0 E0.9 F1200"]);
+
+        // Relative moves are replaced by a relative arc
+        let mut lines = vec!["G0 X10 Y0".to_owned(), "G91".to_owned()];
+        let mut previous = (10.0, 0.0);
+        for step in 1..=9 {
+            let angle = step as Real * PI / 18.0;
+            let current = ((10.0 * angle.cos() * 1e4).round() / 1e4, (10.0 * angle.sin() * 1e4).round() / 1e4);
+            lines.push(format!("G1 X{} Y{}", current.0 - previous.0, current.1 - previous.1));
+            previous = current;
+        }
+        lines[2].push_str(" F100");
+        assert_eq!(fit_arcs(0.05, &lines), vec!["G0 X10 Y0", "G91", "G3 X-10 Y10 I-10 J0 F100"]);
+    }
+}
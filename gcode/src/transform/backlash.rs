@@ -0,0 +1,165 @@
+use crate::Real;
+use crate::command::DistanceMode;
+use crate::geometry::Point3;
+use crate::interp::{Machine, Motion};
+use crate::parser::{Block, Word};
+use crate::printer::PrinterState;
+
+use super::{replace, straight_move, with_axes, Pass};
+
+/// Compensates the backlash of the X, Y and Z axes, for machines whose firmware can not do so.
+///
+/// Whenever an axis reverses its direction, a move taking up the backlash of the axis in the new
+/// direction is inserted before the move, and all following positions of the axis are shifted by
+/// the backlash until it reverses again. The first move of each axis is assumed to have taken up
+/// the backlash already, as after homing.
+///
+/// Only straight moves are considered. Arcs are passed through unchanged, so they should be
+/// flattened before. Blocks which can not be interpreted are passed through unchanged as well.
+#[derive(Debug, Clone)]
+pub struct Backlash {
+    backlash: [Real; 3],
+
+    /// The direction of the last move of each axis, or zero if not moved yet
+    direction: [Real; 3],
+
+    /// The shift of the positions of each axis
+    correction: [Real; 3],
+
+    machine: Machine,
+    printer: PrinterState,
+}
+
+impl Backlash {
+    /// Creates a pass compensating the given backlash of each axis, in millimeters.
+    pub fn new(backlash: Point3) -> Self {
+        Self {
+            backlash: [backlash.x, backlash.y, backlash.z],
+            direction: [0.0; 3],
+            correction: [0.0; 3],
+            machine: Machine::new(),
+            printer: PrinterState::new(),
+        }
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+}
+
+fn shifted(point: Point3, correction: [Real; 3]) -> Point3 {
+    Point3::new(point.x + correction[0], point.y + correction[1], point.z + correction[2])
+}
+
+impl Pass for Backlash {
+    fn name(&self) -> &'static str {
+        "backlash"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let (from, to) = match straight_move(&mut self.machine, &mut self.printer, &block) {
+            Some(found) => found,
+            None => {
+                output.push(block);
+                return;
+            }
+        };
+
+        let previous = self.correction;
+        let deltas = [to.x - from.x, to.y - from.y, to.z - from.z];
+        for (axis, delta) in deltas.iter().enumerate() {
+            if delta.abs() < 1e-9 {
+                continue;
+            }
+
+            let direction = delta.signum();
+            if self.direction[axis] != 0.0 && self.direction[axis] != direction {
+                self.correction[axis] += direction * self.backlash[axis];
+            }
+            self.direction[axis] = direction;
+        }
+
+        if self.correction == previous && self.correction == [0.0; 3] {
+            output.push(block);
+            return;
+        }
+
+        let state = self.machine.state();
+        let letters = ['X', 'Y', 'Z'];
+        let mut blocks = Vec::new();
+
+        if self.correction != previous {
+            let start = state.to_work(shifted(from, previous));
+            let end = state.to_work(shifted(from, self.correction));
+
+            let code = if state.motion == Some(Motion::Rapid) { 0.0 } else { 1.0 };
+            let mut words = vec![Word::new('G', code)];
+            for (axis, (start, end)) in [(start.x, end.x), (start.y, end.y), (start.z, end.z)].iter().enumerate() {
+                if self.correction[axis] != previous[axis] {
+                    let value = match state.distance_mode {
+                        DistanceMode::Absolute => *end,
+                        DistanceMode::Relative => end - start,
+                    };
+                    words.push(Word::new(letters[axis], (value * 1e4).round() / 1e4));
+                }
+            }
+            words.extend(block.words().iter().find(|word| word.mnemonic() == 'F').cloned());
+            blocks.push(words);
+        }
+
+        // Relative moves keep their distances, as the correction is taken up before
+        blocks.push(match state.distance_mode {
+            DistanceMode::Absolute => {
+                let end = state.to_work(shifted(to, self.correction));
+                let axes = [('X', end.x), ('Y', end.y), ('Z', end.z)].iter()
+                        .filter(|(axis, _)| block.words().iter().any(|word| word.mnemonic() == *axis))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                with_axes(&block, &axes)
+            }
+            DistanceMode::Relative => block.words().to_vec(),
+        });
+
+        let mut replaced = Vec::new();
+        replace(&block, blocks, self.name(), &mut replaced);
+        output.extend(replaced.into_iter().map(|replaced| replaced.with_deleted(block.is_deleted())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn backlash(lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut Backlash::new(Point3::new(0.1, 0.2, 0.0)), blocks).iter().map(ToString::to_string).collect();
+    }
+
+    #[test]
+    fn test_backlash() {
+        assert_eq!(backlash(&["G0 X10 Y10", "G1 X20 F100", "N5 G1 X5 Y5 ; back", "G1 Y8", "G1 X30 Z-1"]), vec![
+            "G0 X10 Y10",
+            "G1 X20 F100",
+            "N5 G1 X19.9 Y9.8 ; back",
+            "G1 X4.9 Y4.8",
+            "G1 Y5",
+            "G1 Y8",
+            "G1 X5",
+            "G1 X30 Z-1",
+        ]);
+    }
+
+    #[test]
+    fn test_backlash_relative() {
+        assert_eq!(backlash(&["G91 G0 X10", "G0 X-5", "G0 X-5"]), vec![
+            "G91 G0 X10",
+            "G0 X-0.1",
+            "G0 X-5",
+            "G0 X-5",
+        ]);
+    }
+}
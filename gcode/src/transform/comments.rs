@@ -0,0 +1,51 @@
+use crate::parser::Block;
+
+use super::Pass;
+
+/// Removes all comments from the program.
+///
+/// Blocks only consisting of comments are dropped, while blank lines are kept.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StripComments;
+
+impl StripComments {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Pass for StripComments {
+    fn name(&self) -> &'static str {
+        "strip-comments"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        if block.comments().is_empty() {
+            output.push(block);
+            return;
+        }
+
+        if block.is_empty() && block.line_number().is_none() && !block.is_deleted() && block.argument().is_none() {
+            return;
+        }
+
+        output.push(block.without_comments().with_pass(self.name()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    #[test]
+    fn test_strip_comments() {
+        let blocks = Parser::new().parse_all(["(header)", "G0 X1 (rapid) Y2 ; done", "", "N10 (keep the line)", "M2"].iter()).unwrap();
+        let program = apply(&mut StripComments::new(), blocks);
+
+        assert_eq!(program.to_string(), "G0 X1 Y2\n\nN10\nM2\n");
+        assert_eq!(program.blocks()[0].provenance().passes(), &["strip-comments"]);
+        assert!(program.blocks()[3].provenance().passes().is_empty());
+    }
+}
@@ -0,0 +1,507 @@
+use crate::{Real, EPSILON};
+use crate::command::{Command, CompensationDiameter, DistanceMode, Plane, Side};
+use crate::geometry::Point3;
+use crate::interp::{Machine, Motion, State};
+use crate::parser::{Block, Word};
+use crate::tools::ToolTable;
+
+use super::{replace, Pass};
+
+/// A move of the tool in the XY plane, in machine coordinates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Element {
+    Line {
+        rapid: bool,
+        from: Point3,
+        to: Point3,
+    },
+    Arc {
+        clockwise: bool,
+        center: (Real, Real),
+        from: Point3,
+        to: Point3,
+    },
+}
+
+impl Element {
+    fn from(&self) -> Point3 {
+        match *self {
+            Element::Line { from, .. } | Element::Arc { from, .. } => from,
+        }
+    }
+
+    fn to(&self) -> Point3 {
+        match *self {
+            Element::Line { to, .. } | Element::Arc { to, .. } => to,
+        }
+    }
+
+    fn is_rapid(&self) -> bool {
+        matches!(self, Element::Line { rapid: true, .. })
+    }
+
+    fn with_ends(self, start: Point3, end: Point3) -> Self {
+        match self {
+            Element::Line { rapid, .. } => Element::Line { rapid, from: start, to: end },
+            Element::Arc { clockwise, center, .. } => Element::Arc { clockwise, center, from: start, to: end },
+        }
+    }
+
+    /// The direction of motion at a point of the element, as unit vector.
+    fn tangent(&self, at: Point3) -> (Real, Real) {
+        let (x, y) = match *self {
+            Element::Line { from, to, .. } => (to.x - from.x, to.y - from.y),
+            Element::Arc { clockwise: true, center, .. } => (at.y - center.1, center.0 - at.x),
+            Element::Arc { clockwise: false, center, .. } => (center.1 - at.y, at.x - center.0),
+        };
+
+        let length = x.hypot(y);
+        return (x / length, y / length);
+    }
+
+    /// The element offset by `distance` to its left, or to its right for negative distances.
+    ///
+    /// Arcs shrinking to nothing are replaced by a line between their offset end points.
+    fn offset(&self, distance: Real) -> Element {
+        let shift = |point: Point3| {
+            let (x, y) = self.tangent(point);
+            Point3::new(point.x - y * distance, point.y + x * distance, point.z)
+        };
+
+        let (from, to) = (shift(self.from()), shift(self.to()));
+        if let Element::Arc { center, .. } = *self {
+            if (from.x - center.0).hypot(from.y - center.1) < EPSILON || self.radius() + self.bend() * distance < EPSILON {
+                return Element::Line { rapid: false, from, to };
+            }
+        }
+
+        return self.with_ends(from, to);
+    }
+
+    fn radius(&self) -> Real {
+        match *self {
+            Element::Line { .. } => Real::INFINITY,
+            Element::Arc { center, from, .. } => (from.x - center.0).hypot(from.y - center.1),
+        }
+    }
+
+    /// Whether offsetting to the left grows (1) or shrinks (-1) an arc.
+    fn bend(&self) -> Real {
+        match *self {
+            Element::Arc { clockwise: false, .. } => -1.0,
+            _ => 1.0,
+        }
+    }
+}
+
+/// The intersections of the lines or circles the elements are part of.
+fn intersections(a: &Element, b: &Element) -> Vec<(Real, Real)> {
+    fn line_circle(from: Point3, to: Point3, center: (Real, Real), radius: Real) -> Vec<(Real, Real)> {
+        let (dx, dy) = (to.x - from.x, to.y - from.y);
+        let (fx, fy) = (from.x - center.0, from.y - center.1);
+
+        let a = dx * dx + dy * dy;
+        let b = 2.0 * (fx * dx + fy * dy);
+        let c = fx * fx + fy * fy - radius * radius;
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 || a < EPSILON {
+            return Vec::new();
+        }
+
+        return [-1.0, 1.0].iter()
+                .map(|sign| (-b + sign * discriminant.sqrt()) / (2.0 * a))
+                .map(|t| (from.x + dx * t, from.y + dy * t))
+                .collect();
+    }
+
+    return match (*a, *b) {
+        (Element::Line { from: a1, to: a2, .. }, Element::Line { from: b1, to: b2, .. }) => {
+            let (dax, day) = (a2.x - a1.x, a2.y - a1.y);
+            let (dbx, dby) = (b2.x - b1.x, b2.y - b1.y);
+
+            let denominator = dax * dby - day * dbx;
+            if denominator.abs() < EPSILON {
+                return Vec::new();
+            }
+
+            let t = ((b1.x - a1.x) * dby - (b1.y - a1.y) * dbx) / denominator;
+            vec![(a1.x + dax * t, a1.y + day * t)]
+        }
+
+        (Element::Line { from, to, .. }, arc @ Element::Arc { center, .. })
+        | (arc @ Element::Arc { center, .. }, Element::Line { from, to, .. }) => line_circle(from, to, center, arc.radius()),
+
+        (Element::Arc { center: c1, .. }, Element::Arc { center: c2, .. }) => {
+            let (r1, r2) = (a.radius(), b.radius());
+            let (dx, dy) = (c2.0 - c1.0, c2.1 - c1.1);
+            let distance = dx.hypot(dy);
+            if distance < EPSILON || distance > r1 + r2 || distance < (r1 - r2).abs() {
+                return Vec::new();
+            }
+
+            let along = (r1 * r1 - r2 * r2 + distance * distance) / (2.0 * distance);
+            let height = (r1 * r1 - along * along).max(0.0).sqrt();
+            let (mx, my) = (c1.0 + dx * along / distance, c1.1 + dy * along / distance);
+
+            vec![(mx - dy * height / distance, my + dx * height / distance),
+                 (mx + dy * height / distance, my - dx * height / distance)]
+        }
+    };
+}
+
+/// A block executed while cutter compensation is active.
+#[derive(Debug, Clone)]
+struct Entry {
+    block: Block,
+
+    /// The state after executing the block
+    state: State,
+
+    element: Option<Element>,
+}
+
+/// The blocks since compensation was started.
+#[derive(Debug, Clone)]
+struct Chain {
+    /// The distance to offset by, positive to the left
+    offset: Real,
+
+    entries: Vec<Entry>,
+    cancelled: bool,
+}
+
+/// Applies cutter radius compensation (G41, G42) to a program, for controllers which do not
+/// support it.
+///
+/// While compensation is active, the moves in the XY plane are offset by the tool radius to the
+/// left (G41) or right (G42) of the programmed path. The radius is taken from the tool table for
+/// the tool given by `D`, or the tool in the spindle, or from the diameter given by `D` for G41.1
+/// and G42.1. Offset moves meeting at outside corners are joined by arcs around the corner, while
+/// inside corners are trimmed to the intersection of the moves.
+///
+/// The first move after starting compensation is the entry move: it starts at the programmed
+/// position and ends offset perpendicular to itself. Likewise, the first move after G40 is the
+/// exit move, which ends at the programmed position again. The compensation words are removed.
+/// Compensation for unknown tools or outside of the XY plane is left to the controller.
+#[derive(Debug, Clone)]
+pub struct CompensateCutter {
+    tools: ToolTable,
+    machine: Machine,
+    chain: Option<Chain>,
+}
+
+impl CompensateCutter {
+    pub fn new(tools: ToolTable) -> Self {
+        Self {
+            machine: Machine::new().with_tools(tools.clone()),
+            tools,
+            chain: None,
+        }
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// The XY move of a block, given the states before and after it.
+    fn element(before: &State, after: &State, commands: &[Command]) -> Option<Element> {
+        let (motion, arc) = commands.iter().find_map(|command| match *command {
+            Command::RapidMove(_) => Some((Motion::Rapid, None)),
+            Command::LinearMove(_) => Some((Motion::Linear, None)),
+            Command::ClockwiseArc(arc) => Some((Motion::ClockwiseArc, Some(arc))),
+            Command::CounterClockwiseArc(arc) => Some((Motion::CounterClockwiseArc, Some(arc))),
+            Command::ModalMove(arc) => after.motion.map(|motion| (motion, Some(arc))),
+            _ => None,
+        })?;
+
+        let (from, to) = (before.position, after.position);
+        return match (motion, arc) {
+            (Motion::ClockwiseArc, Some(arc)) | (Motion::CounterClockwiseArc, Some(arc)) => {
+                // The arc starts at the previous position, but with all modes of the block applied
+                let mut start = after.clone();
+                start.position = from;
+
+                let clockwise = motion == Motion::ClockwiseArc;
+                let segment = start.arc(clockwise, &arc).ok()?;
+                let center = (segment.center().x, segment.center().y);
+
+                Some(Element::Arc { clockwise, center, from, to })
+            }
+
+            _ if from.xy().distance(to.xy()) < EPSILON => None,
+            (motion, _) => Some(Element::Line { rapid: motion == Motion::Rapid, from, to }),
+        };
+    }
+
+    /// Writes the blocks of the chain with the offset moves, ending in the exit move if `exit`.
+    fn flush(&mut self, exit: bool, output: &mut Vec<Block>) {
+        let chain = match self.chain.take() {
+            Some(chain) => chain,
+            None => return,
+        };
+
+        let elements = chain.entries.iter().filter_map(|entry| entry.element).collect::<Vec<_>>();
+        let count = elements.len();
+        let compensated = if exit { count.saturating_sub(1) } else { count };
+
+        let mut offsets = elements.iter().map(|element| element.offset(chain.offset)).collect::<Vec<_>>();
+        let mut joins = vec![None; count];
+
+        for i in 1..compensated {
+            let (a, b) = (elements[i - 1], elements[i]);
+            let (ta, tb) = (a.tangent(a.to()), b.tangent(b.from()));
+            let turn = ta.0 * tb.1 - ta.1 * tb.0;
+            let straight = ta.0 * tb.0 + ta.1 * tb.1 > 0.0;
+
+            let (end, start) = (offsets[i - 1].to(), offsets[i].from());
+            let vertex = a.to();
+
+            if turn * chain.offset < -EPSILON || (turn.abs() <= EPSILON && !straight) {
+                // Around outside corners, the tool keeps touching the corner
+                joins[i] = Some(match a.is_rapid() || b.is_rapid() {
+                    true => Element::Line { rapid: true, from: end, to: start },
+                    false => Element::Arc { clockwise: chain.offset > 0.0, center: (vertex.x, vertex.y), from: end, to: start },
+                });
+            } else if turn.abs() > EPSILON {
+                let corner = intersections(&offsets[i - 1], &offsets[i]).into_iter()
+                        .min_by(|p, q| (p.0 - vertex.x).hypot(p.1 - vertex.y)
+                                .partial_cmp(&(q.0 - vertex.x).hypot(q.1 - vertex.y))
+                                .expect("NaN in corner"));
+
+                match corner {
+                    Some((x, y)) => {
+                        offsets[i - 1] = offsets[i - 1].with_ends(offsets[i - 1].from(), Point3::new(x, y, end.z));
+                        offsets[i] = offsets[i].with_ends(Point3::new(x, y, start.z), offsets[i].to());
+                    }
+                    None => joins[i] = Some(Element::Line { rapid: b.is_rapid(), from: end, to: start }),
+                }
+            }
+        }
+
+        if count > 0 {
+            offsets[0] = Element::Line { rapid: elements[0].is_rapid(), from: elements[0].from(), to: offsets[0].to() };
+        }
+        if exit && count > 0 {
+            let from = if count > 1 { offsets[count - 2].to() } else { elements[0].from() };
+            offsets[count - 1] = Element::Line { rapid: elements[count - 1].is_rapid(), from, to: elements[count - 1].to() };
+        }
+
+        let mut index = 0;
+        for entry in chain.entries {
+            let commands = Command::from_block(&entry.block).into_iter()
+                    .filter(|command| !matches!(command, Command::Compensation { .. } | Command::CancelCompensation))
+                    .collect::<Vec<_>>();
+
+            if entry.element.is_none() {
+                let words = Command::to_block(&commands).words().to_vec();
+                replace(&entry.block, if words.is_empty() { Vec::new() } else { vec![words] }, self.name(), output);
+                continue;
+            }
+
+            let motion = |command: &Command| matches!(command,
+                    Command::RapidMove(_) | Command::LinearMove(_) | Command::ClockwiseArc(_)
+                    | Command::CounterClockwiseArc(_) | Command::ModalMove(_));
+            let mut feed = commands.iter().find_map(|command| match *command {
+                Command::RapidMove(target) | Command::LinearMove(target) => target.f,
+                Command::ClockwiseArc(arc) | Command::CounterClockwiseArc(arc) | Command::ModalMove(arc) => arc.f,
+                _ => None,
+            });
+
+            let others = commands.iter().filter(|command| !motion(command)).cloned().collect::<Vec<_>>();
+            let mut blocks = Vec::new();
+            if !others.is_empty() {
+                blocks.push(Command::to_block(&others).words().to_vec());
+            }
+
+            for element in joins[index].iter().chain(Some(&offsets[index])) {
+                blocks.push(words(element, &entry.state, feed.take()));
+            }
+            index += 1;
+
+            replace(&entry.block, blocks, self.name(), output);
+        }
+    }
+}
+
+/// The words of a move along the element, in the work coordinates and modes of the state.
+fn words(element: &Element, state: &State, feed: Option<Real>) -> Vec<Word> {
+    // Coordinates are rounded to the precision of the output, so relative moves do not drift
+    let round = |value: Real| (value * 1e4).round() / 1e4;
+    let scale = state.units.millimeters();
+
+    let (from, to) = (element.from(), element.to());
+    let (start, end) = (state.to_work(from), state.to_work(to));
+    let target = match state.distance_mode {
+        DistanceMode::Absolute => end,
+        DistanceMode::Relative => Point3::new(end.x - start.x, end.y - start.y, end.z - start.z),
+    };
+
+    let code = match *element {
+        Element::Line { rapid: true, .. } => 0.0,
+        Element::Line { rapid: false, .. } => 1.0,
+        Element::Arc { clockwise: true, .. } => 2.0,
+        Element::Arc { clockwise: false, .. } => 3.0,
+    };
+
+    let mut words = vec![Word::new('G', code), Word::new('X', round(target.x)), Word::new('Y', round(target.y))];
+    if (to.z - from.z).abs() > EPSILON {
+        words.push(Word::new('Z', round(target.z)));
+    }
+    if let Element::Arc { center, .. } = *element {
+        words.push(Word::new('I', round((center.0 - from.x) / scale)));
+        words.push(Word::new('J', round((center.1 - from.y) / scale)));
+    }
+    words.extend(feed.map(|f| Word::new('F', f)));
+
+    return words;
+}
+
+impl Pass for CompensateCutter {
+    fn name(&self) -> &'static str {
+        "compensate-cutter"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let before = self.machine.state().clone();
+        if self.machine.execute(&block).is_err() {
+            self.flush(false, output);
+            output.push(block);
+            return;
+        }
+
+        let state = self.machine.state().clone();
+        let commands = Command::from_block(&block);
+
+        let start = commands.iter().find_map(|command| match *command {
+            Command::Compensation { side, diameter } => Some((side, diameter)),
+            _ => None,
+        });
+        if let Some((side, diameter)) = start {
+            self.flush(false, output);
+
+            let radius = match diameter {
+                CompensationDiameter::Tool(number) => number.or(state.tool)
+                        .and_then(|number| self.tools.get(number))
+                        .map(|tool| tool.radius()),
+                CompensationDiameter::Given(diameter) => Some(diameter / 2.0),
+            };
+
+            match radius {
+                Some(radius) if state.plane == Plane::XY => {
+                    self.chain = Some(Chain {
+                        offset: if side == Side::Left { radius } else { -radius },
+                        entries: Vec::new(),
+                        cancelled: false,
+                    });
+                }
+                _ => {
+                    output.push(block);
+                    return;
+                }
+            }
+        }
+
+        let element = Self::element(&before, &state, &commands);
+        let chain = match self.chain.as_mut() {
+            Some(chain) => chain,
+            None => {
+                output.push(block);
+                return;
+            }
+        };
+
+        chain.cancelled |= commands.iter().any(|command| matches!(command, Command::CancelCompensation));
+        chain.entries.push(Entry { block, state, element });
+
+        if chain.cancelled && element.is_some() {
+            self.flush(true, output);
+        }
+    }
+
+    fn finish(&mut self, output: &mut Vec<Block>) {
+        self.flush(false, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::tools::Tool;
+    use crate::transform::apply;
+
+    fn compensate(lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        let mut pass = CompensateCutter::new(ToolTable::new().with_tool(1, Tool::new(2.0)));
+
+        return apply(&mut pass, blocks).iter().map(|block| block.to_string()).collect();
+    }
+
+    #[test]
+    fn test_inside_corner() {
+        assert_eq!(compensate(&[
+            "G0 X-5 Y0 Z1",
+            "T1 M6",
+            "G41",
+            "G1 X0 Y0 F100",
+            "X10",
+            "Y10",
+            "G40 G1 X15 Y10",
+            "M2",
+        ]), vec![
+            "G0 X-5 Y0 Z1",
+            "T1 M6",
+            "G1 X0 Y1 F100",
+            "G1 X9 Y1",
+            "G1 X9 Y10",
+            "G1 X15 Y10",
+            "M2",
+        ]);
+    }
+
+    #[test]
+    fn test_outside_corner() {
+        assert_eq!(compensate(&[
+            "G0 X-5 Y0 Z1",
+            "G42 D1 G1 X0 Y0 F100",
+            "G1 Z-1",
+            "G1 X10",
+            "G1 Y10",
+            "G40",
+            "G0 X15",
+        ]), vec![
+            "G0 X-5 Y0 Z1",
+            "G1 X0 Y-1 F100",
+            "G1 Z-1",
+            "G1 X10 Y-1",
+            "G3 X11 Y0 I0 J1",
+            "G1 X11 Y10",
+            "G0 X15 Y10",
+        ]);
+    }
+
+    #[test]
+    fn test_arcs() {
+        // A quarter circle cut from inside with a 1 mm tool radius, entered around a corner
+        assert_eq!(compensate(&[
+            "G0 X0 Y-5",
+            "G41.1 D2",
+            "G1 X0 Y0 F100",
+            "G3 X-10 Y10 I0 J10",
+            "G40 G1 X-10 Y15",
+        ]), vec![
+            "G0 X0 Y-5",
+            "G1 X-1 Y0 F100",
+            "G2 X0 Y1 I1 J0",
+            "G3 X-9 Y10 I0 J9",
+            "G1 X-10 Y15",
+        ]);
+    }
+
+    #[test]
+    fn test_unknown_tool() {
+        assert_eq!(compensate(&["G41 D5", "G1 X1", "G40"]), vec!["G41 D5", "G1 X1", "G40"]);
+    }
+}
@@ -0,0 +1,454 @@
+use crate::Real;
+use crate::command::{Command, CycleKind, DistanceMode, ReturnMode};
+use crate::geometry::Units;
+use crate::parser::{Block, Word};
+
+use super::{replace, Pass};
+
+/// Lowers canned cycles (G81 to G89) into plain rapid and feed moves, for controllers which do
+/// not support them.
+///
+/// The hole depth `Z`, retract plane `R`, peck depth `Q` and dwell `P` are sticky: blocks
+/// repeating a cycle only need to give the parameters which change, usually just the position
+/// of the next hole. Cycles are repeated `L` times, which in incremental mode (G91) drills a row
+/// of holes. After each hole the tool retracts to the height before the cycle started (G98) or
+/// to the retract plane (G99).
+///
+/// Other words of a block starting a cycle are written in a block of their own before the moves.
+/// G80, G98 and G99 are removed. The pass needs to know the height of the tool before the first
+/// hole, so it tracks the position through the moves of the program. Back boring (G87) and cycles
+/// without a known depth or retract plane are left as they are, along with the blocks repeating
+/// them and the G80, G98 and G99 following them, as the controller runs them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpandCycles {
+    units: Units,
+    distance_mode: DistanceMode,
+    return_mode: ReturnMode,
+
+    /// The position of the tool, if known
+    position: [Option<Real>; 3],
+
+    /// The spindle direction to restore after cycles stopping the spindle
+    clockwise: bool,
+
+    /// The active cycle, with the height before its first hole
+    active: Option<(CycleKind, Option<Real>)>,
+
+    /// Whether a cycle left as it is is active, which the controller runs
+    passed: bool,
+
+    /// The parameters `Z`, `R`, `Q` and `P` of the previous cycle blocks
+    sticky: [Option<Real>; 4],
+}
+
+/// The distance pecks retract above the bottom of the previous peck, in millimeters.
+const PECK_CLEARANCE: Real = 0.254;
+
+impl ExpandCycles {
+    /// Creates the pass, assuming the program starts in millimeters and absolute mode.
+    pub fn new() -> Self {
+        Self {
+            units: Units::Millimeters,
+            distance_mode: DistanceMode::Absolute,
+            return_mode: ReturnMode::Initial,
+            position: [None; 3],
+            clockwise: true,
+            active: None,
+            passed: false,
+            sticky: [None; 4],
+        }
+    }
+
+    /// Sets the units active before the first block of the program.
+    pub fn initial(mut self, units: Units) -> Self {
+        self.units = units;
+        self
+    }
+
+    fn is_cycle(&self, command: &Command) -> bool {
+        return match command {
+            Command::Cycle(..) | Command::CancelCycle | Command::SetReturnMode(_) => true,
+            Command::ModalMove(_) => self.active.is_some(),
+
+            // The sticky parameters of repeated cycles are no parameters of any other command
+            Command::Unknown(word) => self.active.is_some() && "QPL".contains(word.mnemonic()),
+            _ => false,
+        };
+    }
+
+    /// Updates the tracked state by commands which are not part of a cycle.
+    fn track(&mut self, commands: &[Command]) {
+        for command in commands {
+            match *command {
+                Command::SetUnits(units) => self.units = units,
+                Command::SetDistanceMode(mode) => self.distance_mode = mode,
+                Command::SpindleOn { clockwise, .. } => self.clockwise = clockwise,
+
+                Command::RapidMove(target) | Command::LinearMove(target) | Command::SynchronizedMove { target, .. } => {
+                    self.active = None;
+                    self.passed = false;
+                    self.move_to([target.x, target.y, target.z]);
+                }
+                Command::ClockwiseArc(arc) | Command::CounterClockwiseArc(arc) => {
+                    self.active = None;
+                    self.passed = false;
+                    self.move_to([arc.x, arc.y, arc.z]);
+                }
+                Command::ModalMove(arc) => {
+                    self.active = None;
+                    self.move_to([arc.x, arc.y, arc.z]);
+
+                    // The controller drills another hole, retracting to a height not tracked
+                    if self.passed {
+                        self.position[2] = None;
+                    }
+                }
+
+                // Only left for cycles the controller runs
+                Command::CancelCycle => self.passed = false,
+                Command::SetReturnMode(mode) => self.return_mode = mode,
+
+                Command::SetPosition(axes) => {
+                    for (position, value) in self.position.iter_mut().zip(&[axes.x, axes.y, axes.z]) {
+                        *position = value.or(*position);
+                    }
+                }
+                Command::GoToPredefined(..) | Command::Probe(..) | Command::SelectCoordinateSystem(_) | Command::SetWorkOffset { .. }
+                | Command::ClearPositionOffset | Command::SuspendPositionOffset | Command::RestorePositionOffset
+                | Command::SetLatheMode(_) => self.position = [None; 3],
+
+                Command::ProgramEnd { .. } => {
+                    self.distance_mode = DistanceMode::Absolute;
+                    self.active = None;
+                    self.passed = false;
+                }
+
+                _ => {}
+            }
+        }
+    }
+
+    fn move_to(&mut self, target: [Option<Real>; 3]) {
+        for (position, value) in self.position.iter_mut().zip(&target) {
+            *position = match (self.distance_mode, *value) {
+                (_, None) => *position,
+                (DistanceMode::Absolute, value) => value,
+                (DistanceMode::Relative, Some(offset)) => position.map(|position| position + offset),
+            };
+        }
+    }
+
+    /// The moves of a cycle block, or `None` if the cycle can not be expanded.
+    fn expand(&mut self, kind: CycleKind, block: &Block) -> Option<Vec<Vec<Word>>> {
+        let value = |mnemonic: char| block.words().iter()
+                .find(|word| word.mnemonic() == mnemonic)
+                .map(|word| word.value());
+
+        let mut sticky = self.sticky;
+        for (parameter, mnemonic) in sticky.iter_mut().zip(&['Z', 'R', 'Q', 'P']) {
+            *parameter = value(*mnemonic).or(*parameter);
+        }
+
+        let (depth, retract) = match (kind, sticky[0], sticky[1]) {
+            (CycleKind::BackBore, _, _) => return None,
+            (_, Some(depth), Some(retract)) => (depth, retract),
+            _ => return None,
+        };
+
+        let initial = self.active.and_then(|(_, initial)| initial).or(self.position[2]);
+
+        // In incremental mode, the retract plane is relative to the height of the tool and the
+        // depth relative to the retract plane
+        let relative = self.distance_mode == DistanceMode::Relative;
+        let (retract, bottom) = match relative {
+            false => (retract, depth),
+            true => {
+                let retract = self.position[2]? + retract;
+                (retract, retract + depth)
+            }
+        };
+
+        // Holes relative to an unknown position can not be written in absolute coordinates
+        if relative && [value('X'), value('Y')].iter().zip(&self.position).any(|(value, position)| value.is_some() && position.is_none()) {
+            return None;
+        }
+
+        let clear = match (self.return_mode, initial) {
+            (ReturnMode::Initial, Some(initial)) => initial.max(retract),
+            _ => retract,
+        };
+
+        self.sticky = sticky;
+        self.active = Some((kind, initial));
+
+        let clearance = PECK_CLEARANCE / self.units.millimeters();
+        let peck = sticky[2].filter(|&q| q > 0.0);
+        let dwell = sticky[3];
+        let spindle = Word::new('M', if self.clockwise { 3.0 } else { 4.0 });
+
+        let rapid = |z: Real| vec![Word::new('G', 0.0), Word::new('Z', z)];
+        let feed = |z: Real| vec![Word::new('G', 1.0), Word::new('Z', z)];
+
+        // The moves are written in absolute coordinates
+        let mut moves = Vec::new();
+        if relative {
+            moves.push(vec![Word::new('G', 90.0)]);
+        }
+
+        let repeats = value('L').map_or(1, |l| l.max(0.0) as usize);
+        for _ in 0..repeats {
+            if self.position[2].is_none_or(|z| z < retract) {
+                moves.push(rapid(retract));
+            }
+
+            self.move_to([value('X'), value('Y'), None]);
+            let position = [('X', self.position[0]), ('Y', self.position[1])].iter()
+                    .filter(|(mnemonic, _)| value(*mnemonic).is_some())
+                    .filter_map(|&(mnemonic, value)| value.map(|value| Word::new(mnemonic, value)))
+                    .collect::<Vec<_>>();
+            if !position.is_empty() {
+                moves.push([vec![Word::new('G', 0.0)], position].concat());
+            }
+            moves.push(rapid(retract));
+
+            let first = moves.len();
+            match kind {
+                CycleKind::PeckDrill if peck.is_some() => {
+                    let mut z = retract;
+                    while z > bottom {
+                        if z < retract {
+                            moves.push(rapid(z + clearance));
+                        }
+                        z = (z - peck.expect("Peck depth")).max(bottom);
+                        moves.push(feed(z));
+                        moves.push(rapid(retract));
+                    }
+                }
+
+                CycleKind::Drill | CycleKind::DrillDwell | CycleKind::PeckDrill => {
+                    moves.push(feed(bottom));
+                    if kind == CycleKind::DrillDwell {
+                        moves.extend(dwell.map(|p| vec![Word::new('G', 4.0), Word::new('P', p)]));
+                    }
+                    moves.push(rapid(retract));
+                }
+
+                CycleKind::Tap => {
+                    moves.push(feed(bottom));
+                    moves.push(vec![Word::new('M', if self.clockwise { 4.0 } else { 3.0 })]);
+                    moves.push(feed(retract));
+                    moves.push(vec![spindle]);
+                }
+
+                CycleKind::Bore | CycleKind::BoreDwell => {
+                    moves.push(feed(bottom));
+                    if kind == CycleKind::BoreDwell {
+                        moves.extend(dwell.map(|p| vec![Word::new('G', 4.0), Word::new('P', p)]));
+                    }
+                    moves.push(feed(retract));
+                }
+
+                CycleKind::BoreSpindleStop | CycleKind::BoreManual => {
+                    moves.push(feed(bottom));
+                    moves.extend(dwell.map(|p| vec![Word::new('G', 4.0), Word::new('P', p)]));
+                    moves.push(vec![Word::new('M', 5.0)]);
+
+                    // The operator retracts the tool by hand before resuming
+                    if kind == CycleKind::BoreManual {
+                        moves.push(vec![Word::new('M', 0.0)]);
+                    }
+
+                    moves.push(rapid(retract));
+                    moves.push(vec![spindle]);
+                }
+
+                CycleKind::BackBore => unreachable!(),
+            }
+
+            if let Some(f) = value('F') {
+                moves[first].push(Word::new('F', f));
+            }
+
+            if clear > retract {
+                moves.push(rapid(clear));
+            }
+
+            self.position[2] = Some(clear);
+        }
+
+        if relative {
+            moves.push(vec![Word::new('G', 91.0)]);
+        }
+
+        return Some(moves);
+    }
+}
+
+impl Default for ExpandCycles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pass for ExpandCycles {
+    fn name(&self) -> &'static str {
+        "expand-cycles"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let commands = Command::from_block(&block);
+        let starts = commands.iter().any(|command| matches!(command, Command::Cycle(..)));
+        if block.is_deleted() || !commands.iter().any(|command| self.is_cycle(command)) || (self.passed && !starts) {
+            self.track(&commands);
+            output.push(block);
+            return;
+        }
+
+        let (cycle, other): (Vec<_>, Vec<_>) = commands.into_iter()
+                .partition(|command| self.is_cycle(command));
+
+        self.track(&other);
+        let mut blocks = Vec::new();
+        if !other.is_empty() {
+            blocks.push(Command::to_block(&other).words().to_vec());
+        }
+
+        for command in &cycle {
+            match *command {
+                Command::SetReturnMode(mode) => self.return_mode = mode,
+                Command::CancelCycle => {
+                    self.active = None;
+                    self.sticky = [None; 4];
+                }
+                _ => {}
+            }
+        }
+
+        let kind = cycle.iter()
+                .find_map(|command| match command {
+                    Command::Cycle(kind, _) => Some(*kind),
+                    _ => None,
+                })
+                .or_else(|| self.active.map(|(kind, _)| kind));
+
+        if let Some(kind) = kind.filter(|_| cycle.iter().any(|command| matches!(command, Command::Cycle(..) | Command::ModalMove(_)))) {
+            match self.expand(kind, &block) {
+                Some(moves) => blocks.extend(moves),
+                None => {
+                    if starts {
+                        self.active = None;
+                        self.passed = true;
+                        self.position[2] = None;
+                    }
+                    output.push(block);
+                    return;
+                }
+            }
+        }
+
+        replace(&block, blocks, self.name(), output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn expand(lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut ExpandCycles::new(), blocks).iter().map(|block| block.to_string()).collect();
+    }
+
+    #[test]
+    fn test_drill() {
+        assert_eq!(expand(&[
+            "G0 Z10",
+            "M3 G98 G81 X1 Y2 Z-3 R1 F100 (holes)",
+            "X4",
+            "G99 Y5",
+            "G80",
+            "G0 X0 Y0",
+        ]), vec![
+            "G0 Z10",
+            "M3 (holes)",
+            "G0 X1 Y2",
+            "G0 Z1",
+            "G1 Z-3 F100",
+            "G0 Z1",
+            "G0 Z10",
+            "G0 X4",
+            "G0 Z1",
+            "G1 Z-3",
+            "G0 Z1",
+            "G0 Z10",
+            "G0 Y5",
+            "G0 Z1",
+            "G1 Z-3",
+            "G0 Z1",
+            "G0 X0 Y0",
+        ]);
+    }
+
+    #[test]
+    fn test_peck() {
+        assert_eq!(expand(&["G0 Z1", "G99 G83 X0 Y0 Z-5 R1 Q2.5"]), vec![
+            "G0 Z1",
+            "G0 X0 Y0",
+            "G0 Z1",
+            "G1 Z-1.5",
+            "G0 Z1",
+            "G0 Z-1.246",
+            "G1 Z-4",
+            "G0 Z1",
+            "G0 Z-3.746",
+            "G1 Z-5",
+            "G0 Z1",
+        ]);
+    }
+
+    #[test]
+    fn test_incremental() {
+        assert_eq!(expand(&["G0 X0 Y0 Z5", "G91 G99 G82 X10 Z-4 R-3 P0.5 L2"]), vec![
+            "G0 X0 Y0 Z5",
+            "G91",
+            "G90",
+            "G0 X10",
+            "G0 Z2",
+            "G1 Z-2",
+            "G4 P0.5",
+            "G0 Z2",
+            "G0 X20",
+            "G0 Z2",
+            "G1 Z-2",
+            "G4 P0.5",
+            "G0 Z2",
+            "G91",
+        ]);
+    }
+
+    #[test]
+    fn test_unexpanded() {
+        // Without a retract plane, the cycle is left for the controller
+        assert_eq!(expand(&["G81 X1 Z-1"]), vec!["G81 X1 Z-1"]);
+        assert_eq!(expand(&["G87 X1 Z-1 R1"]), vec!["G87 X1 Z-1 R1"]);
+        assert_eq!(expand(&["G91 G81 X1 Z-1 R1"]), vec!["G91 G81 X1 Z-1 R1"]);
+
+        // The controller keeps running the cycle until cancelled
+        let lines = ["G90 G0 Z5", "G91 G99 G83 X1 Z-3 R-4 Q1 L2 F50", "X2", "G98", "G80", "G90 G0 Z5"];
+        assert_eq!(expand(&lines), lines);
+
+        // Cycles expanded afterwards need no cancelling
+        assert_eq!(expand(&["G87 X1 Z-1 R1", "G80", "G0 X0 Z5", "G81 X2 Z-1 R1", "G80"]), vec![
+            "G87 X1 Z-1 R1",
+            "G80",
+            "G0 X0 Z5",
+            "G0 X2",
+            "G0 Z1",
+            "G1 Z-1",
+            "G0 Z1",
+            "G0 Z5",
+        ]);
+    }
+}
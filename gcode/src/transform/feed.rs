@@ -0,0 +1,229 @@
+use crate::Real;
+use crate::command::FeedRateMode;
+use crate::dialect::Dialect;
+use crate::interp::{Machine, Motion};
+use crate::parser::{Block, Word};
+use crate::printer::PrinterState;
+
+use super::{replace, Pass};
+
+/// Scales feed rates and spindle speeds like the override of a controller, clamping them to the
+/// limits of the machine.
+///
+/// Feed rates are scaled by the factor and clamped to the limits for the kind of move using them,
+/// given in millimeters per minute. Travel moves are the moves of printer firmwares without
+/// extrusion, including rapid moves which printers feed like others. All other moves cut, and as
+/// rapid moves of machine tools ignore the feed rate, one given with them is clamped for cutting.
+/// As feed rates are modal, a move using a different rate than the one written last, like a cut
+/// after a travel with a lower limit, gets its rate added. Limits do not apply in inverse time and
+/// per revolution feed rate modes.
+///
+/// Spindle speeds, or the power of lasers, are scaled and clamped with their own factor and limits,
+/// leaving `S` words of printers like temperatures alone. Blocks with expressions are passed
+/// through.
+#[derive(Debug, Clone)]
+pub struct ScaleFeed {
+    factor: Real,
+    travel: Option<(Real, Real)>,
+    cut: Option<(Real, Real)>,
+
+    speed_factor: Real,
+    speed: Option<(Real, Real)>,
+
+    machine: Machine,
+    printer: PrinterState,
+
+    /// The feed rate set by the program and the one written last, both in program units
+    feed: Option<Real>,
+    written: Option<Real>,
+}
+
+impl ScaleFeed {
+    /// Creates a pass scaling feed rates by the factor.
+    ///
+    /// Panics if `factor` is not positive.
+    pub fn new(factor: Real) -> Self {
+        assert!(factor > 0.0, "factor must be positive");
+
+        Self {
+            factor,
+            travel: None,
+            cut: None,
+            speed_factor: 1.0,
+            speed: None,
+            machine: Machine::new(),
+            printer: PrinterState::new(),
+            feed: None,
+            written: None,
+        }
+    }
+
+    /// Clamps the feed rates of travel moves to the limits in millimeters per minute.
+    pub fn with_travel_limits(mut self, min: Real, max: Real) -> Self {
+        self.travel = Some((min, max));
+        self
+    }
+
+    /// Clamps the feed rates of cutting moves to the limits in millimeters per minute.
+    pub fn with_cut_limits(mut self, min: Real, max: Real) -> Self {
+        self.cut = Some((min, max));
+        self
+    }
+
+    /// Scales spindle speeds by the factor.
+    ///
+    /// Panics if `factor` is not positive.
+    pub fn with_speed_factor(mut self, factor: Real) -> Self {
+        assert!(factor > 0.0, "factor must be positive");
+        self.speed_factor = factor;
+        self
+    }
+
+    /// Clamps spindle speeds to the limits.
+    pub fn with_speed_limits(mut self, min: Real, max: Real) -> Self {
+        self.speed = Some((min, max));
+        self
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to select the dialect.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+}
+
+impl Pass for ScaleFeed {
+    fn name(&self) -> &'static str {
+        "scale-feed"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let (_, reduced) = self.printer.apply(&block);
+
+        if !block.expressions().is_empty() || self.machine.execute(&reduced).is_err() {
+            // The feed rate may be anything from here on
+            self.feed = None;
+            self.written = None;
+            output.push(block);
+            return;
+        }
+
+        let state = self.machine.state();
+        let printer = matches!(self.machine.dialect(), Dialect::Marlin | Dialect::RepRap);
+
+        let moves = reduced.words().iter().any(|word| "XYZABCUVW".contains(word.mnemonic()));
+        let extrudes = block.words().iter().any(|word| word.mnemonic() == 'E');
+        let travel = printer && match state.motion {
+            Some(Motion::Rapid) => true,
+            Some(Motion::Linear) => moves && !extrudes,
+            _ => false,
+        };
+
+        // Rapid moves of machine tools ignore the feed rate
+        let feeds = moves && state.motion.is_some() && (printer || state.motion != Some(Motion::Rapid));
+
+        let limits = match state.feed_rate_mode {
+            FeedRateMode::UnitsPerMinute if travel => self.travel,
+            FeedRateMode::UnitsPerMinute => self.cut,
+            _ => None,
+        };
+        let scale = state.units.millimeters();
+        let target = |feed: Real| {
+            let feed = feed * self.factor;
+            let feed = limits.map_or(feed, |(min, max)| (feed * scale).clamp(min, max) / scale);
+            return (feed * 1e4).round() / 1e4;
+        };
+
+        let mut words = block.words().to_vec();
+        let mut changed = false;
+
+        match words.iter_mut().find(|word| word.mnemonic() == 'F') {
+            Some(word) => {
+                let feed = target(word.value());
+                self.feed = Some(word.value());
+                self.written = Some(feed);
+
+                if feed != word.value() {
+                    *word = Word::new('F', feed);
+                    changed = true;
+                }
+            }
+            None if feeds => {
+                if let Some(feed) = self.feed.map(target).filter(|&feed| self.written != Some(feed)) {
+                    words.push(Word::new('F', feed));
+                    self.written = Some(feed);
+                    changed = true;
+                }
+            }
+            None => {}
+        }
+
+        for word in words.iter_mut().filter(|word| word.mnemonic() == 'S' && reduced.words().contains(word)) {
+            let speed = word.value() * self.speed_factor;
+            let speed = self.speed.map_or(speed, |(min, max)| speed.clamp(min, max));
+            let speed = (speed * 1e4).round() / 1e4;
+
+            if speed != word.value() {
+                *word = Word::new('S', speed);
+                changed = true;
+            }
+        }
+
+        if changed {
+            replace(&block, vec![words], self.name(), output);
+        } else {
+            output.push(block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn scale(mut pass: ScaleFeed, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut pass, blocks).iter().map(ToString::to_string).collect();
+    }
+
+    #[test]
+    fn test_scale_feed() {
+        assert_eq!(scale(ScaleFeed::new(1.5), &["G0 X1 F100", "G1 X2", "M3 S1000", "G2 X3 I0.5 F300"]), vec![
+            "G0 X1 F150",
+            "G1 X2",
+            "M3 S1000",
+            "G2 X3 I0.5 F450",
+        ]);
+
+        let pass = ScaleFeed::new(1.0).with_speed_factor(0.5).with_speed_limits(0.0, 400.0);
+        assert_eq!(scale(pass, &["M3 S1000 G1 X1 F100", "S600", "M104 S200"]), vec!["M3 S400 G1 X1 F100", "S300", "M104 S200"]);
+    }
+
+    #[test]
+    fn test_scale_feed_limits() {
+        let pass = ScaleFeed::new(2.0).with_cut_limits(0.0, 1000.0);
+        assert_eq!(scale(pass.clone(), &["G1 X1 F400", "G1 X2 F800", "G0 X0"]), vec!["G1 X1 F800", "G1 X2 F1000", "G0 X0"]);
+
+        // Limits are in millimeters per minute
+        assert_eq!(scale(pass, &["G20 G1 X1 F30"]), vec!["G20 G1 X1 F39.3701"]);
+    }
+
+    #[test]
+    fn test_scale_feed_printer() {
+        // Travel and extruding moves share the modal feed rate, but not the limits
+        let pass = ScaleFeed::new(1.0)
+                .with_travel_limits(0.0, 9000.0)
+                .with_cut_limits(0.0, 3000.0)
+                .with_machine(Machine::new().with_dialect(Dialect::Marlin));
+
+        assert_eq!(scale(pass, &["G1 X10 F12000", "G1 X20 E1", "G1 X30", "G1 F1200", "G1 X40 E2"]), vec![
+            "G1 X10 F9000",
+            "G1 X20 E1 F3000",
+            "G1 X30 F9000",
+            "G1 F1200",
+            "G1 X40 E2",
+        ]);
+    }
+}
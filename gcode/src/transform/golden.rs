@@ -0,0 +1,200 @@
+//! Regression testing of passes against golden files.
+//!
+//! Every program in a corpus directory is run through a fresh instance of the pass and the output
+//! is compared to the golden file stored next to it, named like the program with `.golden`
+//! appended. Both sides are normalized before comparing, so differences in whitespace, letter case,
+//! number formatting and comments do not matter.
+//!
+//! Setting the `GCODE_BLESS` environment variable writes the current output to the golden files
+//! instead of comparing, e.g. to create them for new programs or to accept intended changes.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::batch::{Batch, BoxError};
+use crate::parser::{Block, Parser};
+
+use super::{apply, Pass};
+
+/// The environment variable enabling blessing of golden files.
+pub const BLESS_VAR: &str = "GCODE_BLESS";
+
+/// A difference between the output of a pass and its golden file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// The input program.
+    pub path: PathBuf,
+
+    /// The number of the normalized line, starting at 1.
+    pub line: usize,
+
+    /// The golden line, if the golden file is not shorter than the output.
+    pub expected: Option<String>,
+
+    /// The output line, if the output is not shorter than the golden file.
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: expected ", self.path.display(), self.line)?;
+        match self.expected {
+            Some(ref line) => write!(f, "'{}'", line)?,
+            None => write!(f, "end of program")?,
+        }
+        write!(f, ", got ")?;
+        match self.actual {
+            Some(ref line) => write!(f, "'{}'", line)?,
+            None => write!(f, "end of program")?,
+        }
+
+        return Ok(());
+    }
+}
+
+/// Runs passes against a corpus of programs with golden files.
+#[derive(Debug, Clone)]
+pub struct Golden {
+    corpus: PathBuf,
+    bless: bool,
+}
+
+impl Golden {
+    /// Uses all programs below the given directory, blessing if `GCODE_BLESS` is set.
+    pub fn new<P>(corpus: P) -> Self
+        where P: Into<PathBuf> {
+        Self {
+            corpus: corpus.into(),
+            bless: env::var_os(BLESS_VAR).is_some(),
+        }
+    }
+
+    /// Overrides whether golden files are written instead of compared.
+    pub fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    /// Runs every program through a pass created by `pass` and returns the first mismatch of each
+    /// program in the corpus.
+    ///
+    /// Fails if a program or golden file can not be read or parsed, or if a golden file can not be
+    /// written while blessing.
+    pub fn check<P, F>(&self, mut pass: F) -> Result<Vec<Mismatch>, BoxError>
+        where P: Pass,
+              F: FnMut() -> P {
+        let mut mismatches = Vec::new();
+
+        for path in Batch::new(&self.corpus).files()? {
+            let golden = golden_path(&path);
+
+            let output = apply(&mut pass(), read(&path)?);
+            let actual = normalize(output.iter());
+
+            if self.bless {
+                let mut content = actual.join("\n");
+                content.push('\n');
+                fs::write(&golden, content)?;
+                continue;
+            }
+
+            let expected = normalize(read(&golden)?.iter());
+
+            let line = (0..expected.len().max(actual.len()))
+                    .find(|&i| expected.get(i) != actual.get(i));
+            if let Some(line) = line {
+                mismatches.push(Mismatch {
+                    path,
+                    line: line + 1,
+                    expected: expected.get(line).cloned(),
+                    actual: actual.get(line).cloned(),
+                });
+            }
+        }
+
+        return Ok(mismatches);
+    }
+
+    /// Like `check`, but panics listing all mismatches, for use in tests.
+    pub fn assert<P, F>(&self, pass: F)
+        where P: Pass,
+              F: FnMut() -> P {
+        let mismatches = self.check(pass)
+                .unwrap_or_else(|err| panic!("Failed to check golden files: {}", err));
+
+        if !mismatches.is_empty() {
+            let mismatches = mismatches.iter().map(ToString::to_string).collect::<Vec<_>>();
+            panic!("Output differs from golden files (set {} to update them):\n{}", BLESS_VAR, mismatches.join("\n"));
+        }
+    }
+}
+
+/// The golden file belonging to a program.
+pub fn golden_path(path: &Path) -> PathBuf {
+    let mut golden = path.as_os_str().to_owned();
+    golden.push(".golden");
+    return PathBuf::from(golden);
+}
+
+fn read(path: &Path) -> Result<Vec<Block>, BoxError> {
+    let blocks = Parser::new()
+            .with_source(path.display().to_string())
+            .into_blocks(BufReader::new(File::open(path)?))
+            .collect::<Result<Vec<_>, _>>()?;
+
+    return Ok(blocks);
+}
+
+/// Renders the blocks in canonical form, dropping blocks without any words.
+fn normalize<'a, I>(blocks: I) -> Vec<String>
+    where I: IntoIterator<Item=&'a Block> {
+    blocks.into_iter()
+            .filter(|block| !block.is_empty() || block.line_number().is_some())
+            .map(|block| Block::new(block.words().to_vec())
+                    .with_line_number(block.line_number())
+                    .with_deleted(block.is_deleted())
+                    .line()
+                    .to_owned())
+            .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Units;
+    use crate::transform::ConvertUnits;
+
+    #[test]
+    fn test_golden() {
+        let root = env::temp_dir().join(format!("gcode-golden-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.nc"), "G20\nG0 X1 Y2\nG1 X0.5 F10\n").unwrap();
+
+        let pass = || ConvertUnits::new(Units::Millimeters);
+
+        let golden = Golden::new(&root);
+        assert!(golden.clone().bless(false).check(pass).is_err());
+
+        golden.clone().bless(true).check(pass).unwrap();
+        assert_eq!(fs::read_to_string(root.join("a.nc.golden")).unwrap(), "G21\nG0 X25.4 Y50.8\nG1 X12.7 F254\n");
+
+        // Formatting does not matter
+        fs::write(root.join("a.nc.golden"), "g21\n\ng0 x25.40  y50.8\nG01X12.7F254.000\n").unwrap();
+        golden.clone().bless(false).assert(pass);
+
+        fs::write(root.join("a.nc.golden"), "G21\nG0 X25.4 Y50.8\n").unwrap();
+        let mismatches = golden.clone().bless(false).check(pass).unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(mismatches, vec![Mismatch {
+            path: root.join("a.nc"),
+            line: 3,
+            expected: None,
+            actual: Some("G1 X12.7 F254".to_owned()),
+        }]);
+    }
+}
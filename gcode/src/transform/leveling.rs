@@ -0,0 +1,427 @@
+use std::error;
+use std::fmt;
+
+use crate::Real;
+use crate::command::{Command, DistanceMode, Plane};
+use crate::geometry::{Point, Point3};
+use crate::interp::{Machine, Motion, State};
+use crate::parser::{Block, Word};
+use crate::printer::PrinterState;
+
+use super::{replace, Pass};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshError {
+    InvalidNumber {
+        line: usize,
+        text: String,
+    },
+
+    /// A row of the grid has a different number of heights than the first one.
+    RaggedRow {
+        row: usize,
+    },
+
+    /// The grid has less than two rows or columns.
+    TooSmall,
+
+    /// The area covered by the grid is empty.
+    InvalidArea,
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::InvalidNumber { line, text } => write!(f, "{}: invalid number: {}", line, text),
+            MeshError::RaggedRow { row } => write!(f, "row {} differs in length from the first row", row),
+            MeshError::TooSmall => f.write_str("mesh needs at least two rows and columns"),
+            MeshError::InvalidArea => f.write_str("mesh covers an empty area"),
+        }
+    }
+}
+
+impl error::Error for MeshError {}
+
+/// Heights of the bed measured at the points of a regular grid, in millimeters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mesh {
+    min: Point,
+    max: Point,
+    columns: usize,
+
+    /// The heights row by row, starting with the row at the lowest Y
+    heights: Vec<Real>,
+}
+
+impl Mesh {
+    /// Creates a mesh from rows of heights spread evenly over the area from `min` to `max`, in
+    /// machine coordinates. The first row is measured at the lowest Y and the first height of
+    /// each row at the lowest X.
+    pub fn new(min: Point, max: Point, rows: Vec<Vec<Real>>) -> Result<Self, MeshError> {
+        if !(min.x < max.x && min.y < max.y) {
+            return Err(MeshError::InvalidArea);
+        }
+
+        let columns = rows.first().map_or(0, Vec::len);
+        if rows.len() < 2 || columns < 2 {
+            return Err(MeshError::TooSmall);
+        }
+
+        if let Some(row) = rows.iter().position(|row| row.len() != columns) {
+            return Err(MeshError::RaggedRow { row: row + 1 });
+        }
+
+        return Ok(Self {
+            min,
+            max,
+            columns,
+            heights: rows.into_iter().flatten().collect(),
+        });
+    }
+
+    /// Parses a grid of heights, either as printed by Marlin's `M420 V` for bilinear leveling or
+    /// as rows of numbers separated by whitespace or commas, like the height maps of Grbl senders.
+    ///
+    /// Lines not starting with a number, like `Bilinear Leveling Grid:` or `ok`, are ignored. The
+    /// numbered header and the row numbers of Marlin's grid are recognized and skipped.
+    pub fn parse(text: &str, min: Point, max: Point) -> Result<Self, MeshError> {
+        let mut rows = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let tokens = line.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|token| !token.is_empty())
+                    .collect::<Vec<_>>();
+
+            if tokens.first().is_none_or(|token| token.parse::<Real>().is_err()) {
+                continue;
+            }
+
+            let row = tokens.iter()
+                    .map(|token| token.parse::<Real>().map_err(|_| MeshError::InvalidNumber {
+                        line: i + 1,
+                        text: token.to_string(),
+                    }))
+                    .collect::<Result<Vec<Real>, _>>()?;
+            rows.push(row);
+        }
+
+        // Marlin numbers the columns in a header and the rows in front of each
+        let numbered = |row: &[Real]| row.iter().enumerate().all(|(i, &value)| value == i as Real);
+        let marlin = rows.len() > 1 && numbered(&rows[0])
+                && rows[1..].iter().enumerate().all(|(i, row)| row.len() == rows[0].len() + 1 && row[0] == i as Real);
+        if marlin {
+            rows.remove(0);
+            for row in &mut rows {
+                row.remove(0);
+            }
+        }
+
+        return Self::new(min, max, rows);
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    pub fn rows(&self) -> usize {
+        self.heights.len() / self.columns
+    }
+
+    /// The height at the given point, interpolated bilinearly between the surrounding points of
+    /// the grid. Outside of the grid, the height at its nearest edge is used.
+    pub fn height(&self, x: Real, y: Real) -> Real {
+        let cell = |value: Real, min: Real, max: Real, count: usize| {
+            let position = ((value - min) / (max - min) * (count - 1) as Real).clamp(0.0, (count - 1) as Real);
+            let index = (position.floor() as usize).min(count - 2);
+            return (index, position - index as Real);
+        };
+
+        let (i, tx) = cell(x, self.min.x, self.max.x, self.columns);
+        let (j, ty) = cell(y, self.min.y, self.max.y, self.rows());
+        let at = |i: usize, j: usize| self.heights[j * self.columns + i];
+
+        let front = at(i, j) + (at(i + 1, j) - at(i, j)) * tx;
+        let back = at(i, j + 1) + (at(i + 1, j + 1) - at(i, j + 1)) * tx;
+        return front + (back - front) * ty;
+    }
+}
+
+/// Compensates the height of all moves for an uneven or misaligned bed, by a global Z offset
+/// and the heights of a mesh.
+///
+/// The pass interprets the program to know the positions moved along. Feed moves are split into
+/// moves no longer than the segment length within the XY plane, so they follow the mesh closely,
+/// with extrusion (`E`) spread proportionally. Rapids and arcs in the XY plane are compensated at
+/// their end only, turning arcs into helixes - flatten arcs first to follow the mesh along them.
+/// Other words of a split block are kept in a block of their own before the moves. Blocks which
+/// can not be interpreted, like those with expressions, are passed through unchanged.
+#[derive(Debug, Clone)]
+pub struct Leveling {
+    offset: Real,
+    mesh: Option<Mesh>,
+    segment: Real,
+    machine: Machine,
+    printer: PrinterState,
+}
+
+impl Default for Leveling {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Leveling {
+    /// Creates a pass without any compensation, splitting feed moves every 5 millimeters.
+    pub fn new() -> Self {
+        Self {
+            offset: 0.0,
+            mesh: None,
+            segment: 5.0,
+            machine: Machine::new(),
+            printer: PrinterState::new(),
+        }
+    }
+
+    /// Adds a constant offset to the heights of all moves, in millimeters.
+    pub fn with_offset(mut self, offset: Real) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Follows the heights of the mesh, which are added to the heights of all moves.
+    pub fn with_mesh(mut self, mesh: Mesh) -> Self {
+        self.mesh = Some(mesh);
+        self
+    }
+
+    /// Sets the length of the moves feed moves are split into, in millimeters.
+    ///
+    /// Panics if `length` is not positive.
+    pub fn with_segment_length(mut self, length: Real) -> Self {
+        assert!(length > 0.0, "segment length must be positive");
+        self.segment = length;
+        self
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// The compensated position in the work coordinates of the state.
+    fn compensate(&self, state: &State, point: Point3) -> Point3 {
+        let height = self.offset + self.mesh.as_ref().map_or(0.0, |mesh| mesh.height(point.x, point.y));
+        let point = state.to_work(Point3::new(point.x, point.y, point.z + height));
+
+        // Coordinates are rounded to the precision of the output, so relative moves do not drift
+        let round = |value: Real| (value * 1e4).round() / 1e4;
+        return Point3::new(round(point.x), round(point.y), round(point.z));
+    }
+}
+
+impl Pass for Leveling {
+    fn name(&self) -> &'static str {
+        "leveling"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let extruder = self.printer.extruder;
+        let (extruded, reduced) = self.printer.apply(&block);
+
+        let before = self.machine.state().position;
+        if !block.expressions().is_empty() || self.machine.execute(&reduced).is_err() {
+            output.push(block);
+            return;
+        }
+
+        let state = self.machine.state();
+        let motion = Command::from_block(&reduced).iter().find_map(|command| match command {
+            Command::RapidMove(target) | Command::LinearMove(target) => Some(target.axes.iter().all(|(_, value)| value.is_none())),
+            Command::ClockwiseArc(arc) | Command::CounterClockwiseArc(arc) | Command::ModalMove(arc) => Some(arc.axes.iter().all(|(_, value)| value.is_none())),
+            _ => None,
+        });
+
+        // Only moves along X, Y and Z are compensated
+        if motion != Some(true) {
+            output.push(block);
+            return;
+        }
+
+        let start = self.compensate(state, before);
+        let relative = |point: Point3, previous: Point3| match state.distance_mode {
+            DistanceMode::Absolute => point,
+            DistanceMode::Relative => Point3::new(point.x - previous.x, point.y - previous.y, point.z - previous.z),
+        };
+
+        let mut blocks = Vec::new();
+        match state.motion {
+            Some(Motion::Linear) => {
+                let end = state.position;
+                let length = before.xy().distance(end.xy());
+                let count = if self.mesh.is_some() { (length / self.segment).ceil().max(1.0) as usize } else { 1 };
+
+                let others = block.words().iter()
+                        .filter(|word| !matches!(word.mnemonic(), 'X' | 'Y' | 'Z' | 'E' | 'F'))
+                        .filter(|word| !(word.mnemonic() == 'G' && (word.value() == 0.0 || word.value() == 1.0)))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                if !others.is_empty() {
+                    blocks.push(others);
+                }
+
+                let extrusion = block.words().iter().any(|word| word.mnemonic() == 'E');
+                let mut feed = block.words().iter().find(|word| word.mnemonic() == 'F').cloned();
+
+                let mut previous = start;
+                for i in 1..=count {
+                    let t = i as Real / count as Real;
+                    let point = Point3::new(before.x + (end.x - before.x) * t,
+                                            before.y + (end.y - before.y) * t,
+                                            before.z + (end.z - before.z) * t);
+                    let current = self.compensate(state, point);
+                    let target = relative(current, previous);
+
+                    let mut words = vec![Word::new('G', 1.0)];
+                    if current.x != previous.x || (state.distance_mode == DistanceMode::Absolute && count > 1) {
+                        words.push(Word::new('X', target.x));
+                    }
+                    if current.y != previous.y || (state.distance_mode == DistanceMode::Absolute && count > 1) {
+                        words.push(Word::new('Y', target.y));
+                    }
+                    if current.z != previous.z || state.distance_mode == DistanceMode::Absolute {
+                        words.push(Word::new('Z', target.z));
+                    }
+                    if extrusion {
+                        let e = match self.printer.relative_extrusion {
+                            true => extruded / count as Real,
+                            false => extruder + extruded * t,
+                        };
+                        words.push(Word::new('E', (e * 1e5).round() / 1e5));
+                    }
+                    words.extend(feed.take());
+
+                    blocks.push(words);
+                    previous = current;
+                }
+            }
+
+            // Rapids and arcs only get their end compensated, keeping all other words
+            Some(Motion::Rapid) | Some(Motion::ClockwiseArc) | Some(Motion::CounterClockwiseArc) => {
+                if state.motion != Some(Motion::Rapid) && state.plane != Plane::XY {
+                    output.push(block);
+                    return;
+                }
+
+                let z = relative(self.compensate(state, state.position), start).z;
+                let mut words = block.words().iter()
+                        .filter(|word| word.mnemonic() != 'Z')
+                        .cloned()
+                        .collect::<Vec<_>>();
+                if z != 0.0 || state.distance_mode == DistanceMode::Absolute {
+                    words.push(Word::new('Z', z));
+                }
+                blocks.push(words);
+            }
+
+            None => {
+                output.push(block);
+                return;
+            }
+        }
+
+        let mut replaced = Vec::new();
+        replace(&block, blocks, self.name(), &mut replaced);
+        output.extend(replaced.into_iter().map(|replaced| replaced.with_deleted(block.is_deleted())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn level(pass: Leveling, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut pass.clone(), blocks).iter().map(ToString::to_string).collect();
+    }
+
+    fn mesh() -> Mesh {
+        // Rising by 0.1 along X and by 0.2 along Y over 10mm
+        Mesh::new(Point::new(0.0, 0.0), Point::new(10.0, 10.0), vec![
+            vec![0.0, 0.05, 0.1],
+            vec![0.1, 0.15, 0.2],
+            vec![0.2, 0.25, 0.3],
+        ]).unwrap()
+    }
+
+    #[test]
+    fn test_mesh() {
+        let mesh = mesh();
+        assert_eq!((mesh.columns(), mesh.rows()), (3, 3));
+        assert!((mesh.height(0.0, 0.0) - 0.0).abs() < 1e-9);
+        assert!((mesh.height(2.5, 7.5) - 0.175).abs() < 1e-9);
+        assert!((mesh.height(20.0, -5.0) - 0.1).abs() < 1e-9);
+
+        assert_eq!(Mesh::new(Point::new(0.0, 0.0), Point::new(1.0, 1.0), vec![vec![0.0, 1.0]]), Err(MeshError::TooSmall));
+        assert_eq!(Mesh::new(Point::new(0.0, 0.0), Point::new(1.0, 1.0), vec![vec![0.0, 1.0], vec![0.0]]), Err(MeshError::RaggedRow { row: 2 }));
+        assert_eq!(Mesh::new(Point::new(0.0, 0.0), Point::new(0.0, 1.0), vec![vec![0.0; 2]; 2]), Err(MeshError::InvalidArea));
+    }
+
+    #[test]
+    fn test_mesh_parse() {
+        let marlin = "\
+Bilinear Leveling Grid:
+      0      1      2
+ 0 +0.000 +0.050 +0.100
+ 1 +0.100 +0.150 +0.200
+ 2 +0.200 +0.250 +0.300
+ok
+";
+        assert_eq!(Mesh::parse(marlin, Point::new(0.0, 0.0), Point::new(10.0, 10.0)), Ok(mesh()));
+
+        let plain = "0, 0.05, 0.1\n0.1, 0.15, 0.2\n\n0.2 0.25 0.3\n";
+        assert_eq!(Mesh::parse(plain, Point::new(0.0, 0.0), Point::new(10.0, 10.0)), Ok(mesh()));
+
+        assert_eq!(Mesh::parse("0 1\n0 x\n", Point::new(0.0, 0.0), Point::new(10.0, 10.0)),
+                   Err(MeshError::InvalidNumber { line: 2, text: "x".to_owned() }));
+    }
+
+    #[test]
+    fn test_offset() {
+        assert_eq!(level(Leveling::new().with_offset(-0.1), &["G0 X1 Y1 Z5", "G1 Z0.2 F100", "G1 X10 (cut)", "G91 G1 X1 Y1"]), vec![
+            "G0 X1 Y1 Z4.9",
+            "G1 Z0.1 F100",
+            "G1 X10 Z0.1 (cut)",
+            "G91",
+            "G1 X1 Y1",
+        ]);
+    }
+
+    #[test]
+    fn test_leveling() {
+        let pass = Leveling::new().with_mesh(mesh()).with_segment_length(5.0);
+
+        assert_eq!(level(pass.clone(), &["G1 Z0.2 F100", "N10 G1 X10 E1.5 ; first"]), vec![
+            "G1 Z0.2 F100",
+            "N10 G1 X5 Y0 Z0.25 E0.75 ; first",
+            "G1 X10 Y0 Z0.3 E1.5",
+        ]);
+
+        // Relative extrusion is split as well
+        assert_eq!(level(pass.clone(), &["M83", "G1 Y10 E2 F600"]), vec![
+            "M83",
+            "G1 X0 Y5 Z0.1 E1 F600",
+            "G1 X0 Y10 Z0.2 E1",
+        ]);
+
+        // Arcs and rapids are compensated at their end
+        assert_eq!(level(pass.clone(), &["G17 G0 X10 Y0 Z1", "G3 X0 Y10 R10 F100"]), vec![
+            "G17 G0 X10 Y0 Z1.1",
+            "G3 X0 Y10 R10 F100 Z1.2",
+        ]);
+
+        // Blocks which can not be interpreted are kept as they are
+        assert_eq!(level(pass, &["G1 X[1 + 1] F100", "G92 X0"]), vec!["G1 X[1 + 1] F100", "G92 X0"]);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::parser::{Block, Comment, CommentPosition, Word};
+
+use super::Pass;
+
+/// Reorders the words of each block into a canonical order.
+///
+/// The order is G codes, axes in alphabetical order, arc offsets (`I`, `J`, `K`) and radius,
+/// other parameters like `P` in alphabetical order, then `F`, `S`, `T` and M codes last. Words
+/// of the same rank keep their relative order, so multiple G or M codes stay as they were. The
+/// line number always comes first. As the interpreter executes words in a fixed order regardless
+/// of their position, this does not change the meaning of a block.
+///
+/// Leading and trailing comments stay at the start and the end of the block, inline comments stay
+/// in front of the word they were in front of.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CanonicalOrder;
+
+impl CanonicalOrder {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn rank(word: &Word) -> (u8, char) {
+        return match word.mnemonic() {
+            'G' => (0, ' '),
+            letter @ ('A' | 'B' | 'C' | 'E' | 'U' | 'V' | 'W' | 'X' | 'Y' | 'Z') => (1, letter),
+            letter @ ('I' | 'J' | 'K') => (2, letter),
+            'R' => (3, ' '),
+            'F' => (5, ' '),
+            'S' => (6, ' '),
+            'T' => (7, ' '),
+            'M' => (8, ' '),
+            letter => (4, letter),
+        };
+    }
+}
+
+impl Pass for CanonicalOrder {
+    fn name(&self) -> &'static str {
+        "canonical-order"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let mut order = (0..block.words().len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| Self::rank(&block.words()[i]));
+
+        if order.iter().enumerate().all(|(position, &i)| position == i) {
+            output.push(block);
+            return;
+        }
+
+        let words = order.iter().map(|&i| block.words()[i]).collect();
+        let mut sorted = Block::new(words)
+                .with_line_number(block.line_number())
+                .with_deleted(block.is_deleted())
+                .with_provenance(block.provenance().clone())
+                .with_pass(self.name());
+
+        for comment in block.comments() {
+            let position = match comment.position() {
+                CommentPosition::Inline(index) => match order.iter().position(|&i| i == index) {
+                    Some(0) | None => CommentPosition::Leading,
+                    Some(index) => CommentPosition::Inline(index),
+                },
+                position => position,
+            };
+
+            sorted = sorted.with_comment(Comment::new(comment.text(), comment.style(), position));
+        }
+
+        output.push(sorted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn order(lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut CanonicalOrder::new(), blocks).iter().map(|block| block.to_string()).collect();
+    }
+
+    #[test]
+    fn test_order() {
+        assert_eq!(order(&[
+            "N10 M3 S1000 T1 F100 Y2 X1 G1",
+            "J1 I2 Z5 G2 G91",
+            "P1 G4",
+            "M8 M7",
+            "X1 E2",
+        ]), vec![
+            "N10 G1 X1 Y2 F100 S1000 T1 M3",
+            "G2 G91 Z5 I2 J1",
+            "G4 P1",
+            "M8 M7",
+            "E2 X1",
+        ]);
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let blocks = Parser::new().parse_all(["G1  x1 (keep) Y2 ; as is"].iter()).unwrap();
+        let program = apply(&mut CanonicalOrder::new(), blocks.clone());
+
+        assert_eq!(program.blocks(), &blocks[..]);
+        assert!(program.blocks()[0].provenance().passes().is_empty());
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(order(&["(start) Y2 (before x) X1 (before g) G0 ; end"]), vec!["(start) (before g) G0 (before x) X1 Y2 ; end"]);
+    }
+}
@@ -0,0 +1,365 @@
+use std::mem;
+
+use crate::{Real, EPSILON};
+use crate::command::{Command, DistanceMode, Plane};
+use crate::generate::Direction;
+use crate::geometry::{Contour, Point, Point3};
+use crate::interp::{Machine, Motion, State};
+use crate::modal::ModalGroup;
+use crate::parser::{Block, Word};
+use crate::toolpath::ArcSegment;
+
+use super::{replace, Pass};
+
+/// The chord tolerance for approximating arcs while classifying loops, in millimeters.
+const TOLERANCE: Real = 0.01;
+
+/// A feed move in the XY plane, which may be part of a loop.
+#[derive(Debug, Clone)]
+struct Cut {
+    /// Where the move starts and ends, in machine coordinates
+    start: Point3,
+    end: Point3,
+
+    arc: Option<ArcSegment>,
+
+    /// The state after the move, for converting back to program coordinates
+    state: State,
+
+    /// The feed rate set by the block itself
+    feed: Option<Real>,
+}
+
+/// Interprets the block as a cut, if it only moves along an arc or a line in the XY plane in
+/// absolute distance mode without changing anything else.
+fn cut(block: &Block, before: &State, state: &State) -> Option<Cut> {
+    let plain = block.words().iter().all(|word| match word.mnemonic() {
+        'G' => [1.0, 2.0, 3.0].contains(&word.value()),
+        'X' | 'Y' | 'Z' | 'I' | 'J' | 'R' | 'F' => true,
+        _ => false,
+    });
+    let moves = block.words().iter().any(|word| matches!(word.mnemonic(), 'X' | 'Y'));
+
+    if !plain || !moves || block.is_deleted() || block.control().is_some()
+            || state.plane != Plane::XY || state.distance_mode != DistanceMode::Absolute
+            || state.compensation.is_some() || (state.position.z - before.position.z).abs() > EPSILON {
+        return None;
+    }
+
+    let clockwise = match state.motion {
+        Some(Motion::Linear) => None,
+        Some(Motion::ClockwiseArc) => Some(true),
+        Some(Motion::CounterClockwiseArc) => Some(false),
+        _ => return None,
+    };
+
+    let arc = match clockwise {
+        Some(clockwise) => {
+            let arc = Command::from_block(block).into_iter().find_map(|command| match command {
+                Command::ClockwiseArc(arc) | Command::CounterClockwiseArc(arc) | Command::ModalMove(arc) => Some(arc),
+                _ => None,
+            })?;
+
+            // The arc starts at the previous position, but with all modes of the block applied
+            let mut start = state.clone();
+            start.position = before.position;
+            Some(start.arc(clockwise, &arc).ok()?)
+        }
+        None => None,
+    };
+
+    return Some(Cut {
+        start: before.position,
+        end: state.position,
+        arc,
+        state: state.clone(),
+        feed: block.words().iter().rev().find(|word| word.mnemonic() == 'F').map(Word::value),
+    });
+}
+
+/// The G word of the motion of a cut, optionally traversed backwards.
+fn motion(cut: &Cut, reversed: bool) -> Word {
+    let code = match &cut.arc {
+        Some(arc) if arc.is_clockwise() != reversed => 2.0,
+        Some(_) => 3.0,
+        None => 1.0,
+    };
+
+    return Word::new('G', code);
+}
+
+fn xy(point: Point3) -> Point {
+    Point::new(point.x, point.y)
+}
+
+/// The contour of a loop, with arcs approximated by lines.
+fn contour(cuts: &[Cut]) -> Contour {
+    let mut points = vec![xy(cuts[0].start)];
+    for cut in cuts {
+        match &cut.arc {
+            Some(arc) => points.extend(arc.flatten(TOLERANCE).into_iter().map(xy)),
+            None => points.push(xy(cut.end)),
+        }
+    }
+
+    // The last point is where the loop started
+    points.pop();
+
+    return Contour::closed(points);
+}
+
+/// Reverses the loops of a program as required to cut them in the given direction, like for
+/// climb milling programs generated elsewhere.
+///
+/// A loop is a run of feed moves along lines and arcs in the XY plane returning to where it
+/// started, at a single height and feed rate. Only blocks in absolute distance mode without cutter
+/// compensation and without words besides G1, G2, G3, X, Y, Z, I, J, R and F are part of loops.
+/// Loops are classified like `Direction::apply` does, so ones nested inside an odd number of
+/// larger loops are holes, no matter their height.
+///
+/// Reversed loops keep the line numbers and comments of the blocks in place. Their moves all give
+/// their motion, with clockwise and counter-clockwise arcs swapped and their centers given as
+/// offsets from the new start. The motion the loop ended with is restored on the next block moving
+/// without one. As loops are classified among all others, the pass holds back the whole program
+/// until the end.
+#[derive(Debug, Clone)]
+pub struct OrientLoops {
+    direction: Direction,
+    machine: Machine,
+
+    /// The blocks read so far and the cuts they make
+    blocks: Vec<(Block, Option<Cut>)>,
+}
+
+impl OrientLoops {
+    pub fn new(direction: Direction) -> Self {
+        Self {
+            direction,
+            machine: Machine::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// The index of the block closing the loop starting at `start`, if any.
+    fn closing(&self, start: usize) -> Option<usize> {
+        let first = self.blocks[start].1.as_ref()?;
+
+        for (i, (_, cut)) in self.blocks.iter().enumerate().skip(start) {
+            let cut = cut.as_ref()?;
+            if cut.state.feed_rate != first.state.feed_rate {
+                return None;
+            }
+
+            if xy(cut.end).distance(xy(first.start)) <= EPSILON {
+                return Some(i);
+            }
+        }
+
+        return None;
+    }
+
+    /// Writes the loop in reverse, keeping the line numbers and comments of its blocks in place.
+    fn reverse(&self, blocks: &[(Block, Option<Cut>)], output: &mut Vec<Block>) {
+        let cuts = blocks.iter()
+                .map(|(_, cut)| cut.clone().expect("loop of cuts"))
+                .collect::<Vec<_>>();
+
+        for (i, ((block, _), cut)) in blocks.iter().zip(cuts.iter().rev()).enumerate() {
+            let start = cut.state.to_work(cut.end);
+            let end = cut.state.to_work(cut.start);
+
+            let mut words = vec![motion(cut, true), Word::new('X', end.x), Word::new('Y', end.y)];
+            if let Some(arc) = &cut.arc {
+                let center = cut.state.to_work(arc.center());
+                words.push(Word::new('I', center.x - start.x));
+                words.push(Word::new('J', center.y - start.y));
+            }
+
+            // The feed rate is set where the loop starts, as before
+            if i == 0 {
+                words.extend(cuts[0].feed.map(|feed| Word::new('F', feed)));
+            }
+
+            replace(block, vec![words], self.name(), output);
+        }
+    }
+}
+
+impl Pass for OrientLoops {
+    fn name(&self) -> &'static str {
+        "orient-loops"
+    }
+
+    fn process(&mut self, block: Block, _output: &mut Vec<Block>) {
+        let before = self.machine.state().clone();
+        let executed = block.is_resolved() && self.machine.execute(&block).is_ok();
+
+        let cut = if executed { cut(&block, &before, self.machine.state()) } else { None };
+        self.blocks.push((block, cut));
+    }
+
+    fn finish(&mut self, output: &mut Vec<Block>) {
+        let mut loops = Vec::new();
+        let mut i = 0;
+        while i < self.blocks.len() {
+            match self.closing(i) {
+                Some(end) => {
+                    loops.push(i..=end);
+                    i = end + 1;
+                }
+                None => i += 1,
+            }
+        }
+
+        let contours = loops.iter()
+                .map(|range| {
+                    let cuts = self.blocks[range.clone()].iter()
+                            .map(|(_, cut)| cut.clone().expect("loop of cuts"))
+                            .collect::<Vec<_>>();
+                    contour(&cuts)
+                })
+                .collect::<Vec<_>>();
+        let oriented = self.direction.apply(&contours);
+
+        let mut reversed = loops.into_iter()
+                .zip(contours.iter().zip(&oriented))
+                .filter(|(_, (contour, oriented))| contour != oriented)
+                .map(|(range, _)| range)
+                .peekable();
+
+        let blocks = mem::take(&mut self.blocks);
+
+        // The motion to restore on the next block moving without one
+        let mut restore: Option<Word> = None;
+
+        let mut i = 0;
+        while i < blocks.len() {
+            if let Some(range) = reversed.next_if(|range| *range.start() == i) {
+                let (first, last) = (&blocks[*range.start()].1, &blocks[*range.end()].1);
+                let (first, last) = (first.as_ref().expect("loop of cuts"), last.as_ref().expect("loop of cuts"));
+
+                self.reverse(&blocks[range.clone()], output);
+
+                restore = Some(motion(last, false)).filter(|&word| word != motion(first, true));
+                i = range.end() + 1;
+                continue;
+            }
+
+            let block = blocks[i].0.clone();
+            i += 1;
+
+            let word = match restore {
+                Some(word) => word,
+                None => {
+                    output.push(block);
+                    continue;
+                }
+            };
+
+            let has_motion = block.words().iter()
+                    .any(|word| word.mnemonic() == 'G' && ModalGroup::of(word) == Some(ModalGroup::Motion));
+            let modal_move = Command::from_block(&block).iter()
+                    .any(|command| matches!(command, Command::ModalMove(_)));
+
+            if has_motion || !modal_move {
+                restore = restore.filter(|_| !has_motion);
+                output.push(block);
+            } else if block.is_resolved() {
+                let mut words = vec![word];
+                words.extend_from_slice(block.words());
+                replace(&block, vec![words], self.name(), output);
+                restore = None;
+            } else {
+                // Expressions refer to words by index, so the motion is given by a block of its own
+                output.push(Block::new(vec![word])
+                        .with_provenance(block.provenance().clone())
+                        .with_pass(self.name()));
+                output.push(block);
+                restore = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn orient(direction: Direction, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut OrientLoops::new(direction), blocks).iter().map(ToString::to_string).collect();
+    }
+
+    #[test]
+    fn test_orient_loops() {
+        let lines = ["G21 G90", "G0 X0 Y0 Z1", "G1 Z-1 F100", "N10 G1 X10 Y0 F200", "X10 Y10 (corner)", "X0 Y10", "X0 Y0", "G0 Z5"];
+
+        // Outer loops are cut clockwise when climb milling
+        assert_eq!(orient(Direction::Climb, &lines), vec![
+            "G21 G90",
+            "G0 X0 Y0 Z1",
+            "G1 Z-1 F100",
+            "N10 G1 X0 Y10 F200",
+            "G1 X10 Y10 (corner)",
+            "G1 X10 Y0",
+            "G1 X0 Y0",
+            "G0 Z5",
+        ]);
+        assert_eq!(orient(Direction::Conventional, &lines), lines);
+    }
+
+    #[test]
+    fn test_orient_loops_arcs() {
+        let lines = ["G0 X0 Y0", "G1 X10 Y0 F100", "G3 X10 Y10 I0 J5", "G1 X0 Y10", "G1 X0 Y0"];
+        assert_eq!(orient(Direction::Climb, &lines), vec![
+            "G0 X0 Y0",
+            "G1 X0 Y10 F100",
+            "G1 X10 Y10",
+            "G2 X10 Y0 I0 J-5",
+            "G1 X0 Y0",
+        ]);
+
+        // The loop ends with the arc reversed, so the next block gets the original motion back
+        let lines = ["G0 X10 Y0", "G3 X10 Y10 R5 F100", "G1 X0 Y10", "X0 Y0", "X10 Y0", "X20", "M5"];
+        assert_eq!(orient(Direction::Climb, &lines), vec![
+            "G0 X10 Y0",
+            "G1 X0 Y0 F100",
+            "G1 X0 Y10",
+            "G1 X10 Y10",
+            "G2 X10 Y0 I0 J-5",
+            "G1 X20",
+            "M5",
+        ]);
+    }
+
+    #[test]
+    fn test_orient_loops_holes() {
+        let square = |z: Real, from: Real, to: Real| vec![
+            format!("G0 X{} Y{} Z{}", from, from, z),
+            format!("G1 X{} Y{}", to, from),
+            format!("G1 X{} Y{}", to, to),
+            format!("G1 X{} Y{}", from, to),
+            format!("G1 X{} Y{}", from, from),
+        ];
+
+        // The hole is only cut after the outline, which is cut in two passes
+        let mut lines = vec!["G21 G90 F100".to_owned()];
+        lines.extend(square(-1.0, 0.0, 10.0));
+        lines.extend(square(-2.0, 0.0, 10.0));
+        lines.extend(square(-1.0, 3.0, 7.0));
+        let lines = lines.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let oriented = orient(Direction::Climb, &lines);
+        assert_eq!(oriented[2..6], ["G1 X0 Y10", "G1 X10 Y10", "G1 X10 Y0", "G1 X0 Y0"]);
+        assert_eq!(oriented[7..11], ["G1 X0 Y10", "G1 X10 Y10", "G1 X10 Y0", "G1 X0 Y0"]);
+        assert_eq!(oriented[11..], lines[11..]);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::Real;
+use crate::command::{Arc, Command, DistanceMode, Move};
+use crate::parser::{Block, Word};
+use crate::plasma::PlasmaProfile;
+
+use super::{replace, Pass};
+
+/// Converts a program cutting contours with a spindle, like the ones of the generators, into one
+/// for a plasma cutter.
+///
+/// Moves plunging below the surface of the material turn into pierce sequences: a rapid to the
+/// pierce height, switching the torch on, the pierce delay and a feed move down to the cutting
+/// height, followed by enabling the torch height controller and the kerf compensation. Moves
+/// leaving the material switch all of these off again before the torch is raised. While cutting,
+/// the torch stays at cutting height, so the Z words of the program are dropped. The spindle
+/// commands of the original program are dropped as well.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlasmaCut {
+    profile: PlasmaProfile,
+    surface: Real,
+    distance_mode: DistanceMode,
+    z: Option<Real>,
+    cutting: bool,
+}
+
+impl PlasmaCut {
+    pub fn new(profile: PlasmaProfile) -> Self {
+        Self {
+            profile,
+            surface: 0.0,
+            distance_mode: DistanceMode::Absolute,
+            z: None,
+            cutting: false,
+        }
+    }
+
+    /// Sets the Z height of the material surface, below which the original program cuts.
+    pub fn surface(mut self, z: Real) -> Self {
+        self.surface = z;
+        self
+    }
+
+    fn pierce(&self, f: Option<Real>) -> Vec<Vec<Word>> {
+        let profile = &self.profile;
+
+        let mut feed = vec![Word::new('G', 1.0), Word::new('Z', self.surface + profile.cut_height)];
+        feed.extend(f.map(|f| Word::new('F', f)));
+
+        let mut blocks = vec![
+            vec![Word::new('G', 0.0), Word::new('Z', self.surface + profile.pierce_height)],
+            profile.torch_on.clone(),
+        ];
+        if profile.pierce_delay > 0.0 {
+            blocks.push(vec![Word::new('G', 4.0), Word::new('P', profile.pierce_delay)]);
+        }
+        blocks.push(feed);
+        blocks.extend(profile.thc.as_ref().map(|(enable, _)| enable.clone()));
+        blocks.extend(profile.kerf_compensation());
+
+        return blocks;
+    }
+
+    fn stop(&self) -> Vec<Vec<Word>> {
+        let mut blocks = Vec::new();
+        if self.profile.kerf_compensation().is_some() {
+            blocks.push(vec![Word::new('G', 40.0)]);
+        }
+        blocks.extend(self.profile.thc.as_ref().map(|(_, disable)| disable.clone()));
+        blocks.push(self.profile.torch_off.clone());
+
+        return blocks;
+    }
+
+    /// The Z coordinate and feed rate of a motion command, if it moves along Z.
+    fn z(&self, command: &Command) -> Option<(Option<Real>, Option<Real>)> {
+        let (z, f) = match *command {
+            Command::RapidMove(Move { z, f, .. }) | Command::LinearMove(Move { z, f, .. }) => (z?, f),
+            Command::ClockwiseArc(Arc { z, f, .. }) | Command::CounterClockwiseArc(Arc { z, f, .. })
+            | Command::ModalMove(Arc { z, f, .. }) => (z?, f),
+            _ => return None,
+        };
+
+        return Some(match self.distance_mode {
+            DistanceMode::Absolute => (Some(z), f),
+            DistanceMode::Relative => (self.z.map(|current| current + z), f),
+        });
+    }
+}
+
+/// The command without its Z word, or `None` if nothing is left to move.
+fn planar(command: Command) -> Option<Command> {
+    return match command {
+        Command::RapidMove(target) | Command::LinearMove(target) => {
+            let target = Move { z: None, ..target };
+            if target.x.is_none() && target.y.is_none() {
+                return target.f.map(Command::SetFeedRate);
+            }
+
+            Some(match command {
+                Command::RapidMove(_) => Command::RapidMove(target),
+                _ => Command::LinearMove(target),
+            })
+        }
+        Command::ClockwiseArc(arc) => Some(Command::ClockwiseArc(Arc { z: None, ..arc })),
+        Command::CounterClockwiseArc(arc) => Some(Command::CounterClockwiseArc(Arc { z: None, ..arc })),
+        Command::ModalMove(arc) => {
+            let arc = Arc { z: None, ..arc };
+            if arc == (Arc { f: arc.f, ..Arc::default() }) {
+                return arc.f.map(Command::SetFeedRate);
+            }
+
+            Some(Command::ModalMove(arc))
+        }
+        command => Some(command),
+    };
+}
+
+/// Adds the words of the commands as a block, unless there are none.
+fn push(blocks: &mut Vec<Vec<Word>>, commands: &[Command]) {
+    if !commands.is_empty() {
+        blocks.push(Command::to_block(commands).words().to_vec());
+    }
+}
+
+impl Pass for PlasmaCut {
+    fn name(&self) -> &'static str {
+        "plasma"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        if block.is_deleted() {
+            output.push(block);
+            return;
+        }
+
+        let commands = Command::from_block(&block);
+        for command in &commands {
+            if let Command::SetDistanceMode(mode) = command {
+                self.distance_mode = *mode;
+            }
+        }
+
+        let spindle = |command: &Command| matches!(command,
+                Command::SpindleOn { .. } | Command::SpindleOff | Command::SetSpindleSpeed(_));
+        let ends = commands.iter().any(|command| matches!(command, Command::ProgramEnd { .. }));
+        let target = commands.iter().find_map(|command| self.z(command));
+
+        let below = |z: Option<Real>| z.is_some_and(|z| z <= self.surface);
+        let plunges = !self.cutting && target.is_some_and(|(z, _)| below(z));
+        let leaves = self.cutting && (ends || target.is_some_and(|(z, _)| !below(z)));
+
+        if !plunges && !leaves && !self.cutting && !commands.iter().any(spindle) {
+            if let Some((z, _)) = target {
+                self.z = z;
+            }
+            output.push(block);
+            return;
+        }
+
+        let (motion, other): (Vec<_>, Vec<_>) = commands.into_iter()
+                .filter(|command| !spindle(command))
+                .partition(|command| self.z(command).is_some());
+        let (end, other): (Vec<_>, Vec<_>) = other.into_iter()
+                .partition(|command| matches!(command, Command::ProgramEnd { .. }));
+
+        let mut blocks = Vec::new();
+        if plunges {
+            // The feed rate of the plunge moves on to the feed move down to the cutting height
+            let approach = motion.into_iter()
+                    .filter_map(planar)
+                    .filter(|command| !matches!(command, Command::SetFeedRate(_)))
+                    .collect::<Vec<_>>();
+
+            push(&mut blocks, &other);
+            push(&mut blocks, &approach);
+            blocks.extend(self.pierce(target.and_then(|(_, f)| f)));
+            self.cutting = true;
+        } else if leaves {
+            push(&mut blocks, &other);
+            blocks.extend(self.stop());
+            push(&mut blocks, &motion);
+            push(&mut blocks, &end);
+            self.cutting = false;
+        } else if self.cutting {
+            push(&mut blocks, &[other, motion.into_iter().filter_map(planar).collect(), end].concat());
+        } else {
+            push(&mut blocks, &[other, motion, end].concat());
+        }
+
+        if let Some((z, _)) = target {
+            self.z = z;
+        }
+
+        replace(&block, blocks, self.name(), output);
+    }
+
+    fn finish(&mut self, output: &mut Vec<Block>) {
+        if self.cutting {
+            self.cutting = false;
+            output.extend(self.stop().into_iter().map(|words| Block::new(words).with_pass(self.name())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Side;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn convert(profile: PlasmaProfile, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut PlasmaCut::new(profile), blocks).iter().map(|block| block.to_string()).collect();
+    }
+
+    #[test]
+    fn test_pierce() {
+        assert_eq!(convert(PlasmaProfile::default(), &[
+            "G90",
+            "S10000 M3",
+            "G0 Z5",
+            "G0 X10 Y10",
+            "G1 Z-1 F300",
+            "G1 X20 Y10 F1000",
+            "G1 Z-2",
+            "G1 X10 Y10",
+            "G0 Z5",
+            "M5",
+            "M2",
+        ]), vec![
+            "G90",
+            "G0 Z5",
+            "G0 X10 Y10",
+            "G0 Z3.8",
+            "M3",
+            "G4 P0.5",
+            "G1 Z1.5 F300",
+            "G1 X20 Y10 F1000",
+            "G1 X10 Y10",
+            "M5",
+            "G0 Z5",
+            "M2",
+        ]);
+    }
+
+    #[test]
+    fn test_thc_and_kerf() {
+        let profile = PlasmaProfile {
+            thc: Some((vec![Word::new('M', 65.0), Word::new('P', 2.0)], vec![Word::new('M', 64.0), Word::new('P', 2.0)])),
+            pierce_delay: 0.0,
+            kerf_side: Some(Side::Left),
+            ..PlasmaProfile::default()
+        };
+
+        assert_eq!(convert(profile, &["G0 X0 Y0 Z5", "G1 X1 Z-1", "G1 X2", "M2"]), vec![
+            "G0 X0 Y0 Z5",
+            "G1 X1",
+            "G0 Z3.8",
+            "M3",
+            "G1 Z1.5",
+            "M65 P2",
+            "G41.1 D1.5",
+            "G1 X2",
+            "G40",
+            "M64 P2",
+            "M5",
+            "M2",
+        ]);
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::command::FeedRateMode;
+use crate::interp::{Machine, Motion};
+use crate::modal::ModalGroup;
+use crate::parser::{Block, Word};
+use crate::printer::PrinterState;
+
+use super::{replace, Pass};
+
+/// The letters of axis words.
+const AXES: &str = "XYZABCUVW";
+
+/// What a word sets, to tell whether the program has set it before.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Setting {
+    Group(ModalGroup),
+    Letter(char),
+}
+
+impl Setting {
+    fn of(word: &Word) -> Option<Setting> {
+        match word.mnemonic() {
+            'G' | 'M' => ModalGroup::of(word).map(Setting::Group),
+            letter if AXES.contains(letter) || letter == 'F' || letter == 'S' => Some(Setting::Letter(letter)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the G word is a straight move (G0 or G1).
+fn is_straight(word: &Word) -> bool {
+    word.mnemonic() == 'G' && (word.value() == 0.0 || word.value() == 1.0)
+}
+
+/// What removing redundant words saved.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Savings {
+    /// The number of words removed, including those of dropped blocks.
+    pub words: usize,
+
+    /// The number of blocks dropped.
+    pub blocks: usize,
+
+    /// The number of bytes saved, counting a line break for each dropped block.
+    pub bytes: usize,
+}
+
+impl fmt::Display for Savings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "removed {} words and {} blocks, saving {} bytes", self.words, self.blocks, self.bytes)
+    }
+}
+
+/// Removes words repeating the current state of the machine and drops moves going nowhere.
+///
+/// Candidates are axis words of straight moves ending where the axis already is, and G words of
+/// the motion (G0 to G3), plane, distance mode, feed rate mode, units and coordinate system groups,
+/// `F` and `S` words setting what is already active. A word is only removed if the program has
+/// set its value before, as the state of the machine at the start is unknown, and if the block
+/// leaves the machine in the same state without it. Feed rates are always kept in inverse time
+/// mode, which needs one with each move, and G words are kept for printer firmwares without modal
+/// motion. Blocks with non-modal G words, like `G92`, or with expressions are passed through.
+///
+/// Moves left without any axis word are dropped, unless they have a line number or comments. The
+/// savings are available from `savings` after running the pass.
+#[derive(Debug, Clone)]
+pub struct RemoveRedundant {
+    machine: Machine,
+    printer: PrinterState,
+
+    /// Everything the program has set so far
+    known: HashSet<Setting>,
+
+    savings: Savings,
+}
+
+impl RemoveRedundant {
+    pub fn new() -> Self {
+        Self {
+            machine: Machine::new(),
+            printer: PrinterState::new(),
+            known: HashSet::new(),
+            savings: Savings::default(),
+        }
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to select the dialect.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// What the blocks processed so far saved.
+    pub fn savings(&self) -> Savings {
+        self.savings
+    }
+
+    /// Whether the word may be removed from a block leaving the machine in the given state.
+    fn is_candidate(&self, word: &Word, straight: bool, inverse_time: bool) -> bool {
+        let setting = match Setting::of(word) {
+            Some(setting) if self.known.contains(&setting) => setting,
+            _ => return false,
+        };
+
+        return match setting {
+            Setting::Letter('F') => !inverse_time,
+            Setting::Letter('S') => true,
+            Setting::Letter(_) => straight,
+            Setting::Group(_) => match self.machine.dialect().modal_group(word) {
+                Some(ModalGroup::Motion) => word.value().fract() == 0.0 && word.value() <= 3.0,
+                Some(ModalGroup::PlaneSelection) | Some(ModalGroup::DistanceMode) | Some(ModalGroup::FeedRateMode)
+                | Some(ModalGroup::Units) | Some(ModalGroup::CoordinateSystem) => word.mnemonic() == 'G',
+                _ => false,
+            },
+        };
+    }
+}
+
+impl Default for RemoveRedundant {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pass for RemoveRedundant {
+    fn name(&self) -> &'static str {
+        "remove-redundant"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let (_, reduced) = self.printer.apply(&block);
+
+        let plain = !block.is_deleted() && block.expressions().is_empty() && block.assignments().is_empty()
+                && block.control().is_none() && block.argument().is_none()
+                && !block.words().iter().any(|word| word.mnemonic() == 'G' && match ModalGroup::of(word) {
+                    Some(ModalGroup::NonModal) | None => true,
+                    Some(ModalGroup::Motion) => word.value().fract() != 0.0 || word.value() > 3.0,
+                    Some(_) => false,
+                });
+
+        let before = self.machine.clone();
+        if self.machine.execute(&reduced).is_err() {
+            // The state of the machine is unknown from here on
+            self.known.clear();
+            output.push(block);
+            return;
+        }
+
+        let known = block.words().iter().filter_map(Setting::of).collect::<Vec<_>>();
+        if !plain {
+            self.known.extend(known);
+            output.push(block);
+            return;
+        }
+
+        let target = self.machine.state();
+        let straight = matches!(target.motion, Some(Motion::Rapid) | Some(Motion::Linear)) && target.compensation.is_none();
+        let inverse_time = target.feed_rate_mode == FeedRateMode::InverseTime;
+
+        let mut words = block.words().to_vec();
+        let mut remaining = reduced.words().to_vec();
+
+        let mut i = 0;
+        while i < words.len() {
+            // Printer words are not known to the machine and kept
+            let j = remaining.iter().position(|word| *word == words[i]);
+            let redundant = j.is_some_and(|j| {
+                if !self.is_candidate(&words[i], straight, inverse_time) {
+                    return false;
+                }
+
+                let mut candidate = remaining.clone();
+                candidate.remove(j);
+
+                let mut machine = before.clone();
+                return machine.execute(&Block::new(candidate)).is_ok() && machine.state() == target;
+            });
+
+            match j {
+                Some(j) if redundant => {
+                    words.remove(i);
+                    remaining.remove(j);
+                }
+                _ => i += 1,
+            }
+        }
+
+        // Moves going nowhere are dropped, also on printers keeping their G words
+        if words.iter().all(is_straight) && before.state() == target {
+            words.clear();
+        }
+
+        self.known.extend(known);
+
+        if words.len() == block.words().len() {
+            output.push(block);
+            return;
+        }
+
+        let mut replaced = Vec::new();
+        replace(&block, if words.is_empty() { Vec::new() } else { vec![words.clone()] }, self.name(), &mut replaced);
+
+        let length = block.to_string().len();
+        match replaced.first() {
+            Some(first) => self.savings.bytes += length - first.to_string().len(),
+            None => {
+                self.savings.blocks += 1;
+                self.savings.bytes += length + 1;
+            }
+        }
+        self.savings.words += block.words().len() - words.len();
+
+        output.extend(replaced);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn remove(pass: &mut RemoveRedundant, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(pass, blocks).iter().map(ToString::to_string).collect();
+    }
+
+    #[test]
+    fn test_remove_redundant() {
+        let mut pass = RemoveRedundant::new();
+        assert_eq!(remove(&mut pass, &[
+            "G21 G90 G0 X0 Y0",
+            "G1 X10 F100",
+            "G1 X10 Y5 F100",
+            "G1 X10 Y5",
+            "G21 G0 Z1",
+            "G0 X0 Z1 (back)",
+            "N10 G1 X0",
+        ]), vec![
+            "G21 G90 G0 X0 Y0",
+            "G1 X10 F100",
+            "Y5",
+            "G0 Z1",
+            "X0 (back)",
+            "N10 G1",
+        ]);
+
+        assert_eq!(pass.savings(), Savings { words: 10, blocks: 1, bytes: 35 });
+        assert_eq!(pass.savings().to_string(), "removed 10 words and 1 blocks, saving 35 bytes");
+    }
+
+    #[test]
+    fn test_remove_redundant_unknown() {
+        // Nothing is known about the machine at the start, but offsets are followed
+        let lines = ["G90 G0 X0 F100", "G92 X5", "G0 X5", "G0 X6"];
+        assert_eq!(remove(&mut RemoveRedundant::new(), &lines), vec!["G90 G0 X0 F100", "G92 X5", "X6"]);
+
+        // Inverse time needs a feed rate with each move
+        let lines = ["G93 G1 X1 F10", "G1 X2 F10"];
+        assert_eq!(remove(&mut RemoveRedundant::new(), &lines), vec!["G93 G1 X1 F10", "X2 F10"]);
+    }
+
+    #[test]
+    fn test_remove_redundant_printer() {
+        let mut pass = RemoveRedundant::new().with_machine(Machine::new().with_dialect(Dialect::Marlin));
+        assert_eq!(remove(&mut pass, &["G1 X1 Y1 F1200", "G1 X2 Y1 E0.5 F1200", "G1 X2 E0.5", "G1 X2"]), vec![
+            "G1 X1 Y1 F1200",
+            "G1 X2 E0.5",
+            "G1 E0.5",
+        ]);
+    }
+}
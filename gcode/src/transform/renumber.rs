@@ -0,0 +1,129 @@
+use crate::Real;
+use crate::parser::Block;
+
+use super::Pass;
+
+/// Numbers all blocks with line numbers (N words) in steps, like `N10`, `N20` and so on.
+///
+/// Blank lines, comments without words and Grbl system commands are left without line number.
+/// Existing line numbers are replaced. With `with_numbered_only`, only blocks which already have a
+/// line number are numbered.
+///
+/// Line numbers are independent of the ones sent with checksums to printers, which the `Sender`
+/// numbers consecutively on its own, dropping those of the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Renumber {
+    next: u64,
+    step: u64,
+    numbered_only: bool,
+}
+
+impl Renumber {
+    /// Creates a pass numbering the first block with `start`, increasing the number by `step`.
+    ///
+    /// Panics if `step` is zero.
+    pub fn new(start: u64, step: u64) -> Self {
+        assert!(step > 0, "step must be positive");
+
+        Self {
+            next: start,
+            step,
+            numbered_only: false,
+        }
+    }
+
+    /// Only renumbers blocks with a line number instead of numbering all.
+    pub fn with_numbered_only(mut self, numbered_only: bool) -> Self {
+        self.numbered_only = numbered_only;
+        self
+    }
+}
+
+impl Pass for Renumber {
+    fn name(&self) -> &'static str {
+        "renumber"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        if block.is_empty() || block.system_command().is_some() || (self.numbered_only && block.line_number().is_none()) {
+            output.push(block);
+            return;
+        }
+
+        let number = self.next as Real;
+        self.next += self.step;
+
+        if block.line_number() == Some(number) {
+            output.push(block);
+            return;
+        }
+
+        output.push(block.with_line_number(Some(number)).with_pass(self.name()));
+    }
+}
+
+/// Removes the line numbers (N words) of all blocks.
+///
+/// Blocks left empty, with a line number only, are dropped.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct StripLineNumbers;
+
+impl StripLineNumbers {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Pass for StripLineNumbers {
+    fn name(&self) -> &'static str {
+        "strip-line-numbers"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        if block.line_number().is_none() {
+            output.push(block);
+            return;
+        }
+
+        if block.is_empty() && block.comments().is_empty() {
+            return;
+        }
+
+        output.push(block.with_line_number(None).with_pass(self.name()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    #[test]
+    fn test_renumber() {
+        let blocks = Parser::new().parse_all(["G0 X1", "(comment)", "", "N10 G1 X2", "N99 M2"].iter()).unwrap();
+        let program = apply(&mut Renumber::new(5, 5), blocks);
+
+        assert_eq!(program.to_string(), "N5 G0 X1\n(comment)\n\nN10 G1 X2\nN15 M2\n");
+        assert!(program.blocks()[3].provenance().passes().is_empty());
+        assert_eq!(program.blocks()[4].provenance().passes(), &["renumber"]);
+    }
+
+    #[test]
+    fn test_renumber_numbered_only() {
+        let blocks = Parser::new().parse_all(["G0 X1", "N7 G1 X2", "G1 X3", "N3 M2"].iter()).unwrap();
+        let program = apply(&mut Renumber::new(100, 1).with_numbered_only(true), blocks);
+
+        assert_eq!(program.to_string(), "G0 X1\nN100 G1 X2\nG1 X3\nN101 M2\n");
+    }
+
+    #[test]
+    fn test_strip_line_numbers() {
+        let blocks = Parser::new().parse_all(["N10 G0 X1", "N20", "N30 (comment)", "G1 X2"].iter()).unwrap();
+        let program = apply(&mut StripLineNumbers::new(), blocks);
+
+        assert_eq!(program.to_string(), "G0 X1\n(comment)\nG1 X2\n");
+        assert_eq!(program.blocks()[0].provenance().passes(), &["strip-line-numbers"]);
+        assert!(program.blocks()[2].provenance().passes().is_empty());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::Real;
+use crate::command::DistanceMode;
+use crate::geometry::Point3;
+use crate::interp::{Machine, Motion, State};
+use crate::parser::{Block, Word};
+use crate::printer::PrinterState;
+
+use super::{is_plain_move, straight_move, Pass};
+
+/// A linear move held back to be simplified.
+#[derive(Debug, Clone)]
+struct Pending {
+    block: Block,
+
+    /// The end of the move in machine coordinates
+    end: Point3,
+
+    /// The filament extruded by the move and the position of the extruder after it
+    extruded: Real,
+    extruder: Real,
+}
+
+/// Merges runs of short feed moves into fewer moves deviating at most `tolerance` from the
+/// original path, using the Douglas-Peucker algorithm.
+///
+/// Runs consist of consecutive blocks with nothing but a linear move along X, Y and Z, optionally
+/// extruding with `E`, without comments or line numbers. A feed rate may only start a run, and
+/// runs either extrude all along or not at all. The filament extruded by merged moves is summed
+/// up, so the extrusion along the path stays proportional to its length. Runs where no move can be
+/// dropped are passed through unchanged.
+#[derive(Debug, Clone)]
+pub struct Simplify {
+    tolerance: Real,
+    machine: Machine,
+    printer: PrinterState,
+
+    /// The start of the current run, the state of the machine within it and whether extrusion
+    /// is relative
+    start: Option<(Point3, State, bool)>,
+    run: Vec<Pending>,
+}
+
+impl Simplify {
+    /// Creates a pass keeping the simplified path within `tolerance` (in millimeters) of the
+    /// original one.
+    ///
+    /// Panics if `tolerance` is negative.
+    pub fn new(tolerance: Real) -> Self {
+        assert!(tolerance >= 0.0, "tolerance must not be negative");
+
+        Self {
+            tolerance,
+            machine: Machine::new(),
+            printer: PrinterState::new(),
+            start: None,
+            run: Vec::new(),
+        }
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// Writes the simplified run to `output`.
+    fn flush(&mut self, output: &mut Vec<Block>) {
+        let run = std::mem::take(&mut self.run);
+        let (start, state, relative) = match self.start.take() {
+            Some(start) => start,
+            None => return,
+        };
+
+        let mut points = vec![start];
+        points.extend(run.iter().map(|pending| pending.end));
+
+        let mut keep = vec![false; points.len()];
+        keep[0] = true;
+        keep[points.len() - 1] = true;
+        douglas_peucker(&points, 0, points.len() - 1, self.tolerance, &mut keep);
+
+        if keep.iter().all(|&keep| keep) {
+            output.extend(run.into_iter().map(|pending| pending.block));
+            return;
+        }
+
+        let extruding = run.iter().any(|pending| pending.block.words().iter().any(|word| word.mnemonic() == 'E'));
+        let mut feed = run[0].block.words().iter().find(|word| word.mnemonic() == 'F').cloned();
+
+        let mut previous = state.to_work(start);
+        let mut extruded = 0.0;
+
+        for (i, pending) in run.iter().enumerate() {
+            extruded += pending.extruded;
+            if !keep[i + 1] {
+                continue;
+            }
+
+            let current = state.to_work(pending.end);
+            let mut words = vec![Word::new('G', 1.0)];
+            for &(axis, current, previous) in &[('X', current.x, previous.x), ('Y', current.y, previous.y), ('Z', current.z, previous.z)] {
+                let value = match state.distance_mode {
+                    DistanceMode::Absolute => current,
+                    DistanceMode::Relative => current - previous,
+                };
+
+                if (current - previous).abs() >= 1e-4 {
+                    words.push(Word::new(axis, (value * 1e4).round() / 1e4));
+                }
+            }
+            if extruding {
+                let e = if relative { extruded } else { pending.extruder };
+                words.push(Word::new('E', (e * 1e5).round() / 1e5));
+            }
+            words.extend(feed.take());
+
+            output.push(Block::new(words)
+                    .with_provenance(pending.block.provenance().clone())
+                    .with_pass(self.name()));
+
+            previous = current;
+            extruded = 0.0;
+        }
+    }
+}
+
+/// Marks the points between `first` and `last` to keep, such that the path through them deviates
+/// at most `tolerance` from the path through all points.
+fn douglas_peucker(points: &[Point3], first: usize, last: usize, tolerance: Real, keep: &mut [bool]) {
+    let farthest = (first + 1..last)
+            .map(|i| (i, distance(points[i], points[first], points[last])))
+            .fold(None, |farthest: Option<(usize, Real)>, (i, distance)| match farthest {
+                Some((_, max)) if max >= distance => farthest,
+                _ => Some((i, distance)),
+            });
+
+    if let Some((i, distance)) = farthest {
+        if distance > tolerance {
+            keep[i] = true;
+            douglas_peucker(points, first, i, tolerance, keep);
+            douglas_peucker(points, i, last, tolerance, keep);
+        }
+    }
+}
+
+/// The distance of the point from the line segment between `a` and `b`.
+fn distance(point: Point3, a: Point3, b: Point3) -> Real {
+    let (dx, dy, dz) = (b.x - a.x, b.y - a.y, b.z - a.z);
+    let length = dx * dx + dy * dy + dz * dz;
+    if length == 0.0 {
+        return point.distance(a);
+    }
+
+    let t = (((point.x - a.x) * dx + (point.y - a.y) * dy + (point.z - a.z) * dz) / length).clamp(0.0, 1.0);
+    return point.distance(Point3::new(a.x + dx * t, a.y + dy * t, a.z + dz * t));
+}
+
+impl Pass for Simplify {
+    fn name(&self) -> &'static str {
+        "simplify"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let extruder = self.printer.extruder;
+        let moved = straight_move(&mut self.machine, &mut self.printer, &block);
+        let extrusion = self.printer.extruder - extruder;
+        let linear = self.machine.state().motion == Some(Motion::Linear);
+
+        match moved {
+            Some((from, to)) if is_plain_move(&block) && linear && extrusion >= 0.0 => {
+                // A feed rate or a change between extruding and travelling starts a new run
+                let feed = block.words().iter().any(|word| word.mnemonic() == 'F');
+                let continues = self.run.first().is_some_and(|first| !feed && (first.extruded > 0.0) == (extrusion > 0.0));
+
+                if !continues {
+                    self.flush(output);
+                    self.start = Some((from, self.machine.state().clone(), self.printer.relative_extrusion));
+                }
+
+                self.run.push(Pending {
+                    block,
+                    end: to,
+                    extruded: extrusion,
+                    extruder: self.printer.extruder,
+                });
+            }
+            _ => {
+                self.flush(output);
+                output.push(block);
+            }
+        }
+    }
+
+    fn finish(&mut self, output: &mut Vec<Block>) {
+        self.flush(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn simplify(tolerance: Real, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut Simplify::new(tolerance), blocks).iter().map(ToString::to_string).collect();
+    }
+
+    #[test]
+    fn test_simplify() {
+        assert_eq!(simplify(0.01, &["G0 X0 Y0", "G1 X1 Y0.001 F100", "G1 X2", "G1 X3 Y-0.001", "G1 X4 Y0", "G1 X4 Y4", "M5"]), vec![
+            "G0 X0 Y0",
+            "G1 X4 F100",
+            "G1 Y4",
+            "M5",
+        ]);
+
+        // Deviations beyond the tolerance are kept
+        assert_eq!(simplify(0.01, &["G1 X1 Y0.1 F100", "G1 X2 Y0"]), vec!["G1 X1 Y0.1 F100", "G1 X2 Y0"]);
+
+        // Comments and other words end runs
+        assert_eq!(simplify(0.01, &["G1 X1 F100", "G1 X2 (keep)", "G1 X3", "M3 S100", "G1 X4", "G1 X5"]), vec![
+            "G1 X1 F100",
+            "G1 X2 (keep)",
+            "G1 X3",
+            "M3 S100",
+            "G1 X5",
+        ]);
+    }
+
+    #[test]
+    fn test_simplify_relative() {
+        assert_eq!(simplify(0.01, &["G91 G1 X1 F100", "G1 X1", "G1 X1", "G1 Y1"]), vec![
+            "G91 G1 X1 F100",
+            "G1 X2",
+            "G1 Y1",
+        ]);
+    }
+
+    #[test]
+    fn test_simplify_extrusion() {
+        assert_eq!(simplify(0.01, &["G1 X1 E0.1 F1200", "G1 X2 E0.2", "G1 X3 E0.3", "G1 X4", "G1 X5"]), vec![
+            "G1 X3 E0.3 F1200",
+            "G1 X5",
+        ]);
+
+        assert_eq!(simplify(0.01, &["M83", "G1 X1 E0.1 F1200", "G1 X2 E0.1", "G1 X3 E0.2", "G1 E-1", "G1 X4 E0.1"]), vec![
+            "M83",
+            "G1 X3 E0.4 F1200",
+            "G1 E-1",
+            "G1 X4 E0.1",
+        ]);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::Real;
+use crate::command::DistanceMode;
+use crate::geometry::Point3;
+use crate::interp::Machine;
+use crate::parser::Block;
+use crate::printer::PrinterState;
+
+use super::{replace, straight_move, with_axes, Pass};
+
+/// Corrects the skew of machines whose axes are not exactly perpendicular to each other.
+///
+/// The factors are those of Marlin's `M852`: the tangents of the angles by which the axes deviate
+/// from being square. Positions are corrected like Marlin does, moving X by `y * xy` and by
+/// `z * (xz - xy * yz)`, and Y by `z * yz`, in opposite direction.
+///
+/// As straight lines stay straight, straight moves only get their end corrected. Arcs would turn
+/// into ellipses and are passed through unchanged, so they should be flattened before. Blocks which
+/// can not be interpreted are passed through unchanged as well.
+#[derive(Debug, Clone)]
+pub struct Skew {
+    xy: Real,
+    xz: Real,
+    yz: Real,
+    machine: Machine,
+    printer: PrinterState,
+}
+
+impl Skew {
+    pub fn new(xy: Real, xz: Real, yz: Real) -> Self {
+        Self {
+            xy,
+            xz,
+            yz,
+            machine: Machine::new(),
+            printer: PrinterState::new(),
+        }
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// The position the machine has to move to for reaching the given one.
+    pub fn correct(&self, point: Point3) -> Point3 {
+        Point3::new(point.x - point.y * self.xy - point.z * (self.xz - self.xy * self.yz),
+                    point.y - point.z * self.yz,
+                    point.z)
+    }
+}
+
+impl Pass for Skew {
+    fn name(&self) -> &'static str {
+        "skew"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let (from, to) = match straight_move(&mut self.machine, &mut self.printer, &block) {
+            Some(found) => found,
+            None => {
+                output.push(block);
+                return;
+            }
+        };
+
+        if self.correct(from) == from && self.correct(to) == to {
+            output.push(block);
+            return;
+        }
+
+        let state = self.machine.state();
+        let start = state.to_work(self.correct(from));
+        let end = state.to_work(self.correct(to));
+
+        let present = |axis: char| block.words().iter().any(|word| word.mnemonic() == axis);
+        let mut axes = Vec::new();
+        for &(axis, start, end) in &[('X', start.x, end.x), ('Y', start.y, end.y), ('Z', start.z, end.z)] {
+            let value = match state.distance_mode {
+                DistanceMode::Absolute => end,
+                DistanceMode::Relative => end - start,
+            };
+
+            if present(axis) || (end - start).abs() >= 1e-4 {
+                axes.push((axis, value));
+            }
+        }
+
+        let mut replaced = Vec::new();
+        replace(&block, vec![with_axes(&block, &axes)], self.name(), &mut replaced);
+        output.extend(replaced.into_iter().map(|replaced| replaced.with_deleted(block.is_deleted())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn skew(pass: Skew, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut pass.clone(), blocks).iter().map(ToString::to_string).collect();
+    }
+
+    #[test]
+    fn test_skew() {
+        let pass = Skew::new(0.01, 0.0, 0.02);
+
+        assert_eq!(skew(pass.clone(), &["G0 X10 ; start", "G1 Y100 F600 E5", "G1 Z10", "G2 X0 Y0 I-5 J-50", "M2"]), vec![
+            "G0 X10 ; start",
+            "G1 X9 Y100 F600 E5",
+            "G1 X9.002 Y99.8 Z10",
+            "G2 X0 Y0 I-5 J-50",
+            "M2",
+        ]);
+
+        // Relative moves are corrected by the difference of the corrections
+        assert_eq!(skew(pass, &["G91 G1 X10 Y10 F100", "G1 Z5"]), vec![
+            "G91 G1 X9.9 Y10 F100",
+            "G1 X0.001 Y-0.1 Z5",
+        ]);
+
+        assert_eq!(skew(Skew::new(0.0, 0.0, 0.0), &["G0 X1 Y1 Z1"]), vec!["G0 X1 Y1 Z1"]);
+    }
+}
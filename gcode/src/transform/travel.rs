@@ -0,0 +1,417 @@
+use crate::Real;
+use crate::command::DistanceMode;
+use crate::geometry::{Point, Point3};
+use crate::interp::{Machine, Motion, State};
+use crate::modal::ModalGroup;
+use crate::parser::{Block, Word};
+use crate::printer::PrinterState;
+
+use super::{replace, Pass};
+
+/// A travel followed by the feed moves it leads to, up to the next travel.
+#[derive(Debug, Clone)]
+struct Group {
+    blocks: Vec<Block>,
+
+    /// The position before the group, where its first feed move starts and where its last one
+    /// ends, in machine coordinates
+    origin: Point3,
+    start: Option<Point3>,
+    end: Point3,
+
+    /// The modal state besides position, motion and feed rate before and after the group
+    before: State,
+    after: State,
+
+    /// The axes among X, Y and Z given before the first feed move
+    given: [bool; 3],
+
+    /// The feed rate the first feed move relies on, if the group does not set it itself, and the
+    /// last one the group sets
+    feed: Option<Real>,
+    feed_set: Option<Real>,
+
+    /// Whether all blocks are interpreted in absolute distance mode without non-modal codes
+    absolute: bool,
+}
+
+impl Group {
+    /// Whether the group moves to a fixed position regardless of where it starts.
+    fn is_independent(&self) -> bool {
+        self.absolute && self.given.iter().all(|&given| given)
+    }
+
+    /// Whether the group may be moved anywhere among others of the same modal state.
+    fn is_movable(&self) -> bool {
+        self.is_independent() && self.start.is_some() && self.before == self.after
+    }
+}
+
+/// The distance travelled in the XY plane from the position to the start of the group.
+fn travel(position: Point3, group: &Group) -> Real {
+    let start = group.start.unwrap_or(group.end);
+    Point::new(position.x, position.y).distance(Point::new(start.x, start.y))
+}
+
+/// The modal state without the position, motion and feed rate, which groups may differ in.
+fn modal(state: &State) -> State {
+    let mut modal = state.clone();
+    modal.position = Point3::new(0.0, 0.0, 0.0);
+    modal.motion = None;
+    modal.feed_rate = None;
+    return modal;
+}
+
+/// Reorders the paths of a program to shorten the rapid moves between them, like for plotting or
+/// engraving many disconnected shapes.
+///
+/// A path starts with a run of rapid moves, the travel, and continues with the feed moves it leads
+/// to until the next travel or tool change. Paths are reordered among neighbours starting with the
+/// same modal state, so tool changes or other changes of the spindle or units separate them. Only
+/// paths in absolute distance mode, giving X, Y and Z before their first feed move, ending in the
+/// state they start with and without non-modal codes like `G92` are moved. Others stay in place,
+/// and so does the last path before one depending on where it starts.
+///
+/// Paths are ordered greedily by visiting the nearest one next, optionally improved by 2-opt,
+/// which reverses the order of paths in between as long as that shortens the total travel. Paths
+/// are never reversed themselves. Feed rates are added to the first block of paths which relied
+/// on one set before.
+#[derive(Debug, Clone)]
+pub struct ReorderPaths {
+    two_opt: bool,
+    machine: Machine,
+    printer: PrinterState,
+
+    /// The path read so far and whether it is still travelling
+    current: Option<Group>,
+    travelling: bool,
+
+    /// Movable paths starting with the same modal state, to be reordered
+    region: Vec<Group>,
+
+    /// The feed rate set by the program as read and as written
+    feed: Option<Real>,
+    written: Option<Real>,
+}
+
+impl ReorderPaths {
+    pub fn new() -> Self {
+        Self {
+            two_opt: false,
+            machine: Machine::new(),
+            printer: PrinterState::new(),
+            current: None,
+            travelling: false,
+            region: Vec::new(),
+            feed: None,
+            written: None,
+        }
+    }
+
+    /// Improves the greedy order with 2-opt, which takes time quadratic in the number of paths for
+    /// each improvement.
+    pub fn with_two_opt(mut self, two_opt: bool) -> Self {
+        self.two_opt = two_opt;
+        self
+    }
+
+    /// Uses the given machine to interpret the program, e.g. to configure work offsets.
+    pub fn with_machine(mut self, machine: Machine) -> Self {
+        self.machine = machine;
+        self
+    }
+
+    /// Ends the current path, reordering the paths before it if it can not join them.
+    fn close(&mut self, output: &mut Vec<Block>) {
+        let group = match self.current.take() {
+            Some(group) => group,
+            None => return,
+        };
+
+        if group.is_movable() && self.region.first().is_none_or(|first| first.before == group.before) {
+            self.region.push(group);
+        } else if group.is_movable() {
+            self.reorder(false, output);
+            self.region.push(group);
+        } else {
+            self.reorder(!group.is_independent(), output);
+            self.write(group, output);
+        }
+    }
+
+    /// Writes the paths of the region in a shorter order, keeping the last one in place if `pin`.
+    fn reorder(&mut self, pin: bool, output: &mut Vec<Block>) {
+        let mut groups = std::mem::take(&mut self.region);
+        let last = if pin { groups.pop() } else { None };
+
+        let mut order = Vec::with_capacity(groups.len());
+        if let Some(first) = groups.first() {
+            let mut position = first.origin;
+            let mut visited = vec![false; groups.len()];
+
+            while order.len() < groups.len() {
+                let next = (0..groups.len())
+                        .filter(|&i| !visited[i])
+                        .map(|i| (i, travel(position, &groups[i])))
+                        .fold(None, |nearest: Option<(usize, Real)>, (i, distance)| match nearest {
+                            Some((_, min)) if min <= distance => nearest,
+                            _ => Some((i, distance)),
+                        })
+                        .map(|(i, _)| i)
+                        .expect("unvisited path");
+
+                visited[next] = true;
+                order.push(next);
+                position = groups[next].end;
+            }
+
+            if self.two_opt {
+                two_opt(&groups, first.origin, last.as_ref(), &mut order);
+            }
+        }
+
+        let mut groups = groups.into_iter().map(Some).collect::<Vec<_>>();
+        for i in order {
+            let group = groups[i].take().expect("path visited once");
+            self.write(group, output);
+        }
+
+        if let Some(last) = last {
+            self.write(last, output);
+        }
+    }
+
+    /// Writes the path, adding the feed rate it relies on if the paths written before differ.
+    fn write(&mut self, group: Group, output: &mut Vec<Block>) {
+        let mut blocks = group.blocks.into_iter();
+
+        if let Some(feed) = group.feed.filter(|&feed| self.written != Some(feed)) {
+            let first = blocks.next().expect("path with blocks");
+            let mut words = first.words().to_vec();
+            words.push(Word::new('F', feed));
+            replace(&first, vec![words], self.name(), output);
+            self.written = Some(feed);
+        }
+
+        output.extend(blocks);
+        if group.feed_set.is_some() {
+            self.written = group.feed_set;
+        }
+    }
+}
+
+/// The travel of the paths in the given order, from the origin to the start of `last`, if any.
+fn length(groups: &[Group], origin: Point3, last: Option<&Group>, order: &[usize]) -> Real {
+    let mut length = 0.0;
+    let mut position = origin;
+
+    for group in order.iter().map(|&i| &groups[i]).chain(last) {
+        length += travel(position, group);
+        position = group.end;
+    }
+
+    return length;
+}
+
+/// Reverses parts of the order as long as that shortens the travel.
+fn two_opt(groups: &[Group], origin: Point3, last: Option<&Group>, order: &mut [usize]) {
+    let mut best = length(groups, origin, last, order);
+
+    let mut improved = true;
+    while improved {
+        improved = false;
+
+        for i in 0..order.len() {
+            for j in i + 1..order.len() {
+                order[i..=j].reverse();
+                let length = length(groups, origin, last, order);
+                if length < best - 1e-9 {
+                    best = length;
+                    improved = true;
+                } else {
+                    order[i..=j].reverse();
+                }
+            }
+        }
+    }
+}
+
+impl Default for ReorderPaths {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pass for ReorderPaths {
+    fn name(&self) -> &'static str {
+        "reorder-paths"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        let (_, reduced) = self.printer.apply(&block);
+
+        let before = self.machine.state().clone();
+        let executed = block.expressions().is_empty() && self.machine.execute(&reduced).is_ok();
+        let state = self.machine.state().clone();
+
+        let moves = reduced.words().iter().any(|word| matches!(word.mnemonic(), 'X' | 'Y' | 'Z'));
+        let non_modal = reduced.words().iter()
+                .any(|word| word.mnemonic() == 'G' && ModalGroup::of(word) == Some(ModalGroup::NonModal));
+        let rapid = executed && moves && !non_modal && state.motion == Some(Motion::Rapid);
+        let feed = executed && moves && !non_modal && state.motion.is_some() && !rapid;
+
+        // A rapid move after feed moves starts the next path, as does a tool change
+        let tool_change = executed && state.tool != before.tool;
+        if (rapid && !self.travelling) || tool_change {
+            self.close(output);
+            self.current = Some(Group {
+                blocks: Vec::new(),
+                origin: before.position,
+                start: None,
+                end: before.position,
+                before: modal(&before),
+                after: modal(&before),
+                given: [false; 3],
+                feed: None,
+                feed_set: None,
+                absolute: true,
+            });
+            self.travelling = true;
+        }
+
+        let feed_word = block.words().iter().rev().find(|word| word.mnemonic() == 'F').map(Word::value);
+
+        let group = match self.current.as_mut() {
+            Some(group) => group,
+            None => {
+                // Blocks before the first path are written right away
+                output.push(block);
+                self.feed = feed_word.or(self.feed);
+                self.written = self.feed;
+                return;
+            }
+        };
+
+        if self.travelling {
+            for word in reduced.words() {
+                match word.mnemonic() {
+                    'X' => group.given[0] = true,
+                    'Y' => group.given[1] = true,
+                    'Z' => group.given[2] = true,
+                    _ => {}
+                }
+            }
+        }
+
+        if feed && group.start.is_none() {
+            group.start = Some(before.position);
+            if group.feed_set.is_none() && feed_word.is_none() {
+                group.feed = self.feed;
+            }
+        }
+
+        group.absolute &= executed && !non_modal && state.distance_mode == DistanceMode::Absolute;
+        group.end = state.position;
+        group.after = modal(&state);
+        group.feed_set = feed_word.or(group.feed_set);
+        group.blocks.push(block);
+
+        self.travelling &= !feed;
+        self.feed = feed_word.or(self.feed);
+    }
+
+    fn finish(&mut self, output: &mut Vec<Block>) {
+        self.close(output);
+        self.reorder(false, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn reorder(mut pass: ReorderPaths, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+        return apply(&mut pass, blocks).iter().map(ToString::to_string).collect();
+    }
+
+    /// A path drawing a short line at the given position.
+    fn path(x: Real, y: Real) -> Vec<String> {
+        vec![
+            "G0 Z1".to_owned(),
+            format!("G0 X{} Y{}", x, y),
+            "G1 Z-1".to_owned(),
+            format!("G1 X{}", x + 1.0),
+        ]
+    }
+
+    fn program(paths: &[(Real, Real)], end: &[&str]) -> Vec<String> {
+        let mut lines = vec!["G21 G90 F100".to_owned()];
+        lines.extend(paths.iter().flat_map(|&(x, y)| path(x, y)));
+        lines.extend(end.iter().map(|line| line.to_string()));
+        return lines;
+    }
+
+    fn lines(lines: &[String]) -> Vec<&str> {
+        lines.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn test_reorder_paths() {
+        let lines = program(&[(10.0, 0.0), (0.0, 0.0), (20.0, 0.0), (5.0, 0.0)], &["G0 Z10", "G0 X0 Y0", "M30"]);
+        assert_eq!(reorder(ReorderPaths::new(), &self::lines(&lines)), program(&[(0.0, 0.0), (5.0, 0.0), (10.0, 0.0), (20.0, 0.0)], &["G0 Z10", "G0 X0 Y0", "M30"]));
+    }
+
+    #[test]
+    fn test_reorder_paths_two_opt() {
+        // Going to the nearest path first leaves a long way back
+        let paths = [(-4.0, 5.0), (7.0, 7.0), (5.0, 2.0), (10.0, -6.0)];
+        let lines = program(&paths, &[]);
+
+        let greedy = reorder(ReorderPaths::new(), &self::lines(&lines));
+        assert_eq!(greedy, program(&[(5.0, 2.0), (7.0, 7.0), (-4.0, 5.0), (10.0, -6.0)], &[]));
+
+        let improved = reorder(ReorderPaths::new().with_two_opt(true), &self::lines(&lines));
+        assert_eq!(improved, program(&paths, &[]));
+    }
+
+    #[test]
+    fn test_reorder_paths_boundaries() {
+        // Paths are not moved across tool changes and the one before a relative move stays last
+        let mut lines = program(&[(10.0, 0.0), (0.0, 0.0)], &["T2 M6"]);
+        for &x in &[30.0, 20.0, 28.0, 25.0] {
+            lines.extend(path(x, 0.0));
+        }
+        lines.extend(["G91 G0 Z5".to_owned(), "G0 X1".to_owned(), "G1 X1".to_owned()]);
+
+        let mut expected = program(&[(0.0, 0.0), (10.0, 0.0)], &["T2 M6"]);
+        for &x in &[30.0, 28.0, 20.0, 25.0] {
+            expected.extend(path(x, 0.0));
+        }
+        expected.extend(["G91 G0 Z5".to_owned(), "G0 X1".to_owned(), "G1 X1".to_owned()]);
+
+        assert_eq!(reorder(ReorderPaths::new(), &self::lines(&lines)), expected);
+    }
+
+    #[test]
+    fn test_reorder_paths_feed() {
+        let lines = ["G90", "G0 Z1", "G0 X10 Y0", "G1 Z-1 F100", "G1 X11", "G0 Z1", "G0 X0 Y0", "G1 Z-1", "G1 X1 F200", "G0 Z1", "G0 X5 Y0", "G1 Z-1", "G1 X6"];
+        assert_eq!(reorder(ReorderPaths::new(), &lines), vec![
+            "G90",
+            "G0 Z1 F100",
+            "G0 X0 Y0",
+            "G1 Z-1",
+            "G1 X1 F200",
+            "G0 Z1",
+            "G0 X5 Y0",
+            "G1 Z-1",
+            "G1 X6",
+            "G0 Z1",
+            "G0 X10 Y0",
+            "G1 Z-1 F100",
+            "G1 X11",
+        ]);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::geometry::Units;
+use crate::parser::{Block, Word};
+
+use super::Pass;
+
+/// Converts all lengths of a program to a single unit.
+///
+/// Coordinates, arc parameters and feed rates are scaled from the units active at each block to
+/// the target units, leaving the angles of the rotary axes A, B and C as they are. The G20 and G21 words are removed and a single block selecting the target
+/// units is emitted at the start of the program instead. Blocks left empty by this are dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvertUnits {
+    target: Units,
+    current: Units,
+    started: bool,
+}
+
+impl ConvertUnits {
+    /// Creates a pass converting to the given units, assuming the program starts in millimeters.
+    pub fn new(target: Units) -> Self {
+        Self {
+            target,
+            current: Units::Millimeters,
+            started: false,
+        }
+    }
+
+    /// Sets the units active before the first block of the program.
+    pub fn initial(mut self, units: Units) -> Self {
+        self.current = units;
+        self
+    }
+
+    fn units(word: &Word) -> Option<Units> {
+        return match (word.mnemonic(), word.value()) {
+            ('G', 20.0) => Some(Units::Inches),
+            ('G', 21.0) => Some(Units::Millimeters),
+            _ => None,
+        };
+    }
+}
+
+impl Pass for ConvertUnits {
+    fn name(&self) -> &'static str {
+        "units"
+    }
+
+    fn process(&mut self, block: Block, output: &mut Vec<Block>) {
+        if !self.started {
+            self.started = true;
+
+            let code = match self.target {
+                Units::Inches => 20.0,
+                Units::Millimeters => 21.0,
+            };
+            output.push(Block::new(vec![Word::new('G', code)]).with_pass(self.name()));
+        }
+
+        // Switching units affects all words of the block
+        let switches = block.words().iter().filter_map(Self::units).next_back();
+        if let Some(units) = switches {
+            self.current = units;
+        }
+
+        let scale = self.current.millimeters() / self.target.millimeters();
+        if switches.is_none() && scale == 1.0 {
+            output.push(block);
+            return;
+        }
+
+        let words = block.words().iter()
+                .filter(|word| Self::units(word).is_none())
+                .map(|word| match word.mnemonic() {
+                    'X' | 'Y' | 'Z' | 'U' | 'V' | 'W' | 'I' | 'J' | 'K' | 'R' | 'F' => Word::new(word.mnemonic(), word.value() * scale),
+                    _ => *word,
+                })
+                .collect::<Vec<_>>();
+
+        if words.is_empty() && block.line_number().is_none() {
+            return;
+        }
+
+        output.push(Block::new(words)
+                .with_line_number(block.line_number())
+                .with_deleted(block.is_deleted())
+                .with_provenance(block.provenance().clone())
+                .with_pass(self.name()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::transform::apply;
+
+    fn convert(mut pass: ConvertUnits, lines: &[&str]) -> Vec<String> {
+        let blocks = Parser::new().parse_all(lines.iter()).unwrap();
+
+        return apply(&mut pass, blocks).iter()
+                .map(|block| block.line().to_owned())
+                .collect();
+    }
+
+    #[test]
+    fn test_to_millimeters() {
+        assert_eq!(convert(ConvertUnits::new(Units::Millimeters), &[
+            "G21",
+            "G0 X1 Y2",
+            "G20 G1 X1 F10",
+            "G2 X2 Y0 I0.5 J-0.5",
+            "G0 A90 W1",
+            "M3 S1000",
+        ]), vec![
+            "G21",
+            "G0 X1 Y2",
+            "G1 X25.4 F254",
+            "G2 X50.8 Y0 I12.7 J-12.7",
+            "G0 A90 W25.4",
+            "M3 S1000",
+        ]);
+    }
+
+    #[test]
+    fn test_to_inches() {
+        assert_eq!(convert(ConvertUnits::new(Units::Inches).initial(Units::Inches), &[
+            "X1",
+            "N10 G21",
+            "X25.4",
+        ]), vec![
+            "G20",
+            "X1",
+            "N10",
+            "X1",
+        ]);
+    }
+}
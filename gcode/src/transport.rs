@@ -0,0 +1,229 @@
+//! Transports connecting a sender to networked controllers and, with the `serial` feature on
+//! Unix, to controllers on serial ports.
+//!
+//! Smoothieware and ESP3D accept G-code on a telnet port, replying just like on a serial line. The
+//! telnet protocol is spoken as far as necessary to ignore option negotiation.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+#[cfg(all(feature = "serial", unix))]
+pub use self::serial::Serial;
+
+#[cfg(all(feature = "serial", unix))]
+mod serial;
+
+/// The telnet port, as used by Smoothieware and ESP3D.
+pub const TELNET_PORT: u16 = 23;
+
+/// Interpret as command.
+const IAC: u8 = 255;
+
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+/// Where the receiver is within a telnet command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Command {
+    None,
+
+    /// After an IAC
+    Started,
+
+    /// After WILL, WONT, DO or DONT, expecting the option
+    Negotiation,
+
+    /// Within a subnegotiation, possibly after an IAC
+    Subnegotiation(bool),
+}
+
+/// A connection to a telnet G-code endpoint.
+///
+/// The connection is lost if reading returns no bytes (see `SenderError::Disconnected`) or
+/// writing fails. Connections dropped silently are detected by the timeout of the sender (see
+/// `Sender::with_timeout`), as reads time out after the read timeout of the transport.
+#[derive(Debug)]
+pub struct Telnet {
+    stream: TcpStream,
+    command: Command,
+}
+
+impl Telnet {
+    /// Connects to the first reachable address, waiting up to `timeout` for each.
+    ///
+    /// Reads time out after a second, and writes are not delayed.
+    pub fn connect<A>(addr: A, timeout: Duration) -> io::Result<Self>
+        where A: ToSocketAddrs {
+        let mut last = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Self::with_stream(stream),
+                Err(err) => last = Some(err),
+            }
+        }
+
+        return Err(last.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")));
+    }
+
+    /// Uses an established connection.
+    pub fn with_stream(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+        return Ok(Self {
+            stream,
+            command: Command::None,
+        });
+    }
+
+    /// Sets the time after which reads fail with `TimedOut` or `WouldBlock`.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Opens a second handle to the connection, e.g. for injecting real-time commands while the
+    /// sender waits for a reply (see `Realtime::write_to`).
+    pub fn try_clone(&self) -> io::Result<Self> {
+        return Ok(Self {
+            stream: self.stream.try_clone()?,
+            command: Command::None,
+        });
+    }
+
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+
+    /// Removes telnet commands from the received bytes in place, returning the remaining length.
+    fn strip(&mut self, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        for i in 0..buf.len() {
+            let b = buf[i];
+            self.command = match (self.command, b) {
+                (Command::None, IAC) => Command::Started,
+                (Command::None, _) => {
+                    buf[n] = b;
+                    n += 1;
+                    Command::None
+                }
+
+                // An escaped data byte
+                (Command::Started, IAC) => {
+                    buf[n] = b;
+                    n += 1;
+                    Command::None
+                }
+                (Command::Started, SB) => Command::Subnegotiation(false),
+                (Command::Started, 251..=254) => Command::Negotiation,
+                (Command::Started, _) => Command::None,
+
+                (Command::Negotiation, _) => Command::None,
+
+                (Command::Subnegotiation(true), SE) => Command::None,
+                (Command::Subnegotiation(_), b) => Command::Subnegotiation(b == IAC),
+            };
+        }
+
+        return n;
+    }
+}
+
+impl Read for Telnet {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.stream.read(buf)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            // Do not report the end of the stream for reads holding commands only
+            let n = self.strip(&mut buf[..n]);
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+}
+
+impl Write for Telnet {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.contains(&IAC) {
+            return self.stream.write(buf);
+        }
+
+        let mut escaped = Vec::with_capacity(buf.len() + 1);
+        for &b in buf {
+            escaped.push(b);
+            if b == IAC {
+                escaped.push(IAC);
+            }
+        }
+        self.stream.write_all(&escaped)?;
+
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::parser::Parser;
+    use crate::sender::{Sender, SenderError};
+
+    #[test]
+    fn test_strip() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut telnet = Telnet::connect(listener.local_addr().unwrap(), Duration::from_secs(1)).unwrap();
+
+        let mut buf = *b"o\xff\xfb\x01k\xff\xff\xff";
+        assert_eq!(telnet.strip(&mut buf), 3);
+        assert_eq!(&buf[..3], b"ok\xff");
+
+        let mut buf = *b"\xfa\x18\x00\xff\xf0\n";
+        assert_eq!(telnet.strip(&mut buf), 1);
+        assert_eq!(&buf[..1], b"\n");
+    }
+
+    #[test]
+    fn test_sender() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let controller = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"\xff\xfd\x03Smoothie\r\n").unwrap();
+
+            let mut received = Vec::new();
+            let mut buf = [0u8; 64];
+            while received.iter().filter(|&&b| b == b'\n').count() < 2 {
+                let n = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+                stream.write_all(&b"ok\r\n".repeat(buf[..n].iter().filter(|&&b| b == b'\n').count())).unwrap();
+            }
+
+            // Drop the connection
+            return received;
+        });
+
+        let program = Parser::new().parse_all("G0 X1\nG0 X2\nG0 X3\n".lines()).unwrap().into_iter().collect();
+        let telnet = Telnet::connect(addr, Duration::from_secs(1)).unwrap();
+        let mut sender = Sender::new(telnet, &program).with_timeout(Duration::from_secs(5));
+
+        // Depending on timing, the lost connection is either closed or reset
+        assert!(matches!(sender.run(), Err(SenderError::Disconnected) | Err(SenderError::Io(_))));
+        assert_eq!(controller.join().unwrap(), b"G0 X1\nG0 X2\n");
+        assert_eq!(sender.progress().lines_acknowledged, 2);
+    }
+}
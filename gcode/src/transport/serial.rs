@@ -0,0 +1,277 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// How long DTR is dropped to reset a board.
+const RESET_PULSE: Duration = Duration::from_millis(100);
+
+/// Fails with the last OS error if a libc call failed.
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    return Ok(());
+}
+
+/// The termios speed for a baud rate, for those supported on all Unix systems.
+fn speed(baud: u32) -> Option<libc::speed_t> {
+    Some(match baud {
+        9600 => libc::B9600,
+        19200 => libc::B19200,
+        38400 => libc::B38400,
+        57600 => libc::B57600,
+        115200 => libc::B115200,
+        230400 => libc::B230400,
+        _ => return None,
+    })
+}
+
+/// A serial port, like the USB serial port of a printer or a board running Grbl.
+///
+/// Ports are opened with 8 data bits, no parity, one stop bit and without flow control. The
+/// connection is lost if reading or writing fails, like when the USB cable is unplugged. Reads
+/// time out like those of `Telnet`, so silent controllers are detected by the timeout of the
+/// sender (see `Sender::with_timeout`).
+///
+/// Opening a port raises DTR, which resets most boards with an Arduino bootloader, like many
+/// running Marlin or Grbl. They print a banner while starting and are not ready for a few seconds
+/// (see `settle`). Closing a port drops DTR unless hangup is disabled (see `set_hangup`), so such
+/// boards are not reset by reconnecting, and jobs continue with `Sender::reconnect`.
+#[derive(Debug)]
+pub struct Serial {
+    file: File,
+    path: PathBuf,
+    baud: u32,
+}
+
+impl Serial {
+    /// Opens the port at the given baud rate, one of 9600, 19200, 38400, 57600, 115200 and
+    /// 230400. Rates like 250000 are not supported by termios.
+    ///
+    /// Reads time out after a second.
+    pub fn open<P>(path: P, baud: u32) -> io::Result<Self>
+        where P: AsRef<Path> {
+        let speed = speed(baud)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported baud rate: {}", baud)))?;
+
+        let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NOCTTY)
+                .open(path.as_ref())?;
+
+        let serial = Self {
+            file,
+            path: path.as_ref().to_owned(),
+            baud,
+        };
+
+        let mut termios = serial.termios()?;
+        unsafe { libc::cfmakeraw(&mut termios) };
+        termios.c_cflag |= libc::CLOCAL | libc::CREAD;
+        termios.c_cflag &= !(libc::CSTOPB | libc::CRTSCTS);
+        termios.c_iflag &= !(libc::IXON | libc::IXOFF | libc::IXANY);
+        check(unsafe { libc::cfsetspeed(&mut termios, speed) })?;
+        serial.set_termios(&termios)?;
+
+        serial.set_read_timeout(Some(Duration::from_secs(1)))?;
+        check(unsafe { libc::tcflush(serial.file.as_raw_fd(), libc::TCIOFLUSH) })?;
+
+        return Ok(serial);
+    }
+
+    /// Opens the same port again with the same baud rate, e.g. for `Sender::reconnect`.
+    pub fn reopen(&self) -> io::Result<Self> {
+        Self::open(&self.path, self.baud)
+    }
+
+    /// Opens a second handle to the port, e.g. for injecting real-time commands while the sender
+    /// waits for a reply (see `Realtime::write_to`).
+    pub fn try_clone(&self) -> io::Result<Self> {
+        return Ok(Self {
+            file: self.file.try_clone()?,
+            path: self.path.clone(),
+            baud: self.baud,
+        });
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn baud(&self) -> u32 {
+        self.baud
+    }
+
+    /// Sets the time after which reads fail with `TimedOut`, in steps of a tenth of a second up to
+    /// 25.5 seconds, or lets them block until data arrives.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let (min, time) = match timeout {
+            Some(timeout) if timeout == Duration::ZERO => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "zero timeout"));
+            }
+            Some(timeout) => (0, timeout.as_millis().div_ceil(100).min(255) as libc::cc_t),
+            None => (1, 0),
+        };
+
+        let mut termios = self.termios()?;
+        termios.c_cc[libc::VMIN] = min;
+        termios.c_cc[libc::VTIME] = time;
+        return self.set_termios(&termios);
+    }
+
+    /// Raises or drops DTR.
+    pub fn set_dtr(&self, level: bool) -> io::Result<()> {
+        let request = if level { libc::TIOCMBIS } else { libc::TIOCMBIC };
+        let bits: libc::c_int = libc::TIOCM_DTR;
+        check(unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, &bits) })
+    }
+
+    /// Whether closing the port drops DTR, which is the default.
+    pub fn set_hangup(&self, hangup: bool) -> io::Result<()> {
+        let mut termios = self.termios()?;
+        if hangup {
+            termios.c_cflag |= libc::HUPCL;
+        } else {
+            termios.c_cflag &= !libc::HUPCL;
+        }
+        return self.set_termios(&termios);
+    }
+
+    /// Resets the board by dropping DTR for a moment, and waits until it has started (see
+    /// `settle`).
+    pub fn reset(&mut self, quiet: Duration) -> io::Result<()> {
+        self.set_dtr(false)?;
+        thread::sleep(RESET_PULSE);
+        self.set_dtr(true)?;
+
+        return self.settle(quiet);
+    }
+
+    /// Discards everything received until the board is quiet for the given time, like the banner
+    /// printed while starting after a reset.
+    ///
+    /// Boards with an Arduino bootloader are silent for up to two seconds before starting, so
+    /// waiting for less may return before the banner.
+    pub fn settle(&mut self, quiet: Duration) -> io::Result<()> {
+        let termios = self.termios()?;
+        self.set_read_timeout(Some(quiet))?;
+
+        let mut buf = [0u8; 256];
+        let result = loop {
+            match self.file.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => continue,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => break Err(err),
+            }
+        };
+
+        self.set_termios(&termios)?;
+        return result;
+    }
+
+    fn termios(&self) -> io::Result<libc::termios> {
+        let mut termios = unsafe { mem::zeroed::<libc::termios>() };
+        check(unsafe { libc::tcgetattr(self.file.as_raw_fd(), &mut termios) })?;
+        return Ok(termios);
+    }
+
+    fn set_termios(&self, termios: &libc::termios) -> io::Result<()> {
+        check(unsafe { libc::tcsetattr(self.file.as_raw_fd(), libc::TCSANOW, termios) })
+    }
+}
+
+impl Read for Serial {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+
+        // Reads with a timeout return nothing once it elapses, others only once the port hangs up
+        if n == 0 && !buf.is_empty() && self.termios()?.c_cc[libc::VMIN] == 0 {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "read timed out"));
+        }
+
+        return Ok(n);
+    }
+}
+
+impl Write for Serial {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    /// Waits until all bytes written are transmitted.
+    fn flush(&mut self) -> io::Result<()> {
+        check(unsafe { libc::tcdrain(self.file.as_raw_fd()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::os::unix::io::FromRawFd;
+
+    use super::*;
+    use crate::parser::Parser;
+    use crate::sender::Sender;
+
+    /// Opens a pseudo terminal, returning the controller side and the path of the port.
+    fn pty() -> (File, PathBuf) {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(fd >= 0);
+            assert_eq!(libc::grantpt(fd), 0);
+            assert_eq!(libc::unlockpt(fd), 0);
+
+            let path = CStr::from_ptr(libc::ptsname(fd)).to_str().unwrap().into();
+            return (File::from_raw_fd(fd), path);
+        }
+    }
+
+    #[test]
+    fn test_open() {
+        let (_controller, path) = pty();
+        assert_eq!(Serial::open(&path, 250000).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let mut serial = Serial::open(&path, 115200).unwrap();
+        assert_eq!(serial.baud(), 115200);
+
+        serial.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        assert_eq!(serial.read(&mut [0u8; 16]).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_sender() {
+        let (mut controller, path) = pty();
+        let mut serial = Serial::open(&path, 115200).unwrap();
+
+        // The banner is discarded once the controller is quiet
+        controller.write_all(b"start\r\necho: Marlin\r\n").unwrap();
+        serial.settle(Duration::from_millis(200)).unwrap();
+
+        let replies = thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut buf = [0u8; 64];
+            while received.iter().filter(|&&b| b == b'\n').count() < 2 {
+                let n = controller.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+                controller.write_all(&b"ok\r\n".repeat(buf[..n].iter().filter(|&&b| b == b'\n').count())).unwrap();
+            }
+            // The port hangs up once the controller side is closed, so it is kept open
+            return (controller, received);
+        });
+
+        let program = Parser::new().parse_all("G0 X1\nG0 X2\n".lines()).unwrap().into_iter().collect();
+        let mut sender = Sender::new(serial, &program).with_timeout(Duration::from_secs(5));
+        sender.run().unwrap();
+
+        assert_eq!(replies.join().unwrap().1, b"G0 X1\nG0 X2\n");
+        assert_eq!(sender.progress().lines_acknowledged, 2);
+    }
+}
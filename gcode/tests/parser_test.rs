@@ -1,5 +1,3 @@
-#![feature(impl_trait_in_bindings)]
-
 use gcode::parser::Parser;
 
 #[test]
@@ -13,6 +11,20 @@ fn parse_01() {
 
     let mut parser = Parser::new();
     for line in file.lines() {
-        let block = parser.parse(line.unwrap()).unwrap();
+        parser.parse(line.unwrap()).unwrap();
+    }
+}
+
+#[test]
+fn parse_01_reader() {
+    use std::fs::File;
+    use std::path::Path;
+    use std::io::BufReader;
+
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/gcode/01.gcode");
+    let file = BufReader::new(File::open(file).unwrap());
+
+    for block in Parser::from_reader(file) {
+        block.unwrap();
     }
 }